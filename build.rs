@@ -0,0 +1,5 @@
+fn main() {
+	println!("cargo:rerun-if-changed=build.rs");
+	#[cfg(feature = "grpc")]
+	tonic_build::compile_protos("proto/admin.proto").expect("Failed to generate the gRPC admin API");
+}
@@ -0,0 +1,245 @@
+//! A control socket accepting operational commands while the server is running, such that peers
+//! can be managed, snapshots captured, and channels reverified without a restart.
+//!
+//! The protocol is line based, e. g. for use via `socat - UNIX-CONNECT:<path>`: each command is a
+//! line, which is answered with any number of lines of output followed by a line reading either
+//! `ok` or `error: <description>`. If a token is configured, the first command of every connection
+//! must be `auth <token>`.
+
+use std::fs::{self, Permissions};
+use std::io;
+use std::net::SocketAddr;
+use std::ops::Deref;
+use std::os::unix::fs::PermissionsExt;
+use std::str::FromStr;
+
+use bitcoin::secp256k1::PublicKey;
+use futures::stream::{FuturesUnordered, StreamExt};
+use lightning::{log_info, log_warn};
+use lightning::util::logger::Logger;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+
+use crate::config;
+use crate::listener::{Connection, ListenAddress, Listener};
+use crate::query;
+use crate::RapidSyncProcessor;
+
+const HELP: &str = "\
+peer list                      list the peers connections are maintained to
+peer add <pubkey>@<host:port>  connect to an additional peer
+peer remove <pubkey>           disconnect from a peer
+peer stats                     the gossip received from each peer
+snapshot now                   capture snapshots immediately
+reverify <scid>                verify the funding output of a channel again
+policy <scid>                  the policies of a channel in each direction
+stats                          summarize the network graph and the server's state
+help                           list the commands";
+
+#[derive(Debug, PartialEq)]
+enum AdminCommand {
+	Auth(String),
+	ListPeers,
+	/// The peer as given, which is only resolved when the command is executed
+	AddPeer(String),
+	RemovePeer(PublicKey),
+	PeerStats,
+	Snapshot,
+	Reverify(u64),
+	Policy(u64),
+	Stats,
+	Help,
+}
+
+impl AdminCommand {
+	fn parse(line: &str) -> Result<Self, String> {
+		let words: Vec<&str> = line.split_whitespace().collect();
+		match words.as_slice() {
+			["auth", token] => Ok(AdminCommand::Auth(token.to_string())),
+			["peer", "list"] => Ok(AdminCommand::ListPeers),
+			["peer", "add", peer] => Ok(AdminCommand::AddPeer(peer.to_string())),
+			["peer", "remove", node_id] => PublicKey::from_str(node_id)
+				.map(AdminCommand::RemovePeer)
+				.map_err(|_| format!("invalid node id {}", node_id)),
+			["peer", "stats"] => Ok(AdminCommand::PeerStats),
+			["snapshot", "now"] => Ok(AdminCommand::Snapshot),
+			["reverify", scid] => query::parse_short_channel_id(scid)
+				.map(AdminCommand::Reverify)
+				.ok_or_else(|| format!("invalid short channel id {}", scid)),
+			["policy", scid] => query::parse_short_channel_id(scid)
+				.map(AdminCommand::Policy)
+				.ok_or_else(|| format!("invalid short channel id {}", scid)),
+			["stats"] => Ok(AdminCommand::Stats),
+			["help"] => Ok(AdminCommand::Help),
+			_ => Err(format!("unknown command {}, see help", line.trim())),
+		}
+	}
+}
+
+/// Compare tokens in time independent of where they differ, so they can't be guessed bytewise
+pub(crate) fn tokens_match(token: &str, expected_token: &str) -> bool {
+	token.len() == expected_token.len() && token.bytes().zip(expected_token.bytes()).fold(0, |difference, (a, b)| difference | (a ^ b)) == 0
+}
+
+pub(crate) struct AdminServer<'a, L: Deref + Clone + Send + Sync + 'static> where L::Target: Logger {
+	processor: &'a RapidSyncProcessor<L>,
+	token: Option<String>,
+}
+
+impl<'a, L: Deref + Clone + Send + Sync + 'static> AdminServer<'a, L> where L::Target: Logger {
+	pub(crate) fn new(processor: &'a RapidSyncProcessor<L>) -> Self {
+		Self { processor, token: config::admin_token() }
+	}
+
+	/// Accept commands on `address` indefinitely
+	pub(crate) async fn serve(self, address: ListenAddress) {
+		let listener = address.bind().await
+			.unwrap_or_else(|e| panic!("Failed to bind admin socket to {}: {}", address, e));
+		match &listener {
+			// sockets passed by systemd are only known to be TCP sockets once taken over
+			Listener::Tcp(_) => assert!(self.token.is_some(), "RAPID_GOSSIP_SYNC_SERVER_ADMIN_TOKEN must be set for the admin socket to listen on TCP"),
+			Listener::Unix(_) => if let ListenAddress::Unix(path) = &address {
+				// access to unix sockets is governed by their permissions, so restrict it to our user
+				fs::set_permissions(path, Permissions::from_mode(0o600))
+					.unwrap_or_else(|e| panic!("Failed to restrict access to admin socket {}: {}", path.display(), e));
+			},
+		}
+		log_info!(self.processor.logger, "Accepting admin commands on {}", address);
+
+		// connections borrow the processor, so they're driven here rather than spawned
+		let mut connections = FuturesUnordered::new();
+		loop {
+			tokio::select! {
+				accepted = listener.accept() => match accepted {
+					Ok((connection, client_address)) => connections.push(self.serve_connection(connection, client_address)),
+					Err(e) => log_warn!(self.processor.logger, "Failed to accept admin connection: {}", e),
+				},
+				Some(()) = connections.next(), if !connections.is_empty() => {},
+			}
+		}
+	}
+
+	async fn serve_connection(&self, connection: Connection, client_address: Option<SocketAddr>) {
+		if let Err(e) = self.handle_commands(connection).await {
+			log_warn!(self.processor.logger, "Error serving admin connection from {}: {}", client_address.map_or("a unix socket client".to_string(), |address| address.to_string()), e);
+		}
+	}
+
+	async fn handle_commands(&self, connection: Connection) -> io::Result<()> {
+		let (reader, mut writer) = tokio::io::split(connection);
+		let mut reader = BufReader::new(reader);
+		let mut is_authenticated = self.token.is_none();
+		loop {
+			let mut line = String::new();
+			// bound the line length, as unauthenticated clients could otherwise exhaust our memory
+			let read = (&mut reader).take(config::MAX_ADMIN_COMMAND_LENGTH as u64 + 1).read_line(&mut line).await?;
+			if read == 0 {
+				return Ok(());
+			}
+			if line.len() > config::MAX_ADMIN_COMMAND_LENGTH {
+				writer.write_all(b"error: command too long\n").await?;
+				return Ok(());
+			}
+			if line.trim().is_empty() {
+				continue;
+			}
+
+			let command = AdminCommand::parse(&line);
+			if !is_authenticated {
+				match (command, &self.token) {
+					(Ok(AdminCommand::Auth(token)), Some(expected_token)) if tokens_match(&token, expected_token) => {
+						is_authenticated = true;
+						writer.write_all(b"ok\n").await?;
+						continue;
+					},
+					_ => {
+						writer.write_all(b"error: authentication required\n").await?;
+						return Ok(());
+					}
+				}
+			}
+			let reply = match command {
+				Ok(command) => match self.execute(command).await {
+					Ok(output) if output.is_empty() => "ok\n".to_string(),
+					Ok(output) => format!("{}\nok\n", output),
+					Err(e) => format!("error: {}\n", e),
+				},
+				Err(e) => format!("error: {}\n", e),
+			};
+			writer.write_all(reply.as_bytes()).await?;
+		}
+	}
+
+	async fn execute(&self, command: AdminCommand) -> Result<String, String> {
+		let processor = self.processor;
+		match command {
+			AdminCommand::Auth(_) => Ok(String::new()),
+			AdminCommand::ListPeers => {
+				let peers = processor.controls.list_peers().await?;
+				Ok(peers.into_iter()
+					.map(|peer| format!("{}@{} {}", peer.node_id, peer.address, if peer.is_connected { "connected" } else { "reconnecting" }))
+					.collect::<Vec<_>>()
+					.join("\n"))
+			},
+			AdminCommand::AddPeer(peer) => {
+				let (node_id, address) = config::parse_peer_info(&peer).map_err(|e| e.to_string())?;
+				log_info!(processor.logger, "Adding peer {}@{} as requested via the admin socket", node_id, address);
+				processor.controls.add_peer(node_id, address).await?;
+				Ok(String::new())
+			},
+			AdminCommand::RemovePeer(node_id) => {
+				if !processor.controls.remove_peer(node_id).await? {
+					return Err(format!("no connection is maintained to {}", node_id));
+				}
+				log_info!(processor.logger, "Removed peer {} as requested via the admin socket", node_id);
+				Ok(String::new())
+			},
+			AdminCommand::PeerStats => Ok(query::peers_json(&processor.metrics)),
+			AdminCommand::Snapshot => {
+				log_info!(processor.logger, "Capturing snapshots immediately as requested via the admin socket");
+				processor.controls.snapshot_trigger.notify_one();
+				if processor.health_monitor.is_initial_sync_complete() {
+					Ok(String::new())
+				} else {
+					Ok("snapshots will be captured once the initial sync has completed".to_string())
+				}
+			},
+			AdminCommand::Reverify(short_channel_id) => {
+				let funding_amount = processor.controls.reverify_channel(short_channel_id).await?;
+				Ok(format!("channel {} is funded with {} sat", short_channel_id, funding_amount))
+			},
+			AdminCommand::Policy(short_channel_id) => query::policy_json(&processor.network_graph, &processor.channel_funding_amounts, short_channel_id)
+				.ok_or_else(|| format!("channel {} is unknown", short_channel_id)),
+			AdminCommand::Stats => Ok(query::stats_json(&processor.network_graph, &processor.channel_funding_amounts, &processor.health_monitor, &processor.metrics)),
+			AdminCommand::Help => Ok(HELP.to_string()),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::str::FromStr;
+
+	use bitcoin::secp256k1::PublicKey;
+
+	use crate::admin::{tokens_match, AdminCommand};
+
+	#[test]
+	fn test_admin_command_parsing() {
+		let node_id = "035e4ff418fc8b5554c5d9eea66396c227bd429a3251c8cbc711002ba215bfc226";
+		assert_eq!(AdminCommand::parse("peer list\n"), Ok(AdminCommand::ListPeers));
+		assert_eq!(AdminCommand::parse(&format!("peer add {}@127.0.0.1:9735", node_id)), Ok(AdminCommand::AddPeer(format!("{}@127.0.0.1:9735", node_id))));
+		assert_eq!(AdminCommand::parse(&format!(" peer  remove {} ", node_id)), Ok(AdminCommand::RemovePeer(PublicKey::from_str(node_id).unwrap())));
+		assert!(AdminCommand::parse("peer remove 02").is_err());
+		assert_eq!(AdminCommand::parse("snapshot now"), Ok(AdminCommand::Snapshot));
+		assert_eq!(AdminCommand::parse("reverify 700000x1x0"), Ok(AdminCommand::Reverify(700_000 << 40 | 1 << 16)));
+		assert!(AdminCommand::parse("reverify 700000x1").is_err());
+		assert_eq!(AdminCommand::parse("policy 42"), Ok(AdminCommand::Policy(42)));
+		assert_eq!(AdminCommand::parse("stats"), Ok(AdminCommand::Stats));
+		assert_eq!(AdminCommand::parse("auth secret"), Ok(AdminCommand::Auth("secret".to_string())));
+		assert!(AdminCommand::parse("shutdown").is_err());
+
+		assert!(tokens_match("secret", "secret"));
+		assert!(!tokens_match("secreT", "secret"));
+		assert!(!tokens_match("secre", "secret"));
+	}
+}
@@ -0,0 +1,295 @@
+//! Anonymized statistics of the snapshot requests served by the built-in HTTP server, telling how
+//! far behind the client population is, e. g. for tuning the snapshot intervals. Client addresses
+//! aren't kept at all, sync timestamps are rounded down to the symlink granularity, and user agents
+//! are reduced to their leading product token.
+//!
+//! Requests are tallied in memory since startup, which `/api/requests` reports, and recorded one by
+//! one to either the log or the gossip store's `snapshot_requests` table.
+
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bitcoin::Network;
+use lightning::log_info;
+use lightning::util::logger::Logger;
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+use crate::config::{self, RequestAnalyticsSink};
+use crate::storage;
+
+const DAY: u64 = 24 * 3600;
+
+/// The buckets requests are tallied in by how long ago their client last synced, each holding those
+/// up to its bound. Clients requesting their initial sync and those further behind than the last
+/// bound are tallied as `initial` and `older`, respectively.
+const LAG_BUCKETS: [(u64, &str); 4] = [(DAY, "1d"), (3 * DAY, "3d"), (7 * DAY, "1w"), (14 * DAY, "2w")];
+
+/// A served snapshot request, as recorded
+pub(crate) struct SnapshotRequest {
+	/// When the request was served, in seconds since the epoch
+	pub(crate) requested_at: u64,
+	/// The last sync timestamp requested, rounded down to [`config::SYMLINK_GRANULARITY_INTERVAL`]
+	pub(crate) last_sync_timestamp: u64,
+	/// How long ago the client last synced, per [`lag_bucket`]
+	pub(crate) lag_bucket: &'static str,
+	/// The profile whose snapshot was served, if any
+	pub(crate) profile: Option<String>,
+	/// The file name of the snapshot served, or `dynamic` for one calculated on demand
+	pub(crate) served_snapshot: String,
+	pub(crate) bytes: u64,
+	/// The client's user agent, per [`anonymize_user_agent`]
+	pub(crate) user_agent: String,
+}
+
+#[derive(Clone, Copy, Default)]
+struct RequestTally {
+	requests: u64,
+	bytes: u64,
+}
+
+impl RequestTally {
+	fn add(&mut self, bytes: u64) {
+		self.requests += 1;
+		self.bytes += bytes;
+	}
+}
+
+#[derive(Default)]
+struct RequestSummary {
+	total: RequestTally,
+	lag_buckets: HashMap<&'static str, RequestTally>,
+	snapshot_kinds: HashMap<&'static str, RequestTally>,
+	/// Capped at [`config::MAX_TRACKED_USER_AGENTS`]
+	user_agents: HashMap<String, RequestTally>,
+	/// The requests not recorded individually, as the queue was full
+	dropped_count: u64,
+}
+
+#[derive(Serialize)]
+struct TallyEntry<'a> {
+	name: &'a str,
+	requests: u64,
+	bytes: u64,
+}
+
+impl<'a> TallyEntry<'a> {
+	fn new(name: &'a str, tally: &RequestTally) -> Self {
+		Self { name, requests: tally.requests, bytes: tally.bytes }
+	}
+}
+
+#[derive(Serialize)]
+struct RequestStats<'a> {
+	since: u64,
+	requests: u64,
+	bytes: u64,
+	dropped: u64,
+	lag_buckets: Vec<TallyEntry<'a>>,
+	snapshot_kinds: Vec<TallyEntry<'a>>,
+	user_agents: Vec<TallyEntry<'a>>,
+}
+
+pub(crate) struct RequestAnalytics {
+	/// When the tallies started, in seconds since the epoch
+	since: u64,
+	summary: Mutex<RequestSummary>,
+	sender: mpsc::Sender<SnapshotRequest>,
+	/// Taken by the task recording the requests once spawned
+	receiver: Mutex<Option<mpsc::Receiver<SnapshotRequest>>>,
+	sink: RequestAnalyticsSink,
+}
+
+impl RequestAnalytics {
+	pub(crate) fn new(sink: RequestAnalyticsSink) -> Self {
+		let (sender, receiver) = mpsc::channel(config::REQUEST_ANALYTICS_QUEUE_SIZE);
+		Self { since: current_time(), summary: Mutex::new(RequestSummary::default()), sender, receiver: Mutex::new(Some(receiver)), sink }
+	}
+
+	/// Tally a served snapshot request and queue it for recording. Requests are never held up by
+	/// the recording, which skips those that don't fit in the queue.
+	pub(crate) fn record(&self, user_agent: Option<&str>, profile: Option<&str>, last_sync_timestamp: u64, served_snapshot: String, bytes: u64) {
+		let requested_at = current_time();
+		let lag_bucket = lag_bucket(last_sync_timestamp, requested_at);
+		let user_agent = anonymize_user_agent(user_agent);
+		let granularity = config::SYMLINK_GRANULARITY_INTERVAL as u64;
+		let request = SnapshotRequest {
+			requested_at,
+			last_sync_timestamp: last_sync_timestamp - last_sync_timestamp % granularity,
+			lag_bucket,
+			profile: profile.map(str::to_string),
+			served_snapshot,
+			bytes,
+			user_agent,
+		};
+
+		let mut summary = self.summary.lock().unwrap();
+		summary.total.add(bytes);
+		summary.lag_buckets.entry(lag_bucket).or_default().add(bytes);
+		summary.snapshot_kinds.entry(snapshot_kind(&request.served_snapshot)).or_default().add(bytes);
+		let user_agent_key = if summary.user_agents.contains_key(&request.user_agent) || summary.user_agents.len() < config::MAX_TRACKED_USER_AGENTS {
+			request.user_agent.clone()
+		} else {
+			"other".to_string()
+		};
+		summary.user_agents.entry(user_agent_key).or_default().add(bytes);
+		if self.sender.try_send(request).is_err() {
+			summary.dropped_count += 1;
+		}
+	}
+
+	/// Start recording the queued requests to the configured sink
+	pub(crate) fn spawn_recording<L: Deref + Send + Sync + 'static>(&self, network: Network, logger: L) where L::Target: Logger {
+		let receiver = match self.receiver.lock().unwrap().take() {
+			Some(receiver) => receiver,
+			None => return,
+		};
+		tokio::spawn(record_requests(receiver, self.sink, network, logger));
+	}
+
+	/// The tallies as JSON
+	pub(crate) fn stats_json(&self) -> String {
+		let summary = self.summary.lock().unwrap();
+		let lag_bucket_names = std::iter::once("initial").chain(LAG_BUCKETS.iter().map(|(_, name)| *name)).chain(std::iter::once("older"));
+		let lag_buckets = lag_bucket_names
+			.map(|name| TallyEntry::new(name, &summary.lag_buckets.get(name).copied().unwrap_or_default()))
+			.collect();
+		let mut snapshot_kinds: Vec<_> = summary.snapshot_kinds.iter().map(|(name, tally)| TallyEntry::new(name, tally)).collect();
+		snapshot_kinds.sort_unstable_by_key(|entry| entry.name);
+		let mut user_agents: Vec<_> = summary.user_agents.iter().map(|(name, tally)| TallyEntry::new(name, tally)).collect();
+		user_agents.sort_unstable_by(|a, b| b.requests.cmp(&a.requests).then(a.name.cmp(b.name)));
+		serde_json::to_string(&RequestStats {
+			since: self.since,
+			requests: summary.total.requests,
+			bytes: summary.total.bytes,
+			dropped: summary.dropped_count,
+			lag_buckets,
+			snapshot_kinds,
+			user_agents,
+		}).unwrap()
+	}
+}
+
+async fn record_requests<L: Deref>(mut receiver: mpsc::Receiver<SnapshotRequest>, sink: RequestAnalyticsSink, network: Network, logger: L) where L::Target: Logger {
+	let store = match sink {
+		RequestAnalyticsSink::Database => Some(storage::open(network)),
+		RequestAnalyticsSink::Log => None,
+	};
+	while let Some(request) = receiver.recv().await {
+		let mut batch = vec![request];
+		while batch.len() < config::REQUEST_ANALYTICS_BATCH_SIZE {
+			match receiver.try_recv() {
+				Ok(request) => batch.push(request),
+				Err(_) => break,
+			}
+		}
+		match &store {
+			Some(store) => store.insert_snapshot_requests(batch).await,
+			None => for request in batch {
+				log_info!(logger, "Snapshot request: served_snapshot={} bytes={} profile={} last_sync_timestamp={} lag_bucket={} user_agent={}",
+					request.served_snapshot, request.bytes, request.profile.as_deref().unwrap_or("-"), request.last_sync_timestamp, request.lag_bucket, request.user_agent);
+			},
+		}
+	}
+}
+
+/// The bucket of [`LAG_BUCKETS`] a client last synced at `last_sync_timestamp` falls into
+fn lag_bucket(last_sync_timestamp: u64, current_timestamp: u64) -> &'static str {
+	if last_sync_timestamp == 0 {
+		return "initial";
+	}
+	let lag = current_timestamp.saturating_sub(last_sync_timestamp);
+	LAG_BUCKETS.iter().find(|(bound, _)| lag <= *bound).map_or("older", |(_, name)| name)
+}
+
+/// The kind of snapshot served, for tallying
+fn snapshot_kind(served_snapshot: &str) -> &'static str {
+	if served_snapshot == "dynamic" {
+		"dynamic"
+	} else if served_snapshot.starts_with("empty_delta") {
+		"empty"
+	} else if served_snapshot.contains("__previous-sync:0.") {
+		"full"
+	} else {
+		"delta"
+	}
+}
+
+/// Reduce a `User-Agent` header to its leading product token, e. g. `ldk-node/0.4.0`, which is
+/// all that's needed to tell client implementations apart, without any system details.
+fn anonymize_user_agent(user_agent: Option<&str>) -> String {
+	let product = user_agent.and_then(|user_agent| user_agent.split_whitespace().next()).unwrap_or("");
+	let product: String = product.chars()
+		.filter(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_' | '/'))
+		.take(config::MAX_USER_AGENT_LENGTH)
+		.collect();
+	if product.is_empty() { "unknown".to_string() } else { product }
+}
+
+fn current_time() -> u64 {
+	SystemTime::now().duration_since(UNIX_EPOCH).expect("Time must be > 1970").as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json::Value;
+
+	use crate::analytics::{anonymize_user_agent, lag_bucket, RequestAnalytics};
+	use crate::config::RequestAnalyticsSink;
+
+	#[test]
+	fn test_lag_bucket() {
+		let day = 24 * 3600;
+		let now = 100 * day;
+		assert_eq!(lag_bucket(0, now), "initial");
+		assert_eq!(lag_bucket(now - 3600, now), "1d");
+		assert_eq!(lag_bucket(now - day, now), "1d");
+		assert_eq!(lag_bucket(now - 2 * day, now), "3d");
+		assert_eq!(lag_bucket(now - 10 * day, now), "2w");
+		assert_eq!(lag_bucket(now - 30 * day, now), "older");
+		// clients with skewed clocks may be ahead of us
+		assert_eq!(lag_bucket(now + 60, now), "1d");
+	}
+
+	#[test]
+	fn test_user_agent_anonymization() {
+		assert_eq!(anonymize_user_agent(Some("ldk-node/0.4.0 (Linux; x86_64)")), "ldk-node/0.4.0");
+		assert_eq!(anonymize_user_agent(Some("curl/8.5.0")), "curl/8.5.0");
+		assert_eq!(anonymize_user_agent(Some("<script>")), "script");
+		assert_eq!(anonymize_user_agent(Some("")), "unknown");
+		assert_eq!(anonymize_user_agent(None), "unknown");
+		assert_eq!(anonymize_user_agent(Some(&"a".repeat(1000))).len(), crate::config::MAX_USER_AGENT_LENGTH);
+	}
+
+	#[test]
+	fn test_request_tallies() {
+		let analytics = RequestAnalytics::new(RequestAnalyticsSink::Log);
+		analytics.record(Some("ldk-node/0.4.0"), None, 0, "snapshot__calculated-at:1700000000__range:1700000000-scope__previous-sync:0.lngossip".to_string(), 1000);
+		analytics.record(Some("ldk-node/0.4.0"), Some("small"), 1_000, "empty_delta.lngossip".to_string(), 10);
+		analytics.record(None, None, 1_000, "dynamic".to_string(), 100);
+
+		let stats: Value = serde_json::from_str(&analytics.stats_json()).unwrap();
+		assert_eq!(stats["requests"], 3);
+		assert_eq!(stats["bytes"], 1110);
+		assert_eq!(stats["dropped"], 0);
+		assert_eq!(stats["lag_buckets"][0]["name"], "initial");
+		assert_eq!(stats["lag_buckets"][0]["bytes"], 1000);
+		assert_eq!(stats["lag_buckets"][5]["name"], "older");
+		assert_eq!(stats["lag_buckets"][5]["requests"], 2);
+		let snapshot_kinds: Vec<_> = stats["snapshot_kinds"].as_array().unwrap().iter().map(|kind| kind["name"].as_str().unwrap()).collect();
+		assert_eq!(snapshot_kinds, vec!["dynamic", "empty", "full"]);
+		assert_eq!(stats["user_agents"][0]["name"], "ldk-node/0.4.0");
+		assert_eq!(stats["user_agents"][0]["requests"], 2);
+		assert_eq!(stats["user_agents"][1]["name"], "unknown");
+
+		// the requests are queued for recording, rounded down to the symlink granularity
+		let request = analytics.receiver.lock().unwrap().as_mut().unwrap().try_recv().unwrap();
+		assert_eq!(request.last_sync_timestamp, 0);
+		assert_eq!(request.user_agent, "ldk-node/0.4.0");
+		let request = analytics.receiver.lock().unwrap().as_mut().unwrap().try_recv().unwrap();
+		assert_eq!(request.profile.as_deref(), Some("small"));
+		assert_eq!(request.served_snapshot, "empty_delta.lngossip");
+	}
+}
@@ -0,0 +1,162 @@
+//! Keeps a copy of every round of snapshots, which are otherwise replaced by the next round, so
+//! that past graph states can be reconstructed and the snapshots clients were served reexamined.
+//!
+//! Each round is archived under `<archive>/<reference timestamp>/`, laid out like the cache
+//! directory: the snapshots in `snapshots/`, described by the round's `manifest.json`. The files
+//! are hard linked rather than copied where possible, so archiving costs little until the round
+//! is replaced. Rounds older than the retention period are removed after each archival.
+
+use std::fs;
+use std::io;
+use std::ops::Deref;
+use std::path::Path;
+use std::time::Duration;
+
+use lightning::{log_error, log_info};
+use lightning::util::logger::Logger;
+
+pub(crate) struct SnapshotArchive<L: Deref> where L::Target: Logger {
+	directory: String,
+	/// How long rounds are kept for, or indefinitely if `None`
+	retention: Option<Duration>,
+	logger: L,
+}
+
+impl<L: Deref> SnapshotArchive<L> where L::Target: Logger {
+	pub(crate) fn new(directory: String, retention: Option<Duration>, logger: L) -> Self {
+		Self { directory, retention, logger }
+	}
+
+	/// Archive the round of snapshots last finalized in `cache_path`, returning the directory it
+	/// was archived to. A round archived for the same reference timestamp before is replaced.
+	pub(crate) fn archive(&self, cache_path: &str, reference_timestamp: u64) -> Option<String> {
+		let archive_path = format!("{}/{}", self.directory, reference_timestamp);
+		if let Err(e) = self.archive_round(cache_path, &archive_path) {
+			log_error!(self.logger, "Failed to archive snapshots to {}: {}", archive_path, e);
+			return None;
+		}
+		log_info!(self.logger, "Archived snapshots to {}", archive_path);
+		Some(archive_path)
+	}
+
+	fn archive_round(&self, cache_path: &str, archive_path: &str) -> io::Result<()> {
+		// assembled next to its destination, such that only complete rounds are ever archived
+		let pending_path = format!("{}.pending", archive_path);
+		if fs::metadata(&pending_path).is_ok() {
+			fs::remove_dir_all(&pending_path)?;
+		}
+		fs::create_dir_all(&pending_path)?;
+		link_directory(Path::new(&format!("{}/snapshots", cache_path)), Path::new(&format!("{}/snapshots", pending_path)))?;
+		for file_name in ["manifest.json", "manifest.json.sig", "update_time.txt"] {
+			let source = format!("{}/symlinks/{}", cache_path, file_name);
+			if fs::metadata(&source).is_ok() {
+				link_file(Path::new(&source), Path::new(&format!("{}/{}", pending_path, file_name)))?;
+			}
+		}
+		if fs::metadata(archive_path).is_ok() {
+			fs::remove_dir_all(archive_path)?;
+		}
+		fs::rename(&pending_path, archive_path)
+	}
+
+	/// Remove the rounds whose reference timestamp is beyond the retention period as of
+	/// `current_timestamp`, returning how many were removed
+	pub(crate) fn prune(&self, current_timestamp: u64) -> usize {
+		let retention = match self.retention {
+			Some(retention) => retention,
+			None => return 0,
+		};
+		let entries = match fs::read_dir(&self.directory) {
+			Ok(entries) => entries,
+			Err(e) => {
+				log_error!(self.logger, "Failed to list archived snapshots in {}: {}", self.directory, e);
+				return 0;
+			}
+		};
+		let mut removed_count = 0;
+		for entry in entries.flatten() {
+			// only the archived rounds are named by their timestamp
+			let reference_timestamp = match entry.file_name().to_str().and_then(|name| name.parse::<u64>().ok()) {
+				Some(reference_timestamp) => reference_timestamp,
+				None => continue,
+			};
+			if current_timestamp.saturating_sub(reference_timestamp) <= retention.as_secs() {
+				continue;
+			}
+			match fs::remove_dir_all(entry.path()) {
+				Ok(()) => removed_count += 1,
+				Err(e) => log_error!(self.logger, "Failed to remove archived snapshots {}: {}", entry.path().display(), e),
+			}
+		}
+		if removed_count > 0 {
+			log_info!(self.logger, "Removed {} rounds of archived snapshots older than {} days", removed_count, retention.as_secs() / (24 * 3600));
+		}
+		removed_count
+	}
+}
+
+/// Recursively hard link the files below `source` into `destination`
+fn link_directory(source: &Path, destination: &Path) -> io::Result<()> {
+	fs::create_dir_all(destination)?;
+	for entry in fs::read_dir(source)? {
+		let entry = entry?;
+		let destination_path = destination.join(entry.file_name());
+		if entry.file_type()?.is_dir() {
+			link_directory(&entry.path(), &destination_path)?;
+		} else {
+			link_file(&entry.path(), &destination_path)?;
+		}
+	}
+	Ok(())
+}
+
+/// Hard link a file, or copy it if the archive is on another filesystem
+fn link_file(source: &Path, destination: &Path) -> io::Result<()> {
+	fs::hard_link(source, destination).or_else(|_| fs::copy(source, destination).map(|_| ()))
+}
+
+#[cfg(test)]
+mod tests {
+	use std::fs;
+	use std::sync::Arc;
+	use std::time::Duration;
+
+	use crate::archive::SnapshotArchive;
+	use crate::types::tests::TestLogger;
+
+	#[test]
+	fn test_snapshot_archiving() {
+		let directory = std::env::temp_dir().join(format!("rgs_archive_test_{}", std::process::id()));
+		let cache_path = directory.join("cache").to_str().unwrap().to_string();
+		let archive_path = directory.join("archive").to_str().unwrap().to_string();
+		fs::create_dir_all(format!("{}/snapshots/v2", cache_path)).unwrap();
+		fs::create_dir_all(format!("{}/symlinks", cache_path)).unwrap();
+		let write_round = |contents: &str| {
+			fs::write(format!("{}/snapshots/full.lngossip", cache_path), contents).unwrap();
+			fs::write(format!("{}/snapshots/v2/full.lngossip", cache_path), contents).unwrap();
+			fs::write(format!("{}/symlinks/manifest.json", cache_path), contents).unwrap();
+		};
+
+		let day = 24 * 3600;
+		let archive = SnapshotArchive::new(archive_path.clone(), Some(Duration::from_secs(7 * day)), Arc::new(TestLogger::with_id("archive".to_string())));
+		write_round("first");
+		assert_eq!(archive.archive(&cache_path, 10 * day), Some(format!("{}/{}", archive_path, 10 * day)));
+		// the next round replaces the served snapshots, but not the archived ones
+		fs::remove_dir_all(format!("{}/snapshots", cache_path)).unwrap();
+		fs::remove_dir_all(format!("{}/symlinks", cache_path)).unwrap();
+		fs::create_dir_all(format!("{}/snapshots/v2", cache_path)).unwrap();
+		fs::create_dir_all(format!("{}/symlinks", cache_path)).unwrap();
+		write_round("second");
+		archive.archive(&cache_path, 15 * day).unwrap();
+		assert_eq!(fs::read_to_string(format!("{}/{}/snapshots/v2/full.lngossip", archive_path, 10 * day)).unwrap(), "first");
+		assert_eq!(fs::read_to_string(format!("{}/{}/manifest.json", archive_path, 10 * day)).unwrap(), "first");
+		assert_eq!(fs::read_to_string(format!("{}/{}/snapshots/full.lngossip", archive_path, 15 * day)).unwrap(), "second");
+
+		assert_eq!(archive.prune(17 * day), 0);
+		assert_eq!(archive.prune(18 * day), 1);
+		assert!(fs::metadata(format!("{}/{}", archive_path, 10 * day)).is_err());
+		assert!(fs::metadata(format!("{}/{}", archive_path, 15 * day)).is_ok());
+
+		fs::remove_dir_all(&directory).unwrap();
+	}
+}
@@ -0,0 +1,118 @@
+//! Node ids and short channel ids whose gossip is rejected upon receipt and omitted from snapshots,
+//! e. g. those of known spammers. The blocklist is read from the file at
+//! `RAPID_GOSSIP_SYNC_SERVER_BLOCKLIST_PATH`, which lists one hex-encoded node id or short channel
+//! id per line, and re-read whenever the peers are reloaded. Blank lines and lines starting with `#`
+//! are ignored.
+
+use std::collections::HashSet;
+use std::fs;
+use std::str::FromStr;
+use std::sync::{Arc, OnceLock, RwLock};
+
+use bitcoin::secp256k1::PublicKey;
+use lightning::routing::gossip::NodeId;
+
+use crate::config;
+use crate::query;
+
+#[derive(Default)]
+pub(crate) struct Blocklist {
+	node_ids: HashSet<NodeId>,
+	short_channel_ids: HashSet<u64>,
+}
+
+impl Blocklist {
+	fn parse(contents: &str) -> Result<Self, String> {
+		let mut blocklist = Self::default();
+		for (line_index, line) in contents.lines().enumerate() {
+			let entry = line.trim();
+			if entry.is_empty() || entry.starts_with('#') {
+				continue;
+			}
+			if let Ok(node_id) = PublicKey::from_str(entry) {
+				blocklist.node_ids.insert(NodeId::from_pubkey(&node_id));
+			} else if let Some(short_channel_id) = query::parse_short_channel_id(entry) {
+				blocklist.short_channel_ids.insert(short_channel_id);
+			} else {
+				return Err(format!("Expected a node id or a short channel id on line {}", line_index + 1));
+			}
+		}
+		Ok(blocklist)
+	}
+
+	fn load() -> Result<Self, String> {
+		let path = match config::blocklist_path() {
+			Some(path) => path,
+			None => return Ok(Self::default()),
+		};
+		let contents = fs::read_to_string(&path).map_err(|e| format!("Failed to read blocklist {}: {}", path, e))?;
+		Self::parse(&contents).map_err(|e| format!("Invalid blocklist {}: {}", path, e))
+	}
+
+	pub(crate) fn len(&self) -> usize {
+		self.node_ids.len() + self.short_channel_ids.len()
+	}
+
+	pub(crate) fn blocks_node(&self, node_id: &NodeId) -> bool {
+		self.node_ids.contains(node_id)
+	}
+
+	/// Whether a channel is blocked, either itself or by one of its nodes
+	pub(crate) fn blocks_channel(&self, short_channel_id: u64, node_id_1: &NodeId, node_id_2: &NodeId) -> bool {
+		self.short_channel_ids.contains(&short_channel_id) || self.blocks_node(node_id_1) || self.blocks_node(node_id_2)
+	}
+}
+
+fn blocklist() -> &'static RwLock<Arc<Blocklist>> {
+	static BLOCKLIST: OnceLock<RwLock<Arc<Blocklist>>> = OnceLock::new();
+	BLOCKLIST.get_or_init(|| RwLock::new(Arc::new(Blocklist::load().unwrap_or_else(|e| panic!("{}", e)))))
+}
+
+/// The blocklist as of its latest reload
+pub(crate) fn current() -> Arc<Blocklist> {
+	Arc::clone(&blocklist().read().unwrap())
+}
+
+/// Re-read the blocklist, keeping the previous one if it has become invalid, and return the number
+/// of blocked entries
+pub(crate) fn reload() -> Result<usize, String> {
+	let reloaded = Blocklist::load()?;
+	let entry_count = reloaded.len();
+	*blocklist().write().unwrap() = Arc::new(reloaded);
+	Ok(entry_count)
+}
+
+#[cfg(test)]
+mod tests {
+	use std::str::FromStr;
+
+	use bitcoin::secp256k1::PublicKey;
+	use lightning::routing::gossip::NodeId;
+
+	use crate::blocklist::Blocklist;
+
+	#[test]
+	fn test_parse_blocklist() {
+		let spammer = PublicKey::from_str("035e4ff418fc8b5554c5d9eea66396c227bd429a3251c8cbc711002ba215bfc226").unwrap();
+		let blocklist = Blocklist::parse(&format!("\
+			# spammers\n\
+			{}\n\
+			\n\
+			870000x12x1\n\
+			956575621577719809\n\
+		", spammer)).unwrap();
+		assert_eq!(blocklist.len(), 3);
+
+		let spammer = NodeId::from_pubkey(&spammer);
+		let other_node = NodeId::from_slice(&[2; 33]).unwrap();
+		assert!(blocklist.blocks_node(&spammer));
+		assert!(!blocklist.blocks_node(&other_node));
+		assert!(blocklist.blocks_channel(870000 << 40 | 12 << 16 | 1, &other_node, &other_node));
+		assert!(blocklist.blocks_channel(956575621577719809, &other_node, &other_node));
+		assert!(blocklist.blocks_channel(42, &other_node, &spammer));
+		assert!(!blocklist.blocks_channel(42, &other_node, &other_node));
+
+		assert!(Blocklist::parse("870000x12").is_err());
+		assert!(Blocklist::parse("spammer").is_err());
+	}
+}
@@ -0,0 +1,122 @@
+//! Seeding a fresh deployment from a snapshot published by another rapid gossip sync server, such
+//! that it doesn't have to wait for the P2P sync to discover the whole graph.
+
+use std::fs;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use bitcoin::blockdata::constants::ChainHash;
+use bitcoin::secp256k1::ecdsa::Signature;
+use lightning::{log_info, log_warn};
+use lightning::ln::msgs::{ChannelAnnouncement, ChannelUpdate, UnsignedChannelAnnouncement, UnsignedChannelUpdate};
+use lightning::routing::gossip::{ChannelUpdateInfo, NetworkGraph};
+use lightning::util::logger::Logger;
+use lightning_rapid_gossip_sync::RapidGossipSync;
+use tokio::sync::mpsc;
+
+use crate::types::GossipMessage;
+
+/// Snapshots don't carry signatures, so the gossip seeded from them is signed with this instead
+fn placeholder_signature() -> Signature {
+	Signature::from_compact(&[0u8; 64]).unwrap()
+}
+
+/// Read the snapshot at `source`, which is either an HTTP(S) URL or a local file path
+async fn read_snapshot(source: &str) -> Result<Vec<u8>, String> {
+	if source.starts_with("http://") || source.starts_with("https://") {
+		let response = reqwest::get(source).await.map_err(|e| e.to_string())?;
+		if !response.status().is_success() {
+			return Err(format!("responded with status {}", response.status()));
+		}
+		return Ok(response.bytes().await.map_err(|e| e.to_string())?.to_vec());
+	}
+	fs::read(source).map_err(|e| e.to_string())
+}
+
+/// Apply the snapshot at `source` to `network_graph` and queue the gossip it contained for
+/// persistence, returning the number of channels it added.
+///
+/// Snapshots neither include signatures nor funding amounts, so the seeded channel announcements
+/// are stored with a funding amount of zero, which marks them to be replaced once the actual
+/// announcement is received. Node announcements aren't seeded at all.
+pub(crate) async fn bootstrap_network_graph<L: Deref + Clone>(network_graph: &Arc<NetworkGraph<L>>, persistence_sender: &mpsc::Sender<GossipMessage>, source: &str, logger: L) -> usize where L::Target: Logger {
+	log_info!(logger, "Bootstrapping network graph from snapshot {}…", source);
+	let snapshot = match read_snapshot(source).await {
+		Ok(snapshot) => snapshot,
+		Err(e) => {
+			log_warn!(logger, "Not bootstrapping, as the snapshot {} couldn't be read: {}", source, e);
+			return 0;
+		}
+	};
+	let rapid_sync = RapidGossipSync::new(Arc::clone(network_graph), logger.clone());
+	if let Err(e) = rapid_sync.update_network_graph(&snapshot) {
+		log_warn!(logger, "Not bootstrapping, as the snapshot {} couldn't be applied: {:?}", source, e);
+		return 0;
+	}
+
+	let chain_hash = network_graph.get_chain_hash();
+	let mut seeded_gossip = Vec::new();
+	let mut channel_count = 0;
+	{
+		let graph = network_graph.read_only();
+		for (short_channel_id, channel) in graph.channels().unordered_iter() {
+			// channels we already have the actual announcement of are persisted as usual
+			if channel.announcement_message.is_some() {
+				continue;
+			}
+			let announcement = ChannelAnnouncement {
+				node_signature_1: placeholder_signature(),
+				node_signature_2: placeholder_signature(),
+				bitcoin_signature_1: placeholder_signature(),
+				bitcoin_signature_2: placeholder_signature(),
+				contents: UnsignedChannelAnnouncement {
+					features: channel.features.clone(),
+					chain_hash,
+					short_channel_id: *short_channel_id,
+					node_id_1: channel.node_one,
+					node_id_2: channel.node_two,
+					// unknown, but never served
+					bitcoin_key_1: channel.node_one,
+					bitcoin_key_2: channel.node_two,
+					excess_data: Vec::new(),
+				},
+			};
+			seeded_gossip.push(GossipMessage::ChannelAnnouncement(announcement, 0, None));
+			channel_count += 1;
+			for (direction, info) in [(false, &channel.one_to_two), (true, &channel.two_to_one)] {
+				if let Some(info) = info {
+					seeded_gossip.push(GossipMessage::ChannelUpdate(placeholder_update(chain_hash, *short_channel_id, direction, info), None));
+				}
+			}
+		}
+	}
+
+	for message in seeded_gossip {
+		if persistence_sender.send(message).await.is_err() {
+			// shutting down
+			break;
+		}
+	}
+	log_info!(logger, "Bootstrapped {} channels from snapshot {}", channel_count, source);
+	channel_count
+}
+
+fn placeholder_update(chain_hash: ChainHash, short_channel_id: u64, direction: bool, info: &ChannelUpdateInfo) -> ChannelUpdate {
+	ChannelUpdate {
+		signature: placeholder_signature(),
+		contents: UnsignedChannelUpdate {
+			chain_hash,
+			short_channel_id,
+			timestamp: info.last_update,
+			// the maximum HTLC value is always present
+			message_flags: 1,
+			channel_flags: (direction as u8) | if info.enabled { 0 } else { 2 },
+			cltv_expiry_delta: info.cltv_expiry_delta,
+			htlc_minimum_msat: info.htlc_minimum_msat,
+			htlc_maximum_msat: info.htlc_maximum_msat,
+			fee_base_msat: info.fees.base_msat,
+			fee_proportional_millionths: info.fees.proportional_millionths,
+			excess_data: Vec::new(),
+		},
+	}
+}
@@ -0,0 +1,214 @@
+//! The public API for embedding the server in a larger application rather than running it as a
+//! separate binary.
+
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bitcoin::Network;
+use bitcoin::secp256k1::PublicKey;
+use lightning::ln::msgs::SocketAddress;
+use lightning::routing::gossip::{ChannelInfo, NetworkGraph};
+use lightning::util::logger::Logger;
+use tokio::sync::watch;
+use tokio::runtime::Handle;
+
+use crate::chain::ChainSource;
+use crate::config;
+use crate::config_file;
+use crate::metrics::PeerGossipStats;
+use crate::tracking::PeerInfo;
+use crate::{ProcessorControls, RapidSyncProcessor};
+
+/// A rapid gossip sync server for a single network, syncing gossip from its peers, persisting it,
+/// and capturing snapshots from it for as long as it's running.
+///
+/// ```no_run
+/// # use std::sync::Arc;
+/// # use rapid_gossip_sync_server::RapidGossipSyncServer;
+/// # use rapid_gossip_sync_server::types::RGSSLogger;
+/// # async fn run() -> Result<(), String> {
+/// let server = RapidGossipSyncServer::builder(Arc::new(RGSSLogger::new()))
+///     .network(bitcoin::Network::Signet)
+///     .config("[database]\nbackend = \"sqlite\"\nsqlite_path = \"gossip.sqlite\"")?
+///     .build();
+/// let handle = server.start();
+/// handle.trigger_snapshot();
+/// handle.shutdown().await;
+/// # Ok(())
+/// # }
+/// ```
+pub struct RapidGossipSyncServer<L: Deref + Clone + Send + Sync + 'static> where L::Target: Logger {
+	processor: Arc<RapidSyncProcessor<L>>,
+}
+
+impl<L: Deref + Clone + Send + Sync + 'static> RapidGossipSyncServer<L> where L::Target: Logger {
+	/// Start configuring a server logging to `logger`
+	pub fn builder(logger: L) -> RapidGossipSyncServerBuilder<L> {
+		RapidGossipSyncServerBuilder { logger, network: None, settings: HashMap::new(), chain_source: None }
+	}
+
+	/// Start syncing gossip and capturing snapshots in the background, on the current Tokio
+	/// runtime. Unlike the binary, the server doesn't handle any signals; it keeps running until
+	/// shut down through the returned handle.
+	pub fn start(self) -> ServerHandle<L> {
+		let (shutdown_sender, shutdown_receiver) = watch::channel(false);
+		let processor = Arc::clone(&self.processor);
+		let runtime = Handle::current();
+		// the compiler can't prove the processor's future to be `Send` for every logger, so rather
+		// than spawning it as a task, it's driven by a thread of its own, from which the tasks it
+		// spawns still run on the runtime
+		let thread = std::thread::spawn(move || runtime.block_on(processor.run(shutdown_receiver)));
+		ServerHandle { processor: self.processor, shutdown: shutdown_sender, thread }
+	}
+}
+
+/// Configures a [`RapidGossipSyncServer`]
+pub struct RapidGossipSyncServerBuilder<L: Deref + Clone + Send + Sync + 'static> where L::Target: Logger {
+	logger: L,
+	network: Option<Network>,
+	settings: HashMap<String, String>,
+	chain_source: Option<Arc<dyn ChainSource>>,
+}
+
+impl<L: Deref + Clone + Send + Sync + 'static> RapidGossipSyncServerBuilder<L> where L::Target: Logger {
+	/// The network to operate on, which defaults to `RAPID_GOSSIP_SYNC_SERVER_NETWORK`
+	pub fn network(mut self, network: Network) -> Self {
+		self.network = Some(network);
+		self
+	}
+
+	/// Set one of the settings otherwise read from the environment, by the name of its
+	/// environment variable, e. g. `RAPID_GOSSIP_SYNC_SERVER_SNAPSHOT_INTERVAL`
+	pub fn setting(mut self, name: &str, value: &str) -> Self {
+		self.settings.insert(name.to_string(), value.to_string());
+		self
+	}
+
+	/// Apply the settings of a config file's TOML `contents`, in the same format as the file
+	/// referenced by `RAPID_GOSSIP_SYNC_SERVER_CONFIG_FILE`
+	pub fn config(mut self, contents: &str) -> Result<Self, String> {
+		self.settings.extend(config_file::parse(contents)?);
+		Ok(self)
+	}
+
+	/// Verify the funding outputs of announced channels against `chain_source` rather than the
+	/// configured bitcoind REST endpoints
+	pub fn chain_source(mut self, chain_source: Arc<dyn ChainSource>) -> Self {
+		self.chain_source = Some(chain_source);
+		self
+	}
+
+	/// Build the server, which must happen within a Tokio runtime.
+	///
+	/// The settings take precedence over the environment and the config file, and as the
+	/// configuration is process-wide, they also apply to any other servers in the process.
+	///
+	/// # Panics
+	///
+	/// If any of the settings is invalid.
+	pub fn build(self) -> RapidGossipSyncServer<L> {
+		config::apply_embedded_settings(self.settings);
+		let network = self.network.unwrap_or_else(config::network);
+		let processor = RapidSyncProcessor::with_chain_source(network, self.chain_source, self.logger);
+		RapidGossipSyncServer { processor: Arc::new(processor) }
+	}
+}
+
+/// Controls a running [`RapidGossipSyncServer`]
+pub struct ServerHandle<L: Deref + Clone + Send + Sync + 'static> where L::Target: Logger {
+	processor: Arc<RapidSyncProcessor<L>>,
+	shutdown: watch::Sender<bool>,
+	thread: std::thread::JoinHandle<()>,
+}
+
+/// A summary of a server's state
+#[derive(Clone, Debug)]
+pub struct ServerStats {
+	pub network: Network,
+	/// The number of channels in the network graph
+	pub channel_count: usize,
+	/// The number of nodes in the network graph
+	pub node_count: usize,
+	pub connected_peers: usize,
+	/// Whether the initial sync has completed, upon which snapshots start being captured
+	pub initial_sync_complete: bool,
+	/// How long ago the served snapshots were captured, if there are any
+	pub snapshot_age: Option<Duration>,
+}
+
+impl ServerStats {
+	pub(crate) fn new<L: Deref>(controls: &ProcessorControls<L>) -> Self where L::Target: Logger {
+		let graph = controls.network_graph.read_only();
+		Self {
+			network: config::graph_network(&controls.network_graph),
+			channel_count: graph.channels().len(),
+			node_count: graph.nodes().len(),
+			connected_peers: controls.health_monitor.connected_peer_count(),
+			initial_sync_complete: controls.health_monitor.is_initial_sync_complete(),
+			snapshot_age: controls.health_monitor.snapshot_age(),
+		}
+	}
+}
+
+impl<L: Deref + Clone + Send + Sync + 'static> ServerHandle<L> where L::Target: Logger {
+	/// Capture snapshots immediately rather than at the next interval. Snapshots are only
+	/// captured once the initial sync has completed.
+	pub fn trigger_snapshot(&self) {
+		self.processor.controls.snapshot_trigger.notify_one();
+	}
+
+	pub fn stats(&self) -> ServerStats {
+		ServerStats::new(&self.processor.controls)
+	}
+
+	/// The peers the server maintains connections to, available once the initial connections have
+	/// been established
+	pub async fn peers(&self) -> Result<Vec<PeerInfo>, String> {
+		self.processor.controls.list_peers().await
+	}
+
+	/// Connect to an additional peer, or reconnect to a peer at a different address. Like the
+	/// configured peers, it's reconnected to whenever the connection drops, but it's disconnected
+	/// from when the peers are reloaded unless it's configured by then.
+	pub async fn add_peer(&self, node_id: PublicKey, address: SocketAddress) -> Result<(), String> {
+		self.processor.controls.add_peer(node_id, address).await
+	}
+
+	/// Disconnect from a peer, returning whether the server was maintaining a connection to it
+	pub async fn remove_peer(&self, node_id: PublicKey) -> Result<bool, String> {
+		self.processor.controls.remove_peer(node_id).await
+	}
+
+	/// The gossip received from each peer since the server started, ordered by node id, including
+	/// peers that have since been disconnected from
+	pub fn peer_gossip_stats(&self) -> Vec<(PublicKey, PeerGossipStats)> {
+		self.processor.metrics.peer_gossip_stats()
+	}
+
+	/// The channel with the given short channel id, as currently known to the server
+	pub fn channel(&self, short_channel_id: u64) -> Option<ChannelInfo> {
+		self.processor.network_graph.read_only().channel(short_channel_id).cloned()
+	}
+
+	/// Verify the funding output of a channel against the chain source again, returning its
+	/// funding amount. Channels whose funding output has since been spent are removed from the
+	/// network graph.
+	pub async fn reverify_channel(&self, short_channel_id: u64) -> Result<u64, String> {
+		self.processor.controls.reverify_channel(short_channel_id).await
+	}
+
+	/// The network graph as currently known to the server
+	pub fn network_graph(&self) -> Arc<NetworkGraph<L>> {
+		Arc::clone(&self.processor.network_graph)
+	}
+
+	/// Shut the server down gracefully, persisting the queued gossip and, if configured, capturing
+	/// final snapshots, and wait for it to complete
+	pub async fn shutdown(self) {
+		self.shutdown.send_replace(true);
+		let thread = self.thread;
+		let _ = tokio::task::spawn_blocking(move || thread.join()).await;
+	}
+}
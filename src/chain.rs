@@ -0,0 +1,91 @@
+//! The chain data the funding outputs of announced channels are verified against. Unless an
+//! application embedding the server provides a source of its own, it's retrieved from bitcoind's
+//! REST interface.
+
+use std::io;
+
+use async_trait::async_trait;
+use bitcoin::{Block, OutPoint, Script, TxOut, Txid};
+
+pub(crate) mod cache;
+pub(crate) mod filters;
+#[cfg(any(test, feature = "mock-chain"))]
+pub mod mock;
+
+/// The tip of the best chain a source has synced to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChainTip {
+	pub height: u32,
+	/// The timestamp of the tip's block header
+	pub time: u32,
+	/// The height of the best header the source knows of, if it can tell. It's ahead of the tip
+	/// while the source is still syncing.
+	pub best_header_height: Option<u32>,
+}
+
+/// A source of chain data, e. g. a bitcoind RPC client or an Electrum server. Errors fail the
+/// verification of the channel that required the data, such that its announcement is rejected.
+#[async_trait]
+pub trait ChainSource: Send + Sync {
+	/// The block at `height` in the best chain
+	async fn block_at_height(&self, height: u32) -> io::Result<Block>;
+
+	/// The output a short channel id refers to, along with the id of its transaction, or `None` if
+	/// there's no such output. By default, it's looked up in the block at `block_height`, but
+	/// sources with an index of outputs may override this to avoid retrieving whole blocks.
+	async fn funding_output(&self, block_height: u32, transaction_index: u32, output_index: u16) -> io::Result<Option<(Txid, TxOut)>> {
+		let mut block = self.block_at_height(block_height).await?;
+		if transaction_index as usize >= block.txdata.len() {
+			return Ok(None);
+		}
+		let mut transaction = block.txdata.swap_remove(transaction_index as usize);
+		if output_index as usize >= transaction.output.len() {
+			return Ok(None);
+		}
+		Ok(Some((transaction.compute_txid(), transaction.output.swap_remove(output_index as usize))))
+	}
+
+	/// The outputs of several channels funded in the block at `block_height`, given as pairs of
+	/// transaction and output index, in the same order. By default, each of them is looked up via
+	/// [`ChainSource::funding_output`], but sources retrieving whole blocks should override this to
+	/// retrieve the block only once.
+	async fn funding_outputs(&self, block_height: u32, positions: &[(u32, u16)]) -> io::Result<Vec<Option<(Txid, TxOut)>>> {
+		let mut outputs = Vec::with_capacity(positions.len());
+		for (transaction_index, output_index) in positions {
+			outputs.push(self.funding_output(block_height, *transaction_index, *output_index).await?);
+		}
+		Ok(outputs)
+	}
+
+	/// Notifies the source that a lookup of a funding output in the block at `block_height` has
+	/// been queued, e. g. such that the block can be retrieved ahead of time. Ignored by default.
+	fn expect_lookup(&self, _block_height: u32) {}
+
+	/// Notifies the source of the funding output script a channel's announcement implies, ahead of
+	/// its lookup, e. g. such that it can be checked against a compact block filter. Ignored by
+	/// default.
+	fn expect_funding_script(&self, _short_channel_id: u64, _script: &Script) {}
+
+	/// Whether `outpoint` is unspent, taking the mempool into account. Only queried if
+	/// `RAPID_GOSSIP_SYNC_SERVER_VERIFY_UNSPENT` is enabled.
+	async fn is_output_unspent(&self, outpoint: OutPoint) -> io::Result<bool>;
+
+	/// The tip of the best chain, for monitoring how far the source lags behind the network. Sources
+	/// that can't tell return an [`io::ErrorKind::Unsupported`] error, which disables the
+	/// monitoring.
+	async fn tip(&self) -> io::Result<ChainTip> {
+		Err(io::Error::new(io::ErrorKind::Unsupported, "The chain source doesn't report its tip"))
+	}
+
+	/// Whether the source can currently be reached, for the readiness check
+	async fn is_reachable(&self) -> bool;
+}
+
+/// The outputs at the given pairs of transaction and output index within `block`
+pub(crate) fn block_outputs(block: &Block, positions: &[(u32, u16)]) -> Vec<Option<(Txid, TxOut)>> {
+	positions.iter().map(|(transaction_index, output_index)| {
+		let transaction = block.txdata.get(*transaction_index as usize)?;
+		let output = transaction.output.get(*output_index as usize)?;
+		Some((transaction.compute_txid(), output.clone()))
+	}).collect()
+}
@@ -0,0 +1,209 @@
+//! Caches the blocks retrieved for verifying channels, and warms the cache by retrieving the blocks
+//! of upcoming UTXO lookups ahead of time. While catching up on gossip, most lookups are queued
+//! behind the ones in flight, and the blocks they need can be retrieved in the background
+//! meanwhile, rather than each lookup waiting for its block in turn.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use bitcoin::{Block, OutPoint, TxOut, Txid};
+use futures::future::{BoxFuture, FutureExt, Shared};
+use lightning::log_gossip;
+use lightning::util::logger::Logger;
+
+use crate::chain::{self, ChainSource, ChainTip};
+
+/// A block retrieval that may still be in flight. Retrieval errors are shared as their kind and
+/// description, as [`io::Error`] can't be cloned.
+type SharedBlock = Shared<BoxFuture<'static, Result<Arc<Block>, (io::ErrorKind, String)>>>;
+
+#[derive(Default)]
+struct CacheState {
+	/// The retrieved blocks and those being retrieved, by height
+	blocks: HashMap<u32, SharedBlock>,
+	/// The heights of the cached blocks in the order they were requested, for evicting the oldest
+	request_order: VecDeque<u32>,
+	/// The number of announced channels in each block whose lookups haven't started yet
+	expected_lookups: BTreeMap<u32, usize>,
+}
+
+/// Wraps a chain source, caching the last `capacity` blocks requested from it, and retrieving the
+/// blocks of the next `prefetch_depth` block heights with pending lookups whenever a lookup starts.
+///
+/// Funding outputs are always looked up in the cached blocks, so sources with an index of outputs
+/// shouldn't be wrapped.
+pub(crate) struct CachingChainSource<L: Deref + Send + Sync> where L::Target: Logger {
+	source: Arc<dyn ChainSource>,
+	state: Arc<Mutex<CacheState>>,
+	capacity: usize,
+	prefetch_depth: usize,
+	logger: L,
+}
+
+impl<L: Deref + Send + Sync> CachingChainSource<L> where L::Target: Logger {
+	pub(crate) fn new(source: Arc<dyn ChainSource>, capacity: usize, prefetch_depth: usize, logger: L) -> Self {
+		Self { source, state: Arc::new(Mutex::new(CacheState::default())), capacity, prefetch_depth, logger }
+	}
+
+	/// The cached retrieval of the block at `height`, starting it if there is none
+	fn retrieve(&self, state: &mut CacheState, height: u32) -> SharedBlock {
+		if let Some(block) = state.blocks.get(&height) {
+			return block.clone();
+		}
+		let source = Arc::clone(&self.source);
+		let state_ref = Arc::clone(&self.state);
+		let block = async move {
+			let result = source.block_at_height(height).await.map(Arc::new).map_err(|e| (e.kind(), e.to_string()));
+			if result.is_err() {
+				// let the next request retry the retrieval
+				state_ref.lock().unwrap().blocks.remove(&height);
+			}
+			result
+		}.boxed().shared();
+		state.blocks.insert(height, block.clone());
+		state.request_order.push_back(height);
+		while state.request_order.len() > self.capacity {
+			if let Some(evicted_height) = state.request_order.pop_front() {
+				state.blocks.remove(&evicted_height);
+			}
+		}
+		block
+	}
+
+	/// Start retrieving the blocks of the next lookups after the one of a channel at `height`
+	fn prefetch(&self, state: &mut CacheState, height: u32) {
+		let upcoming_heights: Vec<u32> = state.expected_lookups.range(height + 1..)
+			.map(|(upcoming_height, _)| *upcoming_height)
+			.filter(|upcoming_height| !state.blocks.contains_key(upcoming_height))
+			.take(self.prefetch_depth)
+			.collect();
+		for upcoming_height in upcoming_heights {
+			log_gossip!(self.logger, "Prefetching block {} for pending lookups", upcoming_height);
+			// shared futures only make progress while polled, so a task drives the retrieval
+			tokio::spawn(self.retrieve(state, upcoming_height));
+		}
+	}
+
+	/// The block at `height`, retrieved for `lookup_count` of the lookups expected in it
+	async fn block(&self, height: u32, lookup_count: usize) -> io::Result<Arc<Block>> {
+		let block = {
+			let mut state = self.state.lock().unwrap();
+			let block = self.retrieve(&mut state, height);
+			if let Some(expected_lookup_count) = state.expected_lookups.get_mut(&height) {
+				*expected_lookup_count = expected_lookup_count.saturating_sub(lookup_count);
+				if *expected_lookup_count == 0 {
+					state.expected_lookups.remove(&height);
+				}
+			}
+			self.prefetch(&mut state, height);
+			block
+		};
+		block.await.map_err(|(kind, description)| io::Error::new(kind, description))
+	}
+}
+
+#[async_trait]
+impl<L: Deref + Send + Sync> ChainSource for CachingChainSource<L> where L::Target: Logger {
+	async fn block_at_height(&self, height: u32) -> io::Result<Block> {
+		self.block(height, 0).await.map(|block| (*block).clone())
+	}
+
+	async fn funding_output(&self, block_height: u32, transaction_index: u32, output_index: u16) -> io::Result<Option<(Txid, TxOut)>> {
+		let block = self.block(block_height, 1).await?;
+		Ok(chain::block_outputs(&block, &[(transaction_index, output_index)]).pop().flatten())
+	}
+
+	async fn funding_outputs(&self, block_height: u32, positions: &[(u32, u16)]) -> io::Result<Vec<Option<(Txid, TxOut)>>> {
+		let block = self.block(block_height, positions.len()).await?;
+		Ok(chain::block_outputs(&block, positions))
+	}
+
+	fn expect_lookup(&self, block_height: u32) {
+		*self.state.lock().unwrap().expected_lookups.entry(block_height).or_default() += 1;
+	}
+
+	async fn is_output_unspent(&self, outpoint: OutPoint) -> io::Result<bool> {
+		self.source.is_output_unspent(outpoint).await
+	}
+
+	async fn tip(&self) -> io::Result<ChainTip> {
+		self.source.tip().await
+	}
+
+	async fn is_reachable(&self) -> bool {
+		self.source.is_reachable().await
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io;
+	use std::sync::Arc;
+	use std::sync::atomic::{AtomicUsize, Ordering};
+	use std::time::Duration;
+
+	use async_trait::async_trait;
+	use bitcoin::{Amount, Block, OutPoint, ScriptBuf, TxOut};
+
+	use crate::chain::cache::CachingChainSource;
+	use crate::chain::ChainSource;
+	use crate::chain::mock::MockChainSource;
+	use crate::types::tests::TestLogger;
+
+	/// Counts the blocks retrieved from a mock chain source
+	struct CountingChainSource {
+		source: MockChainSource,
+		retrieval_count: AtomicUsize,
+	}
+
+	#[async_trait]
+	impl ChainSource for CountingChainSource {
+		async fn block_at_height(&self, height: u32) -> io::Result<Block> {
+			self.retrieval_count.fetch_add(1, Ordering::AcqRel);
+			self.source.block_at_height(height).await
+		}
+
+		async fn is_output_unspent(&self, outpoint: OutPoint) -> io::Result<bool> {
+			self.source.is_output_unspent(outpoint).await
+		}
+
+		async fn is_reachable(&self) -> bool {
+			true
+		}
+	}
+
+	#[tokio::test]
+	async fn test_block_prefetching() {
+		let source = Arc::new(CountingChainSource { source: MockChainSource::new(), retrieval_count: AtomicUsize::new(0) });
+		for height in 100..104u64 {
+			source.source.add_funding_output(height << 40 | 1 << 16, TxOut { value: Amount::from_sat(height), script_pubkey: ScriptBuf::new() });
+		}
+		let chain_source = CachingChainSource::new(Arc::clone(&source) as Arc<dyn ChainSource>, 4, 2, Arc::new(TestLogger::with_id("block_cache".to_string())));
+		for height in [100, 100, 101, 102, 103] {
+			chain_source.expect_lookup(height);
+		}
+
+		let (_, output) = chain_source.funding_output(100, 1, 0).await.unwrap().unwrap();
+		assert_eq!(output.value, Amount::from_sat(100));
+		// the next two blocks with pending lookups are retrieved in the background
+		tokio::time::sleep(Duration::from_millis(50)).await;
+		assert_eq!(source.retrieval_count.load(Ordering::Acquire), 3);
+
+		// the second lookup in the same block starts prefetching the block after those
+		assert!(chain_source.funding_output(100, 2, 0).await.unwrap().is_none());
+		tokio::time::sleep(Duration::from_millis(50)).await;
+		assert_eq!(source.retrieval_count.load(Ordering::Acquire), 4);
+		assert_eq!(chain_source.funding_output(101, 1, 0).await.unwrap().unwrap().1.value, Amount::from_sat(101));
+		assert_eq!(chain_source.funding_output(102, 1, 0).await.unwrap().unwrap().1.value, Amount::from_sat(102));
+		assert_eq!(chain_source.funding_output(103, 1, 0).await.unwrap().unwrap().1.value, Amount::from_sat(103));
+		assert_eq!(source.retrieval_count.load(Ordering::Acquire), 4);
+
+		// failed retrievals aren't cached
+		assert!(chain_source.block_at_height(104).await.is_err());
+		assert!(chain_source.block_at_height(104).await.is_err());
+		assert_eq!(source.retrieval_count.load(Ordering::Acquire), 6);
+	}
+}
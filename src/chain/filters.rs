@@ -0,0 +1,124 @@
+//! The experimental `filters` verification mode, for bitcoind nodes that are pruned but serve
+//! compact block filters (BIP 157/158). The funding script a channel's announcement implies is
+//! checked against the filter of the block it refers to first, which rules out announcements of
+//! outputs that don't exist without retrieving the block. Only if the filter matches is the block
+//! retrieved to confirm the output and determine its amount, falling back to the configured
+//! fallback endpoints should the node have pruned it.
+//!
+//! As filters only cover scripts, an announcement whose keys don't match its funding output can't
+//! be told apart from one of a nonexistent output in this mode.
+
+use std::collections::HashMap;
+use std::io;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use bitcoin::{Block, BlockHash, OutPoint, Script, ScriptBuf, TxOut, Txid};
+use lightning::{log_gossip, log_warn};
+use lightning::util::logger::Logger;
+
+use crate::chain::{self, ChainSource, ChainTip};
+use crate::config;
+use crate::verifier::RestChainSource;
+
+pub(crate) struct FilterChainSource<L: Deref + Clone + Send + Sync + 'static> where L::Target: Logger {
+	source: Arc<RestChainSource<L>>,
+	/// Serves the blocks the source has pruned
+	fallback: Option<Arc<RestChainSource<L>>>,
+	/// The funding script announced for each channel pending verification
+	expected_scripts: Mutex<HashMap<u64, ScriptBuf>>,
+	logger: L,
+}
+
+impl<L: Deref + Clone + Send + Sync + 'static> FilterChainSource<L> where L::Target: Logger {
+	pub(crate) fn new(source: Arc<RestChainSource<L>>, fallback: Option<Arc<RestChainSource<L>>>, logger: L) -> Self {
+		Self { source, fallback, expected_scripts: Mutex::new(HashMap::new()), logger }
+	}
+
+	/// The block with the given hash, from the fallback endpoints if the source can't serve it
+	async fn block(&self, height: u32, block_hash: &BlockHash) -> io::Result<Block> {
+		let error = match self.source.block(block_hash).await {
+			Ok(block) => return Ok(block),
+			Err(error) => error,
+		};
+		let fallback = match &self.fallback {
+			Some(fallback) => fallback,
+			None => return Err(error),
+		};
+		log_warn!(self.logger, "Retrieving block {} from the fallback endpoints, as retrieving it failed: {}", height, error);
+		fallback.block(block_hash).await
+	}
+}
+
+#[async_trait]
+impl<L: Deref + Clone + Send + Sync + 'static> ChainSource for FilterChainSource<L> where L::Target: Logger {
+	async fn block_at_height(&self, height: u32) -> io::Result<Block> {
+		let block_hash = self.source.block_hash(height).await?;
+		self.block(height, &block_hash).await
+	}
+
+	async fn funding_output(&self, block_height: u32, transaction_index: u32, output_index: u16) -> io::Result<Option<(Txid, TxOut)>> {
+		Ok(self.funding_outputs(block_height, &[(transaction_index, output_index)]).await?.pop().flatten())
+	}
+
+	async fn funding_outputs(&self, block_height: u32, positions: &[(u32, u16)]) -> io::Result<Vec<Option<(Txid, TxOut)>>> {
+		let scripts: Vec<Option<ScriptBuf>> = {
+			let mut expected_scripts = self.expected_scripts.lock().unwrap();
+			positions.iter().map(|(transaction_index, output_index)| {
+				let short_channel_id = (block_height as u64) << 40 | (*transaction_index as u64) << 16 | *output_index as u64;
+				expected_scripts.remove(&short_channel_id)
+			}).collect()
+		};
+		let block_hash = self.source.block_hash(block_height).await?;
+
+		// outputs without an announced script, e. g. those being reverified, are always looked up
+		let mut is_candidate = vec![true; positions.len()];
+		if scripts.iter().any(|script| script.is_some()) {
+			let filter = self.source.block_filter(&block_hash).await?;
+			for (candidate, script) in is_candidate.iter_mut().zip(&scripts) {
+				if let Some(script) = script {
+					*candidate = filter.match_any(&block_hash, std::iter::once(script.as_bytes()))
+						.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("invalid block filter: {}", e)))?;
+				}
+			}
+		}
+		let candidates: Vec<(u32, u16)> = positions.iter().zip(&is_candidate)
+			.filter(|(_, is_candidate)| **is_candidate)
+			.map(|(position, _)| *position)
+			.collect();
+		if candidates.len() < positions.len() {
+			log_gossip!(self.logger, "Ruled out {} of {} funding outputs in block {} by its filter", positions.len() - candidates.len(), positions.len(), block_height);
+		}
+		if candidates.is_empty() {
+			return Ok(vec![None; positions.len()]);
+		}
+
+		let block = self.block(block_height, &block_hash).await?;
+		let mut candidate_outputs = chain::block_outputs(&block, &candidates).into_iter();
+		Ok(is_candidate.into_iter().map(|is_candidate| if is_candidate { candidate_outputs.next().flatten() } else { None }).collect())
+	}
+
+	fn expect_funding_script(&self, short_channel_id: u64, script: &Script) {
+		let mut expected_scripts = self.expected_scripts.lock().unwrap();
+		// announcements failing other checks are never looked up, so start over rather than
+		// accumulating them indefinitely
+		if expected_scripts.len() >= config::MAX_PENDING_ANNOUNCEMENTS {
+			expected_scripts.clear();
+		}
+		expected_scripts.insert(short_channel_id, script.to_owned());
+	}
+
+	async fn is_output_unspent(&self, outpoint: OutPoint) -> io::Result<bool> {
+		// pruned nodes still maintain the full UTXO set
+		self.source.is_output_unspent(outpoint).await
+	}
+
+	async fn tip(&self) -> io::Result<ChainTip> {
+		self.source.tip().await
+	}
+
+	async fn is_reachable(&self) -> bool {
+		self.source.is_reachable().await
+	}
+}
@@ -0,0 +1,218 @@
+//! A chain source serving fixture blocks rather than a full node's, such that channel verification
+//! can be exercised deterministically, e. g. in CI. Only available with the `mock-chain` feature.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use bitcoin::absolute::LockTime;
+use bitcoin::block::{Header, Version as BlockVersion};
+use bitcoin::consensus::deserialize;
+use bitcoin::hashes::Hash;
+use bitcoin::script::Builder;
+use bitcoin::secp256k1::PublicKey;
+use bitcoin::transaction::Version;
+use bitcoin::{Amount, Block, BlockHash, CompactTarget, OutPoint, Sequence, Transaction, TxIn, TxMerkleNode, TxOut, Witness};
+use lightning::ln::chan_utils::make_funding_redeemscript;
+
+use crate::chain::{ChainSource, ChainTip};
+
+/// Serves the blocks it's given, considering every output unspent unless marked as spent.
+///
+/// Blocks can be loaded from fixture files, or synthesized around the funding outputs of the
+/// channels a test announces, in which case all other transactions and outputs are placeholders.
+pub struct MockChainSource {
+	blocks: Mutex<HashMap<u32, Block>>,
+	spent_outputs: Mutex<HashSet<OutPoint>>,
+	is_reachable: AtomicBool,
+}
+
+impl MockChainSource {
+	pub fn new() -> Self {
+		Self {
+			blocks: Mutex::new(HashMap::new()),
+			spent_outputs: Mutex::new(HashSet::new()),
+			is_reachable: AtomicBool::new(true),
+		}
+	}
+
+	/// Load the blocks in `directory`, each of which is stored consensus-encoded in a file named
+	/// after its height, e. g. `870000.bin`. That's the format bitcoind's REST interface serves
+	/// blocks in, so fixtures can be captured from a node with
+	/// `curl http://localhost:8332/rest/block/<hash>.bin > <height>.bin`.
+	pub fn load_blocks<P: AsRef<Path>>(directory: P) -> io::Result<Self> {
+		let chain_source = Self::new();
+		for entry in fs::read_dir(directory)? {
+			let path = entry?.path();
+			let height = match path.file_name().and_then(|name| name.to_str()).and_then(|name| name.strip_suffix(".bin")) {
+				Some(height) => height.parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid block height in {}", path.display())))?,
+				None => continue,
+			};
+			let block = deserialize(&fs::read(&path)?)
+				.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid block {}: {}", path.display(), e)))?;
+			chain_source.add_block(height, block);
+		}
+		Ok(chain_source)
+	}
+
+	/// Serve `block` at `height`, replacing any block there
+	pub fn add_block(&self, height: u32, block: Block) {
+		self.blocks.lock().unwrap().insert(height, block);
+	}
+
+	/// Place `output` at the position `short_channel_id` refers to, synthesizing the block and
+	/// transaction as needed. Adding an output to a transaction changes its id, so the transaction's
+	/// outputs should all be added before any of them is looked up or spent.
+	pub fn add_funding_output(&self, short_channel_id: u64, output: TxOut) {
+		let block_height = (short_channel_id >> 40) as u32;
+		let transaction_index = ((short_channel_id >> 16) & 0xffffff) as usize;
+		let output_index = (short_channel_id & 0xffff) as usize;
+
+		let mut blocks = self.blocks.lock().unwrap();
+		let block = blocks.entry(block_height).or_insert_with(|| placeholder_block(block_height));
+		while block.txdata.len() <= transaction_index {
+			let placeholder_transaction = placeholder_transaction(block_height, block.txdata.len() as u32);
+			block.txdata.push(placeholder_transaction);
+		}
+		let transaction = &mut block.txdata[transaction_index];
+		if transaction.output.len() <= output_index {
+			transaction.output.resize(output_index + 1, TxOut::NULL);
+		}
+		transaction.output[output_index] = output;
+		block.header.merkle_root = block.compute_merkle_root().unwrap_or(TxMerkleNode::all_zeros());
+	}
+
+	/// Place the funding output of a channel between `bitcoin_key_1` and `bitcoin_key_2` such that
+	/// its announcement passes verification
+	pub fn add_channel_funding(&self, short_channel_id: u64, bitcoin_key_1: &PublicKey, bitcoin_key_2: &PublicKey, amount: Amount) {
+		let script_pubkey = make_funding_redeemscript(bitcoin_key_1, bitcoin_key_2).to_p2wsh();
+		self.add_funding_output(short_channel_id, TxOut { value: amount, script_pubkey });
+	}
+
+	/// The outpoint `short_channel_id` refers to, if it's among the blocks served
+	pub fn funding_outpoint(&self, short_channel_id: u64) -> Option<OutPoint> {
+		let blocks = self.blocks.lock().unwrap();
+		let transaction = blocks.get(&((short_channel_id >> 40) as u32))?.txdata.get(((short_channel_id >> 16) & 0xffffff) as usize)?;
+		let output_index = (short_channel_id & 0xffff) as u32;
+		if output_index as usize >= transaction.output.len() {
+			return None;
+		}
+		Some(OutPoint::new(transaction.compute_txid(), output_index))
+	}
+
+	/// Consider `outpoint` spent from now on
+	pub fn spend(&self, outpoint: OutPoint) {
+		self.spent_outputs.lock().unwrap().insert(outpoint);
+	}
+
+	/// Fail all requests while unreachable, as a full node that's down would
+	pub fn set_reachable(&self, is_reachable: bool) {
+		self.is_reachable.store(is_reachable, Ordering::Release);
+	}
+
+	fn check_reachable(&self) -> io::Result<()> {
+		if !self.is_reachable.load(Ordering::Acquire) {
+			return Err(io::Error::new(io::ErrorKind::ConnectionRefused, "The mock chain source is unreachable"));
+		}
+		Ok(())
+	}
+}
+
+fn placeholder_block(height: u32) -> Block {
+	let header = Header {
+		version: BlockVersion::TWO,
+		prev_blockhash: BlockHash::all_zeros(),
+		merkle_root: TxMerkleNode::all_zeros(),
+		time: height,
+		bits: CompactTarget::from_consensus(0x207fffff),
+		nonce: 0,
+	};
+	Block { header, txdata: Vec::new() }
+}
+
+/// A transaction without outputs, whose input commits to its position such that each placeholder
+/// has a distinct id
+fn placeholder_transaction(block_height: u32, transaction_index: u32) -> Transaction {
+	let script_sig = Builder::new()
+		.push_int(block_height as i64)
+		.push_int(transaction_index as i64)
+		.into_script();
+	Transaction {
+		version: Version::TWO,
+		lock_time: LockTime::ZERO,
+		input: vec![TxIn { previous_output: OutPoint::null(), script_sig, sequence: Sequence::MAX, witness: Witness::new() }],
+		output: Vec::new(),
+	}
+}
+
+impl Default for MockChainSource {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[async_trait]
+impl ChainSource for MockChainSource {
+	async fn block_at_height(&self, height: u32) -> io::Result<Block> {
+		self.check_reachable()?;
+		self.blocks.lock().unwrap().get(&height).cloned()
+			.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("There's no block at height {}", height)))
+	}
+
+	async fn is_output_unspent(&self, outpoint: OutPoint) -> io::Result<bool> {
+		self.check_reachable()?;
+		Ok(!self.spent_outputs.lock().unwrap().contains(&outpoint))
+	}
+
+	async fn tip(&self) -> io::Result<ChainTip> {
+		self.check_reachable()?;
+		let blocks = self.blocks.lock().unwrap();
+		let (height, block) = blocks.iter().max_by_key(|(height, _)| **height)
+			.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "There are no blocks"))?;
+		Ok(ChainTip { height: *height, time: block.header.time, best_header_height: None })
+	}
+
+	async fn is_reachable(&self) -> bool {
+		self.is_reachable.load(Ordering::Acquire)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use bitcoin::{Amount, ScriptBuf, TxOut};
+
+	use crate::chain::ChainSource;
+	use crate::chain::mock::MockChainSource;
+
+	#[tokio::test]
+	async fn test_synthesized_blocks() {
+		let chain_source = MockChainSource::new();
+		let short_channel_id = 870000 << 40 | 3 << 16 | 1;
+		chain_source.add_funding_output(short_channel_id, TxOut { value: Amount::from_sat(250_000), script_pubkey: ScriptBuf::new() });
+
+		let block = chain_source.block_at_height(870000).await.unwrap();
+		assert_eq!(block.txdata.len(), 4);
+		assert_eq!(block.txdata[3].output.len(), 2);
+		assert_ne!(block.txdata[0].compute_txid(), block.txdata[1].compute_txid());
+		let (txid, output) = chain_source.funding_output(870000, 3, 1).await.unwrap().unwrap();
+		assert_eq!(output.value, Amount::from_sat(250_000));
+		assert!(chain_source.funding_output(870000, 4, 0).await.unwrap().is_none());
+		assert!(chain_source.block_at_height(870001).await.is_err());
+
+		assert_eq!(chain_source.tip().await.unwrap().height, 870000);
+
+		let outpoint = chain_source.funding_outpoint(short_channel_id).unwrap();
+		assert_eq!(outpoint.txid, txid);
+		assert!(chain_source.is_output_unspent(outpoint).await.unwrap());
+		chain_source.spend(outpoint);
+		assert!(!chain_source.is_output_unspent(outpoint).await.unwrap());
+
+		chain_source.set_reachable(false);
+		assert!(!chain_source.is_reachable().await);
+		assert!(chain_source.block_at_height(870000).await.is_err());
+	}
+}
@@ -0,0 +1,106 @@
+//! Tracks when each channel last had gossip persisted, such that snapshots only need to look up
+//! the channels that changed within their scope rather than the whole network graph.
+//!
+//! The index only covers the gossip persisted by this process since it was created, so scopes
+//! reaching back further than that fall back to looking up all channels.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config;
+use crate::types::GossipMessage;
+
+pub(crate) struct ChannelChangeIndex {
+	/// When the index started tracking changes
+	tracked_since: AtomicU32,
+	/// The time each channel last had an announcement or update persisted
+	last_changed: Mutex<HashMap<u64, u32>>,
+}
+
+impl ChannelChangeIndex {
+	pub(crate) fn new() -> Self {
+		Self::tracking_since(current_time())
+	}
+
+	fn tracking_since(tracked_since: u32) -> Self {
+		Self { tracked_since: AtomicU32::new(tracked_since), last_changed: Mutex::new(HashMap::new()) }
+	}
+
+	/// Forget the changes recorded so far and start over, e. g. once changes have been persisted
+	/// by another process in the meantime
+	pub(crate) fn restart(&self) {
+		let mut last_changed = self.last_changed.lock().unwrap();
+		last_changed.clear();
+		self.tracked_since.store(current_time(), Ordering::Relaxed);
+	}
+
+	/// Record the channels of a persisted batch of gossip as changed as of now. Must be called once
+	/// the batch has been inserted, so the time is no earlier than the one the store recorded.
+	pub(crate) fn record(&self, short_channel_ids: &[u64]) {
+		self.record_at(short_channel_ids, current_time());
+	}
+
+	fn record_at(&self, short_channel_ids: &[u64], changed_at: u32) {
+		let mut last_changed = self.last_changed.lock().unwrap();
+		for short_channel_id in short_channel_ids {
+			last_changed.insert(*short_channel_id, changed_at);
+		}
+	}
+
+	/// The channels with gossip seen at or after `since`, or `None` if the index doesn't reach back
+	/// that far. Allows for the store's clock to be ahead by up to [`config::MAX_STORE_CLOCK_SKEW`].
+	pub(crate) fn changed_since(&self, since: u32) -> Option<HashSet<u64>> {
+		let margin = config::MAX_STORE_CLOCK_SKEW.as_secs() as u32;
+		if since < self.tracked_since.load(Ordering::Relaxed).saturating_add(margin) {
+			return None;
+		}
+		let threshold = since - margin;
+		Some(self.last_changed.lock().unwrap().iter()
+			.filter(|(_, changed_at)| **changed_at >= threshold)
+			.map(|(short_channel_id, _)| *short_channel_id)
+			.collect())
+	}
+}
+
+/// The channels a batch of gossip concerns
+pub(crate) fn changed_channels(batch: &[GossipMessage]) -> Vec<u64> {
+	batch.iter().filter_map(|message| match message {
+		GossipMessage::ChannelAnnouncement(announcement, _, _) => Some(announcement.contents.short_channel_id),
+		GossipMessage::ChannelUpdate(update, _) => Some(update.contents.short_channel_id),
+		GossipMessage::NodeAnnouncement(_, _) => None,
+	}).collect()
+}
+
+fn current_time() -> u32 {
+	SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as u32
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::HashSet;
+
+	use crate::changes::ChannelChangeIndex;
+	use crate::config;
+
+	#[test]
+	fn test_changed_since() {
+		let margin = config::MAX_STORE_CLOCK_SKEW.as_secs() as u32;
+		let index = ChannelChangeIndex::tracking_since(1_700_000_000);
+		index.record_at(&[1, 2], 1_700_001_000);
+		index.record_at(&[2, 3], 1_700_002_000);
+
+		// the index doesn't know what changed before it started tracking
+		assert_eq!(index.changed_since(1_700_000_000), None);
+		assert_eq!(index.changed_since(1_700_000_000 + margin), Some(HashSet::from([1, 2, 3])));
+		assert_eq!(index.changed_since(1_700_001_500), Some(HashSet::from([2, 3])));
+		// changes recorded shortly before the threshold may have been seen after it by the store
+		assert_eq!(index.changed_since(1_700_002_000 + margin), Some(HashSet::from([2, 3])));
+		assert_eq!(index.changed_since(1_700_002_001 + margin), Some(HashSet::new()));
+
+		// once restarted, the index no longer reaches back as far
+		index.restart();
+		assert_eq!(index.changed_since(1_700_002_000 + margin), None);
+	}
+}
@@ -0,0 +1,106 @@
+use std::io::{self, Read, Write};
+
+/// A codec the snapshots can be precompressed with, so that static file servers and CDNs can
+/// negotiate the content encoding without having to compress on the fly.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum SnapshotCompression {
+	Gzip,
+	Brotli,
+	Zstd,
+}
+
+impl SnapshotCompression {
+	pub(crate) fn from_name(name: &str) -> Option<Self> {
+		match name {
+			"gzip" | "gz" => Some(Self::Gzip),
+			"brotli" | "br" => Some(Self::Brotli),
+			"zstd" | "zst" => Some(Self::Zstd),
+			_ => None,
+		}
+	}
+
+	/// The suffix appended to the file name of the uncompressed snapshot
+	pub(crate) fn file_extension(&self) -> &'static str {
+		match self {
+			Self::Gzip => "gz",
+			Self::Brotli => "br",
+			Self::Zstd => "zst",
+		}
+	}
+
+	/// The token identifying this codec in `Accept-Encoding` and `Content-Encoding` headers
+	pub(crate) fn content_encoding(&self) -> &'static str {
+		match self {
+			Self::Gzip => "gzip",
+			Self::Brotli => "br",
+			Self::Zstd => "zstd",
+		}
+	}
+
+	pub(crate) fn default_level(&self) -> u32 {
+		match self {
+			Self::Gzip => 9,
+			Self::Brotli => 11,
+			Self::Zstd => 19,
+		}
+	}
+
+	pub(crate) fn max_level(&self) -> u32 {
+		match self {
+			Self::Gzip => 9,
+			Self::Brotli => 11,
+			Self::Zstd => 22,
+		}
+	}
+
+	pub(crate) fn compress(&self, data: &[u8], level: u32) -> Vec<u8> {
+		match self {
+			Self::Gzip => {
+				let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::new(level));
+				encoder.write_all(data).unwrap();
+				encoder.finish().unwrap()
+			}
+			Self::Brotli => {
+				let mut output = Vec::new();
+				{
+					let mut encoder = brotli::CompressorWriter::new(&mut output, 4096, level, 22);
+					encoder.write_all(data).unwrap();
+				}
+				output
+			}
+			Self::Zstd => zstd::encode_all(data, level as i32).unwrap(),
+		}
+	}
+
+	pub(crate) fn decompress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+		let mut output = Vec::new();
+		match self {
+			Self::Gzip => flate2::read::GzDecoder::new(data).read_to_end(&mut output)?,
+			Self::Brotli => brotli::Decompressor::new(data, 4096).read_to_end(&mut output)?,
+			Self::Zstd => return zstd::decode_all(data),
+		};
+		Ok(output)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::compression::SnapshotCompression;
+
+	#[test]
+	fn test_compression_round_trip() {
+		let data: Vec<u8> = (0..10_000u32).map(|i| (i % 7) as u8).collect();
+
+		let gzip = SnapshotCompression::Gzip.compress(&data, 9);
+		assert_eq!(SnapshotCompression::Gzip.decompress(&gzip).unwrap(), data);
+
+		let brotli = SnapshotCompression::Brotli.compress(&data, 11);
+		assert_eq!(SnapshotCompression::Brotli.decompress(&brotli).unwrap(), data);
+
+		let zstd = SnapshotCompression::Zstd.compress(&data, 19);
+		assert_eq!(SnapshotCompression::Zstd.decompress(&zstd).unwrap(), data);
+		assert!(SnapshotCompression::Zstd.decompress(&gzip).is_err());
+
+		assert!(gzip.len() < data.len() && brotli.len() < data.len() && zstd.len() < data.len());
+	}
+}
@@ -1,27 +1,38 @@
+use crate::compression::SnapshotCompression;
+use crate::config_file;
 use crate::hex_utils;
-use crate::verifier::ChainVerifier;
+use crate::listener::ListenAddress;
+use crate::logging::{LogFilter, LogFormat};
+use crate::profiles::SnapshotProfile;
+use crate::upload::S3UploadConfig;
+use crate::chain::ChainSource;
+use crate::verifier::{ChainVerifier, RestChainSource};
 
+use std::collections::HashMap;
 use std::env;
-use std::net::{SocketAddr, ToSocketAddrs};
+use std::net::{Ipv6Addr, SocketAddr, ToSocketAddrs};
+use std::str::FromStr;
 use std::ops::Deref;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock, RwLock};
 use std::time::Duration;
 
 use bitcoin::io::Cursor;
 use bitcoin::Network;
 use bitcoin::hashes::hex::FromHex;
-use bitcoin::secp256k1::PublicKey;
+use bitcoin::secp256k1::{PublicKey, SecretKey};
 use futures::stream::{FuturesUnordered, StreamExt};
-use lightning::ln::msgs::ChannelAnnouncement;
+use lightning::ln::msgs::{ChannelAnnouncement, SocketAddress};
+use lightning::routing::gossip::NetworkGraph;
 use lightning::util::logger::Logger;
 use lightning::util::ser::Readable;
 use lightning_block_sync::http::HttpEndpoint;
-use lightning_block_sync::rest::RestClient;
 use tokio_postgres::Config;
+use tokio_postgres::config::SslMode;
 
 use tokio::sync::Semaphore;
 
-pub(crate) const SCHEMA_VERSION: i32 = 15;
+/// The latest Postgres schema, reached by applying the last of the embedded migrations
+pub(crate) const SCHEMA_VERSION: i32 = 18;
 pub(crate) const SYMLINK_GRANULARITY_INTERVAL: u32 = 3600 * 3; // three hours
 pub(crate) const MAX_SNAPSHOT_SCOPE: u32 = 3600 * 24 * 21; // three weeks
 // generate symlinks based on a 3-hour-granularity
@@ -31,20 +42,183 @@ pub(crate) const MAX_SNAPSHOT_SCOPE: u32 = 3600 * 24 * 21; // three weeks
 pub(crate) const CHANNEL_REMINDER_AGE: Duration = Duration::from_secs(6 * 24 * 60 * 60);
 
 /// The interval after which graph data gets pruned after it was first seen
-/// This should match the LDK default pruning interval, which is 14 days
+/// This must match the LDK pruning interval, which is 14 days, and is the default staleness horizon
 pub(crate) const PRUNE_INTERVAL: Duration = Duration::from_secs(14 * 24 * 60 * 60);
 
 /// Maximum number of default features to calculate for node announcements
 pub(crate) const NODE_DEFAULT_FEATURE_COUNT: u8 = 6;
+pub(crate) const SUPPORTED_SERIALIZATION_VERSIONS: [u8; 2] = [1, 2];
+/// The record type identifying a node alias within a node's additional data
+pub(crate) const NODE_ALIAS_RECORD_TYPE: u8 = 1;
 
 /// The number of successful peer connections to await prior to continuing to gossip storage.
 /// The application will still work if the number of specified peers is lower, as long as there is
 /// at least one successful peer connection, but it may result in long startup times.
 pub(crate) const CONNECTED_PEER_ASSERTION_LIMIT: usize = 5;
 pub(crate) const DOWNLOAD_NEW_GOSSIP: bool = true;
+/// How many peers to connect to when discovering them via DNS seeds
+pub(crate) const DEFAULT_DNS_SEED_PEER_COUNT: usize = 8;
+pub(crate) const DEFAULT_MAX_INBOUND_PEERS: usize = 16;
+/// The port assumed for peers configured without one, which Lightning nodes listen on by default
+pub(crate) const DEFAULT_PEER_PORT: u16 = 9735;
+pub(crate) const PEER_CONNECTION_TIMEOUT: Duration = Duration::from_secs(10);
+pub(crate) const PROXIED_PEER_CONNECTION_TIMEOUT: Duration = Duration::from_secs(30);
+/// How many validated gossip messages a subscriber of the gossip stream can fall behind by
+pub(crate) const GOSSIP_STREAM_BUFFER_SIZE: usize = 4096;
+pub(crate) const DEFAULT_GOSSIP_STREAM_MAX_SUBSCRIBERS: usize = 64;
+/// How often the gossip stream is sent a comment to keep idle connections from being closed
+pub(crate) const GOSSIP_STREAM_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
+/// How often the network graph is checked for channels that have been removed from it
+pub(crate) const GOSSIP_STREAM_REMOVAL_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// How often the recorded gossip is flushed to disk while no further messages are received
+pub(crate) const GOSSIP_RECORDING_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+pub(crate) const DEFAULT_GOSSIP_RECORDING_FILE_SIZE_MB: u64 = 100;
+/// How many accepted gossip messages may wait to be recorded before further ones are dropped
+pub(crate) const GOSSIP_RECORDING_QUEUE_SIZE: usize = 65_536;
+/// How many channel announcements pending verification have their origin remembered for the
+/// recording and the per-peer gossip stats, or their replayed seen time
+pub(crate) const MAX_PENDING_ANNOUNCEMENTS: usize = 10_000;
+/// How many of the latest channel announcements whose bitcoin keys didn't match the funding output
+/// are reported under `/api/funding_script_mismatches`
+pub(crate) const MAX_REPORTED_FUNDING_SCRIPT_MISMATCHES: usize = 100;
+/// How many peer commands issued at runtime can be pending before issuing more waits
+pub(crate) const PEER_COMMAND_QUEUE_SIZE: usize = 16;
+/// How often the connected peers' health is checked
+pub(crate) const PEER_HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+pub(crate) const DEFAULT_PEER_SILENCE_TIMEOUT: Duration = Duration::from_secs(600);
+pub(crate) const DEFAULT_PEER_MAX_DISCONNECTIONS: usize = 5;
+pub(crate) const DEFAULT_GOSSIP_STALL_TIMEOUT: Duration = Duration::from_secs(600);
+/// The delay before reconnecting to a peer that disconnected. It doubles with every consecutive
+/// failed connection attempt, up to the configured maximum, and is randomized by up to half.
+pub(crate) const DEFAULT_PEER_RECONNECT_BASE_DELAY_MS: u64 = 1000;
+pub(crate) const DEFAULT_PEER_RECONNECT_MAX_DELAY_SECS: u64 = 300;
+
+/// The default number of blocks that UTXO lookups may be in flight against bitcoind for at any
+/// given time. Each block's lookups are batched, and additional ones are queued and served in the
+/// order they were requested.
+pub(crate) const DEFAULT_MAX_CONCURRENT_UTXO_LOOKUPS: usize = 32;
+/// How many of the blocks retrieved for UTXO lookups are kept in memory by default
+pub(crate) const DEFAULT_BLOCK_CACHE_SIZE: usize = 16;
+/// How many blocks with pending UTXO lookups are retrieved ahead of time by default
+pub(crate) const DEFAULT_BLOCK_PREFETCH_DEPTH: usize = 8;
+/// The defaults of the block cache and prefetch depth under the constrained memory profile
+pub(crate) const CONSTRAINED_BLOCK_CACHE_SIZE: usize = 4;
+pub(crate) const CONSTRAINED_BLOCK_PREFETCH_DEPTH: usize = 2;
+/// How many funding amounts are cached under the constrained memory profile, which are otherwise
+/// kept for each channel looked up
+pub(crate) const CONSTRAINED_FUNDING_AMOUNT_CACHE_ENTRIES: usize = 20_000;
+/// How many channel directions' latest persisted updates are tracked for deduplicating updates
+/// under the constrained memory profile, which are otherwise tracked for each channel direction
+pub(crate) const CONSTRAINED_DEDUPLICATED_UPDATE_DIRECTIONS: usize = 50_000;
+/// How many on-demand snapshots are held in memory under the constrained memory profile
+pub(crate) const CONSTRAINED_DYNAMIC_SNAPSHOT_CACHE_ENTRIES: usize = 16;
+/// How often the memory held by the network graph and the caches alongside it is estimated
+pub(crate) const MEMORY_ESTIMATE_INTERVAL: Duration = Duration::from_secs(60);
+/// The most excess data a node announcement may carry for LDK to relay it, mirroring its own limit
+pub(crate) const MAX_EXCESS_BYTES_FOR_RELAY: usize = 1024;
+/// How many of a round's snapshot scopes are calculated and serialized at once
+pub(crate) const DEFAULT_SNAPSHOT_CONCURRENCY: usize = 4;
+
+/// How often a bitcoind REST request that failed for transient reasons (e. g. bitcoind restarting)
+/// is retried before the lookup is given up on.
+pub(crate) const DEFAULT_BITCOIN_REST_RETRIES: u32 = 5;
+/// The delay before the first retry of a failed bitcoind REST request. It doubles with every
+/// subsequent attempt, up to [`MAX_BITCOIN_REST_RETRY_DELAY`].
+pub(crate) const DEFAULT_BITCOIN_REST_RETRY_DELAY_MS: u64 = 500;
+pub(crate) const MAX_BITCOIN_REST_RETRY_DELAY: Duration = Duration::from_secs(30);
+/// How long connecting to a bitcoind REST endpoint may take before the attempt is given up on (in
+/// seconds)
+pub(crate) const DEFAULT_BITCOIN_REST_CONNECT_TIMEOUT: u64 = 5;
+/// How long a single bitcoind REST request may take before it's considered hung. Full blocks may
+/// take a while to be served, so this is rather generous (in seconds).
+pub(crate) const DEFAULT_BITCOIN_REST_REQUEST_TIMEOUT: u64 = 30;
+/// How many connections are kept open to each bitcoind REST endpoint at most
+pub(crate) const DEFAULT_BITCOIN_REST_MAX_CONNECTIONS: usize = 8;
+/// How long a bitcoind REST request may take before it's logged as slow
+pub(crate) const DEFAULT_BITCOIN_REST_SLOW_REQUEST_MS: u64 = 2_000;
+/// How often the chain source's tip is compared against the wall clock
+pub(crate) const CHAIN_TIP_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// The default number of blocks the chain source may fall behind before it's reported, which
+/// corresponds to a tip that's about an hour old
+pub(crate) const DEFAULT_CHAIN_TIP_LAG_THRESHOLD: u32 = 6;
+/// How often bitcoind REST endpoints that were marked as unhealthy are probed for recovery
+pub(crate) const BITCOIN_REST_HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// How many gossip messages may be queued for persistence before gossip processing is held up
+pub(crate) const DEFAULT_PERSISTENCE_QUEUE_SIZE: usize = 100;
+/// The maximum number of gossip messages persisted within a single batch
+pub(crate) const DEFAULT_DB_BATCH_SIZE: usize = 500;
+/// How long gossip messages may wait for their batch to fill up before being persisted regardless
+pub(crate) const DEFAULT_DB_FLUSH_INTERVAL_MS: u64 = 1000;
+/// How far the read replica may lag behind the primary before snapshots are calculated from the
+/// primary instead
+pub(crate) const MAX_READ_REPLICA_LAG: Duration = Duration::from_secs(60);
+/// How far the store's clock, which determines when gossip was seen, may be ahead of ours without
+/// the snapshots' channel change lookups missing gossip
+pub(crate) const MAX_STORE_CLOCK_SKEW: Duration = Duration::from_secs(60);
+/// How often a channel update is persisted despite leaving its channel direction unchanged, when
+/// deduplicating updates. Those refreshes need to be more frequent than the difference between
+/// [`staleness_horizon`] and [`CHANNEL_REMINDER_AGE`] for the reminders to keep being sent.
+pub(crate) const DEDUPLICATED_UPDATE_REFRESH_INTERVAL: Duration = Duration::from_secs(24 * 3600);
+/// How many of the most recent updates of each channel direction are kept regardless of their age
+/// once channel update retention is enabled
+pub(crate) const DEFAULT_UPDATE_RETENTION_COUNT: u32 = 1;
+/// How many days archived snapshots are kept for by default
+pub(crate) const DEFAULT_SNAPSHOT_ARCHIVE_RETENTION_DAYS: u64 = 30;
+/// The share of a full snapshot's channels whose policies may differ from the network graph's when
+/// verifying it, as gossip keeps being applied to the graph while the snapshot is calculated
+pub(crate) const SNAPSHOT_VERIFICATION_MAX_MISMATCH_RATIO: f64 = 0.01;
+/// By how many percent a snapshot's size, channel announcement or update count may differ from the
+/// previous round's before a warning is logged by default
+pub(crate) const DEFAULT_SNAPSHOT_DEVIATION_THRESHOLD_PERCENT: u32 = 50;
+/// How many rounds of each scope's snapshot sizes are kept in memory and reported under
+/// `/api/stats`, which at the default interval covers two days
+pub(crate) const SNAPSHOT_HISTORY_LENGTH: usize = 48;
+/// How often channel updates beyond the retention window are pruned
+pub(crate) const UPDATE_PRUNING_INTERVAL: Duration = Duration::from_secs(3600);
+/// How often the network graph is cached to disk while gossip keeps arriving
+pub(crate) const DEFAULT_GRAPH_CHECKPOINT_INTERVAL_SECS: u64 = 600;
+/// How far before the cached network graph was written the gossip it's reconciled with on startup
+/// goes back. Gossip is persisted some time after being applied to the graph, and reapplying it is
+/// harmless.
+pub(crate) const GRAPH_RECONCILIATION_MARGIN: Duration = Duration::from_secs(3600);
+/// How long an on-demand snapshot for an arbitrary timestamp is served from memory
+pub(crate) const DEFAULT_DYNAMIC_SNAPSHOT_CACHE_TTL_SECS: u64 = 60;
+/// Upper bound on the number of on-demand snapshots held in memory at any given time
+pub(crate) const MAX_DYNAMIC_SNAPSHOT_CACHE_ENTRIES: usize = 256;
+/// How many requests to the HTTP endpoints computing their responses on demand each client may
+/// send at once, before being limited to the configured rate
+pub(crate) const DEFAULT_HTTP_RATE_LIMIT_BURST: u32 = 10;
+/// Upper bound on the number of clients whose request rate is tracked, beyond which those that
+/// haven't exhausted their allowance are forgotten
+pub(crate) const MAX_RATE_LIMITED_CLIENTS: usize = 100_000;
+/// How often the HTTP server's TLS certificate files are checked for changes
+pub(crate) const TLS_CERTIFICATE_RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+/// How long clients of the HTTP server may take to complete a TLS handshake
+pub(crate) const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// How many served snapshot requests are queued for recording, beyond which they're only counted
+/// in the aggregate statistics
+pub(crate) const REQUEST_ANALYTICS_QUEUE_SIZE: usize = 4096;
+/// Upper bound on the number of snapshot requests recorded at once
+pub(crate) const REQUEST_ANALYTICS_BATCH_SIZE: usize = 500;
+/// Upper bound on the number of distinct user agents tallied, beyond which further ones are
+/// counted as `other`
+pub(crate) const MAX_TRACKED_USER_AGENTS: usize = 100;
+/// How many days stats samples are kept for by default
+pub(crate) const DEFAULT_STATS_RETENTION_DAYS: u64 = 365;
+/// How far back `/api/stats/history` goes unless asked for a starting timestamp
+pub(crate) const DEFAULT_STATS_HISTORY_WINDOW: Duration = Duration::from_secs(7 * 24 * 3600);
+/// Upper bound on the number of stats samples reported per request
+pub(crate) const MAX_STATS_SAMPLES_PER_REQUEST: u32 = 10_000;
+/// Upper bound on the length of the user agents recorded
+pub(crate) const MAX_USER_AGENT_LENGTH: usize = 64;
+/// How often the leader checks that it still holds the leader lock, and followers try to take it.
+/// Elections in tests are settled quickly.
+pub(crate) const LEADER_ELECTION_INTERVAL: Duration = Duration::from_millis(if cfg!(test) { 100 } else { 5000 });
+/// Upper bound on the length of the lines read from admin socket clients
+pub(crate) const MAX_ADMIN_COMMAND_LENGTH: usize = 1024;
 
 pub(crate) fn snapshot_generation_interval() -> u32 {
-	let interval = env::var("RAPID_GOSSIP_SYNC_SERVER_SNAPSHOT_INTERVAL").unwrap_or(SYMLINK_GRANULARITY_INTERVAL.to_string())
+	let interval = var("RAPID_GOSSIP_SYNC_SERVER_SNAPSHOT_INTERVAL").unwrap_or(SYMLINK_GRANULARITY_INTERVAL.to_string())
 		.parse::<u32>()
 		.expect("RAPID_GOSSIP_SYNC_SERVER_SNAPSHOT_INTERVAL env variable must be a u32.");
 	assert!(interval > 0, "RAPID_GOSSIP_SYNC_SERVER_SNAPSHOT_INTERVAL must be positive");
@@ -52,68 +226,999 @@ pub(crate) fn snapshot_generation_interval() -> u32 {
 	interval
 }
 
+/// Whether a final round of snapshots is captured after flushing the persisted gossip on shutdown,
+/// such that the published snapshots include all gossip received up until then
+pub(crate) fn snapshot_on_shutdown() -> bool {
+	var("RAPID_GOSSIP_SYNC_SERVER_SNAPSHOT_ON_SHUTDOWN").unwrap_or("false".to_string())
+		.parse::<bool>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_SNAPSHOT_ON_SHUTDOWN env variable must be a boolean.")
+}
+
+/// Whether each snapshot is applied to an empty network graph after being generated, withholding
+/// the round of snapshots if any fails to apply or the result disagrees with the network graph
+pub(crate) fn snapshot_verification() -> bool {
+	var("RAPID_GOSSIP_SYNC_SERVER_SNAPSHOT_VERIFICATION").unwrap_or("true".to_string())
+		.parse::<bool>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_SNAPSHOT_VERIFICATION env variable must be a boolean.")
+}
+
+/// By how many percent a snapshot's size, channel announcement or update count may differ from the
+/// previous round's before a warning is logged, or `None` if no warnings are logged
+pub(crate) fn snapshot_deviation_threshold_percent() -> Option<u32> {
+	let threshold = var("RAPID_GOSSIP_SYNC_SERVER_SNAPSHOT_DEVIATION_THRESHOLD").unwrap_or(DEFAULT_SNAPSHOT_DEVIATION_THRESHOLD_PERCENT.to_string())
+		.parse::<u32>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_SNAPSHOT_DEVIATION_THRESHOLD env variable must be a u32.");
+	Some(threshold).filter(|threshold| *threshold > 0)
+}
+
+/// The directory every round of snapshots is archived in, which is disabled by default
+pub(crate) fn snapshot_archive_directory(network: Network) -> Option<String> {
+	network_env_var("RAPID_GOSSIP_SYNC_SERVER_ARCHIVE_PATH", network).ok()
+}
+
+/// How long archived snapshots are kept for, or indefinitely if `None`
+pub(crate) fn snapshot_archive_retention() -> Option<Duration> {
+	let retention_days = var("RAPID_GOSSIP_SYNC_SERVER_ARCHIVE_RETENTION_DAYS").unwrap_or(DEFAULT_SNAPSHOT_ARCHIVE_RETENTION_DAYS.to_string())
+		.parse::<u64>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_ARCHIVE_RETENTION_DAYS env variable must be a u64.");
+	// zero days keeps snapshots indefinitely
+	(retention_days > 0).then(|| Duration::from_secs(retention_days * 24 * 3600))
+}
+
+/// Whether archived snapshots are uploaded alongside the served ones if uploads are enabled
+pub(crate) fn snapshot_archive_upload() -> bool {
+	var("RAPID_GOSSIP_SYNC_SERVER_ARCHIVE_UPLOAD").unwrap_or("false".to_string())
+		.parse::<bool>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_ARCHIVE_UPLOAD env variable must be a boolean.")
+}
+
+/// How old the served snapshots may get before the server is reported as unhealthy, defaulting to
+/// twice the snapshot interval.
+pub(crate) fn max_snapshot_age() -> Duration {
+	let max_age = var("RAPID_GOSSIP_SYNC_SERVER_MAX_SNAPSHOT_AGE").ok().map(|age| age.parse::<u64>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_MAX_SNAPSHOT_AGE env variable must be a u64."));
+	Duration::from_secs(max_age.unwrap_or(2 * snapshot_generation_interval() as u64))
+}
+
+/// The ranges the snapshots are calculated for, sorted in ascending order and always ending with a
+/// full snapshot (`u64::MAX`). Unless configured, the range is doubled starting from the snapshot
+/// interval until reaching [`MAX_SNAPSHOT_SCOPE`].
+pub(crate) fn snapshot_scopes() -> Vec<u64> {
+	if let Ok(scopes) = var("RAPID_GOSSIP_SYNC_SERVER_SNAPSHOT_SCOPES") {
+		return parse_snapshot_scopes(&scopes).expect("RAPID_GOSSIP_SYNC_SERVER_SNAPSHOT_SCOPES env variable must be a comma separated list of durations (e. g. 3h, 1d, or 86400) or full.");
+	}
+
+	let mut snapshot_scopes = vec![];
+	// double the coefficient until it reaches the maximum (limited) snapshot scope
+	let mut current_scope = snapshot_generation_interval() as u64;
+	loop {
+		snapshot_scopes.push(current_scope);
+		if current_scope >= MAX_SNAPSHOT_SCOPE as u64 {
+			snapshot_scopes.push(u64::MAX);
+			break;
+		}
+
+		// double the current factor
+		current_scope <<= 1;
+	}
+	snapshot_scopes
+}
+
+pub(crate) fn snapshot_concurrency() -> usize {
+	let limit = var("RAPID_GOSSIP_SYNC_SERVER_SNAPSHOT_CONCURRENCY").unwrap_or(DEFAULT_SNAPSHOT_CONCURRENCY.to_string())
+		.parse::<usize>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_SNAPSHOT_CONCURRENCY env variable must be a usize.");
+	assert!(limit > 0, "RAPID_GOSSIP_SYNC_SERVER_SNAPSHOT_CONCURRENCY must be positive");
+	limit
+}
+
+fn parse_snapshot_scopes(scopes: &str) -> Result<Vec<u64>, &'static str> {
+	let mut snapshot_scopes = Vec::new();
+	for scope in scopes.split(',').map(str::trim).filter(|scope| !scope.is_empty()) {
+		let scope = if scope == "full" {
+			u64::MAX
+		} else {
+			let (value, multiplier) = match scope.char_indices().last() {
+				Some((index, 'm')) => (&scope[..index], 60),
+				Some((index, 'h')) => (&scope[..index], 3600),
+				Some((index, 'd')) => (&scope[..index], 24 * 3600),
+				Some((index, 'w')) => (&scope[..index], 7 * 24 * 3600),
+				_ => (scope, 1),
+			};
+			let value = value.parse::<u64>().map_err(|_| "Invalid snapshot scope")?;
+			if value == 0 {
+				return Err("Snapshot scopes must be positive");
+			}
+			value.checked_mul(multiplier).ok_or("Snapshot scope too large")?
+		};
+		if snapshot_scopes.contains(&scope) {
+			return Err("Duplicate snapshot scope");
+		}
+		snapshot_scopes.push(scope);
+	}
+	snapshot_scopes.sort_unstable();
+	// clients that haven't synced before, or too long ago, always need a full snapshot to fall back on
+	if snapshot_scopes.last() != Some(&u64::MAX) {
+		snapshot_scopes.push(u64::MAX);
+	}
+	Ok(snapshot_scopes)
+}
+
+pub(crate) fn max_concurrent_utxo_lookups() -> usize {
+	let limit = var("RAPID_GOSSIP_SYNC_SERVER_MAX_CONCURRENT_UTXO_LOOKUPS").unwrap_or(DEFAULT_MAX_CONCURRENT_UTXO_LOOKUPS.to_string())
+		.parse::<usize>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_MAX_CONCURRENT_UTXO_LOOKUPS env variable must be a usize.");
+	assert!(limit > 0, "RAPID_GOSSIP_SYNC_SERVER_MAX_CONCURRENT_UTXO_LOOKUPS must be positive");
+	limit
+}
+
+/// The number of blocks to cache for UTXO lookups, where 0 disables caching and prefetching
+pub(crate) fn block_cache_size() -> usize {
+	let default_size = match memory_profile() {
+		MemoryProfile::Standard => DEFAULT_BLOCK_CACHE_SIZE,
+		MemoryProfile::Constrained => CONSTRAINED_BLOCK_CACHE_SIZE,
+	};
+	var("RAPID_GOSSIP_SYNC_SERVER_BLOCK_CACHE_SIZE").unwrap_or(default_size.to_string())
+		.parse::<usize>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_BLOCK_CACHE_SIZE env variable must be a usize.")
+}
+
+/// The number of blocks to prefetch for pending UTXO lookups, where 0 disables prefetching. It must
+/// be smaller than the cache, lest prefetched blocks be evicted before they're looked up.
+pub(crate) fn block_prefetch_depth() -> usize {
+	let default_depth = match memory_profile() {
+		MemoryProfile::Standard => DEFAULT_BLOCK_PREFETCH_DEPTH,
+		MemoryProfile::Constrained => CONSTRAINED_BLOCK_PREFETCH_DEPTH,
+	};
+	let depth = var("RAPID_GOSSIP_SYNC_SERVER_BLOCK_PREFETCH_DEPTH").unwrap_or(default_depth.to_string())
+		.parse::<usize>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_BLOCK_PREFETCH_DEPTH env variable must be a usize.");
+	let cache_size = block_cache_size();
+	assert!(cache_size == 0 || depth < cache_size, "RAPID_GOSSIP_SYNC_SERVER_BLOCK_PREFETCH_DEPTH must be smaller than RAPID_GOSSIP_SYNC_SERVER_BLOCK_CACHE_SIZE");
+	depth
+}
+
+pub(crate) fn bitcoin_rest_retries() -> u32 {
+	var("RAPID_GOSSIP_SYNC_SERVER_BITCOIN_REST_RETRIES").unwrap_or(DEFAULT_BITCOIN_REST_RETRIES.to_string())
+		.parse::<u32>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_BITCOIN_REST_RETRIES env variable must be a u32.")
+}
+
+pub(crate) fn bitcoin_rest_retry_base_delay() -> Duration {
+	let delay_ms = var("RAPID_GOSSIP_SYNC_SERVER_BITCOIN_REST_RETRY_DELAY_MS").unwrap_or(DEFAULT_BITCOIN_REST_RETRY_DELAY_MS.to_string())
+		.parse::<u64>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_BITCOIN_REST_RETRY_DELAY_MS env variable must be a u64.");
+	Duration::from_millis(delay_ms)
+}
+
+pub(crate) fn bitcoin_rest_connect_timeout() -> Duration {
+	let timeout_secs = var("RAPID_GOSSIP_SYNC_SERVER_BITCOIN_REST_CONNECT_TIMEOUT").unwrap_or(DEFAULT_BITCOIN_REST_CONNECT_TIMEOUT.to_string())
+		.parse::<u64>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_BITCOIN_REST_CONNECT_TIMEOUT env variable must be a u64.");
+	assert!(timeout_secs > 0, "RAPID_GOSSIP_SYNC_SERVER_BITCOIN_REST_CONNECT_TIMEOUT must be positive");
+	Duration::from_secs(timeout_secs)
+}
+
+pub(crate) fn bitcoin_rest_request_timeout() -> Duration {
+	let timeout_secs = var("RAPID_GOSSIP_SYNC_SERVER_BITCOIN_REST_REQUEST_TIMEOUT").unwrap_or(DEFAULT_BITCOIN_REST_REQUEST_TIMEOUT.to_string())
+		.parse::<u64>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_BITCOIN_REST_REQUEST_TIMEOUT env variable must be a u64.");
+	assert!(timeout_secs > 0, "RAPID_GOSSIP_SYNC_SERVER_BITCOIN_REST_REQUEST_TIMEOUT must be positive");
+	Duration::from_secs(timeout_secs)
+}
+
+pub(crate) fn bitcoin_rest_max_connections() -> usize {
+	let limit = var("RAPID_GOSSIP_SYNC_SERVER_BITCOIN_REST_MAX_CONNECTIONS").unwrap_or(DEFAULT_BITCOIN_REST_MAX_CONNECTIONS.to_string())
+		.parse::<usize>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_BITCOIN_REST_MAX_CONNECTIONS env variable must be a usize.");
+	assert!(limit > 0, "RAPID_GOSSIP_SYNC_SERVER_BITCOIN_REST_MAX_CONNECTIONS must be positive");
+	limit
+}
+
+pub(crate) fn bitcoin_rest_slow_request_threshold() -> Duration {
+	let threshold_ms = var("RAPID_GOSSIP_SYNC_SERVER_BITCOIN_REST_SLOW_REQUEST_MS").unwrap_or(DEFAULT_BITCOIN_REST_SLOW_REQUEST_MS.to_string())
+		.parse::<u64>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_BITCOIN_REST_SLOW_REQUEST_MS env variable must be a u64.");
+	Duration::from_millis(threshold_ms)
+}
+
+/// Whether to confirm that a newly announced channel's funding output is still unspent before
+/// accepting its announcement, at the cost of one additional REST request per lookup.
+pub(crate) fn verify_unspent_funding_outputs() -> bool {
+	var("RAPID_GOSSIP_SYNC_SERVER_VERIFY_UNSPENT").unwrap_or("false".to_string())
+		.parse::<bool>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_VERIFY_UNSPENT env variable must be a boolean.")
+}
+
+/// Whether to retrieve funding transactions individually via REST `tx` rather than within their
+/// blocks, which requires bitcoind to run with `-txindex`
+pub(crate) fn bitcoin_rest_txindex() -> bool {
+	var("RAPID_GOSSIP_SYNC_SERVER_BITCOIN_REST_TXINDEX").unwrap_or("false".to_string())
+		.parse::<bool>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_BITCOIN_REST_TXINDEX env variable must be a boolean.")
+}
+
+/// How the funding outputs of announced channels are verified against bitcoind
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum VerificationMode {
+	/// Look up each funding output in its block
+	Blocks,
+	/// Check the block's compact filter for the announced funding script first, only retrieving
+	/// the block if it matches. Experimental, for pruned nodes with `-blockfilterindex` enabled.
+	Filters,
+}
+
+pub(crate) fn verification_mode() -> VerificationMode {
+	match var("RAPID_GOSSIP_SYNC_SERVER_VERIFICATION_MODE").as_deref() {
+		Ok("blocks") | Err(_) => VerificationMode::Blocks,
+		Ok("filters") => VerificationMode::Filters,
+		Ok(_) => panic!("RAPID_GOSSIP_SYNC_SERVER_VERIFICATION_MODE env variable must be one of blocks, filters"),
+	}
+}
+
+/// The number of blocks the chain source may fall behind the network before it's reported
+pub(crate) fn chain_tip_lag_threshold() -> u32 {
+	let threshold = var("RAPID_GOSSIP_SYNC_SERVER_CHAIN_TIP_LAG_THRESHOLD").unwrap_or(DEFAULT_CHAIN_TIP_LAG_THRESHOLD.to_string())
+		.parse::<u32>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_CHAIN_TIP_LAG_THRESHOLD env variable must be a u32.");
+	assert!(threshold > 0, "RAPID_GOSSIP_SYNC_SERVER_CHAIN_TIP_LAG_THRESHOLD must be positive");
+	threshold
+}
+
 pub(crate) fn network() -> Network {
-	let network = env::var("RAPID_GOSSIP_SYNC_SERVER_NETWORK").unwrap_or("bitcoin".to_string()).to_lowercase();
+	let network = var("RAPID_GOSSIP_SYNC_SERVER_NETWORK").unwrap_or("bitcoin".to_string());
+	parse_network(&network)
+}
+
+/// The networks to operate on simultaneously, each with its own network graph, peers, database
+/// schema, and snapshot directory. Defaults to just [`network`].
+pub(crate) fn networks() -> Vec<Network> {
+	let list = match var("RAPID_GOSSIP_SYNC_SERVER_NETWORKS") {
+		Ok(list) => list,
+		Err(_) => return vec![network()],
+	};
+	let mut networks = Vec::new();
+	for network in list.split(',').map(|n| n.trim()).filter(|n| !n.is_empty()) {
+		let network = parse_network(network);
+		assert!(!networks.contains(&network), "RAPID_GOSSIP_SYNC_SERVER_NETWORKS contains {} more than once", network);
+		networks.push(network);
+	}
+	assert!(!networks.is_empty(), "RAPID_GOSSIP_SYNC_SERVER_NETWORKS must contain at least one network");
+	networks
+}
+
+fn is_multi_network() -> bool {
+	networks().len() > 1
+}
+
+fn parse_network(network: &str) -> Network {
+	let network = network.to_lowercase();
 	match network.as_str() {
 		"mainnet" => Network::Bitcoin,
 		"bitcoin" => Network::Bitcoin,
 		"testnet" => Network::Testnet,
+		"testnet4" => Network::Testnet4,
 		"signet" => Network::Signet,
 		"regtest" => Network::Regtest,
-		_ => panic!("Invalid network"),
+		_ => panic!("Invalid network: {}. Possible values are mainnet, testnet, testnet4, signet, regtest", network),
+	}
+}
+
+/// Evaluate the settings that are otherwise only read once they're needed, so that invalid values,
+/// whether from the environment or the config file, are reported at startup rather than hours
+/// into operation.
+pub(crate) fn validate(network: Network) {
+	snapshot_generation_interval();
+	snapshot_scopes();
+	snapshot_concurrency();
+	snapshot_serialization_versions();
+	snapshot_compression();
+	snapshot_profiles();
+	snapshot_signing_key();
+	include_node_aliases();
+	min_channel_capacity_sats();
+	crate::blocklist::current();
+	max_snapshot_age();
+	dynamic_snapshots_enabled();
+	graph_export_enabled();
+	query_api_enabled();
+	gossip_stream_enabled();
+	gossip_stream_max_subscribers();
+	gossip_recording_file_size();
+	gossip_recording_max_files();
+	dynamic_snapshot_cache_ttl();
+	max_concurrent_utxo_lookups();
+	block_cache_size();
+	block_prefetch_depth();
+	bitcoin_rest_retries();
+	bitcoin_rest_retry_base_delay();
+	bitcoin_rest_connect_timeout();
+	bitcoin_rest_request_timeout();
+	bitcoin_rest_max_connections();
+	bitcoin_rest_slow_request_threshold();
+	verify_unspent_funding_outputs();
+	chain_tip_lag_threshold();
+	alert_webhook_url(network);
+	db_connection_config();
+	db_read_connection_config();
+	crate::storage::tls_connector();
+	db_backend(network);
+	db_schema(network);
+	leader_election_enabled(network);
+	db_batch_size();
+	db_flush_interval();
+	graph_checkpoint_interval();
+	persistence_queue_size();
+	persistence_overflow();
+	deduplicate_updates();
+	memory_profile();
+	strip_node_announcements();
+	full_snapshot_defaults();
+	snapshot_on_shutdown();
+	snapshot_verification();
+	snapshot_deviation_threshold_percent();
+	snapshot_archive_directory(network);
+	snapshot_archive_retention();
+	snapshot_archive_upload();
+	staleness_horizon();
+	update_retention();
+	update_retention_count();
+	bitcoin_rest_endpoints(network);
+	verification_mode();
+	bitcoin_rest_txindex();
+	bitcoin_rest_fallback_endpoints(network);
+	http_server_address(network);
+	http_tls_certificate();
+	admin_address(network);
+	admin_token();
+	grpc_address(network);
+	http_rate_limit();
+	http_rate_limit_burst();
+	http_max_concurrent_requests();
+	http_request_analytics();
+	stats_sample_interval();
+	stats_retention();
+	listen_address(network);
+	announced_address(network);
+	max_inbound_peers();
+	s3_upload_config(network);
+	dns_seed_peer_count();
+	peer_silence_timeout();
+	gossip_stall_timeout();
+	peer_max_disconnections();
+	peer_reconnect_max_attempts();
+	peer_reconnect_base_delay();
+	peer_reconnect_max_delay();
+	proxy();
+	gossip_relay_enabled();
+	gossip_relay_rate_limit();
+}
+
+/// The network a graph was created for
+pub(crate) fn graph_network<L: Deref>(network_graph: &NetworkGraph<L>) -> Network where L::Target: Logger {
+	Network::from_chain_hash(network_graph.get_chain_hash()).expect("Network graph must be for a known network")
+}
+
+/// Read a setting that may be overridden for an individual network by suffixing its name with
+/// that of the network, e. g. `LN_PEERS_SIGNET` taking precedence over `LN_PEERS`.
+fn network_env_var(name: &str, network: Network) -> Result<String, env::VarError> {
+	var(&format!("{}_{}", name, network.to_string().to_uppercase())).or_else(|_| var(name))
+}
+
+/// The settings read from the file at `RAPID_GOSSIP_SYNC_SERVER_CONFIG_FILE`, keyed by the
+/// environment variable they correspond to.
+fn config_file_variables() -> &'static RwLock<HashMap<String, String>> {
+	static CONFIG_FILE_VARIABLES: OnceLock<RwLock<HashMap<String, String>>> = OnceLock::new();
+	CONFIG_FILE_VARIABLES.get_or_init(|| RwLock::new(read_config_file().unwrap_or_else(|e| panic!("{}", e))))
+}
+
+fn read_config_file() -> Result<HashMap<String, String>, String> {
+	match env::var("RAPID_GOSSIP_SYNC_SERVER_CONFIG_FILE") {
+		Ok(path) => config_file::load(&path).map_err(|e| format!("Invalid config file {}: {}", path, e)),
+		Err(_) => Ok(HashMap::new()),
+	}
+}
+
+/// Re-read the config file, keeping the previous settings if it has become invalid. Settings are
+/// read whenever they're needed, so changes apply from then on.
+pub(crate) fn reload_config_file() -> Result<(), String> {
+	let variables = read_config_file()?;
+	*config_file_variables().write().unwrap() = variables;
+	Ok(())
+}
+
+/// The settings provided by an application embedding the server, keyed by the environment
+/// variable they correspond to
+fn embedded_variables() -> &'static RwLock<HashMap<String, String>> {
+	static EMBEDDED_VARIABLES: OnceLock<RwLock<HashMap<String, String>>> = OnceLock::new();
+	EMBEDDED_VARIABLES.get_or_init(|| RwLock::new(HashMap::new()))
+}
+
+/// Apply settings on behalf of an application embedding the server, which take precedence over
+/// both the environment and the config file for the remainder of the process' lifetime
+pub(crate) fn apply_embedded_settings(settings: HashMap<String, String>) {
+	embedded_variables().write().unwrap().extend(settings);
+}
+
+/// Read a setting from those applied by an embedding application, falling back to the environment
+/// and then to the config file.
+fn var(name: &str) -> Result<String, env::VarError> {
+	if let Some(value) = embedded_variables().read().unwrap().get(name) {
+		return Ok(value.clone());
+	}
+	env::var(name).or_else(|error| config_file_variables().read().unwrap().get(name).cloned().ok_or(error))
+}
+
+/// The port bitcoind serves its REST interface on by default for the given network
+pub(crate) fn default_bitcoin_rest_port(network: Network) -> u16 {
+	match network {
+		Network::Bitcoin => 8332,
+		Network::Testnet => 18332,
+		Network::Testnet4 => 48332,
+		Network::Signet => 38332,
+		Network::Regtest => 18443,
+	}
+}
+
+/// The address to serve snapshots on via the built-in HTTP server, which is disabled by default.
+/// Besides a socket address, this may be a unix socket as `unix:<path>`, or a socket passed by
+/// systemd as `systemd` or `systemd:<name>`.
+pub(crate) fn http_server_address(network: Network) -> Option<ListenAddress> {
+	network_env_var("RAPID_GOSSIP_SYNC_SERVER_HTTP_ADDRESS", network).ok()
+		.map(|address| ListenAddress::from_name(&address)
+			.expect("RAPID_GOSSIP_SYNC_SERVER_HTTP_ADDRESS env variable must be a socket address, unix:<path>, or systemd[:<name>]."))
+}
+
+/// The PEM files of the certificate chain and private key to serve the built-in HTTP server over
+/// HTTPS with, which is served over plain HTTP by default. Both are reloaded when they change.
+pub(crate) fn http_tls_certificate() -> Option<(String, String)> {
+	match (var("RAPID_GOSSIP_SYNC_SERVER_HTTP_TLS_CERT"), var("RAPID_GOSSIP_SYNC_SERVER_HTTP_TLS_KEY")) {
+		(Ok(cert), Ok(key)) => Some((cert, key)),
+		(Err(_), Err(_)) => None,
+		_ => panic!("RAPID_GOSSIP_SYNC_SERVER_HTTP_TLS_CERT and RAPID_GOSSIP_SYNC_SERVER_HTTP_TLS_KEY env variables must be set together"),
 	}
 }
 
-pub(crate) fn log_level() -> lightning::util::logger::Level {
-	let level = env::var("RAPID_GOSSIP_SYNC_SERVER_LOG_LEVEL").unwrap_or("info".to_string()).to_lowercase();
-	match level.as_str() {
-		"gossip" => lightning::util::logger::Level::Gossip,
-		"trace" => lightning::util::logger::Level::Trace,
-		"debug" => lightning::util::logger::Level::Debug,
-		"info" => lightning::util::logger::Level::Info,
-		"warn" => lightning::util::logger::Level::Warn,
-		"error" => lightning::util::logger::Level::Error,
-		_ => panic!("Invalid log level"),
+/// The address to accept admin commands on, which is disabled by default. Like the HTTP server's,
+/// this may be a socket address, a unix socket as `unix:<path>`, or a socket passed by systemd.
+pub(crate) fn admin_address(network: Network) -> Option<ListenAddress> {
+	let address = network_env_var("RAPID_GOSSIP_SYNC_SERVER_ADMIN_ADDRESS", network).ok()
+		.map(|address| ListenAddress::from_name(&address)
+			.expect("RAPID_GOSSIP_SYNC_SERVER_ADMIN_ADDRESS env variable must be a socket address, unix:<path>, or systemd[:<name>]."))?;
+	if let ListenAddress::Tcp(_) = address {
+		assert!(admin_token().is_some(), "RAPID_GOSSIP_SYNC_SERVER_ADMIN_TOKEN must be set for the admin socket to listen on TCP");
 	}
+	Some(address)
+}
+
+/// The address to serve the gRPC admin API on, which is disabled by default and requires the `grpc`
+/// feature. Its clients always authenticate with the admin token.
+pub(crate) fn grpc_address(network: Network) -> Option<SocketAddr> {
+	let address = network_env_var("RAPID_GOSSIP_SYNC_SERVER_GRPC_ADDRESS", network).ok()
+		.map(|address| address.parse::<SocketAddr>()
+			.expect("RAPID_GOSSIP_SYNC_SERVER_GRPC_ADDRESS env variable must be a socket address."))?;
+	#[cfg(not(feature = "grpc"))]
+	panic!("RAPID_GOSSIP_SYNC_SERVER_GRPC_ADDRESS requires the server to be built with the grpc feature, but is set to {}", address);
+	#[cfg(feature = "grpc")]
+	{
+		assert!(admin_token().is_some(), "RAPID_GOSSIP_SYNC_SERVER_ADMIN_TOKEN must be set for the gRPC admin API");
+		Some(address)
+	}
+}
+
+/// The token admin socket clients must authenticate with, which is optional for unix sockets
+pub(crate) fn admin_token() -> Option<String> {
+	let token = var("RAPID_GOSSIP_SYNC_SERVER_ADMIN_TOKEN").ok()?;
+	assert!(!token.is_empty() && !token.contains(char::is_whitespace), "RAPID_GOSSIP_SYNC_SERVER_ADMIN_TOKEN must be non-empty and mustn't contain whitespace");
+	Some(token)
+}
+
+/// How many requests each client may send per minute to the HTTP endpoints computing their
+/// responses on demand, if limited
+pub(crate) fn http_rate_limit() -> Option<u32> {
+	let limit = var("RAPID_GOSSIP_SYNC_SERVER_HTTP_RATE_LIMIT").ok().map(|limit| limit
+		.parse::<u32>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_HTTP_RATE_LIMIT env variable must be a u32."))?;
+	assert!(limit > 0, "RAPID_GOSSIP_SYNC_SERVER_HTTP_RATE_LIMIT must be positive");
+	Some(limit)
+}
+
+pub(crate) fn http_rate_limit_burst() -> u32 {
+	let burst = var("RAPID_GOSSIP_SYNC_SERVER_HTTP_RATE_LIMIT_BURST").unwrap_or(DEFAULT_HTTP_RATE_LIMIT_BURST.to_string())
+		.parse::<u32>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_HTTP_RATE_LIMIT_BURST env variable must be a u32.");
+	assert!(burst > 0, "RAPID_GOSSIP_SYNC_SERVER_HTTP_RATE_LIMIT_BURST must be positive");
+	burst
 }
 
-pub(crate) fn network_graph_cache_path() -> String {
-	format!("{}/network_graph.bin", cache_path())
+/// How many requests to the HTTP endpoints computing their responses on demand are served at
+/// once across all clients, if limited
+pub(crate) fn http_max_concurrent_requests() -> Option<usize> {
+	let limit = var("RAPID_GOSSIP_SYNC_SERVER_HTTP_MAX_CONCURRENT_REQUESTS").ok().map(|limit| limit
+		.parse::<usize>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_HTTP_MAX_CONCURRENT_REQUESTS env variable must be a usize."))?;
+	assert!(limit > 0, "RAPID_GOSSIP_SYNC_SERVER_HTTP_MAX_CONCURRENT_REQUESTS must be positive");
+	Some(limit)
 }
 
-pub(crate) fn cache_path() -> String {
-	let path = env::var("RAPID_GOSSIP_SYNC_SERVER_CACHES_PATH").unwrap_or("./res".to_string()).to_lowercase();
+/// Where the statistics of the snapshot requests served by the built-in HTTP server are recorded
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum RequestAnalyticsSink {
+	/// Logged one line per request
+	Log,
+	/// Inserted into the `snapshot_requests` table of the gossip store
+	Database,
+}
+
+/// Where snapshot requests are recorded, if at all
+pub(crate) fn http_request_analytics() -> Option<RequestAnalyticsSink> {
+	match var("RAPID_GOSSIP_SYNC_SERVER_HTTP_REQUEST_ANALYTICS").as_deref() {
+		Ok("off") | Err(_) => None,
+		Ok("log") => Some(RequestAnalyticsSink::Log),
+		Ok("database") => Some(RequestAnalyticsSink::Database),
+		Ok(_) => panic!("RAPID_GOSSIP_SYNC_SERVER_HTTP_REQUEST_ANALYTICS env variable must be one of off, log, database"),
+	}
+}
+
+/// How often the gossip message rates and the network's growth are sampled into the database, if
+/// at all
+pub(crate) fn stats_sample_interval() -> Option<Duration> {
+	let interval = var("RAPID_GOSSIP_SYNC_SERVER_STATS_SAMPLE_INTERVAL").ok()?
+		.parse::<u64>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_STATS_SAMPLE_INTERVAL env variable must be a u64.");
+	assert!(interval > 0, "RAPID_GOSSIP_SYNC_SERVER_STATS_SAMPLE_INTERVAL must be positive");
+	Some(Duration::from_secs(interval))
+}
+
+pub(crate) fn stats_retention() -> Option<Duration> {
+	let retention_days = var("RAPID_GOSSIP_SYNC_SERVER_STATS_RETENTION_DAYS").unwrap_or(DEFAULT_STATS_RETENTION_DAYS.to_string())
+		.parse::<u64>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_STATS_RETENTION_DAYS env variable must be a u64.");
+	// zero days keeps samples indefinitely
+	(retention_days > 0).then(|| Duration::from_secs(retention_days * 24 * 3600))
+}
+
+/// The address to accept inbound peer connections on, if any
+pub(crate) fn listen_address(network: Network) -> Option<SocketAddr> {
+	network_env_var("RAPID_GOSSIP_SYNC_SERVER_LISTEN_ADDRESS", network).ok()
+		.map(|address| address.parse().expect("RAPID_GOSSIP_SYNC_SERVER_LISTEN_ADDRESS env variable must be a socket address."))
+}
+
+/// The address peers are told to connect to us on, if it differs from the listen address (e. g.
+/// behind NAT or a Tor hidden service)
+pub(crate) fn announced_address(network: Network) -> Option<SocketAddress> {
+	network_env_var("RAPID_GOSSIP_SYNC_SERVER_ANNOUNCED_ADDRESS", network).ok()
+		.map(|address| SocketAddress::from_str(&address).expect("RAPID_GOSSIP_SYNC_SERVER_ANNOUNCED_ADDRESS env variable must be a host:port address."))
+}
+
+pub(crate) fn max_inbound_peers() -> usize {
+	var("RAPID_GOSSIP_SYNC_SERVER_MAX_INBOUND_PEERS").unwrap_or(DEFAULT_MAX_INBOUND_PEERS.to_string())
+		.parse::<usize>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_MAX_INBOUND_PEERS env variable must be a usize.")
+}
+
+/// The serialization versions to generate snapshots in, which must each be supported by
+/// [`crate::serialize_delta`].
+pub(crate) fn snapshot_serialization_versions() -> Vec<u8> {
+	let versions = var("RAPID_GOSSIP_SYNC_SERVER_SNAPSHOT_VERSIONS").unwrap_or("1,2".to_string());
+	parse_serialization_versions(&versions).expect("RAPID_GOSSIP_SYNC_SERVER_SNAPSHOT_VERSIONS env variable must be a comma separated list of supported serialization versions (1, 2).")
+}
+
+fn parse_serialization_versions(versions: &str) -> Result<Vec<u8>, &'static str> {
+	let mut serialization_versions = Vec::new();
+	for version in versions.split(',').map(str::trim).filter(|version| !version.is_empty()) {
+		let version = version.strip_prefix('v').unwrap_or(version).parse::<u8>().map_err(|_| "Invalid serialization version")?;
+		if !SUPPORTED_SERIALIZATION_VERSIONS.contains(&version) {
+			return Err("Unsupported serialization version");
+		}
+		if serialization_versions.contains(&version) {
+			return Err("Duplicate serialization version");
+		}
+		serialization_versions.push(version);
+	}
+	if serialization_versions.is_empty() {
+		return Err("At least one serialization version must be enabled");
+	}
+	serialization_versions.sort_unstable();
+	Ok(serialization_versions)
+}
+
+/// The file listing the node ids and short channel ids whose gossip is rejected and omitted from
+/// snapshots, if any
+pub(crate) fn blocklist_path() -> Option<String> {
+	var("RAPID_GOSSIP_SYNC_SERVER_BLOCKLIST_PATH").ok()
+}
+
+/// The funding amount below which channels are omitted from snapshots, if any. Mobile clients
+/// don't route payments through small channels anyway.
+pub(crate) fn min_channel_capacity_sats() -> Option<u64> {
+	var("RAPID_GOSSIP_SYNC_SERVER_MIN_CHANNEL_CAPACITY_SATS").ok().map(|capacity| capacity.parse::<u64>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_MIN_CHANNEL_CAPACITY_SATS env variable must be a u64."))
+}
+
+/// Whether v2 snapshots carry node aliases in the additional data of node announcement records,
+/// which clients unaware of them skip over.
+pub(crate) fn include_node_aliases() -> bool {
+	var("RAPID_GOSSIP_SYNC_SERVER_INCLUDE_NODE_ALIASES").unwrap_or("false".to_string())
+		.parse::<bool>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_INCLUDE_NODE_ALIASES env variable must be a boolean.")
+}
+
+/// Whether the built-in HTTP server computes snapshots on demand for arbitrary timestamps under
+/// `/dynamic/<timestamp>`, which is considerably more expensive than serving pregenerated ones.
+pub(crate) fn dynamic_snapshots_enabled() -> bool {
+	var("RAPID_GOSSIP_SYNC_SERVER_DYNAMIC_SNAPSHOTS").unwrap_or("false".to_string())
+		.parse::<bool>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_DYNAMIC_SNAPSHOTS env variable must be a boolean.")
+}
+
+pub(crate) fn graph_export_enabled() -> bool {
+	var("RAPID_GOSSIP_SYNC_SERVER_GRAPH_EXPORT").unwrap_or("false".to_string())
+		.parse::<bool>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_GRAPH_EXPORT env variable must be a boolean.")
+}
+
+pub(crate) fn query_api_enabled() -> bool {
+	var("RAPID_GOSSIP_SYNC_SERVER_QUERY_API").unwrap_or("false".to_string())
+		.parse::<bool>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_QUERY_API env variable must be a boolean.")
+}
+
+pub(crate) fn gossip_stream_enabled() -> bool {
+	var("RAPID_GOSSIP_SYNC_SERVER_GOSSIP_STREAM").unwrap_or("false".to_string())
+		.parse::<bool>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_GOSSIP_STREAM env variable must be a boolean.")
+}
+
+/// How many clients may be subscribed to the gossip stream at once
+pub(crate) fn gossip_stream_max_subscribers() -> usize {
+	let limit = var("RAPID_GOSSIP_SYNC_SERVER_GOSSIP_STREAM_MAX_SUBSCRIBERS").unwrap_or(DEFAULT_GOSSIP_STREAM_MAX_SUBSCRIBERS.to_string())
+		.parse::<usize>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_GOSSIP_STREAM_MAX_SUBSCRIBERS env variable must be a usize.");
+	assert!(limit > 0, "RAPID_GOSSIP_SYNC_SERVER_GOSSIP_STREAM_MAX_SUBSCRIBERS must be positive");
+	limit
+}
+
+pub(crate) fn dynamic_snapshot_cache_ttl() -> Duration {
+	let ttl_secs = var("RAPID_GOSSIP_SYNC_SERVER_DYNAMIC_SNAPSHOT_CACHE_TTL").unwrap_or(DEFAULT_DYNAMIC_SNAPSHOT_CACHE_TTL_SECS.to_string())
+		.parse::<u64>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_DYNAMIC_SNAPSHOT_CACHE_TTL env variable must be a u64.");
+	Duration::from_secs(ttl_secs)
+}
+
+/// The named subgraphs to generate snapshots of alongside the full ones, specified as a comma
+/// separated list of profiles as documented in [`crate::profiles`]
+pub(crate) fn snapshot_profiles() -> Vec<SnapshotProfile> {
+	let definitions = var("RAPID_GOSSIP_SYNC_SERVER_SNAPSHOT_PROFILES").unwrap_or_default();
+	let mut profiles: Vec<SnapshotProfile> = Vec::new();
+	for definition in definitions.split(',').map(str::trim).filter(|definition| !definition.is_empty()) {
+		let profile = SnapshotProfile::parse(definition).unwrap_or_else(|e| panic!("RAPID_GOSSIP_SYNC_SERVER_SNAPSHOT_PROFILES: {}", e));
+		assert!(profiles.iter().all(|other| other.name != profile.name), "RAPID_GOSSIP_SYNC_SERVER_SNAPSHOT_PROFILES: Duplicate snapshot profile {}", profile.name);
+		profiles.push(profile);
+	}
+	profiles
+}
+
+/// The codecs, along with their compression levels, to precompress every snapshot with. Specified
+/// as a comma separated list of `codec[:level]`, e. g. `gzip:6,zstd`.
+pub(crate) fn snapshot_compression() -> Vec<(SnapshotCompression, u32)> {
+	let codecs = var("RAPID_GOSSIP_SYNC_SERVER_SNAPSHOT_COMPRESSION").unwrap_or_default();
+	parse_snapshot_compression(&codecs).expect("RAPID_GOSSIP_SYNC_SERVER_SNAPSHOT_COMPRESSION env variable must be a comma separated list of gzip, brotli, or zstd, optionally followed by :<level>.")
+}
+
+fn parse_snapshot_compression(codecs: &str) -> Result<Vec<(SnapshotCompression, u32)>, &'static str> {
+	let mut compression: Vec<(SnapshotCompression, u32)> = Vec::new();
+	for codec in codecs.split(',').map(str::trim).filter(|codec| !codec.is_empty()) {
+		let (name, level) = match codec.split_once(':') {
+			Some((name, level)) => (name, Some(level.parse::<u32>().map_err(|_| "Invalid compression level")?)),
+			None => (codec, None),
+		};
+		let algorithm = SnapshotCompression::from_name(&name.to_lowercase()).ok_or("Unknown compression codec")?;
+		let level = level.unwrap_or(algorithm.default_level());
+		if level > algorithm.max_level() {
+			return Err("Compression level out of range");
+		}
+		if compression.iter().any(|(enabled, _)| *enabled == algorithm) {
+			return Err("Duplicate compression codec");
+		}
+		compression.push((algorithm, level));
+	}
+	Ok(compression)
+}
+
+/// The secp256k1 key to sign generated snapshots with, if any.
+pub(crate) fn snapshot_signing_key() -> Option<SecretKey> {
+	var("RAPID_GOSSIP_SYNC_SERVER_SNAPSHOT_SIGNING_KEY").ok().map(|key| {
+		hex_utils::to_vec(key.trim()).and_then(|key| SecretKey::from_slice(&key).ok())
+			.expect("RAPID_GOSSIP_SYNC_SERVER_SNAPSHOT_SIGNING_KEY env variable must be a hex-encoded 32-byte secp256k1 secret key.")
+	})
+}
+
+/// Where to upload generated snapshots to, if anywhere. Uploading is enabled by setting a bucket.
+pub(crate) fn s3_upload_config(network: Network) -> Option<S3UploadConfig> {
+	let bucket = network_env_var("RAPID_GOSSIP_SYNC_SERVER_S3_BUCKET", network).ok()?;
+	let region = network_env_var("RAPID_GOSSIP_SYNC_SERVER_S3_REGION", network).unwrap_or("us-east-1".to_string());
+	let endpoint = network_env_var("RAPID_GOSSIP_SYNC_SERVER_S3_ENDPOINT", network).unwrap_or(format!("https://s3.{}.amazonaws.com", region));
+	let default_prefix = if is_multi_network() { network.to_string() } else { String::new() };
+	let prefix = network_env_var("RAPID_GOSSIP_SYNC_SERVER_S3_PREFIX", network).unwrap_or(default_prefix).trim_matches('/').to_string();
+	let access_key_id = var("AWS_ACCESS_KEY_ID").expect("AWS_ACCESS_KEY_ID env variable must be set for S3 uploads.");
+	let secret_access_key = var("AWS_SECRET_ACCESS_KEY").expect("AWS_SECRET_ACCESS_KEY env variable must be set for S3 uploads.");
+	let cache_control = network_env_var("RAPID_GOSSIP_SYNC_SERVER_S3_CACHE_CONTROL", network).ok();
+	Some(S3UploadConfig { endpoint, region, bucket, prefix, access_key_id, secret_access_key, cache_control })
+}
+
+/// The URL to POST a JSON description of the generated snapshots to after every snapshot round
+pub(crate) fn snapshot_webhook_url(network: Network) -> Option<String> {
+	network_env_var("RAPID_GOSSIP_SYNC_SERVER_SNAPSHOT_WEBHOOK_URL", network).ok()
+}
+
+/// The URL to POST a JSON description of operational problems to, e. g. the chain source falling
+/// behind, as well as of their resolution
+pub(crate) fn alert_webhook_url(network: Network) -> Option<String> {
+	network_env_var("RAPID_GOSSIP_SYNC_SERVER_ALERT_WEBHOOK_URL", network).ok()
+}
+
+/// The shell command to pipe a JSON description of the generated snapshots to after every
+/// snapshot round
+pub(crate) fn snapshot_hook_command(network: Network) -> Option<String> {
+	network_env_var("RAPID_GOSSIP_SYNC_SERVER_SNAPSHOT_HOOK_COMMAND", network).ok()
+}
+
+/// The log verbosity, either as a single level or as comma separated directives adjusting
+/// individual subsystems, e. g. `info,network_graph=warn,verifier=trace`
+pub(crate) fn log_filter() -> LogFilter {
+	let directives = var("RAPID_GOSSIP_SYNC_SERVER_LOG_LEVEL").unwrap_or("info".to_string());
+	LogFilter::parse(&directives).expect("RAPID_GOSSIP_SYNC_SERVER_LOG_LEVEL env variable must be a log level (gossip, trace, debug, info, warn, error, or off), optionally followed by comma separated subsystem=level directives.")
+}
+
+pub(crate) fn log_format() -> LogFormat {
+	let format = var("RAPID_GOSSIP_SYNC_SERVER_LOG_FORMAT").unwrap_or("text".to_string()).to_lowercase();
+	LogFormat::from_name(&format).expect("RAPID_GOSSIP_SYNC_SERVER_LOG_FORMAT env variable must be either text or json.")
+}
+
+pub(crate) fn network_graph_cache_path(network: Network) -> String {
+	format!("{}/network_graph.bin", cache_path(network))
+}
+
+/// The directory holding the network graph cache and snapshots. When operating on several
+/// networks, each of them defaults to its own subdirectory.
+pub(crate) fn cache_path(network: Network) -> String {
+	let network_variable_name = format!("RAPID_GOSSIP_SYNC_SERVER_CACHES_PATH_{}", network.to_string().to_uppercase());
+	if let Ok(path) = var(&network_variable_name) {
+		return path.to_lowercase();
+	}
+	let path = var("RAPID_GOSSIP_SYNC_SERVER_CACHES_PATH").unwrap_or("./res".to_string()).to_lowercase();
+	if is_multi_network() {
+		return format!("{}/{}", path, network);
+	}
 	path
 }
 
+/// The Postgres schema a network's gossip is stored in. When operating on a single network, the
+/// default search path is used unless overridden.
+pub(crate) fn db_schema(network: Network) -> Option<String> {
+	if let Ok(schema) = network_env_var("RAPID_GOSSIP_SYNC_SERVER_DB_SCHEMA", network) {
+		// the schema is interpolated into the statements selecting it, as they can't take parameters
+		assert!(is_valid_schema_name(&schema), "RAPID_GOSSIP_SYNC_SERVER_DB_SCHEMA must be an unquoted Postgres identifier of letters, digits, and underscores, not starting with a digit, but is {}", schema);
+		return Some(schema);
+	}
+	if is_multi_network() {
+		return Some(format!("rgs_{}", network));
+	}
+	None
+}
+
+/// Whether `schema` is a Postgres identifier that needs no quoting, and is at most as long as
+/// Postgres allows identifiers to be
+fn is_valid_schema_name(schema: &str) -> bool {
+	let mut characters = schema.chars();
+	characters.next().is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+		&& characters.all(|character| character.is_ascii_alphanumeric() || character == '_')
+		&& schema.len() <= 63
+}
+
+pub(crate) enum DatabaseBackend {
+	Postgres,
+	/// A single SQLite database file at the given path
+	Sqlite(String),
+}
+
+/// The storage backend to persist gossip to, Postgres unless `..._DB_BACKEND` is `sqlite`. The
+/// SQLite database defaults to residing in the cache directory.
+pub(crate) fn db_backend(network: Network) -> DatabaseBackend {
+	match network_env_var("RAPID_GOSSIP_SYNC_SERVER_DB_BACKEND", network).as_deref() {
+		Ok("postgres") | Err(_) => DatabaseBackend::Postgres,
+		Ok("sqlite") => {
+			let path = network_env_var("RAPID_GOSSIP_SYNC_SERVER_SQLITE_PATH", network)
+				.unwrap_or_else(|_| format!("{}/gossip.sqlite", cache_path(network)));
+			DatabaseBackend::Sqlite(path)
+		},
+		Ok(_) => panic!("RAPID_GOSSIP_SYNC_SERVER_DB_BACKEND env variable must be one of postgres, sqlite"),
+	}
+}
+
+/// Whether the instances sharing the database elect a leader, which alone persists gossip,
+/// captures snapshots, and relays gossip, rather than each doing so
+pub(crate) fn leader_election_enabled(network: Network) -> bool {
+	let enabled = var("RAPID_GOSSIP_SYNC_SERVER_LEADER_ELECTION").unwrap_or("false".to_string())
+		.parse::<bool>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_LEADER_ELECTION env variable must be a boolean.");
+	assert!(!enabled || matches!(db_backend(network), DatabaseBackend::Postgres), "RAPID_GOSSIP_SYNC_SERVER_LEADER_ELECTION requires the postgres database backend");
+	enabled
+}
+
+/// The Postgres connection settings, either from a connection string (`..._DB_URL`) or from the
+/// individual host, user, and database name settings. A separately set password always applies,
+/// keeping it out of the connection string.
 pub(crate) fn db_connection_config() -> Config {
-	let mut config = Config::new();
-	let env_name_prefix = if cfg!(test) {
+	let env_name_prefix = db_env_name_prefix();
+
+	let mut config = if let Ok(url) = var(&format!("{}{}", env_name_prefix, "_URL")) {
+		url.parse::<Config>().unwrap_or_else(|e| panic!("{}_URL env variable must be a Postgres connection string: {}", env_name_prefix, e))
+	} else {
+		let mut config = Config::new();
+		let host = var(&format!("{}{}", env_name_prefix, "_HOST")).unwrap_or("localhost".to_string());
+		let user = var(&format!("{}{}", env_name_prefix, "_USER")).unwrap_or("alice".to_string());
+		let db = var(&format!("{}{}", env_name_prefix, "_NAME")).unwrap_or("ln_graph_sync".to_string());
+		config.host(&host);
+		config.user(&user);
+		config.dbname(&db);
+		config
+	};
+	if let Ok(password) = var(&format!("{}{}", env_name_prefix, "_PASSWORD")) {
+		config.password(&password);
+	}
+	config.ssl_mode(db_tls_config().mode.into());
+	config
+}
+
+/// The connection settings of a read-only replica to run the snapshot lookups against, if any.
+/// Unless set separately, the password is the primary's, and the TLS settings always are.
+pub(crate) fn db_read_connection_config() -> Option<Config> {
+	let env_name_prefix = db_env_name_prefix();
+	let url = var(&format!("{}{}", env_name_prefix, "_READ_URL")).ok()?;
+	let mut config = url.parse::<Config>().unwrap_or_else(|e| panic!("{}_READ_URL env variable must be a Postgres connection string: {}", env_name_prefix, e));
+	if let Ok(password) = var(&format!("{}{}", env_name_prefix, "_READ_PASSWORD")) {
+		config.password(&password);
+	} else if let (None, Ok(password)) = (config.get_password(), var(&format!("{}{}", env_name_prefix, "_PASSWORD"))) {
+		config.password(&password);
+	}
+	config.ssl_mode(db_tls_config().mode.into());
+	Some(config)
+}
+
+fn db_env_name_prefix() -> &'static str {
+	if cfg!(test) {
 		"RAPID_GOSSIP_TEST_DB"
 	} else {
 		"RAPID_GOSSIP_SYNC_SERVER_DB"
+	}
+}
+
+/// Whether and how the Postgres connection is secured, following libpq's `sslmode`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum DbSslMode {
+	Disable,
+	/// Use TLS if the server supports it, without verifying its certificate
+	Prefer,
+	/// Require TLS, verifying the server's certificate only if a root certificate is configured
+	Require,
+	/// Require TLS and a server certificate issued by a trusted authority
+	VerifyCa,
+	/// Require TLS and a server certificate issued by a trusted authority for the server's host
+	VerifyFull,
+}
+
+impl DbSslMode {
+	fn from_name(name: &str) -> Option<Self> {
+		match name {
+			"disable" => Some(Self::Disable),
+			"prefer" => Some(Self::Prefer),
+			"require" => Some(Self::Require),
+			"verify-ca" => Some(Self::VerifyCa),
+			"verify-full" => Some(Self::VerifyFull),
+			_ => None,
+		}
+	}
+}
+
+impl From<DbSslMode> for SslMode {
+	fn from(mode: DbSslMode) -> Self {
+		match mode {
+			DbSslMode::Disable => SslMode::Disable,
+			DbSslMode::Prefer => SslMode::Prefer,
+			// certificates are verified by the TLS connector
+			DbSslMode::Require | DbSslMode::VerifyCa | DbSslMode::VerifyFull => SslMode::Require,
+		}
+	}
+}
+
+pub(crate) struct DbTlsConfig {
+	pub(crate) mode: DbSslMode,
+	/// The PEM file of the certificate authorities to trust, instead of the web PKI's
+	pub(crate) root_cert: Option<String>,
+	/// The PEM files of the certificate chain and private key to authenticate to the server with
+	pub(crate) client_cert: Option<(String, String)>,
+}
+
+/// The TLS settings applying to all Postgres connections, defaulting to no TLS at all.
+pub(crate) fn db_tls_config() -> DbTlsConfig {
+	let env_name_prefix = db_env_name_prefix();
+	let mode = var(&format!("{}_SSL_MODE", env_name_prefix)).map_or(Some(DbSslMode::Disable), |mode| DbSslMode::from_name(&mode))
+		.unwrap_or_else(|| panic!("{}_SSL_MODE env variable must be one of disable, prefer, require, verify-ca, verify-full", env_name_prefix));
+	let root_cert = var(&format!("{}_SSL_ROOT_CERT", env_name_prefix)).ok();
+	let client_cert = match (var(&format!("{}_SSL_CERT", env_name_prefix)), var(&format!("{}_SSL_KEY", env_name_prefix))) {
+		(Ok(cert), Ok(key)) => Some((cert, key)),
+		(Err(_), Err(_)) => None,
+		_ => panic!("{0}_SSL_CERT and {0}_SSL_KEY env variables must be set together", env_name_prefix),
 	};
+	DbTlsConfig { mode, root_cert, client_cert }
+}
 
-	let host = env::var(format!("{}{}", env_name_prefix, "_HOST")).unwrap_or("localhost".to_string());
-	let user = env::var(format!("{}{}", env_name_prefix, "_USER")).unwrap_or("alice".to_string());
-	let db = env::var(format!("{}{}", env_name_prefix, "_NAME")).unwrap_or("ln_graph_sync".to_string());
-	config.host(&host);
-	config.user(&user);
-	config.dbname(&db);
-	if let Ok(password) = env::var(format!("{}{}", env_name_prefix, "_PASSWORD")) {
-		config.password(&password);
+/// The bitcoind REST endpoints to use for UTXO lookups.
+///
+/// `BITCOIN_REST_ENDPOINTS` takes a comma separated list of `host[:port][/path]` entries, whose
+/// port and path default to `BITCOIN_REST_PORT` and `BITCOIN_REST_PATH`. Absent that list, the
+/// single endpoint described by `BITCOIN_REST_DOMAIN` is used.
+pub(crate) fn bitcoin_rest_endpoints(network: Network) -> Vec<HttpEndpoint> {
+	let default_port = network_env_var("BITCOIN_REST_PORT", network)
+		.map(|port| port.parse::<u16>().expect("BITCOIN_REST_PORT env variable must be a u16."))
+		.unwrap_or_else(|_| default_bitcoin_rest_port(network));
+	let default_path = network_env_var("BITCOIN_REST_PATH", network).unwrap_or("/rest/".to_string());
+
+	if let Ok(list) = network_env_var("BITCOIN_REST_ENDPOINTS", network) {
+		let endpoints = parse_rest_endpoint_list("BITCOIN_REST_ENDPOINTS", &list, default_port, &default_path);
+		assert!(!endpoints.is_empty(), "BITCOIN_REST_ENDPOINTS must contain at least one endpoint");
+		return endpoints;
 	}
-	config
+
+	let host = network_env_var("BITCOIN_REST_DOMAIN", network).unwrap_or("127.0.0.1".to_string());
+	vec![HttpEndpoint::for_host(host).with_port(default_port).with_path(default_path)]
 }
 
-pub(crate) fn bitcoin_rest_endpoint() -> HttpEndpoint {
-	let host = env::var("BITCOIN_REST_DOMAIN").unwrap_or("127.0.0.1".to_string());
-	let port = env::var("BITCOIN_REST_PORT")
-		.unwrap_or("8332".to_string())
-		.parse::<u16>()
-		.expect("BITCOIN_REST_PORT env variable must be a u16.");
-	let path = env::var("BITCOIN_REST_PATH").unwrap_or("/rest/".to_string());
-	HttpEndpoint::for_host(host).with_port(port).with_path(path)
+/// The bitcoind REST endpoints to retrieve blocks from that the primary endpoints have pruned, in
+/// the `filters` verification mode. Their entries take the same form as `BITCOIN_REST_ENDPOINTS`'.
+pub(crate) fn bitcoin_rest_fallback_endpoints(network: Network) -> Vec<HttpEndpoint> {
+	let list = match network_env_var("BITCOIN_REST_FALLBACK_ENDPOINTS", network) {
+		Ok(list) => list,
+		Err(_) => return Vec::new(),
+	};
+	let default_port = network_env_var("BITCOIN_REST_PORT", network)
+		.map(|port| port.parse::<u16>().expect("BITCOIN_REST_PORT env variable must be a u16."))
+		.unwrap_or_else(|_| default_bitcoin_rest_port(network));
+	let default_path = network_env_var("BITCOIN_REST_PATH", network).unwrap_or("/rest/".to_string());
+	parse_rest_endpoint_list("BITCOIN_REST_FALLBACK_ENDPOINTS", &list, default_port, &default_path)
+}
+
+fn parse_rest_endpoint_list(name: &str, list: &str, default_port: u16, default_path: &str) -> Vec<HttpEndpoint> {
+	list.split(',')
+		.map(|entry| entry.trim())
+		.filter(|entry| !entry.is_empty())
+		.enumerate()
+		.map(|(item, entry)| {
+			parse_rest_endpoint(entry, default_port, default_path).unwrap_or_else(|_| {
+				panic!("Invalid endpoint in {} at item {}: {}", name, item, entry)
+			})
+		})
+		.collect()
+}
+
+fn parse_rest_endpoint(endpoint: &str, default_port: u16, default_path: &str) -> Result<HttpEndpoint, &'static str> {
+	let (authority, path) = match endpoint.find('/') {
+		Some(path_start) => endpoint.split_at(path_start),
+		None => (endpoint, default_path),
+	};
+	// don't mistake the colons within bracketed IPv6 literals for a port separator
+	let (host, port) = match authority.rsplit_once(':') {
+		Some((host, port)) if !port.ends_with(']') => {
+			(host, port.parse::<u16>().map_err(|_| "Invalid port")?)
+		},
+		_ => (authority, default_port),
+	};
+	if host.is_empty() {
+		return Err("Missing host");
+	}
+	Ok(HttpEndpoint::for_host(host.to_string()).with_port(port).with_path(path.to_string()))
 }
 
 pub(crate) fn db_config_table_creation_query() -> &'static str {
@@ -176,7 +1281,7 @@ pub(crate) fn db_index_creation_query() -> &'static str {
 }
 
 pub(crate) async fn upgrade_db<L: Deref + Clone + Send + Sync + 'static>(
-	schema: i32, client: &mut tokio_postgres::Client, logger: L,
+	network: Network, schema: i32, client: &mut tokio_postgres::Client, logger: L,
 ) where L::Target: Logger {
 	if schema == 1 {
 		let tx = client.transaction().await.unwrap();
@@ -329,18 +1434,19 @@ pub(crate) async fn upgrade_db<L: Deref + Clone + Send + Sync + 'static>(
 		// resuming on a crash.
 		let _ = client.execute("ALTER TABLE channel_announcements ADD COLUMN funding_amount_sats bigint DEFAULT null", &[]).await;
 		tokio::spawn(async move {
-			let client = crate::connect_to_db().await;
+			let client = crate::connect_to_db(network).await;
 			let mut scids = Box::pin(client.query_raw("SELECT DISTINCT ON (short_channel_id) short_channel_id FROM channel_announcements WHERE funding_amount_sats IS NULL;", &[0i64][1..]).await.unwrap());
 			let sem = Arc::new(Semaphore::new(16));
+			let chain_source: Arc<dyn ChainSource> = Arc::new(RestChainSource::new(network, logger.clone()));
 			while let Some(scid_res) = scids.next().await {
 				let scid: i64 = scid_res.unwrap().get(0);
 				let permit = Arc::clone(&sem).acquire_owned().await.unwrap();
 				let logger = logger.clone();
+				let chain_source = Arc::clone(&chain_source);
 				tokio::spawn(async move {
-					let rest_client = Arc::new(RestClient::new(bitcoin_rest_endpoint()));
-					let txo = ChainVerifier::retrieve_txo(rest_client, scid as u64, logger).await
+					let txo = ChainVerifier::retrieve_txo(chain_source, scid as u64, logger).await
 						.expect("We shouldn't have accepted a channel announce with a bad TXO");
-					let client = crate::connect_to_db().await;
+					let client = crate::connect_to_db(network).await;
 					client.execute("UPDATE channel_announcements SET funding_amount_sats = $1 WHERE short_channel_id = $2", &[&(txo.value.to_sat() as i64), &scid]).await.unwrap();
 					std::mem::drop(permit);
 				});
@@ -350,7 +1456,7 @@ pub(crate) async fn upgrade_db<L: Deref + Clone + Send + Sync + 'static>(
 			client.execute("UPDATE config SET db_schema = 15 WHERE id = 1", &[]).await.unwrap();
 		});
 	}
-	if schema <= 1 || schema > SCHEMA_VERSION {
+	if schema <= 1 {
 		panic!("Unknown schema in db: {}, we support up to {}", schema, SCHEMA_VERSION);
 	}
 	// PostgreSQL (at least v13, but likely later versions as well) handles insert-only tables
@@ -362,24 +1468,315 @@ pub(crate) async fn upgrade_db<L: Deref + Clone + Send + Sync + 'static>(
 	let _ = client.execute("ALTER TABLE channel_announcements SET ( autovacuum_vacuum_insert_scale_factor = 0.005 );", &[]).await;
 }
 
-pub(crate) fn ln_peers() -> Vec<(PublicKey, SocketAddr)> {
-	const WALLET_OF_SATOSHI: &str = "035e4ff418fc8b5554c5d9eea66396c227bd429a3251c8cbc711002ba215bfc226@170.75.163.209:9735";
-	let list = env::var("LN_PEERS").unwrap_or(WALLET_OF_SATOSHI.to_string());
+/// The configured peers, if any, or a description of why they're invalid
+pub(crate) fn try_ln_peers(network: Network) -> Result<Option<Vec<(PublicKey, SocketAddress)>>, String> {
+	peer_list("LN_PEERS", network)
+}
+
+/// The peers to replace silent or flapping ones with before resorting to DNS seeds
+pub(crate) fn backup_ln_peers(network: Network) -> Result<Vec<(PublicKey, SocketAddress)>, String> {
+	Ok(peer_list("LN_BACKUP_PEERS", network)?.unwrap_or_default())
+}
+
+fn peer_list(name: &str, network: Network) -> Result<Option<Vec<(PublicKey, SocketAddress)>>, String> {
+	let list = match network_env_var(name, network) {
+		Ok(list) => list,
+		Err(_) => return Ok(None),
+	};
 	let mut peers = Vec::new();
 	for (item, peer_info) in list.split(',').enumerate() {
 		// Ignore leading or trailing whitespace
 		let trimmed_peer_info = peer_info.trim();
 		// Ignore trailing or repeated commas
 		if !trimmed_peer_info.is_empty() {
-			peers.push(resolve_peer_info(trimmed_peer_info).unwrap_or_else(|_| {
-				panic!("Invalid peer info in LN_PEERS at item {}: {}", item, peer_info)
-			}));
+			let peer = parse_peer_info(trimmed_peer_info)
+				.map_err(|_| format!("Invalid peer info in {} at item {}: {}", name, item, peer_info))?;
+			peers.push(peer);
 		}
 	}
-	peers
+	Ok(Some(peers))
+}
+
+/// The peers to fall back on if none are configured and none could be discovered via DNS seeds
+pub(crate) fn default_ln_peers(network: Network) -> Vec<(PublicKey, SocketAddress)> {
+	const WALLET_OF_SATOSHI: &str = "035e4ff418fc8b5554c5d9eea66396c227bd429a3251c8cbc711002ba215bfc226@170.75.163.209:9735";
+	// our default peer is only of any use on mainnet
+	if network != Network::Bitcoin {
+		return Vec::new();
+	}
+	vec![parse_peer_info(WALLET_OF_SATOSHI).unwrap()]
 }
 
-fn resolve_peer_info(peer_info: &str) -> Result<(PublicKey, SocketAddr), &str> {
+/// The BOLT 10 DNS seeds to discover peers from if none are configured
+pub(crate) fn dns_seeds(network: Network) -> Vec<String> {
+	let default_seeds = match network {
+		Network::Bitcoin => "nodes.lightning.directory,lseed.bitcoinstats.com",
+		Network::Testnet => "test.nodes.lightning.directory",
+		_ => "",
+	};
+	let seeds = network_env_var("RAPID_GOSSIP_SYNC_SERVER_DNS_SEEDS", network).unwrap_or(default_seeds.to_string());
+	seeds.split(',').map(|seed| seed.trim().to_string()).filter(|seed| !seed.is_empty()).collect()
+}
+
+pub(crate) fn dns_seed_peer_count() -> usize {
+	var("RAPID_GOSSIP_SYNC_SERVER_DNS_SEED_PEER_COUNT").unwrap_or(DEFAULT_DNS_SEED_PEER_COUNT.to_string())
+		.parse::<usize>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_DNS_SEED_PEER_COUNT env variable must be a usize.")
+}
+
+/// How long a connected peer may go without sending any gossip before it's replaced
+pub(crate) fn peer_silence_timeout() -> Duration {
+	let secs = var("RAPID_GOSSIP_SYNC_SERVER_PEER_SILENCE_TIMEOUT").unwrap_or(DEFAULT_PEER_SILENCE_TIMEOUT.as_secs().to_string())
+		.parse::<u64>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_PEER_SILENCE_TIMEOUT env variable must be a u64.");
+	Duration::from_secs(secs)
+}
+
+/// How long no gossip at all may be received before the sync is considered stalled
+pub(crate) fn gossip_stall_timeout() -> Duration {
+	let secs = var("RAPID_GOSSIP_SYNC_SERVER_GOSSIP_STALL_TIMEOUT").unwrap_or(DEFAULT_GOSSIP_STALL_TIMEOUT.as_secs().to_string())
+		.parse::<u64>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_GOSSIP_STALL_TIMEOUT env variable must be a u64.");
+	assert!(secs > 0, "RAPID_GOSSIP_SYNC_SERVER_GOSSIP_STALL_TIMEOUT must be positive");
+	Duration::from_secs(secs)
+}
+
+/// How often a peer may disconnect within an hour before it's considered to be flapping and replaced
+pub(crate) fn peer_max_disconnections() -> usize {
+	var("RAPID_GOSSIP_SYNC_SERVER_PEER_MAX_DISCONNECTIONS").unwrap_or(DEFAULT_PEER_MAX_DISCONNECTIONS.to_string())
+		.parse::<usize>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_PEER_MAX_DISCONNECTIONS env variable must be a usize.")
+}
+
+/// The SOCKS5 proxy, e. g. Tor's, to route all outbound peer connections through
+pub(crate) fn proxy() -> Option<SocketAddr> {
+	let address = var("RAPID_GOSSIP_SYNC_SERVER_PROXY").ok()?;
+	let address = address.to_socket_addrs()
+		.ok()
+		.and_then(|mut addresses| addresses.next())
+		.expect("RAPID_GOSSIP_SYNC_SERVER_PROXY env variable must be a host:port address.");
+	Some(address)
+}
+
+/// Whether validated gossip is relayed to our peers, which read-only collectors may disable
+pub(crate) fn gossip_relay_enabled() -> bool {
+	var("RAPID_GOSSIP_SYNC_SERVER_GOSSIP_RELAY").unwrap_or("true".to_string())
+		.parse::<bool>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_GOSSIP_RELAY env variable must be a boolean.")
+}
+
+/// How many of the messages received from each peer are relayed per minute, if limited
+pub(crate) fn gossip_relay_rate_limit() -> Option<u32> {
+	var("RAPID_GOSSIP_SYNC_SERVER_GOSSIP_RELAY_RATE_LIMIT").ok().map(|limit| limit
+		.parse::<u32>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_GOSSIP_RELAY_RATE_LIMIT env variable must be a u32."))
+}
+
+/// How many consecutive connection attempts to a peer may fail before it's given up on, if limited
+pub(crate) fn peer_reconnect_max_attempts() -> Option<u32> {
+	var("RAPID_GOSSIP_SYNC_SERVER_PEER_RECONNECT_MAX_ATTEMPTS").ok().map(|attempts| attempts
+		.parse::<u32>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_PEER_RECONNECT_MAX_ATTEMPTS env variable must be a u32."))
+}
+
+pub(crate) fn db_batch_size() -> usize {
+	let batch_size = var("RAPID_GOSSIP_SYNC_SERVER_DB_BATCH_SIZE").unwrap_or(DEFAULT_DB_BATCH_SIZE.to_string())
+		.parse::<usize>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_DB_BATCH_SIZE env variable must be a usize.");
+	assert!(batch_size > 0, "RAPID_GOSSIP_SYNC_SERVER_DB_BATCH_SIZE must be positive");
+	batch_size
+}
+
+pub(crate) fn db_flush_interval() -> Duration {
+	let interval_ms = var("RAPID_GOSSIP_SYNC_SERVER_DB_FLUSH_INTERVAL_MS").unwrap_or(DEFAULT_DB_FLUSH_INTERVAL_MS.to_string())
+		.parse::<u64>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_DB_FLUSH_INTERVAL_MS env variable must be a u64.");
+	Duration::from_millis(interval_ms)
+}
+
+/// The directory every accepted gossip message is archived to, if any
+pub(crate) fn gossip_recording_path(network: Network) -> Option<String> {
+	network_env_var("RAPID_GOSSIP_SYNC_SERVER_GOSSIP_RECORDING_PATH", network).ok()
+}
+
+/// The size in bytes beyond which a new gossip recording file is started
+pub(crate) fn gossip_recording_file_size() -> u64 {
+	let megabytes = var("RAPID_GOSSIP_SYNC_SERVER_GOSSIP_RECORDING_FILE_SIZE_MB")
+		.map(|size| size.parse::<u64>().expect("RAPID_GOSSIP_SYNC_SERVER_GOSSIP_RECORDING_FILE_SIZE_MB env variable must be an integer"))
+		.unwrap_or(DEFAULT_GOSSIP_RECORDING_FILE_SIZE_MB);
+	assert!(megabytes > 0, "RAPID_GOSSIP_SYNC_SERVER_GOSSIP_RECORDING_FILE_SIZE_MB must be positive");
+	megabytes * 1024 * 1024
+}
+
+/// How many gossip recording files to keep, deleting the oldest ones beyond that, if limited
+pub(crate) fn gossip_recording_max_files() -> Option<usize> {
+	let max_files = var("RAPID_GOSSIP_SYNC_SERVER_GOSSIP_RECORDING_MAX_FILES").ok()?
+		.parse::<usize>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_GOSSIP_RECORDING_MAX_FILES env variable must be an integer");
+	assert!(max_files > 0, "RAPID_GOSSIP_SYNC_SERVER_GOSSIP_RECORDING_MAX_FILES must be positive");
+	Some(max_files)
+}
+
+/// The snapshot, given as an HTTP(S) URL or a file path, for a server without a cached network
+/// graph to bootstrap its graph and database from
+pub(crate) fn bootstrap_snapshot(network: Network) -> Option<String> {
+	network_env_var("RAPID_GOSSIP_SYNC_SERVER_BOOTSTRAP_SNAPSHOT", network).ok()
+}
+
+/// How often the network graph is cached to disk, such that restarts only need to catch up on the
+/// gossip persisted since
+pub(crate) fn graph_checkpoint_interval() -> Duration {
+	let interval = var("RAPID_GOSSIP_SYNC_SERVER_GRAPH_CHECKPOINT_INTERVAL").unwrap_or(DEFAULT_GRAPH_CHECKPOINT_INTERVAL_SECS.to_string())
+		.parse::<u64>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_GRAPH_CHECKPOINT_INTERVAL env variable must be a u64.");
+	assert!(interval > 0, "RAPID_GOSSIP_SYNC_SERVER_GRAPH_CHECKPOINT_INTERVAL must be positive");
+	Duration::from_secs(interval)
+}
+
+pub(crate) fn persistence_queue_size() -> usize {
+	let queue_size = var("RAPID_GOSSIP_SYNC_SERVER_PERSISTENCE_QUEUE_SIZE").unwrap_or(DEFAULT_PERSISTENCE_QUEUE_SIZE.to_string())
+		.parse::<usize>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_PERSISTENCE_QUEUE_SIZE env variable must be a usize.");
+	assert!(queue_size > 0, "RAPID_GOSSIP_SYNC_SERVER_PERSISTENCE_QUEUE_SIZE must be positive");
+	queue_size
+}
+
+/// What to do with gossip while the persistence queue is full
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum PersistenceOverflow {
+	/// Hold up gossip processing, and thereby reading from peers, until there's room in the queue
+	Block,
+	/// Drop channel updates that only refresh the timestamp of the channel direction's current
+	/// parameters, holding up gossip processing for all other messages
+	DropRedundantUpdates,
+}
+
+pub(crate) fn persistence_overflow() -> PersistenceOverflow {
+	match var("RAPID_GOSSIP_SYNC_SERVER_PERSISTENCE_OVERFLOW").as_deref() {
+		Ok("block") | Err(_) => PersistenceOverflow::Block,
+		Ok("drop-redundant-updates") => PersistenceOverflow::DropRedundantUpdates,
+		Ok(_) => panic!("RAPID_GOSSIP_SYNC_SERVER_PERSISTENCE_OVERFLOW env variable must be one of block, drop-redundant-updates"),
+	}
+}
+
+/// How much memory the caches alongside the network graph may take up
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum MemoryProfile {
+	/// Caches sized for throughput, some of which grow along with the network graph
+	Standard,
+	/// Caches bounded for small hosts, at the cost of more chain lookups and database writes
+	Constrained,
+}
+
+pub(crate) fn memory_profile() -> MemoryProfile {
+	match var("RAPID_GOSSIP_SYNC_SERVER_MEMORY_PROFILE").as_deref() {
+		Ok("standard") | Err(_) => MemoryProfile::Standard,
+		Ok("constrained") => MemoryProfile::Constrained,
+		Ok(_) => panic!("RAPID_GOSSIP_SYNC_SERVER_MEMORY_PROFILE env variable must be one of standard, constrained"),
+	}
+}
+
+/// How many funding amounts are cached, if they're bounded at all
+pub(crate) fn funding_amount_cache_capacity() -> Option<usize> {
+	match memory_profile() {
+		MemoryProfile::Standard => None,
+		MemoryProfile::Constrained => Some(CONSTRAINED_FUNDING_AMOUNT_CACHE_ENTRIES),
+	}
+}
+
+/// How many channel directions are tracked for deduplicating updates, if they're bounded at all
+pub(crate) fn deduplicated_update_capacity() -> Option<usize> {
+	match memory_profile() {
+		MemoryProfile::Standard => None,
+		MemoryProfile::Constrained => Some(CONSTRAINED_DEDUPLICATED_UPDATE_DIRECTIONS),
+	}
+}
+
+pub(crate) fn dynamic_snapshot_cache_entries() -> usize {
+	match memory_profile() {
+		MemoryProfile::Standard => MAX_DYNAMIC_SNAPSHOT_CACHE_ENTRIES,
+		MemoryProfile::Constrained => CONSTRAINED_DYNAMIC_SNAPSHOT_CACHE_ENTRIES,
+	}
+}
+
+/// Whether only the node announcement details snapshots are made of are kept in the network graph,
+/// dropping their signatures and excess data, such that they're no longer relayed to syncing peers
+pub(crate) fn strip_node_announcements() -> bool {
+	var("RAPID_GOSSIP_SYNC_SERVER_STRIP_NODE_ANNOUNCEMENTS").unwrap_or("false".to_string())
+		.parse::<bool>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_STRIP_NODE_ANNOUNCEMENTS env variable must be a boolean.")
+}
+
+/// Whether the full snapshot's default channel update values are precomputed as the most common
+/// ones across the graph each round, rather than left at zero
+pub(crate) fn full_snapshot_defaults() -> bool {
+	var("RAPID_GOSSIP_SYNC_SERVER_FULL_SNAPSHOT_DEFAULTS").unwrap_or("true".to_string())
+		.parse::<bool>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_FULL_SNAPSHOT_DEFAULTS env variable must be a boolean.")
+}
+
+/// Whether channel updates leaving their channel direction's parameters unchanged are skipped
+/// rather than persisted, other than for a daily refresh
+pub(crate) fn deduplicate_updates() -> bool {
+	var("RAPID_GOSSIP_SYNC_SERVER_DEDUPLICATE_UPDATES").unwrap_or("false".to_string())
+		.parse::<bool>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_DEDUPLICATE_UPDATES env variable must be a boolean.")
+}
+
+/// How long after its latest update a channel direction is considered stale, upon which it's
+/// removed from the network graph, and thus from the snapshots, along with the channels left
+/// without either direction. LDK's clients prune channels after [`PRUNE_INTERVAL`] on their own.
+pub(crate) fn staleness_horizon() -> Duration {
+	let horizon_days = var("RAPID_GOSSIP_SYNC_SERVER_STALENESS_HORIZON_DAYS").unwrap_or((PRUNE_INTERVAL.as_secs() / (24 * 3600)).to_string())
+		.parse::<u64>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_STALENESS_HORIZON_DAYS env variable must be a u64.");
+	let horizon = Duration::from_secs(horizon_days * 24 * 3600);
+	// channels must outlive their reminders, including those refreshed by deduplicated updates
+	let minimum_horizon = CHANNEL_REMINDER_AGE + DEDUPLICATED_UPDATE_REFRESH_INTERVAL;
+	assert!(horizon > minimum_horizon, "RAPID_GOSSIP_SYNC_SERVER_STALENESS_HORIZON_DAYS must exceed {} days for channel reminders to be sent", minimum_horizon.as_secs() / (24 * 3600));
+	horizon
+}
+
+/// How long channel updates are retained after they were first seen, if they are to be pruned at
+/// all. Snapshots must still be able to look back across their full scope, as well as the
+/// [`staleness_horizon`] behind channel reminders.
+pub(crate) fn update_retention() -> Option<Duration> {
+	let retention_days = var("RAPID_GOSSIP_SYNC_SERVER_UPDATE_RETENTION_DAYS").ok()?
+		.parse::<u64>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_UPDATE_RETENTION_DAYS env variable must be a u64.");
+	let retention = Duration::from_secs(retention_days * 24 * 3600);
+	let longest_scope = snapshot_scopes().into_iter().filter(|scope| *scope != u64::MAX).max().unwrap_or(0);
+	let minimum_retention = Duration::from_secs(longest_scope).max(staleness_horizon());
+	assert!(retention >= minimum_retention, "RAPID_GOSSIP_SYNC_SERVER_UPDATE_RETENTION_DAYS must cover at least {} days to accommodate the snapshot scopes and channel reminders", (minimum_retention.as_secs() + 24 * 3600 - 1) / (24 * 3600));
+	Some(retention)
+}
+
+pub(crate) fn update_retention_count() -> u32 {
+	let count = var("RAPID_GOSSIP_SYNC_SERVER_UPDATE_RETENTION_COUNT").unwrap_or(DEFAULT_UPDATE_RETENTION_COUNT.to_string())
+		.parse::<u32>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_UPDATE_RETENTION_COUNT env variable must be a u32.");
+	assert!(count > 0, "RAPID_GOSSIP_SYNC_SERVER_UPDATE_RETENTION_COUNT must be positive");
+	count
+}
+
+pub(crate) fn peer_reconnect_base_delay() -> Duration {
+	let delay_ms = var("RAPID_GOSSIP_SYNC_SERVER_PEER_RECONNECT_DELAY_MS").unwrap_or(DEFAULT_PEER_RECONNECT_BASE_DELAY_MS.to_string())
+		.parse::<u64>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_PEER_RECONNECT_DELAY_MS env variable must be a u64.");
+	Duration::from_millis(delay_ms)
+}
+
+pub(crate) fn peer_reconnect_max_delay() -> Duration {
+	let delay_secs = var("RAPID_GOSSIP_SYNC_SERVER_PEER_RECONNECT_MAX_DELAY").unwrap_or(DEFAULT_PEER_RECONNECT_MAX_DELAY_SECS.to_string())
+		.parse::<u64>()
+		.expect("RAPID_GOSSIP_SYNC_SERVER_PEER_RECONNECT_MAX_DELAY env variable must be a u64.");
+	Duration::from_secs(delay_secs)
+}
+
+/// Parse `pubkey@host:port`, where the host may be an IPv4 address, an IPv6 address enclosed in
+/// brackets, a host name, or, if peers are connected to via a proxy, an onion address. The port
+/// defaults to [`DEFAULT_PEER_PORT`]. Host names are resolved anew for each connection attempt.
+pub(crate) fn parse_peer_info(peer_info: &str) -> Result<(PublicKey, SocketAddress), &str> {
 	let mut peer_info = peer_info.splitn(2, '@');
 
 	let pubkey = peer_info.next().ok_or("Invalid peer info. Should be formatted as: `pubkey@host:port`")?;
@@ -387,11 +1784,18 @@ fn resolve_peer_info(peer_info: &str) -> Result<(PublicKey, SocketAddr), &str> {
 	let pubkey = PublicKey::from_slice(&pubkey).map_err(|_| "Invalid node pubkey")?;
 
 	let socket_address = peer_info.next().ok_or("Invalid peer info. Should be formatted as: `pubkey@host:port`")?;
-	let socket_address = socket_address
-		.to_socket_addrs()
-		.map_err(|_| "Cannot resolve node address")?
-		.next()
-		.ok_or("Cannot resolve node address")?;
+	if Ipv6Addr::from_str(socket_address).is_ok() {
+		return Err("IPv6 addresses must be enclosed in brackets, as in `[::1]:9735`");
+	}
+	let has_port = match socket_address.strip_prefix('[') {
+		Some(bracketed_address) => bracketed_address.contains("]:"),
+		None => socket_address.contains(':'),
+	};
+	let socket_address = if has_port { socket_address.to_string() } else { format!("{}:{}", socket_address, DEFAULT_PEER_PORT) };
+	let socket_address = SocketAddress::from_str(&socket_address).map_err(|_| "Invalid node address")?;
+	if matches!(socket_address, SocketAddress::OnionV2(_) | SocketAddress::OnionV3 { .. }) && proxy().is_none() {
+		return Err("Onion addresses require RAPID_GOSSIP_SYNC_SERVER_PROXY to be set");
+	}
 
 	Ok((pubkey, socket_address))
 }
@@ -400,41 +1804,135 @@ fn resolve_peer_info(peer_info: &str) -> Result<(PublicKey, SocketAddr), &str> {
 mod tests {
 	use super::*;
 	use hex_conservative::DisplayHex;
-	use std::str::FromStr;
 
 	#[test]
-	fn test_resolve_peer_info() {
+	fn test_embedded_settings() {
+		let name = "RAPID_GOSSIP_SYNC_SERVER_TEST_EMBEDDED_SETTING";
+		assert!(var(name).is_err());
+		env::set_var(name, "environment");
+		assert_eq!(var(name).unwrap(), "environment");
+		apply_embedded_settings(HashMap::from([(name.to_string(), "embedded".to_string())]));
+		assert_eq!(var(name).unwrap(), "embedded");
+	}
+
+	#[test]
+	fn test_schema_name_validation() {
+		assert!(is_valid_schema_name("rgs_bitcoin"));
+		assert!(is_valid_schema_name("_Gossip2"));
+		assert!(!is_valid_schema_name(""));
+		assert!(!is_valid_schema_name("2gossip"));
+		assert!(!is_valid_schema_name("rgs-bitcoin"));
+		assert!(!is_valid_schema_name("public; DROP SCHEMA public CASCADE"));
+		assert!(!is_valid_schema_name("\"rgs\""));
+		assert!(!is_valid_schema_name(&"s".repeat(64)));
+	}
+
+	#[test]
+	fn test_parse_peer_info() {
 		let wallet_of_satoshi = "035e4ff418fc8b5554c5d9eea66396c227bd429a3251c8cbc711002ba215bfc226@170.75.163.209:9735";
-		let (pubkey, socket_address) = resolve_peer_info(wallet_of_satoshi).unwrap();
+		let (pubkey, socket_address) = parse_peer_info(wallet_of_satoshi).unwrap();
 		assert_eq!(
 			pubkey.serialize().to_lower_hex_string(),
 			"035e4ff418fc8b5554c5d9eea66396c227bd429a3251c8cbc711002ba215bfc226"
 		);
-		assert_eq!(socket_address.to_string(), "170.75.163.209:9735");
+		assert_eq!(socket_address, SocketAddress::from_str("170.75.163.209:9735").unwrap());
 
 		let ipv6 = "033d8656219478701227199cbd6f670335c8d408a92ae88b962c49d4dc0e83e025@[2001:db8::1]:80";
-		let (pubkey, socket_address) = resolve_peer_info(ipv6).unwrap();
+		let (pubkey, socket_address) = parse_peer_info(ipv6).unwrap();
 		assert_eq!(
 			pubkey.serialize().to_lower_hex_string(),
 			"033d8656219478701227199cbd6f670335c8d408a92ae88b962c49d4dc0e83e025"
 		);
-		assert_eq!(socket_address.to_string(), "[2001:db8::1]:80");
+		assert_eq!(socket_address, SocketAddress::from_str("[2001:db8::1]:80").unwrap());
 
+		// host names are only resolved when connecting, such that changed addresses are picked up
 		let localhost = "033d8656219478701227199cbd6f670335c8d408a92ae88b962c49d4dc0e83e025@localhost:9735";
-		let (pubkey, socket_address) = resolve_peer_info(localhost).unwrap();
-		assert_eq!(
-			pubkey.serialize().to_lower_hex_string(),
-			"033d8656219478701227199cbd6f670335c8d408a92ae88b962c49d4dc0e83e025"
-		);
-		let socket_address = socket_address.to_string();
-		assert!(socket_address == "127.0.0.1:9735" || socket_address == "[::1]:9735");
+		let (_, socket_address) = parse_peer_info(localhost).unwrap();
+		assert_eq!(socket_address, SocketAddress::from_str("localhost:9735").unwrap());
+		assert!(matches!(socket_address, SocketAddress::Hostname { .. }));
+
+		// the port defaults to the one nodes listen on
+		let (_, socket_address) = parse_peer_info("033d8656219478701227199cbd6f670335c8d408a92ae88b962c49d4dc0e83e025@[2001:db8::1]").unwrap();
+		assert_eq!(socket_address, SocketAddress::from_str("[2001:db8::1]:9735").unwrap());
+		let (_, socket_address) = parse_peer_info("033d8656219478701227199cbd6f670335c8d408a92ae88b962c49d4dc0e83e025@170.75.163.209").unwrap();
+		assert_eq!(socket_address, SocketAddress::from_str("170.75.163.209:9735").unwrap());
+		assert!(parse_peer_info("033d8656219478701227199cbd6f670335c8d408a92ae88b962c49d4dc0e83e025@2001:db8::1").is_err());
+
+		// onion services can only be reached via a proxy
+		let onion = "033d8656219478701227199cbd6f670335c8d408a92ae88b962c49d4dc0e83e025@pg6mmjiyjmcrsslvykfwnntlaru7p5svn6y2ymmju6nubxndf4pscryd.onion:9735";
+		assert!(parse_peer_info(onion).is_err());
+	}
+
+	#[test]
+	fn test_default_bitcoin_rest_port() {
+		assert_eq!(default_bitcoin_rest_port(Network::Bitcoin), 8332);
+		assert_eq!(default_bitcoin_rest_port(Network::Testnet), 18332);
+		assert_eq!(default_bitcoin_rest_port(Network::Signet), 38332);
+		assert_eq!(default_bitcoin_rest_port(Network::Regtest), 18443);
+	}
+
+	#[test]
+	fn test_parse_snapshot_compression() {
+		assert_eq!(parse_snapshot_compression("").unwrap(), vec![]);
+		assert_eq!(parse_snapshot_compression("gzip:6, zstd").unwrap(), vec![(SnapshotCompression::Gzip, 6), (SnapshotCompression::Zstd, 19)]);
+		assert_eq!(parse_snapshot_compression("br").unwrap(), vec![(SnapshotCompression::Brotli, 11)]);
+		assert!(parse_snapshot_compression("gzip:10").is_err());
+		assert!(parse_snapshot_compression("gzip,gz").is_err());
+		assert!(parse_snapshot_compression("lz4").is_err());
+		assert!(parse_snapshot_compression("zstd:fast").is_err());
+	}
+
+	#[test]
+	fn test_db_ssl_mode_from_name() {
+		assert_eq!(DbSslMode::from_name("disable"), Some(DbSslMode::Disable));
+		assert_eq!(DbSslMode::from_name("verify-ca"), Some(DbSslMode::VerifyCa));
+		assert_eq!(DbSslMode::from_name("verify-full"), Some(DbSslMode::VerifyFull));
+		assert_eq!(DbSslMode::from_name("allow"), None);
+	}
+
+	#[test]
+	fn test_parse_snapshot_scopes() {
+		assert_eq!(parse_snapshot_scopes("1h,6h,24h,full").unwrap(), vec![3600, 6 * 3600, 24 * 3600, u64::MAX]);
+		assert_eq!(parse_snapshot_scopes("1w, 10800, 1d").unwrap(), vec![10800, 24 * 3600, 7 * 24 * 3600, u64::MAX]);
+		assert_eq!(parse_snapshot_scopes("").unwrap(), vec![u64::MAX]);
+		assert!(parse_snapshot_scopes("0h").is_err());
+		assert!(parse_snapshot_scopes("1h,3600").is_err());
+		assert!(parse_snapshot_scopes("1y").is_err());
+	}
+
+	#[test]
+	fn test_parse_serialization_versions() {
+		assert_eq!(parse_serialization_versions("1,2").unwrap(), vec![1, 2]);
+		assert_eq!(parse_serialization_versions("v2, v1").unwrap(), vec![1, 2]);
+		assert_eq!(parse_serialization_versions("2").unwrap(), vec![2]);
+		assert!(parse_serialization_versions("").is_err());
+		assert!(parse_serialization_versions("1,3").is_err());
+		assert!(parse_serialization_versions("2,2").is_err());
+	}
+
+	#[test]
+	fn test_parse_rest_endpoint() {
+		let endpoint = parse_rest_endpoint("bitcoind-1", 8332, "/rest/").unwrap();
+		assert_eq!((endpoint.host(), endpoint.port(), endpoint.path()), ("bitcoind-1", 8332, "/rest/"));
+
+		let endpoint = parse_rest_endpoint("10.0.0.2:18332/bitcoin/rest/", 8332, "/rest/").unwrap();
+		assert_eq!((endpoint.host(), endpoint.port(), endpoint.path()), ("10.0.0.2", 18332, "/bitcoin/rest/"));
+
+		let endpoint = parse_rest_endpoint("[2001:db8::1]:8333", 8332, "/rest/").unwrap();
+		assert_eq!((endpoint.host(), endpoint.port(), endpoint.path()), ("[2001:db8::1]", 8333, "/rest/"));
+
+		let endpoint = parse_rest_endpoint("[2001:db8::1]", 8332, "/rest/").unwrap();
+		assert_eq!((endpoint.host(), endpoint.port()), ("[2001:db8::1]", 8332));
+
+		assert!(parse_rest_endpoint("localhost:port", 8332, "/rest/").is_err());
+		assert!(parse_rest_endpoint(":8332", 8332, "/rest/").is_err());
 	}
 
 	#[test]
 	fn test_ln_peers() {
 		// Set the environment variable, including a repeated comma, leading space, and trailing comma.
 		std::env::set_var("LN_PEERS", "035e4ff418fc8b5554c5d9eea66396c227bd429a3251c8cbc711002ba215bfc226@170.75.163.209:9735,, 035e4ff418fc8b5554c5d9eea66396c227bd429a3251c8cbc711002ba215bfc227@170.75.163.210:9735,");
-		let peers = ln_peers();
+		let peers = try_ln_peers(Network::Bitcoin).unwrap().unwrap();
 
 		// Assert output is as expected
 		assert_eq!(
@@ -442,11 +1940,11 @@ mod tests {
 			vec![
 				(
 					PublicKey::from_str("035e4ff418fc8b5554c5d9eea66396c227bd429a3251c8cbc711002ba215bfc226").unwrap(),
-					SocketAddr::from_str("170.75.163.209:9735").unwrap()
+					SocketAddress::from_str("170.75.163.209:9735").unwrap()
 				),
 				(
 					PublicKey::from_str("035e4ff418fc8b5554c5d9eea66396c227bd429a3251c8cbc711002ba215bfc227").unwrap(),
-					SocketAddr::from_str("170.75.163.210:9735").unwrap()
+					SocketAddress::from_str("170.75.163.210:9735").unwrap()
 				)
 			]
 		);
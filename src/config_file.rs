@@ -0,0 +1,290 @@
+//! Support for a TOML config file, as an alternative to setting every environment variable.
+//!
+//! Each setting in the file corresponds to one of the environment variables documented in the
+//! README, which take precedence over the file. Settings that can differ between networks may
+//! also be placed in a table named after the network, e. g. `[signet]` or `[signet.bitcoind]`,
+//! equivalent to suffixing the environment variable with the uppercase network name.
+//!
+//! ```toml
+//! networks = ["bitcoin", "signet"]
+//!
+//! [database]
+//! url = "postgres://rgs@localhost/ln_graph_sync"
+//!
+//! [snapshot]
+//! interval = 10800
+//! scopes = ["3h", "1d", "full"]
+//!
+//! [signet]
+//! ln_peers = ["<pubkey>@<host>:<port>"]
+//!
+//! [signet.bitcoind]
+//! port = 38332
+//! ```
+
+use std::collections::HashMap;
+
+use toml::{Table, Value};
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+	String,
+	Integer,
+	Boolean,
+	/// An array of strings or integers, joined with commas like their environment variable
+	List,
+}
+
+struct Setting {
+	key: &'static str,
+	variable: &'static str,
+	kind: Kind,
+	/// Whether the setting may be overridden for an individual network
+	per_network: bool,
+}
+
+const fn setting(key: &'static str, variable: &'static str, kind: Kind, per_network: bool) -> Setting {
+	Setting { key, variable, kind, per_network }
+}
+
+const SETTINGS: &[Setting] = &[
+	setting("network", "RAPID_GOSSIP_SYNC_SERVER_NETWORK", Kind::String, false),
+	setting("networks", "RAPID_GOSSIP_SYNC_SERVER_NETWORKS", Kind::List, false),
+	setting("cache_path", "RAPID_GOSSIP_SYNC_SERVER_CACHES_PATH", Kind::String, true),
+	setting("ln_peers", "LN_PEERS", Kind::List, true),
+	setting("backup_peers", "LN_BACKUP_PEERS", Kind::List, true),
+	setting("dns_seeds", "RAPID_GOSSIP_SYNC_SERVER_DNS_SEEDS", Kind::List, true),
+	setting("dns_seed_peer_count", "RAPID_GOSSIP_SYNC_SERVER_DNS_SEED_PEER_COUNT", Kind::Integer, false),
+	setting("peer_silence_timeout", "RAPID_GOSSIP_SYNC_SERVER_PEER_SILENCE_TIMEOUT", Kind::Integer, false),
+	setting("gossip_stall_timeout", "RAPID_GOSSIP_SYNC_SERVER_GOSSIP_STALL_TIMEOUT", Kind::Integer, false),
+	setting("peer_max_disconnections", "RAPID_GOSSIP_SYNC_SERVER_PEER_MAX_DISCONNECTIONS", Kind::Integer, false),
+	setting("proxy", "RAPID_GOSSIP_SYNC_SERVER_PROXY", Kind::String, false),
+	setting("peer_reconnect_max_attempts", "RAPID_GOSSIP_SYNC_SERVER_PEER_RECONNECT_MAX_ATTEMPTS", Kind::Integer, false),
+	setting("peer_reconnect_delay_ms", "RAPID_GOSSIP_SYNC_SERVER_PEER_RECONNECT_DELAY_MS", Kind::Integer, false),
+	setting("peer_reconnect_max_delay", "RAPID_GOSSIP_SYNC_SERVER_PEER_RECONNECT_MAX_DELAY", Kind::Integer, false),
+	setting("http_address", "RAPID_GOSSIP_SYNC_SERVER_HTTP_ADDRESS", Kind::String, true),
+	setting("http_tls_cert", "RAPID_GOSSIP_SYNC_SERVER_HTTP_TLS_CERT", Kind::String, false),
+	setting("http_tls_key", "RAPID_GOSSIP_SYNC_SERVER_HTTP_TLS_KEY", Kind::String, false),
+	setting("http_rate_limit", "RAPID_GOSSIP_SYNC_SERVER_HTTP_RATE_LIMIT", Kind::Integer, false),
+	setting("http_rate_limit_burst", "RAPID_GOSSIP_SYNC_SERVER_HTTP_RATE_LIMIT_BURST", Kind::Integer, false),
+	setting("http_max_concurrent_requests", "RAPID_GOSSIP_SYNC_SERVER_HTTP_MAX_CONCURRENT_REQUESTS", Kind::Integer, false),
+	setting("http_request_analytics", "RAPID_GOSSIP_SYNC_SERVER_HTTP_REQUEST_ANALYTICS", Kind::String, false),
+	setting("stats_sample_interval", "RAPID_GOSSIP_SYNC_SERVER_STATS_SAMPLE_INTERVAL", Kind::Integer, false),
+	setting("stats_retention_days", "RAPID_GOSSIP_SYNC_SERVER_STATS_RETENTION_DAYS", Kind::Integer, false),
+	setting("admin_address", "RAPID_GOSSIP_SYNC_SERVER_ADMIN_ADDRESS", Kind::String, true),
+	setting("admin_token", "RAPID_GOSSIP_SYNC_SERVER_ADMIN_TOKEN", Kind::String, false),
+	setting("grpc_address", "RAPID_GOSSIP_SYNC_SERVER_GRPC_ADDRESS", Kind::String, true),
+	setting("graph_export", "RAPID_GOSSIP_SYNC_SERVER_GRAPH_EXPORT", Kind::Boolean, false),
+	setting("query_api", "RAPID_GOSSIP_SYNC_SERVER_QUERY_API", Kind::Boolean, false),
+	setting("gossip_stream", "RAPID_GOSSIP_SYNC_SERVER_GOSSIP_STREAM", Kind::Boolean, false),
+	setting("gossip_stream_max_subscribers", "RAPID_GOSSIP_SYNC_SERVER_GOSSIP_STREAM_MAX_SUBSCRIBERS", Kind::Integer, false),
+	setting("gossip_relay", "RAPID_GOSSIP_SYNC_SERVER_GOSSIP_RELAY", Kind::Boolean, false),
+	setting("gossip_relay_rate_limit", "RAPID_GOSSIP_SYNC_SERVER_GOSSIP_RELAY_RATE_LIMIT", Kind::Integer, false),
+	setting("listen_address", "RAPID_GOSSIP_SYNC_SERVER_LISTEN_ADDRESS", Kind::String, true),
+	setting("announced_address", "RAPID_GOSSIP_SYNC_SERVER_ANNOUNCED_ADDRESS", Kind::String, true),
+	setting("max_inbound_peers", "RAPID_GOSSIP_SYNC_SERVER_MAX_INBOUND_PEERS", Kind::Integer, false),
+	setting("max_snapshot_age", "RAPID_GOSSIP_SYNC_SERVER_MAX_SNAPSHOT_AGE", Kind::Integer, false),
+	setting("log_level", "RAPID_GOSSIP_SYNC_SERVER_LOG_LEVEL", Kind::String, false),
+	setting("log_format", "RAPID_GOSSIP_SYNC_SERVER_LOG_FORMAT", Kind::String, false),
+	setting("database.backend", "RAPID_GOSSIP_SYNC_SERVER_DB_BACKEND", Kind::String, true),
+	setting("database.sqlite_path", "RAPID_GOSSIP_SYNC_SERVER_SQLITE_PATH", Kind::String, true),
+	setting("persistence_queue_size", "RAPID_GOSSIP_SYNC_SERVER_PERSISTENCE_QUEUE_SIZE", Kind::Integer, false),
+	setting("persistence_overflow", "RAPID_GOSSIP_SYNC_SERVER_PERSISTENCE_OVERFLOW", Kind::String, false),
+	setting("alert_webhook_url", "RAPID_GOSSIP_SYNC_SERVER_ALERT_WEBHOOK_URL", Kind::String, true),
+	setting("blocklist_path", "RAPID_GOSSIP_SYNC_SERVER_BLOCKLIST_PATH", Kind::String, false),
+	setting("gossip_recording_path", "RAPID_GOSSIP_SYNC_SERVER_GOSSIP_RECORDING_PATH", Kind::String, true),
+	setting("gossip_recording_file_size_mb", "RAPID_GOSSIP_SYNC_SERVER_GOSSIP_RECORDING_FILE_SIZE_MB", Kind::Integer, false),
+	setting("gossip_recording_max_files", "RAPID_GOSSIP_SYNC_SERVER_GOSSIP_RECORDING_MAX_FILES", Kind::Integer, false),
+	setting("bootstrap_snapshot", "RAPID_GOSSIP_SYNC_SERVER_BOOTSTRAP_SNAPSHOT", Kind::String, true),
+	setting("graph_checkpoint_interval", "RAPID_GOSSIP_SYNC_SERVER_GRAPH_CHECKPOINT_INTERVAL", Kind::Integer, false),
+	setting("deduplicate_updates", "RAPID_GOSSIP_SYNC_SERVER_DEDUPLICATE_UPDATES", Kind::Boolean, false),
+	setting("memory_profile", "RAPID_GOSSIP_SYNC_SERVER_MEMORY_PROFILE", Kind::String, false),
+	setting("strip_node_announcements", "RAPID_GOSSIP_SYNC_SERVER_STRIP_NODE_ANNOUNCEMENTS", Kind::Boolean, false),
+	setting("full_snapshot_defaults", "RAPID_GOSSIP_SYNC_SERVER_FULL_SNAPSHOT_DEFAULTS", Kind::Boolean, false),
+	setting("leader_election", "RAPID_GOSSIP_SYNC_SERVER_LEADER_ELECTION", Kind::Boolean, false),
+	setting("database.batch_size", "RAPID_GOSSIP_SYNC_SERVER_DB_BATCH_SIZE", Kind::Integer, false),
+	setting("database.flush_interval_ms", "RAPID_GOSSIP_SYNC_SERVER_DB_FLUSH_INTERVAL_MS", Kind::Integer, false),
+	setting("database.read_url", "RAPID_GOSSIP_SYNC_SERVER_DB_READ_URL", Kind::String, false),
+	setting("database.read_password", "RAPID_GOSSIP_SYNC_SERVER_DB_READ_PASSWORD", Kind::String, false),
+	setting("database.ssl_mode", "RAPID_GOSSIP_SYNC_SERVER_DB_SSL_MODE", Kind::String, false),
+	setting("database.ssl_root_cert", "RAPID_GOSSIP_SYNC_SERVER_DB_SSL_ROOT_CERT", Kind::String, false),
+	setting("database.ssl_cert", "RAPID_GOSSIP_SYNC_SERVER_DB_SSL_CERT", Kind::String, false),
+	setting("database.ssl_key", "RAPID_GOSSIP_SYNC_SERVER_DB_SSL_KEY", Kind::String, false),
+	setting("database.update_retention_days", "RAPID_GOSSIP_SYNC_SERVER_UPDATE_RETENTION_DAYS", Kind::Integer, false),
+	setting("database.update_retention_count", "RAPID_GOSSIP_SYNC_SERVER_UPDATE_RETENTION_COUNT", Kind::Integer, false),
+	setting("database.url", "RAPID_GOSSIP_SYNC_SERVER_DB_URL", Kind::String, false),
+	setting("database.host", "RAPID_GOSSIP_SYNC_SERVER_DB_HOST", Kind::String, false),
+	setting("database.user", "RAPID_GOSSIP_SYNC_SERVER_DB_USER", Kind::String, false),
+	setting("database.password", "RAPID_GOSSIP_SYNC_SERVER_DB_PASSWORD", Kind::String, false),
+	setting("database.name", "RAPID_GOSSIP_SYNC_SERVER_DB_NAME", Kind::String, false),
+	setting("database.schema", "RAPID_GOSSIP_SYNC_SERVER_DB_SCHEMA", Kind::String, true),
+	setting("bitcoind.endpoints", "BITCOIN_REST_ENDPOINTS", Kind::List, true),
+	setting("bitcoind.fallback_endpoints", "BITCOIN_REST_FALLBACK_ENDPOINTS", Kind::List, true),
+	setting("bitcoind.verification_mode", "RAPID_GOSSIP_SYNC_SERVER_VERIFICATION_MODE", Kind::String, false),
+	setting("bitcoind.txindex", "RAPID_GOSSIP_SYNC_SERVER_BITCOIN_REST_TXINDEX", Kind::Boolean, false),
+	setting("bitcoind.domain", "BITCOIN_REST_DOMAIN", Kind::String, true),
+	setting("bitcoind.port", "BITCOIN_REST_PORT", Kind::Integer, true),
+	setting("bitcoind.path", "BITCOIN_REST_PATH", Kind::String, true),
+	setting("bitcoind.retries", "RAPID_GOSSIP_SYNC_SERVER_BITCOIN_REST_RETRIES", Kind::Integer, false),
+	setting("bitcoind.retry_delay_ms", "RAPID_GOSSIP_SYNC_SERVER_BITCOIN_REST_RETRY_DELAY_MS", Kind::Integer, false),
+	setting("bitcoind.connect_timeout", "RAPID_GOSSIP_SYNC_SERVER_BITCOIN_REST_CONNECT_TIMEOUT", Kind::Integer, false),
+	setting("bitcoind.request_timeout", "RAPID_GOSSIP_SYNC_SERVER_BITCOIN_REST_REQUEST_TIMEOUT", Kind::Integer, false),
+	setting("bitcoind.max_connections", "RAPID_GOSSIP_SYNC_SERVER_BITCOIN_REST_MAX_CONNECTIONS", Kind::Integer, false),
+	setting("bitcoind.slow_request_ms", "RAPID_GOSSIP_SYNC_SERVER_BITCOIN_REST_SLOW_REQUEST_MS", Kind::Integer, false),
+	setting("bitcoind.max_concurrent_utxo_lookups", "RAPID_GOSSIP_SYNC_SERVER_MAX_CONCURRENT_UTXO_LOOKUPS", Kind::Integer, false),
+	setting("bitcoind.block_cache_size", "RAPID_GOSSIP_SYNC_SERVER_BLOCK_CACHE_SIZE", Kind::Integer, false),
+	setting("bitcoind.block_prefetch_depth", "RAPID_GOSSIP_SYNC_SERVER_BLOCK_PREFETCH_DEPTH", Kind::Integer, false),
+	setting("bitcoind.verify_unspent", "RAPID_GOSSIP_SYNC_SERVER_VERIFY_UNSPENT", Kind::Boolean, false),
+	setting("bitcoind.tip_lag_threshold", "RAPID_GOSSIP_SYNC_SERVER_CHAIN_TIP_LAG_THRESHOLD", Kind::Integer, false),
+	setting("snapshot.interval", "RAPID_GOSSIP_SYNC_SERVER_SNAPSHOT_INTERVAL", Kind::Integer, false),
+	setting("snapshot.scopes", "RAPID_GOSSIP_SYNC_SERVER_SNAPSHOT_SCOPES", Kind::List, false),
+	setting("snapshot.concurrency", "RAPID_GOSSIP_SYNC_SERVER_SNAPSHOT_CONCURRENCY", Kind::Integer, false),
+	setting("snapshot.versions", "RAPID_GOSSIP_SYNC_SERVER_SNAPSHOT_VERSIONS", Kind::List, false),
+	setting("snapshot.compression", "RAPID_GOSSIP_SYNC_SERVER_SNAPSHOT_COMPRESSION", Kind::List, false),
+	setting("snapshot.profiles", "RAPID_GOSSIP_SYNC_SERVER_SNAPSHOT_PROFILES", Kind::List, false),
+	setting("snapshot.on_shutdown", "RAPID_GOSSIP_SYNC_SERVER_SNAPSHOT_ON_SHUTDOWN", Kind::Boolean, false),
+	setting("snapshot.verification", "RAPID_GOSSIP_SYNC_SERVER_SNAPSHOT_VERIFICATION", Kind::Boolean, false),
+	setting("snapshot.staleness_horizon_days", "RAPID_GOSSIP_SYNC_SERVER_STALENESS_HORIZON_DAYS", Kind::Integer, false),
+	setting("snapshot.deviation_threshold", "RAPID_GOSSIP_SYNC_SERVER_SNAPSHOT_DEVIATION_THRESHOLD", Kind::Integer, false),
+	setting("snapshot.archive_path", "RAPID_GOSSIP_SYNC_SERVER_ARCHIVE_PATH", Kind::String, true),
+	setting("snapshot.archive_retention_days", "RAPID_GOSSIP_SYNC_SERVER_ARCHIVE_RETENTION_DAYS", Kind::Integer, false),
+	setting("snapshot.archive_upload", "RAPID_GOSSIP_SYNC_SERVER_ARCHIVE_UPLOAD", Kind::Boolean, false),
+	setting("snapshot.signing_key", "RAPID_GOSSIP_SYNC_SERVER_SNAPSHOT_SIGNING_KEY", Kind::String, false),
+	setting("snapshot.include_node_aliases", "RAPID_GOSSIP_SYNC_SERVER_INCLUDE_NODE_ALIASES", Kind::Boolean, false),
+	setting("snapshot.min_channel_capacity_sats", "RAPID_GOSSIP_SYNC_SERVER_MIN_CHANNEL_CAPACITY_SATS", Kind::Integer, false),
+	setting("snapshot.dynamic", "RAPID_GOSSIP_SYNC_SERVER_DYNAMIC_SNAPSHOTS", Kind::Boolean, false),
+	setting("snapshot.dynamic_cache_ttl", "RAPID_GOSSIP_SYNC_SERVER_DYNAMIC_SNAPSHOT_CACHE_TTL", Kind::Integer, false),
+	setting("snapshot.webhook_url", "RAPID_GOSSIP_SYNC_SERVER_SNAPSHOT_WEBHOOK_URL", Kind::String, true),
+	setting("snapshot.hook_command", "RAPID_GOSSIP_SYNC_SERVER_SNAPSHOT_HOOK_COMMAND", Kind::String, true),
+	setting("s3.bucket", "RAPID_GOSSIP_SYNC_SERVER_S3_BUCKET", Kind::String, true),
+	setting("s3.region", "RAPID_GOSSIP_SYNC_SERVER_S3_REGION", Kind::String, true),
+	setting("s3.endpoint", "RAPID_GOSSIP_SYNC_SERVER_S3_ENDPOINT", Kind::String, true),
+	setting("s3.prefix", "RAPID_GOSSIP_SYNC_SERVER_S3_PREFIX", Kind::String, true),
+	setting("s3.cache_control", "RAPID_GOSSIP_SYNC_SERVER_S3_CACHE_CONTROL", Kind::String, true),
+	setting("s3.access_key_id", "AWS_ACCESS_KEY_ID", Kind::String, false),
+	setting("s3.secret_access_key", "AWS_SECRET_ACCESS_KEY", Kind::String, false),
+];
+
+/// The environment variable suffix for a per-network table, if the name is that of a network
+fn network_suffix(name: &str) -> Option<&'static str> {
+	match name {
+		"mainnet" | "bitcoin" => Some("BITCOIN"),
+		"testnet" => Some("TESTNET"),
+		"testnet4" => Some("TESTNET4"),
+		"signet" => Some("SIGNET"),
+		"regtest" => Some("REGTEST"),
+		_ => None,
+	}
+}
+
+/// Read the config file at `path`, mapping the environment variable names to their values.
+pub(crate) fn load(path: &str) -> Result<HashMap<String, String>, String> {
+	let contents = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+	parse(&contents)
+}
+
+pub(crate) fn parse(contents: &str) -> Result<HashMap<String, String>, String> {
+	let table: Table = contents.parse().map_err(|e: toml::de::Error| e.to_string())?;
+	let mut variables = HashMap::new();
+	collect_settings(&table, "", None, &mut variables)?;
+	Ok(variables)
+}
+
+fn collect_settings(table: &Table, key_prefix: &str, network_suffix: Option<&'static str>, variables: &mut HashMap<String, String>) -> Result<(), String> {
+	for (name, value) in table {
+		let key = format!("{}{}", key_prefix, name);
+		if let Value::Table(nested_table) = value {
+			if key_prefix.is_empty() && network_suffix.is_none() {
+				if let Some(suffix) = self::network_suffix(name) {
+					collect_settings(nested_table, "", Some(suffix), variables)?;
+					continue;
+				}
+			}
+			collect_settings(nested_table, &format!("{}.", key), network_suffix, variables)?;
+			continue;
+		}
+
+		let setting = SETTINGS.iter().find(|setting| setting.key == key)
+			.ok_or_else(|| format!("unknown setting `{}`", key))?;
+		#[cfg(not(feature = "grpc"))]
+		if setting.variable == "RAPID_GOSSIP_SYNC_SERVER_GRPC_ADDRESS" {
+			return Err(format!("`{}` requires the server to be built with the grpc feature", key));
+		}
+		let variable = match network_suffix {
+			Some(_) if !setting.per_network => return Err(format!("`{}` cannot be set for an individual network", key)),
+			Some(suffix) => format!("{}_{}", setting.variable, suffix),
+			None => setting.variable.to_string(),
+		};
+		variables.insert(variable, setting_value(&key, value, setting.kind)?);
+	}
+	Ok(())
+}
+
+fn setting_value(key: &str, value: &Value, kind: Kind) -> Result<String, String> {
+	let expected = match kind {
+		Kind::String => "a string",
+		Kind::Integer => "a non-negative integer",
+		Kind::Boolean => "a boolean",
+		Kind::List => "an array",
+	};
+	match (kind, value) {
+		(Kind::String, Value::String(value)) => Ok(value.clone()),
+		(Kind::Integer, Value::Integer(value)) if *value >= 0 => Ok(value.to_string()),
+		(Kind::Boolean, Value::Boolean(value)) => Ok(value.to_string()),
+		(Kind::List, Value::Array(items)) => {
+			let items = items.iter().map(|item| match item {
+				Value::String(item) if !item.contains(',') => Ok(item.clone()),
+				Value::Integer(item) => Ok(item.to_string()),
+				_ => Err(format!("`{}` must only contain strings (without commas) or integers", key)),
+			}).collect::<Result<Vec<String>, String>>()?;
+			Ok(items.join(","))
+		}
+		_ => Err(format!("`{}` must be {}", key, expected)),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::config_file::parse;
+
+	#[test]
+	fn test_parse_config_file() {
+		let variables = parse(r#"
+			networks = ["bitcoin", "signet"]
+
+			[database]
+			url = "postgres://rgs@localhost/ln_graph_sync"
+
+			[snapshot]
+			interval = 10800
+			scopes = ["3h", "1d", "full"]
+			versions = [1, 2]
+			dynamic = true
+
+			[signet]
+			ln_peers = ["02aa@127.0.0.1:9735", "03bb@127.0.0.1:9736"]
+			cache_path = "./res/signet"
+
+			[signet.bitcoind]
+			port = 38332
+		"#).unwrap();
+		assert_eq!(variables.len(), 9);
+		assert_eq!(variables["RAPID_GOSSIP_SYNC_SERVER_NETWORKS"], "bitcoin,signet");
+		assert_eq!(variables["RAPID_GOSSIP_SYNC_SERVER_DB_URL"], "postgres://rgs@localhost/ln_graph_sync");
+		assert_eq!(variables["RAPID_GOSSIP_SYNC_SERVER_SNAPSHOT_INTERVAL"], "10800");
+		assert_eq!(variables["RAPID_GOSSIP_SYNC_SERVER_SNAPSHOT_SCOPES"], "3h,1d,full");
+		assert_eq!(variables["RAPID_GOSSIP_SYNC_SERVER_SNAPSHOT_VERSIONS"], "1,2");
+		assert_eq!(variables["RAPID_GOSSIP_SYNC_SERVER_DYNAMIC_SNAPSHOTS"], "true");
+		assert_eq!(variables["LN_PEERS_SIGNET"], "02aa@127.0.0.1:9735,03bb@127.0.0.1:9736");
+		assert_eq!(variables["RAPID_GOSSIP_SYNC_SERVER_CACHES_PATH_SIGNET"], "./res/signet");
+		assert_eq!(variables["BITCOIN_REST_PORT_SIGNET"], "38332");
+
+		assert_eq!(parse("[snapshot]\nintervall = 10800").unwrap_err(), "unknown setting `snapshot.intervall`");
+		assert_eq!(parse("[snapshot]\ninterval = \"3h\"").unwrap_err(), "`snapshot.interval` must be a non-negative integer");
+		assert_eq!(parse("[signet.snapshot]\ninterval = 10800").unwrap_err(), "`snapshot.interval` cannot be set for an individual network");
+		assert_eq!(parse("ln_peers = [\"a,b\"]").unwrap_err(), "`ln_peers` must only contain strings (without commas) or integers");
+		assert!(parse("network = ").is_err());
+		#[cfg(not(feature = "grpc"))]
+		assert_eq!(parse("[signet]\ngrpc_address = \"127.0.0.1:50051\"").unwrap_err(), "`grpc_address` requires the server to be built with the grpc feature");
+	}
+}
@@ -0,0 +1,96 @@
+//! Peer discovery via Lightning DNS seeds ([BOLT 10]).
+//!
+//! A seed answers SRV queries for its domain with virtual hostnames of the form
+//! `<bech32-encoded node id>.<seed domain>`, each pointing at a node's port and resolving to its
+//! address.
+//!
+//! [BOLT 10]: https://github.com/lightning/bolts/blob/master/10-dns-seed.md
+
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::ops::Deref;
+
+use bitcoin::bech32;
+use bitcoin::secp256k1::PublicKey;
+use hickory_resolver::TokioAsyncResolver;
+use hickory_resolver::config::{ResolverConfig, ResolverOpts};
+use lightning::{log_info, log_warn};
+use lightning::ln::msgs::SocketAddress;
+use lightning::util::logger::Logger;
+
+/// Query the given seeds in order until `peer_count` distinct peers have been discovered.
+pub(crate) async fn discover_peers<L: Deref>(seeds: &[String], peer_count: usize, logger: &L) -> Vec<(PublicKey, SocketAddress)> where L::Target: Logger {
+	let resolver = TokioAsyncResolver::tokio_from_system_conf()
+		.unwrap_or_else(|_| TokioAsyncResolver::tokio(ResolverConfig::default(), ResolverOpts::default()));
+
+	let mut peers = Vec::new();
+	let mut discovered_node_ids = HashSet::new();
+	for seed in seeds {
+		if peers.len() >= peer_count {
+			break;
+		}
+		log_info!(logger, "Querying DNS seed {}", seed);
+		let records = match resolver.srv_lookup(seed.as_str()).await {
+			Ok(records) => records,
+			Err(e) => {
+				log_warn!(logger, "Failed to query DNS seed {}: {}", seed, e);
+				continue;
+			}
+		};
+		for record in records.iter() {
+			if peers.len() >= peer_count {
+				break;
+			}
+			let virtual_hostname = record.target();
+			let node_id = match virtual_hostname.iter().next().and_then(|label| std::str::from_utf8(label).ok()).and_then(decode_node_id) {
+				Some(node_id) => node_id,
+				None => {
+					log_warn!(logger, "Ignoring malformed DNS seed record {}", virtual_hostname);
+					continue;
+				}
+			};
+			if discovered_node_ids.contains(&node_id) {
+				continue;
+			}
+			let address = match resolver.lookup_ip(virtual_hostname.clone()).await {
+				Ok(addresses) => addresses.iter().next(),
+				Err(_) => None,
+			};
+			if let Some(address) = address {
+				discovered_node_ids.insert(node_id);
+				peers.push((node_id, SocketAddr::new(address, record.port()).into()));
+			}
+		}
+	}
+	log_info!(logger, "Discovered {} peers via DNS seeds", peers.len());
+	peers
+}
+
+/// Decode the node id from the first label of a virtual hostname, e. g. `ln1q...`
+fn decode_node_id(label: &str) -> Option<PublicKey> {
+	let (hrp, data) = bech32::decode(label).ok()?;
+	if hrp.as_str() != "ln" {
+		return None;
+	}
+	PublicKey::from_slice(&data).ok()
+}
+
+#[cfg(test)]
+mod tests {
+	use bitcoin::bech32::{self, Bech32, Hrp};
+	use bitcoin::secp256k1::PublicKey;
+
+	use crate::dns_seed::decode_node_id;
+
+	#[test]
+	fn test_decode_node_id() {
+		let node_id: PublicKey = "035e4ff418fc8b5554c5d9eea66396c227bd429a3251c8cbc711002ba215bfc226".parse().unwrap();
+		let label = bech32::encode::<Bech32>(Hrp::parse("ln").unwrap(), &node_id.serialize()).unwrap();
+		assert!(label.starts_with("ln1q"));
+		assert_eq!(decode_node_id(&label), Some(node_id));
+
+		let foreign_label = bech32::encode::<Bech32>(Hrp::parse("bc").unwrap(), &node_id.serialize()).unwrap();
+		assert_eq!(decode_node_id(&foreign_label), None);
+		assert_eq!(decode_node_id("nodes"), None);
+	}
+}
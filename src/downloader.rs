@@ -1,24 +1,35 @@
+use std::collections::HashMap;
 use std::ops::Deref;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 
-use bitcoin::secp256k1::PublicKey;
+use bitcoin::secp256k1::{PublicKey, Secp256k1, VerifyOnly};
+use lightning::log_warn;
 use lightning::events::{MessageSendEvent, MessageSendEventsProvider};
-use lightning::ln::msgs::{ChannelAnnouncement, ChannelUpdate, Init, LightningError, NodeAnnouncement, QueryChannelRange, QueryShortChannelIds, ReplyChannelRange, ReplyShortChannelIdsEnd, RoutingMessageHandler};
-use lightning::routing::gossip::{NetworkGraph, NodeId, P2PGossipSync};
+use lightning::ln::msgs::{ChannelAnnouncement, ChannelUpdate, ErrorAction, Init, LightningError, NodeAnnouncement, QueryChannelRange, QueryShortChannelIds, ReplyChannelRange, ReplyShortChannelIdsEnd, RoutingMessageHandler};
+use lightning::routing::gossip::{verify_node_announcement, ChannelUpdateInfo, NetworkGraph, NodeId, P2PGossipSync};
 use lightning::types::features::{InitFeatures, NodeFeatures};
-use lightning::util::logger::Logger;
+use lightning::util::logger::{Level, Logger};
+use lightning::util::ser::Writeable;
 use tokio::sync::mpsc;
 use tokio::sync::mpsc::error::TrySendError;
 
+use crate::blocklist;
+use crate::chain::ChainSource;
+use crate::config::{self, PersistenceOverflow};
+use crate::feed::GossipFeed;
+use crate::leader::Leadership;
+use crate::metrics::{GossipOutcome, Metrics};
+use crate::peer_health::PeerHealthTracker;
+use crate::recording::{GossipRecorder, CHANNEL_ANNOUNCEMENT_TYPE, CHANNEL_UPDATE_TYPE, NODE_ANNOUNCEMENT_TYPE};
 use crate::types::{GossipMessage, GossipChainAccess, GossipPeerManager};
-use crate::verifier::ChainVerifier;
+use crate::verifier::{ChainVerifier, FundingAmountCache};
 
 pub(crate) struct GossipCounter {
 	pub(crate) node_announcements: u64,
 	pub(crate) channel_announcements: u64,
 	pub(crate) channel_updates: u64,
 	pub(crate) channel_updates_without_htlc_max_msats: u64,
-	pub(crate) channel_announcements_with_mismatched_scripts: u64
 }
 
 impl GossipCounter {
@@ -28,29 +39,113 @@ impl GossipCounter {
 			channel_announcements: 0,
 			channel_updates: 0,
 			channel_updates_without_htlc_max_msats: 0,
-			channel_announcements_with_mismatched_scripts: 0,
 		}
 	}
 }
 
+/// How long the relay rate limit's budget of messages lasts
+const RELAY_RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+
+/// Limits how many of the messages received from each peer are relayed to our other peers per
+/// [`RELAY_RATE_LIMIT_WINDOW`]. Messages beyond the limit are still stored, just not forwarded.
+struct RelayRateLimiter {
+	limit: u32,
+	windows: Mutex<HashMap<PublicKey, (Instant, u32)>>,
+}
+
+impl RelayRateLimiter {
+	fn new(limit: u32) -> Self {
+		Self { limit, windows: Mutex::new(HashMap::new()) }
+	}
+
+	fn allow(&self, node_id: PublicKey) -> bool {
+		let mut windows = self.windows.lock().unwrap();
+		let (window_start, relayed_count) = windows.entry(node_id).or_insert((Instant::now(), 0));
+		if window_start.elapsed() >= RELAY_RATE_LIMIT_WINDOW {
+			*window_start = Instant::now();
+			*relayed_count = 0;
+		}
+		if *relayed_count >= self.limit {
+			return false;
+		}
+		*relayed_count += 1;
+		true
+	}
+}
+
+/// Whether an update merely refreshes the timestamp of the parameters the channel direction
+/// already has, as peers periodically do to keep their channels from being pruned
+fn is_redundant_update(current: Option<&ChannelUpdateInfo>, msg: &ChannelUpdate) -> bool {
+	let current = match current {
+		Some(current) => current,
+		None => return false,
+	};
+	let update = &msg.contents;
+	current.last_update < update.timestamp
+		&& current.enabled == (update.channel_flags & 2 == 0)
+		&& current.cltv_expiry_delta == update.cltv_expiry_delta
+		&& current.htlc_minimum_msat == update.htlc_minimum_msat
+		&& current.htlc_maximum_msat == update.htlc_maximum_msat
+		&& current.fees.base_msat == update.fee_base_msat
+		&& current.fees.proportional_millionths == update.fee_proportional_millionths
+}
+
+/// The origin and overridden seen time of a channel announcement pending verification
+type PendingAnnouncement = (Option<PublicKey>, Option<u32>);
+
 pub(crate) struct GossipRouter<L: Deref + Clone + Send + Sync + 'static> where L::Target: Logger {
 	native_router: P2PGossipSync<Arc<NetworkGraph<L>>, GossipChainAccess<L>, L>,
+	/// Holds the funding amounts of the accepted channel announcements
+	network_graph: Arc<NetworkGraph<L>>,
 	pub(crate) counter: RwLock<GossipCounter>,
 	sender: mpsc::Sender<GossipMessage>,
 	verifier: Arc<ChainVerifier<L>>,
 	outbound_gossiper: Arc<P2PGossipSync<Arc<NetworkGraph<L>>, GossipChainAccess<L>, L>>,
+	peer_health: Arc<PeerHealthTracker>,
+	/// Whether validated gossip is forwarded to our peers, as opposed to only being collected
+	relay_enabled: bool,
+	relay_rate_limiter: Option<RelayRateLimiter>,
+	/// Only set if gossip is only relayed while leading the instances sharing the database
+	leadership: Option<Arc<Leadership>>,
+	persistence_overflow: PersistenceOverflow,
+	/// Whether node announcements are verified here rather than by LDK, such that only their
+	/// details are kept in the network graph
+	strip_node_announcements: bool,
+	secp_ctx: Secp256k1<VerifyOnly>,
+	metrics: Arc<Metrics>,
+	feed: Arc<GossipFeed>,
+	/// Only set if gossip recording is enabled
+	recorder: Option<GossipRecorder>,
+	/// The peers channel announcements pending verification were received from, and when they
+	/// were seen if that's overridden, as their acceptance is only learned of once they're broadcast
+	pending_announcements: Mutex<HashMap<u64, PendingAnnouncement>>,
+	logger: L,
 }
 
 impl<L: Deref + Clone + Send + Sync> GossipRouter<L> where L::Target: Logger {
-	pub(crate) fn new(network_graph: Arc<NetworkGraph<L>>, sender: mpsc::Sender<GossipMessage>, logger: L) -> Self {
+	#[allow(clippy::too_many_arguments)]
+	pub(crate) fn new(network_graph: Arc<NetworkGraph<L>>, sender: mpsc::Sender<GossipMessage>, chain_source: Arc<dyn ChainSource>, channel_funding_amounts: FundingAmountCache, peer_health: Arc<PeerHealthTracker>, metrics: Arc<Metrics>, feed: Arc<GossipFeed>, logger: L) -> Self {
 		let outbound_gossiper = Arc::new(P2PGossipSync::new(Arc::clone(&network_graph), None, logger.clone()));
-		let verifier = Arc::new(ChainVerifier::new(Arc::clone(&network_graph), Arc::clone(&outbound_gossiper), logger.clone()));
+		let verifier = Arc::new(ChainVerifier::new(Arc::clone(&network_graph), Arc::clone(&outbound_gossiper), chain_source, channel_funding_amounts, Arc::clone(&metrics), logger.clone()));
 		Self {
-			native_router: P2PGossipSync::new(network_graph, Some(Arc::clone(&verifier)), logger.clone()),
+			native_router: P2PGossipSync::new(Arc::clone(&network_graph), Some(Arc::clone(&verifier)), logger.clone()),
+			network_graph,
 			outbound_gossiper,
 			counter: RwLock::new(GossipCounter::new()),
 			sender,
-			verifier
+			verifier,
+			peer_health,
+			relay_enabled: config::gossip_relay_enabled(),
+			relay_rate_limiter: config::gossip_relay_rate_limit().map(RelayRateLimiter::new),
+			leadership: None,
+			persistence_overflow: config::persistence_overflow(),
+			strip_node_announcements: config::strip_node_announcements(),
+			secp_ctx: Secp256k1::verification_only(),
+			metrics,
+			feed,
+			recorder: None,
+			pending_announcements: Mutex::new(HashMap::new()),
+			logger,
 		}
 	}
 
@@ -58,55 +153,138 @@ impl<L: Deref + Clone + Send + Sync> GossipRouter<L> where L::Target: Logger {
 		self.verifier.set_ph(peer_handler);
 	}
 
-	fn new_channel_announcement(&self, msg: ChannelAnnouncement) {
+	pub(crate) fn mismatched_chain_announcement_count(&self) -> u64 {
+		self.verifier.mismatched_chain_announcement_count()
+	}
+
+	/// Wait until the channel announcements received so far have been verified, such that the
+	/// accepted ones are broadcast upon the next [`MessageSendEventsProvider::get_and_clear_pending_msg_events`]
+	pub(crate) async fn wait_for_utxo_lookups(&self) {
+		self.verifier.wait_for_pending_lookups().await
+	}
+
+	/// Record the accepted gossip, along with the peers it was received from
+	pub(crate) fn record_to(&mut self, recorder: GossipRecorder) {
+		self.recorder = Some(recorder);
+	}
+
+	/// Only relay gossip, and answer peers' gossip queries, while `leadership` is held
+	pub(crate) fn follow_leadership(&mut self, leadership: Arc<Leadership>) {
+		self.leadership = Some(leadership);
+	}
+
+	/// Whether gossip is currently forwarded to our peers
+	fn is_relaying(&self) -> bool {
+		self.relay_enabled && self.leadership.as_ref().map_or(true, |leadership| leadership.is_leader())
+	}
+
+	/// Whether to forward a message that LDK considers worth relaying
+	fn should_relay(&self, their_node_id: Option<PublicKey>, is_relayable: bool) -> bool {
+		if !is_relayable || !self.is_relaying() {
+			return false;
+		}
+		match (&self.relay_rate_limiter, their_node_id) {
+			(Some(rate_limiter), Some(node_id)) => rate_limiter.allow(node_id),
+			_ => true,
+		}
+	}
+
+	/// Attribute a message to the peer it was received from in the per-peer gossip stats
+	fn record_peer_gossip<M: Writeable>(&self, their_node_id: Option<PublicKey>, message_type: u16, msg: &M, result: &Result<bool, LightningError>) {
+		let node_id = match their_node_id {
+			Some(node_id) => node_id,
+			None => return,
+		};
+		let outcome = match result {
+			Ok(_) => GossipOutcome::New,
+			Err(LightningError { action: ErrorAction::IgnoreError | ErrorAction::IgnoreDuplicateGossip | ErrorAction::IgnoreAndLog(_), .. }) => GossipOutcome::Ignored,
+			Err(_) => GossipOutcome::Invalid,
+		};
+		// the size on the wire includes the message type
+		self.metrics.record_peer_gossip(node_id, message_type, msg.serialized_length() + 2, outcome);
+	}
+
+	/// Reject a message concerning a blocklisted node or channel
+	fn reject_blocklisted<M: Writeable>(&self, their_node_id: Option<PublicKey>, message_type: u16, msg: &M) -> Result<bool, LightningError> {
+		self.metrics.record_blocklisted();
+		let res = Err(LightningError { err: "Blocklisted node or channel".to_owned(), action: ErrorAction::IgnoreAndLog(Level::Gossip) });
+		self.record_peer_gossip(their_node_id, message_type, msg, &res);
+		res
+	}
+
+	fn new_channel_announcement(&self, msg: ChannelAnnouncement, origin: Option<PublicKey>, seen: Option<u32>) {
 		{
 			let mut counter = self.counter.write().unwrap();
 			counter.channel_announcements += 1;
 		}
 
-		let mut funding_amount_sats = self.verifier.get_cached_funding_value(msg.contents.short_channel_id);
+		// LDK stores the funding amount along with the accepted channel, whereas the cache may have
+		// evicted it in the meantime
+		let short_channel_id = msg.contents.short_channel_id;
+		let mut funding_amount_sats = self.network_graph.read_only().channel(short_channel_id).and_then(|channel| channel.capacity_sats)
+			.or_else(|| self.verifier.get_cached_funding_value(short_channel_id));
 		if funding_amount_sats.is_none() {
-			tokio::task::block_in_place(move || { tokio::runtime::Handle::current().block_on(async {
-				funding_amount_sats = self.verifier.retrieve_funding_value(msg.contents.short_channel_id).await.ok();
+			tokio::task::block_in_place(|| { tokio::runtime::Handle::current().block_on(async {
+				funding_amount_sats = self.verifier.retrieve_funding_value(short_channel_id).await.ok();
 			})});
 		}
-		let funding_amount_sats = funding_amount_sats
-			.expect("If we've accepted a ChannelAnnouncement, we must be able to fetch the TXO for it");
+		let funding_amount_sats = match funding_amount_sats {
+			Some(funding_amount_sats) => funding_amount_sats,
+			None => {
+				log_warn!(self.logger, "Not persisting the announcement of channel {}, as its funding amount couldn't be looked up", short_channel_id);
+				return;
+			}
+		};
 
-		let gossip_message = GossipMessage::ChannelAnnouncement(msg, funding_amount_sats, None);
-		if let Err(err) = self.sender.try_send(gossip_message) {
-			let gossip_message = match err { TrySendError::Full(msg)|TrySendError::Closed(msg) => msg };
-			tokio::task::block_in_place(move || { tokio::runtime::Handle::current().block_on(async move {
-				self.sender.send(gossip_message).await.unwrap();
-			})});
+		self.feed.publish_channel_announcement(&msg, funding_amount_sats);
+		if let Some(recorder) = &self.recorder {
+			recorder.record(CHANNEL_ANNOUNCEMENT_TYPE, &msg, origin);
 		}
+		self.persist(GossipMessage::ChannelAnnouncement(msg, funding_amount_sats, seen), false);
 	}
 
-	fn new_node_announcement(&self, msg: NodeAnnouncement) {
+	fn new_node_announcement(&self, msg: NodeAnnouncement, origin: Option<PublicKey>, seen: Option<u32>) {
 		{
 			let mut counter = self.counter.write().unwrap();
 			counter.node_announcements += 1;
 		}
 
-		let gossip_message = GossipMessage::NodeAnnouncement(msg, None);
-		if let Err(err) = self.sender.try_send(gossip_message) {
-			let gossip_message = match err { TrySendError::Full(msg)|TrySendError::Closed(msg) => msg };
-			tokio::task::block_in_place(move || { tokio::runtime::Handle::current().block_on(async move {
-				self.sender.send(gossip_message).await.unwrap();
-			})});
+		self.feed.publish_node_announcement(&msg);
+		if let Some(recorder) = &self.recorder {
+			recorder.record(NODE_ANNOUNCEMENT_TYPE, &msg, origin);
 		}
+		self.persist(GossipMessage::NodeAnnouncement(msg, seen), false);
 	}
 
-	fn new_channel_update(&self, msg: ChannelUpdate) {
+	fn new_channel_update(&self, msg: ChannelUpdate, redundant: bool, origin: Option<PublicKey>, seen: Option<u32>) {
 		self.counter.write().unwrap().channel_updates += 1;
-		let gossip_message = GossipMessage::ChannelUpdate(msg, None);
-
-		if let Err(err) = self.sender.try_send(gossip_message) {
-			let gossip_message = match err { TrySendError::Full(msg)|TrySendError::Closed(msg) => msg };
-			tokio::task::block_in_place(move || { tokio::runtime::Handle::current().block_on(async move {
-				self.sender.send(gossip_message).await.unwrap();
-			})});
+		self.feed.publish_channel_update(&msg);
+		if let Some(recorder) = &self.recorder {
+			recorder.record(CHANNEL_UPDATE_TYPE, &msg, origin);
 		}
+		self.persist(GossipMessage::ChannelUpdate(msg, seen), redundant);
+	}
+
+	/// Hand a message to the persister. While its queue is full, gossip processing is held up until
+	/// there's room, unless the message is redundant and may be dropped instead. Once the persister
+	/// has shut down, messages are discarded.
+	fn persist(&self, gossip_message: GossipMessage, redundant: bool) {
+		let gossip_message = match self.sender.try_send(gossip_message) {
+			Ok(()) => return,
+			Err(TrySendError::Full(msg)) => {
+				if redundant && self.persistence_overflow == PersistenceOverflow::DropRedundantUpdates {
+					self.metrics.record_persistence_dropped();
+					return;
+				}
+				self.metrics.record_persistence_deferred();
+				msg
+			},
+			Err(TrySendError::Closed(_)) => return,
+		};
+		tokio::task::block_in_place(move || { tokio::runtime::Handle::current().block_on(async move {
+			// the persister may shut down while we're waiting
+			let _ = self.sender.send(gossip_message).await;
+		})});
 	}
 }
 
@@ -116,13 +294,17 @@ impl<L: Deref + Clone + Send + Sync> MessageSendEventsProvider for GossipRouter<
 		for ev in gossip_evs {
 			match ev {
 				MessageSendEvent::BroadcastChannelAnnouncement { msg, .. } => {
-					self.new_channel_announcement(msg);
+					let (origin, seen) = self.pending_announcements.lock().unwrap().remove(&msg.contents.short_channel_id).unwrap_or_default();
+					if let Some(origin) = origin {
+						self.metrics.record_peer_gossip_accepted(origin);
+					}
+					self.new_channel_announcement(msg, origin, seen);
 				},
 				MessageSendEvent::BroadcastNodeAnnouncement { msg } => {
-					self.new_node_announcement(msg);
+					self.new_node_announcement(msg, None, None);
 				},
 				MessageSendEvent::BroadcastChannelUpdate { msg } => {
-					self.new_channel_update(msg);
+					self.new_channel_update(msg, false, None, None);
 				},
 				_ => { unreachable!() },
 			}
@@ -131,23 +313,105 @@ impl<L: Deref + Clone + Send + Sync> MessageSendEventsProvider for GossipRouter<
 	}
 }
 
+impl<L: Deref + Clone + Send + Sync> GossipRouter<L> where L::Target: Logger {
+	/// Validate and store a node announcement, which is stored as seen at `seen` if given rather
+	/// than now
+	pub(crate) fn process_node_announcement(&self, their_node_id: Option<PublicKey>, msg: &NodeAnnouncement, seen: Option<u32>) -> Result<bool, LightningError> {
+		if let Some(node_id) = their_node_id {
+			self.peer_health.record_message(node_id);
+		}
+		if blocklist::current().blocks_node(&msg.contents.node_id) {
+			return self.reject_blocklisted(their_node_id, NODE_ANNOUNCEMENT_TYPE, msg);
+		}
+		let res = if self.strip_node_announcements {
+			self.handle_stripped_node_announcement(msg)
+		} else {
+			self.native_router.handle_node_announcement(their_node_id, msg)
+		};
+		self.record_peer_gossip(their_node_id, NODE_ANNOUNCEMENT_TYPE, msg, &res);
+		let res = res?;
+		self.new_node_announcement(msg.clone(), their_node_id, seen);
+		Ok(self.should_relay(their_node_id, res))
+	}
+
+	/// Validate a node announcement like LDK does, but only keep the details snapshots are made of
+	/// in the network graph rather than the signed announcement, which therefore isn't relayed to
+	/// peers syncing the graph from us
+	fn handle_stripped_node_announcement(&self, msg: &NodeAnnouncement) -> Result<bool, LightningError> {
+		let network_graph = self.native_router.network_graph();
+		let last_update = network_graph.read_only().node(&msg.contents.node_id)
+			.and_then(|node| node.announcement_info.as_ref())
+			.map(|announcement_info| announcement_info.last_update());
+		// the graph rejects outdated announcements regardless, so spare verifying their signatures
+		if last_update.map_or(true, |last_update| last_update < msg.contents.timestamp) {
+			verify_node_announcement(msg, &self.secp_ctx)?;
+		}
+		network_graph.update_node_from_unsigned_announcement(&msg.contents)?;
+		let excess_data_length = msg.contents.excess_data.len() + msg.contents.excess_address_data.len();
+		Ok(excess_data_length <= config::MAX_EXCESS_BYTES_FOR_RELAY)
+	}
+
+	pub(crate) fn process_channel_announcement(&self, their_node_id: Option<PublicKey>, msg: &ChannelAnnouncement, seen: Option<u32>) -> Result<bool, LightningError> {
+		if let Some(node_id) = their_node_id {
+			self.peer_health.record_message(node_id);
+		}
+		if blocklist::current().blocks_channel(msg.contents.short_channel_id, &msg.contents.node_id_1, &msg.contents.node_id_2) {
+			return self.reject_blocklisted(their_node_id, CHANNEL_ANNOUNCEMENT_TYPE, msg);
+		}
+		if their_node_id.is_some() || seen.is_some() {
+			let mut pending_announcements = self.pending_announcements.lock().unwrap();
+			// announcements failing verification are never broadcast, so start over rather than
+			// accumulating them indefinitely
+			if pending_announcements.len() >= config::MAX_PENDING_ANNOUNCEMENTS {
+				pending_announcements.clear();
+			}
+			pending_announcements.insert(msg.contents.short_channel_id, (their_node_id, seen));
+		}
+		self.verifier.expect_funding_script(&msg.contents, their_node_id);
+		let res = self.native_router.handle_channel_announcement(their_node_id, msg);
+		self.record_peer_gossip(their_node_id, CHANNEL_ANNOUNCEMENT_TYPE, msg, &res);
+		let res = res?;
+		self.pending_announcements.lock().unwrap().remove(&msg.contents.short_channel_id);
+		self.new_channel_announcement(msg.clone(), their_node_id, seen);
+		Ok(self.should_relay(their_node_id, res))
+	}
+
+	pub(crate) fn process_channel_update(&self, their_node_id: Option<PublicKey>, msg: &ChannelUpdate, seen: Option<u32>) -> Result<bool, LightningError> {
+		if let Some(node_id) = their_node_id {
+			self.peer_health.record_message(node_id);
+		}
+		let (redundant, blocked) = {
+			let graph = self.native_router.network_graph().read_only();
+			let channel = graph.channel(msg.contents.short_channel_id);
+			let direction = channel.and_then(|channel| {
+				if msg.contents.channel_flags & 1 == 0 { channel.one_to_two.as_ref() } else { channel.two_to_one.as_ref() }
+			});
+			// channels of blocklisted nodes may have been announced before the nodes were blocklisted
+			let blocked = channel.is_some_and(|channel| blocklist::current().blocks_channel(msg.contents.short_channel_id, &channel.node_one, &channel.node_two));
+			(is_redundant_update(direction, msg), blocked)
+		};
+		if blocked {
+			return self.reject_blocklisted(their_node_id, CHANNEL_UPDATE_TYPE, msg);
+		}
+		let res = self.native_router.handle_channel_update(their_node_id, msg);
+		self.record_peer_gossip(their_node_id, CHANNEL_UPDATE_TYPE, msg, &res);
+		let res = res?;
+		self.new_channel_update(msg.clone(), redundant, their_node_id, seen);
+		Ok(self.should_relay(their_node_id, res))
+	}
+}
+
 impl<L: Deref + Clone + Send + Sync> RoutingMessageHandler for GossipRouter<L> where L::Target: Logger {
 	fn handle_node_announcement(&self, their_node_id: Option<PublicKey>, msg: &NodeAnnouncement) -> Result<bool, LightningError> {
-		let res = self.native_router.handle_node_announcement(their_node_id, msg)?;
-		self.new_node_announcement(msg.clone());
-		Ok(res)
+		self.process_node_announcement(their_node_id, msg, None)
 	}
 
 	fn handle_channel_announcement(&self, their_node_id: Option<PublicKey>, msg: &ChannelAnnouncement) -> Result<bool, LightningError> {
-		let res = self.native_router.handle_channel_announcement(their_node_id, msg)?;
-		self.new_channel_announcement(msg.clone());
-		Ok(res)
+		self.process_channel_announcement(their_node_id, msg, None)
 	}
 
 	fn handle_channel_update(&self, their_node_id: Option<PublicKey>, msg: &ChannelUpdate) -> Result<bool, LightningError> {
-		let res = self.native_router.handle_channel_update(their_node_id, msg)?;
-		self.new_channel_update(msg.clone());
-		Ok(res)
+		self.process_channel_update(their_node_id, msg, None)
 	}
 
 	fn processing_queue_high(&self) -> bool {
@@ -155,10 +419,17 @@ impl<L: Deref + Clone + Send + Sync> RoutingMessageHandler for GossipRouter<L> w
 	}
 
 	fn get_next_channel_announcement(&self, starting_point: u64) -> Option<(ChannelAnnouncement, Option<ChannelUpdate>, Option<ChannelUpdate>)> {
+		// peers are only synced from us if we relay gossip
+		if !self.is_relaying() {
+			return None;
+		}
 		self.native_router.get_next_channel_announcement(starting_point)
 	}
 
 	fn get_next_node_announcement(&self, starting_point: Option<&NodeId>) -> Option<NodeAnnouncement> {
+		if !self.is_relaying() {
+			return None;
+		}
 		self.native_router.get_next_node_announcement(starting_point)
 	}
 
@@ -175,10 +446,16 @@ impl<L: Deref + Clone + Send + Sync> RoutingMessageHandler for GossipRouter<L> w
 	}
 
 	fn handle_query_channel_range(&self, their_node_id: PublicKey, msg: QueryChannelRange) -> Result<(), LightningError> {
+		if !self.is_relaying() {
+			return Ok(());
+		}
 		self.native_router.handle_query_channel_range(their_node_id, msg)
 	}
 
 	fn handle_query_short_channel_ids(&self, their_node_id: PublicKey, msg: QueryShortChannelIds) -> Result<(), LightningError> {
+		if !self.is_relaying() {
+			return Ok(());
+		}
 		self.native_router.handle_query_short_channel_ids(their_node_id, msg)
 	}
 
@@ -190,3 +467,68 @@ impl<L: Deref + Clone + Send + Sync> RoutingMessageHandler for GossipRouter<L> w
 		self.native_router.provided_node_features()
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use bitcoin::blockdata::constants::ChainHash;
+	use bitcoin::secp256k1::ecdsa::Signature;
+	use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+	use bitcoin::Network;
+	use lightning::ln::msgs::{ChannelUpdate, UnsignedChannelUpdate};
+	use lightning::routing::gossip::{ChannelUpdateInfo, RoutingFees};
+
+	use crate::downloader::{is_redundant_update, RelayRateLimiter};
+
+	#[test]
+	fn test_redundant_update() {
+		let current = ChannelUpdateInfo {
+			htlc_minimum_msat: 1000,
+			htlc_maximum_msat: 100_000_000,
+			fees: RoutingFees { base_msat: 1000, proportional_millionths: 100 },
+			last_update: 1000,
+			cltv_expiry_delta: 144,
+			enabled: true,
+			last_update_message: None,
+		};
+		let mut update = ChannelUpdate {
+			signature: Signature::from_compact(&[0u8; 64]).unwrap(),
+			contents: UnsignedChannelUpdate {
+				chain_hash: ChainHash::using_genesis_block(Network::Bitcoin),
+				short_channel_id: 1,
+				timestamp: 2000,
+				message_flags: 1,
+				channel_flags: 0,
+				cltv_expiry_delta: 144,
+				htlc_minimum_msat: 1000,
+				htlc_maximum_msat: 100_000_000,
+				fee_base_msat: 1000,
+				fee_proportional_millionths: 100,
+				excess_data: vec![],
+			},
+		};
+		assert!(is_redundant_update(Some(&current), &update));
+		assert!(!is_redundant_update(None, &update));
+
+		update.contents.channel_flags = 2;
+		assert!(!is_redundant_update(Some(&current), &update));
+		update.contents.channel_flags = 0;
+		update.contents.fee_proportional_millionths = 200;
+		assert!(!is_redundant_update(Some(&current), &update));
+		update.contents.fee_proportional_millionths = 100;
+		update.contents.timestamp = 1000;
+		assert!(!is_redundant_update(Some(&current), &update));
+	}
+
+	#[test]
+	fn test_relay_rate_limit() {
+		let secp_ctx = Secp256k1::new();
+		let node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[42; 32]).unwrap());
+		let other_node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[43; 32]).unwrap());
+		let rate_limiter = RelayRateLimiter::new(2);
+		assert!(rate_limiter.allow(node_id));
+		assert!(rate_limiter.allow(node_id));
+		assert!(!rate_limiter.allow(node_id));
+		// the limit applies to each peer individually
+		assert!(rate_limiter.allow(other_node_id));
+	}
+}
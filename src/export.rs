@@ -0,0 +1,366 @@
+//! Dumps of the network graph in formats common analysis tooling understands, such that the
+//! collected gossip can be studied without querying the database schema.
+
+use std::fmt::Write;
+use std::ops::Deref;
+use std::str::FromStr;
+
+use hex_conservative::DisplayHex;
+use lightning::routing::gossip::{ChannelInfo, ChannelUpdateInfo, NetworkGraph, NodeId, NodeInfo};
+use lightning::util::logger::Logger;
+use serde::Serialize;
+
+/// The formats the network graph can be dumped in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GraphExportFormat {
+	Json,
+	GraphMl,
+	/// One row per node
+	NodesCsv,
+	/// One row per channel direction
+	ChannelsCsv,
+}
+
+impl FromStr for GraphExportFormat {
+	type Err = String;
+
+	fn from_str(format: &str) -> Result<Self, Self::Err> {
+		match format {
+			"json" => Ok(Self::Json),
+			"graphml" => Ok(Self::GraphMl),
+			"nodes-csv" => Ok(Self::NodesCsv),
+			"channels-csv" => Ok(Self::ChannelsCsv),
+			_ => Err(format!("Unknown graph export format {}, expected json, graphml, nodes-csv, or channels-csv", format)),
+		}
+	}
+}
+
+impl GraphExportFormat {
+	pub(crate) fn content_type(&self) -> &'static str {
+		match self {
+			Self::Json => "application/json",
+			Self::GraphMl => "application/graphml+xml",
+			Self::NodesCsv | Self::ChannelsCsv => "text/csv",
+		}
+	}
+}
+
+#[derive(Serialize)]
+struct ExportedGraph {
+	nodes: Vec<ExportedNode>,
+	channels: Vec<ExportedChannel>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ExportedNode {
+	node_id: String,
+	/// The remaining fields are only known once the node has announced itself
+	alias: Option<String>,
+	rgb: Option<String>,
+	features: Option<String>,
+	addresses: Vec<String>,
+	last_update: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ExportedChannel {
+	short_channel_id: u64,
+	node_one: String,
+	node_two: String,
+	pub(crate) capacity_sats: Option<u64>,
+	features: String,
+	one_to_two: Option<ExportedPolicy>,
+	two_to_one: Option<ExportedPolicy>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ExportedPolicy {
+	enabled: bool,
+	last_update: u32,
+	cltv_expiry_delta: u16,
+	htlc_minimum_msat: u64,
+	htlc_maximum_msat: u64,
+	fee_base_msat: u32,
+	fee_proportional_millionths: u32,
+}
+
+impl From<&ChannelUpdateInfo> for ExportedPolicy {
+	fn from(info: &ChannelUpdateInfo) -> Self {
+		Self {
+			enabled: info.enabled,
+			last_update: info.last_update,
+			cltv_expiry_delta: info.cltv_expiry_delta,
+			htlc_minimum_msat: info.htlc_minimum_msat,
+			htlc_maximum_msat: info.htlc_maximum_msat,
+			fee_base_msat: info.fees.base_msat,
+			fee_proportional_millionths: info.fees.proportional_millionths,
+		}
+	}
+}
+
+/// Feature bits as the hex encoding of their big-endian representation, as on the wire
+fn feature_hex(le_flags: &[u8]) -> String {
+	le_flags.iter().rev().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub(crate) fn export_node(node_id: &NodeId, node: &NodeInfo) -> ExportedNode {
+	let info = node.announcement_info.as_ref();
+	ExportedNode {
+		node_id: node_id.as_slice().to_lower_hex_string(),
+		alias: info.map(|info| info.alias().to_string()),
+		rgb: info.map(|info| info.rgb().to_lower_hex_string()),
+		features: info.map(|info| feature_hex(info.features().le_flags())),
+		addresses: info.map(|info| info.addresses().iter().map(|address| address.to_string()).collect()).unwrap_or_default(),
+		last_update: info.map(|info| info.last_update()),
+	}
+}
+
+pub(crate) fn export_channel(short_channel_id: u64, channel: &ChannelInfo) -> ExportedChannel {
+	ExportedChannel {
+		short_channel_id,
+		node_one: channel.node_one.as_slice().to_lower_hex_string(),
+		node_two: channel.node_two.as_slice().to_lower_hex_string(),
+		capacity_sats: channel.capacity_sats,
+		features: feature_hex(channel.features.le_flags()),
+		one_to_two: channel.one_to_two.as_ref().map(ExportedPolicy::from),
+		two_to_one: channel.two_to_one.as_ref().map(ExportedPolicy::from),
+	}
+}
+
+fn collect_graph<L: Deref>(network_graph: &NetworkGraph<L>) -> ExportedGraph where L::Target: Logger {
+	let graph = network_graph.read_only();
+	let mut nodes: Vec<ExportedNode> = graph.nodes().unordered_iter().map(|(node_id, node)| export_node(node_id, node)).collect();
+	nodes.sort_unstable_by(|a, b| a.node_id.cmp(&b.node_id));
+
+	let mut channels: Vec<ExportedChannel> = graph.channels().unordered_iter()
+		.map(|(short_channel_id, channel)| export_channel(*short_channel_id, channel))
+		.collect();
+	channels.sort_unstable_by_key(|channel| channel.short_channel_id);
+
+	ExportedGraph { nodes, channels }
+}
+
+/// Dump the current state of `network_graph` in `format`
+pub(crate) fn export_graph<L: Deref>(network_graph: &NetworkGraph<L>, format: GraphExportFormat) -> String where L::Target: Logger {
+	let graph = collect_graph(network_graph);
+	match format {
+		GraphExportFormat::Json => serde_json::to_string(&graph).unwrap(),
+		GraphExportFormat::GraphMl => graphml(&graph),
+		GraphExportFormat::NodesCsv => nodes_csv(&graph),
+		GraphExportFormat::ChannelsCsv => channels_csv(&graph),
+	}
+}
+
+/// The directions of each channel with their source and target nodes, as the graph is directed
+fn channel_directions(channel: &ExportedChannel) -> impl Iterator<Item = (&str, &str, bool, &ExportedPolicy)> {
+	[
+		(channel.node_one.as_str(), channel.node_two.as_str(), false, channel.one_to_two.as_ref()),
+		(channel.node_two.as_str(), channel.node_one.as_str(), true, channel.two_to_one.as_ref()),
+	].into_iter().filter_map(|(source, target, direction, policy)| Some((source, target, direction, policy?)))
+}
+
+fn xml_escape(value: &str) -> String {
+	value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&apos;")
+}
+
+fn graphml(graph: &ExportedGraph) -> String {
+	let mut output = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+	output.push_str("<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n");
+	for (id, domain, name, kind) in [
+		("alias", "node", "alias", "string"),
+		("rgb", "node", "rgb", "string"),
+		("node_features", "node", "features", "string"),
+		("addresses", "node", "addresses", "string"),
+		("node_last_update", "node", "last_update", "long"),
+		("short_channel_id", "edge", "short_channel_id", "long"),
+		("direction", "edge", "direction", "boolean"),
+		("capacity_sats", "edge", "capacity_sats", "long"),
+		("channel_features", "edge", "features", "string"),
+		("enabled", "edge", "enabled", "boolean"),
+		("last_update", "edge", "last_update", "long"),
+		("cltv_expiry_delta", "edge", "cltv_expiry_delta", "int"),
+		("htlc_minimum_msat", "edge", "htlc_minimum_msat", "long"),
+		("htlc_maximum_msat", "edge", "htlc_maximum_msat", "long"),
+		("fee_base_msat", "edge", "fee_base_msat", "long"),
+		("fee_proportional_millionths", "edge", "fee_proportional_millionths", "long"),
+	] {
+		writeln!(output, "  <key id=\"{}\" for=\"{}\" attr.name=\"{}\" attr.type=\"{}\"/>", id, domain, name, kind).unwrap();
+	}
+	output.push_str("  <graph id=\"lightning\" edgedefault=\"directed\">\n");
+	for node in &graph.nodes {
+		writeln!(output, "    <node id=\"{}\">", node.node_id).unwrap();
+		if let Some(alias) = &node.alias {
+			writeln!(output, "      <data key=\"alias\">{}</data>", xml_escape(alias)).unwrap();
+		}
+		if let Some(rgb) = &node.rgb {
+			writeln!(output, "      <data key=\"rgb\">{}</data>", rgb).unwrap();
+		}
+		if let Some(features) = &node.features {
+			writeln!(output, "      <data key=\"node_features\">{}</data>", features).unwrap();
+		}
+		if !node.addresses.is_empty() {
+			writeln!(output, "      <data key=\"addresses\">{}</data>", xml_escape(&node.addresses.join(","))).unwrap();
+		}
+		if let Some(last_update) = node.last_update {
+			writeln!(output, "      <data key=\"node_last_update\">{}</data>", last_update).unwrap();
+		}
+		output.push_str("    </node>\n");
+	}
+	for channel in &graph.channels {
+		for (source, target, direction, policy) in channel_directions(channel) {
+			writeln!(output, "    <edge source=\"{}\" target=\"{}\">", source, target).unwrap();
+			writeln!(output, "      <data key=\"short_channel_id\">{}</data>", channel.short_channel_id).unwrap();
+			writeln!(output, "      <data key=\"direction\">{}</data>", direction).unwrap();
+			if let Some(capacity_sats) = channel.capacity_sats {
+				writeln!(output, "      <data key=\"capacity_sats\">{}</data>", capacity_sats).unwrap();
+			}
+			writeln!(output, "      <data key=\"channel_features\">{}</data>", channel.features).unwrap();
+			writeln!(output, "      <data key=\"enabled\">{}</data>", policy.enabled).unwrap();
+			writeln!(output, "      <data key=\"last_update\">{}</data>", policy.last_update).unwrap();
+			writeln!(output, "      <data key=\"cltv_expiry_delta\">{}</data>", policy.cltv_expiry_delta).unwrap();
+			writeln!(output, "      <data key=\"htlc_minimum_msat\">{}</data>", policy.htlc_minimum_msat).unwrap();
+			writeln!(output, "      <data key=\"htlc_maximum_msat\">{}</data>", policy.htlc_maximum_msat).unwrap();
+			writeln!(output, "      <data key=\"fee_base_msat\">{}</data>", policy.fee_base_msat).unwrap();
+			writeln!(output, "      <data key=\"fee_proportional_millionths\">{}</data>", policy.fee_proportional_millionths).unwrap();
+			output.push_str("    </edge>\n");
+		}
+	}
+	output.push_str("  </graph>\n</graphml>\n");
+	output
+}
+
+/// Quote a CSV field if it contains anything that would otherwise break the row up
+fn csv_field(value: &str) -> String {
+	if value.contains([',', '"', '\n', '\r']) {
+		format!("\"{}\"", value.replace('"', "\"\""))
+	} else {
+		value.to_string()
+	}
+}
+
+fn nodes_csv(graph: &ExportedGraph) -> String {
+	let mut output = String::from("node_id,alias,rgb,features,addresses,last_update\n");
+	for node in &graph.nodes {
+		writeln!(output, "{},{},{},{},{},{}",
+			node.node_id,
+			csv_field(node.alias.as_deref().unwrap_or("")),
+			node.rgb.as_deref().unwrap_or(""),
+			node.features.as_deref().unwrap_or(""),
+			csv_field(&node.addresses.join(" ")),
+			node.last_update.map(|last_update| last_update.to_string()).unwrap_or_default(),
+		).unwrap();
+	}
+	output
+}
+
+fn channels_csv(graph: &ExportedGraph) -> String {
+	let mut output = String::from("short_channel_id,direction,source,target,capacity_sats,features,enabled,last_update,cltv_expiry_delta,htlc_minimum_msat,htlc_maximum_msat,fee_base_msat,fee_proportional_millionths\n");
+	for channel in &graph.channels {
+		for (source, target, direction, policy) in channel_directions(channel) {
+			writeln!(output, "{},{},{},{},{},{},{},{},{},{},{},{},{}",
+				channel.short_channel_id,
+				direction as u8,
+				source,
+				target,
+				channel.capacity_sats.map(|capacity_sats| capacity_sats.to_string()).unwrap_or_default(),
+				channel.features,
+				policy.enabled,
+				policy.last_update,
+				policy.cltv_expiry_delta,
+				policy.htlc_minimum_msat,
+				policy.htlc_maximum_msat,
+				policy.fee_base_msat,
+				policy.fee_proportional_millionths,
+			).unwrap();
+		}
+	}
+	output
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::Arc;
+
+	use bitcoin::constants::ChainHash;
+	use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+	use bitcoin::Network;
+	use lightning::ln::msgs::{UnsignedChannelUpdate, UnsignedNodeAnnouncement};
+	use lightning::routing::gossip::{NetworkGraph, NodeAlias, NodeId};
+	use lightning::types::features::{ChannelFeatures, NodeFeatures};
+
+	use crate::export::{export_graph, GraphExportFormat};
+	use crate::types::tests::TestLogger;
+
+	fn node_key(byte: u8) -> PublicKey {
+		PublicKey::from_secret_key(&Secp256k1::new(), &SecretKey::from_slice(&[byte; 32]).unwrap())
+	}
+
+	fn test_graph() -> NetworkGraph<Arc<TestLogger>> {
+		let network_graph = NetworkGraph::new(Network::Bitcoin, Arc::new(TestLogger::with_id("export".to_string())));
+		network_graph.add_channel_from_partial_announcement(42, 1_700_000_000, ChannelFeatures::empty(), node_key(1), node_key(2)).unwrap();
+		network_graph.update_channel_unsigned(&UnsignedChannelUpdate {
+			chain_hash: ChainHash::using_genesis_block(Network::Bitcoin),
+			short_channel_id: 42,
+			timestamp: 1_700_000_100,
+			message_flags: 1,
+			channel_flags: 0,
+			cltv_expiry_delta: 144,
+			htlc_minimum_msat: 1_000,
+			htlc_maximum_msat: 5_000_000,
+			fee_base_msat: 1_000,
+			fee_proportional_millionths: 100,
+			excess_data: Vec::new(),
+		}).unwrap();
+		let mut alias = [0u8; 32];
+		alias[..9].copy_from_slice(b"a, \"b\" <c");
+		network_graph.update_node_from_unsigned_announcement(&UnsignedNodeAnnouncement {
+			features: NodeFeatures::empty(),
+			timestamp: 1_700_000_200,
+			node_id: NodeId::from_pubkey(&node_key(1)),
+			rgb: [0xff, 0x00, 0x80],
+			alias: NodeAlias(alias),
+			addresses: Vec::new(),
+			excess_address_data: Vec::new(),
+			excess_data: Vec::new(),
+		}).unwrap();
+		network_graph
+	}
+
+	#[test]
+	fn test_json_export() {
+		let graph: serde_json::Value = serde_json::from_str(&export_graph(&test_graph(), GraphExportFormat::Json)).unwrap();
+		assert_eq!(graph["nodes"].as_array().unwrap().len(), 2);
+		let channel = &graph["channels"][0];
+		assert_eq!(channel["short_channel_id"], 42);
+		assert_eq!(channel["one_to_two"]["cltv_expiry_delta"], 144);
+		assert_eq!(channel["one_to_two"]["fee_proportional_millionths"], 100);
+		assert!(channel["two_to_one"].is_null());
+
+		let announced_node = graph["nodes"].as_array().unwrap().iter().find(|node| !node["alias"].is_null()).unwrap();
+		assert_eq!(announced_node["node_id"], node_key(1).to_string());
+		assert_eq!(announced_node["rgb"], "ff0080");
+		assert_eq!(announced_node["last_update"], 1_700_000_200);
+	}
+
+	#[test]
+	fn test_csv_export() {
+		let network_graph = test_graph();
+		let channels = export_graph(&network_graph, GraphExportFormat::ChannelsCsv);
+		let rows: Vec<&str> = channels.lines().collect();
+		// only the direction with a known policy is listed
+		assert_eq!(rows.len(), 2);
+		assert!(rows[1].starts_with(&format!("42,0,{},{},", node_key(1), node_key(2))));
+		assert!(rows[1].ends_with(",true,1700000100,144,1000,5000000,1000,100"));
+
+		let nodes = export_graph(&network_graph, GraphExportFormat::NodesCsv);
+		assert!(nodes.contains(&format!("{},\"a, \"\"b\"\" <c\",ff0080,", node_key(1))));
+	}
+
+	#[test]
+	fn test_graphml_export() {
+		let graphml = export_graph(&test_graph(), GraphExportFormat::GraphMl);
+		assert_eq!(graphml.matches("<node id=").count(), 2);
+		assert_eq!(graphml.matches("<edge source=").count(), 1);
+		assert!(graphml.contains("<data key=\"alias\">a, &quot;b&quot; &lt;c</data>"));
+	}
+}
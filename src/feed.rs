@@ -0,0 +1,215 @@
+//! A live feed of the gossip the server validates, streamed to subscribers as server-sent events
+//! such that downstream indexers can follow the network graph without polling snapshots.
+
+use std::collections::HashSet;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use futures::Stream;
+use hex_conservative::DisplayHex;
+use hyper::body::Bytes;
+use lightning::ln::msgs::{ChannelAnnouncement, ChannelUpdate, NodeAnnouncement};
+use lightning::routing::gossip::NetworkGraph;
+use lightning::util::logger::Logger;
+use lightning::util::ser::Writeable;
+use serde::Serialize;
+use tokio::sync::{broadcast, Semaphore};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::Instant;
+
+use crate::config;
+use crate::recording::{self, CHANNEL_ANNOUNCEMENT_TYPE, CHANNEL_UPDATE_TYPE, NODE_ANNOUNCEMENT_TYPE};
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum GossipEvent {
+	ChannelAnnouncement {
+		short_channel_id: u64,
+		node_one: String,
+		node_two: String,
+		capacity_sats: u64,
+		/// The hex-encoded BOLT 7 wire message, including its type
+		message: String,
+	},
+	ChannelUpdate {
+		short_channel_id: u64,
+		direction: u8,
+		timestamp: u32,
+		enabled: bool,
+		cltv_expiry_delta: u16,
+		htlc_minimum_msat: u64,
+		htlc_maximum_msat: u64,
+		fee_base_msat: u32,
+		fee_proportional_millionths: u32,
+		message: String,
+	},
+	NodeAnnouncement {
+		node_id: String,
+		timestamp: u32,
+		alias: String,
+		message: String,
+	},
+	/// The channel was pruned from the network graph, e. g. for having gone stale, or for its funding
+	/// output having been spent
+	ChannelRemoval {
+		short_channel_id: u64,
+	},
+}
+
+fn wire_hex<M: Writeable>(message_type: u16, message: &M) -> String {
+	recording::wire_message(message_type, message).to_lower_hex_string()
+}
+
+/// Fans the validated gossip out to the subscribers of the stream. Events are only serialized
+/// while there are subscribers, and subscribers that fall behind by more than the buffer skip the
+/// events they missed, upon which they're sent a `lagged` event with the number of skipped events.
+pub(crate) struct GossipFeed {
+	sender: broadcast::Sender<Arc<str>>,
+	/// One permit per subscriber the feed admits at once, held for as long as it's subscribed
+	subscriber_slots: Arc<Semaphore>,
+}
+
+impl GossipFeed {
+	pub(crate) fn new(max_subscribers: usize) -> Self {
+		let (sender, _) = broadcast::channel(config::GOSSIP_STREAM_BUFFER_SIZE);
+		Self { sender, subscriber_slots: Arc::new(Semaphore::new(max_subscribers)) }
+	}
+
+	fn publish(&self, event: impl FnOnce() -> GossipEvent) {
+		if self.sender.receiver_count() == 0 {
+			return;
+		}
+		let _ = self.sender.send(serde_json::to_string(&event()).unwrap().into());
+	}
+
+	pub(crate) fn publish_channel_announcement(&self, announcement: &ChannelAnnouncement, capacity_sats: u64) {
+		self.publish(|| GossipEvent::ChannelAnnouncement {
+			short_channel_id: announcement.contents.short_channel_id,
+			node_one: announcement.contents.node_id_1.as_slice().to_lower_hex_string(),
+			node_two: announcement.contents.node_id_2.as_slice().to_lower_hex_string(),
+			capacity_sats,
+			message: wire_hex(CHANNEL_ANNOUNCEMENT_TYPE, announcement),
+		});
+	}
+
+	pub(crate) fn publish_node_announcement(&self, announcement: &NodeAnnouncement) {
+		self.publish(|| GossipEvent::NodeAnnouncement {
+			node_id: announcement.contents.node_id.as_slice().to_lower_hex_string(),
+			timestamp: announcement.contents.timestamp,
+			alias: announcement.contents.alias.to_string(),
+			message: wire_hex(NODE_ANNOUNCEMENT_TYPE, announcement),
+		});
+	}
+
+	pub(crate) fn publish_channel_update(&self, update: &ChannelUpdate) {
+		let contents = &update.contents;
+		self.publish(|| GossipEvent::ChannelUpdate {
+			short_channel_id: contents.short_channel_id,
+			direction: contents.channel_flags & 1,
+			timestamp: contents.timestamp,
+			enabled: contents.channel_flags & 2 == 0,
+			cltv_expiry_delta: contents.cltv_expiry_delta,
+			htlc_minimum_msat: contents.htlc_minimum_msat,
+			htlc_maximum_msat: contents.htlc_maximum_msat,
+			fee_base_msat: contents.fee_base_msat,
+			fee_proportional_millionths: contents.fee_proportional_millionths,
+			message: wire_hex(CHANNEL_UPDATE_TYPE, update),
+		});
+	}
+
+	/// Periodically compare the channels in the network graph against those it held before,
+	/// publishing the channels that have since been removed. Channels can be removed in a number of
+	/// places, so this is simpler than instrumenting each of them.
+	pub(crate) fn spawn_removal_tracking<L: Deref + Send + Sync + 'static>(self: &Arc<Self>, network_graph: Arc<NetworkGraph<L>>) where L::Target: Logger {
+		let feed = Arc::clone(self);
+		tokio::spawn(async move {
+			let mut interval = tokio::time::interval(config::GOSSIP_STREAM_REMOVAL_CHECK_INTERVAL);
+			let mut previous_channels: Option<HashSet<u64>> = None;
+			loop {
+				interval.tick().await;
+				if feed.sender.receiver_count() == 0 {
+					// the channels are tracked anew once there are subscribers again
+					previous_channels = None;
+					continue;
+				}
+				let channels: HashSet<u64> = network_graph.read_only().channels().unordered_keys().copied().collect();
+				if let Some(previous_channels) = &previous_channels {
+					for short_channel_id in previous_channels.difference(&channels) {
+						feed.publish(|| GossipEvent::ChannelRemoval { short_channel_id: *short_channel_id });
+					}
+				}
+				previous_channels = Some(channels);
+			}
+		});
+	}
+
+	/// Subscribe to the feed, as a stream of server-sent events carrying one JSON event each,
+	/// interspersed with comments keeping idle connections alive, unless it already has as many
+	/// subscribers as it admits
+	pub(crate) fn subscribe(&self) -> Option<impl Stream<Item = Bytes> + Send + 'static> {
+		// released once the stream is dropped along with its state
+		let subscriber_slot = Arc::clone(&self.subscriber_slots).try_acquire_owned().ok()?;
+		let receiver = self.sender.subscribe();
+		let keepalive = tokio::time::interval_at(Instant::now() + config::GOSSIP_STREAM_KEEPALIVE_INTERVAL, config::GOSSIP_STREAM_KEEPALIVE_INTERVAL);
+		Some(futures::stream::unfold((receiver, keepalive, subscriber_slot), |(mut receiver, mut keepalive, subscriber_slot)| async move {
+			let frame = tokio::select! {
+				event = receiver.recv() => match event {
+					Ok(event) => Bytes::from(format!("data: {}\n\n", event)),
+					Err(RecvError::Lagged(skipped_count)) => Bytes::from(format!("event: lagged\ndata: {}\n\n", skipped_count)),
+					Err(RecvError::Closed) => return None,
+				},
+				_ = keepalive.tick() => Bytes::from_static(b": keepalive\n\n"),
+			};
+			Some((frame, (receiver, keepalive, subscriber_slot)))
+		}))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use bitcoin::blockdata::constants::ChainHash;
+	use bitcoin::secp256k1::ecdsa::Signature;
+	use bitcoin::Network;
+	use futures::StreamExt;
+	use lightning::ln::msgs::{ChannelUpdate, UnsignedChannelUpdate};
+
+	use crate::feed::GossipFeed;
+
+	#[tokio::test]
+	async fn test_gossip_stream() {
+		let feed = GossipFeed::new(1);
+		let update = ChannelUpdate {
+			signature: Signature::from_compact(&[0u8; 64]).unwrap(),
+			contents: UnsignedChannelUpdate {
+				chain_hash: ChainHash::using_genesis_block(Network::Bitcoin),
+				short_channel_id: 42,
+				timestamp: 1_700_000_000,
+				message_flags: 1,
+				channel_flags: 3,
+				cltv_expiry_delta: 144,
+				htlc_minimum_msat: 1000,
+				htlc_maximum_msat: 100_000_000,
+				fee_base_msat: 1000,
+				fee_proportional_millionths: 100,
+				excess_data: vec![],
+			},
+		};
+		// without subscribers, events are discarded
+		feed.publish_channel_update(&update);
+
+		let mut stream = Box::pin(feed.subscribe().unwrap());
+		// further subscribers are turned away until the first one unsubscribes
+		assert!(feed.subscribe().is_none());
+		feed.publish_channel_update(&update);
+		let frame = stream.next().await.unwrap();
+		let event = std::str::from_utf8(&frame).unwrap().strip_prefix("data: ").unwrap().strip_suffix("\n\n").unwrap();
+		let event: serde_json::Value = serde_json::from_str(event).unwrap();
+		assert_eq!(event["type"], "channel_update");
+		assert_eq!(event["short_channel_id"], 42);
+		assert_eq!(event["direction"], 1);
+		assert_eq!(event["enabled"], false);
+		assert!(event["message"].as_str().unwrap().starts_with("0102"));
+		drop(stream);
+		assert!(feed.subscribe().is_some());
+	}
+}
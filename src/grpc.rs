@@ -0,0 +1,169 @@
+//! The gRPC admin API, exposing the operations of the admin socket and the [`crate::ServerHandle`]
+//! to automation speaking gRPC rather than the socket's line based protocol. The service is defined
+//! in `proto/admin.proto`, and every request must carry the admin token as a bearer token.
+
+use std::net::SocketAddr;
+use std::ops::Deref;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use bitcoin::secp256k1::PublicKey;
+use lightning::log_info;
+use lightning::routing::gossip::ChannelUpdateInfo;
+use lightning::util::logger::Logger;
+use tokio::sync::watch;
+use tonic::metadata::MetadataMap;
+use tonic::{Request, Response, Status};
+
+use crate::admin::tokens_match;
+use crate::builder::ServerStats;
+use crate::config;
+use crate::ProcessorControls;
+
+mod proto {
+	tonic::include_proto!("rapid_gossip_sync.admin");
+}
+
+use proto::admin_server::{Admin, AdminServer};
+use proto::{AddPeerRequest, AddPeerResponse, ChannelDirection, GetChannelRequest, GetChannelResponse, GetStatsRequest, GetStatsResponse, ListPeersRequest, ListPeersResponse, Peer, RemovePeerRequest, RemovePeerResponse, ReverifyChannelRequest, ReverifyChannelResponse, TriggerSnapshotRequest, TriggerSnapshotResponse};
+
+/// Whether a request's metadata carries `token` as `authorization: Bearer <token>`
+fn is_authorized(metadata: &MetadataMap, token: &str) -> bool {
+	metadata.get("authorization")
+		.and_then(|authorization| authorization.to_str().ok())
+		.and_then(|authorization| authorization.strip_prefix("Bearer "))
+		.is_some_and(|presented_token| tokens_match(presented_token, token))
+}
+
+impl From<&ChannelUpdateInfo> for ChannelDirection {
+	fn from(update: &ChannelUpdateInfo) -> Self {
+		Self {
+			last_update: update.last_update,
+			enabled: update.enabled,
+			cltv_expiry_delta: update.cltv_expiry_delta as u32,
+			htlc_minimum_msat: update.htlc_minimum_msat,
+			htlc_maximum_msat: update.htlc_maximum_msat,
+			fee_base_msat: update.fees.base_msat,
+			fee_proportional_millionths: update.fees.proportional_millionths,
+		}
+	}
+}
+
+pub(crate) struct GrpcAdminServer<L: Deref + Clone + Send + Sync + 'static> where L::Target: Logger {
+	controls: Arc<ProcessorControls<L>>,
+}
+
+impl<L: Deref + Clone + Send + Sync + 'static> GrpcAdminServer<L> where L::Target: Logger {
+	pub(crate) fn new(controls: Arc<ProcessorControls<L>>) -> Self {
+		Self { controls }
+	}
+
+	/// Serve requests on `address` until `shutdown` is set
+	pub(crate) async fn serve(self, address: SocketAddr, mut shutdown: watch::Receiver<bool>) {
+		let token = config::admin_token().expect("The gRPC admin API requires an admin token");
+		let logger = self.controls.logger.clone();
+		let authenticate = move |request: Request<()>| match is_authorized(request.metadata(), &token) {
+			true => Ok(request),
+			false => Err(Status::unauthenticated("authentication required")),
+		};
+		log_info!(logger, "Accepting gRPC admin requests on {}", address);
+		tonic::transport::Server::builder()
+			.add_service(AdminServer::with_interceptor(self, authenticate))
+			.serve_with_shutdown(address, async move {
+				let _ = shutdown.wait_for(|is_shutting_down| *is_shutting_down).await;
+			})
+			.await
+			.unwrap_or_else(|e| panic!("Failed to serve the gRPC admin API on {}: {}", address, e));
+	}
+}
+
+#[tonic::async_trait]
+impl<L: Deref + Clone + Send + Sync + 'static> Admin for GrpcAdminServer<L> where L::Target: Logger {
+	async fn list_peers(&self, _request: Request<ListPeersRequest>) -> Result<Response<ListPeersResponse>, Status> {
+		let peers = self.controls.list_peers().await.map_err(Status::unavailable)?;
+		Ok(Response::new(ListPeersResponse {
+			peers: peers.into_iter()
+				.map(|peer| Peer { node_id: peer.node_id.to_string(), address: peer.address.to_string(), is_connected: peer.is_connected })
+				.collect(),
+		}))
+	}
+
+	async fn add_peer(&self, request: Request<AddPeerRequest>) -> Result<Response<AddPeerResponse>, Status> {
+		let peer = request.into_inner().peer;
+		let (node_id, address) = config::parse_peer_info(&peer).map_err(Status::invalid_argument)?;
+		log_info!(self.controls.logger, "Adding peer {}@{} as requested via gRPC", node_id, address);
+		self.controls.add_peer(node_id, address).await.map_err(Status::unavailable)?;
+		Ok(Response::new(AddPeerResponse {}))
+	}
+
+	async fn remove_peer(&self, request: Request<RemovePeerRequest>) -> Result<Response<RemovePeerResponse>, Status> {
+		let node_id = request.into_inner().node_id;
+		let node_id = PublicKey::from_str(&node_id)
+			.map_err(|_| Status::invalid_argument(format!("invalid node id {}", node_id)))?;
+		let was_removed = self.controls.remove_peer(node_id).await.map_err(Status::unavailable)?;
+		if was_removed {
+			log_info!(self.controls.logger, "Removed peer {} as requested via gRPC", node_id);
+		}
+		Ok(Response::new(RemovePeerResponse { was_removed }))
+	}
+
+	async fn trigger_snapshot(&self, _request: Request<TriggerSnapshotRequest>) -> Result<Response<TriggerSnapshotResponse>, Status> {
+		log_info!(self.controls.logger, "Capturing snapshots immediately as requested via gRPC");
+		self.controls.snapshot_trigger.notify_one();
+		let is_deferred = !self.controls.health_monitor.is_initial_sync_complete();
+		Ok(Response::new(TriggerSnapshotResponse { is_deferred }))
+	}
+
+	async fn get_stats(&self, _request: Request<GetStatsRequest>) -> Result<Response<GetStatsResponse>, Status> {
+		let stats = ServerStats::new(&self.controls);
+		Ok(Response::new(GetStatsResponse {
+			network: stats.network.to_string(),
+			channel_count: stats.channel_count as u64,
+			node_count: stats.node_count as u64,
+			connected_peers: stats.connected_peers as u64,
+			initial_sync_complete: stats.initial_sync_complete,
+			snapshot_age_secs: stats.snapshot_age.map(|age| age.as_secs()),
+		}))
+	}
+
+	async fn get_channel(&self, request: Request<GetChannelRequest>) -> Result<Response<GetChannelResponse>, Status> {
+		let short_channel_id = request.into_inner().short_channel_id;
+		let graph = self.controls.network_graph.read_only();
+		let channel = graph.channel(short_channel_id)
+			.ok_or_else(|| Status::not_found(format!("channel {} is unknown", short_channel_id)))?;
+		Ok(Response::new(GetChannelResponse {
+			short_channel_id,
+			node_one: channel.node_one.to_string(),
+			node_two: channel.node_two.to_string(),
+			capacity_sats: channel.capacity_sats,
+			one_to_two: channel.one_to_two.as_ref().map(ChannelDirection::from),
+			two_to_one: channel.two_to_one.as_ref().map(ChannelDirection::from),
+		}))
+	}
+
+	async fn reverify_channel(&self, request: Request<ReverifyChannelRequest>) -> Result<Response<ReverifyChannelResponse>, Status> {
+		let short_channel_id = request.into_inner().short_channel_id;
+		let funding_sats = self.controls.reverify_channel(short_channel_id).await.map_err(Status::failed_precondition)?;
+		Ok(Response::new(ReverifyChannelResponse { funding_sats }))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use tonic::metadata::MetadataMap;
+
+	use crate::grpc::is_authorized;
+
+	#[test]
+	fn test_authorization() {
+		let metadata = |authorization: &str| {
+			let mut metadata = MetadataMap::new();
+			metadata.insert("authorization", authorization.parse().unwrap());
+			metadata
+		};
+		assert!(is_authorized(&metadata("Bearer s3cret"), "s3cret"));
+		assert!(!is_authorized(&metadata("Bearer s3cre"), "s3cret"));
+		assert!(!is_authorized(&metadata("Basic s3cret"), "s3cret"));
+		assert!(!is_authorized(&MetadataMap::new(), "s3cret"));
+	}
+}
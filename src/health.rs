@@ -0,0 +1,224 @@
+use std::fs;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use std::sync::Arc;
+
+use bitcoin::Network;
+use serde::Serialize;
+
+use crate::chain::ChainSource;
+use crate::config;
+use crate::leader::Leadership;
+use crate::storage;
+
+/// How long each of the active readiness checks may take before it's considered failed
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Tracks the state of a network's gossip pipeline for the `/healthz` and `/readyz` endpoints.
+pub(crate) struct HealthMonitor {
+	symlink_directory: String,
+	network: Network,
+	chain_source: Arc<dyn ChainSource>,
+	/// Followers don't capture snapshots, so theirs aren't expected to be recent
+	leadership: Arc<Leadership>,
+	max_snapshot_age: Duration,
+	initial_sync_complete: AtomicBool,
+	connected_peer_count: AtomicUsize,
+	/// Whether no gossip has been received for longer than the stall timeout
+	gossip_stalled: AtomicBool,
+}
+
+#[derive(Serialize)]
+pub(crate) struct HealthReport {
+	pub(crate) initial_sync_complete: bool,
+	pub(crate) connected_peers: usize,
+	pub(crate) gossip_stalled: bool,
+	/// Whether this instance captures snapshots, rather than following the leader among the
+	/// instances sharing its database
+	pub(crate) is_leader: bool,
+	/// Only checked for readiness
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub(crate) bitcoind_reachable: Option<bool>,
+	/// Only checked for readiness
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub(crate) database_writable: Option<bool>,
+	/// How long ago the served snapshots were generated, absent if there are none
+	pub(crate) snapshot_age_secs: Option<u64>,
+	pub(crate) max_snapshot_age_secs: u64,
+}
+
+impl HealthMonitor {
+	pub(crate) fn new(network: Network, chain_source: Arc<dyn ChainSource>, leadership: Arc<Leadership>) -> Self {
+		Self {
+			symlink_directory: format!("{}/symlinks", config::cache_path(network)),
+			network,
+			chain_source,
+			leadership,
+			max_snapshot_age: config::max_snapshot_age(),
+			initial_sync_complete: AtomicBool::new(false),
+			connected_peer_count: AtomicUsize::new(0),
+			gossip_stalled: AtomicBool::new(false),
+		}
+	}
+
+	pub(crate) fn set_initial_sync_complete(&self) {
+		self.initial_sync_complete.store(true, Ordering::Release);
+	}
+
+	pub(crate) fn is_initial_sync_complete(&self) -> bool {
+		self.initial_sync_complete.load(Ordering::Acquire)
+	}
+
+	pub(crate) fn set_connected_peer_count(&self, count: usize) {
+		self.connected_peer_count.store(count, Ordering::Release);
+	}
+
+	pub(crate) fn connected_peer_count(&self) -> usize {
+		self.connected_peer_count.load(Ordering::Acquire)
+	}
+
+	/// Consider the sync stalled if no gossip has been received for longer than `stall_timeout`,
+	/// returning the new state if it changed
+	pub(crate) fn update_gossip_stall(&self, time_since_last_gossip: Duration, stall_timeout: Duration) -> Option<bool> {
+		let is_stalled = time_since_last_gossip > stall_timeout;
+		let was_stalled = self.gossip_stalled.swap(is_stalled, Ordering::AcqRel);
+		(is_stalled != was_stalled).then_some(is_stalled)
+	}
+
+	pub(crate) fn is_gossip_stalled(&self) -> bool {
+		self.gossip_stalled.load(Ordering::Acquire)
+	}
+
+	/// The age of the served snapshots, as recorded by the snapshotter upon finalizing them
+	pub(crate) fn snapshot_age(&self) -> Option<Duration> {
+		let update_time = fs::read_to_string(format!("{}/update_time.txt", self.symlink_directory)).ok()?;
+		let update_time = update_time.trim().parse::<u64>().ok()?;
+		let current_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+		Some(Duration::from_secs(current_time.saturating_sub(update_time)))
+	}
+
+	fn report(&self) -> HealthReport {
+		HealthReport {
+			initial_sync_complete: self.is_initial_sync_complete(),
+			connected_peers: self.connected_peer_count(),
+			gossip_stalled: self.is_gossip_stalled(),
+			is_leader: self.leadership.is_leader(),
+			bitcoind_reachable: None,
+			database_writable: None,
+			snapshot_age_secs: self.snapshot_age().map(|age| age.as_secs()),
+			max_snapshot_age_secs: self.max_snapshot_age.as_secs(),
+		}
+	}
+
+	/// Whether the served snapshots are older than they may be, which is never the case for a
+	/// follower, as it leaves capturing them to the leader
+	fn is_snapshot_stale(&self, report: &HealthReport) -> bool {
+		report.is_leader && report.snapshot_age_secs.map_or(true, |age| age > self.max_snapshot_age.as_secs())
+	}
+
+	/// Whether the process is working at all. Only fails if the leader has stopped generating
+	/// snapshots after the initial sync, which a restart may resolve.
+	pub(crate) fn check_liveness(&self) -> (bool, HealthReport) {
+		let report = self.report();
+		let is_stalled = report.initial_sync_complete && self.is_snapshot_stale(&report);
+		(!is_stalled, report)
+	}
+
+	/// Whether the instance should be serving traffic: it must be connected to peers that send
+	/// gossip, the chain source (bitcoind's REST interface by default) and the database must be usable, and the served snapshots must be recent.
+	pub(crate) async fn check_readiness(&self) -> (bool, HealthReport) {
+		let mut report = self.report();
+		let store = storage::open(self.network);
+		let (bitcoind_reachable, database_writable) = tokio::join!(
+			tokio::time::timeout(CHECK_TIMEOUT, self.chain_source.is_reachable()),
+			tokio::time::timeout(CHECK_TIMEOUT, store.is_writable()),
+		);
+		report.bitcoind_reachable = Some(bitcoind_reachable.unwrap_or(false));
+		report.database_writable = Some(database_writable.unwrap_or(false));
+
+		// peers are only connected to if new gossip is downloaded
+		let has_peers = report.connected_peers > 0 || !config::DOWNLOAD_NEW_GOSSIP;
+		let is_ready = has_peers
+			&& !report.gossip_stalled
+			&& report.bitcoind_reachable == Some(true)
+			&& report.database_writable == Some(true)
+			&& !self.is_snapshot_stale(&report);
+		(is_ready, report)
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use std::sync::Arc;
+	use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+	use bitcoin::Network;
+
+	use crate::health::HealthMonitor;
+	use crate::leader::Leadership;
+	use crate::types::tests::TestLogger;
+	use crate::verifier::RestChainSource;
+
+	#[test]
+	fn test_liveness_tracks_snapshot_age() {
+		let symlink_directory = std::env::temp_dir().join(format!("rgs_health_test_{}", std::process::id()));
+		std::fs::create_dir_all(&symlink_directory).unwrap();
+		let chain_source = Arc::new(RestChainSource::new(Network::Bitcoin, Arc::new(TestLogger::with_id("health".to_string()))));
+		let mut monitor = HealthMonitor::new(Network::Bitcoin, chain_source, Arc::new(Leadership::new(false, None)));
+		monitor.symlink_directory = symlink_directory.to_str().unwrap().to_string();
+		monitor.max_snapshot_age = Duration::from_secs(3600);
+
+		// no snapshots are expected to exist before the initial sync has completed
+		assert!(monitor.check_liveness().0);
+		monitor.set_initial_sync_complete();
+		let (is_live, report) = monitor.check_liveness();
+		assert!(!is_live);
+		assert_eq!(report.snapshot_age_secs, None);
+
+		let current_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+		std::fs::write(symlink_directory.join("update_time.txt"), (current_time - 60).to_string()).unwrap();
+		let (is_live, report) = monitor.check_liveness();
+		assert!(is_live);
+		assert!(report.snapshot_age_secs.unwrap() >= 60);
+
+		std::fs::write(symlink_directory.join("update_time.txt"), (current_time - 7200).to_string()).unwrap();
+		assert!(!monitor.check_liveness().0);
+
+		std::fs::remove_dir_all(&symlink_directory).unwrap();
+	}
+
+	#[test]
+	fn test_follower_liveness_without_snapshots() {
+		let symlink_directory = std::env::temp_dir().join(format!("rgs_health_follower_test_{}", std::process::id()));
+		std::fs::create_dir_all(&symlink_directory).unwrap();
+		let chain_source = Arc::new(RestChainSource::new(Network::Bitcoin, Arc::new(TestLogger::with_id("health".to_string()))));
+		let mut monitor = HealthMonitor::new(Network::Bitcoin, chain_source, Arc::new(Leadership::new(true, None)));
+		monitor.symlink_directory = symlink_directory.to_str().unwrap().to_string();
+		monitor.set_initial_sync_complete();
+
+		let (is_live, report) = monitor.check_liveness();
+		assert!(is_live);
+		assert!(!report.is_leader);
+		assert_eq!(report.snapshot_age_secs, None);
+		assert!(!monitor.is_snapshot_stale(&report));
+
+		std::fs::remove_dir_all(&symlink_directory).unwrap();
+	}
+
+	#[test]
+	fn test_gossip_stall_transitions() {
+		let chain_source = Arc::new(RestChainSource::new(Network::Bitcoin, Arc::new(TestLogger::with_id("health".to_string()))));
+		let monitor = HealthMonitor::new(Network::Bitcoin, chain_source, Arc::new(Leadership::new(false, None)));
+		let stall_timeout = Duration::from_secs(600);
+
+		assert_eq!(monitor.update_gossip_stall(Duration::from_secs(5), stall_timeout), None);
+		assert_eq!(monitor.update_gossip_stall(Duration::from_secs(601), stall_timeout), Some(true));
+		assert!(monitor.report().gossip_stalled);
+		// the stall is only reported once
+		assert_eq!(monitor.update_gossip_stall(Duration::from_secs(700), stall_timeout), None);
+		assert_eq!(monitor.update_gossip_stall(Duration::from_secs(0), stall_timeout), Some(false));
+		assert!(!monitor.is_gossip_stalled());
+	}
+}
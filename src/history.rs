@@ -0,0 +1,217 @@
+//! Periodic samples of the gossip message rates, the network graph's size, and the snapshot sizes,
+//! persisted to the gossip store's `stats_samples` table, such that operators can chart the
+//! network's growth and spot ingestion regressions from `/api/stats/history` alone.
+//!
+//! Only the leader records samples if the instances sharing a database elect one, as the message
+//! counts would otherwise be interleaved with the followers'.
+
+use std::ops::Deref;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use lightning::log_info;
+use lightning::routing::gossip::NetworkGraph;
+use lightning::util::logger::Logger;
+use serde::Serialize;
+
+use crate::config;
+use crate::leader::Leadership;
+use crate::metrics::{Metrics, PeerGossipStats, SnapshotRecord, SnapshotSeries};
+use crate::storage::GossipStore;
+
+/// A sample of the stats, as recorded
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub(crate) struct StatsSample {
+	/// When the sample was taken, in seconds since the epoch
+	pub(crate) sampled_at: u64,
+	/// The number of seconds since the previous sample, which the message counts cover
+	pub(crate) interval_secs: u64,
+	/// The channel announcements received from all peers within the interval
+	pub(crate) channel_announcements: u64,
+	pub(crate) channel_updates: u64,
+	pub(crate) node_announcements: u64,
+	pub(crate) channel_count: u64,
+	pub(crate) node_count: u64,
+	/// The size of the latest full snapshot in the latest serialization version, if any was
+	/// generated since startup
+	pub(crate) full_snapshot_bytes: Option<u64>,
+	/// The combined size of the latest round's snapshots, other than those of profiles
+	pub(crate) snapshot_bytes: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct SampleDetails {
+	#[serde(flatten)]
+	sample: StatsSample,
+	channel_announcements_per_minute: f64,
+	channel_updates_per_minute: f64,
+	node_announcements_per_minute: f64,
+}
+
+impl From<StatsSample> for SampleDetails {
+	fn from(sample: StatsSample) -> Self {
+		let per_minute = |count: u64| if sample.interval_secs == 0 { 0.0 } else { count as f64 * 60.0 / sample.interval_secs as f64 };
+		Self {
+			channel_announcements_per_minute: per_minute(sample.channel_announcements),
+			channel_updates_per_minute: per_minute(sample.channel_updates),
+			node_announcements_per_minute: per_minute(sample.node_announcements),
+			sample,
+		}
+	}
+}
+
+/// Map a request path onto the timestamp the history is requested since, if it's a history path.
+/// `/api/stats/history` covers the last [`config::DEFAULT_STATS_HISTORY_WINDOW`], whereas
+/// `/api/stats/history/<timestamp>` starts at the given timestamp, and yields `Some(None)` if it
+/// can't be parsed.
+pub(crate) fn parse_history_path(request_path: &str, current_timestamp: u64) -> Option<Option<u64>> {
+	let path = request_path.strip_prefix("/api/stats/history")?;
+	if path.is_empty() || path == "/" {
+		return Some(Some(current_timestamp.saturating_sub(config::DEFAULT_STATS_HISTORY_WINDOW.as_secs())));
+	}
+	Some(path.strip_prefix('/')?.parse::<u64>().ok())
+}
+
+/// The samples taken at or after `since` as JSON, oldest first and capped at
+/// [`config::MAX_STATS_SAMPLES_PER_REQUEST`]
+pub(crate) async fn history_json(store: &dyn GossipStore, since: u64) -> String {
+	let samples: Vec<SampleDetails> = store.stats_samples(since, config::MAX_STATS_SAMPLES_PER_REQUEST).await
+		.into_iter().map(SampleDetails::from).collect();
+	serde_json::to_string(&samples).unwrap()
+}
+
+/// The sizes of the full snapshot and of all snapshots of the latest round, other than profiles'
+fn latest_snapshot_sizes(snapshot_history: &[(SnapshotSeries, Vec<SnapshotRecord>)]) -> (Option<u64>, Option<u64>) {
+	let latest_records: Vec<_> = snapshot_history.iter()
+		.filter(|(series, _)| series.profile.is_none())
+		.filter_map(|(series, rounds)| Some((series, rounds.last()?)))
+		.collect();
+	let full_snapshot_bytes = latest_records.iter()
+		.filter(|(series, _)| series.scope == u64::MAX)
+		.max_by_key(|(series, _)| series.serialization_version)
+		.map(|(_, record)| record.size);
+	let latest_round = latest_records.iter().map(|(_, record)| record.reference_timestamp).max();
+	let snapshot_bytes = latest_round.map(|latest_round| latest_records.iter()
+		.filter(|(_, record)| record.reference_timestamp == latest_round)
+		.map(|(_, record)| record.size)
+		.sum());
+	(full_snapshot_bytes, snapshot_bytes)
+}
+
+/// The gossip received from all peers since startup
+fn total_gossip(metrics: &Metrics) -> PeerGossipStats {
+	metrics.peer_gossip_stats().into_iter().fold(PeerGossipStats::default(), |mut total, (_, stats)| {
+		total.channel_announcements += stats.channel_announcements;
+		total.channel_updates += stats.channel_updates;
+		total.node_announcements += stats.node_announcements;
+		total
+	})
+}
+
+/// Take a sample every `interval` until the process exits, recording them to `store` while this
+/// instance leads, and deleting those beyond the retention period
+pub(crate) async fn sample_stats<L: Deref>(network_graph: Arc<NetworkGraph<L>>, metrics: Arc<Metrics>, leadership: Arc<Leadership>, store: Arc<dyn GossipStore>, interval: Duration, logger: L) where L::Target: Logger {
+	let retention = config::stats_retention();
+	let mut previous_totals = total_gossip(&metrics);
+	let mut previous_sample_time = current_time();
+	let mut sample_interval = tokio::time::interval(interval);
+	// the first tick completes immediately
+	sample_interval.tick().await;
+	log_info!(logger, "Sampling gossip message rates and network growth every {} seconds", interval.as_secs());
+	loop {
+		sample_interval.tick().await;
+		let totals = total_gossip(&metrics);
+		let sampled_at = current_time();
+		let (channel_count, node_count) = {
+			let graph = network_graph.read_only();
+			(graph.channels().len() as u64, graph.nodes().len() as u64)
+		};
+		let (full_snapshot_bytes, snapshot_bytes) = latest_snapshot_sizes(&metrics.snapshot_history());
+		let sample = StatsSample {
+			sampled_at,
+			interval_secs: sampled_at.saturating_sub(previous_sample_time),
+			channel_announcements: totals.channel_announcements - previous_totals.channel_announcements,
+			channel_updates: totals.channel_updates - previous_totals.channel_updates,
+			node_announcements: totals.node_announcements - previous_totals.node_announcements,
+			channel_count,
+			node_count,
+			full_snapshot_bytes,
+			snapshot_bytes,
+		};
+		previous_totals = totals;
+		previous_sample_time = sampled_at;
+
+		if !leadership.is_leader() {
+			continue;
+		}
+		store.insert_stats_sample(sample).await;
+		if let Some(retention) = retention {
+			let pruned = store.prune_stats_samples(sampled_at.saturating_sub(retention.as_secs())).await;
+			if pruned > 0 {
+				log_info!(logger, "Pruned {} stats samples older than {} days", pruned, retention.as_secs() / (24 * 3600));
+			}
+		}
+	}
+}
+
+fn current_time() -> u64 {
+	SystemTime::now().duration_since(UNIX_EPOCH).expect("Time must be > 1970").as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json::Value;
+
+	use crate::config;
+	use crate::history::{latest_snapshot_sizes, parse_history_path, SampleDetails, StatsSample};
+	use crate::metrics::{SnapshotRecord, SnapshotSeries};
+
+	#[test]
+	fn test_parse_history_path() {
+		let now = 1_700_000_000;
+		let default_since = now - config::DEFAULT_STATS_HISTORY_WINDOW.as_secs();
+		assert_eq!(parse_history_path("/api/stats/history", now), Some(Some(default_since)));
+		assert_eq!(parse_history_path("/api/stats/history/", now), Some(Some(default_since)));
+		assert_eq!(parse_history_path("/api/stats/history/1600000000", now), Some(Some(1_600_000_000)));
+		assert_eq!(parse_history_path("/api/stats/history/yesterday", now), Some(None));
+		assert_eq!(parse_history_path("/api/stats", now), None);
+		assert_eq!(parse_history_path("/api/stats/historyx", now), None);
+	}
+
+	#[test]
+	fn test_latest_snapshot_sizes() {
+		assert_eq!(latest_snapshot_sizes(&[]), (None, None));
+
+		let series = |profile: Option<&str>, scope: u64, serialization_version: u8| SnapshotSeries { profile: profile.map(str::to_string), scope, serialization_version };
+		let record = |reference_timestamp: u64, size: u64| SnapshotRecord { reference_timestamp, size, channel_announcement_count: 0, update_count: 0 };
+		let history = vec![
+			(series(None, 3600, 2), vec![record(1000, 10), record(2000, 20)]),
+			(series(None, u64::MAX, 1), vec![record(2000, 900)]),
+			(series(None, u64::MAX, 2), vec![record(1000, 800), record(2000, 1000)]),
+			// a scope no longer generated
+			(series(None, 7200, 2), vec![record(1000, 50)]),
+			(series(Some("small"), u64::MAX, 2), vec![record(2000, 100)]),
+		];
+		assert_eq!(latest_snapshot_sizes(&history), (Some(1000), Some(1920)));
+	}
+
+	#[test]
+	fn test_sample_rates() {
+		let sample = StatsSample {
+			sampled_at: 1_700_000_000,
+			interval_secs: 300,
+			channel_announcements: 5,
+			channel_updates: 1500,
+			node_announcements: 0,
+			channel_count: 50_000,
+			node_count: 12_000,
+			full_snapshot_bytes: None,
+			snapshot_bytes: Some(4_000_000),
+		};
+		let details: Value = serde_json::to_value(SampleDetails::from(sample)).unwrap();
+		assert_eq!(details["channel_updates"], 1500);
+		assert_eq!(details["channel_updates_per_minute"], 300.0);
+		assert_eq!(details["channel_announcements_per_minute"], 1.0);
+		assert_eq!(details["full_snapshot_bytes"], Value::Null);
+	}
+}
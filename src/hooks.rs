@@ -0,0 +1,149 @@
+use std::ops::Deref;
+use std::process::Stdio;
+use std::time::Duration;
+
+use bitcoin::Network;
+use lightning::{log_error, log_info};
+use lightning::util::logger::Logger;
+use serde::Serialize;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use crate::manifest::SnapshotManifest;
+
+/// How long a webhook or command may take before it's considered failed
+const HOOK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The JSON payload describing a completed snapshot round
+#[derive(Serialize)]
+struct SnapshotNotification<'a> {
+	event: &'static str,
+	/// The directory the generated snapshots are served from
+	symlink_directory: &'a str,
+	#[serde(flatten)]
+	manifest: &'a SnapshotManifest,
+}
+
+/// The JSON payload describing an operational problem or its resolution
+#[derive(Serialize)]
+struct AlertNotification<'a, T: Serialize> {
+	event: &'static str,
+	network: String,
+	#[serde(flatten)]
+	details: &'a T,
+}
+
+/// Notifies operators of problems such as the chain source falling behind, as well as of their
+/// resolution, by POSTing a JSON payload to a webhook
+pub(crate) struct AlertWebhook<L: Deref> where L::Target: Logger {
+	url: String,
+	network: Network,
+	client: reqwest::Client,
+	logger: L,
+}
+
+impl<L: Deref> AlertWebhook<L> where L::Target: Logger {
+	pub(crate) fn new(url: String, network: Network, logger: L) -> Self {
+		let client = reqwest::Client::builder().timeout(HOOK_TIMEOUT).build().unwrap();
+		Self { url, network, client, logger }
+	}
+
+	/// POST an `event`, whose `details` are merged into the payload
+	pub(crate) async fn notify<T: Serialize>(&self, event: &'static str, details: &T) {
+		let notification = AlertNotification { event, network: self.network.to_string(), details };
+		let payload = serde_json::to_vec(&notification).unwrap();
+		let request = self.client.post(&self.url).header("content-type", "application/json").body(payload);
+		match request.send().await {
+			Ok(response) if response.status().is_success() => log_info!(self.logger, "Notified alert webhook of {}", event),
+			Ok(response) => log_error!(self.logger, "Alert webhook responded with status {} to {}", response.status(), event),
+			Err(e) => log_error!(self.logger, "Failed to notify alert webhook of {}: {}", event, e),
+		}
+	}
+}
+
+/// Notifies external systems, e. g. to purge CDN caches, once a snapshot round has completed, by
+/// POSTing a JSON payload to a webhook and/or piping it to a shell command's stdin.
+pub(crate) struct SnapshotHooks<L: Deref> where L::Target: Logger {
+	webhook_url: Option<String>,
+	command: Option<String>,
+	client: reqwest::Client,
+	logger: L,
+}
+
+impl<L: Deref> SnapshotHooks<L> where L::Target: Logger {
+	pub(crate) fn new(webhook_url: Option<String>, command: Option<String>, logger: L) -> Option<Self> {
+		if webhook_url.is_none() && command.is_none() {
+			return None;
+		}
+		let client = reqwest::Client::builder().timeout(HOOK_TIMEOUT).build().unwrap();
+		Some(Self { webhook_url, command, client, logger })
+	}
+
+	pub(crate) async fn notify(&self, symlink_directory: &str, manifest: &SnapshotManifest) {
+		let notification = SnapshotNotification { event: "snapshots_generated", symlink_directory, manifest };
+		let payload = serde_json::to_vec(&notification).unwrap();
+
+		if let Some(webhook_url) = &self.webhook_url {
+			let request = self.client.post(webhook_url).header("content-type", "application/json").body(payload.clone());
+			match request.send().await {
+				Ok(response) if response.status().is_success() => log_info!(self.logger, "Notified snapshot webhook"),
+				Ok(response) => log_error!(self.logger, "Snapshot webhook responded with status {}", response.status()),
+				Err(e) => log_error!(self.logger, "Failed to invoke snapshot webhook: {}", e),
+			}
+		}
+
+		if let Some(command) = &self.command {
+			match tokio::time::timeout(HOOK_TIMEOUT, Self::run_command(command, &payload)).await {
+				Ok(Ok(status)) if status.success() => log_info!(self.logger, "Ran snapshot hook command"),
+				Ok(Ok(status)) => log_error!(self.logger, "Snapshot hook command exited with {}", status),
+				Ok(Err(e)) => log_error!(self.logger, "Failed to run snapshot hook command: {}", e),
+				Err(_) => log_error!(self.logger, "Snapshot hook command timed out after {:?}", HOOK_TIMEOUT),
+			}
+		}
+	}
+
+	async fn run_command(command: &str, payload: &[u8]) -> std::io::Result<std::process::ExitStatus> {
+		let mut child = Command::new("sh").arg("-c").arg(command)
+			.stdin(Stdio::piped())
+			.kill_on_drop(true)
+			.spawn()?;
+		if let Some(mut stdin) = child.stdin.take() {
+			stdin.write_all(payload).await?;
+			// close stdin so the command sees the end of the payload
+			drop(stdin);
+		}
+		child.wait().await
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::Arc;
+
+	use crate::hooks::SnapshotHooks;
+	use crate::manifest::SnapshotManifest;
+	use crate::types::tests::TestLogger;
+
+	#[tokio::test]
+	async fn test_hook_command_receives_payload() {
+		let output_path = std::env::temp_dir().join(format!("rgs_hook_test_{}.json", std::process::id()));
+		let command = format!("cat > {}", output_path.display());
+		let hooks = SnapshotHooks::new(None, Some(command), Arc::new(TestLogger::with_id("hooks".to_string()))).unwrap();
+		let manifest = SnapshotManifest {
+			network: "bitcoin".to_string(),
+			chain_hash: "chain".to_string(),
+			generated_at: 1700000100,
+			reference_timestamp: 1700000000,
+			snapshots: Vec::new(),
+		};
+		hooks.notify("./res/symlinks", &manifest).await;
+
+		let payload: serde_json::Value = serde_json::from_slice(&std::fs::read(&output_path).unwrap()).unwrap();
+		std::fs::remove_file(&output_path).unwrap();
+		assert_eq!(payload["event"], "snapshots_generated");
+		assert_eq!(payload["symlink_directory"], "./res/symlinks");
+		assert_eq!(payload["reference_timestamp"], 1700000000);
+
+		assert!(SnapshotHooks::new(None, None, Arc::new(TestLogger::with_id("hooks".to_string()))).is_none());
+	}
+}
@@ -0,0 +1,462 @@
+//! Parsing of the snapshots generated, decoding them the way clients do, such that the contents of
+//! a snapshot a client complains about can be examined, or compared against another one.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use bitcoin::blockdata::constants::ChainHash;
+use bitcoin::io::{Cursor, Read};
+use lightning::routing::gossip::NodeId;
+use lightning::types::features::{ChannelFeatures, NodeFeatures};
+use lightning::util::ser::{BigSize, Readable};
+
+use crate::compression::SnapshotCompression;
+use crate::query::format_short_channel_id;
+use crate::GOSSIP_PREFIX;
+
+/// The names of the channel update fields, in the order of their flags from the most significant
+const UPDATE_FIELD_NAMES: [&str; 5] = ["cltv_expiry_delta", "htlc_minimum_msat", "fee_base_msat", "fee_proportional_millionths", "htlc_maximum_msat"];
+
+const INCREMENTAL_UPDATE_FLAG: u8 = 0b_1000_0000;
+const DISABLED_UPDATE_FLAG: u8 = 0b_0000_0010;
+const DIRECTION_UPDATE_FLAG: u8 = 0b_0000_0001;
+
+/// The flag signaling the presence of the update field at `index` in [`UPDATE_FIELD_NAMES`]
+fn update_field_flag(index: usize) -> u8 {
+	0b_0100_0000 >> index
+}
+
+/// The contents of a snapshot
+pub struct ParsedSnapshot {
+	version: u8,
+	chain_hash: ChainHash,
+	latest_seen: u32,
+	default_node_features: Vec<NodeFeatures>,
+	nodes: Vec<SnapshotNode>,
+	announcements: Vec<SnapshotAnnouncement>,
+	/// The values full updates default to, in the order of [`UPDATE_FIELD_NAMES`]
+	update_defaults: Option<[u64; 5]>,
+	updates: Vec<SnapshotUpdate>,
+}
+
+struct SnapshotNode {
+	/// The count of addresses, if they're included
+	address_count: Option<u8>,
+	/// The index of the default features, 1-based, or 7 if custom features are included
+	feature_index: u8,
+	has_extra_data: bool,
+	is_reminder: bool,
+}
+
+struct SnapshotAnnouncement {
+	short_channel_id: u64,
+	node_id_1: NodeId,
+	node_id_2: NodeId,
+}
+
+struct SnapshotUpdate {
+	short_channel_id: u64,
+	flags: u8,
+	/// The values included, in the order of [`UPDATE_FIELD_NAMES`]
+	values: [Option<u64>; 5],
+}
+
+impl SnapshotUpdate {
+	fn is_incremental(&self) -> bool {
+		self.flags & INCREMENTAL_UPDATE_FLAG != 0
+	}
+
+	fn direction(&self) -> u8 {
+		self.flags & DIRECTION_UPDATE_FLAG
+	}
+}
+
+fn read_value<R: Read, T: Readable>(reader: &mut R, description: &str) -> Result<T, String> {
+	T::read(reader).map_err(|e| format!("failed to read the {}: {:?}", description, e))
+}
+
+impl ParsedSnapshot {
+	/// Read a snapshot from a file, decompressing it if its extension is that of a codec
+	pub fn read(path: &str) -> Result<Self, String> {
+		let data = fs::read(path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+		let compression = Path::new(path).extension().and_then(|extension| extension.to_str()).and_then(SnapshotCompression::from_name);
+		let data = match compression {
+			Some(compression) => compression.decompress(&data).map_err(|e| format!("failed to decompress {}: {}", path, e))?,
+			None => data,
+		};
+		Self::parse(&data).map_err(|e| format!("{} is not a valid snapshot: {}", path, e))
+	}
+
+	pub fn parse(data: &[u8]) -> Result<Self, String> {
+		let mut reader = Cursor::new(data);
+		let mut prefix = [0u8; 3];
+		reader.read_exact(&mut prefix).map_err(|_| "it's too short".to_string())?;
+		if prefix != GOSSIP_PREFIX {
+			return Err("it lacks the gossip prefix".to_string());
+		}
+		let version: u8 = read_value(&mut reader, "version")?;
+		if version != 1 && version != 2 {
+			return Err(format!("version {} is unsupported", version));
+		}
+		let chain_hash = read_value(&mut reader, "chain hash")?;
+		let latest_seen = read_value(&mut reader, "latest seen timestamp")?;
+
+		let mut default_node_features = Vec::new();
+		if version >= 2 {
+			let default_feature_count: u8 = read_value(&mut reader, "default node feature count")?;
+			for _ in 0..default_feature_count {
+				default_node_features.push(read_value(&mut reader, "default node features")?);
+			}
+		}
+
+		let node_count: u32 = read_value(&mut reader, "node count")?;
+		let mut node_ids = Vec::new();
+		let mut nodes = Vec::new();
+		for _ in 0..node_count {
+			let mut node_id = [0u8; 33];
+			reader.read_exact(&mut node_id).map_err(|_| "the node ids are truncated".to_string())?;
+			let bitmap = node_id[0];
+			let mut node = SnapshotNode { address_count: None, feature_index: 0, has_extra_data: false, is_reminder: false };
+			if version >= 2 {
+				// the key's prefix is restored from its low bits, the others signal what follows
+				node_id[0] = 2 | (bitmap & 1);
+				node = Self::read_node_details(&mut reader, bitmap, default_node_features.len())
+					.map_err(|e| format!("node {}: {}", node_ids.len(), e))?;
+			}
+			node_ids.push(NodeId::from_slice(&node_id).map_err(|_| format!("node {} has an invalid id", node_ids.len()))?);
+			nodes.push(node);
+		}
+
+		let announcement_count: u32 = read_value(&mut reader, "announcement count")?;
+		let mut announcements = Vec::new();
+		let mut previous_scid = 0u64;
+		for _ in 0..announcement_count {
+			let _features: ChannelFeatures = read_value(&mut reader, "channel features")?;
+			let scid_delta: BigSize = read_value(&mut reader, "short channel id")?;
+			let short_channel_id = previous_scid.checked_add(scid_delta.0).ok_or("the short channel ids overflow")?;
+			let mut node_id = |description: &str| -> Result<NodeId, String> {
+				let index: BigSize = read_value(&mut reader, description)?;
+				node_ids.get(index.0 as usize).copied().ok_or_else(|| format!("channel {} references node {} out of {}", format_short_channel_id(short_channel_id), index.0, node_ids.len()))
+			};
+			let node_id_1 = node_id("first node index")?;
+			let node_id_2 = node_id("second node index")?;
+			announcements.push(SnapshotAnnouncement { short_channel_id, node_id_1, node_id_2 });
+			previous_scid = short_channel_id;
+		}
+
+		let update_count: u32 = read_value(&mut reader, "update count")?;
+		let mut update_defaults = None;
+		let mut updates = Vec::new();
+		if update_count > 0 {
+			update_defaults = Some([
+				read_value::<_, u16>(&mut reader, "default cltv expiry delta")? as u64,
+				read_value::<_, u64>(&mut reader, "default htlc minimum")?,
+				read_value::<_, u32>(&mut reader, "default base fee")? as u64,
+				read_value::<_, u32>(&mut reader, "default proportional fee")? as u64,
+				read_value::<_, u64>(&mut reader, "default htlc maximum")?,
+			]);
+		}
+		let mut previous_scid = 0u64;
+		for _ in 0..update_count {
+			let scid_delta: BigSize = read_value(&mut reader, "update short channel id")?;
+			let short_channel_id = previous_scid.checked_add(scid_delta.0).ok_or("the short channel ids overflow")?;
+			let flags: u8 = read_value(&mut reader, "update flags")?;
+			let mut values = [None; 5];
+			for (index, value) in values.iter_mut().enumerate() {
+				if flags & update_field_flag(index) == 0 {
+					continue;
+				}
+				*value = Some(match index {
+					0 => read_value::<_, u16>(&mut reader, UPDATE_FIELD_NAMES[index])? as u64,
+					2 | 3 => read_value::<_, u32>(&mut reader, UPDATE_FIELD_NAMES[index])? as u64,
+					_ => read_value::<_, u64>(&mut reader, UPDATE_FIELD_NAMES[index])?,
+				});
+			}
+			updates.push(SnapshotUpdate { short_channel_id, flags, values });
+			previous_scid = short_channel_id;
+		}
+
+		let trailing_length = data.len() as u64 - reader.position();
+		if trailing_length > 0 {
+			return Err(format!("{} bytes follow the last update", trailing_length));
+		}
+		Ok(Self { version, chain_hash, latest_seen, default_node_features, nodes, announcements, update_defaults, updates })
+	}
+
+	fn read_node_details<R: Read>(reader: &mut R, bitmap: u8, default_feature_count: usize) -> Result<SnapshotNode, String> {
+		let mut node = SnapshotNode { address_count: None, feature_index: (bitmap >> 3) & 0b111, has_extra_data: bitmap & (1 << 7) != 0, is_reminder: bitmap & (1 << 6) != 0 };
+		if bitmap & (1 << 2) != 0 {
+			let address_count: u8 = read_value(reader, "address count")?;
+			for _ in 0..address_count {
+				// the addresses are length prefixed, such that unknown types can be skipped
+				let address_length: u8 = read_value(reader, "address length")?;
+				let mut address = vec![0u8; address_length as usize];
+				reader.read_exact(&mut address).map_err(|_| "the addresses are truncated".to_string())?;
+			}
+			node.address_count = Some(address_count);
+		}
+		if node.feature_index == 7 {
+			let _features: NodeFeatures = read_value(reader, "features")?;
+		} else if node.feature_index as usize > default_feature_count {
+			return Err(format!("the feature index {} is beyond the {} default features", node.feature_index, default_feature_count));
+		}
+		if node.has_extra_data {
+			let _extra_data: Vec<u8> = read_value(reader, "extra data")?;
+		}
+		Ok(node)
+	}
+
+	/// The updates by channel and direction
+	fn updates_by_direction(&self) -> BTreeMap<(u64, u8), &SnapshotUpdate> {
+		self.updates.iter().map(|update| ((update.short_channel_id, update.direction()), update)).collect()
+	}
+
+	fn announced_channels(&self) -> BTreeSet<u64> {
+		self.announcements.iter().map(|announcement| announcement.short_channel_id).collect()
+	}
+
+	fn summary(&self) -> String {
+		let full_update_count = self.updates.iter().filter(|update| !update.is_incremental()).count();
+		format!("version {}, latest seen {}, {} nodes, {} channel announcements, {} channel updates ({} full, {} incremental)",
+			self.version, self.latest_seen, self.nodes.len(), self.announcements.len(), self.updates.len(), full_update_count, self.updates.len() - full_update_count)
+	}
+}
+
+impl fmt::Display for ParsedSnapshot {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		writeln!(f, "version: {}", self.version)?;
+		writeln!(f, "chain hash: {}", self.chain_hash)?;
+		writeln!(f, "latest seen: {}", self.latest_seen)?;
+
+		write!(f, "nodes: {}", self.nodes.len())?;
+		if self.version >= 2 {
+			let with_addresses = self.nodes.iter().filter(|node| node.address_count.is_some()).count();
+			let with_default_features = self.nodes.iter().filter(|node| (1..7).contains(&node.feature_index)).count();
+			let with_custom_features = self.nodes.iter().filter(|node| node.feature_index == 7).count();
+			let with_extra_data = self.nodes.iter().filter(|node| node.has_extra_data).count();
+			let reminders = self.nodes.iter().filter(|node| node.is_reminder).count();
+			write!(f, " ({} with addresses, {} with default features, {} with custom features, {} with extra data, {} reminders; {} default feature sets)",
+				with_addresses, with_default_features, with_custom_features, with_extra_data, reminders, self.default_node_features.len())?;
+		}
+		writeln!(f)?;
+
+		let announced_channels = self.announced_channels();
+		write!(f, "channel announcements: {}", self.announcements.len())?;
+		if let (Some(first), Some(last)) = (announced_channels.first(), announced_channels.last()) {
+			write!(f, " ({} to {})", format_short_channel_id(*first), format_short_channel_id(*last))?;
+		}
+		let self_channels = self.announcements.iter().filter(|announcement| announcement.node_id_1 == announcement.node_id_2).count();
+		if self_channels > 0 {
+			write!(f, ", {} of which between a node and itself", self_channels)?;
+		}
+		writeln!(f)?;
+
+		let full_updates: Vec<_> = self.updates.iter().filter(|update| !update.is_incremental()).collect();
+		let incremental_updates: Vec<_> = self.updates.iter().filter(|update| update.is_incremental()).collect();
+		let unchanged_updates = incremental_updates.iter().filter(|update| update.values.iter().all(Option::is_none)).count();
+		let disabled_updates = self.updates.iter().filter(|update| update.flags & DISABLED_UPDATE_FLAG != 0).count();
+		let updated_channels: BTreeSet<u64> = self.updates.iter().map(|update| update.short_channel_id).collect();
+		writeln!(f, "channel updates: {} for {} channels ({} full, {} incremental, {} of which without changed fields; {} in direction 0, {} in direction 1; {} disabled)",
+			self.updates.len(), updated_channels.len(), full_updates.len(), incremental_updates.len(), unchanged_updates,
+			self.updates.iter().filter(|update| update.direction() == 0).count(), self.updates.iter().filter(|update| update.direction() == 1).count(), disabled_updates)?;
+
+		if let Some(update_defaults) = self.update_defaults {
+			writeln!(f, "{:<28} {:>20} {:>12} {:>12} {:>12}", "field", "default", "full/default", "full/other", "incremental")?;
+			for (index, field_name) in UPDATE_FIELD_NAMES.iter().enumerate() {
+				let explicit_full = full_updates.iter().filter(|update| update.values[index].is_some()).count();
+				let incremental = incremental_updates.iter().filter(|update| update.values[index].is_some()).count();
+				writeln!(f, "{:<28} {:>20} {:>12} {:>12} {:>12}", field_name, update_defaults[index], full_updates.len() - explicit_full, explicit_full, incremental)?;
+			}
+		}
+		Ok(())
+	}
+}
+
+/// The differences between two snapshots
+pub struct SnapshotDiff<'a> {
+	a: &'a ParsedSnapshot,
+	b: &'a ParsedSnapshot,
+}
+
+impl<'a> SnapshotDiff<'a> {
+	pub fn new(a: &'a ParsedSnapshot, b: &'a ParsedSnapshot) -> Self {
+		Self { a, b }
+	}
+}
+
+impl fmt::Display for SnapshotDiff<'_> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let (a, b) = (self.a, self.b);
+		writeln!(f, "a: {}", a.summary())?;
+		writeln!(f, "b: {}", b.summary())?;
+		if a.chain_hash != b.chain_hash {
+			writeln!(f, "the chain hashes differ: {} and {}", a.chain_hash, b.chain_hash)?;
+		}
+
+		let (a_channels, b_channels) = (a.announced_channels(), b.announced_channels());
+		let list_channels = |f: &mut fmt::Formatter<'_>, description: &str, channels: Vec<&u64>| -> fmt::Result {
+			writeln!(f, "{}: {}", description, channels.len())?;
+			for short_channel_id in channels {
+				writeln!(f, "  {}", format_short_channel_id(*short_channel_id))?;
+			}
+			Ok(())
+		};
+		list_channels(f, "channels announced only in b (new)", b_channels.difference(&a_channels).collect())?;
+		list_channels(f, "channels announced only in a (removed)", a_channels.difference(&b_channels).collect())?;
+
+		let (a_updates, b_updates) = (a.updates_by_direction(), b.updates_by_direction());
+		let only_a = a_updates.keys().filter(|key| !b_updates.contains_key(key)).count();
+		let only_b = b_updates.keys().filter(|key| !a_updates.contains_key(key)).count();
+		let mut differing = 0;
+		let mut differing_by_field = [0usize; 5];
+		for (key, a_update) in &a_updates {
+			let b_update = match b_updates.get(key) {
+				Some(b_update) => b_update,
+				None => continue,
+			};
+			// values omitted from full updates take the snapshot's defaults
+			let resolve = |snapshot: &ParsedSnapshot, update: &SnapshotUpdate, index: usize| {
+				update.values[index].or_else(|| if update.is_incremental() { None } else { snapshot.update_defaults.map(|defaults| defaults[index]) })
+			};
+			let mut is_different = (a_update.flags & DISABLED_UPDATE_FLAG) != (b_update.flags & DISABLED_UPDATE_FLAG);
+			for (index, count) in differing_by_field.iter_mut().enumerate() {
+				if resolve(a, a_update, index) != resolve(b, b_update, index) {
+					*count += 1;
+					is_different = true;
+				}
+			}
+			differing += is_different as usize;
+		}
+		writeln!(f, "channel directions updated only in a: {}, only in b: {}, in both: {} ({} differing)", only_a, only_b, a_updates.len() - only_a, differing)?;
+		for (index, field_name) in UPDATE_FIELD_NAMES.iter().enumerate() {
+			if differing_by_field[index] > 0 {
+				writeln!(f, "  {} differs for {}", field_name, differing_by_field[index])?;
+			}
+		}
+
+		if let (Some(a_defaults), Some(b_defaults)) = (a.update_defaults, b.update_defaults) {
+			for (index, field_name) in UPDATE_FIELD_NAMES.iter().enumerate() {
+				if a_defaults[index] != b_defaults[index] {
+					writeln!(f, "default {} changed from {} to {}", field_name, a_defaults[index], b_defaults[index])?;
+				}
+			}
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use bitcoin::blockdata::constants::ChainHash;
+	use bitcoin::Network;
+	use lightning::types::features::{ChannelFeatures, NodeFeatures};
+	use lightning::util::ser::{BigSize, Writeable};
+
+	use crate::compression::SnapshotCompression;
+	use crate::hex_utils;
+	use crate::inspect::{ParsedSnapshot, SnapshotDiff};
+	use crate::GOSSIP_PREFIX;
+
+	const NODE_ID_1: &str = "035e4ff418fc8b5554c5d9eea66396c227bd429a3251c8cbc711002ba215bfc226";
+	const NODE_ID_2: &str = "02eec7245d6b7d2ccb30380bfbe2a3648cd7a942653f5aa340edcea1f283686619";
+
+	/// A version 2 snapshot announcing the given channels between two nodes, each updated in
+	/// direction 0, with a full update of a fee of `fee_base_msat` for the first
+	fn snapshot(short_channel_ids: &[u64], fee_base_msat: u32) -> Vec<u8> {
+		let mut blob = GOSSIP_PREFIX.to_vec();
+		2u8.write(&mut blob).unwrap();
+		ChainHash::using_genesis_block(Network::Bitcoin).write(&mut blob).unwrap();
+		1_700_000_000u32.write(&mut blob).unwrap();
+		1u8.write(&mut blob).unwrap();
+		NodeFeatures::empty().write(&mut blob).unwrap();
+
+		2u32.write(&mut blob).unwrap();
+		let mut node_id_1 = hex_utils::to_vec(NODE_ID_1).unwrap();
+		// addresses follow, as do the default features
+		node_id_1[0] |= 1 << 2 | 1 << 3;
+		blob.extend_from_slice(&node_id_1);
+		1u8.write(&mut blob).unwrap();
+		let address = lightning::ln::msgs::SocketAddress::TcpIpV4 { addr: [127, 0, 0, 1], port: 9735 };
+		(address.serialized_length() as u8).write(&mut blob).unwrap();
+		address.write(&mut blob).unwrap();
+		blob.extend_from_slice(&hex_utils::to_vec(NODE_ID_2).unwrap());
+
+		(short_channel_ids.len() as u32).write(&mut blob).unwrap();
+		let mut previous_scid = 0;
+		for short_channel_id in short_channel_ids {
+			ChannelFeatures::empty().write(&mut blob).unwrap();
+			BigSize(short_channel_id - previous_scid).write(&mut blob).unwrap();
+			BigSize(0).write(&mut blob).unwrap();
+			BigSize(1).write(&mut blob).unwrap();
+			previous_scid = *short_channel_id;
+		}
+
+		(short_channel_ids.len() as u32).write(&mut blob).unwrap();
+		if !short_channel_ids.is_empty() {
+			40u16.write(&mut blob).unwrap();
+			1000u64.write(&mut blob).unwrap();
+			1000u32.write(&mut blob).unwrap();
+			100u32.write(&mut blob).unwrap();
+			u64::MAX.write(&mut blob).unwrap();
+		}
+		let mut previous_scid = 0;
+		for (index, short_channel_id) in short_channel_ids.iter().enumerate() {
+			BigSize(short_channel_id - previous_scid).write(&mut blob).unwrap();
+			if index == 0 {
+				0b_0001_0000u8.write(&mut blob).unwrap();
+				fee_base_msat.write(&mut blob).unwrap();
+			} else {
+				// an incremental update without changes
+				0b_1000_0000u8.write(&mut blob).unwrap();
+			}
+			previous_scid = *short_channel_id;
+		}
+		blob
+	}
+
+	#[test]
+	fn test_snapshot_inspection() {
+		let scid = |block_height: u64| block_height << 40 | 1 << 16;
+		let data = snapshot(&[scid(700_000), scid(700_001)], 2000);
+		let parsed = ParsedSnapshot::parse(&data).unwrap();
+		assert_eq!(parsed.nodes.len(), 2);
+		assert_eq!(parsed.nodes[0].address_count, Some(1));
+		assert_eq!(parsed.nodes[0].feature_index, 1);
+		assert_eq!(parsed.announcements[1].short_channel_id, scid(700_001));
+		assert_eq!(parsed.updates[0].values, [None, None, Some(2000), None, None]);
+		assert!(parsed.updates[1].is_incremental());
+
+		let report = parsed.to_string();
+		assert!(report.contains("channel announcements: 2 (700000x1x0 to 700001x1x0)"), "{}", report);
+		assert!(report.contains("2 for 2 channels (1 full, 1 incremental, 1 of which without changed fields"), "{}", report);
+		assert!(report.lines().any(|line| line.split_whitespace().collect::<Vec<_>>() == ["fee_base_msat", "1000", "0", "1", "0"]), "{}", report);
+
+		// compressed snapshots are read like the ones served
+		let path = std::env::temp_dir().join(format!("rgs_inspect_test_{}.lngossip.gz", std::process::id()));
+		std::fs::write(&path, SnapshotCompression::Gzip.compress(&data, 9)).unwrap();
+		assert_eq!(ParsedSnapshot::read(path.to_str().unwrap()).unwrap().to_string(), report);
+		std::fs::remove_file(&path).unwrap();
+
+		assert!(ParsedSnapshot::parse(&data[..data.len() - 1]).is_err());
+		let mut trailing_data = data.clone();
+		trailing_data.push(0);
+		assert!(ParsedSnapshot::parse(&trailing_data).is_err());
+		assert_eq!(ParsedSnapshot::parse(&snapshot(&[], 0)).unwrap().updates.len(), 0);
+	}
+
+	#[test]
+	fn test_snapshot_diff() {
+		let scid = |block_height: u64| block_height << 40;
+		let a = ParsedSnapshot::parse(&snapshot(&[scid(700_000), scid(700_001)], 2000)).unwrap();
+		let b = ParsedSnapshot::parse(&snapshot(&[scid(700_000), scid(700_002)], 3000)).unwrap();
+		let diff = SnapshotDiff::new(&a, &b).to_string();
+		assert!(diff.contains("channels announced only in b (new): 1\n  700002x0x0\n"), "{}", diff);
+		assert!(diff.contains("channels announced only in a (removed): 1\n  700001x0x0\n"), "{}", diff);
+		assert!(diff.contains("only in a: 1, only in b: 1, in both: 1 (1 differing)\n  fee_base_msat differs for 1\n"), "{}", diff);
+
+		let diff = SnapshotDiff::new(&a, &a).to_string();
+		assert!(diff.contains("in both: 2 (0 differing)"), "{}", diff);
+	}
+}
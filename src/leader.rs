@@ -0,0 +1,125 @@
+//! Leader election among redundant instances sharing a Postgres database, such that only one of
+//! them persists gossip, captures snapshots, and relays gossip to its peers at any given time.
+//!
+//! The leader is whichever instance holds a session-level advisory lock, which Postgres releases
+//! once the leader's connection is closed, e. g. because it crashed, upon which one of the
+//! followers takes over. Followers keep syncing gossip into their network graph meanwhile, such
+//! that they take over warm, and persist its latest channel gossip when they do, in case the
+//! previous leader missed any of it on its way out.
+//!
+//! Upon taking the lead, the index of changed channels is restarted before the instance considers
+//! itself the leader, as it knows nothing of the changes the previous leader persisted. Snapshots
+//! captured from then on look up all channels for the scopes reaching back before the takeover.
+
+use std::ops::Deref;
+use std::sync::Arc;
+
+use bitcoin::Network;
+use lightning::{log_info, log_warn};
+use lightning::util::logger::Logger;
+use tokio::sync::watch;
+
+use crate::changes::ChannelChangeIndex;
+use crate::config;
+use crate::metrics::Metrics;
+use crate::storage;
+
+pub(crate) struct Leadership {
+	is_leader: watch::Sender<bool>,
+	/// Restarted upon taking the lead, if the snapshots rely on it
+	channel_changes: Option<Arc<ChannelChangeIndex>>,
+}
+
+impl Leadership {
+	/// The leadership of an instance, which starts out as a follower if the leadership is
+	/// contested, and as the leader otherwise
+	pub(crate) fn new(is_contested: bool, channel_changes: Option<Arc<ChannelChangeIndex>>) -> Self {
+		Self { is_leader: watch::channel(!is_contested).0, channel_changes }
+	}
+
+	pub(crate) fn is_leader(&self) -> bool {
+		*self.is_leader.borrow()
+	}
+
+	fn set_leader(&self, is_leader: bool, metrics: &Metrics) {
+		if is_leader && !self.is_leader() {
+			if let Some(channel_changes) = &self.channel_changes {
+				channel_changes.restart();
+			}
+		}
+		self.is_leader.send_replace(is_leader);
+		metrics.set_leader(is_leader);
+	}
+
+	/// Keep trying to take the lead until the process exits, and step down whenever the connection
+	/// holding the lock is lost. The lock is only ever released by exiting, such that a leader
+	/// shutting down remains the leader while it captures its final snapshots.
+	pub(crate) async fn campaign<L: Deref>(&self, network: Network, metrics: &Metrics, logger: L) where L::Target: Logger {
+		// instances syncing other networks, or using other schemas, don't compete with each other
+		let lock_name = format!("rapid-gossip-sync-server/{}/{}", crate::db_schema(network).unwrap_or("public".to_string()), network);
+		metrics.set_leader(self.is_leader());
+		log_info!(logger, "Following the leader among the instances syncing {} until its lock is released", network);
+		loop {
+			match config::db_connection_config().connect(storage::tls_connector()).await {
+				Ok((client, connection)) => {
+					tokio::spawn(async move {
+						let _ = connection.await;
+					});
+					loop {
+						let check = if self.is_leader() {
+							client.query_one("SELECT TRUE", &[]).await
+						} else {
+							client.query_one("SELECT pg_try_advisory_lock(hashtext($1)::bigint)", &[&lock_name]).await
+						};
+						match check.map(|row| row.get::<_, bool>(0)) {
+							Ok(true) if !self.is_leader() => {
+								log_info!(logger, "Took the lead, persisting gossip and capturing snapshots from now on");
+								self.set_leader(true, metrics);
+							},
+							Ok(_) => {},
+							Err(e) => {
+								log_warn!(logger, "Lost the leader election's database connection: {}", e);
+								break;
+							}
+						}
+						tokio::time::sleep(config::LEADER_ELECTION_INTERVAL).await;
+					}
+				},
+				Err(e) => log_warn!(logger, "Failed to connect to the database for the leader election: {}", e),
+			}
+			// the lock is released along with the connection
+			if self.is_leader() {
+				log_warn!(logger, "Stepping down, as the leader's lock can't be held without a connection");
+				self.set_leader(false, metrics);
+			}
+			tokio::time::sleep(config::LEADER_ELECTION_INTERVAL).await;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::Arc;
+	use std::time::{SystemTime, UNIX_EPOCH};
+
+	use crate::changes::ChannelChangeIndex;
+	use crate::config;
+	use crate::leader::Leadership;
+	use crate::metrics::Metrics;
+
+	#[test]
+	fn test_taking_the_lead_restarts_channel_changes() {
+		let channel_changes = Arc::new(ChannelChangeIndex::new());
+		let leadership = Leadership::new(true, Some(Arc::clone(&channel_changes)));
+		let metrics = Metrics::new();
+		let current_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as u32;
+		let since = current_time + config::MAX_STORE_CLOCK_SKEW.as_secs() as u32;
+		channel_changes.record(&[42]);
+		assert!(channel_changes.changed_since(since).unwrap().contains(&42));
+
+		// the index is restarted before the snapshots may consider this instance the leader
+		leadership.set_leader(true, &metrics);
+		assert!(leadership.is_leader());
+		assert!(channel_changes.changed_since(since).map_or(true, |changes| changes.is_empty()));
+	}
+}
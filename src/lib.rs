@@ -13,34 +13,105 @@ use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::BufReader;
 use std::ops::Deref;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
 use bitcoin::blockdata::constants::ChainHash;
-use lightning::log_info;
+use bitcoin::Network;
+use bitcoin::secp256k1::{PublicKey, Secp256k1};
+use lightning::{log_info, log_warn};
 
+use lightning::ln::msgs::SocketAddress;
 use lightning::routing::gossip::{NetworkGraph, NodeId};
+use lightning::routing::utxo::UtxoLookupError;
 use lightning::util::logger::Logger;
 use lightning::util::ser::{ReadableArgs, Writeable};
-use tokio::sync::mpsc;
-use tokio_postgres::{Client, NoTls};
-use crate::config::SYMLINK_GRANULARITY_INTERVAL;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{mpsc, oneshot, watch, Notify};
+use tokio_postgres::Client;
+use crate::config::{SYMLINK_GRANULARITY_INTERVAL, VerificationMode};
+use crate::admin::AdminServer;
+#[cfg(feature = "grpc")]
+use crate::grpc::GrpcAdminServer;
+use crate::chain::ChainSource;
+use crate::chain::cache::CachingChainSource;
+use crate::chain::filters::FilterChainSource;
+use crate::changes::ChannelChangeIndex;
 use crate::lookup::DeltaSet;
 
+use crate::downloader::GossipRouter;
+use crate::feed::GossipFeed;
+use crate::leader::Leadership;
 use crate::persistence::GossipPersister;
+use crate::health::HealthMonitor;
+use crate::hooks::AlertWebhook;
+use crate::metrics::Metrics;
+use crate::peer_health::PeerHealthTracker;
+use crate::profiles::ProfileSelection;
+use crate::replay::UtxoSetChainSource;
+use crate::server::SnapshotServer;
 use crate::serialization::{MutatedNodeProperties, NodeSerializationStrategy, SerializationSet, UpdateSerialization};
 use crate::snapshot::Snapshotter;
-use crate::types::{RGSSLogger, GossipMessage};
-
+use crate::storage::GossipStore;
+use crate::tracking::PeerCommand;
+use crate::types::RGSSLogger;
+use crate::verifier::{ChainVerifier, FundingAmountCache, RestChainSource};
+
+mod blocklist;
+mod changes;
+mod bootstrap;
+mod builder;
+mod export;
+mod feed;
+mod query;
 mod downloader;
 mod tracking;
 mod lookup;
 mod persistence;
+mod recording;
+mod replay;
 mod serialization;
 mod snapshot;
+mod validation;
+mod archive;
+mod profiles;
+mod compression;
+mod inspect;
+mod manifest;
+mod upload;
+mod hooks;
 mod config;
+mod config_file;
+mod dns_seed;
+mod socks;
+mod peer_health;
+mod logging;
 mod hex_utils;
 mod verifier;
+mod server;
+mod admin;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod listener;
+mod rate_limit;
+mod leader;
+mod analytics;
+mod history;
+mod tls;
+mod health;
+mod metrics;
+mod memory;
+mod tip_monitor;
+mod storage;
 
 pub mod types;
+pub mod signing;
+pub mod chain;
+
+pub use crate::builder::{RapidGossipSyncServer, RapidGossipSyncServerBuilder, ServerHandle, ServerStats};
+pub use crate::export::GraphExportFormat;
+pub use crate::inspect::{ParsedSnapshot, SnapshotDiff};
+pub use crate::metrics::PeerGossipStats;
+pub use crate::tracking::PeerInfo;
 
 #[cfg(test)]
 mod tests;
@@ -53,9 +124,79 @@ const GOSSIP_PREFIX: [u8; 3] = [76, 68, 75];
 
 pub struct RapidSyncProcessor<L: Deref> where L::Target: Logger {
 	network_graph: Arc<NetworkGraph<L>>,
+	chain_source: Arc<dyn ChainSource>,
+	/// The funding amounts looked up while verifying channels, shared with the query API
+	channel_funding_amounts: FundingAmountCache,
+	health_monitor: Arc<HealthMonitor>,
+	metrics: Arc<Metrics>,
+	/// The validated gossip, as streamed by the snapshot server
+	feed: Arc<GossipFeed>,
+	/// The channels changed by the gossip persisted since startup, for the snapshots to limit their
+	/// lookups to
+	channel_changes: Arc<ChannelChangeIndex>,
+	/// Notified to capture snapshots immediately rather than at the next interval
+	snapshot_trigger: Arc<Notify>,
+	/// Whether this instance leads the instances sharing its database, if they elect a leader
+	leadership: Arc<Leadership>,
+	/// The runtime controls, as exposed by the admin interfaces and the server handle
+	controls: Arc<ProcessorControls<L>>,
+	/// Taken by the gossip download once the sync starts
+	peer_command_receiver: Mutex<Option<mpsc::Receiver<PeerCommand>>>,
 	logger: L
 }
 
+/// The state the runtime controls act on, shared by the processor such that the controls can be
+/// held beyond a borrow of it, e. g. by the gRPC admin API
+pub(crate) struct ProcessorControls<L: Deref> where L::Target: Logger {
+	network_graph: Arc<NetworkGraph<L>>,
+	chain_source: Arc<dyn ChainSource>,
+	health_monitor: Arc<HealthMonitor>,
+	snapshot_trigger: Arc<Notify>,
+	peer_commands: mpsc::Sender<PeerCommand>,
+	logger: L,
+}
+
+impl<L: Deref + Clone + Send + Sync + 'static> ProcessorControls<L> where L::Target: Logger {
+	/// The peers the running sync maintains connections to. These are only available once the
+	/// initial connections have been established.
+	pub(crate) async fn list_peers(&self) -> Result<Vec<PeerInfo>, String> {
+		let (reply, peers) = oneshot::channel();
+		self.send_peer_command(PeerCommand::List(reply)).await?;
+		peers.await.map_err(|_| "The gossip sync isn't running".to_string())
+	}
+
+	/// Connect to an additional peer until the sync shuts down or the peers are reloaded
+	pub(crate) async fn add_peer(&self, node_id: PublicKey, address: SocketAddress) -> Result<(), String> {
+		self.send_peer_command(PeerCommand::Add((node_id, address))).await
+	}
+
+	/// Disconnect from a peer, returning whether we were maintaining a connection to it
+	pub(crate) async fn remove_peer(&self, node_id: PublicKey) -> Result<bool, String> {
+		let (reply, was_removed) = oneshot::channel();
+		self.send_peer_command(PeerCommand::Remove(node_id, reply)).await?;
+		was_removed.await.map_err(|_| "The gossip sync isn't running".to_string())
+	}
+
+	async fn send_peer_command(&self, command: PeerCommand) -> Result<(), String> {
+		self.peer_commands.send(command).await.map_err(|_| "The gossip sync isn't running".to_string())
+	}
+
+	/// Verify the funding output of a channel against the chain source again, removing the channel
+	/// from the network graph if its output has since been spent. Returns the funding amount of
+	/// channels that are still open.
+	pub(crate) async fn reverify_channel(&self, short_channel_id: u64) -> Result<u64, String> {
+		match ChainVerifier::reverify_txo(Arc::clone(&self.chain_source), short_channel_id, self.logger.clone()).await {
+			Ok(txo) => Ok(txo.value.to_sat()),
+			Err(UtxoLookupError::UnknownTx) => {
+				log_info!(self.logger, "Removing channel {}, whose funding output is no longer unspent", short_channel_id);
+				self.network_graph.channel_failed_permanent(short_channel_id);
+				Err(format!("The funding output of channel {} doesn't exist or has been spent", short_channel_id))
+			}
+			Err(UtxoLookupError::UnknownChain) => Err(format!("Couldn't retrieve the funding output of channel {} from the chain source", short_channel_id)),
+		}
+	}
+}
+
 pub struct SerializedResponse {
 	pub data: Vec<u8>,
 	pub message_count: u32,
@@ -65,6 +206,7 @@ pub struct SerializedResponse {
 	pub node_update_count: u32,
 	pub node_feature_update_count: u32,
 	pub node_address_update_count: u32,
+	pub node_alias_update_count: u32,
 	pub channel_announcement_count: u32,
 	pub update_count: u32,
 	pub update_count_full: u32,
@@ -73,14 +215,34 @@ pub struct SerializedResponse {
 
 impl<L: Deref + Clone + Send + Sync + 'static> RapidSyncProcessor<L> where L::Target: Logger {
 	pub fn new(logger: L) -> Self {
-		let network = config::network();
-		let network_graph = if let Ok(file) = File::open(&config::network_graph_cache_path()) {
+		Self::for_network(config::network(), logger)
+	}
+
+	/// Instantiate a processor for each of the networks the server is configured to operate on.
+	pub fn for_configured_networks(logger: L) -> Vec<Self> {
+		config::networks().into_iter().map(|network| Self::for_network(network, logger.clone())).collect()
+	}
+
+	pub fn for_network(network: Network, logger: L) -> Self {
+		Self::with_chain_source(network, None, logger)
+	}
+
+	/// Instantiate a processor verifying channels against `chain_source`, or bitcoind's REST
+	/// interface if `None`
+	pub(crate) fn with_chain_source(network: Network, chain_source: Option<Arc<dyn ChainSource>>, logger: L) -> Self {
+		config::validate(network);
+		let network_graph = if let Ok(file) = File::open(&config::network_graph_cache_path(network)) {
 			log_info!(logger, "Initializing from cached network graph…");
 			let mut buffered_reader = BufReader::new(file);
 			let network_graph_result = NetworkGraph::read(&mut buffered_reader, logger.clone());
 			if let Ok(network_graph) = network_graph_result {
-				log_info!(logger, "Initialized from cached network graph!");
-				network_graph
+				if network_graph.get_chain_hash() == ChainHash::using_genesis_block(network) {
+					log_info!(logger, "Initialized from cached network graph!");
+					network_graph
+				} else {
+					log_info!(logger, "Ignoring cached network graph, which is for a different network than {}", network);
+					NetworkGraph::new(network, logger.clone())
+				}
 			} else {
 				log_info!(logger, "Initialization from cached network graph failed: {}", network_graph_result.err().unwrap());
 				NetworkGraph::new(network, logger.clone())
@@ -89,65 +251,376 @@ impl<L: Deref + Clone + Send + Sync + 'static> RapidSyncProcessor<L> where L::Ta
 			NetworkGraph::new(network, logger.clone())
 		};
 		let arc_network_graph = Arc::new(network_graph);
+		let chain_source = chain_source.unwrap_or_else(|| {
+			let rest_chain_source = Arc::new(RestChainSource::new(network, logger.clone()));
+			if config::verification_mode() == VerificationMode::Filters {
+				let fallback_endpoints = config::bitcoin_rest_fallback_endpoints(network);
+				let fallback = (!fallback_endpoints.is_empty()).then(|| Arc::new(RestChainSource::with_endpoints(fallback_endpoints, logger.clone())));
+				return Arc::new(FilterChainSource::new(rest_chain_source, fallback, logger.clone()));
+			}
+			let rest_chain_source: Arc<dyn ChainSource> = rest_chain_source;
+			// with a transaction index, only the funding transactions are retrieved rather than blocks
+			match config::block_cache_size() {
+				_ if config::bitcoin_rest_txindex() => rest_chain_source,
+				0 => rest_chain_source,
+				cache_size => Arc::new(CachingChainSource::new(rest_chain_source, cache_size, config::block_prefetch_depth(), logger.clone())),
+			}
+		});
+		let (peer_commands, peer_command_receiver) = mpsc::channel(config::PEER_COMMAND_QUEUE_SIZE);
+		let channel_changes = Arc::new(ChannelChangeIndex::new());
+		let leadership = Arc::new(Leadership::new(config::leader_election_enabled(network), Some(Arc::clone(&channel_changes))));
+		let health_monitor = Arc::new(HealthMonitor::new(network, Arc::clone(&chain_source), Arc::clone(&leadership)));
+		let snapshot_trigger = Arc::new(Notify::new());
+		let controls = Arc::new(ProcessorControls {
+			network_graph: Arc::clone(&arc_network_graph),
+			chain_source: Arc::clone(&chain_source),
+			health_monitor: Arc::clone(&health_monitor),
+			snapshot_trigger: Arc::clone(&snapshot_trigger),
+			peer_commands,
+			logger: logger.clone(),
+		});
 		Self {
 			network_graph: arc_network_graph,
+			health_monitor,
+			chain_source,
+			channel_funding_amounts: Arc::new(Mutex::new(HashMap::new())),
+			metrics: Arc::new(Metrics::new()),
+			feed: Arc::new(GossipFeed::new(config::gossip_stream_max_subscribers())),
+			channel_changes,
+			snapshot_trigger,
+			leadership,
+			controls,
+			peer_command_receiver: Mutex::new(Some(peer_command_receiver)),
 			logger
 		}
 	}
 
+	/// Sync gossip and keep capturing snapshots until `SIGTERM` or `SIGINT` is received. `SIGUSR1`
+	/// triggers an immediate capture, e. g. after recovering from an outage.
 	pub async fn start_sync(&self) {
-		log_info!(self.logger, "Starting Rapid Gossip Sync Server");
+		let shutdown = listen_for_shutdown(self.logger.clone());
+		let mut regeneration_signal = signal(SignalKind::user_defined1()).expect("Failed to register snapshot regeneration signal handler");
+		let snapshot_trigger = Arc::clone(&self.snapshot_trigger);
+		let logger = self.logger.clone();
+		tokio::spawn(async move {
+			while regeneration_signal.recv().await.is_some() {
+				log_info!(logger, "Received regeneration signal, capturing snapshots immediately");
+				snapshot_trigger.notify_one();
+			}
+		});
+		self.run(shutdown).await
+	}
+
+	/// Sync gossip and keep capturing snapshots until `shutdown` is set, accepting admin commands
+	/// and gRPC admin requests meanwhile if enabled
+	pub(crate) async fn run(&self, shutdown: watch::Receiver<bool>) {
+		let network = config::graph_network(&self.network_graph);
+		#[cfg(feature = "grpc")]
+		if let Some(address) = config::grpc_address(network) {
+			tokio::spawn(GrpcAdminServer::new(Arc::clone(&self.controls)).serve(address, shutdown.clone()));
+		}
+		match config::admin_address(network) {
+			Some(address) => tokio::select! {
+				_ = self.sync(shutdown) => {},
+				_ = AdminServer::new(self).serve(address) => {},
+			},
+			None => self.sync(shutdown).await,
+		}
+	}
+
+	async fn sync(&self, mut shutdown: watch::Receiver<bool>) {
+		log_info!(self.logger, "Starting Rapid Gossip Sync Server on {}", config::graph_network(&self.network_graph));
 		log_info!(self.logger, "Snapshot interval: {} seconds", config::snapshot_generation_interval());
+		if let Some(signing_key) = config::snapshot_signing_key() {
+			let public_key = PublicKey::from_secret_key(&Secp256k1::signing_only(), &signing_key);
+			log_info!(self.logger, "Signing snapshots with {}", public_key);
+		}
+
+		let health_monitor = Arc::clone(&self.health_monitor);
+		let metrics = Arc::clone(&self.metrics);
+		if let Some(address) = config::http_server_address(config::graph_network(&self.network_graph)) {
+			// previously generated snapshots can be served while the initial sync is ongoing
+			let server = SnapshotServer::new(address, Arc::clone(&self.network_graph), Arc::clone(&self.channel_funding_amounts), Arc::clone(&health_monitor), Arc::clone(&metrics), Arc::clone(&self.feed), self.logger.clone());
+			tokio::spawn(server.serve());
+		}
+
+		let network = config::graph_network(&self.network_graph);
+		let alerts = config::alert_webhook_url(network).map(|url| AlertWebhook::new(url, network, self.logger.clone()));
+		tokio::spawn(tip_monitor::monitor_chain_tip(Arc::clone(&self.chain_source), Arc::clone(&metrics), alerts,
+			config::chain_tip_lag_threshold(), shutdown.clone(), self.logger.clone()));
 
 		// means to indicate sync completion status within this module
 		let (sync_completion_sender, mut sync_completion_receiver) = mpsc::channel::<()>(1);
 
+		tokio::spawn(memory::report_memory_usage(Arc::clone(&self.network_graph), Arc::clone(&self.channel_funding_amounts), Arc::clone(&metrics)));
+
+		if let Some(interval) = config::stats_sample_interval() {
+			tokio::spawn(history::sample_stats(Arc::clone(&self.network_graph), Arc::clone(&metrics), Arc::clone(&self.leadership), storage::open(network), interval, self.logger.clone()));
+		}
+
+		if config::leader_election_enabled(network) {
+			let leadership = Arc::clone(&self.leadership);
+			let metrics = Arc::clone(&metrics);
+			let logger = self.logger.clone();
+			tokio::spawn(async move { leadership.campaign(network, &metrics, logger).await });
+		}
+
+		let mut persistence = None;
 		if config::DOWNLOAD_NEW_GOSSIP {
 			let (mut persister, persistence_sender) =
 				GossipPersister::new(self.network_graph.clone(), self.logger.clone()).await;
+			persister.track_channel_changes(Arc::clone(&self.channel_changes));
+			persister.follow_leadership(Arc::clone(&self.leadership));
+			persister.report_memory_to(Arc::clone(&metrics));
+			if let Some(retention) = config::update_retention() {
+				log_info!(self.logger, "Pruning channel updates after {} days", retention.as_secs() / (24 * 3600));
+				persister.spawn_update_pruning(retention);
+			}
+			persister.reconcile_network_graph().await;
 			log_info!(self.logger, "Starting gossip db persistence listener");
-			tokio::spawn(async move { persister.persist_gossip().await; });
+			let mut persister_shutdown = shutdown.clone();
+			persistence = Some(tokio::spawn(async move {
+				persister.persist_gossip_until(async move { shutdown_initiated(&mut persister_shutdown).await }).await;
+				persister
+			}));
+
+			if let Some(source) = config::bootstrap_snapshot(config::graph_network(&self.network_graph)) {
+				if self.network_graph.read_only().channels().is_empty() {
+					bootstrap::bootstrap_network_graph(&self.network_graph, &persistence_sender, &source, self.logger.clone()).await;
+				} else {
+					log_info!(self.logger, "Not bootstrapping from snapshot {}, as the network graph is already populated", source);
+				}
+			}
 
 			{
 				log_info!(self.logger, "Backfilling latest gossip from cached network graph…");
-				let graph = self.network_graph.read_only();
-				for (_, chan) in graph.channels().unordered_iter() {
-					if let Some(announcement) = &chan.announcement_message {
-						if let Some(funding) = chan.capacity_sats {
-							let gossip_msg = GossipMessage::ChannelAnnouncement(announcement.clone(), funding, None);
-							persistence_sender.send(gossip_msg).await.unwrap();
-						}
-					}
-					if let Some(update) = chan.one_to_two.as_ref().map(|i| i.last_update_message.as_ref()).flatten() {
-						persistence_sender.send(GossipMessage::ChannelUpdate(update.clone(), None)).await.unwrap();
-					}
-					if let Some(update) = chan.two_to_one.as_ref().map(|i| i.last_update_message.as_ref()).flatten() {
-						persistence_sender.send(GossipMessage::ChannelUpdate(update.clone(), None)).await.unwrap();
-					}
+				// collect the gossip first, as the graph can't be held onto while the queue is full
+				let backfilled_gossip = persistence::latest_channel_gossip(&self.network_graph);
+				for gossip_msg in backfilled_gossip {
+					// the persister rejects gossip once a shutdown has been initiated
+					let _ = persistence_sender.send(gossip_msg).await;
 				}
 			}
 
 			log_info!(self.logger, "Starting gossip download");
+			let peer_commands = self.peer_command_receiver.lock().unwrap().take().expect("The gossip sync can only be started once");
 			tokio::spawn(tracking::download_gossip(persistence_sender, sync_completion_sender,
-				Arc::clone(&self.network_graph), self.logger.clone()));
+				Arc::clone(&self.network_graph), Arc::clone(&self.chain_source), Arc::clone(&self.channel_funding_amounts), Arc::clone(&health_monitor), Arc::clone(&metrics), Arc::clone(&self.feed), Arc::clone(&self.leadership), peer_commands, shutdown.clone(), self.logger.clone()));
 		} else {
 			sync_completion_sender.send(()).await.unwrap();
 		}
 
-		let sync_completion = sync_completion_receiver.recv().await;
-		if sync_completion.is_none() {
-			panic!("Sync failed!");
+		let is_initial_sync_complete = tokio::select! {
+			biased;
+			_ = shutdown_initiated(&mut shutdown) => false,
+			sync_completion = sync_completion_receiver.recv() => {
+				if sync_completion.is_none() {
+					panic!("Sync failed!");
+				}
+				true
+			}
+		};
+
+		let snapshotter = Snapshotter::new(Arc::clone(&self.network_graph), Some(Arc::clone(&self.channel_changes)), Arc::clone(&self.metrics), self.logger.clone());
+		if is_initial_sync_complete {
+			log_info!(self.logger, "Initial sync complete!");
+			health_monitor.set_initial_sync_complete();
+
+			// start the gossip snapshotting service, which keeps running until shutdown
+			snapshotter.snapshot_gossip(&self.snapshot_trigger, &self.leadership, shutdown).await;
+		}
+
+		if let Some(persistence) = persistence {
+			// wait for the queued gossip to be persisted
+			let persister = persistence.await.unwrap();
+			// the persister's runtime can't be dropped from within an asynchronous context
+			tokio::task::spawn_blocking(move || drop(persister)).await.unwrap();
+		}
+		if is_initial_sync_complete && config::snapshot_on_shutdown() && self.leadership.is_leader() {
+			log_info!(self.logger, "Capturing final snapshots before shutting down");
+			snapshotter.capture_snapshots().await;
+		}
+		log_info!(self.logger, "Shut down Rapid Gossip Sync Server on {}", config::graph_network(&self.network_graph));
+	}
+
+	/// Capture a single round of snapshots from the persisted gossip, as an alternative to the
+	/// continuous snapshotting of [`Self::start_sync`] for cron-driven deployments
+	pub async fn snapshot_once(&self) {
+		self.reconcile_network_graph().await;
+		Snapshotter::new(Arc::clone(&self.network_graph), None, Arc::clone(&self.metrics), self.logger.clone()).capture_snapshots().await;
+	}
+
+	/// Feed the gossip recorded at `recording` through the validation and persistence applied to
+	/// gossip received from peers, then capture a round of snapshots. Messages are replayed at
+	/// `speed` times the pace they were recorded at, or as fast as possible without one, and the
+	/// channels' funding outputs are looked up in the UTXO set file at `utxo_set` if given rather
+	/// than in the chain source.
+	pub async fn replay(&self, recording: &str, speed: Option<f64>, utxo_set: Option<&str>) -> Result<(), String> {
+		let chain_source: Arc<dyn ChainSource> = match utxo_set {
+			Some(path) => Arc::new(UtxoSetChainSource::load(path)?),
+			None => Arc::clone(&self.chain_source),
+		};
+		let files = replay::recording_files(recording)?;
+
+		let (mut persister, persistence_sender) = GossipPersister::new(Arc::clone(&self.network_graph), self.logger.clone()).await;
+		persister.reconcile_network_graph().await;
+		let (shutdown_sender, shutdown_receiver) = oneshot::channel::<()>();
+		let persistence = tokio::spawn(async move {
+			persister.persist_gossip_until(async move { let _ = shutdown_receiver.await; }).await;
+			persister
+		});
+		let router = GossipRouter::new(Arc::clone(&self.network_graph), persistence_sender, chain_source, Arc::clone(&self.channel_funding_amounts),
+			Arc::new(PeerHealthTracker::new()), Arc::clone(&self.metrics), Arc::clone(&self.feed), self.logger.clone());
+		let replay_result = replay::replay_gossip(&router, &files, speed, self.logger.clone()).await;
+
+		// wait for the replayed gossip to be persisted, keeping the router's queue open until then
+		let _ = shutdown_sender.send(());
+		let persister = persistence.await.unwrap();
+		drop(router);
+		// the persister's runtime can't be dropped from within an asynchronous context
+		tokio::task::spawn_blocking(move || drop(persister)).await.unwrap();
+
+		let counts = replay_result?;
+		log_info!(self.logger, "Replayed {} gossip messages, {} of which were rejected", counts.replayed, counts.rejected);
+		Snapshotter::new(Arc::clone(&self.network_graph), None, Arc::clone(&self.metrics), self.logger.clone()).capture_snapshots().await;
+		Ok(())
+	}
+
+	/// Dump the network graph, brought up to date with the persisted gossip, in `format`
+	pub async fn export_graph(&self, format: GraphExportFormat) -> String {
+		self.reconcile_network_graph().await;
+		export::export_graph(&self.network_graph, format)
+	}
+
+	/// Check that the database schema is up to date and that the stored gossip is consistent,
+	/// logging any problems found. Returns whether there were none.
+	pub async fn verify_db(&self) -> bool {
+		let network = config::graph_network(&self.network_graph);
+		let store = storage::open(network);
+		let latest_schema_version = storage::latest_schema_version(network);
+		let schema_version = match store.schema_version().await {
+			Some(schema_version) => schema_version,
+			None => {
+				log_warn!(self.logger, "The {} database hasn't been initialized", network);
+				return false;
+			}
+		};
+		let mut is_consistent = true;
+		if schema_version < latest_schema_version {
+			log_warn!(self.logger, "The {} database is at schema {}, and needs to be migrated to schema {}", network, schema_version, latest_schema_version);
+			is_consistent = false;
+		} else if schema_version > latest_schema_version {
+			log_warn!(self.logger, "The {} database is at schema {}, which is newer than the latest schema {} supported by this server", network, schema_version, latest_schema_version);
+			is_consistent = false;
+		}
+		if !store.is_writable().await {
+			log_warn!(self.logger, "The {} database isn't writable", network);
+			is_consistent = false;
+		}
+		let orphaned_update_count = store.orphaned_update_count().await;
+		if orphaned_update_count > 0 {
+			log_warn!(self.logger, "The {} database contains {} channel updates without an announcement of their channel", network, orphaned_update_count);
+			is_consistent = false;
 		}
-		log_info!(self.logger, "Initial sync complete!");
+		if is_consistent {
+			log_info!(self.logger, "The {} database is at schema {} and consistent", network, schema_version);
+		}
+		is_consistent
+	}
 
-		// start the gossip snapshotting service
-		Snapshotter::new(Arc::clone(&self.network_graph), self.logger.clone()).snapshot_gossip().await;
+	/// Prune the channel updates that have outlived the configured retention once, returning how
+	/// many were removed, or `None` if no retention is configured
+	pub async fn prune(&self) -> Option<u64> {
+		let retention = config::update_retention()?;
+		let store = storage::open(config::graph_network(&self.network_graph));
+		Some(persistence::prune_channel_updates(&*store, retention, config::update_retention_count(), &self.logger).await)
+	}
+
+	/// Create or upgrade the database schema, returning the schema version it's at afterwards
+	pub async fn migrate(&self) -> i32 {
+		let network = config::graph_network(&self.network_graph);
+		let store = storage::initialize(network, self.logger.clone()).await;
+		let schema_version = store.schema_version().await.unwrap();
+		let latest_schema_version = storage::latest_schema_version(network);
+		if schema_version < latest_schema_version {
+			// the upgrade to the Postgres migrations' baseline runs in the background
+			log_warn!(self.logger, "Migrated the {} database to schema {}; run the migration again to reach schema {}", network, schema_version, latest_schema_version);
+		} else {
+			log_info!(self.logger, "The {} database is at schema {}", network, schema_version);
+		}
+		schema_version
+	}
+
+	/// Bring the cached network graph up to date with the persisted gossip, for the one-off
+	/// operations that don't sync with peers themselves
+	async fn reconcile_network_graph(&self) {
+		let (persister, _) = GossipPersister::new(Arc::clone(&self.network_graph), self.logger.clone()).await;
+		if self.network_graph.read_only().channels().is_empty() {
+			// without a cached network graph, all of the persisted gossip is needed
+			log_info!(self.logger, "Loading network graph from the persisted gossip…");
+			persister.apply_persisted_gossip(0).await;
+		} else {
+			persister.reconcile_network_graph().await;
+		}
+		// the persister's runtime can't be dropped from within an asynchronous context
+		tokio::task::spawn_blocking(move || drop(persister)).await.unwrap();
 	}
 }
 
-pub(crate) async fn connect_to_db() -> Client {
-	let connection_config = config::db_connection_config();
-	let (client, connection) = connection_config.connect(NoTls).await.unwrap();
+/// Listen for `SIGTERM` and `SIGINT` in the background, returning a receiver that's notified upon
+/// the first one such that the server can shut down gracefully. A second one exits immediately.
+fn listen_for_shutdown<L: Deref + Clone + Send + Sync + 'static>(logger: L) -> watch::Receiver<bool> where L::Target: Logger {
+	let mut termination_signal = signal(SignalKind::terminate()).expect("Failed to register termination signal handler");
+	let mut interrupt_signal = signal(SignalKind::interrupt()).expect("Failed to register interrupt signal handler");
+	let (shutdown_sender, shutdown_receiver) = watch::channel(false);
+	tokio::spawn(async move {
+		tokio::select! {
+			_ = termination_signal.recv() => {},
+			_ = interrupt_signal.recv() => {},
+		}
+		log_info!(logger, "Received shutdown signal, shutting down gracefully");
+		shutdown_sender.send_replace(true);
+		tokio::select! {
+			_ = termination_signal.recv() => {},
+			_ = interrupt_signal.recv() => {},
+		}
+		log_info!(logger, "Received second shutdown signal, exiting immediately");
+		std::process::exit(1);
+	});
+	shutdown_receiver
+}
+
+/// Completes once a shutdown has been initiated
+pub(crate) async fn shutdown_initiated(shutdown: &mut watch::Receiver<bool>) {
+	// the sender is only dropped along with the runtime
+	let _ = shutdown.wait_for(|is_shutting_down| *is_shutting_down).await;
+}
+
+pub(crate) async fn connect_to_db(network: Network) -> Client {
+	connect_to_db_schema(db_schema(network)).await
+}
+
+/// The Postgres schema to use for `network`. Tests each use their own, which is only known to the
+/// thread running them.
+pub(crate) fn db_schema(network: Network) -> Option<String> {
+	#[cfg(not(test))]
+	return config::db_schema(network);
+
+	#[cfg(test)]
+	{
+		let _ = network;
+		Some(tests::db_test_schema())
+	}
+}
+
+pub(crate) async fn connect_to_db_schema(schema: Option<String>) -> Client {
+	try_connect_to_db(&config::db_connection_config(), schema, true).await.unwrap()
+}
+
+/// Connect to the Postgres server described by `connection_config`, operating within `schema`.
+/// Read replicas can't create the schema, which must then already exist on the primary.
+pub(crate) async fn try_connect_to_db(connection_config: &tokio_postgres::Config, schema: Option<String>, create_schema: bool) -> Result<Client, tokio_postgres::Error> {
+	let (client, connection) = connection_config.connect(storage::tls_connector()).await?;
 
 	tokio::spawn(async move {
 		if let Err(e) = connection.await {
@@ -155,16 +628,25 @@ pub(crate) async fn connect_to_db() -> Client {
 		}
 	});
 
-	#[cfg(test)]
-	{
-		let schema_name = tests::db_test_schema();
-		let schema_creation_command = format!("CREATE SCHEMA IF NOT EXISTS {}", schema_name);
-		client.execute(&schema_creation_command, &[]).await.unwrap();
-		client.execute(&format!("SET search_path TO {}", schema_name), &[]).await.unwrap();
+	if let Some(schema_name) = schema {
+		if create_schema {
+			let schema_creation_command = format!("CREATE SCHEMA IF NOT EXISTS {}", schema_name);
+			client.execute(&schema_creation_command, &[]).await?;
+		}
+		client.execute(&format!("SET search_path TO {}", schema_name), &[]).await?;
 	}
 
-	client.execute("set time zone UTC", &[]).await.unwrap();
-	client
+	client.execute("set time zone UTC", &[]).await?;
+	Ok(client)
+}
+
+/// Remove the channel directions whose latest update is beyond the [`config::staleness_horizon`]
+/// from the network graph, along with the channels left without either
+pub(crate) fn remove_stale_channels<L: Deref>(network_graph: &NetworkGraph<L>) where L::Target: Logger {
+	let current_timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+	// LDK removes what is older than its fixed pruning interval as of the time passed
+	let horizon_offset = config::PRUNE_INTERVAL.as_secs() as i64 - config::staleness_horizon().as_secs() as i64;
+	network_graph.remove_stale_channels_and_tracking_with_time(current_timestamp.saturating_add_signed(horizon_offset));
 }
 
 /// This method generates a no-op blob that can be used as a delta where none exists.
@@ -180,12 +662,10 @@ pub(crate) async fn connect_to_db() -> Client {
 /// of our granularity constant. Note that for that purpose, this method could be very dangerous,
 /// because if consumed, the `timestamp` value calculated here will overwrite the timestamp that
 /// the client previously had, which could result in duplicated or omitted gossip down the line.
-fn serialize_empty_blob(current_timestamp: u64, serialization_version: u8) -> Vec<u8> {
+fn serialize_empty_blob(chain_hash: ChainHash, current_timestamp: u64, serialization_version: u8) -> Vec<u8> {
 	let mut blob = GOSSIP_PREFIX.to_vec();
 	serialization_version.write(&mut blob).unwrap();
 
-	let network = config::network();
-	let chain_hash = ChainHash::using_genesis_block(network);
 	chain_hash.write(&mut blob).unwrap();
 
 	let blob_timestamp = Snapshotter::<Arc<RGSSLogger>>::round_down_to_nearest_multiple(current_timestamp, SYMLINK_GRANULARITY_INTERVAL as u64) as u32;
@@ -203,24 +683,40 @@ fn serialize_empty_blob(current_timestamp: u64, serialization_version: u8) -> Ve
 }
 
 async fn calculate_delta<L: Deref + Clone>(network_graph: Arc<NetworkGraph<L>>, last_sync_timestamp: u32, snapshot_reference_timestamp: Option<u64>, logger: L) -> SerializationSet where L::Target: Logger {
-	let client = connect_to_db().await;
+	calculate_snapshot_delta(network_graph, None, None, last_sync_timestamp, snapshot_reference_timestamp, logger).await
+}
 
-	network_graph.remove_stale_channels_and_tracking();
+/// Calculate a delta limited to a snapshot profile's selection of channels and nodes, if given,
+/// looking up only the `changed_channels` since the last sync, if known
+async fn calculate_snapshot_delta<L: Deref + Clone>(network_graph: Arc<NetworkGraph<L>>, selection: Option<&ProfileSelection>, changed_channels: Option<&HashSet<u64>>, last_sync_timestamp: u32, snapshot_reference_timestamp: Option<u64>, logger: L) -> SerializationSet where L::Target: Logger {
+	let store = storage::open_for_reads(config::graph_network(&network_graph), logger.clone()).await;
+	calculate_store_delta(network_graph, &*store, selection, changed_channels, last_sync_timestamp, snapshot_reference_timestamp, logger).await
+}
+
+async fn calculate_store_delta<L: Deref + Clone>(network_graph: Arc<NetworkGraph<L>>, store: &dyn GossipStore, selection: Option<&ProfileSelection>, changed_channels: Option<&HashSet<u64>>, last_sync_timestamp: u32, snapshot_reference_timestamp: Option<u64>, logger: L) -> SerializationSet where L::Target: Logger {
+	remove_stale_channels(&network_graph);
 
 	// set a flag if the chain hash is prepended
 	// chain hash only necessary if either channel announcements or non-incremental updates are present
 	// for announcement-free incremental-only updates, chain hash can be skipped
 
 	let mut delta_set = DeltaSet::new();
-	lookup::fetch_channel_announcements(&mut delta_set, Arc::clone(&network_graph), &client, last_sync_timestamp, snapshot_reference_timestamp, logger.clone()).await;
+	lookup::fetch_channel_announcements(&mut delta_set, Arc::clone(&network_graph), store, selection, changed_channels, last_sync_timestamp, snapshot_reference_timestamp, logger.clone()).await;
 	log_info!(logger, "announcement channel count: {}", delta_set.len());
-	lookup::fetch_channel_updates(&mut delta_set, &client, last_sync_timestamp, logger.clone()).await;
+	lookup::fetch_channel_updates(&mut delta_set, store, last_sync_timestamp, logger.clone()).await;
 	log_info!(logger, "update-fetched channel count: {}", delta_set.len());
-	let node_delta_set = lookup::fetch_node_updates(network_graph, &client, last_sync_timestamp, snapshot_reference_timestamp, logger.clone()).await;
+	let node_delta_set = lookup::fetch_node_updates(Arc::clone(&network_graph), store, selection, last_sync_timestamp, snapshot_reference_timestamp, logger.clone()).await;
 	log_info!(logger, "update-fetched node count: {}", node_delta_set.len());
 	lookup::filter_delta_set(&mut delta_set, logger.clone());
 	log_info!(logger, "update-filtered channel count: {}", delta_set.len());
-	serialization::serialize_delta_set(delta_set, node_delta_set, last_sync_timestamp)
+	let serialization_set = serialization::serialize_delta_set(network_graph.get_chain_hash(), delta_set, node_delta_set, last_sync_timestamp);
+	if last_sync_timestamp == 0 {
+		let defaults = &serialization_set.full_update_defaults;
+		log_info!(logger, "full snapshot update defaults (CLTV delta {}, HTLC minimum {} msat, base fee {} msat, fee rate {} ppm, HTLC maximum {} msat) omit {} bytes",
+			defaults.cltv_expiry_delta, defaults.htlc_minimum_msat, defaults.fee_base_msat, defaults.fee_proportional_millionths, defaults.htlc_maximum_msat,
+			defaults.omitted_bytes(&serialization_set.updates));
+	}
+	serialization_set
 }
 
 fn serialize_delta<L: Deref + Clone>(serialization_details: &SerializationSet, serialization_version: u8, logger: L) -> SerializedResponse where L::Target: Logger {
@@ -324,6 +820,8 @@ fn serialize_delta<L: Deref + Clone>(serialization_details: &SerializationSet, s
 	let mut node_update_count = 0u32;
 	let mut node_feature_update_count = 0u32;
 	let mut node_address_update_count = 0u32;
+	let mut node_alias_update_count = 0u32;
+	let include_node_aliases = config::include_node_aliases();
 
 	for current_node_id in node_ids {
 		let mut current_node_delta_serialization: Vec<u8> = Vec::new();
@@ -336,7 +834,8 @@ fn serialize_delta<L: Deref + Clone>(serialization_details: &SerializationSet, s
 
 				/*
 				Bitmap:
-				7: expect extra data after the pubkey (a u16 for the count, and then that number of bytes)
+				7: expect extra data after the pubkey (a u16 for the count, and then that number of bytes),
+				   which consists of records of a u8 type, a u16 length, and the value (e. g. the alias)
 				5-3: index of new features among default (1-6). If index is 7 (all 3 bits are set, it's
 				outside the present default range). 0 means no feature changes.
 				2: addresses have changed
@@ -395,6 +894,24 @@ fn serialize_delta<L: Deref + Clone>(serialization_details: &SerializationSet, s
 					_ => {}
 				}
 
+				match strategy {
+					NodeSerializationStrategy::Mutated(MutatedNodeProperties { alias: true, .. }) | NodeSerializationStrategy::Full if include_node_aliases => {
+						let alias = &node_delta.latest_details.as_ref().unwrap().alias;
+						node_alias_update_count += 1;
+						node_has_update = true;
+
+						let mut additional_data = Vec::new();
+						config::NODE_ALIAS_RECORD_TYPE.write(&mut additional_data).unwrap();
+						(alias.0.len() as u16).write(&mut additional_data).unwrap();
+						additional_data.extend_from_slice(&alias.0);
+
+						// signal the presence of additional data, serialized with a u16 length prefix
+						current_node_delta_serialization[0] |= 1 << 7;
+						additional_data.write(&mut current_node_delta_serialization).unwrap();
+					},
+					_ => {}
+				}
+
 				if node_has_update {
 					node_update_count += 1;
 				} else if let NodeSerializationStrategy::Reminder = strategy {
@@ -418,6 +935,7 @@ fn serialize_delta<L: Deref + Clone>(serialization_details: &SerializationSet, s
 		node_update_count,
 		node_feature_update_count,
 		node_address_update_count,
+		node_alias_update_count,
 		channel_announcement_count: announcement_count,
 		update_count,
 		update_count_full,
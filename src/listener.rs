@@ -0,0 +1,212 @@
+//! The listeners the built-in HTTP server and the admin socket accept connections on: a TCP port, a
+//! unix socket, e. g. for a reverse proxy sharing the host, or a socket inherited from systemd's
+//! socket activation.
+
+use std::collections::HashSet;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::os::fd::{FromRawFd, OwnedFd, RawFd};
+use std::os::unix::fs::FileTypeExt;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+
+/// The first file descriptor passed by systemd, per `sd_listen_fds(3)`
+const SYSTEMD_LISTEN_FDS_START: RawFd = 3;
+
+/// The inherited file descriptors already taken ownership of, as each may only be owned once
+static TAKEN_SYSTEMD_FDS: Mutex<Option<HashSet<RawFd>>> = Mutex::new(None);
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum ListenAddress {
+	Tcp(SocketAddr),
+	/// The path of a unix socket, given as `unix:<path>`
+	Unix(PathBuf),
+	/// A socket passed by systemd, given as `systemd` for the first one, or as `systemd:<name>` for
+	/// the one of the matching `FileDescriptorName=`
+	Systemd(Option<String>),
+}
+
+impl ListenAddress {
+	pub(crate) fn from_name(name: &str) -> Option<Self> {
+		if let Some(path) = name.strip_prefix("unix:") {
+			return if path.is_empty() { None } else { Some(ListenAddress::Unix(PathBuf::from(path))) };
+		}
+		if name == "systemd" {
+			return Some(ListenAddress::Systemd(None));
+		}
+		if let Some(fd_name) = name.strip_prefix("systemd:") {
+			return if fd_name.is_empty() { None } else { Some(ListenAddress::Systemd(Some(fd_name.to_string()))) };
+		}
+		name.parse().ok().map(ListenAddress::Tcp)
+	}
+
+	/// Bind to the address, or take over the listening socket systemd passed for it
+	pub(crate) async fn bind(&self) -> io::Result<Listener> {
+		match self {
+			ListenAddress::Tcp(address) => Ok(Listener::Tcp(TcpListener::bind(address).await?)),
+			ListenAddress::Unix(path) => {
+				// a socket left behind by a previous run would fail the bind
+				if fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+					fs::remove_file(path)?;
+				}
+				Ok(Listener::Unix(UnixListener::bind(path)?))
+			},
+			ListenAddress::Systemd(fd_name) => systemd_listener(fd_name.as_deref()),
+		}
+	}
+}
+
+impl fmt::Display for ListenAddress {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			ListenAddress::Tcp(address) => write!(f, "{}", address),
+			ListenAddress::Unix(path) => write!(f, "unix:{}", path.display()),
+			ListenAddress::Systemd(None) => write!(f, "systemd"),
+			ListenAddress::Systemd(Some(fd_name)) => write!(f, "systemd:{}", fd_name),
+		}
+	}
+}
+
+/// The listening socket systemd passed with the given name, or the first one if none is given
+fn systemd_listener(fd_name: Option<&str>) -> io::Result<Listener> {
+	let not_passed = |description: String| io::Error::new(io::ErrorKind::NotFound, description);
+	// the variables are inherited by child processes, which mustn't take them for their own
+	let is_for_us = env::var("LISTEN_PID").ok().and_then(|pid| pid.parse::<u32>().ok()) == Some(std::process::id());
+	let fd_count = env::var("LISTEN_FDS").ok().and_then(|count| count.parse::<RawFd>().ok()).filter(|_| is_for_us).unwrap_or(0);
+	if fd_count < 1 {
+		return Err(not_passed("no sockets were passed by systemd".to_string()));
+	}
+	let fd_offset = match fd_name {
+		Some(fd_name) => env::var("LISTEN_FDNAMES").unwrap_or_default().split(':').position(|name| name == fd_name)
+			.map(|position| position as RawFd)
+			.filter(|position| *position < fd_count)
+			.ok_or_else(|| not_passed(format!("no socket named {} was passed by systemd", fd_name)))?,
+		None => 0,
+	};
+	let fd = SYSTEMD_LISTEN_FDS_START + fd_offset;
+	if !TAKEN_SYSTEMD_FDS.lock().unwrap().get_or_insert_with(HashSet::new).insert(fd) {
+		return Err(io::Error::new(io::ErrorKind::AddrInUse, format!("the socket passed by systemd as descriptor {} is already in use", fd)));
+	}
+
+	#[allow(unsafe_code)]
+	// SAFETY: systemd passes the descriptors from SYSTEMD_LISTEN_FDS_START on open to the process
+	// LISTEN_PID names, and each is only taken ownership of once as tracked above
+	let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+	// the address family tells TCP sockets apart from unix ones
+	let tcp_listener = std::net::TcpListener::from(fd);
+	if tcp_listener.local_addr().is_ok() {
+		tcp_listener.set_nonblocking(true)?;
+		return Ok(Listener::Tcp(TcpListener::from_std(tcp_listener)?));
+	}
+	let unix_listener = std::os::unix::net::UnixListener::from(OwnedFd::from(tcp_listener));
+	unix_listener.local_addr()?;
+	unix_listener.set_nonblocking(true)?;
+	Ok(Listener::Unix(UnixListener::from_std(unix_listener)?))
+}
+
+pub(crate) enum Listener {
+	Tcp(TcpListener),
+	Unix(UnixListener),
+}
+
+impl Listener {
+	/// Accept a connection, along with the address of the client if it connected via TCP
+	pub(crate) async fn accept(&self) -> io::Result<(Connection, Option<SocketAddr>)> {
+		match self {
+			Listener::Tcp(listener) => listener.accept().await.map(|(stream, address)| (Connection::Tcp(stream), Some(address))),
+			Listener::Unix(listener) => listener.accept().await.map(|(stream, _)| (Connection::Unix(stream), None)),
+		}
+	}
+}
+
+/// The address requests are attributed to, e. g. for rate limiting. Connections over unix sockets
+/// stem from a proxy on the same host.
+pub(crate) fn client_ip(client_address: Option<SocketAddr>) -> std::net::IpAddr {
+	client_address.map_or(Ipv4Addr::LOCALHOST.into(), |address| address.ip())
+}
+
+pub(crate) enum Connection {
+	Tcp(TcpStream),
+	Unix(UnixStream),
+}
+
+impl AsyncRead for Connection {
+	fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+		match self.get_mut() {
+			Connection::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+			Connection::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+		}
+	}
+}
+
+impl AsyncWrite for Connection {
+	fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+		match self.get_mut() {
+			Connection::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+			Connection::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+		}
+	}
+
+	fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		match self.get_mut() {
+			Connection::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+			Connection::Unix(stream) => Pin::new(stream).poll_flush(cx),
+		}
+	}
+
+	fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		match self.get_mut() {
+			Connection::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+			Connection::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::path::PathBuf;
+
+	use tokio::io::{AsyncReadExt, AsyncWriteExt};
+	use tokio::net::UnixStream;
+
+	use crate::listener::{ListenAddress, Listener};
+
+	#[test]
+	fn test_listen_address_parsing() {
+		assert_eq!(ListenAddress::from_name("127.0.0.1:8011"), Some(ListenAddress::Tcp("127.0.0.1:8011".parse().unwrap())));
+		assert_eq!(ListenAddress::from_name("unix:/run/rgs/http.sock"), Some(ListenAddress::Unix(PathBuf::from("/run/rgs/http.sock"))));
+		assert_eq!(ListenAddress::from_name("systemd"), Some(ListenAddress::Systemd(None)));
+		assert_eq!(ListenAddress::from_name("systemd:http"), Some(ListenAddress::Systemd(Some("http".to_string()))));
+		assert_eq!(ListenAddress::from_name("unix:"), None);
+		assert_eq!(ListenAddress::from_name("localhost"), None);
+	}
+
+	#[tokio::test]
+	async fn test_unix_socket_listener() {
+		let path = std::env::temp_dir().join(format!("rgs_listener_test_{}.sock", std::process::id()));
+		let address = ListenAddress::Unix(path.clone());
+		drop(address.bind().await.unwrap());
+		// the socket left behind is replaced
+		let listener = address.bind().await.unwrap();
+		assert!(matches!(listener, Listener::Unix(_)));
+
+		let mut client = UnixStream::connect(&path).await.unwrap();
+		let (mut connection, client_address) = listener.accept().await.unwrap();
+		assert_eq!(client_address, None);
+		client.write_all(b"ping").await.unwrap();
+		let mut message = [0; 4];
+		connection.read_exact(&mut message).await.unwrap();
+		assert_eq!(&message, b"ping");
+
+		std::fs::remove_file(&path).unwrap();
+	}
+}
@@ -0,0 +1,220 @@
+//! Log output via [`tracing`].
+//!
+//! LDK's [`Record`]s, including those logged by this crate through LDK's macros, are forwarded as
+//! `tracing` events targeting their module path. Verbosity is configured per subsystem via
+//! [`LogFilter`] directives, or entirely through `RUST_LOG` if that is set. Spans around UTXO lookups, database writes, and snapshot generation give the events
+//! emitted within them additional context.
+
+use std::sync::OnceLock;
+
+use lightning::util::logger::{Level, Record};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::EnvFilter;
+
+use crate::config;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum LogFormat {
+	/// Human-readable lines
+	Text,
+	/// One JSON object per line, for log aggregation
+	Json,
+}
+
+impl LogFormat {
+	pub(crate) fn from_name(name: &str) -> Option<Self> {
+		match name {
+			"text" => Some(Self::Text),
+			"json" => Some(Self::Json),
+			_ => None,
+		}
+	}
+}
+
+/// Short names for the subsystems whose verbosity can be adjusted, mapping to the module they log
+/// from. Any other target containing `::` is taken to be a module path.
+const SUBSYSTEMS: &[(&str, &str)] = &[
+	("bootstrap", "rapid_gossip_sync_server::bootstrap"),
+	("downloader", "rapid_gossip_sync_server::downloader"),
+	("tracking", "rapid_gossip_sync_server::tracking"),
+	("verifier", "rapid_gossip_sync_server::verifier"),
+	("persistence", "rapid_gossip_sync_server::persistence"),
+	("lookup", "rapid_gossip_sync_server::lookup"),
+	("snapshot", "rapid_gossip_sync_server::snapshot"),
+	("server", "rapid_gossip_sync_server::server"),
+	("upload", "rapid_gossip_sync_server::upload"),
+	("hooks", "rapid_gossip_sync_server::hooks"),
+	("recording", "rapid_gossip_sync_server::recording"),
+	("replay", "rapid_gossip_sync_server::replay"),
+	("tip_monitor", "rapid_gossip_sync_server::tip_monitor"),
+	("rgs", "rapid_gossip_sync_server"),
+	("ldk", "lightning"),
+	("network_graph", "lightning::routing::gossip"),
+	("peer_handler", "lightning::ln::peer_handler"),
+	("block_sync", "lightning_block_sync"),
+	("net", "lightning_net_tokio"),
+];
+
+/// The minimum level of the records logged, by default and for individual modules, parsed from
+/// directives such as `info,network_graph=warn,verifier=trace`. A level of `None` disables logging.
+#[derive(Debug, PartialEq)]
+pub(crate) struct LogFilter {
+	default_level: Option<Level>,
+	/// Sorted by descending module path length, so the most specific directive matches first
+	module_levels: Vec<(String, Option<Level>)>,
+}
+
+impl LogFilter {
+	pub(crate) fn parse(directives: &str) -> Result<Self, &'static str> {
+		let mut default_level = Some(Level::Info);
+		let mut module_levels: Vec<(String, Option<Level>)> = Vec::new();
+		for directive in directives.split(',').map(str::trim).filter(|directive| !directive.is_empty()) {
+			match directive.split_once('=') {
+				Some((target, level)) => {
+					let target = target.trim();
+					let module_path = match SUBSYSTEMS.iter().find(|(name, _)| *name == target) {
+						Some((_, module_path)) => module_path.to_string(),
+						None if target.contains("::") => target.to_string(),
+						None => return Err("Unknown log subsystem"),
+					};
+					let level = parse_level(level.trim())?;
+					module_levels.retain(|(existing_path, _)| *existing_path != module_path);
+					module_levels.push((module_path, level));
+				}
+				None => default_level = parse_level(directive)?,
+			}
+		}
+		module_levels.sort_by_key(|(module_path, _)| std::cmp::Reverse(module_path.len()));
+		Ok(Self { default_level, module_levels })
+	}
+
+	fn level_for(&self, module_path: &str) -> Option<Level> {
+		self.module_levels.iter()
+			.find(|(path, _)| module_path == path || module_path.strip_prefix(path.as_str()).is_some_and(|rest| rest.starts_with("::")))
+			.map_or(self.default_level, |(_, level)| *level)
+	}
+
+	pub(crate) fn enabled(&self, module_path: &str, level: Level) -> bool {
+		self.level_for(module_path).is_some_and(|threshold| level >= threshold)
+	}
+
+	/// The equivalent `tracing` directives, which additionally apply to spans
+	fn env_filter(&self) -> EnvFilter {
+		let mut directives = vec![tracing_level_name(self.default_level).to_string()];
+		for (module_path, level) in self.module_levels.iter().rev() {
+			directives.push(format!("{}={}", module_path, tracing_level_name(*level)));
+		}
+		EnvFilter::new(directives.join(","))
+	}
+}
+
+fn parse_level(level: &str) -> Result<Option<Level>, &'static str> {
+	match level.to_lowercase().as_str() {
+		"off" => Ok(None),
+		"gossip" => Ok(Some(Level::Gossip)),
+		"trace" => Ok(Some(Level::Trace)),
+		"debug" => Ok(Some(Level::Debug)),
+		"info" => Ok(Some(Level::Info)),
+		"warn" => Ok(Some(Level::Warn)),
+		"error" => Ok(Some(Level::Error)),
+		_ => Err("Invalid log level"),
+	}
+}
+
+/// `tracing` has no level below trace, so LDK's gossip level is folded into it. Whether gossip
+/// records are forwarded is decided by the [`LogFilter`] instead.
+fn tracing_level_name(level: Option<Level>) -> &'static str {
+	match level {
+		None => "off",
+		Some(Level::Gossip) | Some(Level::Trace) => "trace",
+		Some(Level::Debug) => "debug",
+		Some(Level::Info) => "info",
+		Some(Level::Warn) => "warn",
+		Some(Level::Error) => "error",
+	}
+}
+
+/// The configured filter, unless `RUST_LOG` takes precedence
+static LOG_FILTER: OnceLock<Option<LogFilter>> = OnceLock::new();
+
+/// Install the global `tracing` subscriber, unless one was already set up (e. g. by an application
+/// embedding the server). Logs are written to stdout, unless `to_stderr` keeps it free for the
+/// output of one-off commands.
+pub(crate) fn init(to_stderr: bool) {
+	LOG_FILTER.get_or_init(|| {
+		let (log_filter, env_filter) = match std::env::var(EnvFilter::DEFAULT_ENV) {
+			Ok(directives) => (None, EnvFilter::new(directives)),
+			Err(_) => {
+				let log_filter = config::log_filter();
+				let env_filter = log_filter.env_filter();
+				(Some(log_filter), env_filter)
+			}
+		};
+		let writer = if to_stderr { BoxMakeWriter::new(std::io::stderr) } else { BoxMakeWriter::new(std::io::stdout) };
+		let builder = tracing_subscriber::fmt().with_env_filter(env_filter).with_writer(writer);
+		let _ = match config::log_format() {
+			LogFormat::Text => builder.try_init(),
+			LogFormat::Json => builder.json().with_current_span(true).with_span_list(true).try_init(),
+		};
+		log_filter
+	});
+}
+
+pub(crate) fn forward(record: &Record) {
+	if let Some(Some(log_filter)) = LOG_FILTER.get() {
+		if !log_filter.enabled(record.module_path, record.level) {
+			return;
+		}
+	}
+	let level = match record.level {
+		Level::Gossip | Level::Trace => log::Level::Trace,
+		Level::Debug => log::Level::Debug,
+		Level::Info => log::Level::Info,
+		Level::Warn => log::Level::Warn,
+		Level::Error => log::Level::Error,
+	};
+	let _ = tracing_log::format_trace(&log::Record::builder()
+		.args(record.args)
+		.level(level)
+		.target(record.module_path)
+		.module_path_static(Some(record.module_path))
+		.file_static(Some(record.file))
+		.line(Some(record.line))
+		.build());
+}
+
+#[cfg(test)]
+mod tests {
+	use lightning::util::logger::Level;
+
+	use crate::logging::LogFilter;
+
+	#[test]
+	fn test_log_filter() {
+		let default_filter = LogFilter::parse("").unwrap();
+		assert!(default_filter.enabled("rapid_gossip_sync_server::verifier", Level::Info));
+		assert!(!default_filter.enabled("rapid_gossip_sync_server::verifier", Level::Debug));
+
+		let filter = LogFilter::parse("gossip, network_graph=warn, lookup=off, verifier=error, lightning::ln=debug").unwrap();
+		assert!(filter.enabled("rapid_gossip_sync_server::downloader", Level::Gossip));
+		assert!(!filter.enabled("lightning::routing::gossip", Level::Info));
+		assert!(filter.enabled("lightning::routing::gossip", Level::Warn));
+		assert!(!filter.enabled("rapid_gossip_sync_server::lookup", Level::Error));
+		assert!(!filter.enabled("rapid_gossip_sync_server::verifier", Level::Warn));
+		assert!(filter.enabled("rapid_gossip_sync_server::verifier", Level::Error));
+		assert!(!filter.enabled("lightning::ln::peer_handler", Level::Trace));
+		assert!(filter.enabled("lightning::ln::peer_handler", Level::Debug));
+		// module paths only match on path segment boundaries
+		assert!(filter.enabled("rapid_gossip_sync_server::lookup_extra", Level::Gossip));
+
+		let filter = LogFilter::parse("warn,rgs=info,snapshot=debug").unwrap();
+		assert!(!filter.enabled("lightning::ln::peer_handler", Level::Info));
+		assert!(filter.enabled("rapid_gossip_sync_server", Level::Info));
+		assert!(!filter.enabled("rapid_gossip_sync_server::tracking", Level::Debug));
+		assert!(filter.enabled("rapid_gossip_sync_server::snapshot", Level::Debug));
+
+		assert!(LogFilter::parse("verbose").is_err());
+		assert!(LogFilter::parse("unknown=info").is_err());
+		assert!(LogFilter::parse("verifier=loud").is_err());
+	}
+}
@@ -6,9 +6,8 @@ use std::time::{Instant, SystemTime, UNIX_EPOCH};
 use bitcoin::io::Cursor;
 
 use lightning::ln::msgs::{ChannelAnnouncement, ChannelUpdate, NodeAnnouncement, SocketAddress, UnsignedChannelAnnouncement, UnsignedChannelUpdate};
-use lightning::routing::gossip::{NetworkGraph, NodeId};
+use lightning::routing::gossip::{NetworkGraph, NodeAlias, NodeId};
 use lightning::util::ser::Readable;
-use tokio_postgres::Client;
 
 use futures::StreamExt;
 use hex_conservative::DisplayHex;
@@ -16,7 +15,10 @@ use lightning::{log_debug, log_gossip, log_info};
 use lightning::types::features::NodeFeatures;
 use lightning::util::logger::Logger;
 
+use crate::blocklist::{self, Blocklist};
 use crate::config;
+use crate::profiles::ProfileSelection;
+use crate::storage::GossipStore;
 use crate::serialization::{MutatedNodeProperties, MutatedProperties, NodeSerializationStrategy};
 
 /// The delta set needs to be a BTreeMap so the keys are sorted.
@@ -68,7 +70,8 @@ pub(super) struct NodeDelta {
 pub(super) struct NodeDetails {
 	pub(super) seen: Option<u32>,
 	pub(super) features: NodeFeatures,
-	pub(super) addresses: HashSet<SocketAddress>
+	pub(super) addresses: HashSet<SocketAddress>,
+	pub(super) alias: NodeAlias,
 }
 
 impl Default for ChannelDelta {
@@ -121,54 +124,63 @@ fn should_snapshot_include_reminders<L: Deref>(last_sync_timestamp: u32, current
 	(is_reminder_hour && is_reminder_day) || is_reminder_scope
 }
 
+/// The channels in the network graph to include in snapshots: those with updates in both
+/// directions that aren't blocklisted, and, given a `min_capacity_sats`, whose funding amount as
+/// looked up by the verifier is at least that. Channels whose funding amount is unknown, such as
+/// ones bootstrapped from a snapshot, are included regardless.
+pub(super) fn snapshot_channel_ids<L: Deref>(network_graph: &NetworkGraph<L>, blocklist: &Blocklist, min_capacity_sats: Option<u64>) -> Vec<i64> where L::Target: Logger {
+	let read_only_graph = network_graph.read_only();
+	read_only_graph.channels().unordered_iter()
+		// channels bootstrapped from a snapshot lack the announcement message, but are persisted
+		.filter(|c| c.1.one_to_two.is_some() && c.1.two_to_one.is_some())
+		.filter(|c| !blocklist.blocks_channel(*c.0, &c.1.node_one, &c.1.node_two))
+		.filter(|c| match (min_capacity_sats, c.1.capacity_sats) {
+			(Some(min_capacity_sats), Some(capacity_sats)) => capacity_sats >= min_capacity_sats,
+			_ => true,
+		})
+		.map(|c| *c.0 as i64)
+		.collect()
+}
+
 /// Fetch all the channel announcements that are presently in the network graph, regardless of
 /// whether they had been seen before.
 /// Also include all announcements for which the first update was announced
 /// after `last_sync_timestamp`
-pub(super) async fn fetch_channel_announcements<L: Deref>(delta_set: &mut DeltaSet, network_graph: Arc<NetworkGraph<L>>, client: &Client, last_sync_timestamp: u32, snapshot_reference_timestamp: Option<u64>, logger: L) where L::Target: Logger {
+///
+/// Given the `changed_channels` since the last sync, only those need to be looked up, as any other
+/// channel could only be included for lack of recent updates, i. e. as a reminder.
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn fetch_channel_announcements<L: Deref>(delta_set: &mut DeltaSet, network_graph: Arc<NetworkGraph<L>>, store: &dyn GossipStore, selection: Option<&ProfileSelection>, changed_channels: Option<&HashSet<u64>>, last_sync_timestamp: u32, snapshot_reference_timestamp: Option<u64>, logger: L) where L::Target: Logger {
 	log_info!(logger, "Obtaining channel ids from network graph");
-	let channel_ids = {
-		let read_only_graph = network_graph.read_only();
-		log_info!(logger, "Retrieved read-only network graph copy");
-		let channel_iterator = read_only_graph.channels().unordered_iter();
-		channel_iterator
-			.filter(|c| c.1.announcement_message.is_some() && c.1.one_to_two.is_some() && c.1.two_to_one.is_some())
-			.map(|c| c.1.announcement_message.as_ref().unwrap().contents.short_channel_id as i64)
-			.collect::<Vec<_>>()
-	};
+	let min_capacity_sats = config::min_channel_capacity_sats();
+	if let Some(min_capacity_sats) = min_capacity_sats {
+		log_info!(logger, "Omitting channels with a capacity below {} sats", min_capacity_sats);
+	}
+	let mut channel_ids = snapshot_channel_ids(&network_graph, &blocklist::current(), min_capacity_sats);
+	if let Some(selection) = selection {
+		channel_ids.retain(|short_channel_id| selection.includes_channel(*short_channel_id as u64));
+	}
 	#[cfg(test)]
 	log_info!(logger, "Channel IDs: {:?}", channel_ids);
 	log_info!(logger, "Last sync timestamp: {}", last_sync_timestamp);
-	let last_sync_timestamp_float = last_sync_timestamp as f64;
-
 	let current_timestamp = snapshot_reference_timestamp.unwrap_or(SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs());
 	log_info!(logger, "Current timestamp: {}", current_timestamp);
 
 	let include_reminders = should_snapshot_include_reminders(last_sync_timestamp, current_timestamp, &logger);
 
+	// the channels that may have changed since the last sync
+	let lookup_channel_ids = match changed_channels {
+		Some(changed_channels) => {
+			let changed_channel_ids: Vec<i64> = channel_ids.iter().copied().filter(|short_channel_id| changed_channels.contains(&(*short_channel_id as u64))).collect();
+			log_info!(logger, "Looking up {} of {} channels changed since the last sync", changed_channel_ids.len(), channel_ids.len());
+			changed_channel_ids
+		}
+		None => channel_ids.clone(),
+	};
+
 	log_info!(logger, "Obtaining corresponding database entries");
 	// get all the channel announcements that are currently in the network graph
-	let announcement_rows = client.query_raw("SELECT announcement_signed, CAST(EXTRACT('epoch' from seen) AS BIGINT) AS seen FROM channel_announcements WHERE short_channel_id = any($1) ORDER BY short_channel_id ASC", [&channel_ids]).await.unwrap();
-	let mut pinned_rows = Box::pin(announcement_rows);
-
-	let mut announcement_count = 0;
-	while let Some(row_res) = pinned_rows.next().await {
-		let current_announcement_row = row_res.unwrap();
-		let blob: Vec<u8> = current_announcement_row.get("announcement_signed");
-		let mut readable = Cursor::new(&blob);
-		let unsigned_announcement = ChannelAnnouncement::read(&mut readable).unwrap().contents;
-
-		let scid = unsigned_announcement.short_channel_id;
-		let current_seen_timestamp = current_announcement_row.get::<_, i64>("seen") as u32;
-
-		let current_channel_delta = delta_set.entry(scid).or_insert(ChannelDelta::default());
-		(*current_channel_delta).announcement = Some(AnnouncementDelta {
-			announcement: unsigned_announcement,
-			seen: current_seen_timestamp,
-		});
-
-		announcement_count += 1;
-	}
+	let announcement_count = fetch_announcements(delta_set, store, lookup_channel_ids.clone()).await;
 	log_info!(logger, "Fetched {} announcement rows", announcement_count);
 
 	{
@@ -182,32 +194,15 @@ pub(super) async fn fetch_channel_announcements<L: Deref>(delta_set: &mut DeltaS
 
 		// here is where the channels whose first update in either direction occurred after
 		// `last_seen_timestamp` are added to the selection
-		let params: [&(dyn tokio_postgres::types::ToSql + Sync); 2] =
-			[&channel_ids, &last_sync_timestamp_float];
-		let newer_oldest_directional_updates = client.query_raw("
-			SELECT short_channel_id, CAST(EXTRACT('epoch' from distinct_chans.seen) AS BIGINT) AS seen FROM (
-				SELECT DISTINCT ON (short_channel_id) *
-				FROM (
-					SELECT DISTINCT ON (short_channel_id, direction) short_channel_id, seen
-					FROM channel_updates
-					WHERE short_channel_id = any($1)
-					ORDER BY short_channel_id ASC, direction ASC, seen ASC
-				) AS directional_last_seens
-				ORDER BY short_channel_id ASC, seen DESC
-			) AS distinct_chans
-			WHERE distinct_chans.seen >= TO_TIMESTAMP($2)
-			", params).await.unwrap();
-		let mut pinned_updates = Box::pin(newer_oldest_directional_updates);
+		let mut newer_oldest_directional_updates = store.first_bidirectional_updates(lookup_channel_ids, last_sync_timestamp).await;
 
 		let mut newer_oldest_directional_update_count = 0;
-		while let Some(row_res) = pinned_updates.next().await {
-			let current_row = row_res.unwrap();
-
-			let scid: i64 = current_row.get("short_channel_id");
-			let current_seen_timestamp = current_row.get::<_, i64>("seen") as u32;
+		while let Some(current_row) = newer_oldest_directional_updates.next().await {
+			let scid = current_row.short_channel_id;
+			let current_seen_timestamp = current_row.seen;
 
 			// the newer of the two oldest seen directional updates came after last sync timestamp
-			let current_channel_delta = delta_set.entry(scid as u64).or_insert(ChannelDelta::default());
+			let current_channel_delta = delta_set.entry(scid).or_insert(ChannelDelta::default());
 			// first time a channel was seen in both directions
 			(*current_channel_delta).first_bidirectional_updates_seen = Some(current_seen_timestamp);
 
@@ -226,11 +221,10 @@ pub(super) async fn fetch_channel_announcements<L: Deref>(delta_set: &mut DeltaS
 		let reminder_threshold_timestamp = current_timestamp.checked_sub(config::CHANNEL_REMINDER_AGE.as_secs()).unwrap() as f64;
 
 		log_info!(logger, "Fetch first time we saw the current value combination for each direction (prior mutations excepted)");
-		let reminder_lookup_threshold_timestamp = current_timestamp.checked_sub(config::PRUNE_INTERVAL.as_secs()).unwrap() as f64;
-		let params: [&(dyn tokio_postgres::types::ToSql + Sync); 2] = [&channel_ids, &reminder_lookup_threshold_timestamp];
+		let reminder_lookup_threshold_timestamp = current_timestamp.checked_sub(config::staleness_horizon().as_secs()).unwrap() as u32;
 
 		/*
-		What exactly is the below query doing?
+		What exactly is the store's mutated updates query doing?
 
 		First, the inner query groups all channel updates by their scid/direction combination,
 		and then sorts those in reverse chronological order by the "seen" column.
@@ -250,43 +244,15 @@ pub(super) async fn fetch_channel_announcements<L: Deref>(delta_set: &mut DeltaS
 		3x the timeframe that we consider necessitates reminders.
 		*/
 
-		let mutated_updates = client.query_raw("
-		SELECT DISTINCT ON (short_channel_id, direction) short_channel_id, direction, blob_signed, CAST(EXTRACT('epoch' from seen) AS BIGINT) AS seen FROM (
-			SELECT short_channel_id, direction, timestamp, seen, blob_signed, COALESCE (
-				disable<>lead(disable) OVER w1
-					OR
-				cltv_expiry_delta<>lead(cltv_expiry_delta) OVER w1
-					OR
-				htlc_minimum_msat<>lead(htlc_minimum_msat) OVER w1
-					OR
-				fee_base_msat<>lead(fee_base_msat) OVER w1
-					OR
-				fee_proportional_millionths<>lead(fee_proportional_millionths) OVER w1
-					OR
-				htlc_maximum_msat<>lead(htlc_maximum_msat) OVER w1,
-				TRUE
-			) has_distinct_successor
-			FROM channel_updates
-			WHERE short_channel_id = any($1) AND seen >= TO_TIMESTAMP($2)
-			WINDOW w1 AS (PARTITION BY short_channel_id, direction ORDER BY seen DESC)
-		) _
-		WHERE has_distinct_successor
-		ORDER BY short_channel_id ASC, direction ASC, timestamp DESC
-		", params).await.unwrap();
-
-		let mut pinned_updates = Box::pin(mutated_updates);
+		let mut mutated_updates = store.mutated_updates(channel_ids, reminder_lookup_threshold_timestamp).await;
 		let mut older_latest_directional_update_count = 0;
-		while let Some(row_res) = pinned_updates.next().await {
-			let current_row = row_res.unwrap();
-			let seen = current_row.get::<_, i64>("seen") as u32;
-
-			if seen < reminder_threshold_timestamp as u32 {
-				let blob: Vec<u8> = current_row.get("blob_signed");
-				let mut readable = Cursor::new(&blob);
+		while let Some(current_row) = mutated_updates.next().await {
+			if current_row.seen < reminder_threshold_timestamp as u32 {
+				let mut readable = Cursor::new(&current_row.blob_signed);
 				let unsigned_channel_update = ChannelUpdate::read(&mut readable).unwrap().contents;
 
 				let scid = unsigned_channel_update.short_channel_id;
-				let direction: bool = current_row.get("direction");
+				let direction = current_row.direction;
 
 				let current_channel_delta = delta_set.entry(scid).or_insert(ChannelDelta::default());
 
@@ -320,47 +286,64 @@ pub(super) async fn fetch_channel_announcements<L: Deref>(delta_set: &mut DeltaS
 			}
 		}
 		log_info!(logger, "Fetched {} update rows of the latest update in the less recently updated direction", older_latest_directional_update_count);
+
+		if changed_channels.is_some() {
+			// the reminders of channels that didn't change still need their announcements
+			let reminder_channel_ids: Vec<i64> = delta_set.iter()
+				.filter(|(_, channel_delta)| channel_delta.requires_reminder && channel_delta.announcement.is_none())
+				.map(|(short_channel_id, _)| *short_channel_id as i64)
+				.collect();
+			let announcement_count = fetch_announcements(delta_set, store, reminder_channel_ids).await;
+			log_info!(logger, "Fetched {} announcement rows of unchanged channels requiring reminders", announcement_count);
+		}
 	}
 }
 
-pub(super) async fn fetch_channel_updates<L: Deref>(delta_set: &mut DeltaSet, client: &Client, last_sync_timestamp: u32, logger: L) where L::Target: Logger {
+/// Add the announcements of the given channels to the delta set, returning how many were found
+async fn fetch_announcements(delta_set: &mut DeltaSet, store: &dyn GossipStore, short_channel_ids: Vec<i64>) -> usize {
+	let mut announcement_rows = store.channel_announcements(short_channel_ids).await;
+
+	let mut announcement_count = 0;
+	while let Some(current_announcement_row) = announcement_rows.next().await {
+		let mut readable = Cursor::new(&current_announcement_row.announcement_signed);
+		let unsigned_announcement = ChannelAnnouncement::read(&mut readable).unwrap().contents;
+
+		let scid = unsigned_announcement.short_channel_id;
+		let current_seen_timestamp = current_announcement_row.seen;
+
+		let current_channel_delta = delta_set.entry(scid).or_insert(ChannelDelta::default());
+		(*current_channel_delta).announcement = Some(AnnouncementDelta {
+			announcement: unsigned_announcement,
+			seen: current_seen_timestamp,
+		});
+
+		announcement_count += 1;
+	}
+	announcement_count
+}
+
+pub(super) async fn fetch_channel_updates<L: Deref>(delta_set: &mut DeltaSet, store: &dyn GossipStore, last_sync_timestamp: u32, logger: L) where L::Target: Logger {
 	let start = Instant::now();
-	let last_sync_timestamp_float = last_sync_timestamp as f64;
 
 	// get the latest channel update in each direction prior to last_sync_timestamp, provided
 	// there was an update in either direction that happened after the last sync (to avoid
 	// collecting too many reference updates)
-	let reference_rows = client.query_raw("
-		SELECT id, direction, CAST(EXTRACT('epoch' from seen) AS BIGINT) AS seen, blob_signed FROM channel_updates
-		WHERE id IN (
-			SELECT DISTINCT ON (short_channel_id, direction) id
-			FROM channel_updates
-			WHERE seen < TO_TIMESTAMP($1) AND short_channel_id IN (
-				SELECT DISTINCT ON (short_channel_id) short_channel_id
-				FROM channel_updates
-				WHERE seen >= TO_TIMESTAMP($1)
-			)
-			ORDER BY short_channel_id ASC, direction ASC, seen DESC
-		)
-		", [last_sync_timestamp_float]).await.unwrap();
-	let mut pinned_rows = Box::pin(reference_rows);
+	let mut reference_rows = store.reference_updates(last_sync_timestamp).await;
 
 	log_info!(logger, "Fetched reference rows in {:?}", start.elapsed());
 
-	let mut last_seen_update_ids: Vec<i32> = Vec::new();
-	let mut non_intermediate_ids: HashSet<i32> = HashSet::new();
+	let mut last_seen_update_ids: Vec<i64> = Vec::new();
+	let mut non_intermediate_ids: HashSet<i64> = HashSet::new();
 	let mut reference_row_count = 0;
 
-	while let Some(row_res) = pinned_rows.next().await {
-		let current_reference = row_res.unwrap();
-		let update_id: i32 = current_reference.get("id");
+	while let Some(current_reference) = reference_rows.next().await {
+		let update_id = current_reference.id;
 		last_seen_update_ids.push(update_id);
 		non_intermediate_ids.insert(update_id);
 
-		let direction: bool = current_reference.get("direction");
-		let seen = current_reference.get::<_, i64>("seen") as u32;
-		let blob: Vec<u8> = current_reference.get("blob_signed");
-		let mut readable = Cursor::new(&blob);
+		let direction = current_reference.direction;
+		let seen = current_reference.seen;
+		let mut readable = Cursor::new(&current_reference.blob_signed);
 		let unsigned_channel_update = ChannelUpdate::read(&mut readable).unwrap().contents;
 		let scid = unsigned_channel_update.short_channel_id;
 
@@ -386,31 +369,23 @@ pub(super) async fn fetch_channel_updates<L: Deref>(delta_set: &mut DeltaSet, cl
 	// (to calculate the set of mutated fields for snapshotting, where intermediate updates may
 	// have been omitted)
 
-	let intermediate_updates = client.query_raw("
-		SELECT id, direction, blob_signed, CAST(EXTRACT('epoch' from seen) AS BIGINT) AS seen
-		FROM channel_updates
-		WHERE seen >= TO_TIMESTAMP($1)
-		ORDER BY short_channel_id ASC, timestamp DESC
-		", [last_sync_timestamp_float]).await.unwrap();
-	let mut pinned_updates = Box::pin(intermediate_updates);
+	let mut intermediate_updates = store.intermediate_updates(last_sync_timestamp).await;
 	log_info!(logger, "Fetched intermediate rows in {:?}", start.elapsed());
 
 	let mut previous_scid = u64::MAX;
 	let mut previously_seen_directions = (false, false);
 
 	let mut intermediate_update_count = 0;
-	while let Some(row_res) = pinned_updates.next().await {
-		let intermediate_update = row_res.unwrap();
-		let update_id: i32 = intermediate_update.get("id");
+	while let Some(intermediate_update) = intermediate_updates.next().await {
+		let update_id = intermediate_update.id;
 		if non_intermediate_ids.contains(&update_id) {
 			continue;
 		}
 		intermediate_update_count += 1;
 
-		let direction: bool = intermediate_update.get("direction");
-		let current_seen_timestamp = intermediate_update.get::<_, i64>("seen") as u32;
-		let blob: Vec<u8> = intermediate_update.get("blob_signed");
-		let mut readable = Cursor::new(&blob);
+		let direction = intermediate_update.direction;
+		let current_seen_timestamp = intermediate_update.seen;
+		let mut readable = Cursor::new(&intermediate_update.blob_signed);
 		let unsigned_channel_update = ChannelUpdate::read(&mut readable).unwrap().contents;
 
 		let scid = unsigned_channel_update.short_channel_id;
@@ -469,18 +444,22 @@ pub(super) async fn fetch_channel_updates<L: Deref>(delta_set: &mut DeltaSet, cl
 	log_info!(logger, "Processed intermediate rows ({}) (delta size: {}): {:?}", intermediate_update_count, delta_set.len(), start.elapsed());
 }
 
-pub(super) async fn fetch_node_updates<L: Deref + Clone>(network_graph: Arc<NetworkGraph<L>>, client: &Client, last_sync_timestamp: u32, snapshot_reference_timestamp: Option<u64>, logger: L) -> NodeDeltaSet where L::Target: Logger {
+pub(super) async fn fetch_node_updates<L: Deref + Clone>(network_graph: Arc<NetworkGraph<L>>, store: &dyn GossipStore, selection: Option<&ProfileSelection>, last_sync_timestamp: u32, snapshot_reference_timestamp: Option<u64>, logger: L) -> NodeDeltaSet where L::Target: Logger {
 	let start = Instant::now();
-	let last_sync_timestamp_float = last_sync_timestamp as f64;
 
+	let blocklist = blocklist::current();
 	let mut delta_set: NodeDeltaSet = {
 		let read_only_graph = network_graph.read_only();
 		read_only_graph.nodes().unordered_iter().flat_map(|(node_id, node_info)| {
+			if blocklist.blocks_node(node_id) || selection.is_some_and(|selection| !selection.includes_node(node_id)) {
+				return None;
+			}
 			let details: NodeDetails = if let Some(details) = node_info.announcement_info.as_ref() {
 				NodeDetails {
 					seen: None,
 					features: details.features().clone(),
 					addresses: details.addresses().into_iter().cloned().collect(),
+					alias: *details.alias(),
 				}
 			} else {
 				return None;
@@ -498,27 +477,15 @@ pub(super) async fn fetch_node_updates<L: Deref + Clone>(network_graph: Arc<Netw
 	log_info!(logger, "Node IDs: {:?}", node_ids);
 
 	// get the latest node updates prior to last_sync_timestamp
-	let params: [&(dyn tokio_postgres::types::ToSql + Sync); 2] = [&node_ids, &last_sync_timestamp_float];
-	let reference_rows = client.query_raw("
-		SELECT DISTINCT ON (public_key) public_key, CAST(EXTRACT('epoch' from seen) AS BIGINT) AS seen, announcement_signed
-		FROM node_announcements
-		WHERE
-			public_key = ANY($1) AND
-			seen < TO_TIMESTAMP($2)
-		ORDER BY public_key ASC, seen DESC
-		", params).await.unwrap();
-	let mut pinned_rows = Box::pin(reference_rows);
+	let mut reference_rows = store.node_reference_announcements(node_ids.clone(), last_sync_timestamp).await;
 
 	log_info!(logger, "Fetched node announcement reference rows in {:?}", start.elapsed());
 
 	let mut reference_row_count = 0;
 
-	while let Some(row_res) = pinned_rows.next().await {
-		let current_reference = row_res.unwrap();
-
-		let seen = current_reference.get::<_, i64>("seen") as u32;
-		let blob: Vec<u8> = current_reference.get("announcement_signed");
-		let mut readable = Cursor::new(&blob);
+	while let Some(current_reference) = reference_rows.next().await {
+		let seen = current_reference.seen;
+		let mut readable = Cursor::new(&current_reference.announcement_signed);
 		let unsigned_node_announcement = NodeAnnouncement::read(&mut readable).unwrap().contents;
 		let node_id = unsigned_node_announcement.node_id;
 
@@ -529,6 +496,7 @@ pub(super) async fn fetch_node_updates<L: Deref + Clone>(network_graph: Arc<Netw
 				seen: Some(seen),
 				features: unsigned_node_announcement.features,
 				addresses: address_set,
+				alias: unsigned_node_announcement.alias,
 			}
 		});
 		log_gossip!(logger, "Node {} last update before seen: {} (seen at {})", node_id, unsigned_node_announcement.timestamp, seen);
@@ -542,12 +510,12 @@ pub(super) async fn fetch_node_updates<L: Deref + Clone>(network_graph: Arc<Netw
 
 	let current_timestamp = snapshot_reference_timestamp.unwrap_or(SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs());
 	let reminder_inclusion_threshold_timestamp = current_timestamp.checked_sub(config::CHANNEL_REMINDER_AGE.as_secs()).unwrap() as u32;
-	let reminder_lookup_threshold_timestamp = current_timestamp.checked_sub(config::PRUNE_INTERVAL.as_secs()).unwrap() as u32;
+	let reminder_lookup_threshold_timestamp = current_timestamp.checked_sub(config::staleness_horizon().as_secs()).unwrap() as u32;
 
 	// this is the timestamp we need to fetch all relevant updates
 	let include_reminders = should_snapshot_include_reminders(last_sync_timestamp, current_timestamp, &logger);
 	let effective_threshold_timestamp = if include_reminders {
-		std::cmp::min(last_sync_timestamp, reminder_lookup_threshold_timestamp) as f64
+		std::cmp::min(last_sync_timestamp, reminder_lookup_threshold_timestamp)
 	} else {
 		// If we include reminders, the decision logic is as follows:
 		// If the pre-sync update was more than 6 days ago, serialize in full.
@@ -556,22 +524,13 @@ pub(super) async fn fetch_node_updates<L: Deref + Clone>(network_graph: Arc<Netw
 		// Otherwise:
 		// If the last mutation occurred more than 6 days ago, serialize as a reminder.
 		// Otherwise, don't serialize at all.
-		last_sync_timestamp as f64
+		last_sync_timestamp
 	};
 
 	// get all the intermediate node updates
 	// (to calculate the set of mutated fields for snapshotting, where intermediate updates may
 	// have been omitted)
-	let params: [&(dyn tokio_postgres::types::ToSql + Sync); 2] = [&node_ids, &effective_threshold_timestamp];
-	let intermediate_updates = client.query_raw("
-		SELECT announcement_signed, CAST(EXTRACT('epoch' from seen) AS BIGINT) AS seen
-		FROM node_announcements
-		WHERE
-			public_key = ANY($1) AND
-			seen >= TO_TIMESTAMP($2)
-		ORDER BY public_key ASC, timestamp DESC
-		", params).await.unwrap();
-	let mut pinned_updates = Box::pin(intermediate_updates);
+	let mut intermediate_updates = store.node_intermediate_announcements(node_ids, effective_threshold_timestamp).await;
 	log_info!(logger, "Fetched intermediate node announcement rows in {:?}", start.elapsed());
 
 	let mut previous_node_id: Option<NodeId> = None;
@@ -579,14 +538,15 @@ pub(super) async fn fetch_node_updates<L: Deref + Clone>(network_graph: Arc<Netw
 	let mut intermediate_update_count = 0;
 	let mut has_address_set_changed = false;
 	let mut has_feature_set_changed = false;
+	let mut has_alias_changed = false;
+	// alias changes are only relevant to snapshots carrying them
+	let track_aliases = config::include_node_aliases();
 	let mut latest_mutation_timestamp = None;
-	while let Some(row_res) = pinned_updates.next().await {
-		let intermediate_update = row_res.unwrap();
+	while let Some(intermediate_update) = intermediate_updates.next().await {
 		intermediate_update_count += 1;
 
-		let current_seen_timestamp = intermediate_update.get::<_, i64>("seen") as u32;
-		let blob: Vec<u8> = intermediate_update.get("announcement_signed");
-		let mut readable = Cursor::new(&blob);
+		let current_seen_timestamp = intermediate_update.seen;
+		let mut readable = Cursor::new(&intermediate_update.announcement_signed);
 		let unsigned_node_announcement = NodeAnnouncement::read(&mut readable).unwrap().contents;
 
 		let node_id = unsigned_node_announcement.node_id;
@@ -599,6 +559,7 @@ pub(super) async fn fetch_node_updates<L: Deref + Clone>(network_graph: Arc<Netw
 			// we're traversing a new node id, initialize the values
 			has_address_set_changed = false;
 			has_feature_set_changed = false;
+			has_alias_changed = false;
 			latest_mutation_timestamp = None;
 
 			// this is the highest timestamp value, so set the seen timestamp accordingly
@@ -619,14 +580,21 @@ pub(super) async fn fetch_node_updates<L: Deref + Clone>(network_graph: Arc<Netw
 						latest_mutation_timestamp = Some(current_seen_timestamp);
 					}
 				}
+				if track_aliases && unsigned_node_announcement.alias != last_seen_update.alias {
+					has_alias_changed = true;
+					if latest_mutation_timestamp.is_none() {
+						latest_mutation_timestamp = Some(current_seen_timestamp);
+					}
+				}
 			}
 
 			if current_seen_timestamp >= last_sync_timestamp {
-				if has_address_set_changed || has_feature_set_changed {
+				if has_address_set_changed || has_feature_set_changed || has_alias_changed {
 					// if the last mutation occurred since the last sync, send the mutation variant
 					current_node_delta.strategy = Some(NodeSerializationStrategy::Mutated(MutatedNodeProperties {
 						addresses: has_address_set_changed,
 						features: has_feature_set_changed,
+						alias: has_alias_changed,
 					}));
 				}
 			} else if include_reminders && latest_mutation_timestamp.unwrap_or(u32::MAX) <= reminder_inclusion_threshold_timestamp {
@@ -1,9 +1,200 @@
+use std::process::ExitCode;
 use std::sync::Arc;
-use rapid_gossip_sync_server::RapidSyncProcessor;
+use clap::{Parser, Subcommand};
+use rapid_gossip_sync_server::{GraphExportFormat, ParsedSnapshot, RapidSyncProcessor, SnapshotDiff};
 use rapid_gossip_sync_server::types::RGSSLogger;
 
+/// Syncs Lightning gossip and serves it as rapid gossip sync snapshots
+#[derive(Debug, Parser)]
+#[command(version, after_help = "\
+Except for inspect and diff, which only read the given snapshot files, every command operates on
+the networks and database configured through the environment or the file at
+RAPID_GOSSIP_SYNC_SERVER_CONFIG_FILE.")]
+struct Cli {
+	/// Defaults to serve
+	#[command(subcommand)]
+	command: Option<Command>,
+}
+
+#[derive(Debug, PartialEq, Subcommand)]
+enum Command {
+	/// Sync gossip and continuously generate snapshots
+	Serve,
+	/// Generate a single round of snapshots from the persisted gossip
+	SnapshotOnce,
+	/// Dump the network graph
+	ExportGraph {
+		/// json, graphml, nodes-csv, or channels-csv
+		#[arg(long, default_value = "json")]
+		format: GraphExportFormat,
+		/// File to write the dump to instead of stdout
+		#[arg(long, value_name = "PATH")]
+		output: Option<String>,
+	},
+	/// Feed a gossip recording through validation and persistence, then generate a round of
+	/// snapshots
+	Replay {
+		/// A recording file, or the directory gossip was recorded to
+		#[arg(value_name = "PATH")]
+		recording: String,
+		/// Replay at this multiple of the recorded pace rather than at once
+		#[arg(long, value_name = "FACTOR", value_parser = parse_speed)]
+		speed: Option<f64>,
+		/// File to look up funding outputs in instead of the chain source
+		#[arg(long, value_name = "PATH")]
+		utxo_set: Option<String>,
+	},
+	/// Check the database schema and the consistency of the stored gossip
+	VerifyDb,
+	/// Prune the channel updates that have outlived the configured retention
+	Prune,
+	/// Create or upgrade the database schema
+	Migrate,
+	/// Print the counts of nodes, channels and updates in a snapshot, and how often the update
+	/// fields take the snapshot's defaults
+	Inspect {
+		snapshot: String,
+	},
+	/// Print the channels announced and updated in only one of two snapshots, and the updates
+	/// differing between them
+	Diff {
+		a: String,
+		b: String,
+	},
+}
+
+fn parse_speed(value: &str) -> Result<f64, String> {
+	value.parse::<f64>().ok().filter(|speed| *speed > 0.0).ok_or_else(|| format!("Invalid replay speed {}", value))
+}
+
 #[tokio::main]
-async fn main() {
-	let logger = Arc::new(RGSSLogger::new());
-	RapidSyncProcessor::new(logger).start_sync().await;
+async fn main() -> ExitCode {
+	let command = Cli::parse().command.unwrap_or(Command::Serve);
+
+	let logger = match command {
+		// the snapshot files are all these need, without any configuration
+		Command::Inspect { snapshot } => return match ParsedSnapshot::read(&snapshot) {
+			Ok(snapshot) => {
+				print!("{}", snapshot);
+				ExitCode::SUCCESS
+			}
+			Err(e) => {
+				eprintln!("{}", e);
+				ExitCode::FAILURE
+			}
+		},
+		Command::Diff { a, b } => return match ParsedSnapshot::read(&a).and_then(|a| Ok((a, ParsedSnapshot::read(&b)?))) {
+			Ok((a, b)) => {
+				print!("{}", SnapshotDiff::new(&a, &b));
+				ExitCode::SUCCESS
+			}
+			Err(e) => {
+				eprintln!("{}", e);
+				ExitCode::FAILURE
+			}
+		},
+		Command::Serve => Arc::new(RGSSLogger::new()),
+		// keep stdout free for the commands' output
+		_ => Arc::new(RGSSLogger::stderr()),
+	};
+	let processors = RapidSyncProcessor::for_configured_networks(logger);
+	match command {
+		Command::Serve => {
+			futures::future::join_all(processors.iter().map(|processor| processor.start_sync())).await;
+		}
+		Command::SnapshotOnce => {
+			for processor in &processors {
+				processor.snapshot_once().await;
+			}
+		}
+		Command::ExportGraph { format, output } => {
+			if processors.len() > 1 {
+				eprintln!("export-graph operates on a single network, but several are configured");
+				return ExitCode::FAILURE;
+			}
+			let graph = processors[0].export_graph(format).await;
+			match output {
+				Some(path) => if let Err(e) = std::fs::write(&path, graph) {
+					eprintln!("Failed to write the network graph to {}: {}", path, e);
+					return ExitCode::FAILURE;
+				},
+				None => print!("{}", graph),
+			}
+		}
+		Command::Replay { recording, speed, utxo_set } => {
+			if processors.len() > 1 {
+				eprintln!("replay operates on a single network, but several are configured");
+				return ExitCode::FAILURE;
+			}
+			if let Err(e) = processors[0].replay(&recording, speed, utxo_set.as_deref()).await {
+				eprintln!("{}", e);
+				return ExitCode::FAILURE;
+			}
+		}
+		Command::VerifyDb => {
+			let mut is_consistent = true;
+			for processor in &processors {
+				is_consistent &= processor.verify_db().await;
+			}
+			if !is_consistent {
+				return ExitCode::FAILURE;
+			}
+		}
+		Command::Prune => {
+			for processor in &processors {
+				if processor.prune().await.is_none() {
+					eprintln!("No channel update retention is configured, see RAPID_GOSSIP_SYNC_SERVER_UPDATE_RETENTION_DAYS");
+					return ExitCode::FAILURE;
+				}
+			}
+		}
+		Command::Migrate => {
+			for processor in &processors {
+				processor.migrate().await;
+			}
+		}
+		Command::Inspect { .. } | Command::Diff { .. } => unreachable!(),
+	}
+	ExitCode::SUCCESS
+}
+
+#[cfg(test)]
+mod tests {
+	use clap::{CommandFactory, Parser};
+	use rapid_gossip_sync_server::GraphExportFormat;
+
+	use crate::{Cli, Command};
+
+	fn parse(args: &[&str]) -> Result<Option<Command>, clap::Error> {
+		Cli::try_parse_from(std::iter::once("rapid-gossip-sync-server").chain(args.iter().copied())).map(|cli| cli.command)
+	}
+
+	#[test]
+	fn test_cli() {
+		Cli::command().debug_assert();
+	}
+
+	#[test]
+	fn test_parse_command() {
+		assert_eq!(parse(&[]).unwrap(), None);
+		assert_eq!(parse(&["serve"]).unwrap(), Some(Command::Serve));
+		assert_eq!(parse(&["snapshot-once"]).unwrap(), Some(Command::SnapshotOnce));
+		assert_eq!(parse(&["export-graph"]).unwrap(), Some(Command::ExportGraph { format: GraphExportFormat::Json, output: None }));
+		assert_eq!(parse(&["export-graph", "--output", "graph.csv", "--format", "channels-csv"]).unwrap(),
+			Some(Command::ExportGraph { format: GraphExportFormat::ChannelsCsv, output: Some("graph.csv".to_string()) }));
+		assert!(parse(&["export-graph", "--format"]).is_err());
+		assert!(parse(&["export-graph", "--format", "xml"]).is_err());
+		assert_eq!(parse(&["replay", "recordings"]).unwrap(), Some(Command::Replay { recording: "recordings".to_string(), speed: None, utxo_set: None }));
+		assert_eq!(parse(&["replay", "gossip.log", "--speed", "2.5", "--utxo-set", "utxos.txt"]).unwrap(),
+			Some(Command::Replay { recording: "gossip.log".to_string(), speed: Some(2.5), utxo_set: Some("utxos.txt".to_string()) }));
+		assert!(parse(&["replay"]).is_err());
+		assert!(parse(&["replay", "gossip.log", "--speed", "0"]).is_err());
+		assert!(parse(&["migrate", "now"]).is_err());
+		assert_eq!(parse(&["inspect", "full.lngossip"]).unwrap(), Some(Command::Inspect { snapshot: "full.lngossip".to_string() }));
+		assert!(parse(&["inspect"]).is_err());
+		assert_eq!(parse(&["diff", "a.lngossip", "b.lngossip.gz"]).unwrap(), Some(Command::Diff { a: "a.lngossip".to_string(), b: "b.lngossip.gz".to_string() }));
+		assert!(parse(&["diff", "a.lngossip"]).is_err());
+		assert!(parse(&["diff", "a.lngossip", "b.lngossip", "c.lngossip"]).is_err());
+		assert!(parse(&["vacuum"]).is_err());
+	}
 }
@@ -0,0 +1,85 @@
+use bitcoin::hashes::{sha256, Hash};
+use serde::Serialize;
+
+use crate::SerializedResponse;
+
+/// Describes a set of generated snapshots, persisted as `manifest.json` next to the symlinks so
+/// that mirrors and monitoring can validate them without parsing the binary format.
+#[derive(Serialize)]
+pub(crate) struct SnapshotManifest {
+	pub(crate) network: String,
+	pub(crate) chain_hash: String,
+	/// When the snapshot generation finished
+	pub(crate) generated_at: u64,
+	/// The timestamp the snapshots were calculated for, rounded down to the snapshot interval
+	pub(crate) reference_timestamp: u64,
+	pub(crate) snapshots: Vec<ManifestSnapshot>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ManifestSnapshot {
+	/// The snapshot's path relative to the cache directory
+	pub(crate) file: String,
+	pub(crate) serialization_version: u8,
+	/// The range of the snapshot in seconds, absent for the empty dummy snapshot
+	pub(crate) scope: Option<u64>,
+	pub(crate) last_sync_timestamp: u64,
+	pub(crate) message_count: u32,
+	pub(crate) channel_announcement_count: u32,
+	pub(crate) update_count: u32,
+	pub(crate) update_count_full: u32,
+	pub(crate) update_count_incremental: u32,
+	pub(crate) node_announcement_count: u32,
+	pub(crate) size: u64,
+	pub(crate) sha256: String,
+	pub(crate) compressed_variants: Vec<ManifestFileVariant>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ManifestFileVariant {
+	pub(crate) content_encoding: &'static str,
+	pub(crate) file: String,
+	pub(crate) size: u64,
+	pub(crate) sha256: String,
+}
+
+impl ManifestSnapshot {
+	pub(crate) fn new(file: String, serialization_version: u8, scope: Option<u64>, last_sync_timestamp: u64, data: &[u8], snapshot: Option<&SerializedResponse>) -> Self {
+		Self {
+			file,
+			serialization_version,
+			scope,
+			last_sync_timestamp,
+			message_count: snapshot.map_or(0, |s| s.message_count),
+			channel_announcement_count: snapshot.map_or(0, |s| s.channel_announcement_count),
+			update_count: snapshot.map_or(0, |s| s.update_count),
+			update_count_full: snapshot.map_or(0, |s| s.update_count_full),
+			update_count_incremental: snapshot.map_or(0, |s| s.update_count_incremental),
+			node_announcement_count: snapshot.map_or(0, |s| s.node_announcement_count),
+			size: data.len() as u64,
+			sha256: sha256_hex(data),
+			compressed_variants: Vec::new(),
+		}
+	}
+}
+
+pub(crate) fn sha256_hex(data: &[u8]) -> String {
+	sha256::Hash::hash(data).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::manifest::{sha256_hex, ManifestSnapshot};
+
+	#[test]
+	fn test_manifest_snapshot_serialization() {
+		assert_eq!(sha256_hex(b""), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+
+		let snapshot = ManifestSnapshot::new("snapshots/empty_delta.lngossip".to_string(), 1, None, 1700000000, &[1, 2, 3], None);
+		let json = serde_json::to_value(&snapshot).unwrap();
+		assert_eq!(json["size"], 3);
+		assert_eq!(json["scope"], serde_json::Value::Null);
+		assert_eq!(json["message_count"], 0);
+		assert_eq!(json["sha256"], "039058c6f2c0cb492c533b0a4d14ef77cc0f78abccced5287d84a1a2011cfb81");
+	}
+}
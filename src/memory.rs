@@ -0,0 +1,92 @@
+//! Rough estimates of the memory taken up by the network graph and the caches alongside it, as
+//! reported under `/metrics` for sizing hosts and choosing a memory profile. They account for the
+//! entries' own sizes and the hash tables holding them, but not for the allocator's overhead.
+
+use std::mem::{size_of, size_of_val};
+use std::ops::Deref;
+use std::sync::Arc;
+
+use lightning::routing::gossip::{ChannelInfo, NetworkGraph, NodeAnnouncementInfo, NodeId, NodeInfo};
+use lightning::util::logger::Logger;
+
+use crate::config;
+use crate::metrics::Metrics;
+use crate::verifier::FundingAmountCache;
+
+/// The size of a hash map with room for `capacity` entries, each of which takes up a control byte
+/// besides the entry itself
+pub(crate) fn hash_map_size<K, V>(capacity: usize) -> u64 {
+	(capacity * (size_of::<(K, V)>() + 1)) as u64
+}
+
+/// The size of the network graph's channels and nodes, including the heap allocations of the
+/// gossip they retain
+pub(crate) fn network_graph_size<L: Deref>(network_graph: &NetworkGraph<L>) -> u64 where L::Target: Logger {
+	let graph = network_graph.read_only();
+	let channels = graph.channels();
+	// the channels and nodes are also indexed by a sorted list of their keys
+	let mut size = hash_map_size::<u64, ChannelInfo>(channels.len()) + (channels.len() * size_of::<u64>()) as u64;
+	for (_, channel) in channels.unordered_iter() {
+		size += channel.features.le_flags().len() as u64;
+		if let Some(announcement) = &channel.announcement_message {
+			size += (announcement.contents.features.le_flags().len() + announcement.contents.excess_data.len()) as u64;
+		}
+		for update in [&channel.one_to_two, &channel.two_to_one].into_iter().flatten() {
+			size += update.last_update_message.as_ref().map_or(0, |message| message.contents.excess_data.len()) as u64;
+		}
+	}
+
+	let nodes = graph.nodes();
+	size += hash_map_size::<NodeId, NodeInfo>(nodes.len()) + (nodes.len() * size_of::<NodeId>()) as u64;
+	for (_, node) in nodes.unordered_iter() {
+		size += (node.channels.capacity() * size_of::<u64>()) as u64;
+		let announcement_info = match &node.announcement_info {
+			Some(announcement_info) => announcement_info,
+			None => continue,
+		};
+		size += (announcement_info.features().le_flags().len() + size_of_val(announcement_info.addresses())) as u64;
+		if let NodeAnnouncementInfo::Relayed(announcement) = announcement_info {
+			size += (announcement.contents.excess_address_data.len() + announcement.contents.excess_data.len()) as u64;
+		}
+	}
+	size
+}
+
+/// Periodically report the memory estimated to be taken up by the network graph and the funding
+/// amount cache, until the process exits
+pub(crate) async fn report_memory_usage<L: Deref>(network_graph: Arc<NetworkGraph<L>>, funding_amounts: FundingAmountCache, metrics: Arc<Metrics>) where L::Target: Logger {
+	let mut interval = tokio::time::interval(config::MEMORY_ESTIMATE_INTERVAL);
+	loop {
+		interval.tick().await;
+		metrics.set_memory_estimate("network_graph", network_graph_size(&network_graph));
+		let funding_amount_capacity = funding_amounts.lock().unwrap().capacity();
+		metrics.set_memory_estimate("funding_amounts", hash_map_size::<u64, u64>(funding_amount_capacity));
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use bitcoin::Network;
+	use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+	use lightning::routing::gossip::NetworkGraph;
+	use lightning::types::features::ChannelFeatures;
+	use std::sync::Arc;
+
+	use crate::memory::network_graph_size;
+	use crate::types::tests::TestLogger;
+
+	#[test]
+	fn test_network_graph_size() {
+		let logger = Arc::new(TestLogger::with_id("memory".to_string()));
+		let network_graph = NetworkGraph::new(Network::Bitcoin, logger);
+		assert_eq!(network_graph_size(&network_graph), 0);
+
+		let secp_ctx = Secp256k1::new();
+		let node_id = |byte: u8| PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[byte; 32]).unwrap());
+		network_graph.add_channel_from_partial_announcement(42, 1_700_000_000, ChannelFeatures::empty(), node_id(1), node_id(2)).unwrap();
+		let size = network_graph_size(&network_graph);
+		assert!(size > 0);
+		network_graph.add_channel_from_partial_announcement(43, 1_700_000_000, ChannelFeatures::empty(), node_id(2), node_id(3)).unwrap();
+		assert!(network_graph_size(&network_graph) > size);
+	}
+}
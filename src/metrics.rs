@@ -0,0 +1,413 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use bitcoin::secp256k1::PublicKey;
+use bitcoin::ScriptBuf;
+use serde::Serialize;
+
+use crate::config;
+use crate::recording::{CHANNEL_ANNOUNCEMENT_TYPE, CHANNEL_UPDATE_TYPE, NODE_ANNOUNCEMENT_TYPE};
+
+/// The gossip received from a single peer since startup
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct PeerGossipStats {
+	pub channel_announcements: u64,
+	pub channel_updates: u64,
+	pub node_announcements: u64,
+	/// Messages that were new to the network graph, as opposed to ones already received from other
+	/// peers, outdated ones, or invalid ones
+	pub new_messages: u64,
+	/// Messages rejected as invalid, e. g. for bad signatures
+	pub invalid_messages: u64,
+	/// The size of the received messages on the wire
+	pub bytes: u64,
+	/// Channel announcements whose bitcoin keys didn't match their channel's funding output
+	pub funding_script_mismatches: u64,
+}
+
+/// A channel announcement whose bitcoin keys didn't match the funding output of its channel
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct FundingScriptMismatch {
+	pub(crate) short_channel_id: u64,
+	/// The peer the announcement was received from, if known
+	pub(crate) peer: Option<PublicKey>,
+	/// The funding output script implied by the announced bitcoin keys
+	pub(crate) announced_script: ScriptBuf,
+	/// The script of the funding output in the chain
+	pub(crate) funding_script: ScriptBuf,
+	pub(crate) detected_at: u64,
+}
+
+/// The size and contents of a snapshot of one round
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub(crate) struct SnapshotRecord {
+	pub(crate) reference_timestamp: u64,
+	pub(crate) size: u64,
+	pub(crate) channel_announcement_count: u32,
+	pub(crate) update_count: u32,
+}
+
+/// Identifies the snapshots whose sizes are comparable from one round to the next: those of the
+/// same profile, if any, scope, and serialization version
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct SnapshotSeries {
+	pub(crate) profile: Option<String>,
+	pub(crate) scope: u64,
+	pub(crate) serialization_version: u8,
+}
+
+/// The value of a per-scope snapshot gauge
+type SnapshotGaugeValue = fn(&SnapshotRecord) -> u64;
+
+/// The values of a per-peer counter, labeled by message type if it's broken down by type
+type PeerCounterValues = fn(&PeerGossipStats) -> Vec<(Option<&'static str>, u64)>;
+
+/// How a gossip message received from a peer was handled
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum GossipOutcome {
+	New,
+	/// Already known, outdated, or still being verified
+	Ignored,
+	Invalid,
+}
+
+/// Counters describing the gossip pipeline, served in the Prometheus text format under `/metrics`.
+pub(crate) struct Metrics {
+	/// Attempts to reconnect to a peer after a disconnection or failed connection attempt
+	peer_reconnect_attempts: AtomicU64,
+	peer_reconnect_successes: AtomicU64,
+	peer_reconnect_failures: AtomicU64,
+	/// Peers given up on after exhausting the configured reconnection attempts
+	peers_abandoned: AtomicU64,
+	/// Gossip messages whose persistence held up gossip processing, as the queue was full
+	persistence_deferred: AtomicU64,
+	/// Redundant channel updates dropped rather than persisted, as the queue was full
+	persistence_dropped: AtomicU64,
+	/// Accepted gossip messages left unrecorded, as the recording had fallen behind
+	recording_dropped: AtomicU64,
+	/// Gossip messages rejected for concerning a blocklisted node or channel
+	blocklisted: AtomicU64,
+	/// Channel announcements whose bitcoin keys didn't match their channel's funding output
+	funding_script_mismatches: AtomicU64,
+	/// HTTP requests turned away with a 429 for exceeding the rate or concurrency limits
+	http_requests_throttled: AtomicU64,
+	/// Gossip messages waiting to be handed to the persister, which blocks gossip processing once
+	/// its queue is full
+	persistence_queue_depth: AtomicU64,
+	/// The height of the chain source's tip as of the latest check
+	chain_tip_height: AtomicU64,
+	chain_tip_age_secs: AtomicU64,
+	/// How many blocks the chain source's tip is estimated to be behind the network's
+	chain_tip_lag_blocks: AtomicU64,
+	/// 1 while this instance leads the instances sharing its database, or if there's no election
+	is_leader: AtomicU64,
+	peer_gossip: Mutex<HashMap<PublicKey, PeerGossipStats>>,
+	/// The latest [`config::MAX_REPORTED_FUNDING_SCRIPT_MISMATCHES`] funding script mismatches
+	recent_funding_script_mismatches: Mutex<VecDeque<FundingScriptMismatch>>,
+	/// The latest [`config::SNAPSHOT_HISTORY_LENGTH`] rounds of each series of snapshots
+	snapshot_history: Mutex<BTreeMap<SnapshotSeries, VecDeque<SnapshotRecord>>>,
+	/// The memory estimated to be taken up by the network graph and each of the caches
+	memory_estimates: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+impl Metrics {
+	pub(crate) fn new() -> Self {
+		Self {
+			peer_reconnect_attempts: AtomicU64::new(0),
+			peer_reconnect_successes: AtomicU64::new(0),
+			peer_reconnect_failures: AtomicU64::new(0),
+			peers_abandoned: AtomicU64::new(0),
+			persistence_deferred: AtomicU64::new(0),
+			persistence_dropped: AtomicU64::new(0),
+			recording_dropped: AtomicU64::new(0),
+			blocklisted: AtomicU64::new(0),
+			funding_script_mismatches: AtomicU64::new(0),
+			http_requests_throttled: AtomicU64::new(0),
+			persistence_queue_depth: AtomicU64::new(0),
+			chain_tip_height: AtomicU64::new(0),
+			chain_tip_age_secs: AtomicU64::new(0),
+			chain_tip_lag_blocks: AtomicU64::new(0),
+			is_leader: AtomicU64::new(1),
+			peer_gossip: Mutex::new(HashMap::new()),
+			recent_funding_script_mismatches: Mutex::new(VecDeque::new()),
+			snapshot_history: Mutex::new(BTreeMap::new()),
+			memory_estimates: Mutex::new(BTreeMap::new()),
+		}
+	}
+
+	pub(crate) fn record_reconnect_attempt(&self, succeeded: bool) {
+		self.peer_reconnect_attempts.fetch_add(1, Ordering::Relaxed);
+		if succeeded {
+			self.peer_reconnect_successes.fetch_add(1, Ordering::Relaxed);
+		} else {
+			self.peer_reconnect_failures.fetch_add(1, Ordering::Relaxed);
+		}
+	}
+
+	pub(crate) fn record_peer_abandoned(&self) {
+		self.peers_abandoned.fetch_add(1, Ordering::Relaxed);
+	}
+
+	pub(crate) fn record_persistence_deferred(&self) {
+		self.persistence_deferred.fetch_add(1, Ordering::Relaxed);
+	}
+
+	pub(crate) fn record_persistence_dropped(&self) {
+		self.persistence_dropped.fetch_add(1, Ordering::Relaxed);
+	}
+
+	pub(crate) fn record_recording_dropped(&self) {
+		self.recording_dropped.fetch_add(1, Ordering::Relaxed);
+	}
+
+	pub(crate) fn record_blocklisted(&self) {
+		self.blocklisted.fetch_add(1, Ordering::Relaxed);
+	}
+
+	pub(crate) fn record_http_request_throttled(&self) {
+		self.http_requests_throttled.fetch_add(1, Ordering::Relaxed);
+	}
+
+	pub(crate) fn record_funding_script_mismatch(&self, mismatch: FundingScriptMismatch) {
+		self.funding_script_mismatches.fetch_add(1, Ordering::Relaxed);
+		if let Some(peer) = mismatch.peer {
+			self.peer_gossip.lock().unwrap().entry(peer).or_default().funding_script_mismatches += 1;
+		}
+		let mut recent_mismatches = self.recent_funding_script_mismatches.lock().unwrap();
+		if recent_mismatches.len() >= config::MAX_REPORTED_FUNDING_SCRIPT_MISMATCHES {
+			recent_mismatches.pop_front();
+		}
+		recent_mismatches.push_back(mismatch);
+	}
+
+	pub(crate) fn funding_script_mismatch_count(&self) -> u64 {
+		self.funding_script_mismatches.load(Ordering::Relaxed)
+	}
+
+	/// The most recent funding script mismatches, oldest first
+	pub(crate) fn recent_funding_script_mismatches(&self) -> Vec<FundingScriptMismatch> {
+		self.recent_funding_script_mismatches.lock().unwrap().iter().cloned().collect()
+	}
+
+	/// Record a round's snapshot of `series`, returning the previous round's for comparison
+	pub(crate) fn record_snapshot(&self, series: SnapshotSeries, record: SnapshotRecord) -> Option<SnapshotRecord> {
+		let mut snapshot_history = self.snapshot_history.lock().unwrap();
+		let history = snapshot_history.entry(series).or_default();
+		let previous_record = history.back().cloned();
+		if history.len() >= config::SNAPSHOT_HISTORY_LENGTH {
+			history.pop_front();
+		}
+		history.push_back(record);
+		previous_record
+	}
+
+	/// The recent rounds of each series of snapshots, oldest first
+	pub(crate) fn snapshot_history(&self) -> Vec<(SnapshotSeries, Vec<SnapshotRecord>)> {
+		self.snapshot_history.lock().unwrap().iter().map(|(series, history)| (series.clone(), history.iter().cloned().collect())).collect()
+	}
+
+	pub(crate) fn set_persistence_queue_depth(&self, depth: usize) {
+		self.persistence_queue_depth.store(depth as u64, Ordering::Relaxed);
+	}
+
+	pub(crate) fn set_chain_tip(&self, height: u32, age_secs: u64, lag_blocks: u32) {
+		self.chain_tip_height.store(height as u64, Ordering::Relaxed);
+		self.chain_tip_age_secs.store(age_secs, Ordering::Relaxed);
+		self.chain_tip_lag_blocks.store(lag_blocks as u64, Ordering::Relaxed);
+	}
+
+	pub(crate) fn set_leader(&self, is_leader: bool) {
+		self.is_leader.store(is_leader as u64, Ordering::Relaxed);
+	}
+
+	pub(crate) fn set_memory_estimate(&self, component: &'static str, bytes: u64) {
+		self.memory_estimates.lock().unwrap().insert(component, bytes);
+	}
+
+	pub(crate) fn record_peer_gossip(&self, node_id: PublicKey, message_type: u16, size: usize, outcome: GossipOutcome) {
+		let mut peer_gossip = self.peer_gossip.lock().unwrap();
+		let stats = peer_gossip.entry(node_id).or_default();
+		match message_type {
+			CHANNEL_ANNOUNCEMENT_TYPE => stats.channel_announcements += 1,
+			NODE_ANNOUNCEMENT_TYPE => stats.node_announcements += 1,
+			CHANNEL_UPDATE_TYPE => stats.channel_updates += 1,
+			_ => {}
+		}
+		match outcome {
+			GossipOutcome::New => stats.new_messages += 1,
+			GossipOutcome::Ignored => {}
+			GossipOutcome::Invalid => stats.invalid_messages += 1,
+		}
+		stats.bytes += size as u64;
+	}
+
+	/// Credit a peer with a message that was only found to be new once verified, such as a channel
+	/// announcement whose funding output had to be looked up first
+	pub(crate) fn record_peer_gossip_accepted(&self, node_id: PublicKey) {
+		self.peer_gossip.lock().unwrap().entry(node_id).or_default().new_messages += 1;
+	}
+
+	/// The gossip received from each peer, ordered by node id
+	pub(crate) fn peer_gossip_stats(&self) -> Vec<(PublicKey, PeerGossipStats)> {
+		let mut stats: Vec<_> = self.peer_gossip.lock().unwrap().iter().map(|(node_id, stats)| (*node_id, stats.clone())).collect();
+		stats.sort_unstable_by_key(|(node_id, _)| *node_id);
+		stats
+	}
+
+	pub(crate) fn render(&self) -> String {
+		let counters = [
+			("rgs_peer_reconnect_attempts_total", "Attempts to reconnect to gossip peers", &self.peer_reconnect_attempts),
+			("rgs_peer_reconnect_successes_total", "Successful reconnections to gossip peers", &self.peer_reconnect_successes),
+			("rgs_peer_reconnect_failures_total", "Failed attempts to reconnect to gossip peers", &self.peer_reconnect_failures),
+			("rgs_peers_abandoned_total", "Gossip peers given up on after exhausting the reconnection attempts", &self.peers_abandoned),
+			("rgs_persistence_deferred_total", "Gossip messages that held up gossip processing while the persistence queue was full", &self.persistence_deferred),
+			("rgs_persistence_dropped_total", "Redundant channel updates dropped while the persistence queue was full", &self.persistence_dropped),
+			("rgs_recording_dropped_total", "Accepted gossip messages left unrecorded while the recording queue was full", &self.recording_dropped),
+			("rgs_blocklisted_messages_total", "Gossip messages rejected for concerning a blocklisted node or channel", &self.blocklisted),
+			("rgs_funding_script_mismatches_total", "Channel announcements whose bitcoin keys didn't match the funding output", &self.funding_script_mismatches),
+			("rgs_http_requests_throttled_total", "HTTP requests rejected for exceeding the rate or concurrency limits", &self.http_requests_throttled),
+		];
+		let gauges = [
+			("rgs_persistence_queue_depth", "Gossip messages waiting to be persisted", &self.persistence_queue_depth),
+			("rgs_chain_tip_height", "Height of the chain source's tip", &self.chain_tip_height),
+			("rgs_chain_tip_age_seconds", "Age of the chain source's tip block", &self.chain_tip_age_secs),
+			("rgs_chain_tip_lag_blocks", "Estimated number of blocks the chain source is behind", &self.chain_tip_lag_blocks),
+			("rgs_leader", "Whether this instance is the leader among those sharing its database", &self.is_leader),
+		];
+		let mut output = String::new();
+		for (metric_type, metrics) in [("counter", &counters[..]), ("gauge", &gauges[..])] {
+			for (name, help, value) in metrics {
+				writeln!(output, "# HELP {} {}", name, help).unwrap();
+				writeln!(output, "# TYPE {} {}", name, metric_type).unwrap();
+				writeln!(output, "{} {}", name, value.load(Ordering::Relaxed)).unwrap();
+			}
+		}
+
+		writeln!(output, "# HELP rgs_estimated_memory_bytes Estimated memory taken up by the network graph and each of the caches").unwrap();
+		writeln!(output, "# TYPE rgs_estimated_memory_bytes gauge").unwrap();
+		for (component, bytes) in self.memory_estimates.lock().unwrap().iter() {
+			writeln!(output, "rgs_estimated_memory_bytes{{component=\"{}\"}} {}", component, bytes).unwrap();
+		}
+
+		let snapshot_history = self.snapshot_history();
+		let snapshot_gauges: [(&str, &str, SnapshotGaugeValue); 3] = [
+			("rgs_snapshot_size_bytes", "Size of the latest snapshot of each scope", |record| record.size),
+			("rgs_snapshot_channel_announcements", "Channel announcements in the latest snapshot of each scope", |record| record.channel_announcement_count as u64),
+			("rgs_snapshot_channel_updates", "Channel updates in the latest snapshot of each scope", |record| record.update_count as u64),
+		];
+		for (name, help, value) in snapshot_gauges {
+			writeln!(output, "# HELP {} {}", name, help).unwrap();
+			writeln!(output, "# TYPE {} gauge", name).unwrap();
+			for (series, history) in &snapshot_history {
+				let latest_record = match history.last() {
+					Some(latest_record) => latest_record,
+					None => continue,
+				};
+				let profile_label = series.profile.as_ref().map_or(String::new(), |profile| format!("profile=\"{}\",", profile));
+				writeln!(output, "{}{{{}scope=\"{}\",version=\"{}\"}} {}", name, profile_label, series.scope, series.serialization_version, value(latest_record)).unwrap();
+			}
+		}
+
+		let peer_gossip = self.peer_gossip_stats();
+		let peer_counters: [(&str, &str, PeerCounterValues); 5] = [
+			("rgs_peer_gossip_messages_total", "Gossip messages received from each peer, by type", |stats| vec![
+				(Some("channel_announcement"), stats.channel_announcements),
+				(Some("channel_update"), stats.channel_updates),
+				(Some("node_announcement"), stats.node_announcements),
+			]),
+			("rgs_peer_gossip_new_messages_total", "Gossip messages received from each peer that were new to the network graph", |stats| vec![(None, stats.new_messages)]),
+			("rgs_peer_gossip_invalid_messages_total", "Invalid gossip messages received from each peer", |stats| vec![(None, stats.invalid_messages)]),
+			("rgs_peer_gossip_bytes_total", "Size of the gossip messages received from each peer", |stats| vec![(None, stats.bytes)]),
+			("rgs_peer_funding_script_mismatches_total", "Channel announcements received from each peer whose bitcoin keys didn't match the funding output", |stats| vec![(None, stats.funding_script_mismatches)]),
+		];
+		for (name, help, values) in peer_counters {
+			writeln!(output, "# HELP {} {}", name, help).unwrap();
+			writeln!(output, "# TYPE {} counter", name).unwrap();
+			for (node_id, stats) in &peer_gossip {
+				for (message_type, value) in values(stats) {
+					match message_type {
+						Some(message_type) => writeln!(output, "{}{{peer=\"{}\",type=\"{}\"}} {}", name, node_id, message_type, value).unwrap(),
+						None => writeln!(output, "{}{{peer=\"{}\"}} {}", name, node_id, value).unwrap(),
+					}
+				}
+			}
+		}
+		output
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+
+	use crate::metrics::{GossipOutcome, Metrics, SnapshotRecord, SnapshotSeries};
+	use crate::recording::{CHANNEL_ANNOUNCEMENT_TYPE, CHANNEL_UPDATE_TYPE};
+
+	#[test]
+	fn test_render_metrics() {
+		let metrics = Metrics::new();
+		metrics.record_reconnect_attempt(false);
+		metrics.record_reconnect_attempt(true);
+		let output = metrics.render();
+		assert!(output.contains("# TYPE rgs_peer_reconnect_attempts_total counter\nrgs_peer_reconnect_attempts_total 2\n"));
+		assert!(output.contains("\nrgs_peer_reconnect_successes_total 1\n"));
+		assert!(output.contains("\nrgs_peer_reconnect_failures_total 1\n"));
+		assert!(output.contains("\nrgs_peers_abandoned_total 0\n"));
+
+		metrics.record_persistence_deferred();
+		metrics.record_persistence_dropped();
+		metrics.record_persistence_dropped();
+		let output = metrics.render();
+		assert!(output.contains("\nrgs_persistence_deferred_total 1\n"));
+		assert!(output.contains("\nrgs_persistence_dropped_total 2\n"));
+
+		metrics.record_recording_dropped();
+		assert!(metrics.render().contains("\nrgs_recording_dropped_total 1\n"));
+
+		metrics.record_blocklisted();
+		assert!(metrics.render().contains("\nrgs_blocklisted_messages_total 1\n"));
+
+		metrics.set_persistence_queue_depth(42);
+		assert!(metrics.render().contains("# TYPE rgs_persistence_queue_depth gauge\nrgs_persistence_queue_depth 42\n"));
+
+		metrics.set_chain_tip(870000, 4200, 7);
+		let output = metrics.render();
+		assert!(output.contains("# TYPE rgs_chain_tip_height gauge\nrgs_chain_tip_height 870000\n"));
+		assert!(output.contains("\nrgs_chain_tip_age_seconds 4200\n"));
+		assert!(output.contains("\nrgs_chain_tip_lag_blocks 7\n"));
+
+		assert!(output.contains("# TYPE rgs_leader gauge\nrgs_leader 1\n"));
+		metrics.set_leader(false);
+		assert!(metrics.render().contains("\nrgs_leader 0\n"));
+
+		metrics.set_memory_estimate("network_graph", 1000);
+		metrics.set_memory_estimate("funding_amounts", 200);
+		metrics.set_memory_estimate("network_graph", 1500);
+		assert!(metrics.render().contains("# TYPE rgs_estimated_memory_bytes gauge\nrgs_estimated_memory_bytes{component=\"funding_amounts\"} 200\nrgs_estimated_memory_bytes{component=\"network_graph\"} 1500\n"));
+
+		let peer = PublicKey::from_secret_key(&Secp256k1::new(), &SecretKey::from_slice(&[1; 32]).unwrap());
+		metrics.record_peer_gossip(peer, CHANNEL_ANNOUNCEMENT_TYPE, 432, GossipOutcome::Ignored);
+		metrics.record_peer_gossip_accepted(peer);
+		metrics.record_peer_gossip(peer, CHANNEL_UPDATE_TYPE, 138, GossipOutcome::New);
+		metrics.record_peer_gossip(peer, CHANNEL_UPDATE_TYPE, 138, GossipOutcome::Invalid);
+		let stats = &metrics.peer_gossip_stats()[0].1;
+		assert_eq!((stats.channel_announcements, stats.channel_updates, stats.node_announcements), (1, 2, 0));
+		assert_eq!((stats.new_messages, stats.invalid_messages, stats.bytes), (2, 1, 708));
+		let output = metrics.render();
+		assert!(output.contains(&format!("\nrgs_peer_gossip_messages_total{{peer=\"{}\",type=\"channel_update\"}} 2\n", peer)));
+		assert!(output.contains(&format!("\nrgs_peer_gossip_invalid_messages_total{{peer=\"{}\"}} 1\n", peer)));
+		assert!(output.contains(&format!("\nrgs_peer_gossip_bytes_total{{peer=\"{}\"}} 708\n", peer)));
+
+		let series = SnapshotSeries { profile: None, scope: 86400, serialization_version: 2 };
+		let record = |reference_timestamp: u64, size: u64| SnapshotRecord { reference_timestamp, size, channel_announcement_count: 10, update_count: 20 };
+		assert_eq!(metrics.record_snapshot(series.clone(), record(1000, 500)), None);
+		assert_eq!(metrics.record_snapshot(series.clone(), record(2000, 800)), Some(record(1000, 500)));
+		metrics.record_snapshot(SnapshotSeries { profile: Some("mobile".to_string()), ..series.clone() }, record(2000, 300));
+		let output = metrics.render();
+		assert!(output.contains("# TYPE rgs_snapshot_size_bytes gauge\nrgs_snapshot_size_bytes{scope=\"86400\",version=\"2\"} 800\n"));
+		assert!(output.contains("\nrgs_snapshot_size_bytes{profile=\"mobile\",scope=\"86400\",version=\"2\"} 300\n"));
+		assert_eq!(metrics.snapshot_history()[0], (series, vec![record(1000, 500), record(2000, 800)]));
+	}
+}
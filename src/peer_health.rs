@@ -0,0 +1,157 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use bitcoin::secp256k1::PublicKey;
+
+/// How far back disconnections are counted towards a peer flapping
+pub(crate) const FLAPPING_WINDOW: Duration = Duration::from_secs(3600);
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum PeerHealth {
+	Healthy,
+	/// Connected, but hasn't sent any gossip within the silence timeout
+	Silent,
+	/// Disconnected too often within the [`FLAPPING_WINDOW`]
+	Flapping,
+	/// Given up on after exhausting the reconnection attempts
+	Unreachable,
+}
+
+struct PeerActivity {
+	connected_since: Option<Instant>,
+	last_message: Option<Instant>,
+	message_count: u64,
+	/// The message count as of the previous call to [`PeerHealthTracker::message_rates`]
+	reported_message_count: u64,
+	disconnections: VecDeque<Instant>,
+	abandoned: bool,
+}
+
+/// Tracks the gossip received from and the connection history of each peer, so that peers which
+/// have gone silent or keep disconnecting can be replaced.
+pub(crate) struct PeerHealthTracker {
+	peers: Mutex<HashMap<PublicKey, PeerActivity>>,
+}
+
+impl PeerHealthTracker {
+	pub(crate) fn new() -> Self {
+		Self { peers: Mutex::new(HashMap::new()) }
+	}
+
+	fn with_activity<F: FnOnce(&mut PeerActivity)>(&self, node_id: PublicKey, f: F) {
+		let mut peers = self.peers.lock().unwrap();
+		let activity = peers.entry(node_id).or_insert_with(|| PeerActivity {
+			connected_since: None,
+			last_message: None,
+			message_count: 0,
+			reported_message_count: 0,
+			disconnections: VecDeque::new(),
+			abandoned: false,
+		});
+		f(activity);
+	}
+
+	pub(crate) fn record_message(&self, node_id: PublicKey) {
+		self.with_activity(node_id, |activity| {
+			activity.last_message = Some(Instant::now());
+			activity.message_count += 1;
+		});
+	}
+
+	pub(crate) fn record_connection(&self, node_id: PublicKey) {
+		self.with_activity(node_id, |activity| {
+			activity.connected_since = Some(Instant::now());
+			activity.abandoned = false;
+		});
+	}
+
+	pub(crate) fn record_disconnection(&self, node_id: PublicKey) {
+		self.with_activity(node_id, |activity| {
+			activity.connected_since = None;
+			activity.disconnections.push_back(Instant::now());
+		});
+	}
+
+	pub(crate) fn record_abandonment(&self, node_id: PublicKey) {
+		self.with_activity(node_id, |activity| activity.abandoned = true);
+	}
+
+	/// Forget a peer we no longer connect to
+	pub(crate) fn remove(&self, node_id: &PublicKey) {
+		self.peers.lock().unwrap().remove(node_id);
+	}
+
+	pub(crate) fn health(&self, node_id: &PublicKey, silence_timeout: Duration, max_disconnections: usize) -> PeerHealth {
+		let mut peers = self.peers.lock().unwrap();
+		let activity = match peers.get_mut(node_id) {
+			Some(activity) => activity,
+			None => return PeerHealth::Healthy,
+		};
+		if activity.abandoned {
+			return PeerHealth::Unreachable;
+		}
+		while activity.disconnections.front().is_some_and(|time| time.elapsed() > FLAPPING_WINDOW) {
+			activity.disconnections.pop_front();
+		}
+		if activity.disconnections.len() > max_disconnections {
+			return PeerHealth::Flapping;
+		}
+		if let Some(connected_since) = activity.connected_since {
+			let last_activity = activity.last_message.map_or(connected_since, |last_message| last_message.max(connected_since));
+			if last_activity.elapsed() > silence_timeout {
+				return PeerHealth::Silent;
+			}
+		}
+		PeerHealth::Healthy
+	}
+
+	/// The number of messages received from each peer since the previous call
+	pub(crate) fn message_rates(&self) -> Vec<(PublicKey, u64)> {
+		let mut peers = self.peers.lock().unwrap();
+		peers.iter_mut().map(|(node_id, activity)| {
+			let new_messages = activity.message_count - activity.reported_message_count;
+			activity.reported_message_count = activity.message_count;
+			(*node_id, new_messages)
+		}).collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+
+	use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+
+	use crate::peer_health::{PeerHealth, PeerHealthTracker};
+
+	#[test]
+	fn test_peer_health() {
+		let secp_ctx = Secp256k1::new();
+		let node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[42; 32]).unwrap());
+		let tracker = PeerHealthTracker::new();
+		assert_eq!(tracker.health(&node_id, Duration::ZERO, 1), PeerHealth::Healthy);
+
+		tracker.record_connection(node_id);
+		std::thread::sleep(Duration::from_millis(10));
+		assert_eq!(tracker.health(&node_id, Duration::from_millis(5), 1), PeerHealth::Silent);
+		tracker.record_message(node_id);
+		tracker.record_message(node_id);
+		assert_eq!(tracker.health(&node_id, Duration::from_millis(5), 1), PeerHealth::Healthy);
+		assert_eq!(tracker.message_rates(), vec![(node_id, 2)]);
+		assert_eq!(tracker.message_rates(), vec![(node_id, 0)]);
+
+		// disconnected peers aren't silent, but may be flapping
+		tracker.record_disconnection(node_id);
+		std::thread::sleep(Duration::from_millis(10));
+		assert_eq!(tracker.health(&node_id, Duration::from_millis(5), 1), PeerHealth::Healthy);
+		tracker.record_connection(node_id);
+		tracker.record_disconnection(node_id);
+		assert_eq!(tracker.health(&node_id, Duration::from_millis(5), 1), PeerHealth::Flapping);
+
+		tracker.record_abandonment(node_id);
+		assert_eq!(tracker.health(&node_id, Duration::from_millis(5), 1), PeerHealth::Unreachable);
+		tracker.remove(&node_id);
+		assert_eq!(tracker.health(&node_id, Duration::from_millis(5), 1), PeerHealth::Healthy);
+	}
+}
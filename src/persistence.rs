@@ -1,328 +1,515 @@
-use std::fs::OpenOptions;
-use std::io::{BufWriter, Write};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::future::Future;
+use std::io::BufWriter;
 use std::ops::Deref;
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use bitcoin::blockdata::constants::ChainHash;
+use bitcoin::io::Cursor;
+use bitcoin::{Amount, Network, TxOut};
+use futures::StreamExt;
 use lightning::log_info;
+use lightning::ln::chan_utils::make_funding_redeemscript;
+use lightning::ln::msgs::{ChannelAnnouncement, ChannelUpdate, NodeAnnouncement, UnsignedChannelUpdate};
 use lightning::routing::gossip::NetworkGraph;
+use lightning::routing::utxo::{UtxoLookup, UtxoResult};
 use lightning::util::logger::Logger;
-use lightning::util::ser::Writeable;
+use lightning::util::ser::{Readable, Writeable};
 use tokio::runtime::Runtime;
-use tokio::sync::{mpsc, Mutex, Semaphore};
+use tokio::task::JoinHandle;
+use tokio::sync::{mpsc, Semaphore};
+use tracing::{info_span, Instrument};
 
+use crate::changes::{self, ChannelChangeIndex};
 use crate::config;
+use crate::leader::Leadership;
+use crate::memory;
+use crate::metrics::Metrics;
+use crate::storage::{self, GossipStore};
 use crate::types::GossipMessage;
 
-const POSTGRES_INSERT_TIMEOUT: Duration = Duration::from_secs(15);
 const INSERT_PARALELLISM: usize = 16;
 
-pub(crate) struct GossipPersister<L: Deref> where L::Target: Logger {
-	gossip_persistence_receiver: mpsc::Receiver<GossipMessage>,
-	network_graph: Arc<NetworkGraph<L>>,
-	tokio_runtime: Runtime,
-	logger: L
+/// The parameters a channel update sets for its channel direction, i. e. all but its timestamp
+#[derive(PartialEq)]
+struct UpdateParameters {
+	disabled: bool,
+	cltv_expiry_delta: u16,
+	htlc_minimum_msat: u64,
+	htlc_maximum_msat: u64,
+	fee_base_msat: u32,
+	fee_proportional_millionths: u32,
 }
 
-impl<L: Deref + Clone + Send + Sync + 'static> GossipPersister<L> where L::Target: Logger {
-	pub async fn new(network_graph: Arc<NetworkGraph<L>>, logger: L) -> (Self, mpsc::Sender<GossipMessage>) {
-		{ // initialize the database
-			// this client instance is only used once
-			let mut client = crate::connect_to_db().await;
-
-			let initialization = client
-				.execute(config::db_config_table_creation_query(), &[])
-				.await;
-			if let Err(initialization_error) = initialization {
-				panic!("db init error: {}", initialization_error);
-			}
+impl From<&UnsignedChannelUpdate> for UpdateParameters {
+	fn from(update: &UnsignedChannelUpdate) -> Self {
+		Self {
+			disabled: update.channel_flags & 2 == 2,
+			cltv_expiry_delta: update.cltv_expiry_delta,
+			htlc_minimum_msat: update.htlc_minimum_msat,
+			htlc_maximum_msat: update.htlc_maximum_msat,
+			fee_base_msat: update.fee_base_msat,
+			fee_proportional_millionths: update.fee_proportional_millionths,
+		}
+	}
+}
 
-			let cur_schema = client.query("SELECT db_schema FROM config WHERE id = $1", &[&1]).await.unwrap();
-			if !cur_schema.is_empty() {
-				config::upgrade_db(cur_schema[0].get(0), &mut client, logger.clone()).await;
-			}
+/// Tracks the latest persisted update of each channel direction, such that updates not changing
+/// it need only be persisted every so often
+struct UpdateDeduplicator {
+	latest_updates: HashMap<(u64, bool), (UpdateParameters, u32)>,
+	refresh_interval: u32,
+	/// How many channel directions are tracked at most, if bounded
+	capacity: Option<usize>,
+}
 
-			let preparation = client.execute("set time zone UTC", &[]).await;
-			if let Err(preparation_error) = preparation {
-				panic!("db preparation error: {}", preparation_error);
-			}
+impl UpdateDeduplicator {
+	fn new(refresh_interval: Duration, capacity: Option<usize>) -> Self {
+		Self { latest_updates: HashMap::new(), refresh_interval: refresh_interval.as_secs() as u32, capacity }
+	}
 
-			let initialization = client
-				.execute(
-					// TODO: figure out a way to fix the id value without Postgres complaining about
-					// its value not being default
-					"INSERT INTO config (id, db_schema) VALUES ($1, $2) ON CONFLICT (id) DO NOTHING",
-					&[&1, &config::SCHEMA_VERSION]
-				).await;
-			if let Err(initialization_error) = initialization {
-				panic!("db init error: {}", initialization_error);
-			}
+	/// Make room for another channel direction, first forgetting those due for a refresh as of
+	/// `timestamp`, and then a quarter of the remaining ones, whose next updates will be persisted
+	fn make_room(&mut self, timestamp: u32) {
+		let capacity = match self.capacity {
+			Some(capacity) if self.latest_updates.len() >= capacity => capacity,
+			_ => return,
+		};
+		let refresh_interval = self.refresh_interval;
+		self.latest_updates.retain(|_, (_, latest_timestamp)| timestamp < latest_timestamp.saturating_add(refresh_interval));
+		if self.latest_updates.len() >= capacity {
+			let mut evicted_count = 0;
+			self.latest_updates.retain(|_, _| {
+				evicted_count += 1;
+				evicted_count > (capacity + 3) / 4
+			});
+		}
+	}
 
-			let table_creation_queries = [
-				config::db_announcement_table_creation_query(),
-				config::db_channel_update_table_creation_query(),
-				config::db_channel_update_table_creation_query(),
-				config::db_node_announcement_table_creation_query()
-			];
-
-			for current_table_creation_query in table_creation_queries {
-				let initialization = client
-					.execute(current_table_creation_query, &[])
-					.await;
-				if let Err(initialization_error) = initialization {
-					panic!("db init error: {}", initialization_error);
-				}
+	/// An estimate of the memory taken up by the tracked channel directions
+	fn estimated_size(&self) -> u64 {
+		memory::hash_map_size::<(u64, bool), (UpdateParameters, u32)>(self.latest_updates.capacity())
+	}
+
+	/// Whether an update may be skipped, as it has the same parameters as the latest persisted one
+	/// and isn't due to refresh it yet. Updates that aren't skipped are assumed to be persisted.
+	fn is_redundant(&mut self, update: &UnsignedChannelUpdate) -> bool {
+		let key = (update.short_channel_id, update.channel_flags & 1 == 1);
+		let parameters = UpdateParameters::from(update);
+		if let Some((latest_parameters, latest_timestamp)) = self.latest_updates.get(&key) {
+			if *latest_parameters == parameters && update.timestamp < latest_timestamp.saturating_add(self.refresh_interval) {
+				return true;
+			}
+			if update.timestamp <= *latest_timestamp {
+				// an outdated update, which doesn't supersede the latest one
+				return false;
 			}
+		} else {
+			self.make_room(update.timestamp);
+		}
+		self.latest_updates.insert(key, (parameters, update.timestamp));
+		false
+	}
+}
+
+/// Resolves the funding output of a channel from its persisted announcement, which was verified
+/// against the chain before being persisted
+struct PersistedFunding(TxOut);
+
+impl PersistedFunding {
+	fn new(announcement: &ChannelAnnouncement, funding_amount_sats: u64) -> Option<Self> {
+		let bitcoin_key_1 = announcement.contents.bitcoin_key_1.as_pubkey().ok()?;
+		let bitcoin_key_2 = announcement.contents.bitcoin_key_2.as_pubkey().ok()?;
+		Some(Self(TxOut {
+			value: Amount::from_sat(funding_amount_sats),
+			script_pubkey: make_funding_redeemscript(&bitcoin_key_1, &bitcoin_key_2).to_p2wsh(),
+		}))
+	}
+}
 
-			let initialization = client
-				.batch_execute(config::db_index_creation_query())
-				.await;
-			if let Err(initialization_error) = initialization {
-				panic!("db init error: {}", initialization_error);
+impl UtxoLookup for PersistedFunding {
+	fn get_utxo(&self, _chain_hash: &ChainHash, _short_channel_id: u64) -> UtxoResult {
+		UtxoResult::Sync(Ok(self.0.clone()))
+	}
+}
+
+/// Prune the channel updates seen longer than `retention` ago, except for the `keep_latest` most
+/// recent ones of each channel direction, returning how many were removed
+pub(crate) async fn prune_channel_updates<L: Deref>(store: &dyn GossipStore, retention: Duration, keep_latest: u32, logger: &L) -> u64 where L::Target: Logger {
+	let threshold = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().saturating_sub(retention).as_secs() as u32;
+	let start = Instant::now();
+	let pruned = store.prune_channel_updates(threshold, keep_latest).await;
+	log_info!(logger, "Pruned {} channel updates seen before {} in {:?}", pruned, threshold, start.elapsed());
+	pruned
+}
+
+/// The announcement and latest updates of each channel in the network graph, for persisting gossip
+/// that may not have been persisted before
+pub(crate) fn latest_channel_gossip<L: Deref>(network_graph: &NetworkGraph<L>) -> Vec<GossipMessage> where L::Target: Logger {
+	let mut gossip = Vec::new();
+	let graph = network_graph.read_only();
+	for (_, chan) in graph.channels().unordered_iter() {
+		if let Some(announcement) = &chan.announcement_message {
+			if let Some(funding) = chan.capacity_sats {
+				gossip.push(GossipMessage::ChannelAnnouncement(announcement.clone(), funding, None));
 			}
 		}
+		if let Some(update) = chan.one_to_two.as_ref().and_then(|i| i.last_update_message.as_ref()) {
+			gossip.push(GossipMessage::ChannelUpdate(update.clone(), None));
+		}
+		if let Some(update) = chan.two_to_one.as_ref().and_then(|i| i.last_update_message.as_ref()) {
+			gossip.push(GossipMessage::ChannelUpdate(update.clone(), None));
+		}
+	}
+	gossip
+}
+
+pub(crate) struct GossipPersister<L: Deref> where L::Target: Logger {
+	gossip_persistence_receiver: mpsc::Receiver<GossipMessage>,
+	network_graph: Arc<NetworkGraph<L>>,
+	network: Network,
+	store: Arc<dyn GossipStore>,
+	update_deduplicator: Option<UpdateDeduplicator>,
+	/// Only set if the changed channels are tracked for the snapshots
+	channel_changes: Option<Arc<ChannelChangeIndex>>,
+	/// Only set if gossip is only persisted while leading the instances sharing the database
+	leadership: Option<Arc<Leadership>>,
+	/// Only set if the memory taken up by the update deduplication is reported
+	metrics: Option<Arc<Metrics>>,
+	tokio_runtime: Runtime,
+	logger: L
+}
+
+impl<L: Deref + Clone + Send + Sync + 'static> GossipPersister<L> where L::Target: Logger {
+	pub async fn new(network_graph: Arc<NetworkGraph<L>>, logger: L) -> (Self, mpsc::Sender<GossipMessage>) {
+		let network = config::graph_network(&network_graph);
+		let store = storage::initialize(network, logger.clone()).await;
 
 		let (gossip_persistence_sender, gossip_persistence_receiver) =
-			mpsc::channel::<GossipMessage>(100);
+			mpsc::channel::<GossipMessage>(config::persistence_queue_size());
 		let runtime = Runtime::new().unwrap();
 		(GossipPersister {
 			gossip_persistence_receiver,
 			network_graph,
+			network,
+			store,
+			update_deduplicator: config::deduplicate_updates().then(|| UpdateDeduplicator::new(config::DEDUPLICATED_UPDATE_REFRESH_INTERVAL, config::deduplicated_update_capacity())),
+			channel_changes: None,
+			leadership: None,
+			metrics: None,
 			tokio_runtime: runtime,
 			logger
 		}, gossip_persistence_sender)
 	}
 
+	/// Record the channels of the persisted gossip as changed in `channel_changes`
+	pub(crate) fn track_channel_changes(&mut self, channel_changes: Arc<ChannelChangeIndex>) {
+		self.channel_changes = Some(channel_changes);
+	}
+
+	/// Only persist gossip while `leadership` is held, discarding it otherwise. Upon taking the
+	/// lead, the latest channel gossip of the network graph is persisted once.
+	pub(crate) fn follow_leadership(&mut self, leadership: Arc<Leadership>) {
+		self.leadership = Some(leadership);
+	}
+
+	/// Report the memory estimated to be taken up by the update deduplication to `metrics`
+	pub(crate) fn report_memory_to(&mut self, metrics: Arc<Metrics>) {
+		self.metrics = Some(metrics);
+	}
+
+	/// Persist gossip until all senders are dropped
+	#[cfg(test)]
 	pub(crate) async fn persist_gossip(&mut self) {
+		self.persist_gossip_until(std::future::pending()).await
+	}
+
+	/// Persist gossip until `shutdown` completes or all senders are dropped. Upon shutdown, no
+	/// further gossip is accepted, but what's already queued is persisted, after which the network
+	/// graph is cached as well.
+	pub(crate) async fn persist_gossip_until<F: Future<Output = ()>>(&mut self, shutdown: F) {
+		tokio::pin!(shutdown);
+		let mut is_shutting_down = false;
 		// print log statement every minute
 		let mut latest_persistence_log = Instant::now() - Duration::from_secs(60);
 		let mut i = 0u32;
-		let mut latest_graph_cache_time = Instant::now();
+		let mut skipped_update_count = 0u32;
+		let checkpoint_interval = config::graph_checkpoint_interval();
+		let mut graph_checkpoint = tokio::time::interval_at(tokio::time::Instant::now() + checkpoint_interval, checkpoint_interval);
+		let mut is_graph_checkpoint_stale = false;
 		let insert_limiter = Arc::new(Semaphore::new(INSERT_PARALELLISM));
-		let connections_cache = Arc::new(Mutex::new(Vec::with_capacity(INSERT_PARALELLISM)));
+		let batch_size = config::db_batch_size();
+		let flush_interval = config::db_flush_interval();
+		let mut batch = Vec::with_capacity(batch_size);
+		let mut flush_deadline = tokio::time::Instant::now();
+		let mut was_leader = false;
+		let mut discarded_count = 0u32;
 		#[cfg(test)]
 		let mut tasks_spawned = Vec::new();
 		// TODO: it would be nice to have some sort of timeout here so after 10 seconds of
 		// inactivity, some sort of message could be broadcast signaling the activation of request
 		// processing
-		while let Some(gossip_message) = self.gossip_persistence_receiver.recv().await {
+		loop {
+			let gossip_message = tokio::select! {
+				gossip_message = self.gossip_persistence_receiver.recv() => gossip_message,
+				_ = &mut shutdown, if !is_shutting_down => {
+					log_info!(self.logger, "Shutting down, persisting {} queued gossip messages", self.gossip_persistence_receiver.len());
+					self.gossip_persistence_receiver.close();
+					is_shutting_down = true;
+					continue;
+				}
+				_ = graph_checkpoint.tick() => {
+					// only cache the graph once new gossip has come in
+					if is_graph_checkpoint_stale {
+						self.persist_network_graph();
+						is_graph_checkpoint_stale = false;
+					}
+					continue;
+				}
+				_ = tokio::time::sleep_until(flush_deadline), if !batch.is_empty() => {
+					let _task = self.flush(std::mem::take(&mut batch), &insert_limiter).await;
+					#[cfg(test)]
+					tasks_spawned.push(_task);
+					continue;
+				}
+			};
+			let gossip_message = match gossip_message {
+				Some(gossip_message) => gossip_message,
+				None => break,
+			};
+			// the message has already been applied to the graph
+			is_graph_checkpoint_stale = true;
+			if let Some(leadership) = &self.leadership {
+				let is_leader = leadership.is_leader();
+				if is_leader && !was_leader {
+					// the previous leader may have missed some of the gossip we've been discarding,
+					// with the index of changed channels having been restarted upon taking the lead
+					log_info!(self.logger, "Took the lead after discarding {} gossip messages, persisting the network graph's latest channel gossip", discarded_count);
+					batch.extend(latest_channel_gossip(&self.network_graph));
+					discarded_count = 0;
+				}
+				was_leader = is_leader;
+				if !is_leader {
+					discarded_count += 1;
+					continue;
+				}
+			}
+			if let (Some(deduplicator), GossipMessage::ChannelUpdate(update, _)) = (&mut self.update_deduplicator, &gossip_message) {
+				if deduplicator.is_redundant(&update.contents) {
+					skipped_update_count += 1;
+					continue;
+				}
+			}
 			i += 1; // count the persisted gossip messages
 
 			if latest_persistence_log.elapsed().as_secs() >= 60 {
 				log_info!(self.logger, "Persisting gossip message #{}", i);
+				if let Some(deduplicator) = &self.update_deduplicator {
+					log_info!(self.logger, "Skipped {} redundant channel updates", skipped_update_count);
+					if let Some(metrics) = &self.metrics {
+						metrics.set_memory_estimate("update_deduplicator", deduplicator.estimated_size());
+					}
+				}
 				latest_persistence_log = Instant::now();
 			}
 
-			// has it been ten minutes? Just cache it
-			if latest_graph_cache_time.elapsed().as_secs() >= 600 {
-				self.persist_network_graph();
-				latest_graph_cache_time = Instant::now();
+			if batch.is_empty() {
+				flush_deadline = tokio::time::Instant::now() + flush_interval;
 			}
-			insert_limiter.acquire().await.unwrap().forget();
-
-			let limiter_ref = Arc::clone(&insert_limiter);
-			let client = {
-				let mut connections_set = connections_cache.lock().await;
-				let client = if connections_set.is_empty() {
-					crate::connect_to_db().await
-				} else {
-					connections_set.pop().unwrap()
-				};
-				client
-			};
-
-			let connections_cache_ref = Arc::clone(&connections_cache);
-			match gossip_message {
-				GossipMessage::NodeAnnouncement(announcement, seen_override) => {
-					let public_key_hex = announcement.contents.node_id.to_string();
-
-					let mut announcement_signed = Vec::new();
-					announcement.write(&mut announcement_signed).unwrap();
-
-					let features = announcement.contents.features.encode();
-					let timestamp = announcement.contents.timestamp as i64;
-
-					let mut serialized_addresses = Vec::new();
-					announcement.contents.addresses.write(&mut serialized_addresses).unwrap();
-
-					let _task = self.tokio_runtime.spawn(async move {
-						if cfg!(test) && seen_override.is_some() {
-							tokio::time::timeout(POSTGRES_INSERT_TIMEOUT, client
-								.execute("INSERT INTO node_announcements (\
-								public_key, \
-								features, \
-								socket_addresses, \
-								timestamp, \
-								announcement_signed, \
-								seen \
-							) VALUES ($1, $2, $3, $4, $5, TO_TIMESTAMP($6))", &[
-									&public_key_hex,
-									&features,
-									&serialized_addresses,
-									&timestamp,
-									&announcement_signed,
-									&(seen_override.unwrap() as f64)
-								])).await.unwrap().unwrap();
-						} else {
-							tokio::time::timeout(POSTGRES_INSERT_TIMEOUT, client
-								.execute("INSERT INTO node_announcements (\
-								public_key, \
-								features, \
-								socket_addresses, \
-								timestamp, \
-								announcement_signed \
-							) VALUES ($1, $2, $3, $4, $5)", &[
-									&public_key_hex,
-									&features,
-									&serialized_addresses,
-									&timestamp,
-									&announcement_signed,
-								])).await.unwrap().unwrap();
-						}
-						let mut connections_set = connections_cache_ref.lock().await;
-						connections_set.push(client);
-						limiter_ref.add_permits(1);
-					});
-					#[cfg(test)]
-					tasks_spawned.push(_task);
-				},
-				GossipMessage::ChannelAnnouncement(announcement, funding_value, seen_override) => {
-					let scid = announcement.contents.short_channel_id as i64;
-
-					// start with the type prefix, which is already known a priori
-					let mut announcement_signed = Vec::new();
-					announcement.write(&mut announcement_signed).unwrap();
-
-					let _task = self.tokio_runtime.spawn(async move {
-						if cfg!(test) && seen_override.is_some() {
-							tokio::time::timeout(POSTGRES_INSERT_TIMEOUT, client
-								.execute("INSERT INTO channel_announcements (\
-								short_channel_id, \
-								funding_amount_sats, \
-								announcement_signed, \
-								seen \
-							) VALUES ($1, $2, $3, TO_TIMESTAMP($4)) ON CONFLICT (short_channel_id) DO NOTHING", &[
-									&scid,
-									&(funding_value as i64),
-									&announcement_signed,
-									&(seen_override.unwrap() as f64)
-								])).await.unwrap().unwrap();
-						} else {
-							tokio::time::timeout(POSTGRES_INSERT_TIMEOUT, client
-								.execute("INSERT INTO channel_announcements (\
-								short_channel_id, \
-								funding_amount_sats, \
-								announcement_signed \
-							) VALUES ($1, $2, $3) ON CONFLICT (short_channel_id) DO NOTHING", &[
-									&scid,
-									&(funding_value as i64),
-									&announcement_signed
-								])).await.unwrap().unwrap();
-						}
-						let mut connections_set = connections_cache_ref.lock().await;
-						connections_set.push(client);
-						limiter_ref.add_permits(1);
-					});
-					#[cfg(test)]
-					tasks_spawned.push(_task);
-				}
-				GossipMessage::ChannelUpdate(update, seen_override) => {
-					let scid = update.contents.short_channel_id as i64;
-
-					let timestamp = update.contents.timestamp as i64;
-
-					let direction = (update.contents.channel_flags & 1) == 1;
-					let disable = (update.contents.channel_flags & 2) > 0;
-
-					let cltv_expiry_delta = update.contents.cltv_expiry_delta as i32;
-					let htlc_minimum_msat = update.contents.htlc_minimum_msat as i64;
-					let fee_base_msat = update.contents.fee_base_msat as i32;
-					let fee_proportional_millionths =
-						update.contents.fee_proportional_millionths as i32;
-					let htlc_maximum_msat = update.contents.htlc_maximum_msat as i64;
-
-					// start with the type prefix, which is already known a priori
-					let mut update_signed = Vec::new();
-					update.write(&mut update_signed).unwrap();
-
-					let insertion_statement = if cfg!(test) {
-						"INSERT INTO channel_updates (\
-							short_channel_id, \
-							timestamp, \
-							seen, \
-							channel_flags, \
-							direction, \
-							disable, \
-							cltv_expiry_delta, \
-							htlc_minimum_msat, \
-							fee_base_msat, \
-							fee_proportional_millionths, \
-							htlc_maximum_msat, \
-							blob_signed \
-						) VALUES ($1, $2, TO_TIMESTAMP($3), $4, $5, $6, $7, $8, $9, $10, $11, $12)  ON CONFLICT DO NOTHING"
-					} else {
-						"INSERT INTO channel_updates (\
-							short_channel_id, \
-							timestamp, \
-							channel_flags, \
-							direction, \
-							disable, \
-							cltv_expiry_delta, \
-							htlc_minimum_msat, \
-							fee_base_msat, \
-							fee_proportional_millionths, \
-							htlc_maximum_msat, \
-							blob_signed \
-						) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)  ON CONFLICT DO NOTHING"
-					};
-
-					// this may not be used outside test cfg
-					let _seen_timestamp = seen_override.unwrap_or(timestamp as u32) as f64;
-
-					let _task = self.tokio_runtime.spawn(async move {
-						tokio::time::timeout(POSTGRES_INSERT_TIMEOUT, client
-							.execute(insertion_statement, &[
-								&scid,
-								&timestamp,
-								#[cfg(test)]
-									&_seen_timestamp,
-								&(update.contents.channel_flags as i16),
-								&direction,
-								&disable,
-								&cltv_expiry_delta,
-								&htlc_minimum_msat,
-								&fee_base_msat,
-								&fee_proportional_millionths,
-								&htlc_maximum_msat,
-								&update_signed
-							])).await.unwrap().unwrap();
-						let mut connections_set = connections_cache_ref.lock().await;
-						connections_set.push(client);
-						limiter_ref.add_permits(1);
-					});
-					#[cfg(test)]
-					tasks_spawned.push(_task);
-				}
+			batch.push(gossip_message);
+			if batch.len() >= batch_size {
+				let _task = self.flush(std::mem::replace(&mut batch, Vec::with_capacity(batch_size)), &insert_limiter).await;
+				#[cfg(test)]
+				tasks_spawned.push(_task);
 			}
 		}
+		if !batch.is_empty() {
+			let _task = self.flush(batch, &insert_limiter).await;
+			#[cfg(test)]
+			tasks_spawned.push(_task);
+		}
 		#[cfg(test)]
 		for task in tasks_spawned {
 			task.await.unwrap();
 		}
+		if is_shutting_down {
+			// wait for the batches still being inserted
+			let _ = insert_limiter.acquire_many(INSERT_PARALELLISM as u32).await.unwrap();
+			self.persist_network_graph();
+			log_info!(self.logger, "Flushed gossip persistence queue");
+		}
 	}
 
+	/// Insert a batch of gossip in the background, once fewer than [`INSERT_PARALELLISM`] batches
+	/// are being inserted already
+	async fn flush(&self, batch: Vec<GossipMessage>, insert_limiter: &Arc<Semaphore>) -> JoinHandle<()> {
+		insert_limiter.acquire().await.unwrap().forget();
+
+		let limiter_ref = Arc::clone(insert_limiter);
+		let store = Arc::clone(&self.store);
+		let channel_changes = self.channel_changes.clone();
+		let span = info_span!("db_write", messages = batch.len());
+		self.tokio_runtime.spawn(async move {
+			let changed_channels = if channel_changes.is_some() { changes::changed_channels(&batch) } else { Vec::new() };
+			store.insert_batch(batch).await;
+			if let Some(channel_changes) = channel_changes {
+				channel_changes.record(&changed_channels);
+			}
+			limiter_ref.add_permits(1);
+		}.instrument(span))
+	}
+
+	/// Periodically prune the channel updates that have outlived `retention`
+	pub(crate) fn spawn_update_pruning(&self, retention: Duration) {
+		let store = Arc::clone(&self.store);
+		let keep_latest = config::update_retention_count();
+		let leadership = self.leadership.clone();
+		let logger = self.logger.clone();
+		self.tokio_runtime.spawn(async move {
+			let mut pruning_interval = tokio::time::interval(config::UPDATE_PRUNING_INTERVAL);
+			loop {
+				pruning_interval.tick().await;
+				if leadership.as_ref().is_some_and(|leadership| !leadership.is_leader()) {
+					continue;
+				}
+				prune_channel_updates(&*store, retention, keep_latest, &logger).await;
+			}
+		}.instrument(info_span!("db_prune")));
+	}
+
+	/// Bring the cached network graph up to date with the gossip persisted since it was written,
+	/// which includes whatever was received after the last checkpoint before a crash
+	pub(crate) async fn reconcile_network_graph(&self) {
+		let cache_path = config::network_graph_cache_path(self.network);
+		let checkpoint_time = match fs::metadata(&cache_path).and_then(|metadata| metadata.modified()) {
+			Ok(checkpoint_time) => checkpoint_time,
+			Err(_) => return,
+		};
+		let since = checkpoint_time.duration_since(UNIX_EPOCH).unwrap_or_default().saturating_sub(config::GRAPH_RECONCILIATION_MARGIN).as_secs() as u32;
+		log_info!(self.logger, "Reconciling cached network graph with gossip seen since {}…", since);
+		let start = Instant::now();
+		let (announcement_count, update_count, node_announcement_count) = self.apply_persisted_gossip(since).await;
+		log_info!(self.logger, "Applied {} channel announcements, {} channel updates, and {} node announcements missing from the cached network graph in {:?}",
+			announcement_count, update_count, node_announcement_count, start.elapsed());
+	}
+
+	/// Apply the gossip persisted since `since` to the network graph, returning how many channel
+	/// announcements, channel updates, and node announcements it didn't know about yet
+	pub(crate) async fn apply_persisted_gossip(&self, since: u32) -> (usize, usize, usize) {
+		let mut announcement_count = 0;
+		let mut announcements = self.store.recent_channel_announcements(since).await;
+		while let Some(row) = announcements.next().await {
+			let announcement = ChannelAnnouncement::read(&mut Cursor::new(&row.announcement_signed)).unwrap();
+			let funding = match PersistedFunding::new(&announcement, row.funding_amount_sats) {
+				Some(funding) => funding,
+				None => continue,
+			};
+			if self.network_graph.update_channel_from_announcement(&announcement, &Some(&funding)).is_ok() {
+				announcement_count += 1;
+			}
+		}
+
+		let mut update_count = 0;
+		let mut updates = self.store.intermediate_updates(since).await;
+		while let Some(row) = updates.next().await {
+			let update = ChannelUpdate::read(&mut Cursor::new(&row.blob_signed)).unwrap();
+			if self.network_graph.update_channel(&update).is_ok() {
+				update_count += 1;
+			}
+		}
+
+		let mut node_announcement_count = 0;
+		let mut node_announcements = self.store.recent_node_announcements(since).await;
+		while let Some(row) = node_announcements.next().await {
+			let announcement = NodeAnnouncement::read(&mut Cursor::new(&row.announcement_signed)).unwrap();
+			if self.network_graph.update_node_from_announcement(&announcement).is_ok() {
+				node_announcement_count += 1;
+			}
+		}
+		(announcement_count, update_count, node_announcement_count)
+	}
+
+	/// Cache the network graph, replacing the previous cache only once it has been fully written
 	fn persist_network_graph(&self) {
 		log_info!(self.logger, "Caching network graph…");
-		let cache_path = config::network_graph_cache_path();
+		let cache_path = config::network_graph_cache_path(self.network);
+		let pending_cache_path = format!("{}.pending", cache_path);
 		let file = OpenOptions::new()
 			.create(true)
 			.write(true)
 			.truncate(true)
-			.open(&cache_path)
+			.open(&pending_cache_path)
 			.unwrap();
-		self.network_graph.remove_stale_channels_and_tracking();
+		crate::remove_stale_channels(&self.network_graph);
 		let mut writer = BufWriter::new(file);
 		self.network_graph.write(&mut writer).unwrap();
-		writer.flush().unwrap();
+		writer.into_inner().unwrap().sync_all().unwrap();
+		fs::rename(&pending_cache_path, &cache_path).unwrap();
 		log_info!(self.logger, "Cached network graph!");
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+
+	use bitcoin::blockdata::constants::ChainHash;
+	use bitcoin::Network;
+	use lightning::ln::msgs::UnsignedChannelUpdate;
+
+	use crate::persistence::UpdateDeduplicator;
+
+	fn update(direction: bool, timestamp: u32, fee_base_msat: u32) -> UnsignedChannelUpdate {
+		UnsignedChannelUpdate {
+			chain_hash: ChainHash::using_genesis_block(Network::Bitcoin),
+			short_channel_id: 1,
+			timestamp,
+			message_flags: 1,
+			channel_flags: if direction { 1 } else { 0 },
+			cltv_expiry_delta: 144,
+			htlc_minimum_msat: 1000,
+			htlc_maximum_msat: 100_000_000,
+			fee_base_msat,
+			fee_proportional_millionths: 100,
+			excess_data: vec![],
+		}
+	}
+
+	#[test]
+	fn test_update_deduplication() {
+		let mut deduplicator = UpdateDeduplicator::new(Duration::from_secs(100), None);
+		assert!(!deduplicator.is_redundant(&update(false, 1000, 1000)));
+		assert!(deduplicator.is_redundant(&update(false, 1050, 1000)));
+		// directions are tracked independently
+		assert!(!deduplicator.is_redundant(&update(true, 1050, 1000)));
+		// changed parameters are always persisted
+		assert!(!deduplicator.is_redundant(&update(false, 1060, 2000)));
+		// as are refreshes once the latest persisted update is old enough
+		assert!(deduplicator.is_redundant(&update(false, 1159, 2000)));
+		assert!(!deduplicator.is_redundant(&update(false, 1160, 2000)));
+		// outdated updates don't supersede the latest one
+		assert!(!deduplicator.is_redundant(&update(false, 1100, 1000)));
+		assert!(deduplicator.is_redundant(&update(false, 1200, 2000)));
+	}
+
+	#[test]
+	fn test_bounded_update_deduplication() {
+		let mut deduplicator = UpdateDeduplicator::new(Duration::from_secs(100), Some(4));
+		let channel_update = |short_channel_id: u64, timestamp: u32| UnsignedChannelUpdate { short_channel_id, ..update(false, timestamp, 1000) };
+		for short_channel_id in 0..4 {
+			assert!(!deduplicator.is_redundant(&channel_update(short_channel_id, 1000 + short_channel_id as u32 * 50)));
+		}
+		// the directions due for a refresh are forgotten first
+		assert!(!deduplicator.is_redundant(&channel_update(4, 1110)));
+		assert!(!deduplicator.latest_updates.contains_key(&(0, false)));
+		assert_eq!(deduplicator.latest_updates.len(), 4);
+		assert!(deduplicator.is_redundant(&channel_update(3, 1160)));
+		assert!(!deduplicator.is_redundant(&channel_update(0, 1160)));
+
+		// followed by a quarter of the remaining ones
+		assert!(!deduplicator.is_redundant(&channel_update(5, 1170)));
+		assert_eq!(deduplicator.latest_updates.len(), 4);
+		assert!(deduplicator.estimated_size() > 0);
+	}
+}
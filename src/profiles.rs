@@ -0,0 +1,167 @@
+//! Named snapshot profiles, whose snapshots are generated alongside the full ones from a subgraph,
+//! for clients too constrained to process the whole network graph.
+//!
+//! A profile is defined as `<name>=<filter>`, where the filter is either `top:<count>`, selecting
+//! the channels between the `count` nodes with the most capacity (or, where capacities are
+//! unknown, the most channels), or `nodes:<node id>[+<node id>…]`, selecting the channels of any
+//! of the given nodes.
+
+use std::collections::{HashMap, HashSet};
+use std::ops::Deref;
+use std::str::FromStr;
+
+use bitcoin::secp256k1::PublicKey;
+use lightning::routing::gossip::{NetworkGraph, NodeId};
+use lightning::util::logger::Logger;
+
+enum ProfileFilter {
+	TopNodes(usize),
+	Nodes(HashSet<NodeId>),
+}
+
+pub(crate) struct SnapshotProfile {
+	/// Names the directory the profile's snapshots are stored in, and the path they're served under
+	pub(crate) name: String,
+	filter: ProfileFilter,
+}
+
+/// The channels and nodes a profile's snapshots are limited to as of a snapshot round
+pub(crate) struct ProfileSelection {
+	short_channel_ids: HashSet<u64>,
+	node_ids: HashSet<NodeId>,
+}
+
+impl ProfileSelection {
+	pub(crate) fn includes_channel(&self, short_channel_id: u64) -> bool {
+		self.short_channel_ids.contains(&short_channel_id)
+	}
+
+	pub(crate) fn includes_node(&self, node_id: &NodeId) -> bool {
+		self.node_ids.contains(node_id)
+	}
+
+	pub(crate) fn channel_count(&self) -> usize {
+		self.short_channel_ids.len()
+	}
+
+	pub(crate) fn node_count(&self) -> usize {
+		self.node_ids.len()
+	}
+}
+
+/// Whether a profile name can safely be used as a path component
+pub(crate) fn is_valid_profile_name(name: &str) -> bool {
+	!name.is_empty() && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+impl SnapshotProfile {
+	pub(crate) fn parse(definition: &str) -> Result<Self, String> {
+		let (name, filter) = definition.split_once('=')
+			.ok_or_else(|| format!("Expected <name>=<filter> in snapshot profile {}", definition))?;
+		if !is_valid_profile_name(name) {
+			return Err(format!("Invalid snapshot profile name {}. Only letters, digits, '-', and '_' are allowed", name));
+		}
+		let filter = match filter.split_once(':') {
+			Some(("top", count)) => {
+				let count = count.parse::<usize>().ok().filter(|count| *count > 0)
+					.ok_or_else(|| format!("Invalid node count {} in snapshot profile {}", count, name))?;
+				ProfileFilter::TopNodes(count)
+			}
+			Some(("nodes", node_ids)) => {
+				let node_ids = node_ids.split('+').map(|node_id| PublicKey::from_str(node_id).map(|node_id| NodeId::from_pubkey(&node_id)))
+					.collect::<Result<HashSet<_>, _>>()
+					.map_err(|_| format!("Invalid node id in snapshot profile {}", name))?;
+				ProfileFilter::Nodes(node_ids)
+			}
+			_ => return Err(format!("Invalid filter {} in snapshot profile {}. Expected top:<count> or nodes:<node id>[+<node id>…]", filter, name)),
+		};
+		Ok(Self { name: name.to_string(), filter })
+	}
+
+	/// Select the channels and nodes of the profile in the current network graph. Nodes are selected
+	/// if they're an endpoint of a selected channel.
+	pub(crate) fn select<L: Deref>(&self, network_graph: &NetworkGraph<L>) -> ProfileSelection where L::Target: Logger {
+		let read_only_graph = network_graph.read_only();
+		let channels = read_only_graph.channels();
+		let short_channel_ids: HashSet<u64> = match &self.filter {
+			ProfileFilter::TopNodes(count) => {
+				// the total capacity and the channel count of each node
+				let mut node_capacities: HashMap<NodeId, (u64, u64)> = HashMap::new();
+				for (_, channel) in channels.unordered_iter() {
+					let capacity_sats = channel.capacity_sats.unwrap_or(0);
+					for node_id in [channel.node_one, channel.node_two] {
+						let (node_capacity_sats, channel_count) = node_capacities.entry(node_id).or_default();
+						*node_capacity_sats += capacity_sats;
+						*channel_count += 1;
+					}
+				}
+				let mut node_capacities: Vec<(NodeId, (u64, u64))> = node_capacities.into_iter().collect();
+				// ties are broken by node id, such that the selection is stable across rounds
+				node_capacities.sort_unstable_by(|(node_a, capacity_a), (node_b, capacity_b)| capacity_b.cmp(capacity_a).then(node_a.cmp(node_b)));
+				let top_nodes: HashSet<NodeId> = node_capacities.into_iter().take(*count).map(|(node_id, _)| node_id).collect();
+				channels.unordered_iter()
+					.filter(|(_, channel)| top_nodes.contains(&channel.node_one) && top_nodes.contains(&channel.node_two))
+					.map(|(short_channel_id, _)| *short_channel_id)
+					.collect()
+			}
+			ProfileFilter::Nodes(node_ids) => channels.unordered_iter()
+				.filter(|(_, channel)| node_ids.contains(&channel.node_one) || node_ids.contains(&channel.node_two))
+				.map(|(short_channel_id, _)| *short_channel_id)
+				.collect(),
+		};
+		let node_ids = short_channel_ids.iter()
+			.filter_map(|short_channel_id| channels.get(short_channel_id))
+			.flat_map(|channel| [channel.node_one, channel.node_two])
+			.collect();
+		ProfileSelection { short_channel_ids, node_ids }
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::Arc;
+
+	use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+	use bitcoin::Network;
+	use lightning::routing::gossip::{NetworkGraph, NodeId};
+	use lightning::types::features::ChannelFeatures;
+
+	use crate::profiles::SnapshotProfile;
+	use crate::types::tests::TestLogger;
+
+	fn node_key(byte: u8) -> PublicKey {
+		PublicKey::from_secret_key(&Secp256k1::new(), &SecretKey::from_slice(&[byte; 32]).unwrap())
+	}
+
+	#[test]
+	fn test_profile_selection() {
+		let network_graph = NetworkGraph::new(Network::Bitcoin, Arc::new(TestLogger::with_id("profiles".to_string())));
+		network_graph.add_channel_from_partial_announcement(1, 1_700_000_000, ChannelFeatures::empty(), node_key(1), node_key(2)).unwrap();
+		network_graph.add_channel_from_partial_announcement(2, 1_700_000_000, ChannelFeatures::empty(), node_key(2), node_key(3)).unwrap();
+		network_graph.add_channel_from_partial_announcement(3, 1_700_000_000, ChannelFeatures::empty(), node_key(3), node_key(4)).unwrap();
+		network_graph.add_channel_from_partial_announcement(4, 1_700_000_000, ChannelFeatures::empty(), node_key(5), node_key(6)).unwrap();
+
+		let profile = SnapshotProfile::parse(&format!("merchant=nodes:{}+{}", node_key(1), node_key(6))).unwrap();
+		assert_eq!(profile.name, "merchant");
+		let selection = profile.select(&network_graph);
+		assert!(selection.includes_channel(1) && selection.includes_channel(4));
+		assert!(!selection.includes_channel(2));
+		assert_eq!(selection.node_count(), 4);
+		assert!(selection.includes_node(&NodeId::from_pubkey(&node_key(2))));
+		assert!(!selection.includes_node(&NodeId::from_pubkey(&node_key(3))));
+
+		// without known capacities, nodes 2 and 3 stand out for having the most channels, and a
+		// single node doesn't have any channels among the selected nodes
+		assert_eq!(SnapshotProfile::parse("small=top:1").unwrap().select(&network_graph).channel_count(), 0);
+		let selection = SnapshotProfile::parse("small=top:2").unwrap().select(&network_graph);
+		assert_eq!(selection.channel_count(), 1);
+		assert!(selection.includes_channel(2));
+		assert_eq!(SnapshotProfile::parse("all=top:6").unwrap().select(&network_graph).channel_count(), 4);
+
+		assert!(SnapshotProfile::parse("top:5").is_err());
+		assert!(SnapshotProfile::parse("../small=top:5").is_err());
+		assert!(SnapshotProfile::parse("small=top:0").is_err());
+		assert!(SnapshotProfile::parse("small=nodes:02").is_err());
+		assert!(SnapshotProfile::parse("small=largest:5").is_err());
+	}
+}
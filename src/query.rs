@@ -0,0 +1,322 @@
+//! A read-only query API over the network graph, for clients that need to look up individual
+//! channels or nodes rather than sync the whole graph.
+
+use std::ops::Deref;
+use std::str::FromStr;
+
+use bitcoin::secp256k1::PublicKey;
+use hex_conservative::DisplayHex;
+use lightning::routing::gossip::{NetworkGraph, NodeId};
+use lightning::util::logger::Logger;
+use serde::Serialize;
+
+use crate::config;
+use crate::export::{self, ExportedChannel, ExportedNode, ExportedPolicy};
+use crate::health::HealthMonitor;
+use crate::metrics::{Metrics, PeerGossipStats, SnapshotRecord};
+use crate::verifier::{cached_funding_value, FundingAmountCache};
+
+/// A request to the query API
+#[derive(Debug, PartialEq)]
+pub(crate) enum Query {
+	Channel(u64),
+	/// A channel's policy in each direction, for its nodes to check what the server knows of it
+	Policy(u64),
+	Node(NodeId),
+	Stats,
+	Peers,
+	FundingScriptMismatches,
+	/// A path under `/api/` whose short channel id or node id couldn't be parsed
+	Malformed,
+}
+
+#[derive(Serialize)]
+struct NodeDetails {
+	#[serde(flatten)]
+	node: ExportedNode,
+	/// The short channel ids of the node's channels, in ascending order
+	channels: Vec<u64>,
+}
+
+#[derive(Serialize)]
+struct ChannelPolicies {
+	short_channel_id: u64,
+	/// The capacity the network graph holds for the channel, unknown for channels added from
+	/// partial announcements
+	capacity_sats: Option<u64>,
+	/// The value of the funding output as looked up while verifying the channel, if it's cached
+	cached_funding_sats: Option<u64>,
+	/// The policies of the channel's first node towards its second, and of its second towards its
+	/// first
+	directions: [DirectedPolicy; 2],
+}
+
+#[derive(Serialize)]
+struct DirectedPolicy {
+	source_node: String,
+	target_node: String,
+	/// The policy of the latest channel update in this direction, if any was received
+	policy: Option<ExportedPolicy>,
+}
+
+#[derive(Serialize)]
+struct PeerDetails {
+	node_id: String,
+	#[serde(flatten)]
+	stats: PeerGossipStats,
+}
+
+#[derive(Serialize)]
+struct FundingScriptMismatchDetails {
+	short_channel_id: u64,
+	peer: Option<String>,
+	announced_script: String,
+	funding_script: String,
+	detected_at: u64,
+}
+
+#[derive(Serialize)]
+struct Stats {
+	network: String,
+	channel_count: usize,
+	node_count: usize,
+	connected_peers: usize,
+	initial_sync_complete: bool,
+	snapshot_age_secs: Option<u64>,
+	/// The number of channels whose funding amount has been looked up since startup
+	cached_funding_amounts: usize,
+	/// The number of channel announcements whose bitcoin keys didn't match the funding output
+	funding_script_mismatches: u64,
+	/// The sizes of each scope's recent snapshots since startup
+	snapshot_history: Vec<SnapshotHistory>,
+}
+
+#[derive(Serialize)]
+struct SnapshotHistory {
+	profile: Option<String>,
+	scope: u64,
+	serialization_version: u8,
+	/// The recent rounds, oldest first
+	rounds: Vec<SnapshotRecord>,
+}
+
+/// Parse a short channel id, either as its integer representation or in the common
+/// `<block>x<transaction>x<output>` notation
+pub(crate) fn parse_short_channel_id(scid: &str) -> Option<u64> {
+	if let Ok(scid) = scid.parse::<u64>() {
+		return Some(scid);
+	}
+	let mut components = scid.split('x');
+	let block_height = components.next()?.parse::<u32>().ok().filter(|height| *height < 1 << 24)?;
+	let transaction_index = components.next()?.parse::<u32>().ok().filter(|index| *index < 1 << 24)?;
+	let output_index = components.next()?.parse::<u16>().ok()?;
+	if components.next().is_some() {
+		return None;
+	}
+	Some((block_height as u64) << 40 | (transaction_index as u64) << 16 | output_index as u64)
+}
+
+/// Format a short channel id in the `<block>x<transaction>x<output>` notation
+pub(crate) fn format_short_channel_id(scid: u64) -> String {
+	format!("{}x{}x{}", scid >> 40, (scid >> 16) & 0xff_ffff, scid & 0xffff)
+}
+
+/// Map a request path onto a query, if it's a query API path
+pub(crate) fn parse_query(request_path: &str) -> Option<Query> {
+	let path = request_path.strip_prefix("/api/")?;
+	if path == "stats" {
+		return Some(Query::Stats);
+	}
+	if path == "peers" {
+		return Some(Query::Peers);
+	}
+	if path == "funding_script_mismatches" {
+		return Some(Query::FundingScriptMismatches);
+	}
+	if let Some(scid) = path.strip_prefix("channel/") {
+		return Some(parse_short_channel_id(scid).map_or(Query::Malformed, Query::Channel));
+	}
+	if let Some(scid) = path.strip_prefix("policy/") {
+		return Some(parse_short_channel_id(scid).map_or(Query::Malformed, Query::Policy));
+	}
+	if let Some(pubkey) = path.strip_prefix("node/") {
+		return Some(PublicKey::from_str(pubkey).map_or(Query::Malformed, |pubkey| Query::Node(NodeId::from_pubkey(&pubkey))));
+	}
+	None
+}
+
+/// A channel as JSON, with its funding amount taken from the verifier's cache should the graph be
+/// missing it, or `None` if the channel is unknown
+pub(crate) fn channel_json<L: Deref>(network_graph: &NetworkGraph<L>, funding_amounts: &FundingAmountCache, short_channel_id: u64) -> Option<String> where L::Target: Logger {
+	let mut channel: ExportedChannel = {
+		let graph = network_graph.read_only();
+		export::export_channel(short_channel_id, graph.channel(short_channel_id)?)
+	};
+	if channel.capacity_sats.is_none() {
+		channel.capacity_sats = cached_funding_value(funding_amounts, short_channel_id);
+	}
+	Some(serde_json::to_string(&channel).unwrap())
+}
+
+/// A channel's policy in each direction as JSON, alongside both the capacity the graph holds and
+/// the funding amount the verifier has cached, or `None` if the channel is unknown
+pub(crate) fn policy_json<L: Deref>(network_graph: &NetworkGraph<L>, funding_amounts: &FundingAmountCache, short_channel_id: u64) -> Option<String> where L::Target: Logger {
+	let policies = {
+		let graph = network_graph.read_only();
+		let channel = graph.channel(short_channel_id)?;
+		let node_one = channel.node_one.as_slice().to_lower_hex_string();
+		let node_two = channel.node_two.as_slice().to_lower_hex_string();
+		ChannelPolicies {
+			short_channel_id,
+			capacity_sats: channel.capacity_sats,
+			cached_funding_sats: cached_funding_value(funding_amounts, short_channel_id),
+			directions: [
+				DirectedPolicy { source_node: node_one.clone(), target_node: node_two.clone(), policy: channel.one_to_two.as_ref().map(ExportedPolicy::from) },
+				DirectedPolicy { source_node: node_two, target_node: node_one, policy: channel.two_to_one.as_ref().map(ExportedPolicy::from) },
+			],
+		}
+	};
+	Some(serde_json::to_string(&policies).unwrap())
+}
+
+/// A node along with its channels as JSON, or `None` if the node is unknown
+pub(crate) fn node_json<L: Deref>(network_graph: &NetworkGraph<L>, node_id: &NodeId) -> Option<String> where L::Target: Logger {
+	let graph = network_graph.read_only();
+	let node = graph.node(node_id)?;
+	let mut channels = node.channels.clone();
+	channels.sort_unstable();
+	let details = NodeDetails { node: export::export_node(node_id, node), channels };
+	Some(serde_json::to_string(&details).unwrap())
+}
+
+pub(crate) fn stats_json<L: Deref>(network_graph: &NetworkGraph<L>, funding_amounts: &FundingAmountCache, health_monitor: &HealthMonitor, metrics: &Metrics) -> String where L::Target: Logger {
+	let (channel_count, node_count) = {
+		let graph = network_graph.read_only();
+		(graph.channels().len(), graph.nodes().len())
+	};
+	let stats = Stats {
+		network: config::graph_network(network_graph).to_string(),
+		channel_count,
+		node_count,
+		connected_peers: health_monitor.connected_peer_count(),
+		initial_sync_complete: health_monitor.is_initial_sync_complete(),
+		snapshot_age_secs: health_monitor.snapshot_age().map(|age| age.as_secs()),
+		cached_funding_amounts: funding_amounts.lock().unwrap().len(),
+		funding_script_mismatches: metrics.funding_script_mismatch_count(),
+		snapshot_history: metrics.snapshot_history().into_iter()
+			.map(|(series, rounds)| SnapshotHistory { profile: series.profile, scope: series.scope, serialization_version: series.serialization_version, rounds })
+			.collect(),
+	};
+	serde_json::to_string(&stats).unwrap()
+}
+
+/// The gossip received from each peer as JSON, ordered by node id
+pub(crate) fn peers_json(metrics: &Metrics) -> String {
+	let peers: Vec<PeerDetails> = metrics.peer_gossip_stats().into_iter()
+		.map(|(node_id, stats)| PeerDetails { node_id: node_id.to_string(), stats })
+		.collect();
+	serde_json::to_string(&peers).unwrap()
+}
+
+/// The most recent channel announcements whose bitcoin keys didn't match the funding output as
+/// JSON, oldest first
+pub(crate) fn funding_script_mismatches_json(metrics: &Metrics) -> String {
+	let mismatches: Vec<FundingScriptMismatchDetails> = metrics.recent_funding_script_mismatches().into_iter()
+		.map(|mismatch| FundingScriptMismatchDetails {
+			short_channel_id: mismatch.short_channel_id,
+			peer: mismatch.peer.map(|peer| peer.to_string()),
+			announced_script: mismatch.announced_script.to_hex_string(),
+			funding_script: mismatch.funding_script.to_hex_string(),
+			detected_at: mismatch.detected_at,
+		})
+		.collect();
+	serde_json::to_string(&mismatches).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::HashMap;
+	use std::sync::{Arc, Mutex};
+	use std::time::{SystemTime, UNIX_EPOCH};
+
+	use bitcoin::blockdata::constants::ChainHash;
+	use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+	use bitcoin::Network;
+	use lightning::ln::msgs::UnsignedChannelUpdate;
+	use lightning::routing::gossip::{NetworkGraph, NodeId};
+	use lightning::types::features::ChannelFeatures;
+
+	use crate::query::{channel_json, node_json, parse_query, parse_short_channel_id, policy_json, Query};
+	use crate::types::tests::TestLogger;
+
+	fn node_key(byte: u8) -> PublicKey {
+		PublicKey::from_secret_key(&Secp256k1::new(), &SecretKey::from_slice(&[byte; 32]).unwrap())
+	}
+
+	#[test]
+	fn test_parse_query() {
+		assert_eq!(parse_short_channel_id("870000x1234x1"), Some(870000 << 40 | 1234 << 16 | 1));
+		assert_eq!(parse_short_channel_id("956575621577719809"), Some(956575621577719809));
+		assert_eq!(parse_short_channel_id("16777216x0x0"), None);
+		assert_eq!(parse_short_channel_id("1x2x3x4"), None);
+		assert_eq!(parse_short_channel_id("1x2"), None);
+
+		assert_eq!(parse_query("/api/stats"), Some(Query::Stats));
+		assert_eq!(parse_query("/api/peers"), Some(Query::Peers));
+		assert_eq!(parse_query("/api/funding_script_mismatches"), Some(Query::FundingScriptMismatches));
+		assert_eq!(parse_query("/api/channel/1x2x3"), Some(Query::Channel(1 << 40 | 2 << 16 | 3)));
+		assert_eq!(parse_query("/api/channel/abc"), Some(Query::Malformed));
+		assert_eq!(parse_query("/api/policy/42"), Some(Query::Policy(42)));
+		assert_eq!(parse_query("/api/policy/"), Some(Query::Malformed));
+		assert_eq!(parse_query(&format!("/api/node/{}", node_key(1))), Some(Query::Node(NodeId::from_pubkey(&node_key(1)))));
+		assert_eq!(parse_query("/api/node/02"), Some(Query::Malformed));
+		assert_eq!(parse_query("/api/graph"), None);
+		assert_eq!(parse_query("/snapshot/0"), None);
+	}
+
+	#[test]
+	fn test_channel_and_node_lookup() {
+		let network_graph = NetworkGraph::new(Network::Bitcoin, Arc::new(TestLogger::with_id("query".to_string())));
+		network_graph.add_channel_from_partial_announcement(42, 1_700_000_000, ChannelFeatures::empty(), node_key(1), node_key(2)).unwrap();
+		network_graph.add_channel_from_partial_announcement(7, 1_700_000_000, ChannelFeatures::empty(), node_key(1), node_key(3)).unwrap();
+		let funding_amounts = Arc::new(Mutex::new(HashMap::from([(42, 250_000)])));
+
+		// the graph doesn't know the capacities of channels added from partial announcements
+		let channel: serde_json::Value = serde_json::from_str(&channel_json(&network_graph, &funding_amounts, 42).unwrap()).unwrap();
+		assert_eq!(channel["capacity_sats"], 250_000);
+		assert_eq!(channel["node_one"], node_key(1).to_string());
+		let channel: serde_json::Value = serde_json::from_str(&channel_json(&network_graph, &funding_amounts, 7).unwrap()).unwrap();
+		assert!(channel["capacity_sats"].is_null());
+		assert!(channel_json(&network_graph, &funding_amounts, 43).is_none());
+
+		let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as u32;
+		network_graph.update_channel_unsigned(&UnsignedChannelUpdate {
+			chain_hash: ChainHash::using_genesis_block(Network::Bitcoin),
+			short_channel_id: 42,
+			timestamp,
+			message_flags: 1,
+			channel_flags: 1,
+			cltv_expiry_delta: 144,
+			htlc_minimum_msat: 1000,
+			htlc_maximum_msat: 100_000_000,
+			fee_base_msat: 1000,
+			fee_proportional_millionths: 100,
+			excess_data: vec![],
+		}).unwrap();
+		let policies: serde_json::Value = serde_json::from_str(&policy_json(&network_graph, &funding_amounts, 42).unwrap()).unwrap();
+		assert!(policies["capacity_sats"].is_null());
+		assert_eq!(policies["cached_funding_sats"], 250_000);
+		assert_eq!(policies["directions"][0]["source_node"], node_key(1).to_string());
+		assert!(policies["directions"][0]["policy"].is_null());
+		assert_eq!(policies["directions"][1]["source_node"], node_key(2).to_string());
+		assert_eq!(policies["directions"][1]["policy"]["fee_base_msat"], 1000);
+		assert_eq!(policies["directions"][1]["policy"]["last_update"], timestamp);
+		assert!(policy_json(&network_graph, &funding_amounts, 43).is_none());
+
+		let node: serde_json::Value = serde_json::from_str(&node_json(&network_graph, &NodeId::from_pubkey(&node_key(1))).unwrap()).unwrap();
+		assert_eq!(node["node_id"], node_key(1).to_string());
+		assert_eq!(node["channels"], serde_json::json!([7, 42]));
+		assert!(node["alias"].is_null());
+		assert!(node_json(&network_graph, &NodeId::from_pubkey(&node_key(4))).is_none());
+	}
+}
@@ -0,0 +1,95 @@
+//! Guards the HTTP endpoints computing their responses on demand (dynamic snapshots, the query API,
+//! and the graph export) against being overwhelmed, by limiting the rate of each client's requests
+//! with a token bucket, and optionally the number of such requests served at once.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config;
+
+/// Hands out tokens to each client at a steady rate, up to a burst of them while idle
+pub(crate) struct ClientRateLimiter {
+	tokens_per_sec: f64,
+	burst: f64,
+	/// The tokens available to each client as of the time they were last counted
+	buckets: Mutex<HashMap<IpAddr, (f64, Instant)>>,
+}
+
+impl ClientRateLimiter {
+	pub(crate) fn new(requests_per_minute: u32, burst: u32) -> Self {
+		Self { tokens_per_sec: requests_per_minute as f64 / 60.0, burst: burst as f64, buckets: Mutex::new(HashMap::new()) }
+	}
+
+	/// Take a token for a request from `client`, or return how long until one becomes available
+	pub(crate) fn acquire(&self, client: IpAddr) -> Result<(), Duration> {
+		self.acquire_at(client, Instant::now())
+	}
+
+	fn acquire_at(&self, client: IpAddr, now: Instant) -> Result<(), Duration> {
+		let mut buckets = self.buckets.lock().unwrap();
+		if buckets.len() >= config::MAX_RATE_LIMITED_CLIENTS {
+			// clients whose bucket has refilled are indistinguishable from new ones
+			buckets.retain(|_, (tokens, counted_at)| self.refill(*tokens, *counted_at, now) < self.burst);
+		}
+		let (tokens, counted_at) = buckets.entry(client_key(client)).or_insert((self.burst, now));
+		*tokens = self.refill(*tokens, *counted_at, now);
+		*counted_at = now;
+		if *tokens < 1.0 {
+			return Err(Duration::from_secs_f64((1.0 - *tokens) / self.tokens_per_sec));
+		}
+		*tokens -= 1.0;
+		Ok(())
+	}
+
+	fn refill(&self, tokens: f64, counted_at: Instant, now: Instant) -> f64 {
+		(tokens + now.saturating_duration_since(counted_at).as_secs_f64() * self.tokens_per_sec).min(self.burst)
+	}
+}
+
+/// The address clients are told apart by. IPv6 clients are commonly assigned a whole /64, so they're
+/// limited by that rather than by each of its addresses.
+fn client_key(client: IpAddr) -> IpAddr {
+	match client {
+		IpAddr::V4(_) => client,
+		IpAddr::V6(address) => match address.to_ipv4_mapped() {
+			Some(address) => IpAddr::V4(address),
+			None => IpAddr::V6((u128::from(address) & !(u64::MAX as u128)).into()),
+		},
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::net::IpAddr;
+	use std::str::FromStr;
+	use std::time::{Duration, Instant};
+
+	use crate::rate_limit::ClientRateLimiter;
+
+	#[test]
+	fn test_client_rate_limiting() {
+		let limiter = ClientRateLimiter::new(60, 2);
+		let client = IpAddr::from_str("203.0.113.7").unwrap();
+		let start = Instant::now();
+		assert!(limiter.acquire_at(client, start).is_ok());
+		assert!(limiter.acquire_at(client, start).is_ok());
+		assert_eq!(limiter.acquire_at(client, start), Err(Duration::from_secs(1)));
+		// other clients have buckets of their own
+		assert!(limiter.acquire_at(IpAddr::from_str("203.0.113.8").unwrap(), start).is_ok());
+
+		// a token is added every second, up to the burst
+		assert!(limiter.acquire_at(client, start + Duration::from_millis(1500)).is_ok());
+		assert_eq!(limiter.acquire_at(client, start + Duration::from_millis(1500)), Err(Duration::from_millis(500)));
+		assert!(limiter.acquire_at(client, start + Duration::from_secs(60)).is_ok());
+		assert!(limiter.acquire_at(client, start + Duration::from_secs(60)).is_ok());
+		assert!(limiter.acquire_at(client, start + Duration::from_secs(60)).is_err());
+
+		// the addresses of an IPv6 /64 share a bucket
+		assert!(limiter.acquire_at(IpAddr::from_str("2001:db8::1").unwrap(), start).is_ok());
+		assert!(limiter.acquire_at(IpAddr::from_str("2001:db8::2").unwrap(), start).is_ok());
+		assert!(limiter.acquire_at(IpAddr::from_str("2001:db8::3").unwrap(), start).is_err());
+		assert!(limiter.acquire_at(IpAddr::from_str("2001:db8:0:1::1").unwrap(), start).is_ok());
+	}
+}
@@ -0,0 +1,271 @@
+//! Archives every accepted gossip message to append-only log files, such that the gossip seen by
+//! the server can be studied or replayed later.
+//!
+//! Each line of a log file holds one message as `<received at> <origin> <message>`, where the
+//! receive time is in milliseconds since the epoch, the origin is the node id of the peer the
+//! message was received from, or `-` if unknown, and the message is the hex-encoded BOLT 7 wire
+//! message, including its type. Recordings can be fed back through the server with the `replay`
+//! command.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::ops::Deref;
+use std::str::FromStr;
+use std::sync::{mpsc, Arc};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bitcoin::secp256k1::PublicKey;
+use hex_conservative::{DisplayHex, FromHex};
+use lightning::{log_error, log_info};
+use lightning::util::logger::Logger;
+use lightning::util::ser::Writeable;
+
+use crate::config;
+use crate::metrics::Metrics;
+
+pub(crate) const CHANNEL_ANNOUNCEMENT_TYPE: u16 = 256;
+pub(crate) const NODE_ANNOUNCEMENT_TYPE: u16 = 257;
+pub(crate) const CHANNEL_UPDATE_TYPE: u16 = 258;
+
+/// Serialize a gossip message as on the wire, prefixed by its message type
+pub(crate) fn wire_message<M: Writeable>(message_type: u16, message: &M) -> Vec<u8> {
+	let mut wire_message = message_type.to_be_bytes().to_vec();
+	wire_message.extend(message.encode());
+	wire_message
+}
+
+pub(crate) struct RecordedMessage {
+	pub(crate) received_at_ms: u64,
+	pub(crate) origin: Option<PublicKey>,
+	pub(crate) wire_message: Vec<u8>,
+}
+
+impl RecordedMessage {
+	fn to_line(&self) -> String {
+		let origin = self.origin.map_or("-".to_string(), |origin| origin.to_string());
+		format!("{} {} {}\n", self.received_at_ms, origin, self.wire_message.to_lower_hex_string())
+	}
+
+	/// Parse a line of a log file, without its trailing newline
+	pub(crate) fn from_line(line: &str) -> Result<Self, String> {
+		let mut fields = line.split(' ');
+		let (received_at_ms, origin, wire_message) = match (fields.next(), fields.next(), fields.next(), fields.next()) {
+			(Some(received_at_ms), Some(origin), Some(wire_message), None) => (received_at_ms, origin, wire_message),
+			_ => return Err("Expected a receive time, an origin, and a message".to_string()),
+		};
+		let received_at_ms = received_at_ms.parse().map_err(|_| format!("Invalid receive time {}", received_at_ms))?;
+		let origin = match origin {
+			"-" => None,
+			origin => Some(PublicKey::from_str(origin).map_err(|_| format!("Invalid origin {}", origin))?),
+		};
+		let wire_message = Vec::from_hex(wire_message).map_err(|_| "Invalid message hex".to_string())?;
+		Ok(Self { received_at_ms, origin, wire_message })
+	}
+}
+
+/// Hands the accepted gossip to a thread writing it to the log files, rotating them once they
+/// exceed the configured size, such that disk I/O never holds up gossip processing. Should the
+/// writer fall behind, messages beyond the bounded queue are dropped and counted instead.
+pub(crate) struct GossipRecorder {
+	sender: mpsc::SyncSender<RecordedMessage>,
+	metrics: Arc<Metrics>,
+}
+
+impl GossipRecorder {
+	pub(crate) fn start<L: Deref + Send + 'static>(directory: String, max_file_size: u64, max_file_count: Option<usize>, metrics: Arc<Metrics>, logger: L) -> Self where L::Target: Logger {
+		let (sender, receiver) = mpsc::sync_channel(config::GOSSIP_RECORDING_QUEUE_SIZE);
+		log_info!(logger, "Recording gossip to {}", directory);
+		std::thread::spawn(move || write_records(receiver, directory, max_file_size, max_file_count, logger));
+		Self { sender, metrics }
+	}
+
+	pub(crate) fn record<M: Writeable>(&self, message_type: u16, message: &M, origin: Option<PublicKey>) {
+		let received_at_ms = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+		let record = RecordedMessage { received_at_ms, origin, wire_message: wire_message(message_type, message) };
+		if let Err(mpsc::TrySendError::Full(_)) = self.sender.try_send(record) {
+			self.metrics.record_recording_dropped();
+		}
+	}
+}
+
+/// Start a new log file, named after the time it was started at such that the files sort
+/// chronologically, returning it along with that time. As deleting expired files frees their
+/// names, files are named after no earlier a time than the previous one started at.
+fn open_log_file(directory: &str, previous_started_at_ms: Option<u128>) -> io::Result<(BufWriter<File>, u128)> {
+	fs::create_dir_all(directory)?;
+	let mut started_at_ms = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis();
+	if let Some(previous_started_at_ms) = previous_started_at_ms {
+		started_at_ms = started_at_ms.max(previous_started_at_ms + 1);
+	}
+	loop {
+		let path = format!("{}/gossip-{}.log", directory, started_at_ms);
+		match OpenOptions::new().create_new(true).append(true).open(path) {
+			Ok(file) => return Ok((BufWriter::new(file), started_at_ms)),
+			// files may be rotated more than once per millisecond
+			Err(e) if e.kind() == io::ErrorKind::AlreadyExists => started_at_ms += 1,
+			Err(e) => return Err(e),
+		}
+	}
+}
+
+/// Delete the oldest log files in `directory` beyond the `max_file_count` most recent ones
+fn prune_log_files(directory: &str, max_file_count: usize) -> io::Result<usize> {
+	let mut log_files: Vec<(u128, String)> = fs::read_dir(directory)?
+		.filter_map(|entry| {
+			let file_name = entry.ok()?.file_name().into_string().ok()?;
+			let started_at_ms = file_name.strip_prefix("gossip-")?.strip_suffix(".log")?.parse().ok()?;
+			Some((started_at_ms, file_name))
+		})
+		.collect();
+	if log_files.len() <= max_file_count {
+		return Ok(0);
+	}
+	log_files.sort_unstable();
+	let expired_count = log_files.len() - max_file_count;
+	for (_, file_name) in &log_files[..expired_count] {
+		fs::remove_file(format!("{}/{}", directory, file_name))?;
+	}
+	Ok(expired_count)
+}
+
+fn write_records<L: Deref>(receiver: mpsc::Receiver<RecordedMessage>, directory: String, max_file_size: u64, max_file_count: Option<usize>, logger: L) where L::Target: Logger {
+	let mut log_file: Option<(BufWriter<File>, u64)> = None;
+	let mut log_file_started_at_ms = None;
+	loop {
+		let record = match receiver.recv_timeout(config::GOSSIP_RECORDING_FLUSH_INTERVAL) {
+			Ok(record) => record,
+			Err(mpsc::RecvTimeoutError::Timeout) => {
+				if let Some((writer, _)) = &mut log_file {
+					if let Err(e) = writer.flush() {
+						log_error!(logger, "Failed to flush gossip recording: {}", e);
+					}
+				}
+				continue;
+			}
+			Err(mpsc::RecvTimeoutError::Disconnected) => break,
+		};
+
+		if log_file.as_ref().map_or(true, |(_, size)| *size >= max_file_size) {
+			if let Some((mut writer, _)) = log_file.take() {
+				let _ = writer.flush();
+			}
+			match open_log_file(&directory, log_file_started_at_ms) {
+				Ok((writer, started_at_ms)) => {
+					log_file = Some((writer, 0));
+					log_file_started_at_ms = Some(started_at_ms);
+				}
+				Err(e) => {
+					log_error!(logger, "Failed to open gossip recording file in {}, dropping message: {}", directory, e);
+					continue;
+				}
+			}
+			if let Some(max_file_count) = max_file_count {
+				match prune_log_files(&directory, max_file_count) {
+					Ok(0) => {}
+					Ok(expired_count) => log_info!(logger, "Deleted {} expired gossip recording files from {}", expired_count, directory),
+					Err(e) => log_error!(logger, "Failed to delete expired gossip recording files from {}: {}", directory, e),
+				}
+			}
+		}
+		let (writer, size) = log_file.as_mut().unwrap();
+		let line = record.to_line();
+		match writer.write_all(line.as_bytes()) {
+			Ok(()) => *size += line.len() as u64,
+			Err(e) => log_error!(logger, "Failed to record gossip message: {}", e),
+		}
+	}
+	if let Some((mut writer, _)) = log_file {
+		let _ = writer.flush();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::{mpsc, Arc};
+
+	use bitcoin::blockdata::constants::ChainHash;
+	use bitcoin::Network;
+	use lightning::ln::msgs::UnsignedChannelUpdate;
+
+	use crate::metrics::Metrics;
+	use crate::recording::{wire_message, write_records, GossipRecorder, RecordedMessage, CHANNEL_UPDATE_TYPE};
+	use crate::types::tests::TestLogger;
+
+	fn test_update(timestamp: u32) -> UnsignedChannelUpdate {
+		UnsignedChannelUpdate {
+			chain_hash: ChainHash::using_genesis_block(Network::Bitcoin),
+			short_channel_id: 42,
+			timestamp,
+			message_flags: 1,
+			channel_flags: 0,
+			cltv_expiry_delta: 144,
+			htlc_minimum_msat: 1000,
+			htlc_maximum_msat: 100_000_000,
+			fee_base_msat: 1000,
+			fee_proportional_millionths: 100,
+			excess_data: vec![],
+		}
+	}
+
+	#[test]
+	fn test_recording_rotation() {
+		let directory = std::env::temp_dir().join(format!("rgs_recording_{}", std::process::id()));
+		let update = test_update(1_700_000_000);
+		{
+			// every file exceeds the limit after a single message
+			let recorder = GossipRecorder::start(directory.to_str().unwrap().to_string(), 1, None, Arc::new(Metrics::new()), Arc::new(TestLogger::with_id("recording".to_string())));
+			for _ in 0..3 {
+				recorder.record(CHANNEL_UPDATE_TYPE, &update, None);
+			}
+		}
+		// the writer flushes and exits once the recorder is dropped
+		let mut lines = Vec::new();
+		for _ in 0..100 {
+			lines = std::fs::read_dir(&directory).map(|entries| entries.flat_map(|entry| {
+				std::fs::read_to_string(entry.unwrap().path()).unwrap().lines().map(str::to_string).collect::<Vec<_>>()
+			}).collect()).unwrap_or_default();
+			if lines.len() == 3 {
+				break;
+			}
+			std::thread::sleep(std::time::Duration::from_millis(10));
+		}
+		assert_eq!(lines.len(), 3);
+		assert_eq!(std::fs::read_dir(&directory).unwrap().count(), 3);
+		let record = RecordedMessage::from_line(&lines[0]).unwrap();
+		assert!(record.origin.is_none());
+		assert_eq!(record.wire_message, wire_message(CHANNEL_UPDATE_TYPE, &update));
+		assert!(RecordedMessage::from_line("1700000000000 - 0102 extra").is_err());
+		assert!(RecordedMessage::from_line("1700000000000 02 0102").is_err());
+		std::fs::remove_dir_all(&directory).unwrap();
+	}
+	#[test]
+	fn test_recording_retention() {
+		let directory = std::env::temp_dir().join(format!("rgs_recording_retention_{}", std::process::id()));
+		let (sender, receiver) = mpsc::sync_channel(4);
+		for timestamp in 0..4 {
+			let record = RecordedMessage { received_at_ms: timestamp as u64, origin: None, wire_message: wire_message(CHANNEL_UPDATE_TYPE, &test_update(timestamp)) };
+			sender.send(record).unwrap();
+		}
+		drop(sender);
+		// every file exceeds the limit after a single message, and only the latest two are kept
+		write_records(receiver, directory.to_str().unwrap().to_string(), 1, Some(2), Arc::new(TestLogger::with_id("recording".to_string())));
+		let mut recorded_at_ms: Vec<u64> = std::fs::read_dir(&directory).unwrap().flat_map(|entry| {
+			std::fs::read_to_string(entry.unwrap().path()).unwrap().lines().map(|line| RecordedMessage::from_line(line).unwrap().received_at_ms).collect::<Vec<_>>()
+		}).collect();
+		recorded_at_ms.sort_unstable();
+		assert_eq!(recorded_at_ms, vec![2, 3]);
+		std::fs::remove_dir_all(&directory).unwrap();
+	}
+
+	#[test]
+	fn test_recording_overflow() {
+		// without a writer draining the queue, messages beyond its capacity are dropped
+		let (sender, _receiver) = mpsc::sync_channel(1);
+		let metrics = Arc::new(Metrics::new());
+		let recorder = GossipRecorder { sender, metrics: Arc::clone(&metrics) };
+		for timestamp in 0..3 {
+			recorder.record(CHANNEL_UPDATE_TYPE, &test_update(timestamp), None);
+		}
+		assert!(metrics.render().contains("\nrgs_recording_dropped_total 2\n"));
+	}
+}
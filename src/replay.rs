@@ -0,0 +1,207 @@
+//! Feeds recorded gossip back through the validation and persistence applied to gossip received
+//! from peers, e. g. to regenerate snapshots offline, or to run deterministic integration tests.
+//!
+//! The funding outputs of the replayed channel announcements are verified against the configured
+//! chain source, or against a UTXO set file listing one output per line as
+//! `<short channel id> <amount in satoshis> <hex-encoded script pubkey>`, where the short channel
+//! id may be given in either of the notations the query API accepts. Blank lines and lines starting
+//! with `#` are ignored.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bitcoin::hashes::Hash;
+use bitcoin::{Amount, Block, OutPoint, ScriptBuf, TxOut, Txid};
+use lightning::events::MessageSendEventsProvider;
+use lightning::ln::msgs::{ChannelAnnouncement, ChannelUpdate, DecodeError, LightningError, NodeAnnouncement};
+use lightning::util::logger::Logger;
+use lightning::util::ser::Readable;
+use lightning::{log_gossip, log_info};
+use tokio::time::Instant;
+
+use crate::chain::ChainSource;
+use crate::downloader::GossipRouter;
+use crate::query;
+use crate::recording::{RecordedMessage, CHANNEL_ANNOUNCEMENT_TYPE, CHANNEL_UPDATE_TYPE, NODE_ANNOUNCEMENT_TYPE};
+
+/// A chain source serving the funding outputs listed in a UTXO set file, all of which are
+/// considered unspent
+pub(crate) struct UtxoSetChainSource {
+	outputs: HashMap<u64, TxOut>,
+}
+
+impl UtxoSetChainSource {
+	pub(crate) fn load(path: &str) -> Result<Self, String> {
+		let contents = fs::read_to_string(path).map_err(|e| format!("Failed to read UTXO set {}: {}", path, e))?;
+		Self::parse(&contents).map_err(|e| format!("Invalid UTXO set {}: {}", path, e))
+	}
+
+	fn parse(contents: &str) -> Result<Self, String> {
+		let mut outputs = HashMap::new();
+		for (line_index, line) in contents.lines().enumerate() {
+			let line = line.trim();
+			if line.is_empty() || line.starts_with('#') {
+				continue;
+			}
+			let fields: Vec<&str> = line.split_whitespace().collect();
+			let (short_channel_id, amount, script_pubkey) = match fields[..] {
+				[short_channel_id, amount, script_pubkey] => (short_channel_id, amount, script_pubkey),
+				_ => return Err(format!("Expected a short channel id, an amount, and a script pubkey on line {}", line_index + 1)),
+			};
+			let short_channel_id = query::parse_short_channel_id(short_channel_id)
+				.ok_or_else(|| format!("Invalid short channel id {} on line {}", short_channel_id, line_index + 1))?;
+			let amount = amount.parse().map_err(|_| format!("Invalid amount {} on line {}", amount, line_index + 1))?;
+			let script_pubkey = ScriptBuf::from_hex(script_pubkey).map_err(|_| format!("Invalid script pubkey on line {}", line_index + 1))?;
+			outputs.insert(short_channel_id, TxOut { value: Amount::from_sat(amount), script_pubkey });
+		}
+		Ok(Self { outputs })
+	}
+}
+
+#[async_trait]
+impl ChainSource for UtxoSetChainSource {
+	async fn block_at_height(&self, height: u32) -> io::Result<Block> {
+		Err(io::Error::new(io::ErrorKind::Unsupported, format!("A UTXO set can't serve block {}", height)))
+	}
+
+	async fn funding_output(&self, block_height: u32, transaction_index: u32, output_index: u16) -> io::Result<Option<(Txid, TxOut)>> {
+		let short_channel_id = (block_height as u64) << 40 | (transaction_index as u64) << 16 | output_index as u64;
+		// the UTXO set doesn't include transaction ids, but they're merely used to check whether
+		// outputs are unspent, which all of them are
+		let txid = Txid::hash(&short_channel_id.to_be_bytes());
+		Ok(self.outputs.get(&short_channel_id).map(|output| (txid, output.clone())))
+	}
+
+	async fn is_output_unspent(&self, _outpoint: OutPoint) -> io::Result<bool> {
+		Ok(true)
+	}
+
+	async fn is_reachable(&self) -> bool {
+		true
+	}
+}
+
+/// The log files of a recording, in the order they were written. `path` may either be a single
+/// file, or the directory the gossip was recorded to.
+pub(crate) fn recording_files(path: &str) -> Result<Vec<PathBuf>, String> {
+	let path = Path::new(path);
+	if !path.is_dir() {
+		return Ok(vec![path.to_path_buf()]);
+	}
+	let mut files = Vec::new();
+	for entry in fs::read_dir(path).map_err(|e| format!("Failed to read recording directory {}: {}", path.display(), e))? {
+		let entry = entry.map_err(|e| format!("Failed to read recording directory {}: {}", path.display(), e))?;
+		let file_name = entry.file_name();
+		let file_name = file_name.to_string_lossy();
+		if file_name.starts_with("gossip-") && file_name.ends_with(".log") {
+			files.push(entry.path());
+		}
+	}
+	// the files are named after the time they were started at
+	files.sort();
+	if files.is_empty() {
+		return Err(format!("The recording directory {} doesn't contain any gossip recordings", path.display()));
+	}
+	Ok(files)
+}
+
+/// Decode a recorded message and hand it to `router`, returning its type along with whether it
+/// was accepted
+fn replay_message<L: Deref + Clone + Send + Sync + 'static>(router: &GossipRouter<L>, record: &RecordedMessage) -> Result<(u16, Result<bool, LightningError>), String> where L::Target: Logger {
+	if record.wire_message.len() < 2 {
+		return Err("Truncated message".to_string());
+	}
+	let message_type = u16::from_be_bytes([record.wire_message[0], record.wire_message[1]]);
+	let mut readable = &record.wire_message[2..];
+	let decoding_failed = |e: DecodeError| format!("Failed to decode message of type {}: {:?}", message_type, e);
+	let seen = Some((record.received_at_ms / 1000) as u32);
+	let result = match message_type {
+		CHANNEL_ANNOUNCEMENT_TYPE => router.process_channel_announcement(record.origin, &ChannelAnnouncement::read(&mut readable).map_err(decoding_failed)?, seen),
+		NODE_ANNOUNCEMENT_TYPE => router.process_node_announcement(record.origin, &NodeAnnouncement::read(&mut readable).map_err(decoding_failed)?, seen),
+		CHANNEL_UPDATE_TYPE => router.process_channel_update(record.origin, &ChannelUpdate::read(&mut readable).map_err(decoding_failed)?, seen),
+		_ => return Err(format!("Unexpected message type {}", message_type)),
+	};
+	Ok((message_type, result))
+}
+
+/// How many of the replayed messages were accepted
+pub(crate) struct ReplayCounts {
+	pub(crate) replayed: u64,
+	pub(crate) rejected: u64,
+}
+
+/// Hand the messages of a recording to `router` as if they were received from their origins when
+/// recorded. With a `speed`, messages are replayed at that multiple of the pace they were recorded
+/// at, and otherwise as fast as they can be processed.
+///
+/// Each channel announcement is verified before the next message is replayed, such that the
+/// channel's updates following it are accepted, and the outcome doesn't depend on the chain
+/// source's latency.
+pub(crate) async fn replay_gossip<L: Deref + Clone + Send + Sync + 'static>(router: &GossipRouter<L>, files: &[PathBuf], speed: Option<f64>, logger: L) -> Result<ReplayCounts, String> where L::Target: Logger {
+	let mut counts = ReplayCounts { replayed: 0, rejected: 0 };
+	let mut replay_start: Option<(u64, Instant)> = None;
+	for file in files {
+		log_info!(logger, "Replaying gossip from {}", file.display());
+		let contents = tokio::fs::read_to_string(file).await.map_err(|e| format!("Failed to read recording {}: {}", file.display(), e))?;
+		for (line_index, line) in contents.lines().enumerate() {
+			let invalid_line = |e: String| format!("Invalid line {} of recording {}: {}", line_index + 1, file.display(), e);
+			let record = RecordedMessage::from_line(line).map_err(invalid_line)?;
+
+			if let Some(speed) = speed {
+				let (first_received_at_ms, started_at) = *replay_start.get_or_insert((record.received_at_ms, Instant::now()));
+				let offset_ms = record.received_at_ms.saturating_sub(first_received_at_ms) as f64 / speed;
+				tokio::time::sleep_until(started_at + Duration::from_secs_f64(offset_ms / 1000.0)).await;
+			}
+
+			let (message_type, result) = replay_message(router, &record).map_err(invalid_line)?;
+			counts.replayed += 1;
+			if let Err(e) = result {
+				log_gossip!(logger, "Rejected replayed message on line {} of {}: {}", line_index + 1, file.display(), e.err);
+				counts.rejected += 1;
+			}
+			if message_type == CHANNEL_ANNOUNCEMENT_TYPE {
+				router.wait_for_utxo_lookups().await;
+			}
+			// hand the announcements accepted once verified to the persister
+			router.get_and_clear_pending_msg_events();
+		}
+	}
+	Ok(counts)
+}
+
+#[cfg(test)]
+mod tests {
+	use bitcoin::Amount;
+
+	use crate::chain::ChainSource;
+	use crate::replay::UtxoSetChainSource;
+
+	#[tokio::test]
+	async fn test_utxo_set() {
+		let utxo_set = UtxoSetChainSource::parse("\
+			# block x transaction x output, amount, script pubkey\n\
+			\n\
+			870000x12x1 250000 0020aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa\n\
+			956575621577719809 1000000 0014bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb\n\
+		").unwrap();
+		let (txid, output) = utxo_set.funding_output(870000, 12, 1).await.unwrap().unwrap();
+		assert_eq!(output.value, Amount::from_sat(250_000));
+		assert!(output.script_pubkey.is_p2wsh());
+		// distinct outputs have distinct synthetic transaction ids
+		let (other_txid, other_output) = utxo_set.funding_output(870000, 7711984, 16385).await.unwrap().unwrap();
+		assert_eq!(other_output.value, Amount::from_sat(1_000_000));
+		assert_ne!(txid, other_txid);
+		assert!(utxo_set.funding_output(870000, 12, 0).await.unwrap().is_none());
+		assert!(utxo_set.block_at_height(870000).await.is_err());
+
+		assert!(UtxoSetChainSource::parse("870000x12x1 250000").is_err());
+		assert!(UtxoSetChainSource::parse("870000x12 250000 0014").is_err());
+		assert!(UtxoSetChainSource::parse("870000x12x1 lots 0014").is_err());
+		assert!(UtxoSetChainSource::parse("870000x12x1 250000 00zz").is_err());
+	}
+}
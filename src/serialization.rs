@@ -40,6 +40,25 @@ impl Default for DefaultUpdateValues {
 	}
 }
 
+impl DefaultUpdateValues {
+	/// The number of bytes the full updates among `updates` save by leaving out the values matching
+	/// these defaults
+	pub(super) fn omitted_bytes(&self, updates: &[UpdateSerialization]) -> usize {
+		updates.iter().map(|update| match update {
+			UpdateSerialization::Full(update) => {
+				let mut omitted_bytes = 0;
+				if update.cltv_expiry_delta == self.cltv_expiry_delta { omitted_bytes += 2; };
+				if update.htlc_minimum_msat == self.htlc_minimum_msat { omitted_bytes += 8; };
+				if update.fee_base_msat == self.fee_base_msat { omitted_bytes += 4; };
+				if update.fee_proportional_millionths == self.fee_proportional_millionths { omitted_bytes += 4; };
+				if update.htlc_maximum_msat == self.htlc_maximum_msat { omitted_bytes += 8; };
+				omitted_bytes
+			},
+			_ => 0,
+		}).sum()
+	}
+}
+
 pub(super) struct MutatedProperties {
 	pub(super) flags: bool,
 	pub(super) cltv_expiry_delta: bool,
@@ -101,6 +120,7 @@ impl UpdateSerialization {
 pub(super) struct MutatedNodeProperties {
 	pub(super) addresses: bool,
 	pub(super) features: bool,
+	pub(super) alias: bool,
 }
 
 pub(super) enum NodeSerializationStrategy {
@@ -122,14 +142,14 @@ struct FullUpdateValueHistograms {
 	htlc_maximum_msat: HashMap<u64, usize>,
 }
 
-pub(super) fn serialize_delta_set(channel_delta_set: DeltaSet, node_delta_set: NodeDeltaSet, last_sync_timestamp: u32) -> SerializationSet {
+pub(super) fn serialize_delta_set(chain_hash: ChainHash, channel_delta_set: DeltaSet, node_delta_set: NodeDeltaSet, last_sync_timestamp: u32) -> SerializationSet {
 	let mut serialization_set = SerializationSet {
 		announcements: vec![],
 		updates: vec![],
 		full_update_defaults: Default::default(),
 		node_announcement_feature_defaults: vec![],
 		node_mutations: Default::default(),
-		chain_hash: ChainHash::using_genesis_block(config::network()),
+		chain_hash,
 		latest_seen: 0,
 	};
 
@@ -206,6 +226,7 @@ pub(super) fn serialize_delta_set(channel_delta_set: DeltaSet, node_delta_set: N
 					}
 				} else if is_newly_included_announcement {
 					if let Some(unannounced_update) = updates.last_update_before_seen {
+						record_full_update_in_histograms(&unannounced_update.update);
 						serialization_set.updates.push(UpdateSerialization::Full(unannounced_update.update));
 					}
 				} else if let Some(flags) = updates.serialization_update_flags {
@@ -226,7 +247,10 @@ pub(super) fn serialize_delta_set(channel_delta_set: DeltaSet, node_delta_set: N
 		htlc_maximum_msat: find_most_common_histogram_entry_with_default(full_update_histograms.htlc_maximum_msat, 0),
 	};
 
-	serialization_set.full_update_defaults = default_update_values;
+	// the full snapshot may leave its defaults at zero rather than precomputing them each round
+	if last_sync_timestamp > 0 || config::full_snapshot_defaults() {
+		serialization_set.full_update_defaults = default_update_values;
+	}
 
 	serialization_set.node_mutations = node_delta_set.into_iter().filter_map(|(id, mut delta)| {
 		if delta.strategy.is_none() {
@@ -362,8 +386,9 @@ pub(super) fn serialize_stripped_channel_update(update: &UpdateSerialization, de
 	prefixed_serialization
 }
 
-pub(super) fn find_most_common_histogram_entry_with_default<T: Copy>(histogram: HashMap<T, usize>, default: T) -> T {
-	let most_frequent_entry = histogram.iter().max_by(|a, b| a.1.cmp(&b.1));
+pub(super) fn find_most_common_histogram_entry_with_default<T: Copy + Ord>(histogram: HashMap<T, usize>, default: T) -> T {
+	// break ties in favor of the lower value, such that an unchanged graph yields the same defaults
+	let most_frequent_entry = histogram.iter().max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(a.0)));
 	if let Some(entry_details) = most_frequent_entry {
 		// .0 is the value
 		// .1 is the frequency
@@ -379,3 +404,53 @@ pub(super) fn find_leading_histogram_entries(histogram: HashMap<&NodeFeatures, u
 	entry_counts.sort_by(|a, b| b.1.cmp(&a.1));
 	entry_counts.into_iter().take(count).map(|(&features, _count)| features.clone()).collect()
 }
+
+#[cfg(test)]
+mod tests {
+	use std::collections::HashMap;
+
+	use bitcoin::blockdata::constants::ChainHash;
+	use bitcoin::Network;
+	use lightning::ln::msgs::UnsignedChannelUpdate;
+
+	use crate::serialization::{find_most_common_histogram_entry_with_default, DefaultUpdateValues, UpdateSerialization};
+
+	fn update(short_channel_id: u64, fee_base_msat: u32) -> UnsignedChannelUpdate {
+		UnsignedChannelUpdate {
+			chain_hash: ChainHash::using_genesis_block(Network::Bitcoin),
+			short_channel_id,
+			timestamp: 1_700_000_000,
+			message_flags: 1,
+			channel_flags: 0,
+			cltv_expiry_delta: 144,
+			htlc_minimum_msat: 1000,
+			htlc_maximum_msat: 100_000_000,
+			fee_base_msat,
+			fee_proportional_millionths: 100,
+			excess_data: vec![],
+		}
+	}
+
+	#[test]
+	fn test_default_update_values() {
+		// ties are broken in favor of the lower value
+		let histogram: HashMap<u32, usize> = [(1000, 2), (0, 2), (500, 1)].into_iter().collect();
+		assert_eq!(find_most_common_histogram_entry_with_default(histogram, 42), 0);
+		assert_eq!(find_most_common_histogram_entry_with_default(HashMap::<u32, usize>::new(), 42), 42);
+
+		let defaults = DefaultUpdateValues {
+			cltv_expiry_delta: 144,
+			htlc_minimum_msat: 1000,
+			fee_base_msat: 1000,
+			fee_proportional_millionths: 100,
+			htlc_maximum_msat: 100_000_000,
+		};
+		let updates = vec![
+			UpdateSerialization::Full(update(1, 1000)),
+			UpdateSerialization::Full(update(2, 0)),
+			UpdateSerialization::Reminder(3, 0),
+		];
+		assert_eq!(defaults.omitted_bytes(&updates), 26 + 22);
+		assert_eq!(DefaultUpdateValues::default().omitted_bytes(&updates), 4);
+	}
+}
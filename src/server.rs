@@ -0,0 +1,776 @@
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use futures::future::{BoxFuture, Shared};
+use futures::{FutureExt, StreamExt};
+use http_body_util::combinators::UnsyncBoxBody;
+use http_body_util::{BodyExt, Full, StreamBody};
+use hyper::body::{Bytes, Frame, Incoming};
+use hyper::header::{HeaderValue, ACCEPT_ENCODING, ALLOW, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_TYPE, RETRY_AFTER, USER_AGENT, VARY};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use lightning::{log_error, log_info, log_warn};
+use lightning::routing::gossip::NetworkGraph;
+use lightning::util::logger::Logger;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use crate::analytics::RequestAnalytics;
+use crate::compression::SnapshotCompression;
+use crate::config;
+use crate::export::{self, GraphExportFormat};
+use crate::feed::GossipFeed;
+use crate::health::{HealthMonitor, HealthReport};
+use crate::history;
+use crate::listener::{self, ListenAddress};
+use crate::metrics::Metrics;
+use crate::query::{self, Query};
+use crate::rate_limit::ClientRateLimiter;
+use crate::storage;
+use crate::tls;
+use crate::verifier::FundingAmountCache;
+
+/// Serves the snapshots written by the [`Snapshotter`](crate::snapshot::Snapshotter) under
+/// `/snapshot/<timestamp>` (and `/v<version>/snapshot/<timestamp>` or, equivalently,
+/// `/snapshot/v<version>/<timestamp>` for later serialization versions),
+/// removing the need for a reverse proxy in small deployments.
+///
+/// If enabled, snapshots for arbitrary timestamps are additionally calculated on demand under
+/// `/dynamic/<timestamp>` (and `/v<version>/dynamic/<timestamp>`) once the initial sync has completed.
+///
+/// `/healthz` and `/readyz` report the state of the gossip pipeline to orchestrators and load
+/// balancers, and `/metrics` exposes its counters to Prometheus.
+///
+/// If enabled, the current network graph is dumped under `/graph.json`, `/graph.graphml`,
+/// `/graph/channels.csv`, and `/graph/nodes.csv` for analysis, and individual channels and nodes
+/// can be looked up under `/api/channel/<scid>` and `/api/node/<pubkey>`, next to `/api/stats`.
+/// `/api/policy/<scid>` reports a channel's policy in each direction.
+///
+/// If enabled, anonymized statistics of the snapshot requests served are recorded, and their
+/// tallies since startup reported under `/api/requests`.
+///
+/// If stats sampling is enabled, the samples recorded are reported under `/api/stats/history`,
+/// covering the last week, or since a given timestamp under `/api/stats/history/<timestamp>`.
+///
+/// If enabled, newly validated gossip is streamed as server-sent events under `/gossip/stream`, to
+/// a limited number of subscribers at once, beyond which further ones are answered with a 503.
+///
+/// The snapshots of each configured profile are served under `/profiles/<name>/`, e. g.
+/// `/profiles/<name>/snapshot/<timestamp>` and `/profiles/<name>/snapshot/manifest.json`.
+///
+/// Requests to the endpoints computing their responses on demand may be rate limited per client
+/// and capped globally, in which case excess requests are answered with a 429.
+///
+/// If a certificate is configured, all of the above is served over HTTPS instead.
+pub(crate) struct SnapshotServer<L: Deref + Clone + Send + Sync + 'static> where L::Target: Logger {
+	address: ListenAddress,
+	symlink_directory: String,
+	/// The directory holding the snapshots of each profile in a subdirectory of its name
+	profile_directory: String,
+	/// The names of the configured snapshot profiles
+	profile_names: HashSet<String>,
+	/// The codecs snapshots are precompressed with, in order of preference
+	compression: Vec<SnapshotCompression>,
+	network_graph: Arc<NetworkGraph<L>>,
+	channel_funding_amounts: FundingAmountCache,
+	health_monitor: Arc<HealthMonitor>,
+	metrics: Arc<Metrics>,
+	dynamic_snapshot_cache: Option<Arc<DynamicSnapshotCache>>,
+	graph_export_enabled: bool,
+	query_api_enabled: bool,
+	/// Only set if the gossip stream is enabled
+	gossip_feed: Option<Arc<GossipFeed>>,
+	rate_limiter: Option<ClientRateLimiter>,
+	/// Bounds the number of requests to the endpoints computing their responses on demand served
+	/// at once, if limited
+	computed_request_limiter: Option<Semaphore>,
+	/// Only set if request analytics are enabled
+	request_analytics: Option<RequestAnalytics>,
+	stats_history_enabled: bool,
+	logger: L,
+}
+
+impl<L: Deref + Clone + Send + Sync + 'static> SnapshotServer<L> where L::Target: Logger {
+	pub(crate) fn new(address: ListenAddress, network_graph: Arc<NetworkGraph<L>>, channel_funding_amounts: FundingAmountCache, health_monitor: Arc<HealthMonitor>, metrics: Arc<Metrics>, gossip_feed: Arc<GossipFeed>, logger: L) -> Self {
+		let cache_path = config::cache_path(config::graph_network(&network_graph));
+		let symlink_directory = format!("{}/symlinks", cache_path);
+		let profile_directory = format!("{}/profiles", cache_path);
+		let dynamic_snapshot_cache = if config::dynamic_snapshots_enabled() {
+			Some(Arc::new(DynamicSnapshotCache::new(config::dynamic_snapshot_cache_ttl(), config::dynamic_snapshot_cache_entries())))
+		} else {
+			None
+		};
+		let mut compression: Vec<SnapshotCompression> = config::snapshot_compression().into_iter().map(|(algorithm, _)| algorithm).collect();
+		// favor the codecs with the better compression ratio
+		compression.sort_by_key(|algorithm| match algorithm {
+			SnapshotCompression::Brotli => 0,
+			SnapshotCompression::Zstd => 1,
+			SnapshotCompression::Gzip => 2,
+		});
+		let graph_export_enabled = config::graph_export_enabled();
+		let query_api_enabled = config::query_api_enabled();
+		let gossip_feed = if config::gossip_stream_enabled() { Some(gossip_feed) } else { None };
+		let profile_names = config::snapshot_profiles().into_iter().map(|profile| profile.name).collect();
+		let rate_limiter = config::http_rate_limit().map(|limit| ClientRateLimiter::new(limit, config::http_rate_limit_burst()));
+		let computed_request_limiter = config::http_max_concurrent_requests().map(Semaphore::new);
+		let request_analytics = config::http_request_analytics().map(RequestAnalytics::new);
+		let stats_history_enabled = config::stats_sample_interval().is_some();
+		Self { address, symlink_directory, profile_directory, profile_names, compression, network_graph, channel_funding_amounts, health_monitor, metrics, dynamic_snapshot_cache, graph_export_enabled, query_api_enabled, gossip_feed, rate_limiter, computed_request_limiter, request_analytics, stats_history_enabled, logger }
+	}
+
+	pub(crate) async fn serve(self) {
+		let listener = match self.address.bind().await {
+			Ok(listener) => listener,
+			Err(e) => panic!("Failed to bind snapshot server to {}: {}", self.address, e),
+		};
+		let tls_acceptor = config::http_tls_certificate().map(|(certificate_path, key_path)| {
+			tls::tls_acceptor(certificate_path, key_path, self.logger.clone())
+				.unwrap_or_else(|e| panic!("Failed to load the HTTP server's TLS certificate: {}", e))
+		});
+		log_info!(self.logger, "Serving snapshots over {} on {}", if tls_acceptor.is_some() { "HTTPS" } else { "HTTP" }, self.address);
+		if let Some(gossip_feed) = &self.gossip_feed {
+			gossip_feed.spawn_removal_tracking(Arc::clone(&self.network_graph));
+		}
+		if let Some(request_analytics) = &self.request_analytics {
+			request_analytics.spawn_recording(config::graph_network(&self.network_graph), self.logger.clone());
+		}
+
+		let server = Arc::new(self);
+		loop {
+			let (stream, client_address) = match listener.accept().await {
+				Ok(connection) => connection,
+				Err(e) => {
+					log_warn!(server.logger, "Failed to accept snapshot server connection: {}", e);
+					continue;
+				}
+			};
+
+			let connection_server = Arc::clone(&server);
+			let tls_acceptor = tls_acceptor.clone();
+			tokio::spawn(async move {
+				let tls_acceptor = match tls_acceptor {
+					Some(tls_acceptor) => tls_acceptor,
+					None => return connection_server.serve_connection(stream, client_address).await,
+				};
+				match tokio::time::timeout(config::TLS_HANDSHAKE_TIMEOUT, tls_acceptor.accept(stream)).await {
+					Ok(Ok(stream)) => connection_server.serve_connection(stream, client_address).await,
+					Ok(Err(e)) => log_warn!(connection_server.logger, "TLS handshake with {} failed: {}", Self::describe_client(client_address), e),
+					Err(_) => log_warn!(connection_server.logger, "TLS handshake with {} timed out", Self::describe_client(client_address)),
+				}
+			});
+		}
+	}
+
+	async fn serve_connection<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(self: Arc<Self>, stream: S, client_address: Option<SocketAddr>) {
+		let service = service_fn(|request| {
+			let request_server = Arc::clone(&self);
+			async move {
+				if let Some(gossip_feed) = request_server.gossip_feed.as_ref().filter(|_| request.method() == Method::GET && request.uri().path() == "/gossip/stream") {
+					// subscribers hold no permit of the concurrency cap, as they remain subscribed indefinitely
+					if let Err(retry_after) = request_server.admit_client(listener::client_ip(client_address)) {
+						return Ok::<_, Infallible>(Self::too_many_requests_response(retry_after).map(|body| body.boxed_unsync()));
+					}
+					return Ok(Self::gossip_stream_response(gossip_feed));
+				}
+				Ok(request_server.handle_request(request, listener::client_ip(client_address)).await.map(|body| body.boxed_unsync()))
+			}
+		});
+		if let Err(e) = http1::Builder::new().serve_connection(TokioIo::new(stream), service).await {
+			log_error!(self.logger, "Error serving {}: {}", Self::describe_client(client_address), e);
+		}
+	}
+
+	fn describe_client(client_address: Option<SocketAddr>) -> String {
+		client_address.map_or("a unix socket client".to_string(), |address| address.to_string())
+	}
+
+	async fn handle_request(&self, request: Request<Incoming>, client: IpAddr) -> Response<Full<Bytes>> {
+		if request.method() != Method::GET && request.method() != Method::HEAD {
+			let mut response = Self::empty_response(StatusCode::METHOD_NOT_ALLOWED);
+			response.headers_mut().insert(ALLOW, HeaderValue::from_static("GET, HEAD"));
+			return response;
+		}
+
+		let request_path = request.uri().path();
+		if request_path == "/healthz" {
+			let (is_live, report) = self.health_monitor.check_liveness();
+			return Self::health_response(is_live, &report);
+		}
+		if request_path == "/readyz" {
+			let (is_ready, report) = self.health_monitor.check_readiness().await;
+			return Self::health_response(is_ready, &report);
+		}
+		if request_path == "/metrics" {
+			let mut response = Response::new(Full::new(Bytes::from(self.metrics.render())));
+			response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("text/plain; version=0.0.4"));
+			response.headers_mut().insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+			return response;
+		}
+		if let Some(request_analytics) = self.request_analytics.as_ref().filter(|_| request_path == "/api/requests") {
+			let body = if request.method() == Method::HEAD { Bytes::new() } else { Bytes::from(request_analytics.stats_json()) };
+			let mut response = Response::new(Full::new(body));
+			response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+			response.headers_mut().insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+			return response;
+		}
+
+		let stats_history_since = if self.stats_history_enabled { history::parse_history_path(request_path, SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()) } else { None };
+		let is_computed = (self.graph_export_enabled && graph_export_format(request_path).is_some())
+			|| stats_history_since.is_some()
+			|| (self.query_api_enabled && query::parse_query(request_path).is_some())
+			|| (self.dynamic_snapshot_cache.is_some() && parse_timestamp_path(request_path, "dynamic").is_some());
+		// held until the response has been computed
+		let _computed_request_permit = if is_computed {
+			match self.admit_computed_request(client) {
+				Ok(permit) => permit,
+				Err(retry_after) => return Self::too_many_requests_response(retry_after),
+			}
+		} else {
+			None
+		};
+
+		if let Some(since) = stats_history_since {
+			let since = match since {
+				Some(since) => since,
+				None => return Self::empty_response(StatusCode::BAD_REQUEST),
+			};
+			let store = storage::open_for_reads(config::graph_network(&self.network_graph), self.logger.clone()).await;
+			let history = history::history_json(&*store, since).await;
+			let body = if request.method() == Method::HEAD { Bytes::new() } else { Bytes::from(history) };
+			let mut response = Response::new(Full::new(body));
+			response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+			response.headers_mut().insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+			return response;
+		}
+
+		if self.graph_export_enabled {
+			if let Some(format) = graph_export_format(request_path) {
+				let network_graph = Arc::clone(&self.network_graph);
+				// walking the whole graph takes a while, so keep it off the connection tasks
+				let graph = tokio::task::spawn_blocking(move || export::export_graph(&network_graph, format)).await.unwrap();
+				let body = if request.method() == Method::HEAD { Bytes::new() } else { Bytes::from(graph) };
+				let mut response = Response::new(Full::new(body));
+				response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static(format.content_type()));
+				response.headers_mut().insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+				return response;
+			}
+		}
+
+		if self.query_api_enabled {
+			if let Some(query) = query::parse_query(request_path) {
+				return self.serve_query(&request, query);
+			}
+		}
+
+		if let Some(dynamic_snapshot_cache) = &self.dynamic_snapshot_cache {
+			if let Some((serialization_version, last_sync_timestamp)) = parse_timestamp_path(request_path, "dynamic") {
+				return self.serve_dynamic_snapshot(&request, dynamic_snapshot_cache, serialization_version, last_sync_timestamp).await;
+			}
+		}
+
+		// a profile's snapshots are laid out just like the full ones, within a directory of their own
+		let (symlink_directory, request_path, profile_name) = match split_profile_path(request_path) {
+			Some((profile_name, profile_path)) if self.profile_names.contains(profile_name) => {
+				(format!("{}/{}/symlinks", self.profile_directory, profile_name), profile_path, Some(profile_name))
+			}
+			Some(_) => return Self::empty_response(StatusCode::NOT_FOUND),
+			None => (self.symlink_directory.clone(), request_path, None),
+		};
+
+		if request_path == "/snapshot/manifest.json" {
+			return match tokio::fs::read(format!("{}/manifest.json", symlink_directory)).await {
+				Ok(manifest) => {
+					let mut response = Self::snapshot_response(&request, Bytes::from(manifest), seconds_until_next_snapshot());
+					response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+					response
+				}
+				Err(_) => Self::empty_response(StatusCode::NOT_FOUND),
+			};
+		}
+
+		let (relative_path, last_sync_timestamp) = match (snapshot_file_path(request_path), parse_timestamp_path(split_signature_suffix(request_path).0, "snapshot")) {
+			(Some(path), Some((_, last_sync_timestamp))) => (path, last_sync_timestamp),
+			_ => return Self::empty_response(StatusCode::NOT_FOUND),
+		};
+
+		// the symlinks are swapped out atomically by the snapshotter, so reading them while
+		// snapshots are being regenerated is safe
+		let snapshot_path = format!("{}/{}", symlink_directory, relative_path);
+		if split_signature_suffix(request_path).1.is_some() {
+			return match tokio::fs::read(&snapshot_path).await {
+				Ok(signature) => Self::snapshot_response(&request, Bytes::from(signature), seconds_until_next_snapshot()),
+				Err(_) => Self::empty_response(StatusCode::NOT_FOUND),
+			};
+		}
+		let accept_encoding = request.headers().get(ACCEPT_ENCODING).and_then(|value| value.to_str().ok()).unwrap_or("");
+		let mut content_encoding = None;
+		for algorithm in self.compression.iter().filter(|algorithm| accepts_encoding(accept_encoding, algorithm.content_encoding())) {
+			if let Ok(snapshot) = tokio::fs::read(format!("{}.{}", snapshot_path, algorithm.file_extension())).await {
+				content_encoding = Some((*algorithm, snapshot));
+				break;
+			}
+		}
+		let (snapshot, content_encoding) = match content_encoding {
+			Some((algorithm, snapshot)) => (snapshot, Some(algorithm.content_encoding())),
+			None => match tokio::fs::read(&snapshot_path).await {
+				Ok(snapshot) => (snapshot, None),
+				Err(_) => return Self::empty_response(StatusCode::NOT_FOUND),
+			},
+		};
+
+		if let Some(request_analytics) = self.request_analytics.as_ref().filter(|_| request.method() == Method::GET) {
+			// the symlink names the snapshot it was resolved to
+			let served_snapshot = tokio::fs::read_link(&snapshot_path).await.ok()
+				.and_then(|target| target.file_name().and_then(|name| name.to_str()).map(str::to_string))
+				.unwrap_or(relative_path);
+			request_analytics.record(Self::user_agent(&request), profile_name, last_sync_timestamp, served_snapshot, snapshot.len() as u64);
+		}
+
+		// the snapshot behind a timestamp only changes once the next snapshots are generated
+		let mut response = Self::snapshot_response(&request, Bytes::from(snapshot), seconds_until_next_snapshot());
+		if !self.compression.is_empty() {
+			response.headers_mut().insert(VARY, HeaderValue::from_static("Accept-Encoding"));
+		}
+		if let Some(content_encoding) = content_encoding {
+			response.headers_mut().insert(CONTENT_ENCODING, HeaderValue::from_static(content_encoding));
+		}
+		response
+	}
+
+	/// Admit a request to one of the endpoints computing their responses on demand, or return how
+	/// long the client should wait if it exceeds its rate limit or the global concurrency cap.
+	fn admit_computed_request(&self, client: IpAddr) -> Result<Option<SemaphorePermit<'_>>, Duration> {
+		self.admit_client(client)?;
+		match &self.computed_request_limiter {
+			Some(limiter) => match limiter.try_acquire() {
+				Ok(permit) => Ok(Some(permit)),
+				Err(_) => {
+					self.metrics.record_http_request_throttled();
+					Err(Duration::from_secs(1))
+				}
+			},
+			None => Ok(None),
+		}
+	}
+
+	/// Charge a request against the client's rate limit, if any, or return how long the client
+	/// should wait if it exceeds it.
+	fn admit_client(&self, client: IpAddr) -> Result<(), Duration> {
+		if let Some(rate_limiter) = &self.rate_limiter {
+			if let Err(retry_after) = rate_limiter.acquire(client) {
+				self.metrics.record_http_request_throttled();
+				return Err(retry_after);
+			}
+		}
+		Ok(())
+	}
+
+	fn too_many_requests_response(retry_after: Duration) -> Response<Full<Bytes>> {
+		let mut response = Self::empty_response(StatusCode::TOO_MANY_REQUESTS);
+		// Retry-After is given in whole seconds
+		let retry_after_secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+		response.headers_mut().insert(RETRY_AFTER, HeaderValue::from(retry_after_secs.max(1)));
+		response
+	}
+
+	fn health_response(is_healthy: bool, report: &HealthReport) -> Response<Full<Bytes>> {
+		let status = if is_healthy { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+		let mut response = Response::new(Full::new(Bytes::from(serde_json::to_vec(report).unwrap())));
+		*response.status_mut() = status;
+		response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+		response.headers_mut().insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+		response
+	}
+
+	/// Stream the validated gossip until the client disconnects, unless the stream is already
+	/// serving as many subscribers as it admits
+	fn gossip_stream_response(gossip_feed: &GossipFeed) -> Response<UnsyncBoxBody<Bytes, Infallible>> {
+		let events = match gossip_feed.subscribe() {
+			Some(events) => events.map(|event| Ok(Frame::data(event))),
+			None => return Self::empty_response(StatusCode::SERVICE_UNAVAILABLE).map(|body| body.boxed_unsync()),
+		};
+		let mut response = Response::new(StreamBody::new(events).boxed_unsync());
+		response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("text/event-stream"));
+		response.headers_mut().insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+		response
+	}
+
+	fn serve_query(&self, request: &Request<Incoming>, query: Query) -> Response<Full<Bytes>> {
+		let result = match query {
+			Query::Channel(short_channel_id) => query::channel_json(&self.network_graph, &self.channel_funding_amounts, short_channel_id),
+			Query::Policy(short_channel_id) => query::policy_json(&self.network_graph, &self.channel_funding_amounts, short_channel_id),
+			Query::Node(node_id) => query::node_json(&self.network_graph, &node_id),
+			Query::Stats => Some(query::stats_json(&self.network_graph, &self.channel_funding_amounts, &self.health_monitor, &self.metrics)),
+			Query::Peers => Some(query::peers_json(&self.metrics)),
+			Query::FundingScriptMismatches => Some(query::funding_script_mismatches_json(&self.metrics)),
+			Query::Malformed => return Self::empty_response(StatusCode::BAD_REQUEST),
+		};
+		let result = match result {
+			Some(result) => result,
+			None => return Self::empty_response(StatusCode::NOT_FOUND),
+		};
+		let body = if request.method() == Method::HEAD { Bytes::new() } else { Bytes::from(result) };
+		let mut response = Response::new(Full::new(body));
+		response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+		response.headers_mut().insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+		response
+	}
+
+	async fn serve_dynamic_snapshot(&self, request: &Request<Incoming>, cache: &Arc<DynamicSnapshotCache>, serialization_version: u8, last_sync_timestamp: u64) -> Response<Full<Bytes>> {
+		if !self.health_monitor.is_initial_sync_complete() {
+			// a snapshot calculated from a partially synced graph would cause clients to miss
+			// whatever gossip we haven't seen yet
+			return Self::empty_response(StatusCode::SERVICE_UNAVAILABLE);
+		}
+		let last_sync_timestamp = match u32::try_from(last_sync_timestamp) {
+			Ok(timestamp) => timestamp,
+			Err(_) => return Self::empty_response(StatusCode::BAD_REQUEST),
+		};
+
+		let cache_key = (last_sync_timestamp, serialization_version);
+		let snapshot = match cache.get(&cache_key) {
+			Some(snapshot) => snapshot,
+			None => {
+				let network_graph = Arc::clone(&self.network_graph);
+				let logger = self.logger.clone();
+				let snapshot = cache.get_or_calculate(cache_key, move || async move {
+					log_info!(logger, "Calculating dynamic v{} snapshot since {}", serialization_version, last_sync_timestamp);
+					let delta = super::calculate_delta(network_graph, last_sync_timestamp, None, logger.clone()).await;
+					Bytes::from(super::serialize_delta(&delta, serialization_version, logger).data)
+				}).await;
+				self.metrics.set_memory_estimate("dynamic_snapshots", cache.size());
+				snapshot
+			}
+		};
+		if let Some(request_analytics) = self.request_analytics.as_ref().filter(|_| request.method() == Method::GET) {
+			request_analytics.record(Self::user_agent(request), None, last_sync_timestamp as u64, "dynamic".to_string(), snapshot.len() as u64);
+		}
+		Self::snapshot_response(request, snapshot, cache.ttl.as_secs())
+	}
+
+	fn user_agent(request: &Request<Incoming>) -> Option<&str> {
+		request.headers().get(USER_AGENT).and_then(|value| value.to_str().ok())
+	}
+
+	fn snapshot_response(request: &Request<Incoming>, snapshot: Bytes, max_age: u64) -> Response<Full<Bytes>> {
+		let body = if request.method() == Method::HEAD { Bytes::new() } else { snapshot };
+		let mut response = Response::new(Full::new(body));
+		let headers = response.headers_mut();
+		headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/octet-stream"));
+		let cache_control = format!("public, max-age={}", max_age);
+		headers.insert(CACHE_CONTROL, HeaderValue::from_str(&cache_control).unwrap());
+		response
+	}
+
+	fn empty_response(status: StatusCode) -> Response<Full<Bytes>> {
+		let mut response = Response::new(Full::new(Bytes::new()));
+		*response.status_mut() = status;
+		response
+	}
+}
+
+type PendingSnapshot = Shared<BoxFuture<'static, Bytes>>;
+
+/// Holds recently calculated on-demand snapshots, keyed by their last sync timestamp and
+/// serialization version.
+struct DynamicSnapshotCache {
+	ttl: Duration,
+	max_entries: usize,
+	entries: Mutex<HashMap<(u32, u8), (Instant, Bytes)>>,
+	/// The snapshots being calculated, which concurrent requests for them await rather than
+	/// calculating them again. Locked before `entries` when both are.
+	pending: Mutex<HashMap<(u32, u8), PendingSnapshot>>,
+}
+
+impl DynamicSnapshotCache {
+	fn new(ttl: Duration, max_entries: usize) -> Self {
+		Self { ttl, max_entries, entries: Mutex::new(HashMap::new()), pending: Mutex::new(HashMap::new()) }
+	}
+
+	/// Get the snapshot cached for `key`, or else the one being calculated for it, or else
+	/// calculate and cache it using `calculate`. The calculation runs to completion in a task of
+	/// its own, such that requests abandoned while awaiting it don't waste its work.
+	async fn get_or_calculate<F: Future<Output = Bytes> + Send + 'static>(self: &Arc<Self>, key: (u32, u8), calculate: impl FnOnce() -> F) -> Bytes {
+		let pending_snapshot = {
+			let mut pending = self.pending.lock().unwrap();
+			// the snapshot may have been cached since the caller last checked
+			if let Some(snapshot) = self.get(&key) {
+				return snapshot;
+			}
+			match pending.get(&key) {
+				Some(pending_snapshot) => pending_snapshot.clone(),
+				None => {
+					let cache = Arc::clone(self);
+					let calculation = calculate();
+					// the task can't finish before its snapshot is marked as pending, as it has to
+					// acquire the lock held here
+					let task = tokio::spawn(async move {
+						let snapshot = calculation.await;
+						let mut pending = cache.pending.lock().unwrap();
+						cache.insert(key, snapshot.clone());
+						pending.remove(&key);
+						snapshot
+					});
+					let pending_snapshot = task.map(|result| result.expect("Dynamic snapshot calculation panicked")).boxed().shared();
+					pending.insert(key, pending_snapshot.clone());
+					pending_snapshot
+				}
+			}
+		};
+		pending_snapshot.await
+	}
+
+	/// The size of the snapshots cached now
+	fn size(&self) -> u64 {
+		self.entries.lock().unwrap().values().map(|(_, snapshot)| snapshot.len() as u64).sum()
+	}
+
+	fn get(&self, key: &(u32, u8)) -> Option<Bytes> {
+		let entries = self.entries.lock().unwrap();
+		entries.get(key).filter(|(created_at, _)| created_at.elapsed() < self.ttl).map(|(_, snapshot)| snapshot.clone())
+	}
+
+	/// Cache a snapshot, returning the size of the snapshots cached now
+	fn insert(&self, key: (u32, u8), snapshot: Bytes) -> u64 {
+		let mut entries = self.entries.lock().unwrap();
+		if entries.len() >= self.max_entries {
+			entries.retain(|_, (created_at, _)| created_at.elapsed() < self.ttl);
+		}
+		if entries.len() >= self.max_entries {
+			// still full, so make room by evicting the oldest entry
+			let oldest_key = entries.iter().min_by_key(|(_, (created_at, _))| *created_at).map(|(key, _)| *key);
+			if let Some(oldest_key) = oldest_key {
+				entries.remove(&oldest_key);
+			}
+		}
+		entries.insert(key, (Instant::now(), snapshot));
+		entries.values().map(|(_, snapshot)| snapshot.len() as u64).sum()
+	}
+}
+
+/// Split a leading `v<version>/` path component off, if present.
+fn strip_version_component(path: &str) -> Option<(u8, &str)> {
+	let (component, remainder) = path.split_once('/')?;
+	let version = component.strip_prefix('v')?;
+	if version.is_empty() || !version.bytes().all(|b| b.is_ascii_digit()) {
+		return None;
+	}
+	Some((version.parse().ok()?, remainder))
+}
+
+/// Extract the serialization version and timestamp from a path of the form
+/// `/[v<version>/]<prefix>/[v<version>/]<timestamp>[.bin]`, rejecting anything that isn't a plain
+/// timestamp or a supported version. Absent a version, the request is for version 1.
+fn parse_timestamp_path(request_path: &str, prefix: &str) -> Option<(u8, u64)> {
+	let mut path = request_path.strip_prefix('/')?;
+	let mut serialization_version = None;
+	if let Some((version, remainder)) = strip_version_component(path) {
+		serialization_version = Some(version);
+		path = remainder;
+	}
+	path = path.strip_prefix(prefix)?.strip_prefix('/')?;
+	if serialization_version.is_none() {
+		if let Some((version, remainder)) = strip_version_component(path) {
+			serialization_version = Some(version);
+			path = remainder;
+		}
+	}
+	let serialization_version = serialization_version.unwrap_or(1);
+	if !config::SUPPORTED_SERIALIZATION_VERSIONS.contains(&serialization_version) {
+		return None;
+	}
+
+	let timestamp = path.strip_suffix(".bin").unwrap_or(path);
+	if timestamp.is_empty() || !timestamp.bytes().all(|b| b.is_ascii_digit()) {
+		return None;
+	}
+	Some((serialization_version, timestamp.parse().ok()?))
+}
+
+/// Split a path of the form `/profiles/<name>/<path>` into the profile name and `/<path>`.
+fn split_profile_path(request_path: &str) -> Option<(&str, &str)> {
+	let path = request_path.strip_prefix("/profiles/")?;
+	let separator_index = path.find('/')?;
+	Some((&path[..separator_index], &path[separator_index..]))
+}
+
+/// Split the suffix of a detached signature off a request path, e. g. `/snapshot/0.bin.gz.sig`
+/// into `/snapshot/0.bin` and `.gz.sig`.
+fn split_signature_suffix(request_path: &str) -> (&str, Option<&str>) {
+	let signed_path = match request_path.strip_suffix(".sig") {
+		Some(signed_path) => signed_path,
+		None => return (request_path, None),
+	};
+	// the signature of a precompressed variant is named after the variant
+	let unsigned_path = signed_path.rsplit_once('.')
+		.filter(|(_, extension)| SnapshotCompression::from_name(extension).is_some_and(|algorithm| algorithm.file_extension() == *extension))
+		.map_or(signed_path, |(unsigned_path, _)| unsigned_path);
+	(unsigned_path, Some(&request_path[unsigned_path.len()..]))
+}
+
+/// Map a request path onto the snapshot file relative to the symlink directory, or onto the
+/// detached signature of the snapshot or one of its precompressed variants.
+fn snapshot_file_path(request_path: &str) -> Option<String> {
+	let (request_path, signature_suffix) = split_signature_suffix(request_path);
+	let (serialization_version, timestamp) = parse_timestamp_path(request_path, "snapshot")?;
+	let signature_suffix = signature_suffix.unwrap_or("");
+	if serialization_version == 1 {
+		return Some(format!("{}.bin{}", timestamp, signature_suffix));
+	}
+	Some(format!("v{}/{}.bin{}", serialization_version, timestamp, signature_suffix))
+}
+
+/// Map a request path onto the format the graph is to be dumped in, if it's an export path
+fn graph_export_format(request_path: &str) -> Option<GraphExportFormat> {
+	match request_path {
+		"/graph.json" => Some(GraphExportFormat::Json),
+		"/graph.graphml" => Some(GraphExportFormat::GraphMl),
+		"/graph/channels.csv" => Some(GraphExportFormat::ChannelsCsv),
+		"/graph/nodes.csv" => Some(GraphExportFormat::NodesCsv),
+		_ => None,
+	}
+}
+
+/// Whether an `Accept-Encoding` header value admits the given content encoding
+fn accepts_encoding(accept_encoding: &str, encoding: &str) -> bool {
+	accept_encoding.split(',').any(|entry| {
+		let mut parameters = entry.split(';').map(str::trim);
+		let coding = parameters.next().unwrap_or("");
+		if !coding.eq_ignore_ascii_case(encoding) && coding != "*" {
+			return false;
+		}
+		// a quality value of 0 explicitly rules out the encoding
+		!parameters.any(|parameter| {
+			parameter.strip_prefix("q=").and_then(|quality| quality.parse::<f32>().ok()).is_some_and(|quality| quality <= 0.0)
+		})
+	})
+}
+
+fn seconds_until_next_snapshot() -> u64 {
+	let snapshot_interval = config::snapshot_generation_interval() as u64;
+	let current_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+	snapshot_interval - current_time % snapshot_interval
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::atomic::{AtomicUsize, Ordering};
+	use std::sync::Arc;
+	use std::time::Duration;
+
+	use hyper::body::Bytes;
+
+	use crate::export::GraphExportFormat;
+	use crate::server::{accepts_encoding, graph_export_format, parse_timestamp_path, snapshot_file_path, split_profile_path, DynamicSnapshotCache};
+
+	#[test]
+	fn test_snapshot_file_path() {
+		assert_eq!(snapshot_file_path("/snapshot/0"), Some("0.bin".to_string()));
+		assert_eq!(snapshot_file_path("/snapshot/1700000000"), Some("1700000000.bin".to_string()));
+		assert_eq!(snapshot_file_path("/snapshot/1700000000.bin"), Some("1700000000.bin".to_string()));
+		assert_eq!(snapshot_file_path("/snapshot/v2/1700000000"), Some("v2/1700000000.bin".to_string()));
+		assert_eq!(snapshot_file_path("/v2/snapshot/1700000000"), Some("v2/1700000000.bin".to_string()));
+		assert_eq!(snapshot_file_path("/v1/snapshot/1700000000"), Some("1700000000.bin".to_string()));
+		assert_eq!(snapshot_file_path("/v2/snapshot/v2/1700000000"), None);
+		assert_eq!(snapshot_file_path("/snapshot/"), None);
+		assert_eq!(snapshot_file_path("/snapshot/v2/"), None);
+		assert_eq!(snapshot_file_path("/snapshot/-1"), None);
+		assert_eq!(snapshot_file_path("/snapshot/../network_graph.bin"), None);
+		assert_eq!(snapshot_file_path("/snapshot/v3/1700000000"), None);
+		assert_eq!(snapshot_file_path("/1700000000.bin"), None);
+		assert_eq!(snapshot_file_path("/snapshot/1700000000.bin.sig"), Some("1700000000.bin.sig".to_string()));
+		assert_eq!(snapshot_file_path("/snapshot/v2/1700000000.bin.gz.sig"), Some("v2/1700000000.bin.gz.sig".to_string()));
+		assert_eq!(snapshot_file_path("/v2/snapshot/1700000000.br.sig"), Some("v2/1700000000.bin.br.sig".to_string()));
+		assert_eq!(snapshot_file_path("/snapshot/1700000000.bin.zst.sig"), Some("1700000000.bin.zst.sig".to_string()));
+		assert_eq!(snapshot_file_path("/snapshot/1700000000.bin.gzip.sig"), None);
+		assert_eq!(snapshot_file_path("/snapshot/1700000000.bin.gz"), None);
+	}
+
+	#[test]
+	fn test_split_profile_path() {
+		assert_eq!(split_profile_path("/profiles/mobile/snapshot/1700000000"), Some(("mobile", "/snapshot/1700000000")));
+		assert_eq!(split_profile_path("/profiles/mobile/snapshot/manifest.json"), Some(("mobile", "/snapshot/manifest.json")));
+		assert_eq!(split_profile_path("/profiles/mobile"), None);
+		assert_eq!(split_profile_path("/snapshot/1700000000"), None);
+	}
+
+	#[test]
+	fn test_parse_timestamp_path() {
+		assert_eq!(parse_timestamp_path("/dynamic/1700000123", "dynamic"), Some((1, 1700000123)));
+		assert_eq!(parse_timestamp_path("/dynamic/v2/1700000123.bin", "dynamic"), Some((2, 1700000123)));
+		assert_eq!(parse_timestamp_path("/dynamic/v2/+1", "dynamic"), None);
+		assert_eq!(parse_timestamp_path("/snapshot/1700000123", "dynamic"), None);
+	}
+
+	#[test]
+	fn test_graph_export_format() {
+		assert_eq!(graph_export_format("/graph.json"), Some(GraphExportFormat::Json));
+		assert_eq!(graph_export_format("/graph/channels.csv"), Some(GraphExportFormat::ChannelsCsv));
+		assert_eq!(graph_export_format("/graph/edges.csv"), None);
+		assert_eq!(graph_export_format("/graph.xml"), None);
+	}
+
+	#[test]
+	fn test_accepts_encoding() {
+		assert!(accepts_encoding("gzip, deflate, br", "br"));
+		assert!(accepts_encoding("gzip;q=0.5, zstd", "gzip"));
+		assert!(accepts_encoding("*", "zstd"));
+		assert!(!accepts_encoding("gzip;q=0", "gzip"));
+		assert!(!accepts_encoding("gzip", "br"));
+		assert!(!accepts_encoding("", "gzip"));
+	}
+
+	#[test]
+	fn test_dynamic_snapshot_cache_eviction() {
+		let cache = DynamicSnapshotCache::new(Duration::from_secs(60), 2);
+		cache.insert((1, 1), Bytes::from_static(&[1]));
+		cache.insert((2, 1), Bytes::from_static(&[2]));
+		assert_eq!(cache.get(&(1, 1)), Some(Bytes::from_static(&[1])));
+		assert_eq!(cache.get(&(1, 2)), None);
+
+		// the oldest entry makes room for the new one
+		assert_eq!(cache.insert((3, 1), Bytes::from_static(&[3, 3])), 3);
+		assert_eq!(cache.get(&(1, 1)), None);
+		assert_eq!(cache.get(&(2, 1)), Some(Bytes::from_static(&[2])));
+		assert_eq!(cache.get(&(3, 1)), Some(Bytes::from_static(&[3, 3])));
+
+		let expired_cache = DynamicSnapshotCache::new(Duration::ZERO, 2);
+		expired_cache.insert((1, 1), Bytes::from_static(&[1]));
+		assert_eq!(expired_cache.get(&(1, 1)), None);
+	}
+
+	#[tokio::test]
+	async fn test_dynamic_snapshot_coalescing() {
+		let cache = Arc::new(DynamicSnapshotCache::new(Duration::from_secs(60), 2));
+		let calculation_count = Arc::new(AtomicUsize::new(0));
+		let calculate = || {
+			let calculation_count = Arc::clone(&calculation_count);
+			move || async move {
+				calculation_count.fetch_add(1, Ordering::SeqCst);
+				tokio::time::sleep(Duration::from_millis(50)).await;
+				Bytes::from_static(&[1, 2])
+			}
+		};
+		// concurrent requests for the same snapshot share a single calculation
+		let (first_snapshot, second_snapshot) = tokio::join!(cache.get_or_calculate((1, 2), calculate()), cache.get_or_calculate((1, 2), calculate()));
+		assert_eq!(first_snapshot, Bytes::from_static(&[1, 2]));
+		assert_eq!(second_snapshot, first_snapshot);
+		assert_eq!(calculation_count.load(Ordering::SeqCst), 1);
+		assert_eq!(cache.get(&(1, 2)), Some(first_snapshot));
+		assert!(cache.pending.lock().unwrap().is_empty());
+		assert_eq!(cache.size(), 2);
+
+		// an abandoned request still has its snapshot cached
+		let abandoned_request = tokio::time::timeout(Duration::from_millis(10), cache.get_or_calculate((3, 2), calculate())).await;
+		assert!(abandoned_request.is_err());
+		assert_eq!(cache.get_or_calculate((3, 2), calculate()).await, Bytes::from_static(&[1, 2]));
+		assert_eq!(calculation_count.load(Ordering::SeqCst), 2);
+	}
+}
@@ -0,0 +1,52 @@
+//! Detached signatures for generated snapshots.
+//!
+//! If a signing key is configured, every snapshot file (and the manifest) is accompanied by a
+//! `.sig` file containing the 64-byte compact ECDSA signature over the SHA-256 digest of the
+//! file's contents, created with the configured secp256k1 key. Mirrors and clients retrieving
+//! snapshots via a CDN can authenticate them against the operator's public key using
+//! [`verify_snapshot_signature`].
+
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::secp256k1::{ecdsa, Message, PublicKey, Secp256k1, SecretKey};
+
+fn snapshot_digest(snapshot: &[u8]) -> Message {
+	Message::from_digest(sha256::Hash::hash(snapshot).to_byte_array())
+}
+
+pub(crate) fn sign_snapshot(snapshot: &[u8], signing_key: &SecretKey) -> [u8; 64] {
+	let secp_ctx = Secp256k1::signing_only();
+	secp_ctx.sign_ecdsa(&snapshot_digest(snapshot), signing_key).serialize_compact()
+}
+
+/// Check the contents of a `.sig` file against the snapshot (or manifest) it was generated for
+/// and the public key of the server operator.
+pub fn verify_snapshot_signature(snapshot: &[u8], signature: &[u8], public_key: &PublicKey) -> bool {
+	let signature = match ecdsa::Signature::from_compact(signature) {
+		Ok(signature) => signature,
+		Err(_) => return false,
+	};
+	let secp_ctx = Secp256k1::verification_only();
+	secp_ctx.verify_ecdsa(&snapshot_digest(snapshot), &signature, public_key).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+	use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+
+	use crate::signing::{sign_snapshot, verify_snapshot_signature};
+
+	#[test]
+	fn test_snapshot_signature() {
+		let secp_ctx = Secp256k1::new();
+		let signing_key = SecretKey::from_slice(&[42; 32]).unwrap();
+		let public_key = PublicKey::from_secret_key(&secp_ctx, &signing_key);
+		let other_public_key = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[43; 32]).unwrap());
+
+		let snapshot = [76, 68, 75, 1, 2, 3];
+		let signature = sign_snapshot(&snapshot, &signing_key);
+		assert!(verify_snapshot_signature(&snapshot, &signature, &public_key));
+		assert!(!verify_snapshot_signature(&snapshot, &signature, &other_public_key));
+		assert!(!verify_snapshot_signature(&snapshot[1..], &signature, &public_key));
+		assert!(!verify_snapshot_signature(&snapshot, &signature[1..], &public_key));
+	}
+}
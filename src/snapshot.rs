@@ -4,46 +4,65 @@ use std::ops::Deref;
 use std::os::unix::fs::symlink;
 use std::sync::Arc;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
-use lightning::log_info;
+use bitcoin::secp256k1::SecretKey;
+use futures::{stream, StreamExt};
+use lightning::{log_error, log_info, log_warn};
+use tokio::sync::{watch, Notify};
 
 use lightning::routing::gossip::NetworkGraph;
 use lightning::util::logger::Logger;
 
+use crate::archive::SnapshotArchive;
+use crate::changes::ChannelChangeIndex;
+use crate::compression::SnapshotCompression;
 use crate::config;
-use crate::config::cache_path;
+use crate::manifest::{sha256_hex, ManifestFileVariant, ManifestSnapshot, SnapshotManifest};
+use crate::hooks::SnapshotHooks;
+use crate::leader::Leadership;
+use crate::metrics::{Metrics, SnapshotRecord, SnapshotSeries};
+use crate::profiles::ProfileSelection;
+use crate::signing::sign_snapshot;
+use crate::upload::SnapshotUploader;
+use crate::validation::validate_snapshot;
 
 pub(crate) struct Snapshotter<L: Deref + Clone> where L::Target: Logger {
 	network_graph: Arc<NetworkGraph<L>>,
+	/// Only set if the persisted gossip's changed channels are tracked
+	channel_changes: Option<Arc<ChannelChangeIndex>>,
+	uploader: Option<SnapshotUploader<L>>,
+	hooks: Option<SnapshotHooks<L>>,
+	/// The directory each round of snapshots is archived in, if they're archived
+	archive_directory: Option<String>,
+	metrics: Arc<Metrics>,
 	logger: L,
 }
 
-impl<L: Deref + Clone> Snapshotter<L> where L::Target: Logger {
-	pub fn new(network_graph: Arc<NetworkGraph<L>>, logger: L) -> Self {
-		Self { network_graph, logger }
+impl<L: Deref + Clone + Send + Sync + 'static> Snapshotter<L> where L::Target: Logger {
+	pub fn new(network_graph: Arc<NetworkGraph<L>>, channel_changes: Option<Arc<ChannelChangeIndex>>, metrics: Arc<Metrics>, logger: L) -> Self {
+		let network = config::graph_network(&network_graph);
+		let uploader = config::s3_upload_config(network)
+			.map(|upload_config| SnapshotUploader::new(upload_config, logger.clone()));
+		let hooks = SnapshotHooks::new(config::snapshot_webhook_url(network), config::snapshot_hook_command(network), logger.clone());
+		let archive_directory = config::snapshot_archive_directory(network);
+		Self { network_graph, channel_changes, uploader, hooks, archive_directory, metrics, logger }
 	}
 
-	pub(crate) async fn snapshot_gossip(&self) {
+	/// Keep capturing snapshots at the configured interval until a shutdown is initiated. A capture
+	/// in progress is completed first. Notifying `trigger` captures snapshots immediately. Rounds
+	/// are skipped while `leadership` isn't held, as the leader captures them instead.
+	pub(crate) async fn snapshot_gossip(&self, trigger: &Notify, leadership: &Leadership, mut shutdown: watch::Receiver<bool>) {
 		log_info!(self.logger, "Initiating snapshotting service");
 
 		let snapshot_interval = config::snapshot_generation_interval() as u64;
-		let mut snapshot_scopes = vec![];
-		{ // double the coefficient until it reaches the maximum (limited) snapshot scope
-			let mut current_scope = snapshot_interval;
-			loop {
-				snapshot_scopes.push(current_scope);
-				if current_scope >= config::MAX_SNAPSHOT_SCOPE as u64 {
-					snapshot_scopes.push(u64::MAX);
-					break;
-				}
-
-				// double the current factor
-				current_scope <<= 1;
-			}
-		}
+		log_info!(self.logger, "Snapshot scopes: {:?}", config::snapshot_scopes());
 
 		// this is gonna be a never-ending background job
 		loop {
-			self.generate_snapshots(config::SYMLINK_GRANULARITY_INTERVAL as u64, snapshot_interval, &snapshot_scopes, &cache_path(), None).await;
+			if leadership.is_leader() {
+				self.capture_snapshots().await;
+			} else {
+				log_info!(self.logger, "Skipping snapshot capture, as another instance is the leader");
+			}
 
 			// constructing the snapshots may have taken a while
 			let current_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
@@ -58,16 +77,125 @@ impl<L: Deref + Clone> Snapshotter<L> where L::Target: Logger {
 			log_info!(self.logger, "Sleeping until next snapshot capture: {}s", time_until_next_generation);
 			// add in an extra five seconds to assure the rounding down works correctly
 			let sleep = tokio::time::sleep(Duration::from_secs(time_until_next_generation + 5));
-			sleep.await;
+			tokio::select! {
+				_ = sleep => {},
+				_ = trigger.notified() => {},
+				_ = crate::shutdown_initiated(&mut shutdown) => {
+					log_info!(self.logger, "Stopping snapshotting service");
+					return;
+				}
+			}
+		}
+	}
+
+	/// Capture a round of snapshots, upload them, and notify the hooks
+	pub(crate) async fn capture_snapshots(&self) {
+		let snapshot_interval = config::snapshot_generation_interval() as u64;
+		let snapshot_scopes = config::snapshot_scopes();
+		let cache_path = config::cache_path(config::graph_network(&self.network_graph));
+		let manifest = match self.generate_snapshots(config::SYMLINK_GRANULARITY_INTERVAL as u64, snapshot_interval, &snapshot_scopes, &cache_path, None, None).await {
+			Some(manifest) => manifest,
+			// the previous round keeps being served
+			None => return,
+		};
+		self.record_snapshot_sizes(&manifest, None);
+		let symlink_directory = format!("{}/symlinks", cache_path);
+		if let Some(uploader) = &self.uploader {
+			uploader.upload_round(&cache_path, None).await;
+		}
+		if let Some(archive_directory) = &self.archive_directory {
+			self.archive_snapshots(archive_directory.clone(), &cache_path, manifest.reference_timestamp, None).await;
+		}
+		if let Some(hooks) = &self.hooks {
+			hooks.notify(&symlink_directory, &manifest).await;
+		}
+
+		// each profile's snapshots are kept in, and uploaded to, a `profiles/<name>` subdirectory
+		for profile in config::snapshot_profiles() {
+			let selection = profile.select(&self.network_graph);
+			log_info!(self.logger, "Capturing snapshots of profile {} ({} channels, {} nodes)", profile.name, selection.channel_count(), selection.node_count());
+			let profile_key_prefix = format!("profiles/{}", profile.name);
+			let profile_cache_path = format!("{}/{}", cache_path, profile_key_prefix);
+			let profile_manifest = match self.generate_snapshots(config::SYMLINK_GRANULARITY_INTERVAL as u64, snapshot_interval, &snapshot_scopes, &profile_cache_path, None, Some(&selection)).await {
+				Some(profile_manifest) => profile_manifest,
+				None => continue,
+			};
+			self.record_snapshot_sizes(&profile_manifest, Some(&profile.name));
+			if let Some(uploader) = &self.uploader {
+				uploader.upload_round(&profile_cache_path, Some(&profile_key_prefix)).await;
+			}
+			if let Some(archive_directory) = &self.archive_directory {
+				let profile_archive_directory = format!("{}/{}", archive_directory, profile_key_prefix);
+				self.archive_snapshots(profile_archive_directory, &profile_cache_path, profile_manifest.reference_timestamp, Some(&profile_key_prefix)).await;
+			}
 		}
 	}
 
-	pub(crate) async fn generate_snapshots(&self, granularity_interval: u64, snapshot_interval: u64, snapshot_scopes: &[u64], cache_path: &str, max_symlink_count: Option<u64>) {
-		let pending_snapshot_directory = format!("{}/snapshots_pending", cache_path);
-		let pending_symlink_directory = format!("{}/symlinks_pending", cache_path);
-		let finalized_snapshot_directory = format!("{}/snapshots", cache_path);
-		let finalized_symlink_directory = format!("{}/symlinks", cache_path);
+	/// Record the sizes of a round's snapshots (those of `profile`, if given), warning about each
+	/// that deviates from the previous round's by more than the configured threshold. Such jumps
+	/// tend to stem from a misbehaving gossip source or a bug, rather than from the network.
+	pub(crate) fn record_snapshot_sizes(&self, manifest: &SnapshotManifest, profile: Option<&str>) {
+		let deviation_threshold = config::snapshot_deviation_threshold_percent();
+		for snapshot in &manifest.snapshots {
+			// the empty dummy snapshot is the same every round
+			let scope = match snapshot.scope {
+				Some(scope) => scope,
+				None => continue,
+			};
+			let series = SnapshotSeries { profile: profile.map(str::to_string), scope, serialization_version: snapshot.serialization_version };
+			let record = SnapshotRecord {
+				reference_timestamp: manifest.reference_timestamp,
+				size: snapshot.size,
+				channel_announcement_count: snapshot.channel_announcement_count,
+				update_count: snapshot.update_count,
+			};
+			let previous_record = match self.metrics.record_snapshot(series, record.clone()) {
+				Some(previous_record) => previous_record,
+				None => continue,
+			};
+			let threshold = match deviation_threshold {
+				Some(threshold) => threshold,
+				None => continue,
+			};
+			let deviations = [
+				("size", previous_record.size, record.size),
+				("channel announcement count", previous_record.channel_announcement_count as u64, record.channel_announcement_count as u64),
+				("update count", previous_record.update_count as u64, record.update_count as u64),
+			];
+			for (quantity, previous_value, value) in deviations {
+				if let Some(deviation) = deviation_percent(previous_value, value).filter(|deviation| deviation.abs() > threshold as f64) {
+					log_warn!(self.logger, "The {} of snapshot {} changed by {:+.0}% since the previous round, from {} to {}", quantity, snapshot.file, deviation, previous_value, value);
+				}
+			}
+		}
+	}
+
+	/// Archive the round of snapshots just captured in `cache_path` to `archive_directory`,
+	/// uploading it below `archive/` (within `key_prefix`, if given) if enabled, and remove the
+	/// rounds that have expired
+	async fn archive_snapshots(&self, archive_directory: String, cache_path: &str, reference_timestamp: u64, key_prefix: Option<&str>) {
+		let archive = SnapshotArchive::new(archive_directory, config::snapshot_archive_retention(), self.logger.clone());
+		let archive_path = archive.archive(cache_path, reference_timestamp);
+		if let (Some(archive_path), Some(uploader)) = (archive_path, self.uploader.as_ref().filter(|_| config::snapshot_archive_upload())) {
+			let archive_key_prefix = match key_prefix {
+				Some(key_prefix) => format!("{}/archive/{}", key_prefix, reference_timestamp),
+				None => format!("archive/{}", reference_timestamp),
+			};
+			uploader.upload_directory(&archive_path, Some(&archive_key_prefix)).await;
+		}
+		archive.prune(SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs());
+	}
+
+	/// Generate a round of snapshots in `cache_path`, replacing the previous round once complete.
+	/// If self-verification is enabled, a round containing a snapshot that fails it is left pending
+	/// for inspection instead, returning `None`.
+	#[tracing::instrument(name = "snapshot_generation", skip_all, fields(snapshot_interval = snapshot_interval))]
+	pub(crate) async fn generate_snapshots(&self, granularity_interval: u64, snapshot_interval: u64, snapshot_scopes: &[u64], cache_path: &str, max_symlink_count: Option<u64>, profile: Option<&ProfileSelection>) -> Option<SnapshotManifest> {
 		let relative_symlink_to_snapshot_path = "../snapshots";
+		let compression = config::snapshot_compression();
+		let signing_key = config::snapshot_signing_key();
+		let verify_snapshots = config::snapshot_verification();
+		let variant_extensions = Self::variant_extensions(&compression, signing_key.is_some());
 
 		// 1. get the current timestamp
 		let snapshot_generation_time = SystemTime::now();
@@ -75,6 +203,17 @@ impl<L: Deref + Clone> Snapshotter<L> where L::Target: Logger {
 		let reference_timestamp = Self::round_down_to_nearest_multiple(snapshot_generation_timestamp, snapshot_interval);
 		log_info!(self.logger, "Capturing snapshots at {} for: {}", snapshot_generation_timestamp, reference_timestamp);
 
+		// each round is staged in a directory of its own, which is only published once complete
+		let rounds_directory = format!("{}/rounds", cache_path);
+		Self::remove_pending_rounds(&rounds_directory);
+		let round_name = (0..).map(|index| format!("{}-{}", reference_timestamp, index))
+			.find(|round_name| fs::symlink_metadata(format!("{}/{}", rounds_directory, round_name)).is_err())
+			.unwrap();
+		let round_directory = format!("{}/{}", rounds_directory, round_name);
+		let pending_round_directory = format!("{}.pending", round_directory);
+		let pending_snapshot_directory = format!("{}/snapshots", pending_round_directory);
+		let pending_symlink_directory = format!("{}/symlinks", pending_round_directory);
+
 		// 2. sleep until the next round interval
 		// 3. refresh all snapshots
 
@@ -91,20 +230,20 @@ impl<L: Deref + Clone> Snapshotter<L> where L::Target: Logger {
 		// The snapshots, unlike dynamic updates, should account for all intermediate
 		// channel updates
 
-		// purge and recreate the pending directories
-		let suffixes = [("", ""), ("/v2", "../")];
-		for (suffix, _) in suffixes {
-			let versioned_snapshot_directory = format!("{}{}", pending_snapshot_directory, suffix);
-			let versioned_symlink_directory = format!("{}{}", pending_symlink_directory, suffix);
-
-			if fs::metadata(&versioned_snapshot_directory).is_ok() {
-				fs::remove_dir_all(&versioned_snapshot_directory).expect("Failed to remove pending snapshot directory.");
-			}
-			if fs::metadata(&versioned_symlink_directory).is_ok() {
-				fs::remove_dir_all(&versioned_symlink_directory).expect("Failed to remove pending symlink directory.");
-			}
-			fs::create_dir_all(&versioned_snapshot_directory).expect("Failed to create pending snapshot directory");
-			fs::create_dir_all(&versioned_symlink_directory).expect("Failed to create pending symlink directory");
+		// version 1 snapshots live at the root for backwards compatibility, all later versions in
+		// a subdirectory of their own, e. g. `v2/`
+		let serialization_versions = config::snapshot_serialization_versions();
+		let version_suffix = |version: u8| if version == 1 { String::new() } else { format!("/v{}", version) };
+		let version_path_to_root = |version: u8| if version == 1 { "" } else { "../" };
+
+		// create the pending directories
+		// the root directory is always needed for the update time and manifest, and must be
+		// created prior to the versioned subdirectories within it
+		let directory_versions = std::iter::once(1).chain(serialization_versions.iter().copied().filter(|version| *version != 1));
+		for version in directory_versions {
+			let suffix = version_suffix(version);
+			fs::create_dir_all(format!("{}{}", pending_snapshot_directory, suffix)).expect("Failed to create pending snapshot directory");
+			fs::create_dir_all(format!("{}{}", pending_symlink_directory, suffix)).expect("Failed to create pending symlink directory");
 		}
 
 		let mut snapshot_sync_timestamps: Vec<(u64, u64)> = Vec::new();
@@ -114,41 +253,80 @@ impl<L: Deref + Clone> Snapshotter<L> where L::Target: Logger {
 		};
 
 		let mut snapshot_filenames_by_scope: HashMap<u64, String> = HashMap::with_capacity(10);
-
-		for (current_scope, current_last_sync_timestamp) in &snapshot_sync_timestamps {
-			let network_graph_clone = self.network_graph.clone();
-			{
-				log_info!(self.logger, "Calculating {}-second snapshot", current_scope);
-				// calculate the snapshot
-				let delta = super::calculate_delta(network_graph_clone.clone(), current_last_sync_timestamp.clone() as u32, Some(reference_timestamp), self.logger.clone()).await;
-				let snapshot_v1 = super::serialize_delta(&delta, 1, self.logger.clone());
-				let snapshot_v2 = super::serialize_delta(&delta, 2, self.logger.clone());
-
-				// persist the snapshot and update the symlink
-				let snapshot_filename = format!("snapshot__calculated-at:{}__range:{}-scope__previous-sync:{}.lngossip", reference_timestamp, current_scope, current_last_sync_timestamp);
-				let snapshot_path_v1 = format!("{}/{}", pending_snapshot_directory, snapshot_filename);
-				let snapshot_path_v2 = format!("{}/v2/{}", pending_snapshot_directory, snapshot_filename);
-				log_info!(self.logger, "Persisting {}-second snapshot: {} ({} messages, {} announcements, {} updates ({} full, {} incremental))", current_scope, snapshot_filename, snapshot_v1.message_count, snapshot_v1.channel_announcement_count, snapshot_v1.update_count, snapshot_v1.update_count_full, snapshot_v1.update_count_incremental);
-				fs::write(&snapshot_path_v1, snapshot_v1.data).unwrap();
-				fs::write(&snapshot_path_v2, snapshot_v2.data).unwrap();
-				snapshot_filenames_by_scope.insert(current_scope.clone(), snapshot_filename);
-			}
+		let mut manifest_snapshots: Vec<ManifestSnapshot> = Vec::new();
+
+		// the scopes are calculated concurrently, and their CPU-bound serialization, compression, and
+		// signing is moved off the runtime so as to spread across cores. Their results are collected
+		// in order, keeping the manifest stable.
+		let scope_snapshots: Vec<(u64, String, Result<Vec<ManifestSnapshot>, String>)> = stream::iter(snapshot_sync_timestamps.iter().copied())
+			.map(|(current_scope, current_last_sync_timestamp)| {
+				let network_graph_clone = self.network_graph.clone();
+				let source_network_graph = self.network_graph.clone();
+				let serialization_versions = serialization_versions.clone();
+				let pending_snapshot_directory = pending_snapshot_directory.clone();
+				let compression = compression.clone();
+				let logger = self.logger.clone();
+				async move {
+					log_info!(logger, "Calculating {}-second snapshot", current_scope);
+					// calculate the snapshot, only looking up the channels changed within its scope if known
+					let changed_channels = self.channel_changes.as_ref().and_then(|channel_changes| channel_changes.changed_since(current_last_sync_timestamp as u32));
+					let delta = super::calculate_snapshot_delta(network_graph_clone, profile, changed_channels.as_ref(), current_last_sync_timestamp as u32, Some(reference_timestamp), logger.clone()).await;
+
+					// persist the snapshot and update the symlink
+					let snapshot_filename = format!("snapshot__calculated-at:{}__range:{}-scope__previous-sync:{}.lngossip", reference_timestamp, current_scope, current_last_sync_timestamp);
+					let persisted_filename = snapshot_filename.clone();
+					let manifest_entries = tokio::task::spawn_blocking(move || {
+						let mut manifest_entries = Vec::with_capacity(serialization_versions.len());
+						for version in &serialization_versions {
+							let snapshot = super::serialize_delta(&delta, *version, logger.clone());
+							let suffix = version_suffix(*version);
+							let snapshot_path = format!("{}{}/{}", pending_snapshot_directory, suffix, persisted_filename);
+							log_info!(logger, "Persisting {}-second v{} snapshot: {} ({} messages, {} announcements, {} updates ({} full, {} incremental))", current_scope, version, persisted_filename, snapshot.message_count, snapshot.channel_announcement_count, snapshot.update_count, snapshot.update_count_full, snapshot.update_count_incremental);
+							if verify_snapshots {
+								// only snapshots for an initial sync convey every channel's complete policies
+								let is_full_snapshot = current_last_sync_timestamp == 0;
+								validate_snapshot(&snapshot.data, snapshot.channel_announcement_count, is_full_snapshot, &source_network_graph, logger.clone())
+									.map_err(|e| format!("the {}-second v{} snapshot {} failed verification, as {}", current_scope, version, snapshot_path, e))?;
+							}
+							let mut manifest_entry = ManifestSnapshot::new(format!("snapshots{}/{}", suffix, persisted_filename), *version, Some(current_scope), current_last_sync_timestamp, &snapshot.data, Some(&snapshot));
+							Self::write_snapshot(&snapshot_path, &snapshot.data, &compression, signing_key.as_ref(), &mut manifest_entry);
+							manifest_entries.push(manifest_entry);
+						}
+						Ok(manifest_entries)
+					}).await.unwrap();
+					(current_scope, snapshot_filename, manifest_entries)
+				}
+			})
+			.buffered(config::snapshot_concurrency())
+			.collect().await;
+		for (current_scope, snapshot_filename, manifest_entries) in scope_snapshots {
+			let manifest_entries = match manifest_entries {
+				Ok(manifest_entries) => manifest_entries,
+				Err(e) => {
+					log_error!(self.logger, "Not publishing the snapshots captured at {}: {}", reference_timestamp, e);
+					return None;
+				}
+			};
+			manifest_snapshots.extend(manifest_entries);
+			snapshot_filenames_by_scope.insert(current_scope, snapshot_filename);
 		}
 
 		{
 			// create dummy symlink
 			let dummy_filename = "empty_delta.lngossip";
-			let dummy_snapshot_v1 = super::serialize_empty_blob(reference_timestamp, 1);
-			let dummy_snapshot_v2 = super::serialize_empty_blob(reference_timestamp, 2);
-			let dummy_snapshot_path_v1 = format!("{}/{}", pending_snapshot_directory, dummy_filename);
-			let dummy_snapshot_path_v2 = format!("{}/v2/{}", pending_snapshot_directory, dummy_filename);
-			fs::write(&dummy_snapshot_path_v1, dummy_snapshot_v1).unwrap();
-			fs::write(&dummy_snapshot_path_v2, dummy_snapshot_v2).unwrap();
-
-			let dummy_symlink_path = format!("{}/{}.bin", pending_symlink_directory, reference_timestamp);
-			let relative_dummy_snapshot_path = format!("{}/{}", relative_symlink_to_snapshot_path, dummy_filename);
-			log_info!(self.logger, "Symlinking dummy: {} -> {}", dummy_symlink_path, relative_dummy_snapshot_path);
-			symlink(&relative_dummy_snapshot_path, &dummy_symlink_path).unwrap();
+			for version in &serialization_versions {
+				let suffix = version_suffix(*version);
+				let dummy_snapshot = super::serialize_empty_blob(self.network_graph.get_chain_hash(), reference_timestamp, *version);
+				let dummy_snapshot_path = format!("{}{}/{}", pending_snapshot_directory, suffix, dummy_filename);
+				let mut manifest_entry = ManifestSnapshot::new(format!("snapshots{}/{}", suffix, dummy_filename), *version, None, reference_timestamp, &dummy_snapshot, None);
+				Self::write_snapshot(&dummy_snapshot_path, &dummy_snapshot, &compression, signing_key.as_ref(), &mut manifest_entry);
+				manifest_snapshots.push(manifest_entry);
+
+				let dummy_symlink_path = format!("{}{}/{}.bin", pending_symlink_directory, suffix, reference_timestamp);
+				let relative_dummy_snapshot_path = format!("{}{}{}/{}", version_path_to_root(*version), relative_symlink_to_snapshot_path, suffix, dummy_filename);
+				log_info!(self.logger, "Symlinking dummy: {} -> {}", dummy_symlink_path, relative_dummy_snapshot_path);
+				Self::symlink_snapshot(&relative_dummy_snapshot_path, &dummy_symlink_path, &variant_extensions);
+			}
 		}
 
 		// Number of intervals since Jan 1, 2022, a few months before RGS server was released.
@@ -188,7 +366,9 @@ impl<L: Deref + Clone> Snapshotter<L> where L::Target: Logger {
 			};
 			log_info!(self.logger, "i: {}, referenced scope: {}", i, referenced_scope);
 
-			for (suffix, path_to_root) in suffixes {
+			for version in &serialization_versions {
+				let suffix = version_suffix(*version);
+				let path_to_root = version_path_to_root(*version);
 				let snapshot_filename = snapshot_filenames_by_scope.get(&referenced_scope).unwrap();
 				let relative_snapshot_path = format!("{}{}{}/{}", path_to_root, relative_symlink_to_snapshot_path, suffix, snapshot_filename);
 
@@ -201,7 +381,7 @@ impl<L: Deref + Clone> Snapshotter<L> where L::Target: Logger {
 				let symlink_path = format!("{}{}/{}.bin", pending_symlink_directory, suffix, canonical_last_sync_timestamp);
 
 				log_info!(self.logger, "Symlinking: {} -> {} ({} -> {}", i, referenced_scope, symlink_path, relative_snapshot_path);
-				symlink(&relative_snapshot_path, &symlink_path).unwrap();
+				Self::symlink_snapshot(&relative_snapshot_path, &symlink_path, &variant_extensions);
 			}
 		}
 
@@ -209,14 +389,119 @@ impl<L: Deref + Clone> Snapshotter<L> where L::Target: Logger {
 		let update_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
 		fs::write(&update_time_path, format!("{}", update_time)).unwrap();
 
-		if fs::metadata(&finalized_snapshot_directory).is_ok() {
-			fs::remove_dir_all(&finalized_snapshot_directory).expect("Failed to remove finalized snapshot directory.");
+		let manifest = SnapshotManifest {
+			network: config::graph_network(&self.network_graph).to_string(),
+			chain_hash: self.network_graph.get_chain_hash().to_string(),
+			generated_at: update_time,
+			reference_timestamp,
+			snapshots: manifest_snapshots,
+		};
+		let manifest_path = format!("{}/manifest.json", pending_symlink_directory);
+		let serialized_manifest = serde_json::to_vec_pretty(&manifest).unwrap();
+		fs::write(&manifest_path, &serialized_manifest).unwrap();
+		if let Some(signing_key) = &signing_key {
+			fs::write(format!("{}.sig", manifest_path), sign_snapshot(&serialized_manifest, signing_key)).unwrap();
+		}
+
+		fs::rename(&pending_round_directory, &round_directory).expect("Failed to finalize round directory.");
+		Self::publish_round(cache_path, &round_name);
+		log_info!(self.logger, "Published snapshot round {}", round_name);
+
+		Some(manifest)
+	}
+
+	/// Remove the rounds left pending, e. g. by a failed verification or a crash
+	fn remove_pending_rounds(rounds_directory: &str) {
+		let entries = match fs::read_dir(rounds_directory) {
+			Ok(entries) => entries,
+			Err(_) => return,
+		};
+		for entry in entries.flatten() {
+			if entry.file_name().to_string_lossy().ends_with(".pending") {
+				fs::remove_dir_all(entry.path()).expect("Failed to remove pending round directory.");
+			}
+		}
+	}
+
+	/// Serve the round in `rounds/<round_name>` in place of the previous one.
+	///
+	/// `snapshots` and `symlinks` in the cache directory link to the directories of the same name
+	/// within `current`, which in turn links to the round being served. Replacing `current` is
+	/// atomic, so each lookup resolves to files of either round, but never a mix of both. The
+	/// previous round is kept until the next is published, for the lookups still resolving to it.
+	fn publish_round(cache_path: &str, round_name: &str) {
+		let current_path = format!("{}/current", cache_path);
+		let previous_round_name = fs::read_link(&current_path).ok()
+			.and_then(|target| target.file_name().map(|name| name.to_string_lossy().into_owned()));
+
+		let staged_current_path = format!("{}/current.pending", cache_path);
+		if fs::symlink_metadata(&staged_current_path).is_ok() {
+			fs::remove_file(&staged_current_path).expect("Failed to remove pending round symlink.");
 		}
-		if fs::metadata(&finalized_symlink_directory).is_ok() {
-			fs::remove_dir_all(&finalized_symlink_directory).expect("Failed to remove pending symlink directory.");
+		symlink(format!("rounds/{}", round_name), &staged_current_path).expect("Failed to create pending round symlink.");
+		fs::rename(&staged_current_path, &current_path).expect("Failed to publish round.");
+
+		for directory_name in ["snapshots", "symlinks"] {
+			let directory_path = format!("{}/{}", cache_path, directory_name);
+			match fs::symlink_metadata(&directory_path) {
+				Ok(metadata) if metadata.file_type().is_symlink() => continue,
+				// left behind by versions replacing the directories themselves
+				Ok(_) => fs::remove_dir_all(&directory_path).expect("Failed to remove unversioned snapshot directory."),
+				Err(_) => {},
+			}
+			symlink(format!("current/{}", directory_name), &directory_path).expect("Failed to link snapshot directory.");
+		}
+
+		let rounds_directory = format!("{}/rounds", cache_path);
+		for entry in fs::read_dir(&rounds_directory).expect("Failed to list round directories.").flatten() {
+			let entry_name = entry.file_name().to_string_lossy().into_owned();
+			if entry_name != round_name && Some(&entry_name) != previous_round_name.as_ref() {
+				fs::remove_dir_all(entry.path()).expect("Failed to remove expired round directory.");
+			}
+		}
+	}
+
+	/// Write a snapshot along with a precompressed copy for each of the enabled codecs, e. g.
+	/// `<path>.gz` for gzip, recording the latter in the snapshot's manifest entry. If a signing
+	/// key is configured, each of the files is accompanied by a detached `<file>.sig` signature.
+	fn write_snapshot(path: &str, data: &[u8], compression: &[(SnapshotCompression, u32)], signing_key: Option<&SecretKey>, manifest_entry: &mut ManifestSnapshot) {
+		let write_signed = |file_path: &str, contents: &[u8]| {
+			fs::write(file_path, contents).unwrap();
+			if let Some(signing_key) = signing_key {
+				fs::write(format!("{}.sig", file_path), sign_snapshot(contents, signing_key)).unwrap();
+			}
+		};
+		write_signed(path, data);
+		for (algorithm, level) in compression {
+			let extension = algorithm.file_extension();
+			let compressed_data = algorithm.compress(data, *level);
+			write_signed(&format!("{}.{}", path, extension), &compressed_data);
+			manifest_entry.compressed_variants.push(ManifestFileVariant {
+				content_encoding: algorithm.content_encoding(),
+				file: format!("{}.{}", manifest_entry.file, extension),
+				size: compressed_data.len() as u64,
+				sha256: sha256_hex(&compressed_data),
+			});
+		}
+	}
+
+	/// The extensions of the files accompanying each snapshot, which get a symlink of their own:
+	/// the precompressed variants and, if signing, the signatures of the snapshot and each variant.
+	fn variant_extensions(compression: &[(SnapshotCompression, u32)], is_signing: bool) -> Vec<String> {
+		let mut variant_extensions: Vec<String> = compression.iter().map(|(algorithm, _)| algorithm.file_extension().to_string()).collect();
+		if is_signing {
+			let signature_extensions: Vec<String> = variant_extensions.iter().map(|extension| format!("{}.sig", extension)).collect();
+			variant_extensions.push("sig".to_string());
+			variant_extensions.extend(signature_extensions);
+		}
+		variant_extensions
+	}
+
+	fn symlink_snapshot(snapshot_path: &str, symlink_path: &str, variant_extensions: &[String]) {
+		symlink(snapshot_path, symlink_path).unwrap();
+		for extension in variant_extensions {
+			symlink(format!("{}.{}", snapshot_path, extension), format!("{}.{}", symlink_path, extension)).unwrap();
 		}
-		fs::rename(&pending_snapshot_directory, &finalized_snapshot_directory).expect("Failed to finalize snapshot directory.");
-		fs::rename(&pending_symlink_directory, &finalized_symlink_directory).expect("Failed to finalize symlink directory.");
 	}
 
 	pub(super) fn round_down_to_nearest_multiple(number: u64, multiple: u64) -> u64 {
@@ -224,3 +509,47 @@ impl<L: Deref + Clone> Snapshotter<L> where L::Target: Logger {
 		number - round_multiple_delta
 	}
 }
+
+/// How many percent `value` differs from `previous_value` by, unless the latter is zero
+fn deviation_percent(previous_value: u64, value: u64) -> Option<f64> {
+	if previous_value == 0 {
+		return None;
+	}
+	Some((value as f64 - previous_value as f64) * 100.0 / previous_value as f64)
+}
+
+#[cfg(test)]
+mod tests {
+	use std::fs;
+	use std::sync::Arc;
+
+	use crate::compression::SnapshotCompression;
+	use crate::snapshot::Snapshotter;
+	use crate::types::tests::TestLogger;
+
+	type TestSnapshotter = Snapshotter<Arc<TestLogger>>;
+
+	#[test]
+	fn test_signed_variant_symlinks() {
+		let compression = [(SnapshotCompression::Gzip, 6), (SnapshotCompression::Zstd, 3)];
+		assert_eq!(TestSnapshotter::variant_extensions(&compression, false), vec!["gz", "zst"]);
+		let variant_extensions = TestSnapshotter::variant_extensions(&compression, true);
+		assert_eq!(variant_extensions, vec!["gz", "zst", "sig", "gz.sig", "zst.sig"]);
+
+		let directory = std::env::temp_dir().join(format!("rgs_symlink_test_{}", std::process::id()));
+		fs::create_dir_all(directory.join("snapshots")).unwrap();
+		fs::create_dir_all(directory.join("symlinks")).unwrap();
+		for extension in ["bin".to_string()].into_iter().chain(variant_extensions.iter().map(|extension| format!("bin.{}", extension))) {
+			fs::write(directory.join("snapshots").join(format!("full.{}", extension)), &extension).unwrap();
+		}
+		let symlink_path = directory.join("symlinks/0.bin").to_str().unwrap().to_string();
+		TestSnapshotter::symlink_snapshot("../snapshots/full.bin", &symlink_path, &variant_extensions);
+		for extension in ["gz.sig", "zst.sig", "sig"] {
+			let variant_symlink_path = format!("{}.{}", symlink_path, extension);
+			assert!(fs::symlink_metadata(&variant_symlink_path).unwrap().file_type().is_symlink());
+			assert_eq!(fs::read_to_string(&variant_symlink_path).unwrap(), format!("bin.{}", extension));
+		}
+
+		fs::remove_dir_all(&directory).unwrap();
+	}
+}
@@ -0,0 +1,147 @@
+//! A minimal SOCKS5 ([RFC 1928]) client, allowing peer connections to be routed through a proxy
+//! such as Tor's in order to reach `.onion` peers.
+//!
+//! [RFC 1928]: https://www.rfc-editor.org/rfc/rfc1928
+
+use std::io;
+use std::net::SocketAddr;
+
+use lightning::ln::msgs::SocketAddress;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const SOCKS_VERSION: u8 = 5;
+const NO_AUTHENTICATION: u8 = 0;
+const CONNECT_COMMAND: u8 = 1;
+const IPV4_ADDRESS: u8 = 1;
+const DOMAIN_NAME: u8 = 3;
+const IPV6_ADDRESS: u8 = 4;
+
+/// Connect to `target` through the SOCKS5 proxy at `proxy`. Host names, including those of onion
+/// services, are resolved by the proxy.
+pub(crate) async fn connect(proxy: SocketAddr, target: &SocketAddress) -> io::Result<TcpStream> {
+	let mut stream = TcpStream::connect(proxy).await?;
+
+	stream.write_all(&[SOCKS_VERSION, 1, NO_AUTHENTICATION]).await?;
+	let mut method_selection = [0u8; 2];
+	stream.read_exact(&mut method_selection).await?;
+	if method_selection != [SOCKS_VERSION, NO_AUTHENTICATION] {
+		return Err(io::Error::new(io::ErrorKind::Other, "SOCKS5 proxy does not accept unauthenticated connections"));
+	}
+
+	stream.write_all(&connect_request(target)?).await?;
+	let mut reply = [0u8; 4];
+	stream.read_exact(&mut reply).await?;
+	if reply[0] != SOCKS_VERSION {
+		return Err(io::Error::new(io::ErrorKind::Other, "Invalid SOCKS5 proxy reply"));
+	}
+	if reply[1] != 0 {
+		return Err(io::Error::new(io::ErrorKind::Other, format!("SOCKS5 proxy failed to connect to {}: {}", target, reply_description(reply[1]))));
+	}
+	// skip the address the proxy bound to on our behalf, followed by its port
+	let bound_address_length = match reply[3] {
+		IPV4_ADDRESS => 4,
+		IPV6_ADDRESS => 16,
+		DOMAIN_NAME => stream.read_u8().await? as usize,
+		_ => return Err(io::Error::new(io::ErrorKind::Other, "Invalid SOCKS5 proxy reply")),
+	};
+	let mut bound_address = vec![0u8; bound_address_length + 2];
+	stream.read_exact(&mut bound_address).await?;
+	Ok(stream)
+}
+
+fn connect_request(target: &SocketAddress) -> io::Result<Vec<u8>> {
+	let mut request = vec![SOCKS_VERSION, CONNECT_COMMAND, 0];
+	let port = match target {
+		SocketAddress::TcpIpV4 { addr, port } => {
+			request.push(IPV4_ADDRESS);
+			request.extend_from_slice(addr);
+			*port
+		}
+		SocketAddress::TcpIpV6 { addr, port } => {
+			request.push(IPV6_ADDRESS);
+			request.extend_from_slice(addr);
+			*port
+		}
+		SocketAddress::OnionV3 { port, .. } => {
+			let address = target.to_string();
+			let (host, _) = address.rsplit_once(':').unwrap();
+			request.push(DOMAIN_NAME);
+			request.push(host.len() as u8);
+			request.extend_from_slice(host.as_bytes());
+			*port
+		}
+		SocketAddress::Hostname { hostname, port } => {
+			request.push(DOMAIN_NAME);
+			request.push(hostname.len());
+			request.extend_from_slice(hostname.as_bytes());
+			*port
+		}
+		SocketAddress::OnionV2(_) => return Err(io::Error::new(io::ErrorKind::Other, "Onion v2 addresses are no longer supported by Tor")),
+	};
+	request.extend_from_slice(&port.to_be_bytes());
+	Ok(request)
+}
+
+fn reply_description(reply: u8) -> &'static str {
+	match reply {
+		1 => "general failure",
+		2 => "connection not allowed by ruleset",
+		3 => "network unreachable",
+		4 => "host unreachable",
+		5 => "connection refused",
+		6 => "TTL expired",
+		7 => "command not supported",
+		8 => "address type not supported",
+		_ => "unknown error",
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::str::FromStr;
+
+	use lightning::ln::msgs::SocketAddress;
+	use tokio::io::{AsyncReadExt, AsyncWriteExt};
+	use tokio::net::TcpListener;
+
+	use crate::socks;
+
+	const ONION_ADDRESS: &str = "pg6mmjiyjmcrsslvykfwnntlaru7p5svn6y2ymmju6nubxndf4pscryd.onion:9735";
+
+	/// Accept a single connection, expect the given connect request, and answer it with `reply`
+	async fn mock_proxy(expected_request: Vec<u8>, reply: u8) -> std::net::SocketAddr {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let address = listener.local_addr().unwrap();
+		tokio::spawn(async move {
+			let (mut stream, _) = listener.accept().await.unwrap();
+			let mut greeting = [0u8; 3];
+			stream.read_exact(&mut greeting).await.unwrap();
+			assert_eq!(greeting, [5, 1, 0]);
+			stream.write_all(&[5, 0]).await.unwrap();
+
+			let mut request = vec![0u8; expected_request.len()];
+			stream.read_exact(&mut request).await.unwrap();
+			assert_eq!(request, expected_request);
+			stream.write_all(&[5, reply, 0, 1, 127, 0, 0, 1, 0x26, 0x07]).await.unwrap();
+		});
+		address
+	}
+
+	#[tokio::test]
+	async fn test_socks_connect() {
+		let onion_address = SocketAddress::from_str(ONION_ADDRESS).unwrap();
+		let onion_host = onion_address.to_string().split(':').next().unwrap().to_string();
+		assert!(onion_host.eq_ignore_ascii_case(ONION_ADDRESS.split(':').next().unwrap()));
+		let mut expected_request = vec![5, 1, 0, 3, 62];
+		expected_request.extend_from_slice(onion_host.as_bytes());
+		expected_request.extend_from_slice(&9735u16.to_be_bytes());
+		let proxy = mock_proxy(expected_request, 0).await;
+		socks::connect(proxy, &onion_address).await.unwrap();
+
+		let ipv4_address = SocketAddress::from_str("10.0.0.1:9735").unwrap();
+		let proxy = mock_proxy(vec![5, 1, 0, 1, 10, 0, 0, 1, 0x26, 0x07], 5).await;
+		let error = socks::connect(proxy, &ipv4_address).await.unwrap_err();
+		assert_eq!(error.to_string(), "SOCKS5 proxy failed to connect to 10.0.0.1:9735: connection refused");
+	}
+}
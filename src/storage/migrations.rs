@@ -0,0 +1,69 @@
+//! Schema changes embedded as SQL and applied at startup, each in its own transaction along with
+//! the bump of the schema version recorded in the `config` table. Applied migrations are also
+//! listed in the `schema_migrations` table.
+
+/// A schema change upgrading the database from the preceding version to `version`
+pub(crate) struct Migration {
+	pub(crate) version: i32,
+	pub(crate) name: &'static str,
+	pub(crate) sql: &'static str,
+}
+
+/// The Postgres migrations following the schema created from scratch, or reached through
+/// [`crate::config::upgrade_db`], which is [`POSTGRES_BASELINE_VERSION`].
+pub(crate) const POSTGRES_MIGRATIONS: &[Migration] = &[
+	Migration { version: 16, name: "schema_migrations", sql: include_str!("migrations/postgres/0016_schema_migrations.sql") },
+	Migration { version: 17, name: "snapshot_requests", sql: include_str!("migrations/postgres/0017_snapshot_requests.sql") },
+	Migration { version: 18, name: "stats_samples", sql: include_str!("migrations/postgres/0018_stats_samples.sql") },
+];
+pub(crate) const POSTGRES_BASELINE_VERSION: i32 = 15;
+
+/// The SQLite migrations following the schema created from scratch, which is
+/// [`SQLITE_BASELINE_VERSION`]. SQLite's schema versions are tracked independently of Postgres'.
+pub(crate) const SQLITE_MIGRATIONS: &[Migration] = &[
+	Migration { version: 2, name: "schema_migrations", sql: include_str!("migrations/sqlite/0002_schema_migrations.sql") },
+	Migration { version: 3, name: "snapshot_requests", sql: include_str!("migrations/sqlite/0003_snapshot_requests.sql") },
+	Migration { version: 4, name: "stats_samples", sql: include_str!("migrations/sqlite/0004_stats_samples.sql") },
+];
+pub(crate) const SQLITE_BASELINE_VERSION: i32 = 1;
+
+/// The schema version the migrations lead up to
+pub(crate) fn latest_version(migrations: &[Migration], baseline_version: i32) -> i32 {
+	migrations.last().map_or(baseline_version, |migration| migration.version)
+}
+
+/// The migrations yet to be applied to a database at schema `version`, refusing databases whose
+/// schema is newer than this server supports, e. g. after a downgrade.
+pub(crate) fn pending(migrations: &[Migration], baseline_version: i32, version: i32) -> Result<&[Migration], String> {
+	let latest_version = latest_version(migrations, baseline_version);
+	if version > latest_version {
+		return Err(format!("Database schema {} is newer than the latest schema {} supported by this server", version, latest_version));
+	}
+	let applied = migrations.iter().take_while(|migration| migration.version <= version).count();
+	Ok(&migrations[applied..])
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_migration_versions() {
+		for (migrations, baseline_version) in [(POSTGRES_MIGRATIONS, POSTGRES_BASELINE_VERSION), (SQLITE_MIGRATIONS, SQLITE_BASELINE_VERSION)] {
+			let mut expected_version = baseline_version;
+			for migration in migrations {
+				expected_version += 1;
+				assert_eq!(migration.version, expected_version, "migration {} is out of sequence", migration.name);
+			}
+		}
+		assert_eq!(latest_version(POSTGRES_MIGRATIONS, POSTGRES_BASELINE_VERSION), crate::config::SCHEMA_VERSION);
+	}
+
+	#[test]
+	fn test_pending_migrations() {
+		assert_eq!(pending(POSTGRES_MIGRATIONS, POSTGRES_BASELINE_VERSION, 15).unwrap().len(), 3);
+		assert_eq!(pending(POSTGRES_MIGRATIONS, POSTGRES_BASELINE_VERSION, 17).unwrap().len(), 1);
+		assert!(pending(POSTGRES_MIGRATIONS, POSTGRES_BASELINE_VERSION, 18).unwrap().is_empty());
+		assert!(pending(POSTGRES_MIGRATIONS, POSTGRES_BASELINE_VERSION, 19).is_err());
+	}
+}
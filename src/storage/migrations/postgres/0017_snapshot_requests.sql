@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS snapshot_requests (
+	id SERIAL PRIMARY KEY,
+	last_sync_timestamp bigint NOT NULL,
+	lag_bucket text NOT NULL,
+	profile text,
+	served_snapshot text NOT NULL,
+	bytes bigint NOT NULL,
+	user_agent text NOT NULL,
+	requested_at timestamp NOT NULL DEFAULT NOW()
+);
+CREATE INDEX IF NOT EXISTS snapshot_requests_requested_at ON snapshot_requests(requested_at);
@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS stats_samples (
+	id SERIAL PRIMARY KEY,
+	interval_secs bigint NOT NULL,
+	channel_announcements bigint NOT NULL,
+	channel_updates bigint NOT NULL,
+	node_announcements bigint NOT NULL,
+	channel_count bigint NOT NULL,
+	node_count bigint NOT NULL,
+	full_snapshot_bytes bigint,
+	snapshot_bytes bigint,
+	sampled_at timestamp NOT NULL DEFAULT NOW()
+);
+CREATE INDEX IF NOT EXISTS stats_samples_sampled_at ON stats_samples(sampled_at);
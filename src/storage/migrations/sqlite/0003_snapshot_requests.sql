@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS snapshot_requests (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	last_sync_timestamp INTEGER NOT NULL,
+	lag_bucket TEXT NOT NULL,
+	profile TEXT,
+	served_snapshot TEXT NOT NULL,
+	bytes INTEGER NOT NULL,
+	user_agent TEXT NOT NULL,
+	requested_at INTEGER NOT NULL DEFAULT (unixepoch())
+);
+CREATE INDEX IF NOT EXISTS snapshot_requests_requested_at ON snapshot_requests(requested_at);
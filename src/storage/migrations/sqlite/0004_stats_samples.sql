@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS stats_samples (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	interval_secs INTEGER NOT NULL,
+	channel_announcements INTEGER NOT NULL,
+	channel_updates INTEGER NOT NULL,
+	node_announcements INTEGER NOT NULL,
+	channel_count INTEGER NOT NULL,
+	node_count INTEGER NOT NULL,
+	full_snapshot_bytes INTEGER,
+	snapshot_bytes INTEGER,
+	sampled_at INTEGER NOT NULL DEFAULT (unixepoch())
+);
+CREATE INDEX IF NOT EXISTS stats_samples_sampled_at ON stats_samples(sampled_at);
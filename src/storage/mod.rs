@@ -0,0 +1,182 @@
+//! The persistence layer gossip is stored in and snapshots are calculated from, backed by either
+//! Postgres or, for small and test deployments, SQLite.
+
+use std::ops::Deref;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use bitcoin::Network;
+use futures::stream::BoxStream;
+use lightning::log_warn;
+use lightning::util::logger::Logger;
+
+use crate::analytics::SnapshotRequest;
+use crate::config::{self, DatabaseBackend};
+use crate::history::StatsSample;
+use crate::types::GossipMessage;
+
+mod migrations;
+mod postgres;
+mod sqlite;
+mod tls;
+
+pub(crate) use self::postgres::PostgresStore;
+pub(crate) use self::sqlite::SqliteStore;
+pub(crate) use self::tls::tls_connector;
+
+/// A signed announcement, be it a channel's or a node's, along with when we first saw it
+pub(crate) struct AnnouncementRow {
+	pub(crate) announcement_signed: Vec<u8>,
+	pub(crate) seen: u32,
+}
+
+/// A signed channel announcement along with the amount locked up in its funding output
+pub(crate) struct ChannelAnnouncementRow {
+	pub(crate) announcement_signed: Vec<u8>,
+	pub(crate) funding_amount_sats: u64,
+}
+
+/// The time at which a channel was first seen to have updates in both directions
+pub(crate) struct FirstBidirectionalUpdateRow {
+	pub(crate) short_channel_id: u64,
+	pub(crate) seen: u32,
+}
+
+pub(crate) struct UpdateRow {
+	pub(crate) id: i64,
+	pub(crate) direction: bool,
+	pub(crate) blob_signed: Vec<u8>,
+	pub(crate) seen: u32,
+}
+
+/// The gossip storage backing the persister and the snapshot lookups. Each of the queries mirrors
+/// one of the steps in [`crate::lookup`], which documents their purpose. Timestamps are in
+/// seconds since the epoch.
+#[async_trait]
+pub(crate) trait GossipStore: Send + Sync {
+	/// Persist a batch of gossip, ignoring messages that have already been stored. Each message is
+	/// considered seen at the time it's inserted, unless overridden in tests. Channel announcements
+	/// seeded from a snapshot, which have a funding amount of zero, are replaced by actual ones.
+	async fn insert_batch(&self, messages: Vec<GossipMessage>);
+
+	/// The announcements of the given channels, ordered by short channel id
+	async fn channel_announcements(&self, short_channel_ids: Vec<i64>) -> BoxStream<'static, AnnouncementRow>;
+
+	/// The given channels whose oldest update in either direction was seen at or after `since`,
+	/// along with the newer of those two oldest updates
+	async fn first_bidirectional_updates(&self, short_channel_ids: Vec<i64>, since: u32) -> BoxStream<'static, FirstBidirectionalUpdateRow>;
+
+	/// For each of the given channels and direction, the most recent update among those seen at or
+	/// after `since` which differs from its predecessor, i. e. when the current values were first
+	/// seen. Ordered by short channel id and direction.
+	async fn mutated_updates(&self, short_channel_ids: Vec<i64>, since: u32) -> BoxStream<'static, UpdateRow>;
+
+	/// The latest update seen before `last_sync_timestamp` in each direction of the channels that
+	/// have been updated since
+	async fn reference_updates(&self, last_sync_timestamp: u32) -> BoxStream<'static, UpdateRow>;
+
+	/// All updates seen at or after `since`, ordered by short channel id and descending timestamp
+	async fn intermediate_updates(&self, since: u32) -> BoxStream<'static, UpdateRow>;
+
+	/// The latest announcement seen before `last_sync_timestamp` of each of the given nodes,
+	/// ordered by public key
+	async fn node_reference_announcements(&self, public_keys: Vec<String>, last_sync_timestamp: u32) -> BoxStream<'static, AnnouncementRow>;
+
+	/// All announcements of the given nodes seen at or after `since`, ordered by public key and
+	/// descending timestamp
+	async fn node_intermediate_announcements(&self, public_keys: Vec<String>, since: u32) -> BoxStream<'static, AnnouncementRow>;
+
+	/// All channel announcements seen at or after `since`, for bringing a restored network graph up
+	/// to date. Announcements seeded from a snapshot lack signatures, and are thus omitted.
+	async fn recent_channel_announcements(&self, since: u32) -> BoxStream<'static, ChannelAnnouncementRow>;
+
+	/// All node announcements seen at or after `since`, ordered by ascending timestamp, for bringing
+	/// a restored network graph up to date
+	async fn recent_node_announcements(&self, since: u32) -> BoxStream<'static, AnnouncementRow>;
+
+	/// Delete the channel updates seen before `before`, other than the first and the latest
+	/// `keep_latest` ones of each channel direction, returning how many were deleted. The first
+	/// updates determine when channels became bidirectional, and the latest ones serve as the
+	/// reference for the next snapshots.
+	async fn prune_channel_updates(&self, before: u32, keep_latest: u32) -> u64;
+
+	/// Record a batch of served snapshot requests
+	async fn insert_snapshot_requests(&self, requests: Vec<SnapshotRequest>);
+
+	/// Record a sample of the gossip message rates and the network's growth
+	async fn insert_stats_sample(&self, sample: StatsSample);
+
+	/// Up to `limit` of the stats samples taken at or after `since`, oldest first
+	async fn stats_samples(&self, since: u64, limit: u32) -> Vec<StatsSample>;
+
+	/// Delete the stats samples taken before `before`, returning how many were deleted
+	async fn prune_stats_samples(&self, before: u64) -> u64;
+
+	/// Whether gossip can currently be written, for the readiness check
+	async fn is_writable(&self) -> bool;
+
+	/// The schema version recorded in the database, or `None` if it hasn't been initialized
+	async fn schema_version(&self) -> Option<i32>;
+
+	/// The number of channel updates stored without an announcement of their channel
+	async fn orphaned_update_count(&self) -> u64;
+}
+
+/// When a message is to be considered seen, if other than upon insertion. Overrides only apply in
+/// tests, where channel updates are otherwise considered seen at their own timestamp.
+fn seen_override(message: &GossipMessage) -> Option<u32> {
+	if !cfg!(test) {
+		return None;
+	}
+	match message {
+		GossipMessage::NodeAnnouncement(_, seen_override) => *seen_override,
+		GossipMessage::ChannelAnnouncement(_, _, seen_override) => *seen_override,
+		GossipMessage::ChannelUpdate(update, seen_override) => Some(seen_override.unwrap_or(update.contents.timestamp)),
+	}
+}
+
+/// The schema version this server creates or upgrades the configured store for `network` to
+pub(crate) fn latest_schema_version(network: Network) -> i32 {
+	match config::db_backend(network) {
+		DatabaseBackend::Postgres => migrations::latest_version(migrations::POSTGRES_MIGRATIONS, migrations::POSTGRES_BASELINE_VERSION),
+		DatabaseBackend::Sqlite(_) => migrations::latest_version(migrations::SQLITE_MIGRATIONS, migrations::SQLITE_BASELINE_VERSION),
+	}
+}
+
+/// Open the configured store for `network`, which must have been initialized before use.
+pub(crate) fn open(network: Network) -> Arc<dyn GossipStore> {
+	match config::db_backend(network) {
+		DatabaseBackend::Postgres => Arc::new(PostgresStore::new(network)),
+		DatabaseBackend::Sqlite(path) => Arc::new(SqliteStore::open(&path)),
+	}
+}
+
+/// Open the store to run the snapshot lookups against, which is the configured Postgres read
+/// replica unless it's unreachable or lagging behind by more than [`config::MAX_READ_REPLICA_LAG`],
+/// in which case the snapshots would miss recent gossip.
+pub(crate) async fn open_for_reads<L: Deref>(network: Network, logger: L) -> Arc<dyn GossipStore> where L::Target: Logger {
+	if let (DatabaseBackend::Postgres, Some(connection_config)) = (config::db_backend(network), config::db_read_connection_config()) {
+		let replica = PostgresStore::read_replica(network, connection_config);
+		match replica.replication_lag().await {
+			Some(lag) if lag <= config::MAX_READ_REPLICA_LAG => return Arc::new(replica),
+			Some(lag) => log_warn!(logger, "Reading from the primary, as the read replica is lagging behind by {:?}", lag),
+			None => log_warn!(logger, "Reading from the primary, as the read replica is unreachable"),
+		}
+	}
+	open(network)
+}
+
+/// Create or upgrade the configured store's schema for `network`, and open it.
+pub(crate) async fn initialize<L: Deref + Clone + Send + Sync + 'static>(network: Network, logger: L) -> Arc<dyn GossipStore> where L::Target: Logger {
+	match config::db_backend(network) {
+		DatabaseBackend::Postgres => {
+			PostgresStore::initialize(network, logger).await;
+			Arc::new(PostgresStore::new(network))
+		},
+		DatabaseBackend::Sqlite(path) => {
+			let store = SqliteStore::open(&path);
+			store.initialize();
+			Arc::new(store)
+		},
+	}
+}
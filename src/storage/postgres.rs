@@ -0,0 +1,576 @@
+use std::collections::hash_map::{Entry, HashMap};
+use std::fmt::Write;
+use std::ops::Deref;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bitcoin::Network;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use lightning::{log_info, log_warn};
+use lightning::util::logger::Logger;
+use lightning::util::ser::Writeable;
+use tokio::sync::Mutex;
+use tokio_postgres::types::ToSql;
+use tokio_postgres::{Client, Config, Row};
+
+use crate::analytics::SnapshotRequest;
+use crate::config;
+use crate::history::StatsSample;
+use crate::storage::migrations::{self, POSTGRES_BASELINE_VERSION, POSTGRES_MIGRATIONS};
+use crate::storage::{seen_override, AnnouncementRow, ChannelAnnouncementRow, FirstBidirectionalUpdateRow, GossipStore, UpdateRow};
+use crate::types::GossipMessage;
+
+const POSTGRES_INSERT_TIMEOUT: Duration = Duration::from_secs(15);
+/// Keeps the parameters of a multi-row insert well within Postgres' limit of 65535
+const MAX_ROWS_PER_STATEMENT: usize = 1000;
+
+type InsertParam = Box<dyn ToSql + Send + Sync>;
+
+/// Gossip stored in Postgres. Connections are opened as needed and kept around for reuse, such
+/// that concurrent inserts don't contend for a single connection.
+pub(crate) struct PostgresStore {
+	connection_config: Config,
+	schema: Option<String>,
+	/// Whether this is a read replica, which only serves lookups
+	read_only: bool,
+	connections: Mutex<Vec<Client>>,
+}
+
+impl PostgresStore {
+	pub(crate) fn new(network: Network) -> Self {
+		// inserts connect from the persister's runtime, so the schema must be determined up front
+		Self { connection_config: config::db_connection_config(), schema: crate::db_schema(network), read_only: false, connections: Mutex::new(Vec::new()) }
+	}
+
+	/// A store reading from the replica at `connection_config`, whose schema is that of the primary
+	pub(crate) fn read_replica(network: Network, connection_config: Config) -> Self {
+		Self { connection_config, schema: crate::db_schema(network), read_only: true, connections: Mutex::new(Vec::new()) }
+	}
+
+	/// How far the server is behind its primary, which is nothing if it is the primary itself or has
+	/// replayed everything it received. `None` if the server can't be reached.
+	pub(crate) async fn replication_lag(&self) -> Option<Duration> {
+		let client = crate::try_connect_to_db(&self.connection_config, self.schema.clone(), !self.read_only).await.ok()?;
+		let lag: f64 = client.query_one("
+			SELECT CASE
+				WHEN NOT pg_is_in_recovery() OR pg_last_wal_receive_lsn() = pg_last_wal_replay_lsn() THEN 0
+				ELSE COALESCE(EXTRACT(EPOCH FROM (now() - pg_last_xact_replay_timestamp())), 'Infinity')
+			END::float8", &[]).await.ok()?.get(0);
+		self.release(client).await;
+		Some(Duration::try_from_secs_f64(lag.max(0.0)).unwrap_or(Duration::MAX))
+	}
+
+	pub(crate) async fn initialize<L: Deref + Clone + Send + Sync + 'static>(network: Network, logger: L) where L::Target: Logger {
+		// this client instance is only used once
+		let mut client = crate::connect_to_db(network).await;
+
+		let initialization = client
+			.execute(config::db_config_table_creation_query(), &[])
+			.await;
+		if let Err(initialization_error) = initialization {
+			panic!("db init error: {}", initialization_error);
+		}
+
+		let cur_schema = client.query("SELECT db_schema FROM config WHERE id = $1", &[&1]).await.unwrap();
+		if !cur_schema.is_empty() {
+			let schema = cur_schema[0].get(0);
+			if let Err(incompatibility) = migrations::pending(POSTGRES_MIGRATIONS, POSTGRES_BASELINE_VERSION, schema) {
+				panic!("db init error: {}", incompatibility);
+			}
+			config::upgrade_db(network, schema, &mut client, logger.clone()).await;
+		}
+
+		let preparation = client.execute("set time zone UTC", &[]).await;
+		if let Err(preparation_error) = preparation {
+			panic!("db preparation error: {}", preparation_error);
+		}
+
+		let initialization = client
+			.execute(
+				// TODO: figure out a way to fix the id value without Postgres complaining about
+				// its value not being default
+				"INSERT INTO config (id, db_schema) VALUES ($1, $2) ON CONFLICT (id) DO NOTHING",
+				&[&1, &POSTGRES_BASELINE_VERSION]
+			).await;
+		if let Err(initialization_error) = initialization {
+			panic!("db init error: {}", initialization_error);
+		}
+
+		let table_creation_queries = [
+			config::db_announcement_table_creation_query(),
+			config::db_channel_update_table_creation_query(),
+			config::db_channel_update_table_creation_query(),
+			config::db_node_announcement_table_creation_query()
+		];
+
+		for current_table_creation_query in table_creation_queries {
+			let initialization = client
+				.execute(current_table_creation_query, &[])
+				.await;
+			if let Err(initialization_error) = initialization {
+				panic!("db init error: {}", initialization_error);
+			}
+		}
+
+		let initialization = client
+			.batch_execute(config::db_index_creation_query())
+			.await;
+		if let Err(initialization_error) = initialization {
+			panic!("db init error: {}", initialization_error);
+		}
+
+		Self::migrate(&mut client, logger).await;
+	}
+
+	/// Apply the migrations the database hasn't seen yet, unless the legacy upgrade to the
+	/// migrations' baseline is still ongoing in the background, in which case they're applied upon
+	/// the next start.
+	async fn migrate<L: Deref>(client: &mut Client, logger: L) where L::Target: Logger {
+		let schema: i32 = client.query_one("SELECT db_schema FROM config WHERE id = $1", &[&1]).await.unwrap().get(0);
+		if schema < POSTGRES_BASELINE_VERSION {
+			log_warn!(logger, "Deferring schema migrations until the upgrade to schema {} completes", POSTGRES_BASELINE_VERSION);
+			return;
+		}
+		let pending_migrations = migrations::pending(POSTGRES_MIGRATIONS, POSTGRES_BASELINE_VERSION, schema)
+			.unwrap_or_else(|incompatibility| panic!("db init error: {}", incompatibility));
+		for migration in pending_migrations {
+			let tx = client.transaction().await.unwrap();
+			// locking the config row serializes instances migrating concurrently, any of which may
+			// have applied this migration since the schema was read above
+			let locked_schema: i32 = tx.query_one("SELECT db_schema FROM config WHERE id = 1 FOR UPDATE", &[]).await.unwrap().get(0);
+			if locked_schema >= migration.version {
+				continue;
+			}
+			log_info!(logger, "Migrating database to schema {} ({})", migration.version, migration.name);
+			tx.batch_execute(migration.sql).await.unwrap_or_else(|e| panic!("db migration {} error: {}", migration.version, e));
+			tx.execute("UPDATE config SET db_schema = $1 WHERE id = 1", &[&migration.version]).await.unwrap();
+			tx.execute("INSERT INTO schema_migrations (version, name) VALUES ($1, $2)", &[&migration.version, &migration.name]).await.unwrap();
+			tx.commit().await.unwrap();
+		}
+	}
+
+	async fn acquire(&self) -> Client {
+		let cached_client = self.connections.lock().await.pop();
+		match cached_client {
+			Some(client) => client,
+			None => crate::try_connect_to_db(&self.connection_config, self.schema.clone(), !self.read_only).await.unwrap(),
+		}
+	}
+
+	async fn release(&self, client: Client) {
+		self.connections.lock().await.push(client);
+	}
+
+	/// Insert rows, each consisting of its values followed by an optional override of when it was
+	/// seen, using as few multi-row statements as the parameter limit permits. Rows not overriding
+	/// the time they were seen are assigned the current time individually, rather than sharing the
+	/// statement's, which keeps the lookups' ordering by `seen` unambiguous.
+	async fn insert_rows(&self, insertion: &str, conflict_clause: &str, rows: Vec<(Vec<InsertParam>, Option<f64>)>) {
+		if rows.is_empty() {
+			return;
+		}
+		let client = self.acquire().await;
+		for chunk in rows.chunks(MAX_ROWS_PER_STATEMENT) {
+			let mut statement = format!("{} VALUES ", insertion);
+			let mut params: Vec<&(dyn ToSql + Sync)> = Vec::new();
+			for (row_index, (values, seen)) in chunk.iter().enumerate() {
+				if row_index > 0 {
+					statement.push_str(", ");
+				}
+				statement.push('(');
+				for value in values {
+					params.push(value.as_ref());
+					write!(statement, "${}, ", params.len()).unwrap();
+				}
+				params.push(seen);
+				write!(statement, "COALESCE(TO_TIMESTAMP(${}), clock_timestamp()))", params.len()).unwrap();
+			}
+			statement.push_str(conflict_clause);
+			tokio::time::timeout(POSTGRES_INSERT_TIMEOUT, client.execute(&statement, &params)).await.unwrap().unwrap();
+		}
+		self.release(client).await;
+	}
+
+	/// Run a query whose rows are streamed. Connections multiplex their queries, so the client is
+	/// released for reuse straight away.
+	async fn query<T: 'static, F: Fn(Row) -> T + Send + 'static>(&self, query: &str, params: &[&(dyn ToSql + Sync)], map: F) -> BoxStream<'static, T> {
+		let client = self.acquire().await;
+		let rows = client.query_raw(query, params.iter().copied()).await.unwrap();
+		self.release(client).await;
+		rows.map(move |row| map(row.unwrap())).boxed()
+	}
+}
+
+fn stats_sample_row(row: Row) -> StatsSample {
+	StatsSample {
+		sampled_at: row.get::<_, i64>("sampled_at") as u64,
+		interval_secs: row.get::<_, i64>("interval_secs") as u64,
+		channel_announcements: row.get::<_, i64>("channel_announcements") as u64,
+		channel_updates: row.get::<_, i64>("channel_updates") as u64,
+		node_announcements: row.get::<_, i64>("node_announcements") as u64,
+		channel_count: row.get::<_, i64>("channel_count") as u64,
+		node_count: row.get::<_, i64>("node_count") as u64,
+		full_snapshot_bytes: row.get::<_, Option<i64>>("full_snapshot_bytes").map(|bytes| bytes as u64),
+		snapshot_bytes: row.get::<_, Option<i64>>("snapshot_bytes").map(|bytes| bytes as u64),
+	}
+}
+
+fn announcement_row(row: Row) -> AnnouncementRow {
+	AnnouncementRow {
+		announcement_signed: row.get("announcement_signed"),
+		seen: row.get::<_, i64>("seen") as u32,
+	}
+}
+
+fn channel_announcement_row(row: Row) -> ChannelAnnouncementRow {
+	ChannelAnnouncementRow {
+		announcement_signed: row.get("announcement_signed"),
+		funding_amount_sats: row.get::<_, i64>("funding_amount_sats") as u64,
+	}
+}
+
+fn update_row(row: Row) -> UpdateRow {
+	UpdateRow {
+		id: row.get::<_, i32>("id") as i64,
+		direction: row.get("direction"),
+		blob_signed: row.get("blob_signed"),
+		seen: row.get::<_, i64>("seen") as u32,
+	}
+}
+
+#[async_trait]
+impl GossipStore for PostgresStore {
+	async fn insert_batch(&self, messages: Vec<GossipMessage>) {
+		let mut node_announcements = Vec::new();
+		let mut channel_announcements = Vec::new();
+		let mut announcement_indices = HashMap::new();
+		let mut channel_updates = Vec::new();
+		for message in messages {
+			let seen = seen_override(&message).map(|seen| seen as f64);
+			match message {
+				GossipMessage::NodeAnnouncement(announcement, _) => {
+					let mut announcement_signed = Vec::new();
+					announcement.write(&mut announcement_signed).unwrap();
+
+					let mut serialized_addresses = Vec::new();
+					announcement.contents.addresses.write(&mut serialized_addresses).unwrap();
+
+					node_announcements.push((vec![
+						Box::new(announcement.contents.node_id.to_string()) as InsertParam,
+						Box::new(announcement.contents.features.encode()),
+						Box::new(serialized_addresses),
+						Box::new(announcement.contents.timestamp as i64),
+						Box::new(announcement_signed),
+					], seen));
+				},
+				GossipMessage::ChannelAnnouncement(announcement, funding_amount_sats, _) => {
+					// start with the type prefix, which is already known a priori
+					let mut announcement_signed = Vec::new();
+					announcement.write(&mut announcement_signed).unwrap();
+
+					let row = (vec![
+						Box::new(announcement.contents.short_channel_id as i64) as InsertParam,
+						Box::new(funding_amount_sats as i64),
+						Box::new(announcement_signed),
+					], seen);
+					// a statement may only update each row once, so duplicates are resolved up front,
+					// preferring actual announcements over those seeded from a snapshot
+					match announcement_indices.entry(announcement.contents.short_channel_id) {
+						Entry::Vacant(entry) => {
+							entry.insert((channel_announcements.len(), funding_amount_sats));
+							channel_announcements.push(row);
+						},
+						Entry::Occupied(mut entry) => if entry.get().1 == 0 && funding_amount_sats != 0 {
+							channel_announcements[entry.get().0] = row;
+							entry.get_mut().1 = funding_amount_sats;
+						},
+					}
+				},
+				GossipMessage::ChannelUpdate(update, _) => {
+					let contents = &update.contents;
+					let direction = (contents.channel_flags & 1) == 1;
+					let disable = (contents.channel_flags & 2) > 0;
+
+					// start with the type prefix, which is already known a priori
+					let mut update_signed = Vec::new();
+					update.write(&mut update_signed).unwrap();
+
+					channel_updates.push((vec![
+						Box::new(contents.short_channel_id as i64) as InsertParam,
+						Box::new(contents.timestamp as i64),
+						Box::new(contents.channel_flags as i16),
+						Box::new(direction),
+						Box::new(disable),
+						Box::new(contents.cltv_expiry_delta as i32),
+						Box::new(contents.htlc_minimum_msat as i64),
+						Box::new(contents.fee_base_msat as i32),
+						Box::new(contents.fee_proportional_millionths as i32),
+						Box::new(contents.htlc_maximum_msat as i64),
+						Box::new(update_signed),
+					], seen));
+				},
+			}
+		}
+
+		self.insert_rows("INSERT INTO node_announcements (\
+			public_key, \
+			features, \
+			socket_addresses, \
+			timestamp, \
+			announcement_signed, \
+			seen \
+		)", "", node_announcements).await;
+		self.insert_rows("INSERT INTO channel_announcements (\
+			short_channel_id, \
+			funding_amount_sats, \
+			announcement_signed, \
+			seen \
+		)", " ON CONFLICT (short_channel_id) DO UPDATE SET \
+			funding_amount_sats = excluded.funding_amount_sats, \
+			announcement_signed = excluded.announcement_signed \
+			WHERE channel_announcements.funding_amount_sats = 0", channel_announcements).await;
+		self.insert_rows("INSERT INTO channel_updates (\
+			short_channel_id, \
+			timestamp, \
+			channel_flags, \
+			direction, \
+			disable, \
+			cltv_expiry_delta, \
+			htlc_minimum_msat, \
+			fee_base_msat, \
+			fee_proportional_millionths, \
+			htlc_maximum_msat, \
+			blob_signed, \
+			seen \
+		)", " ON CONFLICT DO NOTHING", channel_updates).await;
+	}
+
+	async fn channel_announcements(&self, short_channel_ids: Vec<i64>) -> BoxStream<'static, AnnouncementRow> {
+		self.query("SELECT announcement_signed, CAST(EXTRACT('epoch' from seen) AS BIGINT) AS seen FROM channel_announcements WHERE short_channel_id = any($1) ORDER BY short_channel_id ASC", &[&short_channel_ids], announcement_row).await
+	}
+
+	async fn first_bidirectional_updates(&self, short_channel_ids: Vec<i64>, since: u32) -> BoxStream<'static, FirstBidirectionalUpdateRow> {
+		self.query("
+			SELECT short_channel_id, CAST(EXTRACT('epoch' from distinct_chans.seen) AS BIGINT) AS seen FROM (
+				SELECT DISTINCT ON (short_channel_id) *
+				FROM (
+					SELECT DISTINCT ON (short_channel_id, direction) short_channel_id, seen
+					FROM channel_updates
+					WHERE short_channel_id = any($1)
+					ORDER BY short_channel_id ASC, direction ASC, seen ASC
+				) AS directional_last_seens
+				ORDER BY short_channel_id ASC, seen DESC
+			) AS distinct_chans
+			WHERE distinct_chans.seen >= TO_TIMESTAMP($2)
+			", &[&short_channel_ids, &(since as f64)], |row| FirstBidirectionalUpdateRow {
+				short_channel_id: row.get::<_, i64>("short_channel_id") as u64,
+				seen: row.get::<_, i64>("seen") as u32,
+			}).await
+	}
+
+	async fn mutated_updates(&self, short_channel_ids: Vec<i64>, since: u32) -> BoxStream<'static, UpdateRow> {
+		self.query("
+		SELECT DISTINCT ON (short_channel_id, direction) id, short_channel_id, direction, blob_signed, CAST(EXTRACT('epoch' from seen) AS BIGINT) AS seen FROM (
+			SELECT id, short_channel_id, direction, timestamp, seen, blob_signed, COALESCE (
+				disable<>lead(disable) OVER w1
+					OR
+				cltv_expiry_delta<>lead(cltv_expiry_delta) OVER w1
+					OR
+				htlc_minimum_msat<>lead(htlc_minimum_msat) OVER w1
+					OR
+				fee_base_msat<>lead(fee_base_msat) OVER w1
+					OR
+				fee_proportional_millionths<>lead(fee_proportional_millionths) OVER w1
+					OR
+				htlc_maximum_msat<>lead(htlc_maximum_msat) OVER w1,
+				TRUE
+			) has_distinct_successor
+			FROM channel_updates
+			WHERE short_channel_id = any($1) AND seen >= TO_TIMESTAMP($2)
+			WINDOW w1 AS (PARTITION BY short_channel_id, direction ORDER BY seen DESC)
+		) _
+		WHERE has_distinct_successor
+		ORDER BY short_channel_id ASC, direction ASC, timestamp DESC
+		", &[&short_channel_ids, &(since as f64)], update_row).await
+	}
+
+	async fn reference_updates(&self, last_sync_timestamp: u32) -> BoxStream<'static, UpdateRow> {
+		self.query("
+			SELECT id, direction, CAST(EXTRACT('epoch' from seen) AS BIGINT) AS seen, blob_signed FROM channel_updates
+			WHERE id IN (
+				SELECT DISTINCT ON (short_channel_id, direction) id
+				FROM channel_updates
+				WHERE seen < TO_TIMESTAMP($1) AND short_channel_id IN (
+					SELECT DISTINCT ON (short_channel_id) short_channel_id
+					FROM channel_updates
+					WHERE seen >= TO_TIMESTAMP($1)
+				)
+				ORDER BY short_channel_id ASC, direction ASC, seen DESC
+			)
+			", &[&(last_sync_timestamp as f64)], update_row).await
+	}
+
+	async fn intermediate_updates(&self, since: u32) -> BoxStream<'static, UpdateRow> {
+		self.query("
+			SELECT id, direction, blob_signed, CAST(EXTRACT('epoch' from seen) AS BIGINT) AS seen
+			FROM channel_updates
+			WHERE seen >= TO_TIMESTAMP($1)
+			ORDER BY short_channel_id ASC, timestamp DESC
+			", &[&(since as f64)], update_row).await
+	}
+
+	async fn node_reference_announcements(&self, public_keys: Vec<String>, last_sync_timestamp: u32) -> BoxStream<'static, AnnouncementRow> {
+		self.query("
+			SELECT DISTINCT ON (public_key) public_key, CAST(EXTRACT('epoch' from seen) AS BIGINT) AS seen, announcement_signed
+			FROM node_announcements
+			WHERE
+				public_key = ANY($1) AND
+				seen < TO_TIMESTAMP($2)
+			ORDER BY public_key ASC, seen DESC
+			", &[&public_keys, &(last_sync_timestamp as f64)], announcement_row).await
+	}
+
+	async fn node_intermediate_announcements(&self, public_keys: Vec<String>, since: u32) -> BoxStream<'static, AnnouncementRow> {
+		self.query("
+			SELECT announcement_signed, CAST(EXTRACT('epoch' from seen) AS BIGINT) AS seen
+			FROM node_announcements
+			WHERE
+				public_key = ANY($1) AND
+				seen >= TO_TIMESTAMP($2)
+			ORDER BY public_key ASC, timestamp DESC
+			", &[&public_keys, &(since as f64)], announcement_row).await
+	}
+
+	async fn recent_channel_announcements(&self, since: u32) -> BoxStream<'static, ChannelAnnouncementRow> {
+		self.query("
+			SELECT announcement_signed, funding_amount_sats
+			FROM channel_announcements
+			WHERE seen >= TO_TIMESTAMP($1) AND funding_amount_sats > 0
+			ORDER BY short_channel_id ASC
+			", &[&(since as f64)], channel_announcement_row).await
+	}
+
+	async fn recent_node_announcements(&self, since: u32) -> BoxStream<'static, AnnouncementRow> {
+		self.query("
+			SELECT announcement_signed, CAST(EXTRACT('epoch' from seen) AS BIGINT) AS seen
+			FROM node_announcements
+			WHERE seen >= TO_TIMESTAMP($1)
+			ORDER BY timestamp ASC
+			", &[&(since as f64)], announcement_row).await
+	}
+
+	async fn prune_channel_updates(&self, before: u32, keep_latest: u32) -> u64 {
+		let client = self.acquire().await;
+		let pruned = client.execute("
+			DELETE FROM channel_updates WHERE id IN (
+				SELECT id FROM (
+					SELECT id, seen,
+						ROW_NUMBER() OVER (PARTITION BY short_channel_id, direction ORDER BY seen DESC, id DESC) AS recency,
+						ROW_NUMBER() OVER (PARTITION BY short_channel_id, direction ORDER BY seen ASC, id ASC) AS age
+					FROM channel_updates
+				) AS ranked_updates
+				WHERE seen < TO_TIMESTAMP($1) AND recency > $2 AND age > 1
+			)", &[&(before as f64), &(keep_latest as i64)]).await.unwrap();
+		self.release(client).await;
+		pruned
+	}
+
+	async fn insert_snapshot_requests(&self, requests: Vec<SnapshotRequest>) {
+		let rows = requests.into_iter().map(|request| (vec![
+			Box::new(request.last_sync_timestamp as i64) as InsertParam,
+			Box::new(request.lag_bucket),
+			Box::new(request.profile),
+			Box::new(request.served_snapshot),
+			Box::new(request.bytes as i64),
+			Box::new(request.user_agent),
+		], Some(request.requested_at as f64))).collect();
+		self.insert_rows("INSERT INTO snapshot_requests (\
+			last_sync_timestamp, \
+			lag_bucket, \
+			profile, \
+			served_snapshot, \
+			bytes, \
+			user_agent, \
+			requested_at \
+		)", "", rows).await;
+	}
+
+	async fn insert_stats_sample(&self, sample: StatsSample) {
+		let row = (vec![
+			Box::new(sample.interval_secs as i64) as InsertParam,
+			Box::new(sample.channel_announcements as i64),
+			Box::new(sample.channel_updates as i64),
+			Box::new(sample.node_announcements as i64),
+			Box::new(sample.channel_count as i64),
+			Box::new(sample.node_count as i64),
+			Box::new(sample.full_snapshot_bytes.map(|bytes| bytes as i64)),
+			Box::new(sample.snapshot_bytes.map(|bytes| bytes as i64)),
+		], Some(sample.sampled_at as f64));
+		self.insert_rows("INSERT INTO stats_samples (\
+			interval_secs, \
+			channel_announcements, \
+			channel_updates, \
+			node_announcements, \
+			channel_count, \
+			node_count, \
+			full_snapshot_bytes, \
+			snapshot_bytes, \
+			sampled_at \
+		)", "", vec![row]).await;
+	}
+
+	async fn stats_samples(&self, since: u64, limit: u32) -> Vec<StatsSample> {
+		self.query("
+			SELECT CAST(EXTRACT('epoch' from sampled_at) AS BIGINT) AS sampled_at, interval_secs, channel_announcements, channel_updates,
+				node_announcements, channel_count, node_count, full_snapshot_bytes, snapshot_bytes
+			FROM stats_samples
+			WHERE sampled_at >= TO_TIMESTAMP($1)
+			ORDER BY sampled_at ASC
+			LIMIT $2
+			", &[&(since as f64), &(limit as i64)], stats_sample_row).await.collect().await
+	}
+
+	async fn prune_stats_samples(&self, before: u64) -> u64 {
+		let client = self.acquire().await;
+		let pruned = client.execute("DELETE FROM stats_samples WHERE sampled_at < TO_TIMESTAMP($1)", &[&(before as f64)]).await.unwrap();
+		self.release(client).await;
+		pruned
+	}
+
+	async fn is_writable(&self) -> bool {
+		// use a fresh connection rather than a cached one, which may outlive an unreachable server
+		let (client, connection) = match config::db_connection_config().connect(crate::storage::tls_connector()).await {
+			Ok(connection) => connection,
+			Err(_) => return false,
+		};
+		tokio::spawn(async move {
+			let _ = connection.await;
+		});
+		// a standby replica is reachable, but not writable
+		match client.query_one("SELECT NOT pg_is_in_recovery()", &[]).await {
+			Ok(row) => row.get::<_, bool>(0),
+			Err(_) => false,
+		}
+	}
+
+	async fn schema_version(&self) -> Option<i32> {
+		let client = self.acquire().await;
+		// the config table doesn't exist before initialization
+		let schema = client.query_opt("SELECT db_schema FROM config WHERE id = $1", &[&1]).await.ok().flatten().map(|row| row.get(0));
+		self.release(client).await;
+		schema
+	}
+
+	async fn orphaned_update_count(&self) -> u64 {
+		let client = self.acquire().await;
+		let count: i64 = client.query_one("
+			SELECT COUNT(*) FROM channel_updates
+			WHERE NOT EXISTS (
+				SELECT 1 FROM channel_announcements
+				WHERE channel_announcements.short_channel_id = channel_updates.short_channel_id
+			)", &[]).await.unwrap().get(0);
+		self.release(client).await;
+		count as u64
+	}
+}
@@ -0,0 +1,491 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use lightning::util::ser::Writeable;
+use rusqlite::{Connection, DatabaseName, Params, Row};
+
+use crate::analytics::SnapshotRequest;
+use crate::history::StatsSample;
+use crate::storage::migrations::{self, SQLITE_BASELINE_VERSION, SQLITE_MIGRATIONS};
+use crate::storage::{seen_override, AnnouncementRow, ChannelAnnouncementRow, FirstBidirectionalUpdateRow, GossipStore, UpdateRow};
+use crate::types::GossipMessage;
+
+/// The same tables and indices as used with Postgres, with `seen` holding seconds since the epoch.
+/// Databases created from it are at [`SQLITE_BASELINE_VERSION`], and then migrated.
+const SCHEMA: &str = "
+	CREATE TABLE IF NOT EXISTS config (
+		id INTEGER PRIMARY KEY,
+		db_schema INTEGER
+	);
+	CREATE TABLE IF NOT EXISTS channel_announcements (
+		id INTEGER PRIMARY KEY AUTOINCREMENT,
+		short_channel_id INTEGER NOT NULL UNIQUE,
+		funding_amount_sats INTEGER NOT NULL,
+		announcement_signed BLOB,
+		seen INTEGER NOT NULL DEFAULT (unixepoch())
+	);
+	CREATE TABLE IF NOT EXISTS channel_updates (
+		id INTEGER PRIMARY KEY AUTOINCREMENT,
+		short_channel_id INTEGER NOT NULL,
+		timestamp INTEGER NOT NULL,
+		channel_flags INTEGER NOT NULL,
+		direction INTEGER NOT NULL,
+		disable INTEGER NOT NULL,
+		cltv_expiry_delta INTEGER NOT NULL,
+		htlc_minimum_msat INTEGER NOT NULL,
+		fee_base_msat INTEGER NOT NULL,
+		fee_proportional_millionths INTEGER NOT NULL,
+		htlc_maximum_msat INTEGER NOT NULL,
+		blob_signed BLOB NOT NULL,
+		seen INTEGER NOT NULL DEFAULT (unixepoch())
+	);
+	CREATE TABLE IF NOT EXISTS node_announcements (
+		id INTEGER PRIMARY KEY AUTOINCREMENT,
+		public_key TEXT NOT NULL,
+		features BLOB NOT NULL,
+		socket_addresses BLOB NOT NULL,
+		timestamp INTEGER NOT NULL,
+		announcement_signed BLOB,
+		seen INTEGER NOT NULL DEFAULT (unixepoch())
+	);
+	CREATE INDEX IF NOT EXISTS channel_updates_seen_scid ON channel_updates(seen, short_channel_id);
+	CREATE INDEX IF NOT EXISTS channel_updates_scid_dir_seen ON channel_updates(short_channel_id, direction, seen);
+	CREATE UNIQUE INDEX IF NOT EXISTS channel_updates_key ON channel_updates(short_channel_id, direction, timestamp);
+	CREATE INDEX IF NOT EXISTS channel_updates_scid_timestamp ON channel_updates(short_channel_id, timestamp);
+	CREATE INDEX IF NOT EXISTS node_announcements_seen_pubkey ON node_announcements(seen, public_key);
+	CREATE INDEX IF NOT EXISTS node_announcements_pubkey_seen ON node_announcements(public_key, seen);
+";
+
+/// Gossip stored in a single SQLite database file. Having no `DISTINCT ON`, the lookups rank rows
+/// using window functions instead, and lists of ids are passed as JSON arrays. `seen` only has a
+/// resolution of seconds, so ties are broken by insertion order.
+pub(crate) struct SqliteStore {
+	connection: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStore {
+	pub(crate) fn open(path: &str) -> Self {
+		if let Some(directory) = Path::new(path).parent() {
+			std::fs::create_dir_all(directory).unwrap();
+		}
+		let connection = Connection::open(path).unwrap_or_else(|e| panic!("Failed to open SQLite database {}: {}", path, e));
+		// let the persister's writes proceed alongside snapshot lookups
+		connection.pragma_update(None, "journal_mode", "WAL").unwrap();
+		connection.busy_timeout(std::time::Duration::from_secs(15)).unwrap();
+		Self { connection: Arc::new(Mutex::new(connection)) }
+	}
+
+	pub(crate) fn initialize(&self) {
+		let connection = self.connection.lock().unwrap();
+		connection.execute_batch(SCHEMA).unwrap_or_else(|e| panic!("db init error: {}", e));
+		connection.execute("INSERT INTO config (id, db_schema) VALUES (1, ?1) ON CONFLICT (id) DO NOTHING", [SQLITE_BASELINE_VERSION])
+			.unwrap_or_else(|e| panic!("db init error: {}", e));
+
+		let schema: i32 = connection.query_row("SELECT db_schema FROM config WHERE id = 1", [], |row| row.get(0)).unwrap();
+		let pending_migrations = migrations::pending(SQLITE_MIGRATIONS, SQLITE_BASELINE_VERSION, schema)
+			.unwrap_or_else(|incompatibility| panic!("db init error: {}", incompatibility));
+		for migration in pending_migrations {
+			let tx = connection.unchecked_transaction().unwrap();
+			tx.execute_batch(migration.sql).unwrap_or_else(|e| panic!("db migration {} error: {}", migration.version, e));
+			tx.execute("UPDATE config SET db_schema = ?1 WHERE id = 1", [migration.version]).unwrap();
+			tx.execute("INSERT INTO schema_migrations (version, name) VALUES (?1, ?2)", (migration.version, migration.name)).unwrap();
+			tx.commit().unwrap();
+		}
+	}
+
+	/// Run a query on the blocking thread pool, collecting its rows
+	async fn query<T, P, F>(&self, query: &'static str, params: P, map: F) -> BoxStream<'static, T>
+		where T: Send + 'static, P: Params + Send + 'static, F: FnMut(&Row) -> rusqlite::Result<T> + Send + 'static
+	{
+		let connection = Arc::clone(&self.connection);
+		let rows = tokio::task::spawn_blocking(move || {
+			let connection = connection.lock().unwrap();
+			let mut statement = connection.prepare_cached(query).unwrap();
+			let rows = statement.query_map(params, map).unwrap();
+			rows.collect::<rusqlite::Result<Vec<T>>>().unwrap()
+		}).await.unwrap();
+		futures::stream::iter(rows).boxed()
+	}
+}
+
+fn stats_sample_row(row: &Row) -> rusqlite::Result<StatsSample> {
+	Ok(StatsSample {
+		sampled_at: row.get::<_, i64>("sampled_at")? as u64,
+		interval_secs: row.get::<_, i64>("interval_secs")? as u64,
+		channel_announcements: row.get::<_, i64>("channel_announcements")? as u64,
+		channel_updates: row.get::<_, i64>("channel_updates")? as u64,
+		node_announcements: row.get::<_, i64>("node_announcements")? as u64,
+		channel_count: row.get::<_, i64>("channel_count")? as u64,
+		node_count: row.get::<_, i64>("node_count")? as u64,
+		full_snapshot_bytes: row.get::<_, Option<i64>>("full_snapshot_bytes")?.map(|bytes| bytes as u64),
+		snapshot_bytes: row.get::<_, Option<i64>>("snapshot_bytes")?.map(|bytes| bytes as u64),
+	})
+}
+
+fn announcement_row(row: &Row) -> rusqlite::Result<AnnouncementRow> {
+	Ok(AnnouncementRow {
+		announcement_signed: row.get("announcement_signed")?,
+		seen: row.get::<_, i64>("seen")? as u32,
+	})
+}
+
+fn channel_announcement_row(row: &Row) -> rusqlite::Result<ChannelAnnouncementRow> {
+	Ok(ChannelAnnouncementRow {
+		announcement_signed: row.get("announcement_signed")?,
+		funding_amount_sats: row.get::<_, i64>("funding_amount_sats")? as u64,
+	})
+}
+
+fn update_row(row: &Row) -> rusqlite::Result<UpdateRow> {
+	Ok(UpdateRow {
+		id: row.get("id")?,
+		direction: row.get("direction")?,
+		blob_signed: row.get("blob_signed")?,
+		seen: row.get::<_, i64>("seen")? as u32,
+	})
+}
+
+fn json_array<T: serde::Serialize>(values: &[T]) -> String {
+	serde_json::to_string(values).unwrap()
+}
+
+#[async_trait]
+impl GossipStore for SqliteStore {
+	async fn insert_batch(&self, messages: Vec<GossipMessage>) {
+		let connection = Arc::clone(&self.connection);
+		tokio::task::spawn_blocking(move || {
+			let mut connection = connection.lock().unwrap();
+			let transaction = connection.transaction().unwrap();
+			for message in messages {
+				let seen = seen_override(&message).map(|seen| seen as i64);
+				match message {
+					GossipMessage::NodeAnnouncement(announcement, _) => {
+						let mut announcement_signed = Vec::new();
+						announcement.write(&mut announcement_signed).unwrap();
+
+						let mut serialized_addresses = Vec::new();
+						announcement.contents.addresses.write(&mut serialized_addresses).unwrap();
+
+						transaction.prepare_cached("INSERT INTO node_announcements (\
+							public_key, \
+							features, \
+							socket_addresses, \
+							timestamp, \
+							announcement_signed, \
+							seen \
+						) VALUES (?1, ?2, ?3, ?4, ?5, COALESCE(?6, unixepoch()))").unwrap().execute((
+							announcement.contents.node_id.to_string(),
+							announcement.contents.features.encode(),
+							serialized_addresses,
+							announcement.contents.timestamp as i64,
+							announcement_signed,
+							seen,
+						)).unwrap();
+					},
+					GossipMessage::ChannelAnnouncement(announcement, funding_amount_sats, _) => {
+						let mut announcement_signed = Vec::new();
+						announcement.write(&mut announcement_signed).unwrap();
+
+						transaction.prepare_cached("INSERT INTO channel_announcements (\
+							short_channel_id, \
+							funding_amount_sats, \
+							announcement_signed, \
+							seen \
+						) VALUES (?1, ?2, ?3, COALESCE(?4, unixepoch())) ON CONFLICT (short_channel_id) DO UPDATE SET \
+							funding_amount_sats = excluded.funding_amount_sats, \
+							announcement_signed = excluded.announcement_signed \
+							WHERE channel_announcements.funding_amount_sats = 0").unwrap().execute((
+							announcement.contents.short_channel_id as i64,
+							funding_amount_sats as i64,
+							announcement_signed,
+							seen,
+						)).unwrap();
+					},
+					GossipMessage::ChannelUpdate(update, _) => {
+						let contents = &update.contents;
+						let direction = (contents.channel_flags & 1) == 1;
+						let disable = (contents.channel_flags & 2) > 0;
+
+						let mut update_signed = Vec::new();
+						update.write(&mut update_signed).unwrap();
+
+						transaction.prepare_cached("INSERT INTO channel_updates (\
+							short_channel_id, \
+							timestamp, \
+							channel_flags, \
+							direction, \
+							disable, \
+							cltv_expiry_delta, \
+							htlc_minimum_msat, \
+							fee_base_msat, \
+							fee_proportional_millionths, \
+							htlc_maximum_msat, \
+							blob_signed, \
+							seen \
+						) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, COALESCE(?12, unixepoch())) ON CONFLICT DO NOTHING").unwrap().execute((
+							contents.short_channel_id as i64,
+							contents.timestamp as i64,
+							contents.channel_flags as i64,
+							direction,
+							disable,
+							contents.cltv_expiry_delta as i64,
+							contents.htlc_minimum_msat as i64,
+							contents.fee_base_msat as i64,
+							contents.fee_proportional_millionths as i64,
+							contents.htlc_maximum_msat as i64,
+							update_signed,
+							seen,
+						)).unwrap();
+					},
+				}
+			}
+			transaction.commit().unwrap();
+		}).await.unwrap();
+	}
+
+	async fn channel_announcements(&self, short_channel_ids: Vec<i64>) -> BoxStream<'static, AnnouncementRow> {
+		self.query("
+			SELECT announcement_signed, seen FROM channel_announcements
+			WHERE short_channel_id IN (SELECT value FROM json_each(?1))
+			ORDER BY short_channel_id ASC
+			", [json_array(&short_channel_ids)], announcement_row).await
+	}
+
+	async fn first_bidirectional_updates(&self, short_channel_ids: Vec<i64>, since: u32) -> BoxStream<'static, FirstBidirectionalUpdateRow> {
+		self.query("
+			SELECT short_channel_id, MAX(first_seen) AS seen FROM (
+				SELECT short_channel_id, MIN(seen) AS first_seen
+				FROM channel_updates
+				WHERE short_channel_id IN (SELECT value FROM json_each(?1))
+				GROUP BY short_channel_id, direction
+			)
+			GROUP BY short_channel_id
+			HAVING MAX(first_seen) >= ?2
+			ORDER BY short_channel_id ASC
+			", (json_array(&short_channel_ids), since), |row| Ok(FirstBidirectionalUpdateRow {
+				short_channel_id: row.get::<_, i64>("short_channel_id")? as u64,
+				seen: row.get::<_, i64>("seen")? as u32,
+			})).await
+	}
+
+	async fn mutated_updates(&self, short_channel_ids: Vec<i64>, since: u32) -> BoxStream<'static, UpdateRow> {
+		self.query("
+			SELECT id, direction, blob_signed, seen FROM (
+				SELECT id, short_channel_id, direction, blob_signed, seen,
+					ROW_NUMBER() OVER (PARTITION BY short_channel_id, direction ORDER BY timestamp DESC) AS position
+				FROM (
+					SELECT id, short_channel_id, direction, timestamp, seen, blob_signed, COALESCE (
+						disable<>lead(disable) OVER w1
+							OR
+						cltv_expiry_delta<>lead(cltv_expiry_delta) OVER w1
+							OR
+						htlc_minimum_msat<>lead(htlc_minimum_msat) OVER w1
+							OR
+						fee_base_msat<>lead(fee_base_msat) OVER w1
+							OR
+						fee_proportional_millionths<>lead(fee_proportional_millionths) OVER w1
+							OR
+						htlc_maximum_msat<>lead(htlc_maximum_msat) OVER w1,
+						TRUE
+					) AS has_distinct_successor
+					FROM channel_updates
+					WHERE short_channel_id IN (SELECT value FROM json_each(?1)) AND seen >= ?2
+					WINDOW w1 AS (PARTITION BY short_channel_id, direction ORDER BY seen DESC, id DESC)
+				)
+				WHERE has_distinct_successor
+			)
+			WHERE position = 1
+			ORDER BY short_channel_id ASC, direction ASC
+			", (json_array(&short_channel_ids), since), update_row).await
+	}
+
+	async fn reference_updates(&self, last_sync_timestamp: u32) -> BoxStream<'static, UpdateRow> {
+		self.query("
+			SELECT id, direction, seen, blob_signed FROM (
+				SELECT id, direction, seen, blob_signed,
+					ROW_NUMBER() OVER (PARTITION BY short_channel_id, direction ORDER BY seen DESC, id DESC) AS position
+				FROM channel_updates
+				WHERE seen < ?1 AND short_channel_id IN (
+					SELECT short_channel_id FROM channel_updates WHERE seen >= ?1
+				)
+			)
+			WHERE position = 1
+			", [last_sync_timestamp], update_row).await
+	}
+
+	async fn intermediate_updates(&self, since: u32) -> BoxStream<'static, UpdateRow> {
+		self.query("
+			SELECT id, direction, blob_signed, seen
+			FROM channel_updates
+			WHERE seen >= ?1
+			ORDER BY short_channel_id ASC, timestamp DESC
+			", [since], update_row).await
+	}
+
+	async fn node_reference_announcements(&self, public_keys: Vec<String>, last_sync_timestamp: u32) -> BoxStream<'static, AnnouncementRow> {
+		self.query("
+			SELECT seen, announcement_signed FROM (
+				SELECT public_key, seen, announcement_signed,
+					ROW_NUMBER() OVER (PARTITION BY public_key ORDER BY seen DESC, id DESC) AS position
+				FROM node_announcements
+				WHERE public_key IN (SELECT value FROM json_each(?1)) AND seen < ?2
+			)
+			WHERE position = 1
+			ORDER BY public_key ASC
+			", (json_array(&public_keys), last_sync_timestamp), announcement_row).await
+	}
+
+	async fn node_intermediate_announcements(&self, public_keys: Vec<String>, since: u32) -> BoxStream<'static, AnnouncementRow> {
+		self.query("
+			SELECT announcement_signed, seen
+			FROM node_announcements
+			WHERE public_key IN (SELECT value FROM json_each(?1)) AND seen >= ?2
+			ORDER BY public_key ASC, timestamp DESC
+			", (json_array(&public_keys), since), announcement_row).await
+	}
+
+	async fn recent_channel_announcements(&self, since: u32) -> BoxStream<'static, ChannelAnnouncementRow> {
+		self.query("
+			SELECT announcement_signed, funding_amount_sats
+			FROM channel_announcements
+			WHERE seen >= ?1 AND funding_amount_sats > 0
+			ORDER BY short_channel_id ASC
+			", [since], channel_announcement_row).await
+	}
+
+	async fn recent_node_announcements(&self, since: u32) -> BoxStream<'static, AnnouncementRow> {
+		self.query("
+			SELECT announcement_signed, seen
+			FROM node_announcements
+			WHERE seen >= ?1
+			ORDER BY timestamp ASC
+			", [since], announcement_row).await
+	}
+
+	async fn prune_channel_updates(&self, before: u32, keep_latest: u32) -> u64 {
+		let connection = Arc::clone(&self.connection);
+		tokio::task::spawn_blocking(move || {
+			let connection = connection.lock().unwrap();
+			connection.execute("
+				DELETE FROM channel_updates WHERE id IN (
+					SELECT id FROM (
+						SELECT id, seen,
+							ROW_NUMBER() OVER (PARTITION BY short_channel_id, direction ORDER BY seen DESC, id DESC) AS recency,
+							ROW_NUMBER() OVER (PARTITION BY short_channel_id, direction ORDER BY seen ASC, id ASC) AS age
+						FROM channel_updates
+					)
+					WHERE seen < ?1 AND recency > ?2 AND age > 1
+				)", (before as i64, keep_latest as i64)).unwrap() as u64
+		}).await.unwrap()
+	}
+
+	async fn insert_snapshot_requests(&self, requests: Vec<SnapshotRequest>) {
+		let connection = Arc::clone(&self.connection);
+		tokio::task::spawn_blocking(move || {
+			let mut connection = connection.lock().unwrap();
+			let transaction = connection.transaction().unwrap();
+			for request in requests {
+				transaction.prepare_cached("INSERT INTO snapshot_requests (\
+					last_sync_timestamp, \
+					lag_bucket, \
+					profile, \
+					served_snapshot, \
+					bytes, \
+					user_agent, \
+					requested_at \
+				) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)").unwrap().execute((
+					request.last_sync_timestamp as i64,
+					request.lag_bucket,
+					request.profile,
+					request.served_snapshot,
+					request.bytes as i64,
+					request.user_agent,
+					request.requested_at as i64,
+				)).unwrap();
+			}
+			transaction.commit().unwrap();
+		}).await.unwrap()
+	}
+
+	async fn insert_stats_sample(&self, sample: StatsSample) {
+		let connection = Arc::clone(&self.connection);
+		tokio::task::spawn_blocking(move || {
+			let connection = connection.lock().unwrap();
+			connection.prepare_cached("INSERT INTO stats_samples (\
+				interval_secs, \
+				channel_announcements, \
+				channel_updates, \
+				node_announcements, \
+				channel_count, \
+				node_count, \
+				full_snapshot_bytes, \
+				snapshot_bytes, \
+				sampled_at \
+			) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)").unwrap().execute((
+				sample.interval_secs as i64,
+				sample.channel_announcements as i64,
+				sample.channel_updates as i64,
+				sample.node_announcements as i64,
+				sample.channel_count as i64,
+				sample.node_count as i64,
+				sample.full_snapshot_bytes.map(|bytes| bytes as i64),
+				sample.snapshot_bytes.map(|bytes| bytes as i64),
+				sample.sampled_at as i64,
+			)).unwrap();
+		}).await.unwrap()
+	}
+
+	async fn stats_samples(&self, since: u64, limit: u32) -> Vec<StatsSample> {
+		self.query("
+			SELECT sampled_at, interval_secs, channel_announcements, channel_updates, node_announcements,
+				channel_count, node_count, full_snapshot_bytes, snapshot_bytes
+			FROM stats_samples
+			WHERE sampled_at >= ?1
+			ORDER BY sampled_at ASC
+			LIMIT ?2
+			", (since as i64, limit as i64), stats_sample_row).await.collect().await
+	}
+
+	async fn prune_stats_samples(&self, before: u64) -> u64 {
+		let connection = Arc::clone(&self.connection);
+		tokio::task::spawn_blocking(move || {
+			let connection = connection.lock().unwrap();
+			connection.execute("DELETE FROM stats_samples WHERE sampled_at < ?1", [before as i64]).unwrap() as u64
+		}).await.unwrap()
+	}
+
+	async fn is_writable(&self) -> bool {
+		let connection = Arc::clone(&self.connection);
+		tokio::task::spawn_blocking(move || {
+			let connection = connection.lock().unwrap();
+			// taking the write lock fails if another process holds it for too long
+			!connection.is_readonly(DatabaseName::Main).unwrap_or(true)
+				&& connection.execute_batch("BEGIN IMMEDIATE; ROLLBACK;").is_ok()
+		}).await.unwrap_or(false)
+	}
+
+	async fn schema_version(&self) -> Option<i32> {
+		let connection = Arc::clone(&self.connection);
+		tokio::task::spawn_blocking(move || {
+			let connection = connection.lock().unwrap();
+			// the config table doesn't exist before initialization
+			connection.query_row("SELECT db_schema FROM config WHERE id = 1", [], |row| row.get(0)).ok()
+		}).await.unwrap()
+	}
+
+	async fn orphaned_update_count(&self) -> u64 {
+		let connection = Arc::clone(&self.connection);
+		tokio::task::spawn_blocking(move || {
+			let connection = connection.lock().unwrap();
+			connection.query_row("
+				SELECT COUNT(*) FROM channel_updates
+				WHERE NOT EXISTS (
+					SELECT 1 FROM channel_announcements
+					WHERE channel_announcements.short_channel_id = channel_updates.short_channel_id
+				)", [], |row| row.get::<_, i64>(0)).unwrap() as u64
+		}).await.unwrap()
+	}
+}
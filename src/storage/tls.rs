@@ -0,0 +1,95 @@
+//! TLS for the Postgres connections, based on rustls
+
+use std::sync::Arc;
+
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{CertificateError, ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use tokio_postgres_rustls::MakeRustlsConnect;
+
+use crate::config::{self, DbSslMode};
+
+/// The connector used for all Postgres connections. Whether it's used to establish TLS at all
+/// depends on the SSL mode set in [`config::db_connection_config`].
+pub(crate) fn tls_connector() -> MakeRustlsConnect {
+	let tls_config = config::db_tls_config();
+	let provider = Arc::new(rustls::crypto::ring::default_provider());
+
+	let mut roots = RootCertStore::empty();
+	match &tls_config.root_cert {
+		Some(path) => {
+			let certificates = CertificateDer::pem_file_iter(path)
+				.and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+				.unwrap_or_else(|e| panic!("Failed to read the Postgres root certificate {}: {}", path, e));
+			let (added, _) = roots.add_parsable_certificates(certificates);
+			assert!(added > 0, "The Postgres root certificate file {} contains no valid certificates", path);
+		},
+		None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+	}
+	let inner = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), Arc::clone(&provider)).build().unwrap();
+	let verifier = ServerVerifier {
+		inner,
+		verify_chain: match tls_config.mode {
+			DbSslMode::Disable | DbSslMode::Prefer => false,
+			DbSslMode::Require => tls_config.root_cert.is_some(),
+			DbSslMode::VerifyCa | DbSslMode::VerifyFull => true,
+		},
+		verify_host: tls_config.mode == DbSslMode::VerifyFull,
+	};
+
+	let builder = ClientConfig::builder_with_provider(provider)
+		.with_safe_default_protocol_versions()
+		.unwrap()
+		.dangerous()
+		.with_custom_certificate_verifier(Arc::new(verifier));
+	let client_config = match &tls_config.client_cert {
+		Some((cert_path, key_path)) => {
+			let certificates = CertificateDer::pem_file_iter(cert_path)
+				.and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+				.unwrap_or_else(|e| panic!("Failed to read the Postgres client certificate {}: {}", cert_path, e));
+			let key = PrivateKeyDer::from_pem_file(key_path)
+				.unwrap_or_else(|e| panic!("Failed to read the Postgres client key {}: {}", key_path, e));
+			builder.with_client_auth_cert(certificates, key)
+				.unwrap_or_else(|e| panic!("Invalid Postgres client certificate or key: {}", e))
+		},
+		None => builder.with_no_client_auth(),
+	};
+	MakeRustlsConnect::new(client_config)
+}
+
+/// Verifies the server's certificate to the extent the SSL mode demands. Handshake signatures are
+/// always verified, such that the certificate presented is at least the server's own.
+#[derive(Debug)]
+struct ServerVerifier {
+	inner: Arc<WebPkiServerVerifier>,
+	verify_chain: bool,
+	verify_host: bool,
+}
+
+impl ServerCertVerifier for ServerVerifier {
+	fn verify_server_cert(&self, end_entity: &CertificateDer<'_>, intermediates: &[CertificateDer<'_>], server_name: &ServerName<'_>, ocsp_response: &[u8], now: UnixTime) -> Result<ServerCertVerified, rustls::Error> {
+		if !self.verify_chain {
+			return Ok(ServerCertVerified::assertion());
+		}
+		match self.inner.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now) {
+			Err(rustls::Error::InvalidCertificate(CertificateError::NotValidForName | CertificateError::NotValidForNameContext { .. })) if !self.verify_host => {
+				Ok(ServerCertVerified::assertion())
+			},
+			verification => verification,
+		}
+	}
+
+	fn verify_tls12_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+		self.inner.verify_tls12_signature(message, cert, dss)
+	}
+
+	fn verify_tls13_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+		self.inner.verify_tls13_signature(message, cert, dss)
+	}
+
+	fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+		self.inner.supported_verify_schemes()
+	}
+}
@@ -1,26 +1,43 @@
 //! Multi-module tests that use database fixtures
 
 use std::cell::RefCell;
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
 use std::{fs, thread};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use bitcoin::blockdata::constants::ChainHash;
-use bitcoin::Network;
+use bitcoin::{Amount, Network};
 use bitcoin::secp256k1::ecdsa::Signature;
 use bitcoin::secp256k1::{Secp256k1, SecretKey};
 use bitcoin::hashes::Hash;
 use bitcoin::hashes::sha256d::Hash as Sha256dHash;
+use futures::StreamExt;
 use hex_conservative::DisplayHex;
-use lightning::ln::msgs::{ChannelAnnouncement, ChannelUpdate, NodeAnnouncement, SocketAddress, UnsignedChannelAnnouncement, UnsignedChannelUpdate, UnsignedNodeAnnouncement};
+use lightning::events::MessageSendEventsProvider;
+use lightning::ln::msgs::{ChannelAnnouncement, ChannelUpdate, NodeAnnouncement, RoutingMessageHandler, SocketAddress, UnsignedChannelAnnouncement, UnsignedChannelUpdate, UnsignedNodeAnnouncement};
 use lightning::routing::gossip::{NetworkGraph, NodeAlias, NodeId};
 use lightning::types::features::{ChannelFeatures, NodeFeatures};
 use lightning::util::ser::Writeable;
 use lightning_rapid_gossip_sync::RapidGossipSync;
-use crate::{calculate_delta, config, serialize_delta, serialize_empty_blob};
+use crate::analytics::SnapshotRequest;
+use crate::history::{self, StatsSample};
+use crate::{calculate_delta, calculate_store_delta, config, lookup, serialize_delta, serialize_empty_blob};
+use crate::blocklist::Blocklist;
+use crate::chain::mock::MockChainSource;
+use crate::downloader::GossipRouter;
+use crate::feed::GossipFeed;
+use crate::inspect::ParsedSnapshot;
+use crate::leader::Leadership;
+use crate::metrics::Metrics;
+use crate::peer_health::PeerHealthTracker;
 use crate::persistence::GossipPersister;
 use crate::snapshot::Snapshotter;
+use crate::storage::{self, GossipStore, PostgresStore, SqliteStore};
 use crate::types::{GossipMessage, tests::TestLogger};
 
+#[cfg(feature = "regtest-tests")]
+mod regtest;
+
 const CLIENT_BACKDATE_INTERVAL: u32 = 3600 * 24 * 7; // client backdates RGS by a week
 
 thread_local! {
@@ -130,6 +147,12 @@ fn generate_update(scid: u64, direction: bool, timestamp: u32, expiry_delta: u16
 	}
 }
 
+fn sign_update(mut update: ChannelUpdate, private_key: &SecretKey) -> ChannelUpdate {
+	let msg_hash = bitcoin::secp256k1::Message::from_slice(&Sha256dHash::hash(&update.contents.encode()[..])[..]).unwrap();
+	update.signature = Secp256k1::new().sign_ecdsa(&msg_hash, private_key);
+	update
+}
+
 struct SchemaSanitizer {}
 
 impl SchemaSanitizer {
@@ -196,7 +219,7 @@ impl Drop for CacheSanitizer {
 
 
 async fn clean_test_db() {
-	let client = crate::connect_to_db().await;
+	let client = crate::connect_to_db(Network::Bitcoin).await;
 	let schema = db_test_schema();
 	client.execute(&format!("DROP SCHEMA IF EXISTS {} CASCADE", schema), &[]).await.unwrap();
 	IS_TEST_SCHEMA_CLEAN.with(|cleanliness_reference| {
@@ -220,12 +243,179 @@ async fn test_persistence_runtime() {
 	clean_test_db().await;
 }
 
+#[tokio::test]
+async fn test_persistence_shutdown() {
+	let schema_sanitizer = SchemaSanitizer::new();
+	let cache_sanitizer = CacheSanitizer::new(&schema_sanitizer);
+	fs::create_dir_all(cache_sanitizer.cache_path()).unwrap();
+	// no other test operates on testnet
+	std::env::set_var("RAPID_GOSSIP_SYNC_SERVER_CACHES_PATH_TESTNET", cache_sanitizer.cache_path());
+	let logger = Arc::new(TestLogger::new());
+	let network_graph_arc = Arc::new(NetworkGraph::new(Network::Testnet, logger.clone()));
+	let (mut persister, sender) = GossipPersister::new(network_graph_arc.clone(), logger.clone()).await;
+
+	let short_channel_id = 1;
+	let timestamp = current_time() - 10;
+	sender.send(GossipMessage::ChannelAnnouncement(generate_channel_announcement(short_channel_id), 100, None)).await.unwrap();
+	sender.send(GossipMessage::ChannelUpdate(generate_update(short_channel_id, false, timestamp, 0, 0, 0, 5, 0), None)).await.unwrap();
+	sender.send(GossipMessage::ChannelUpdate(generate_update(short_channel_id, true, timestamp, 0, 0, 0, 10, 0), None)).await.unwrap();
+
+	// the queued gossip is persisted even though the sender is still around
+	persister.persist_gossip_until(std::future::ready(())).await;
+	assert!(sender.send(GossipMessage::ChannelUpdate(generate_update(short_channel_id, false, timestamp + 1, 0, 0, 0, 5, 0), None)).await.is_err());
+	logger.assert_log_contains("rapid_gossip_sync_server::persistence", "Flushed gossip persistence queue", 1);
+	assert!(fs::metadata(format!("{}network_graph.bin", cache_sanitizer.cache_path())).is_ok());
+
+	let client = crate::connect_to_db(Network::Bitcoin).await;
+	let update_count: i64 = client.query_one("SELECT COUNT(*) FROM channel_updates", &[]).await.unwrap().get(0);
+	assert_eq!(update_count, 2);
+
+	tokio::task::spawn_blocking(move || {
+		drop(persister);
+	}).await.unwrap();
+	clean_test_db().await;
+}
+
+#[tokio::test]
+async fn test_graph_reconciliation() {
+	let _sanitizer = SchemaSanitizer::new();
+	let logger = Arc::new(TestLogger::new());
+	let network_graph_arc = Arc::new(NetworkGraph::new(Network::Bitcoin, logger.clone()));
+	let (mut persister, sender) = GossipPersister::new(network_graph_arc.clone(), logger.clone()).await;
+
+	let short_channel_id = 1;
+	let timestamp = current_time() - 10;
+	let secp_context = Secp256k1::new();
+	let signed_update = |direction: bool, private_key: [u8; 32]| {
+		let mut update = generate_update(short_channel_id, direction, timestamp, 0, 0, 0, 5, 0);
+		let msg_hash = bitcoin::secp256k1::Message::from_slice(&Sha256dHash::hash(&update.contents.encode()[..])[..]).unwrap();
+		update.signature = secp_context.sign_ecdsa(&msg_hash, &SecretKey::from_slice(&private_key).unwrap());
+		update
+	};
+	sender.send(GossipMessage::ChannelAnnouncement(generate_channel_announcement(short_channel_id), 100, None)).await.unwrap();
+	sender.send(GossipMessage::ChannelUpdate(signed_update(false, [1; 32]), None)).await.unwrap();
+	sender.send(GossipMessage::ChannelUpdate(signed_update(true, [2; 32]), None)).await.unwrap();
+	sender.send(GossipMessage::NodeAnnouncement(generate_node_announcement(None), None)).await.unwrap();
+	drop(sender);
+	persister.persist_gossip().await;
+
+	// an outdated cache lacking the persisted gossip is brought up to date
+	let restored_graph_arc = Arc::new(NetworkGraph::new(Network::Bitcoin, logger.clone()));
+	let (restored_persister, _sender) = GossipPersister::new(restored_graph_arc.clone(), logger.clone()).await;
+	assert_eq!(restored_persister.apply_persisted_gossip(0).await, (1, 2, 1));
+	assert_eq!(restored_persister.apply_persisted_gossip(0).await, (0, 0, 0));
+	{
+		let graph = restored_graph_arc.read_only();
+		let channel = graph.channel(short_channel_id).unwrap();
+		assert_eq!(channel.capacity_sats, Some(100));
+		assert!(channel.one_to_two.as_ref().unwrap().last_update_message.is_some());
+		assert!(channel.two_to_one.as_ref().unwrap().last_update_message.is_some());
+		assert_eq!(graph.nodes().len(), 2);
+	}
+
+	tokio::task::spawn_blocking(move || {
+		drop(persister);
+		drop(restored_persister);
+	}).await.unwrap();
+	clean_test_db().await;
+}
+
+#[tokio::test]
+async fn test_snapshot_bootstrap() {
+	let schema_sanitizer = SchemaSanitizer::new();
+	let cache_sanitizer = CacheSanitizer::new(&schema_sanitizer);
+	fs::create_dir_all(cache_sanitizer.cache_path()).unwrap();
+	let logger = Arc::new(TestLogger::new());
+	let short_channel_id = 1;
+	let timestamp = current_time() - 10;
+	let announcement = generate_channel_announcement(short_channel_id);
+
+	// another server's snapshot
+	let snapshot_path = format!("{}snapshot.bin", cache_sanitizer.cache_path());
+	{
+		let network_graph_arc = Arc::new(NetworkGraph::new(Network::Bitcoin, logger.clone()));
+		let (mut persister, sender) = GossipPersister::new(network_graph_arc.clone(), logger.clone()).await;
+		let update_1 = generate_update(short_channel_id, false, timestamp, 0, 0, 0, 5, 0);
+		let update_2 = generate_update(short_channel_id, true, timestamp, 0, 0, 0, 10, 0);
+		network_graph_arc.update_channel_from_announcement_no_lookup(&announcement).unwrap();
+		network_graph_arc.update_channel_unsigned(&update_1.contents).unwrap();
+		network_graph_arc.update_channel_unsigned(&update_2.contents).unwrap();
+		sender.send(GossipMessage::ChannelAnnouncement(announcement.clone(), 100, None)).await.unwrap();
+		sender.send(GossipMessage::ChannelUpdate(update_1, None)).await.unwrap();
+		sender.send(GossipMessage::ChannelUpdate(update_2, None)).await.unwrap();
+		drop(sender);
+		persister.persist_gossip().await;
+
+		let delta = calculate_delta(network_graph_arc.clone(), 0, None, logger.clone()).await;
+		fs::write(&snapshot_path, serialize_delta(&delta, 2, logger.clone()).data).unwrap();
+		tokio::task::spawn_blocking(move || drop(persister)).await.unwrap();
+		clean_test_db().await;
+	}
+
+	let network_graph_arc = Arc::new(NetworkGraph::new(Network::Bitcoin, logger.clone()));
+	let (mut persister, sender) = GossipPersister::new(network_graph_arc.clone(), logger.clone()).await;
+	assert_eq!(crate::bootstrap::bootstrap_network_graph(&network_graph_arc, &sender, &snapshot_path, logger.clone()).await, 1);
+	assert_eq!(crate::bootstrap::bootstrap_network_graph(&network_graph_arc, &sender, "./nonexistent.bin", logger.clone()).await, 0);
+	drop(sender);
+	persister.persist_gossip().await;
+	assert!(network_graph_arc.read_only().channel(short_channel_id).is_some());
+
+	// the seeded gossip can be served, and the seeded announcement is replaced by the actual one
+	let delta = calculate_delta(network_graph_arc.clone(), 0, None, logger.clone()).await;
+	let serialization = serialize_delta(&delta, 2, logger.clone());
+	assert_eq!(serialization.channel_announcement_count, 1);
+	assert_eq!(serialization.update_count, 2);
+	let sqlite_store = SqliteStore::open(&format!("{}gossip.sqlite", cache_sanitizer.cache_path()));
+	sqlite_store.initialize();
+	let postgres_store = storage::initialize(Network::Bitcoin, logger.clone()).await;
+	for store in [&sqlite_store as &dyn GossipStore, postgres_store.as_ref()] {
+		let mut placeholder = announcement.clone();
+		placeholder.node_signature_1 = blank_signature();
+		store.insert_batch(vec![GossipMessage::ChannelAnnouncement(placeholder, 0, None)]).await;
+		assert_eq!(store.recent_channel_announcements(0).await.collect::<Vec<_>>().await.len(), 0);
+		store.insert_batch(vec![
+			GossipMessage::ChannelAnnouncement(announcement.clone(), 100, None),
+			GossipMessage::ChannelAnnouncement(announcement.clone(), 100, None),
+		]).await;
+		let announcements = store.recent_channel_announcements(0).await.collect::<Vec<_>>().await;
+		assert_eq!(announcements.len(), 1);
+		assert_eq!(announcements[0].funding_amount_sats, 100);
+	}
+
+	tokio::task::spawn_blocking(move || drop(persister)).await.unwrap();
+	clean_test_db().await;
+}
+
+#[tokio::test]
+async fn test_db_verification() {
+	let schema_sanitizer = SchemaSanitizer::new();
+	let cache_sanitizer = CacheSanitizer::new(&schema_sanitizer);
+	fs::create_dir_all(cache_sanitizer.cache_path()).unwrap();
+	let logger = Arc::new(TestLogger::new());
+	let short_channel_id = 1;
+	let sqlite_store = SqliteStore::open(&format!("{}gossip.sqlite", cache_sanitizer.cache_path()));
+	assert_eq!(sqlite_store.schema_version().await, None);
+	sqlite_store.initialize();
+	let postgres_store = storage::initialize(Network::Bitcoin, logger.clone()).await;
+	assert_eq!(postgres_store.schema_version().await, Some(storage::latest_schema_version(Network::Bitcoin)));
+	for store in [&sqlite_store as &dyn GossipStore, postgres_store.as_ref()] {
+		assert!(store.schema_version().await.is_some());
+		let update = generate_update(short_channel_id, false, current_time() - 10, 0, 0, 0, 5, 0);
+		store.insert_batch(vec![GossipMessage::ChannelUpdate(update, None)]).await;
+		assert_eq!(store.orphaned_update_count().await, 1);
+		store.insert_batch(vec![GossipMessage::ChannelAnnouncement(generate_channel_announcement(short_channel_id), 100, None)]).await;
+		assert_eq!(store.orphaned_update_count().await, 0);
+	}
+
+	clean_test_db().await;
+}
+
 
 #[test]
 fn test_no_op() {
 	let logger = Arc::new(TestLogger::with_id("test_no_op".to_string()));
 	for serialization_version in 1..3 {
-		let serialization = serialize_empty_blob(current_time() as u64, serialization_version);
+		let serialization = serialize_empty_blob(genesis_hash(), current_time() as u64, serialization_version);
 
 		let client_graph = NetworkGraph::new(Network::Bitcoin, logger.clone());
 		let client_graph_arc = Arc::new(client_graph);
@@ -308,6 +498,59 @@ async fn test_trivial_setup() {
 	}).await.unwrap();
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn test_verified_gossip_pipeline() {
+	let _sanitizer = SchemaSanitizer::new();
+	let logger = Arc::new(TestLogger::new());
+	let network_graph_arc = Arc::new(NetworkGraph::new(Network::Bitcoin, logger.clone()));
+	let (mut persister, sender) = GossipPersister::new(network_graph_arc.clone(), logger.clone()).await;
+
+	let private_key_1 = SecretKey::from_slice(&[1; 32]).unwrap();
+	let private_key_2 = SecretKey::from_slice(&[2; 32]).unwrap();
+	let secp_context = Secp256k1::new();
+	let funded_channel = 870000 << 40 | 1 << 16;
+	let unfunded_channel = funded_channel + 1;
+	let timestamp = current_time() - 10;
+
+	let chain_source = Arc::new(MockChainSource::new());
+	chain_source.add_channel_funding(funded_channel, &private_key_1.public_key(&secp_context), &private_key_2.public_key(&secp_context), Amount::from_sat(250_000));
+	let router = GossipRouter::new(network_graph_arc.clone(), sender, chain_source, Arc::new(Mutex::new(HashMap::new())),
+		Arc::new(PeerHealthTracker::new()), Arc::new(Metrics::new()), Arc::new(GossipFeed::new(1)), logger.clone());
+	{
+		for short_channel_id in [funded_channel, unfunded_channel] {
+			// the announcements are accepted once their funding outputs have been looked up
+			let _ = router.handle_channel_announcement(None, &generate_channel_announcement(short_channel_id));
+		}
+		router.wait_for_utxo_lookups().await;
+		router.get_and_clear_pending_msg_events();
+
+		let update_1 = sign_update(generate_update(funded_channel, false, timestamp, 0, 0, 0, 5, 0), &private_key_1);
+		let update_2 = sign_update(generate_update(funded_channel, true, timestamp, 0, 0, 0, 10, 0), &private_key_2);
+		router.handle_channel_update(None, &update_1).unwrap();
+		router.handle_channel_update(None, &update_2).unwrap();
+		let unfunded_update = sign_update(generate_update(unfunded_channel, false, timestamp, 0, 0, 0, 5, 0), &private_key_1);
+		assert!(router.handle_channel_update(None, &unfunded_update).is_err());
+		drop(router);
+		persister.persist_gossip().await;
+	}
+
+	assert_eq!(network_graph_arc.read_only().channels().len(), 1);
+	assert_eq!(network_graph_arc.read_only().channel(funded_channel).unwrap().capacity_sats, Some(250_000));
+	assert_eq!(lookup::snapshot_channel_ids(&network_graph_arc, &Blocklist::default(), Some(250_000)), vec![funded_channel as i64]);
+	assert!(lookup::snapshot_channel_ids(&network_graph_arc, &Blocklist::default(), Some(250_001)).is_empty());
+
+	let delta = calculate_delta(network_graph_arc.clone(), 0, None, logger.clone()).await;
+	let serialization = serialize_delta(&delta, 1, logger.clone());
+	clean_test_db().await;
+
+	assert_eq!(serialization.channel_announcement_count, 1);
+	assert_eq!(serialization.update_count, 2);
+
+	tokio::task::spawn_blocking(move || {
+		drop(persister);
+	}).await.unwrap();
+}
+
 #[tokio::test]
 async fn test_node_announcement_persistence() {
 	let _sanitizer = SchemaSanitizer::new();
@@ -436,6 +679,11 @@ async fn test_node_announcement_delta_detection() {
 	assert_eq!(serialization.node_update_count, 1);
 	assert_eq!(serialization.node_feature_update_count, 1);
 	assert_eq!(serialization.node_address_update_count, 1);
+
+	// the snapshot is inspected the way clients decode it
+	let report = ParsedSnapshot::parse(&serialization.data).unwrap().to_string();
+	assert!(report.contains("nodes: 2 (1 with addresses, 0 with default features, 1 with custom features"), "{}", report);
+	assert!(report.contains("channel announcements: 1 "), "{}", report);
 }
 
 /// If a channel has only seen updates in one direction, it should not be announced
@@ -643,6 +891,8 @@ async fn test_channel_reminders() {
 	assert_eq!(serialization.update_count, 4);
 	assert_eq!(serialization.update_count_full, 0);
 	assert_eq!(serialization.update_count_incremental, 4);
+	let report = ParsedSnapshot::parse(&serialization.data).unwrap().to_string();
+	assert!(report.contains("channel updates: 4 for 2 channels (0 full, 4 incremental, 4 of which without changed fields; 2 in direction 0, 2 in direction 1"), "{}", report);
 
 	tokio::task::spawn_blocking(move || {
 		drop(persister);
@@ -1147,7 +1397,8 @@ async fn test_full_snapshot_persistence() {
 	let logger = Arc::new(TestLogger::new());
 	let network_graph = NetworkGraph::new(Network::Bitcoin, logger.clone());
 	let network_graph_arc = Arc::new(network_graph);
-	let snapshotter = Snapshotter::new(network_graph_arc.clone(), logger.clone());
+	let metrics = Arc::new(Metrics::new());
+	let snapshotter = Snapshotter::new(network_graph_arc.clone(), None, metrics.clone(), logger.clone());
 	let cache_sanitizer = CacheSanitizer::new(&schema_sanitizer);
 
 	let short_channel_id = 1;
@@ -1186,7 +1437,12 @@ async fn test_full_snapshot_persistence() {
 
 	// generate snapshots
 	{
-		snapshotter.generate_snapshots(20, 5, &[5, u64::MAX], &cache_path, Some(10)).await;
+		// the directories published by previous versions are replaced
+		fs::create_dir_all(format!("{}/symlinks", cache_path)).unwrap();
+		fs::write(format!("{}/symlinks/0.bin", cache_path), "stale").unwrap();
+		let manifest = snapshotter.generate_snapshots(20, 5, &[5, u64::MAX], &cache_path, Some(10), None).await.unwrap();
+		assert!(fs::symlink_metadata(format!("{}/symlinks", cache_path)).unwrap().file_type().is_symlink());
+		snapshotter.record_snapshot_sizes(&manifest, None);
 
 		let symlinked_data = fs::read(&symlink_path).unwrap();
 		let client_graph = NetworkGraph::new(Network::Bitcoin, logger.clone());
@@ -1209,6 +1465,22 @@ async fn test_full_snapshot_persistence() {
 		assert_eq!(first_channel.two_to_one.as_ref().unwrap().fees.proportional_millionths, 10);
 	}
 
+	// the manifest must describe every snapshot that was written
+	{
+		let manifest: serde_json::Value = serde_json::from_slice(&fs::read(format!("{}/symlinks/manifest.json", cache_path)).unwrap()).unwrap();
+		assert_eq!(manifest["network"], "bitcoin");
+		let snapshots = manifest["snapshots"].as_array().unwrap();
+		// two scopes and the empty dummy, each in two serialization versions
+		assert_eq!(snapshots.len(), 6);
+		for snapshot in snapshots {
+			let data = fs::read(format!("{}/{}", cache_path, snapshot["file"].as_str().unwrap())).unwrap();
+			assert_eq!(snapshot["size"], data.len());
+			assert_eq!(snapshot["sha256"], crate::manifest::sha256_hex(&data));
+		}
+		let full_snapshot = snapshots.iter().find(|snapshot| snapshot["scope"] == u64::MAX && snapshot["serialization_version"] == 1).unwrap();
+		assert_eq!(full_snapshot["channel_announcement_count"], 1);
+	}
+
 	{ // update the db
 		let (mut persister, receiver) = GossipPersister::new(network_graph_arc.clone(), logger.clone()).await;
 
@@ -1228,7 +1500,14 @@ async fn test_full_snapshot_persistence() {
 
 	// regenerate snapshots
 	{
-		snapshotter.generate_snapshots(20, 5, &[5, u64::MAX], &cache_path, Some(10)).await;
+		let mut manifest = snapshotter.generate_snapshots(20, 5, &[5, u64::MAX], &cache_path, Some(10), None).await.unwrap();
+		// only the full snapshot's size jumping is warned about
+		let full_snapshot = manifest.snapshots.iter_mut().find(|snapshot| snapshot.scope == Some(u64::MAX) && snapshot.serialization_version == 2).unwrap();
+		full_snapshot.size *= 3;
+		snapshotter.record_snapshot_sizes(&manifest, None);
+		logger.assert_log_contains("rapid_gossip_sync_server::snapshot", "The size of snapshot snapshots/", 1);
+		logger.assert_log_contains("rapid_gossip_sync_server::snapshot", "changed by +20", 1);
+		assert_eq!(metrics.snapshot_history().len(), 4);
 
 		let symlinked_data = fs::read(&symlink_path).unwrap();
 		let client_graph = NetworkGraph::new(Network::Bitcoin, logger.clone());
@@ -1249,8 +1528,331 @@ async fn test_full_snapshot_persistence() {
 		// ensure the update in one direction shows the latest fee
 		assert_eq!(first_channel.one_to_two.as_ref().unwrap().fees.proportional_millionths, 39);
 		assert_eq!(first_channel.two_to_one.as_ref().unwrap().fees.proportional_millionths, 10);
+
+		// the replaced round is kept for the lookups that may still resolve to it
+		let round_count = || fs::read_dir(format!("{}/rounds", cache_path)).unwrap().count();
+		snapshotter.generate_snapshots(20, 5, &[5, u64::MAX], &cache_path, Some(10), None).await.unwrap();
+		assert_eq!(round_count(), 2);
+	}
+
+	// a round disagreeing with the network graph is withheld, leaving the previous one served
+	{
+		let update = generate_update(short_channel_id, false, timestamp + 40, 0, 0, 0, 0, 40);
+		network_graph_arc.update_channel_unsigned(&update.contents).unwrap();
+		let served_data = fs::read(&symlink_path).unwrap();
+		assert!(snapshotter.generate_snapshots(20, 5, &[5, u64::MAX], &cache_path, Some(10), None).await.is_none());
+		assert_eq!(fs::read(&symlink_path).unwrap(), served_data);
+		logger.assert_log_contains("rapid_gossip_sync_server::snapshot", "failed verification, as the policies of 1 of its 1 channels differ", 1);
 	}
 
 	// clean up afterwards
 	clean_test_db().await;
 }
+
+#[tokio::test]
+async fn test_sqlite_store() {
+	// the channel reminder scenario, which exercises every lookup query, against SQLite
+	let schema_sanitizer = SchemaSanitizer::new();
+	let cache_sanitizer = CacheSanitizer::new(&schema_sanitizer);
+	let logger = Arc::new(TestLogger::new());
+	let network_graph = NetworkGraph::new(Network::Bitcoin, logger.clone());
+	let network_graph_arc = Arc::new(network_graph);
+	let store = SqliteStore::open(&format!("{}gossip.sqlite", cache_sanitizer.cache_path()));
+	store.initialize();
+
+	let timestamp = current_time();
+	let channel_reminder_delta = config::CHANNEL_REMINDER_AGE.as_secs() as u32;
+
+	{ // seed the db
+		{ // unupdated channel
+			let short_channel_id = 1;
+			let announcement = generate_channel_announcement(short_channel_id);
+			let update_1 = generate_update(short_channel_id, false, timestamp - channel_reminder_delta - 1, 0, 0, 0, 5, 0);
+			let update_2 = generate_update(short_channel_id, true, timestamp - channel_reminder_delta - 1, 0, 0, 0, 3, 0);
+
+			network_graph_arc.update_channel_from_announcement_no_lookup(&announcement).unwrap();
+			network_graph_arc.update_channel_unsigned(&update_1.contents).unwrap();
+			network_graph_arc.update_channel_unsigned(&update_2.contents).unwrap();
+
+			store.insert_batch(vec![GossipMessage::ChannelAnnouncement(announcement, 100, Some(timestamp - channel_reminder_delta - 1))]).await;
+			store.insert_batch(vec![
+				GossipMessage::ChannelUpdate(update_1, Some(timestamp - channel_reminder_delta - 1)),
+				GossipMessage::ChannelUpdate(update_2, Some(timestamp - channel_reminder_delta - 1)),
+			]).await;
+		}
+		{ // unmodified but updated channel
+			let short_channel_id = 2;
+			let announcement = generate_channel_announcement(short_channel_id);
+			let update_1 = generate_update(short_channel_id, false, timestamp - channel_reminder_delta - 10, 0, 0, 0, 5, 0);
+			// in the false direction, we have one update that's different prior
+			let update_2 = generate_update(short_channel_id, false, timestamp - channel_reminder_delta - 5, 0, 1, 0, 5, 0);
+			let update_3 = generate_update(short_channel_id, false, timestamp - channel_reminder_delta - 1, 0, 0, 0, 5, 0);
+			let update_4 = generate_update(short_channel_id, true, timestamp - channel_reminder_delta - 1, 0, 0, 0, 3, 0);
+			let update_5 = generate_update(short_channel_id, false, timestamp - channel_reminder_delta + 10, 0, 0, 0, 5, 0);
+			let update_6 = generate_update(short_channel_id, true, timestamp - channel_reminder_delta + 10, 0, 0, 0, 3, 0);
+			let update_7 = generate_update(short_channel_id, false, timestamp - channel_reminder_delta + 20, 0, 0, 0, 5, 0);
+			let update_8 = generate_update(short_channel_id, true, timestamp - channel_reminder_delta + 20, 0, 0, 0, 3, 0);
+
+			network_graph_arc.update_channel_from_announcement_no_lookup(&announcement).unwrap();
+			network_graph_arc.update_channel_unsigned(&update_7.contents).unwrap();
+			network_graph_arc.update_channel_unsigned(&update_8.contents).unwrap();
+
+			store.insert_batch(vec![GossipMessage::ChannelAnnouncement(announcement, 100, Some(timestamp - channel_reminder_delta - 1))]).await;
+			// channel updates are considered seen at their own timestamp
+			let updates = [update_1, update_2, update_3, update_4, update_5, update_6, update_7, update_8];
+			store.insert_batch(updates.into_iter().map(|update| GossipMessage::ChannelUpdate(update, None)).collect()).await;
+		}
+		{ // a node announced before and after the last sync
+			let announcement = generate_node_announcement(None);
+			store.insert_batch(vec![GossipMessage::NodeAnnouncement(announcement, Some(timestamp - channel_reminder_delta - 1))]).await;
+			let mut announcement = generate_node_announcement(None);
+			announcement.contents.timestamp = 1;
+			announcement.contents.addresses.push(SocketAddress::TcpIpV4 { addr: [127, 0, 0, 1], port: 9735 });
+			network_graph_arc.update_node_from_unsigned_announcement(&announcement.contents).unwrap();
+			store.insert_batch(vec![GossipMessage::NodeAnnouncement(announcement, Some(timestamp - channel_reminder_delta + 20))]).await;
+		}
+	}
+
+	let delta = calculate_store_delta(network_graph_arc.clone(), &store, None, None, timestamp - channel_reminder_delta + 15, None, logger.clone()).await;
+	let serialization = serialize_delta(&delta, 2, logger.clone());
+
+	logger.assert_log_contains("rapid_gossip_sync_server::lookup", "Fetched 2 announcement rows", 1);
+	logger.assert_log_contains("rapid_gossip_sync_server::lookup", "Fetched 0 update rows of the first update in a new direction", 1);
+	logger.assert_log_contains("rapid_gossip_sync_server::lookup", "Fetched 4 update rows of the latest update in the less recently updated direction", 1);
+	logger.assert_log_contains("rapid_gossip_sync_server::lookup", "Processed 2 reference rows", 1);
+	logger.assert_log_contains("rapid_gossip_sync_server::lookup", "Processed intermediate rows (2)", 1);
+	logger.assert_log_contains("rapid_gossip_sync_server::lookup", "Processed 1 node announcement reference rows", 1);
+
+	// only the updated channel changed since the last sync, but the other one still gets its reminder
+	let changed_channels = HashSet::from([2]);
+	let incremental_delta = calculate_store_delta(network_graph_arc.clone(), &store, None, Some(&changed_channels), timestamp - channel_reminder_delta + 15, None, logger.clone()).await;
+	let incremental_serialization = serialize_delta(&incremental_delta, 2, logger.clone());
+	clean_test_db().await;
+
+	logger.assert_log_contains("rapid_gossip_sync_server::lookup", "Looking up 1 of 2 channels changed since the last sync", 1);
+	logger.assert_log_contains("rapid_gossip_sync_server::lookup", "Fetched 1 announcement rows of unchanged channels requiring reminders", 1);
+	assert_eq!(incremental_serialization.data, serialization.data);
+
+	assert_eq!(serialization.channel_announcement_count, 0);
+	assert_eq!(serialization.update_count, 4);
+	assert_eq!(serialization.update_count_full, 0);
+	assert_eq!(serialization.update_count_incremental, 4);
+	assert_eq!(serialization.node_address_update_count, 1);
+}
+
+#[tokio::test]
+async fn test_update_pruning() {
+	let schema_sanitizer = SchemaSanitizer::new();
+	let cache_sanitizer = CacheSanitizer::new(&schema_sanitizer);
+	let logger = Arc::new(TestLogger::new());
+	let sqlite_store = SqliteStore::open(&format!("{}gossip.sqlite", cache_sanitizer.cache_path()));
+	sqlite_store.initialize();
+	let postgres_store = storage::initialize(Network::Bitcoin, logger.clone()).await;
+	let timestamp = current_time();
+
+	for store in [&sqlite_store as &dyn GossipStore, postgres_store.as_ref()] {
+		let updates = vec![
+			generate_update(1, false, timestamp - 100, 0, 0, 0, 5, 0),
+			generate_update(1, false, timestamp - 90, 0, 1, 0, 5, 0),
+			generate_update(1, false, timestamp - 80, 0, 2, 0, 5, 0),
+			generate_update(1, false, timestamp - 70, 0, 3, 0, 5, 0),
+			generate_update(1, false, timestamp - 10, 0, 4, 0, 5, 0),
+			// the only update in its direction is both the first and the latest one
+			generate_update(1, true, timestamp - 100, 0, 0, 0, 3, 0),
+		];
+		store.insert_batch(updates.into_iter().map(|update| GossipMessage::ChannelUpdate(update, None)).collect()).await;
+
+		// the first update and the latest two are retained
+		assert_eq!(store.prune_channel_updates(timestamp - 50, 2).await, 2);
+		assert_eq!(store.prune_channel_updates(timestamp - 50, 2).await, 0);
+		let mut remaining = store.intermediate_updates(0).await.map(|update| update.seen).collect::<Vec<_>>().await;
+		remaining.sort_unstable();
+		assert_eq!(remaining, vec![timestamp - 100, timestamp - 100, timestamp - 70, timestamp - 10]);
+	}
+
+	clean_test_db().await;
+}
+
+#[tokio::test]
+async fn test_snapshot_request_recording() {
+	let schema_sanitizer = SchemaSanitizer::new();
+	let cache_sanitizer = CacheSanitizer::new(&schema_sanitizer);
+	let logger = Arc::new(TestLogger::new());
+	let sqlite_path = format!("{}gossip.sqlite", cache_sanitizer.cache_path());
+	let sqlite_store = SqliteStore::open(&sqlite_path);
+	sqlite_store.initialize();
+	let postgres_store = storage::initialize(Network::Bitcoin, logger.clone()).await;
+	let request = |profile: Option<&str>| SnapshotRequest {
+		requested_at: current_time() as u64,
+		last_sync_timestamp: 1_700_000_000 - 1_700_000_000 % config::SYMLINK_GRANULARITY_INTERVAL as u64,
+		lag_bucket: "older",
+		profile: profile.map(str::to_string),
+		served_snapshot: "empty_delta.lngossip".to_string(),
+		bytes: 10,
+		user_agent: "ldk-node/0.4.0".to_string(),
+	};
+
+	for store in [&sqlite_store as &dyn GossipStore, postgres_store.as_ref()] {
+		store.insert_snapshot_requests(vec![request(None), request(Some("small"))]).await;
+	}
+	let connection = rusqlite::Connection::open(&sqlite_path).unwrap();
+	let sqlite_count: i64 = connection.query_row("SELECT COUNT(*) FROM snapshot_requests WHERE profile = 'small'", [], |row| row.get(0)).unwrap();
+	assert_eq!(sqlite_count, 1);
+	let client = crate::connect_to_db(Network::Bitcoin).await;
+	let postgres_count: i64 = client.query_one("SELECT COUNT(*) FROM snapshot_requests WHERE user_agent = 'ldk-node/0.4.0'", &[]).await.unwrap().get(0);
+	assert_eq!(postgres_count, 2);
+
+	clean_test_db().await;
+}
+
+#[tokio::test]
+async fn test_stats_sample_recording() {
+	let schema_sanitizer = SchemaSanitizer::new();
+	let cache_sanitizer = CacheSanitizer::new(&schema_sanitizer);
+	let logger = Arc::new(TestLogger::new());
+	let sqlite_store = SqliteStore::open(&format!("{}gossip.sqlite", cache_sanitizer.cache_path()));
+	sqlite_store.initialize();
+	let postgres_store = storage::initialize(Network::Bitcoin, logger.clone()).await;
+	let sample = |sampled_at: u64, full_snapshot_bytes: Option<u64>| StatsSample {
+		sampled_at,
+		interval_secs: 300,
+		channel_announcements: 2,
+		channel_updates: 600,
+		node_announcements: 10,
+		channel_count: 50_000,
+		node_count: 12_000,
+		full_snapshot_bytes,
+		snapshot_bytes: Some(4_000_000),
+	};
+
+	for store in [&sqlite_store as &dyn GossipStore, postgres_store.as_ref()] {
+		for (sampled_at, full_snapshot_bytes) in [(1_700_000_000, None), (1_700_000_300, Some(3_000_000)), (1_700_000_600, Some(3_000_100))] {
+			store.insert_stats_sample(sample(sampled_at, full_snapshot_bytes)).await;
+		}
+		assert_eq!(store.stats_samples(1_700_000_300, 10).await, vec![sample(1_700_000_300, Some(3_000_000)), sample(1_700_000_600, Some(3_000_100))]);
+		assert_eq!(store.stats_samples(0, 1).await, vec![sample(1_700_000_000, None)]);
+
+		assert_eq!(store.prune_stats_samples(1_700_000_600).await, 2);
+		assert_eq!(store.stats_samples(0, 10).await, vec![sample(1_700_000_600, Some(3_000_100))]);
+
+		let history: serde_json::Value = serde_json::from_str(&history::history_json(store, 0).await).unwrap();
+		assert_eq!(history[0]["sampled_at"], 1_700_000_600);
+		assert_eq!(history[0]["channel_updates_per_minute"], 120.0);
+	}
+
+	clean_test_db().await;
+}
+
+#[tokio::test]
+async fn test_schema_migrations() {
+	let _sanitizer = SchemaSanitizer::new();
+	let logger = Arc::new(TestLogger::new());
+	storage::initialize(Network::Bitcoin, logger.clone()).await;
+	logger.assert_log_contains("rapid_gossip_sync_server::storage::postgres", "Migrating database to schema 16 (schema_migrations)", 1);
+	logger.assert_log_contains("rapid_gossip_sync_server::storage::postgres", "Migrating database to schema 17 (snapshot_requests)", 1);
+	logger.assert_log_contains("rapid_gossip_sync_server::storage::postgres", "Migrating database to schema 18 (stats_samples)", 1);
+
+	let client = crate::connect_to_db(Network::Bitcoin).await;
+	let schema: i32 = client.query_one("SELECT db_schema FROM config WHERE id = 1", &[]).await.unwrap().get(0);
+	assert_eq!(schema, config::SCHEMA_VERSION);
+	let applied_migrations = client.query("SELECT version FROM schema_migrations", &[]).await.unwrap();
+	assert_eq!(applied_migrations.len(), 3);
+
+	// migrations already applied are skipped
+	storage::initialize(Network::Bitcoin, logger.clone()).await;
+	logger.assert_log_contains("rapid_gossip_sync_server::storage::postgres", "Migrating database", 3);
+
+	// a schema written by a newer server is refused
+	client.execute("UPDATE config SET db_schema = $1 WHERE id = 1", &[&(config::SCHEMA_VERSION + 1)]).await.unwrap();
+	let initialization = tokio::spawn(async move {
+		storage::initialize(Network::Bitcoin, logger).await;
+	}).await;
+	assert!(initialization.unwrap_err().is_panic());
+
+	clean_test_db().await;
+}
+
+#[tokio::test]
+async fn test_concurrent_schema_migrations() {
+	let _sanitizer = SchemaSanitizer::new();
+	// roll the schema back to its baseline, which two instances then migrate at once
+	storage::initialize(Network::Bitcoin, Arc::new(TestLogger::new())).await;
+	let client = crate::connect_to_db(Network::Bitcoin).await;
+	client.execute("UPDATE config SET db_schema = $1 WHERE id = 1", &[&15]).await.unwrap();
+	client.batch_execute("DROP TABLE schema_migrations, snapshot_requests, stats_samples").await.unwrap();
+
+	let logger = Arc::new(TestLogger::new());
+	tokio::join!(
+		storage::initialize(Network::Bitcoin, logger.clone()),
+		storage::initialize(Network::Bitcoin, logger.clone()),
+	);
+	logger.assert_log_contains("rapid_gossip_sync_server::storage::postgres", "Migrating database", 3);
+	let schema: i32 = client.query_one("SELECT db_schema FROM config WHERE id = 1", &[]).await.unwrap().get(0);
+	assert_eq!(schema, config::SCHEMA_VERSION);
+	let applied_migrations = client.query("SELECT version FROM schema_migrations", &[]).await.unwrap();
+	assert_eq!(applied_migrations.len(), 3);
+
+	clean_test_db().await;
+}
+
+#[tokio::test]
+async fn test_leader_election() {
+	let _sanitizer = SchemaSanitizer::new();
+	let logger = Arc::new(TestLogger::new());
+	let wait_for_leader = |leadership: Arc<Leadership>| async move {
+		for _ in 0..100 {
+			if leadership.is_leader() {
+				return true;
+			}
+			tokio::time::sleep(config::LEADER_ELECTION_INTERVAL).await;
+		}
+		false
+	};
+	let campaign = |leadership: &Arc<Leadership>, metrics: &Arc<Metrics>| {
+		let (leadership, metrics, logger) = (Arc::clone(leadership), Arc::clone(metrics), logger.clone());
+		tokio::spawn(async move { leadership.campaign(Network::Bitcoin, &metrics, logger).await })
+	};
+
+	let (first, first_metrics) = (Arc::new(Leadership::new(true, None)), Arc::new(Metrics::new()));
+	let (second, second_metrics) = (Arc::new(Leadership::new(true, None)), Arc::new(Metrics::new()));
+	let first_campaign = campaign(&first, &first_metrics);
+	assert!(wait_for_leader(Arc::clone(&first)).await);
+	assert!(first_metrics.render().contains("\nrgs_leader 1\n"));
+
+	let second_campaign = campaign(&second, &second_metrics);
+	tokio::time::sleep(config::LEADER_ELECTION_INTERVAL * 5).await;
+	assert!(!second.is_leader());
+	assert!(second_metrics.render().contains("\nrgs_leader 0\n"));
+
+	// the lock is released along with the leader's connection, e. g. once it crashes
+	first_campaign.abort();
+	assert!(wait_for_leader(Arc::clone(&second)).await);
+	logger.assert_log_contains("rapid_gossip_sync_server::leader", "Took the lead", 2);
+	second_campaign.abort();
+
+	clean_test_db().await;
+}
+
+#[tokio::test]
+async fn test_read_replica() {
+	let _sanitizer = SchemaSanitizer::new();
+	let logger = Arc::new(TestLogger::new());
+	let store = storage::initialize(Network::Bitcoin, logger.clone()).await;
+	let timestamp = current_time();
+	store.insert_batch(vec![
+		GossipMessage::ChannelUpdate(generate_update(1, false, timestamp - 10, 0, 0, 0, 5, 0), None),
+		GossipMessage::ChannelUpdate(generate_update(1, true, timestamp - 10, 0, 0, 0, 3, 0), None),
+	]).await;
+
+	// the primary serves as its own replica, which can't be lagging behind
+	let replica = PostgresStore::read_replica(Network::Bitcoin, config::db_connection_config());
+	assert_eq!(replica.replication_lag().await, Some(Duration::ZERO));
+	assert_eq!(replica.intermediate_updates(0).await.count().await, 2);
+
+	let mut unreachable_config = tokio_postgres::Config::new();
+	unreachable_config.host("127.0.0.1").port(1).user("postgres");
+	let unreachable_replica = PostgresStore::read_replica(Network::Bitcoin, unreachable_config);
+	assert_eq!(unreachable_replica.replication_lag().await, None);
+
+	clean_test_db().await;
+}
@@ -0,0 +1,353 @@
+//! End-to-end tests running the whole server against a regtest bitcoind, which are only built with
+//! the `regtest-tests` feature. The bitcoind binary is taken from `BITCOIND_EXE`, defaulting to
+//! the one on the `PATH`.
+//!
+//! The harness starts bitcoind along with two LDK nodes, each of which serves the gossip of the
+//! channels between them from its own network graph, like any peer of the server does. The
+//! channels are opened by funding their 2-of-2 outputs from bitcoind's wallet and signing their
+//! announcements with both nodes' keys, which yields the same gossip as opening them through LDK's
+//! channel manager, without the commitment state that's of no concern to the server. The server
+//! then syncs the gossip from both nodes, verifies it against bitcoind's REST interface, persists
+//! it, and captures snapshots, which are applied to a fresh client graph.
+//!
+//! As the server's configuration is process-wide, these tests must be run on their own:
+//!
+//! ```sh
+//! BITCOIND_EXE=/path/to/bitcoind cargo test --features regtest-tests tests::regtest -- --test-threads=1
+//! ```
+
+use std::fs;
+use std::net::{SocketAddr, TcpListener as StdTcpListener};
+use std::process::{Child, Command, Stdio};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use bitcoin::{Address, Amount, Network, ScriptBuf, Transaction};
+use bitcoin::blockdata::constants::ChainHash;
+use bitcoin::consensus::encode::deserialize;
+use bitcoin::hashes::Hash;
+use bitcoin::hashes::sha256d::Hash as Sha256dHash;
+use bitcoin::secp256k1::{Message, PublicKey, Secp256k1, SecretKey};
+use hex_conservative::FromHex;
+use lightning::ln::chan_utils::make_funding_redeemscript;
+use lightning::ln::msgs::{ChannelAnnouncement, ChannelUpdate, NodeAnnouncement, UnsignedChannelAnnouncement, UnsignedChannelUpdate, UnsignedNodeAnnouncement};
+use lightning::ln::peer_handler::{ErroringMessageHandler, IgnoringMessageHandler, MessageHandler, PeerManager};
+use lightning::routing::gossip::{NetworkGraph, NodeAlias, NodeId, P2PGossipSync};
+use lightning::routing::utxo::UtxoLookup;
+use lightning::sign::{KeysManager, NodeSigner, Recipient};
+use lightning::types::features::{ChannelFeatures, NodeFeatures};
+use lightning::util::ser::Writeable;
+use lightning_net_tokio::SocketDescriptor;
+use lightning_rapid_gossip_sync::RapidGossipSync;
+use serde_json::{json, Value};
+
+use crate::RapidGossipSyncServer;
+use crate::types::tests::TestLogger;
+
+/// How long bitcoind, the initial sync, and the snapshots may each take
+const STEP_TIMEOUT: Duration = Duration::from_secs(120);
+const RPC_USER: &str = "rgs";
+const RPC_PASSWORD: &str = "rgs";
+
+type TestGossipSync = P2PGossipSync<Arc<NetworkGraph<Arc<TestLogger>>>, Arc<dyn UtxoLookup + Send + Sync>, Arc<TestLogger>>;
+type TestPeerManager = PeerManager<SocketDescriptor, ErroringMessageHandler, Arc<TestGossipSync>, IgnoringMessageHandler, Arc<TestLogger>, IgnoringMessageHandler, Arc<KeysManager>>;
+
+fn current_time() -> u64 {
+	SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+fn unused_port() -> u16 {
+	StdTcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+fn chain_hash() -> ChainHash {
+	ChainHash::using_genesis_block(Network::Regtest)
+}
+
+fn gossip_hash<M: Writeable>(contents: &M) -> Message {
+	Message::from_digest(Sha256dHash::hash(&contents.encode()).to_byte_array())
+}
+
+/// Poll `condition` until it holds, failing the test after [`STEP_TIMEOUT`]
+async fn wait_for<F: Fn() -> bool>(description: &str, condition: F) {
+	let start = Instant::now();
+	while !condition() {
+		assert!(start.elapsed() < STEP_TIMEOUT, "Timed out waiting for {}", description);
+		tokio::time::sleep(Duration::from_millis(500)).await;
+	}
+}
+
+/// A regtest bitcoind serving RPC and REST on the same port, which is killed along with its data
+/// directory once dropped
+struct Bitcoind {
+	process: Child,
+	data_directory: String,
+	rpc_port: u16,
+	client: reqwest::Client,
+}
+
+impl Bitcoind {
+	async fn start(data_directory: String) -> Self {
+		fs::create_dir_all(&data_directory).unwrap();
+		let rpc_port = unused_port();
+		let executable = std::env::var("BITCOIND_EXE").unwrap_or("bitcoind".to_string());
+		let process = Command::new(&executable)
+			.args(["-regtest", "-server", "-rest", "-listen=0", "-fallbackfee=0.0001", "-printtoconsole=0"])
+			.arg(format!("-datadir={}", data_directory))
+			.arg(format!("-rpcport={}", rpc_port))
+			.arg(format!("-rpcuser={}", RPC_USER))
+			.arg(format!("-rpcpassword={}", RPC_PASSWORD))
+			.stdout(Stdio::null())
+			.spawn()
+			.unwrap_or_else(|e| panic!("Failed to start {}, which BITCOIND_EXE may point to instead: {}", executable, e));
+		let bitcoind = Self { process, data_directory, rpc_port, client: reqwest::Client::new() };
+
+		let start = Instant::now();
+		while bitcoind.try_rpc("getblockchaininfo", json!([])).await.is_err() {
+			assert!(start.elapsed() < STEP_TIMEOUT, "Timed out waiting for bitcoind to start");
+			tokio::time::sleep(Duration::from_millis(200)).await;
+		}
+		bitcoind.rpc("createwallet", json!(["rgs"])).await;
+		// coinbase outputs need 100 confirmations to be spendable
+		bitcoind.mine(101).await;
+		bitcoind
+	}
+
+	async fn try_rpc(&self, method: &str, params: Value) -> Result<Value, String> {
+		let request = json!({ "jsonrpc": "1.0", "id": method, "method": method, "params": params });
+		let response = self.client.post(format!("http://127.0.0.1:{}/", self.rpc_port))
+			.basic_auth(RPC_USER, Some(RPC_PASSWORD))
+			.body(request.to_string())
+			.send().await
+			.map_err(|e| e.to_string())?;
+		let response: Value = serde_json::from_slice(&response.bytes().await.map_err(|e| e.to_string())?).map_err(|e| e.to_string())?;
+		match &response["error"] {
+			Value::Null => Ok(response["result"].clone()),
+			error => Err(error.to_string()),
+		}
+	}
+
+	async fn rpc(&self, method: &str, params: Value) -> Value {
+		self.try_rpc(method, params).await.unwrap_or_else(|e| panic!("bitcoind failed to {}: {}", method, e))
+	}
+
+	async fn mine(&self, block_count: u32) {
+		let address = self.rpc("getnewaddress", json!([])).await;
+		self.rpc("generatetoaddress", json!([block_count, address])).await;
+	}
+
+	/// Fund and confirm an output paying `amount` to `script_pubkey`, returning the short channel
+	/// id it is referred to by
+	async fn fund_output(&self, script_pubkey: &ScriptBuf, amount: Amount) -> u64 {
+		let address = Address::from_script(script_pubkey, Network::Regtest).unwrap();
+		let txid = self.rpc("sendtoaddress", json!([address.to_string(), amount.to_btc()])).await;
+		// channels are only announced once their funding is buried by six blocks
+		self.mine(6).await;
+		let transaction_details = self.rpc("gettransaction", json!([txid])).await;
+		let transaction: Transaction = deserialize(&Vec::<u8>::from_hex(transaction_details["hex"].as_str().unwrap()).unwrap()).unwrap();
+		let output_index = transaction.output.iter().position(|output| &output.script_pubkey == script_pubkey).unwrap() as u64;
+		let block_height = transaction_details["blockheight"].as_u64().unwrap();
+		let transaction_index = transaction_details["blockindex"].as_u64().unwrap();
+		block_height << 40 | transaction_index << 16 | output_index
+	}
+}
+
+impl Drop for Bitcoind {
+	fn drop(&mut self) {
+		let _ = self.process.kill();
+		let _ = self.process.wait();
+		let _ = fs::remove_dir_all(&self.data_directory);
+	}
+}
+
+/// An LDK node accepting peer connections, which serves the gossip known to its network graph
+struct TestNode {
+	node_secret: SecretKey,
+	node_id: PublicKey,
+	network_graph: Arc<NetworkGraph<Arc<TestLogger>>>,
+	address: SocketAddr,
+}
+
+impl TestNode {
+	async fn start(seed: u8, logger: Arc<TestLogger>) -> Self {
+		let keys_manager = Arc::new(KeysManager::new(&[seed; 32], current_time(), 0));
+		let node_secret = keys_manager.get_node_secret_key();
+		let node_id = keys_manager.get_node_id(Recipient::Node).unwrap();
+		let network_graph = Arc::new(NetworkGraph::new(Network::Regtest, Arc::clone(&logger)));
+		let gossip_sync: Arc<TestGossipSync> = Arc::new(P2PGossipSync::new(Arc::clone(&network_graph), None, Arc::clone(&logger)));
+		let message_handler = MessageHandler {
+			chan_handler: ErroringMessageHandler::new(),
+			route_handler: gossip_sync,
+			onion_message_handler: IgnoringMessageHandler {},
+			custom_message_handler: IgnoringMessageHandler {},
+		};
+		let peer_manager: Arc<TestPeerManager> = Arc::new(PeerManager::new(message_handler, current_time() as u32, &[seed; 32], logger, keys_manager));
+
+		let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let address = listener.local_addr().unwrap();
+		let connection_manager = Arc::clone(&peer_manager);
+		tokio::spawn(async move {
+			while let Ok((stream, _)) = listener.accept().await {
+				tokio::spawn(lightning_net_tokio::setup_inbound(Arc::clone(&connection_manager), stream.into_std().unwrap()));
+			}
+		});
+		tokio::spawn(async move {
+			let mut interval = tokio::time::interval(Duration::from_secs(1));
+			loop {
+				interval.tick().await;
+				peer_manager.process_events();
+				peer_manager.timer_tick_occurred();
+			}
+		});
+		Self { node_secret, node_id, network_graph, address }
+	}
+
+	fn peer_setting(&self) -> String {
+		format!("{}@{}", self.node_id, self.address)
+	}
+
+	fn update(&self, short_channel_id: u64, direction: bool, fee_proportional_millionths: u32, capacity: Amount) -> ChannelUpdate {
+		let contents = UnsignedChannelUpdate {
+			chain_hash: chain_hash(),
+			short_channel_id,
+			timestamp: current_time() as u32,
+			message_flags: 1,
+			channel_flags: if direction { 1 } else { 0 },
+			cltv_expiry_delta: 144,
+			htlc_minimum_msat: 1000,
+			htlc_maximum_msat: capacity.to_sat() * 1000,
+			fee_base_msat: 1000,
+			fee_proportional_millionths,
+			excess_data: vec![],
+		};
+		let signature = Secp256k1::signing_only().sign_ecdsa(&gossip_hash(&contents), &self.node_secret);
+		ChannelUpdate { signature, contents }
+	}
+
+	fn announcement(&self, alias: &str) -> NodeAnnouncement {
+		let mut alias_bytes = [0; 32];
+		alias_bytes[..alias.len()].copy_from_slice(alias.as_bytes());
+		let contents = UnsignedNodeAnnouncement {
+			features: NodeFeatures::empty(),
+			timestamp: current_time() as u32,
+			node_id: NodeId::from_pubkey(&self.node_id),
+			rgb: [0, 128, 255],
+			alias: NodeAlias(alias_bytes),
+			addresses: vec![],
+			excess_address_data: vec![],
+			excess_data: vec![],
+		};
+		let signature = Secp256k1::signing_only().sign_ecdsa(&gossip_hash(&contents), &self.node_secret);
+		NodeAnnouncement { signature, contents }
+	}
+
+	/// Learn of gossip as if it had been received from, or broadcast by, the channel's peers
+	fn apply_channel_gossip(&self, announcement: &ChannelAnnouncement, updates: &[ChannelUpdate]) {
+		self.network_graph.update_channel_from_announcement_no_lookup(announcement).unwrap();
+		for update in updates {
+			self.network_graph.update_channel(update).unwrap();
+		}
+	}
+}
+
+/// Open a channel of `capacity` between two nodes, each charging the given proportional fees, and
+/// have both of them know its announcement and updates. Returns its short channel id along with
+/// the fees of its first and second direction.
+async fn open_channel(bitcoind: &Bitcoind, nodes: (&TestNode, &TestNode), capacity: Amount, fees: (u32, u32)) -> (u64, (u32, u32)) {
+	let secp_ctx = Secp256k1::new();
+	// the node with the lesser id comes first, and its updates are those of the first direction
+	let ((node_1, fee_1), (node_2, fee_2)) = if NodeId::from_pubkey(&nodes.0.node_id) < NodeId::from_pubkey(&nodes.1.node_id) {
+		((nodes.0, fees.0), (nodes.1, fees.1))
+	} else {
+		((nodes.1, fees.1), (nodes.0, fees.0))
+	};
+	let bitcoin_secret_1 = SecretKey::from_slice(&Sha256dHash::hash(&node_1.node_secret[..]).to_byte_array()).unwrap();
+	let bitcoin_secret_2 = SecretKey::from_slice(&Sha256dHash::hash(&node_2.node_secret[..]).to_byte_array()).unwrap();
+	let bitcoin_key_1 = bitcoin_secret_1.public_key(&secp_ctx);
+	let bitcoin_key_2 = bitcoin_secret_2.public_key(&secp_ctx);
+	let funding_script = make_funding_redeemscript(&bitcoin_key_1, &bitcoin_key_2).to_p2wsh();
+	let short_channel_id = bitcoind.fund_output(&funding_script, capacity).await;
+
+	let contents = UnsignedChannelAnnouncement {
+		features: ChannelFeatures::empty(),
+		chain_hash: chain_hash(),
+		short_channel_id,
+		node_id_1: NodeId::from_pubkey(&node_1.node_id),
+		node_id_2: NodeId::from_pubkey(&node_2.node_id),
+		bitcoin_key_1: NodeId::from_pubkey(&bitcoin_key_1),
+		bitcoin_key_2: NodeId::from_pubkey(&bitcoin_key_2),
+		excess_data: vec![],
+	};
+	let msg_hash = gossip_hash(&contents);
+	let announcement = ChannelAnnouncement {
+		node_signature_1: secp_ctx.sign_ecdsa(&msg_hash, &node_1.node_secret),
+		node_signature_2: secp_ctx.sign_ecdsa(&msg_hash, &node_2.node_secret),
+		bitcoin_signature_1: secp_ctx.sign_ecdsa(&msg_hash, &bitcoin_secret_1),
+		bitcoin_signature_2: secp_ctx.sign_ecdsa(&msg_hash, &bitcoin_secret_2),
+		contents,
+	};
+	let updates = [node_1.update(short_channel_id, false, fee_1, capacity), node_2.update(short_channel_id, true, fee_2, capacity)];
+	node_1.apply_channel_gossip(&announcement, &updates);
+	node_2.apply_channel_gossip(&announcement, &updates);
+	(short_channel_id, (fee_1, fee_2))
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_regtest_snapshot_pipeline() {
+	let test_directory = format!("./res/regtest-{}", SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos());
+	let bitcoind = Bitcoind::start(format!("{}/bitcoind", test_directory)).await;
+	let logger = Arc::new(TestLogger::with_id("regtest".to_string()));
+	let node_a = TestNode::start(1, Arc::clone(&logger)).await;
+	let node_b = TestNode::start(2, Arc::clone(&logger)).await;
+
+	let channels = [
+		open_channel(&bitcoind, (&node_a, &node_b), Amount::from_sat(1_000_000), (100, 200)).await,
+		open_channel(&bitcoind, (&node_b, &node_a), Amount::from_sat(2_500_000), (300, 400)).await,
+	];
+	for (node, alias) in [(&node_a, "node a"), (&node_b, "node b")] {
+		let announcement = node.announcement(alias);
+		node_a.network_graph.update_node_from_announcement(&announcement).unwrap();
+		node_b.network_graph.update_node_from_announcement(&announcement).unwrap();
+	}
+
+	let server = RapidGossipSyncServer::builder(Arc::clone(&logger))
+		.network(Network::Regtest)
+		.setting("RAPID_GOSSIP_SYNC_SERVER_CACHES_PATH", &format!("{}/cache", test_directory))
+		.setting("RAPID_GOSSIP_SYNC_SERVER_DB_BACKEND", "sqlite")
+		.setting("RAPID_GOSSIP_SYNC_SERVER_SQLITE_PATH", &format!("{}/gossip.sqlite", test_directory))
+		.setting("BITCOIN_REST_DOMAIN", "127.0.0.1")
+		.setting("BITCOIN_REST_PORT", &bitcoind.rpc_port.to_string())
+		.setting("LN_PEERS", &format!("{},{}", node_a.peer_setting(), node_b.peer_setting()))
+		.build();
+	let handle = server.start();
+
+	wait_for("the initial sync", || {
+		let stats = handle.stats();
+		stats.initial_sync_complete && stats.channel_count == channels.len()
+	}).await;
+	for (short_channel_id, _) in &channels {
+		// the funding amounts were looked up from bitcoind
+		assert!(handle.channel(*short_channel_id).unwrap().capacity_sats.is_some());
+	}
+	handle.trigger_snapshot();
+	wait_for("the snapshots", || handle.stats().snapshot_age.is_some()).await;
+
+	let snapshot = fs::read(format!("{}/cache/symlinks/0.bin", test_directory)).unwrap();
+	let client_graph = NetworkGraph::new(Network::Regtest, Arc::clone(&logger));
+	RapidGossipSync::new(&client_graph, Arc::clone(&logger)).update_network_graph(&snapshot).unwrap();
+	{
+		let client_graph = client_graph.read_only();
+		assert_eq!(client_graph.channels().len(), channels.len());
+		for (short_channel_id, (fee_1, fee_2)) in channels {
+			let channel = client_graph.channel(short_channel_id).unwrap();
+			let node_ids = [NodeId::from_pubkey(&node_a.node_id), NodeId::from_pubkey(&node_b.node_id)];
+			assert!(node_ids.contains(&channel.node_one) && node_ids.contains(&channel.node_two));
+			assert_eq!(channel.one_to_two.as_ref().unwrap().fees.proportional_millionths, fee_1);
+			assert_eq!(channel.two_to_one.as_ref().unwrap().fees.proportional_millionths, fee_2);
+		}
+	}
+
+	handle.shutdown().await;
+	drop(bitcoind);
+	fs::remove_dir_all(&test_directory).unwrap();
+}
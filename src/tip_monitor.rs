@@ -0,0 +1,111 @@
+//! Watches how far the chain source lags behind the network. Channels can only be verified once
+//! their funding transaction is among the source's blocks, so a lagging source silently holds up
+//! newly announced channels.
+
+use std::io;
+use std::ops::Deref;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use lightning::{log_error, log_info, log_warn};
+use lightning::util::logger::Logger;
+use serde::Serialize;
+use tokio::sync::watch;
+
+use crate::chain::{ChainSource, ChainTip};
+use crate::config;
+use crate::hooks::AlertWebhook;
+use crate::metrics::Metrics;
+use crate::shutdown_initiated;
+
+/// The average time between blocks
+const BLOCK_INTERVAL_SECS: u64 = 600;
+
+/// The details of a chain tip alert
+#[derive(Serialize)]
+struct ChainTipStatus {
+	tip_height: u32,
+	tip_age_secs: u64,
+	lag_blocks: u32,
+}
+
+/// The number of blocks `tip` is estimated to be behind the network at `now`, either because the
+/// source is still syncing the headers it knows of, or because its tip is older than the blocks
+/// found since would suggest
+fn lag_blocks(tip: &ChainTip, now: u64) -> u32 {
+	let header_lag = tip.best_header_height.map_or(0, |best_header_height| best_header_height.saturating_sub(tip.height));
+	let age_lag = (now.saturating_sub(tip.time as u64) / BLOCK_INTERVAL_SECS) as u32;
+	header_lag.max(age_lag)
+}
+
+/// Compare the chain source's tip against the wall clock every [`config::CHAIN_TIP_CHECK_INTERVAL`]
+/// until `shutdown` is set, reporting when it falls `lag_threshold` or more blocks behind and when
+/// it has caught up again
+pub(crate) async fn monitor_chain_tip<L: Deref>(chain_source: Arc<dyn ChainSource>, metrics: Arc<Metrics>, alerts: Option<AlertWebhook<L>>, lag_threshold: u32, mut shutdown: watch::Receiver<bool>, logger: L) where L::Target: Logger {
+	let mut interval = tokio::time::interval(config::CHAIN_TIP_CHECK_INTERVAL);
+	let mut is_lagging = false;
+	loop {
+		tokio::select! {
+			_ = interval.tick() => {},
+			_ = shutdown_initiated(&mut shutdown) => return,
+		}
+
+		let tip = match chain_source.tip().await {
+			Ok(tip) => tip,
+			Err(e) if e.kind() == io::ErrorKind::Unsupported => {
+				log_info!(logger, "Not monitoring the chain tip, as the chain source doesn't report it");
+				return;
+			}
+			Err(e) => {
+				log_warn!(logger, "Failed to retrieve the chain tip: {}", e);
+				continue;
+			}
+		};
+		let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+		let status = ChainTipStatus {
+			tip_height: tip.height,
+			tip_age_secs: now.saturating_sub(tip.time as u64),
+			lag_blocks: lag_blocks(&tip, now),
+		};
+		metrics.set_chain_tip(status.tip_height, status.tip_age_secs, status.lag_blocks);
+
+		let event = if status.lag_blocks >= lag_threshold && !is_lagging {
+			log_error!(logger, "Chain backend is {} blocks behind at height {}, its tip being {} seconds old", status.lag_blocks, status.tip_height, status.tip_age_secs);
+			"chain_tip_lagging"
+		} else if status.lag_blocks < lag_threshold && is_lagging {
+			log_info!(logger, "Chain backend has caught up at height {}", status.tip_height);
+			"chain_tip_recovered"
+		} else {
+			continue;
+		};
+		is_lagging = !is_lagging;
+		if let Some(alerts) = &alerts {
+			alerts.notify(event, &status).await;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::chain::ChainTip;
+	use crate::tip_monitor::lag_blocks;
+
+	#[test]
+	fn test_lag_blocks() {
+		let now = 1_700_000_000;
+		let tip = ChainTip { height: 870000, time: now as u32 - 300, best_header_height: Some(870000) };
+		assert_eq!(lag_blocks(&tip, now), 0);
+
+		// a tip that's two hours old is estimated to be twelve blocks behind
+		let stale_tip = ChainTip { time: now as u32 - 7200, ..tip };
+		assert_eq!(lag_blocks(&stale_tip, now), 12);
+
+		// a source that's still syncing is as far behind as the headers it hasn't got the blocks for
+		let syncing_tip = ChainTip { best_header_height: Some(870020), ..tip };
+		assert_eq!(lag_blocks(&syncing_tip, now), 20);
+		assert_eq!(lag_blocks(&ChainTip { best_header_height: None, ..stale_tip }, now), 12);
+
+		// clock skew doesn't make the tip appear to be ahead
+		assert_eq!(lag_blocks(&ChainTip { time: now as u32 + 60, ..tip }, now), 0);
+	}
+}
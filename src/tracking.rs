@@ -1,44 +1,82 @@
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::Entry;
 use std::collections::hash_map::RandomState;
+use std::fs::{self, File, OpenOptions};
 use std::hash::{BuildHasher, Hasher};
-use std::net::SocketAddr;
+use std::future::Future;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{SocketAddr, ToSocketAddrs};
 use std::ops::Deref;
+use std::os::unix::fs::OpenOptionsExt;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::{Duration, Instant};
 
+use bitcoin::Network;
 use bitcoin::secp256k1::PublicKey;
 use hex_conservative::display::DisplayHex;
+use lightning::ln::msgs::SocketAddress;
 use lightning::ln::peer_handler::{
 	ErroringMessageHandler, IgnoringMessageHandler, MessageHandler, PeerManager,
 };
-use lightning::{log_info, log_warn};
+use lightning::{log_error, log_info, log_warn};
 use lightning::routing::gossip::NetworkGraph;
-use lightning::sign::KeysManager;
+use lightning::sign::{KeysManager, NodeSigner, Recipient};
 use lightning::util::logger::Logger;
-use tokio::sync::mpsc;
-use tokio::task::JoinSet;
+use serde::Serialize;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::task::{JoinHandle, JoinSet};
 
+use crate::blocklist;
+use crate::chain::ChainSource;
 use crate::config;
+use crate::dns_seed;
+use crate::feed::GossipFeed;
+use crate::socks;
 use crate::downloader::GossipRouter;
+use crate::health::HealthMonitor;
+use crate::hooks::AlertWebhook;
+use crate::leader::Leadership;
+use crate::metrics::Metrics;
+use crate::peer_health::{PeerHealth, PeerHealthTracker};
+use crate::recording::GossipRecorder;
 use crate::types::{GossipMessage, GossipPeerManager};
+use crate::verifier::FundingAmountCache;
 
+#[allow(clippy::too_many_arguments)]
 pub(crate) async fn download_gossip<L: Deref + Clone + Send + Sync + 'static>(persistence_sender: mpsc::Sender<GossipMessage>,
 	completion_sender: mpsc::Sender<()>,
 	network_graph: Arc<NetworkGraph<L>>,
+	chain_source: Arc<dyn ChainSource>,
+	channel_funding_amounts: FundingAmountCache,
+	health_monitor: Arc<HealthMonitor>,
+	metrics: Arc<Metrics>,
+	feed: Arc<GossipFeed>,
+	leadership: Arc<Leadership>,
+	peer_commands: mpsc::Receiver<PeerCommand>,
+	mut shutdown: watch::Receiver<bool>,
 	logger: L,
 ) where L::Target: Logger {
-	let mut key = [42; 32];
+	let network = config::graph_network(&network_graph);
+	let key = load_node_seed(network);
 	let mut random_data = [43; 32];
 	// Get something psuedo-random from std.
-	let mut key_hasher = RandomState::new().build_hasher();
-	key_hasher.write_u8(1);
-	key[0..8].copy_from_slice(&key_hasher.finish().to_ne_bytes());
 	let mut rand_hasher = RandomState::new().build_hasher();
 	rand_hasher.write_u8(2);
 	random_data[0..8].copy_from_slice(&rand_hasher.finish().to_ne_bytes());
 
 	let keys_manager = Arc::new(KeysManager::new(&key, 0xdeadbeef, 0xdeadbeef));
+	let node_id = keys_manager.get_node_id(Recipient::Node).unwrap();
 
-	let router = Arc::new(GossipRouter::new(network_graph, persistence_sender.clone(), logger.clone()));
+	let peer_health = Arc::new(PeerHealthTracker::new());
+	let mut router = GossipRouter::new(network_graph, persistence_sender.clone(), chain_source, channel_funding_amounts, Arc::clone(&peer_health), Arc::clone(&metrics), feed, logger.clone());
+	router.follow_leadership(leadership);
+	if let Some(path) = config::gossip_recording_path(network) {
+		router.record_to(GossipRecorder::start(path, config::gossip_recording_file_size(), config::gossip_recording_max_files(), Arc::clone(&metrics), logger.clone()));
+	}
+	let router = Arc::new(router);
 
 	let message_handler = MessageHandler {
 		chan_handler: ErroringMessageHandler::new(),
@@ -64,20 +102,40 @@ pub(crate) async fn download_gossip<L: Deref + Clone + Send + Sync + 'static>(pe
 		}
 	});
 
+	let mut inbound_peers = None;
+	if let Some(listen_address) = config::listen_address(network) {
+		let listener = TcpListener::bind(listen_address).await
+			.unwrap_or_else(|e| panic!("Failed to bind peer listener to {}: {}", listen_address, e));
+		let announced_address = config::announced_address(network).unwrap_or(listen_address.into());
+		log_info!(logger, "Accepting inbound peer connections on {} as {}@{}", listen_address, node_id, announced_address);
+		inbound_peers = Some(tokio::spawn(accept_inbound_peers(listener, Arc::clone(&peer_handler), logger.clone())));
+	}
+
+	// registered before connecting, as a hangup would otherwise terminate the process
+	let reload_signal = signal(SignalKind::hangup()).expect("Failed to register peer reload signal handler");
+
 	log_info!(logger, "Connecting to Lightning peers...");
-	let peers = config::ln_peers();
+	let peers = resolve_peers(network, &logger).await.unwrap_or_else(|e| panic!("{}", e));
 	let mut handles = JoinSet::new();
 	let mut connected_peer_count = 0;
+	let mut peer_connections = HashMap::new();
 
 	if peers.len() <= config::CONNECTED_PEER_ASSERTION_LIMIT {
 		log_warn!(logger, "Peer assertion threshold is {}, but only {} peers specified.", config::CONNECTED_PEER_ASSERTION_LIMIT, peers.len());
 	}
 
 	for current_peer in peers {
-		let peer_handler_clone = peer_handler.clone();
-		let logger_clone = logger.clone();
+		let entry = match peer_connections.entry(current_peer.0) {
+			Entry::Vacant(entry) => entry,
+			Entry::Occupied(_) => {
+				log_warn!(logger, "Ignoring duplicate peer {}", current_peer.0);
+				continue;
+			}
+		};
+		let (connection, mut first_attempt) = spawn_peer_connection(current_peer, peer_handler.clone(), Arc::clone(&peer_health), Arc::clone(&metrics), logger.clone());
+		entry.insert(connection);
 		handles.spawn(async move {
-			connect_peer(current_peer, peer_handler_clone, logger_clone).await
+			first_attempt.recv().await.unwrap_or(false)
 		});
 	}
 
@@ -97,11 +155,16 @@ pub(crate) async fn download_gossip<L: Deref + Clone + Send + Sync + 'static>(pe
 	}
 
 	log_info!(logger, "Connected to {} Lightning peers!", connected_peer_count);
+	let peers = ManagedPeers { network, connections: peer_connections, retired_peers: HashSet::new(), peer_manager: Arc::clone(&peer_handler), peer_health, metrics: Arc::clone(&metrics), logger: logger.clone() };
+	tokio::spawn(manage_peers(peers, reload_signal, peer_commands, shutdown.clone()));
 
 	let mut previous_announcement_count = 0u64;
 	let mut previous_update_count = 0u64;
 	let mut is_caught_up_with_gossip = false;
 
+	let stall_timeout = config::gossip_stall_timeout();
+	let alerts = config::alert_webhook_url(network).map(|url| AlertWebhook::new(url, network, logger.clone()));
+
 	let mut i = 0u32;
 	let mut latest_new_gossip_time = Instant::now();
 	let mut needs_to_notify_persister = false;
@@ -109,7 +172,19 @@ pub(crate) async fn download_gossip<L: Deref + Clone + Send + Sync + 'static>(pe
 	loop {
 		i += 1; // count the background activity
 		let sleep = tokio::time::sleep(Duration::from_secs(5));
-		sleep.await;
+		tokio::select! {
+			_ = sleep => {},
+			_ = crate::shutdown_initiated(&mut shutdown) => {
+				if let Some(inbound_peers) = inbound_peers {
+					inbound_peers.abort();
+				}
+				log_info!(logger, "Stopped downloading gossip");
+				return;
+			}
+		}
+		health_monitor.set_connected_peer_count(peer_handler.list_peers().len());
+		let persistence_queue_depth = persistence_sender.max_capacity() - persistence_sender.capacity();
+		metrics.set_persistence_queue_depth(persistence_queue_depth);
 
 		{
 			let counter = router.counter.read().unwrap();
@@ -127,14 +202,16 @@ pub(crate) async fn download_gossip<L: Deref + Clone + Send + Sync + 'static>(pe
 			if !is_caught_up_with_gossip || (is_caught_up_with_gossip != was_previously_caught_up_with_gossip) {
 				log_info!(
 					logger,
-					"gossip count (iteration {}): {} (delta: {}):\n\tannouncements: {}\n\t\tmismatched scripts: {}\n\tupdates: {}\n\t\tno HTLC max: {}\n",
+					"gossip count (iteration {}): {} (delta: {}):\n\tannouncements: {}\n\t\tmismatched scripts: {}\n\t\tmismatched chains: {}\n\tupdates: {}\n\t\tno HTLC max: {}\n\tpersistence queue: {}\n",
 					i,
 					total_message_count,
 					new_message_count,
 					counter.channel_announcements,
-					counter.channel_announcements_with_mismatched_scripts,
+					metrics.funding_script_mismatch_count(),
+					router.mismatched_chain_announcement_count(),
 					counter.channel_updates,
-					counter.channel_updates_without_htlc_max_msats
+					counter.channel_updates_without_htlc_max_msats,
+					persistence_queue_depth
 				);
 			} else {
 				log_info!(logger, "Monitoring for gossip…")
@@ -147,15 +224,25 @@ pub(crate) async fn download_gossip<L: Deref + Clone + Send + Sync + 'static>(pe
 				log_info!(logger, "Received new messages since catching up with gossip!");
 			}
 
-			let continuous_caught_up_duration = latest_new_gossip_time.elapsed();
-			if continuous_caught_up_duration.as_secs() > 600 {
-				log_warn!(logger, "No new gossip messages in 10 minutes! Something's amiss!");
-			}
-
 			previous_announcement_count = counter.channel_announcements;
 			previous_update_count = counter.channel_updates;
 		}
 
+		let time_since_last_gossip = latest_new_gossip_time.elapsed();
+		if let Some(is_stalled) = health_monitor.update_gossip_stall(time_since_last_gossip, stall_timeout) {
+			let event = if is_stalled {
+				log_error!(logger, "No new gossip messages in {} seconds! Snapshots will go stale until peers send gossip again", time_since_last_gossip.as_secs());
+				"gossip_stalled"
+			} else {
+				log_info!(logger, "Receiving gossip again");
+				"gossip_recovered"
+			};
+			if let Some(alerts) = &alerts {
+				let status = GossipStallStatus { secs_since_last_gossip: time_since_last_gossip.as_secs(), connected_peers: health_monitor.connected_peer_count() };
+				alerts.notify(event, &status).await;
+			}
+		}
+
 		if needs_to_notify_persister {
 			needs_to_notify_persister = false;
 			completion_sender.send(()).await.unwrap();
@@ -163,37 +250,459 @@ pub(crate) async fn download_gossip<L: Deref + Clone + Send + Sync + 'static>(pe
 	}
 }
 
-async fn connect_peer<L: Deref + Clone + Send + Sync + 'static>(current_peer: (PublicKey, SocketAddr), peer_manager: GossipPeerManager<L>, logger: L) -> bool where L::Target: Logger {
+/// The details of a gossip stall alert
+#[derive(Serialize)]
+struct GossipStallStatus {
+	secs_since_last_gossip: u64,
+	connected_peers: usize,
+}
+
+/// The seed our node key is derived from, which is persisted in the cache directory so that our
+/// node id remains the same across restarts for peers connecting to us. The seed is drawn from the
+/// OS' randomness and only readable by our user, and an existing one is never overwritten.
+fn load_node_seed(network: Network) -> [u8; 32] {
+	let seed_path = format!("{}/node_seed", config::cache_path(network));
+	if let Ok(seed) = fs::read(&seed_path) {
+		return seed.try_into().unwrap_or_else(|_| panic!("{} must contain 32 bytes", seed_path));
+	}
+
+	let mut seed = [0; 32];
+	File::open("/dev/urandom").and_then(|mut urandom| urandom.read_exact(&mut seed)).expect("Failed to read randomness for the node seed");
+	fs::create_dir_all(config::cache_path(network)).expect("Failed to create cache directory");
+	let mut seed_file = match OpenOptions::new().write(true).create_new(true).mode(0o600).open(&seed_path) {
+		Ok(seed_file) => seed_file,
+		// another process created the seed in the meantime
+		Err(e) if e.kind() == ErrorKind::AlreadyExists => return load_node_seed(network),
+		Err(e) => panic!("Failed to create node seed file {}: {}", seed_path, e),
+	};
+	seed_file.write_all(&seed).and_then(|_| seed_file.sync_all()).expect("Failed to persist node seed");
+	seed
+}
+
+/// Serve peers connecting to us, up to the configured number at a time
+async fn accept_inbound_peers<L: Deref + Clone + Send + Sync + 'static>(listener: TcpListener, peer_manager: GossipPeerManager<L>, logger: L) where L::Target: Logger {
+	let max_inbound_peers = config::max_inbound_peers();
+	let inbound_peer_count = Arc::new(AtomicUsize::new(0));
+	loop {
+		let (stream, remote_address) = match listener.accept().await {
+			Ok(connection) => connection,
+			Err(e) => {
+				log_warn!(logger, "Failed to accept inbound peer connection: {}", e);
+				continue;
+			}
+		};
+		if inbound_peer_count.load(Ordering::Acquire) >= max_inbound_peers {
+			log_warn!(logger, "Rejecting inbound peer connection from {}, already serving {} inbound peers", remote_address, max_inbound_peers);
+			continue;
+		}
+		let stream = match stream.into_std() {
+			Ok(stream) => stream,
+			Err(e) => {
+				log_warn!(logger, "Failed to set up inbound peer connection from {}: {}", remote_address, e);
+				continue;
+			}
+		};
+		log_info!(logger, "Accepted inbound peer connection from {}", remote_address);
+		inbound_peer_count.fetch_add(1, Ordering::AcqRel);
+		let connection_count = Arc::clone(&inbound_peer_count);
+		let connection_logger = logger.clone();
+		let disconnection_future = lightning_net_tokio::setup_inbound(Arc::clone(&peer_manager), stream);
+		tokio::spawn(async move {
+			disconnection_future.await;
+			connection_count.fetch_sub(1, Ordering::AcqRel);
+			log_info!(connection_logger, "Inbound peer {} disconnected", remote_address);
+		});
+	}
+}
+
+/// The configured peers or, absent those, peers discovered via DNS seeds
+async fn resolve_peers<L: Deref>(network: Network, logger: &L) -> Result<Vec<(PublicKey, SocketAddress)>, String> where L::Target: Logger {
+	if let Some(peers) = config::try_ln_peers(network)? {
+		return Ok(peers);
+	}
+	let mut peers = dns_seed::discover_peers(&config::dns_seeds(network), config::dns_seed_peer_count(), logger).await;
+	if peers.is_empty() {
+		peers = config::default_ln_peers(network);
+	}
+	if peers.is_empty() {
+		return Err(format!("LN_PEERS or RAPID_GOSSIP_SYNC_SERVER_DNS_SEEDS must be set when operating on {}", network));
+	}
+	Ok(peers)
+}
+
+/// A peer the server maintains a connection to
+#[derive(Clone, Debug)]
+pub struct PeerInfo {
+	pub node_id: PublicKey,
+	pub address: SocketAddress,
+	/// Whether the connection is currently established, rather than being reestablished
+	pub is_connected: bool,
+}
+
+/// A query of, or change to, the peers we maintain connections to, issued while the sync is running
+pub(crate) enum PeerCommand {
+	List(oneshot::Sender<Vec<PeerInfo>>),
+	/// Connect to a peer, or reconnect to it at the given address if we're already connected to it
+	Add((PublicKey, SocketAddress)),
+	/// Disconnect from a peer, replying whether we maintained a connection to it
+	Remove(PublicKey, oneshot::Sender<bool>),
+}
+
+/// A peer we keep a connection to, reconnecting whenever it drops
+struct PeerConnection {
+	address: SocketAddress,
+	task: JoinHandle<()>,
+}
+
+impl PeerConnection {
+	fn disconnect<L: Deref + Clone + Send + Sync + 'static>(&self, node_id: PublicKey, peer_manager: &GossipPeerManager<L>) where L::Target: Logger {
+		self.task.abort();
+		peer_manager.disconnect_by_node_id(node_id);
+	}
+}
+
+/// Connect to a peer in the background, returning the connection along with the outcome of the
+/// first connection attempt. Dropped connections are reestablished with exponential backoff, unless
+/// the configured number of consecutive attempts has failed.
+fn spawn_peer_connection<L: Deref + Clone + Send + Sync + 'static>(current_peer: (PublicKey, SocketAddress), peer_manager: GossipPeerManager<L>, peer_health: Arc<PeerHealthTracker>, metrics: Arc<Metrics>, logger: L) -> (PeerConnection, mpsc::Receiver<bool>) where L::Target: Logger {
 	// we seek to find out if the first connection attempt was successful
-	let (sender, mut receiver) = mpsc::channel::<bool>(1);
-	tokio::spawn(async move {
+	let (sender, receiver) = mpsc::channel::<bool>(1);
+	let max_attempts = config::peer_reconnect_max_attempts();
+	let base_delay = config::peer_reconnect_base_delay();
+	let max_delay = config::peer_reconnect_max_delay();
+	let proxy = config::proxy();
+	let address = current_peer.1.clone();
+	let task = tokio::spawn(async move {
 		let current_peer_pubkey_hex = current_peer.0.serialize().to_lower_hex_string();
 		log_info!(logger, "Connecting to peer {}@{}...", current_peer_pubkey_hex, current_peer.1);
 		let mut is_first_iteration = true;
+		let mut failed_attempts = 0u32;
 		loop {
-			if let Some(disconnection_future) = lightning_net_tokio::connect_outbound(
-				Arc::clone(&peer_manager),
-				current_peer.0,
-				current_peer.1,
-			).await {
+			if let Some(disconnection_future) = connect_outbound(Arc::clone(&peer_manager), current_peer.0, &current_peer.1, proxy).await {
 				log_info!(logger, "Connected to peer {}@{}!", current_peer_pubkey_hex, current_peer.1);
+				peer_health.record_connection(current_peer.0);
 				if is_first_iteration {
-					sender.send(true).await.unwrap();
+					let _ = sender.send(true).await;
+				} else {
+					metrics.record_reconnect_attempt(true);
 				}
+				failed_attempts = 0;
 				disconnection_future.await;
 				log_warn!(logger, "Disconnected from peer {}@{}", current_peer_pubkey_hex, current_peer.1);
+				peer_health.record_disconnection(current_peer.0);
 			} else {
 				log_warn!(logger, "Failed to connect to peer {}@{}!", current_peer_pubkey_hex, current_peer.1);
 				if is_first_iteration {
-					sender.send(false).await.unwrap();
+					let _ = sender.send(false).await;
+				} else {
+					metrics.record_reconnect_attempt(false);
+				}
+				failed_attempts += 1;
+				if max_attempts.is_some_and(|max_attempts| failed_attempts >= max_attempts) {
+					log_error!(logger, "Giving up on peer {}@{} after {} failed connection attempts", current_peer_pubkey_hex, current_peer.1, failed_attempts);
+					peer_health.record_abandonment(current_peer.0);
+					metrics.record_peer_abandoned();
+					return;
 				}
 			}
 			is_first_iteration = false;
-			tokio::time::sleep(Duration::from_secs(10)).await;
-			log_warn!(logger, "Reconnecting to peer {}@{}...", current_peer_pubkey_hex, current_peer.1);
+			let delay = reconnect_delay(base_delay, max_delay, failed_attempts, RandomState::new().build_hasher().finish());
+			log_warn!(logger, "Reconnecting to peer {}@{} in {:?}...", current_peer_pubkey_hex, current_peer.1, delay);
+			tokio::time::sleep(delay).await;
 		}
 	});
 
-	let success = receiver.recv().await.unwrap();
-	success
+	(PeerConnection { address, task }, receiver)
+}
+
+/// Connect to a peer, through the proxy if one is configured. Like
+/// [`lightning_net_tokio::connect_outbound`], this returns a future completing once the peer
+/// disconnects.
+async fn connect_outbound<L: Deref + Clone + Send + Sync + 'static>(peer_manager: GossipPeerManager<L>, node_id: PublicKey, address: &SocketAddress, proxy: Option<SocketAddr>) -> Option<impl Future<Output = ()>> where L::Target: Logger {
+	let connect_future = async {
+		match proxy {
+			Some(proxy) => socks::connect(proxy, address).await,
+			None => {
+				// host names are resolved anew for every attempt, so moved peers are reconnected to
+				let mut last_error = io::Error::new(io::ErrorKind::Other, "Cannot resolve node address");
+				for socket_address in resolve_address(address).await? {
+					match TcpStream::connect(socket_address).await {
+						Ok(stream) => return Ok(stream),
+						Err(e) => last_error = e,
+					}
+				}
+				Err(last_error)
+			}
+		}
+	};
+	// connections via Tor take considerably longer to establish
+	let timeout = if proxy.is_some() { config::PROXIED_PEER_CONNECTION_TIMEOUT } else { config::PEER_CONNECTION_TIMEOUT };
+	let stream = tokio::time::timeout(timeout, connect_future).await.ok()?.ok()?;
+	Some(lightning_net_tokio::setup_outbound(peer_manager, node_id, stream.into_std().ok()?))
+}
+
+/// The socket addresses a peer's address resolves to, looking up host names without blocking
+async fn resolve_address(address: &SocketAddress) -> io::Result<Vec<SocketAddr>> {
+	match address {
+		SocketAddress::Hostname { hostname, port } => Ok(tokio::net::lookup_host((hostname.as_str(), *port)).await?.collect()),
+		_ => Ok(address.to_socket_addrs()?.collect()),
+	}
+}
+
+/// The delay before reconnecting after the given number of consecutive failed attempts, doubling
+/// with each of them up to a cap. Half of it is randomized using `entropy`, so that peers dropped at
+/// the same time aren't all reconnected to at once.
+fn reconnect_delay(base_delay: Duration, max_delay: Duration, failed_attempts: u32, entropy: u64) -> Duration {
+	let delay = base_delay.saturating_mul(1u32.checked_shl(failed_attempts).unwrap_or(u32::MAX)).min(max_delay);
+	let jitter_range_ms = delay.as_millis() as u64 / 2;
+	let jitter = Duration::from_millis(entropy % (jitter_range_ms + 1));
+	delay - Duration::from_millis(jitter_range_ms) + jitter
+}
+
+/// The peers we maintain connections to once the initial connections have been established
+struct ManagedPeers<L: Deref + Clone + Send + Sync + 'static> where L::Target: Logger {
+	network: Network,
+	connections: HashMap<PublicKey, PeerConnection>,
+	/// Peers replaced for being unhealthy, which aren't reconnected to until the peers are reloaded
+	retired_peers: HashSet<PublicKey>,
+	peer_manager: GossipPeerManager<L>,
+	peer_health: Arc<PeerHealthTracker>,
+	metrics: Arc<Metrics>,
+	logger: L,
+}
+
+/// Reload the peers whenever a hangup signal is received, apply the peer commands received at
+/// runtime, and periodically replace peers that have gone silent or keep disconnecting. Upon
+/// shutdown, all peers are disconnected.
+async fn manage_peers<L: Deref + Clone + Send + Sync + 'static>(mut peers: ManagedPeers<L>, mut reload_signal: Signal, mut commands: mpsc::Receiver<PeerCommand>, mut shutdown: watch::Receiver<bool>) where L::Target: Logger {
+	let mut health_check = tokio::time::interval(config::PEER_HEALTH_CHECK_INTERVAL);
+	// the first tick completes immediately
+	health_check.tick().await;
+	loop {
+		tokio::select! {
+			signal = reload_signal.recv() => {
+				if signal.is_none() {
+					break;
+				}
+				peers.reload().await;
+			}
+			Some(command) = commands.recv() => {
+				peers.execute(command);
+			}
+			_ = health_check.tick() => {
+				peers.replace_unhealthy().await;
+			}
+			_ = crate::shutdown_initiated(&mut shutdown) => {
+				peers.disconnect_all();
+				break;
+			}
+		}
+	}
+}
+
+impl<L: Deref + Clone + Send + Sync + 'static> ManagedPeers<L> where L::Target: Logger {
+	fn connect(&mut self, peer: (PublicKey, SocketAddress)) {
+		let node_id = peer.0;
+		let (connection, _) = spawn_peer_connection(peer, Arc::clone(&self.peer_manager), Arc::clone(&self.peer_health), Arc::clone(&self.metrics), self.logger.clone());
+		self.connections.insert(node_id, connection);
+	}
+
+	fn execute(&mut self, command: PeerCommand) {
+		match command {
+			PeerCommand::List(reply) => {
+				let peers = self.connections.iter().map(|(node_id, connection)| PeerInfo {
+					node_id: *node_id,
+					address: connection.address.clone(),
+					is_connected: self.peer_manager.peer_by_node_id(node_id).is_some(),
+				}).collect();
+				let _ = reply.send(peers);
+			}
+			PeerCommand::Add(peer) => {
+				if let Some(connection) = self.connections.remove(&peer.0) {
+					connection.disconnect(peer.0, &self.peer_manager);
+				}
+				log_info!(self.logger, "Adding peer {}@{}", peer.0, peer.1);
+				self.retired_peers.remove(&peer.0);
+				self.connect(peer);
+			}
+			PeerCommand::Remove(node_id, reply) => {
+				let connection = self.connections.remove(&node_id);
+				if let Some(connection) = &connection {
+					log_info!(self.logger, "Removing peer {}@{}", node_id, connection.address);
+					connection.disconnect(node_id, &self.peer_manager);
+					self.peer_health.remove(&node_id);
+				}
+				let _ = reply.send(connection.is_some());
+			}
+		}
+	}
+
+	fn disconnect_all(&mut self) {
+		log_info!(self.logger, "Disconnecting from all peers");
+		for (pubkey, connection) in self.connections.drain() {
+			connection.disconnect(pubkey, &self.peer_manager);
+		}
+		self.peer_manager.disconnect_all_peers();
+	}
+
+	/// Apply changes to the configured peers, connecting to added peers and disconnecting from
+	/// removed ones while the sync keeps running.
+	async fn reload(&mut self) {
+		log_info!(self.logger, "Received hangup signal, reloading peers");
+		if let Err(e) = config::reload_config_file() {
+			log_error!(self.logger, "Keeping the current peers: {}", e);
+			return;
+		}
+		match blocklist::reload() {
+			Ok(entry_count) => log_info!(self.logger, "Reloaded the blocklist with {} entries", entry_count),
+			Err(e) => log_error!(self.logger, "Keeping the current blocklist: {}", e),
+		}
+		let peers = match resolve_peers(self.network, &self.logger).await {
+			Ok(peers) => peers,
+			Err(e) => {
+				log_error!(self.logger, "Keeping the current peers: {}", e);
+				return;
+			}
+		};
+
+		let configured_peers: HashMap<PublicKey, SocketAddress> = peers.iter().cloned().collect();
+		self.connections.retain(|pubkey, connection| {
+			if configured_peers.get(pubkey) == Some(&connection.address) {
+				return true;
+			}
+			log_info!(self.logger, "Disconnecting from peer {}@{}", pubkey, connection.address);
+			connection.disconnect(*pubkey, &self.peer_manager);
+			self.peer_health.remove(pubkey);
+			false
+		});
+		for current_peer in peers {
+			if !self.connections.contains_key(&current_peer.0) {
+				self.connect(current_peer);
+			}
+		}
+		// give previously replaced peers another chance
+		self.retired_peers.clear();
+		log_info!(self.logger, "Reloaded peers, now maintaining connections to {}", self.connections.len());
+	}
+
+	async fn replace_unhealthy(&mut self) {
+		for (pubkey, message_count) in self.peer_health.message_rates() {
+			if self.connections.contains_key(&pubkey) {
+				log_info!(self.logger, "Received {} gossip messages from peer {} in the last {}s", message_count, pubkey, config::PEER_HEALTH_CHECK_INTERVAL.as_secs());
+			}
+		}
+
+		let silence_timeout = config::peer_silence_timeout();
+		let max_disconnections = config::peer_max_disconnections();
+		let unhealthy_peers: Vec<(PublicKey, PeerHealth)> = self.connections.keys()
+			.map(|pubkey| (*pubkey, self.peer_health.health(pubkey, silence_timeout, max_disconnections)))
+			.filter(|(_, health)| *health != PeerHealth::Healthy)
+			.collect();
+		if unhealthy_peers.is_empty() {
+			return;
+		}
+
+		let mut replacements = self.replacement_candidates(unhealthy_peers.len()).await.into_iter();
+		for (pubkey, health) in unhealthy_peers {
+			match replacements.next() {
+				Some(replacement) => {
+					log_warn!(self.logger, "Replacing {:?} peer {} with {}@{}", health, pubkey, replacement.0, replacement.1);
+					if let Some(connection) = self.connections.remove(&pubkey) {
+						connection.disconnect(pubkey, &self.peer_manager);
+					}
+					self.peer_health.remove(&pubkey);
+					self.retired_peers.insert(pubkey);
+					self.connect(replacement);
+				}
+				None if health == PeerHealth::Silent => {
+					// the connection task reconnects once the disconnection is noticed
+					log_warn!(self.logger, "No replacement for silent peer {}, reconnecting", pubkey);
+					self.peer_manager.disconnect_by_node_id(pubkey);
+				}
+				None if health == PeerHealth::Unreachable => {
+					log_warn!(self.logger, "No replacement for unreachable peer {}, retrying", pubkey);
+					if let Some(address) = self.connections.get(&pubkey).map(|connection| connection.address.clone()) {
+						self.connect((pubkey, address));
+					}
+				}
+				None => {
+					log_warn!(self.logger, "No replacement for flapping peer {}, keeping it", pubkey);
+				}
+			}
+		}
+	}
+
+	/// Peers we're neither connected to nor have replaced before, taken from the backup peers and,
+	/// if those don't suffice, DNS seeds
+	async fn replacement_candidates(&self, count: usize) -> Vec<(PublicKey, SocketAddress)> {
+		let network = self.network;
+		let mut candidates = match config::backup_ln_peers(network) {
+			Ok(peers) => peers,
+			Err(e) => {
+				log_error!(self.logger, "Ignoring backup peers: {}", e);
+				Vec::new()
+			}
+		};
+		let mut seen_peers = HashSet::new();
+		candidates.retain(|(pubkey, _)| self.is_replacement_candidate(pubkey) && seen_peers.insert(*pubkey));
+		if candidates.len() < count {
+			let discovered_peers = dns_seed::discover_peers(&config::dns_seeds(network), config::dns_seed_peer_count(), &self.logger).await;
+			candidates.extend(discovered_peers.into_iter().filter(|(pubkey, _)| self.is_replacement_candidate(pubkey) && seen_peers.insert(*pubkey)));
+		}
+		candidates
+	}
+
+	fn is_replacement_candidate(&self, pubkey: &PublicKey) -> bool {
+		!self.connections.contains_key(pubkey) && !self.retired_peers.contains(pubkey)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::os::unix::fs::PermissionsExt;
+	use std::str::FromStr;
+	use std::time::Duration;
+
+	use bitcoin::Network;
+	use lightning::ln::msgs::SocketAddress;
+
+	use crate::tracking::{load_node_seed, reconnect_delay, resolve_address};
+
+	#[test]
+	fn test_reconnect_delay() {
+		let base_delay = Duration::from_secs(1);
+		let max_delay = Duration::from_secs(300);
+		// half of the delay is randomized
+		assert_eq!(reconnect_delay(base_delay, max_delay, 0, 0), Duration::from_millis(500));
+		assert_eq!(reconnect_delay(base_delay, max_delay, 0, 500), Duration::from_secs(1));
+		assert_eq!(reconnect_delay(base_delay, max_delay, 0, 501), Duration::from_millis(500));
+		assert_eq!(reconnect_delay(base_delay, max_delay, 3, 1000), Duration::from_secs(4) + Duration::from_millis(1000));
+		for entropy in [0, 42, u64::MAX] {
+			let delay = reconnect_delay(base_delay, max_delay, 40, entropy);
+			assert!(delay >= max_delay / 2 && delay <= max_delay);
+		}
+	}
+
+	#[tokio::test]
+	async fn test_address_resolution() {
+		let addresses = resolve_address(&SocketAddress::from_str("[2001:db8::1]:9735").unwrap()).await.unwrap();
+		assert_eq!(addresses, vec!["[2001:db8::1]:9735".parse().unwrap()]);
+		let addresses = resolve_address(&SocketAddress::from_str("localhost:9735").unwrap()).await.unwrap();
+		assert!(!addresses.is_empty());
+		assert!(addresses.iter().all(|address| address.ip().is_loopback() && address.port() == 9735));
+	}
+
+	#[test]
+	fn test_node_seed_persistence() {
+		let cache_path = std::env::temp_dir().join(format!("rgs_node_seed_{}", std::process::id()));
+		std::env::set_var("RAPID_GOSSIP_SYNC_SERVER_CACHES_PATH_REGTEST", cache_path.to_str().unwrap());
+		let seed = load_node_seed(Network::Regtest);
+		assert_eq!(std::fs::read(cache_path.join("node_seed")).unwrap(), seed);
+		assert_eq!(load_node_seed(Network::Regtest), seed);
+		// the seed is only accessible to our user
+		let mode = std::fs::metadata(cache_path.join("node_seed")).unwrap().permissions().mode();
+		assert_eq!(mode & 0o777, 0o600);
+		std::fs::remove_dir_all(&cache_path).unwrap();
+		std::env::remove_var("RAPID_GOSSIP_SYNC_SERVER_CACHES_PATH_REGTEST");
+	}
 }
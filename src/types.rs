@@ -4,7 +4,7 @@ use lightning::sign::KeysManager;
 use lightning::ln::msgs::{ChannelAnnouncement, ChannelUpdate, NodeAnnouncement};
 use lightning::ln::peer_handler::{ErroringMessageHandler, IgnoringMessageHandler, PeerManager};
 use lightning::util::logger::{Logger, Record};
-use crate::config;
+use crate::logging;
 
 use crate::downloader::GossipRouter;
 use crate::verifier::ChainVerifier;
@@ -24,18 +24,23 @@ pub(crate) enum GossipMessage {
 pub struct RGSSLogger {}
 
 impl RGSSLogger {
+	/// Create a logger forwarding into `tracing`, installing a subscriber configured from the
+	/// environment if none is set yet.
 	pub fn new() -> RGSSLogger {
+		logging::init(false);
+		Self {}
+	}
+
+	/// Like [`Self::new`], but logging to stderr, such that stdout is left to command output.
+	pub fn stderr() -> RGSSLogger {
+		logging::init(true);
 		Self {}
 	}
 }
 
 impl Logger for RGSSLogger {
 	fn log(&self, record: Record) {
-		let threshold = config::log_level();
-		if record.level < threshold {
-			return;
-		}
-		println!("{:<5} [{} : {}, {}] {}", record.level.to_string(), record.module_path, record.file, record.line, record.args);
+		logging::forward(&record);
 	}
 }
 
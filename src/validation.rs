@@ -0,0 +1,89 @@
+//! Checking each snapshot generated by applying it the way clients do, such that a round containing
+//! a snapshot clients would reject, or which would corrupt their graph, is withheld rather than
+//! served.
+
+use std::ops::Deref;
+
+use lightning::routing::gossip::{ChannelUpdateInfo, NetworkGraph};
+use lightning::util::logger::Logger;
+use lightning_rapid_gossip_sync::RapidGossipSync;
+
+use crate::config;
+
+/// The policy fields a snapshot conveys for a channel direction
+fn policy(info: &ChannelUpdateInfo) -> (bool, u16, u64, u64, u32, u32) {
+	(info.enabled, info.cltv_expiry_delta, info.htlc_minimum_msat, info.htlc_maximum_msat, info.fees.base_msat, info.fees.proportional_millionths)
+}
+
+/// Apply `snapshot` to an empty network graph, checking that each of the `announcement_count`
+/// channels it announces is added. For full snapshots, the policies they convey are also compared
+/// against `network_graph`, which the snapshot was calculated from.
+///
+/// The graph keeps changing while the snapshot is calculated from the persisted gossip, so a share
+/// of the channels of up to [`config::SNAPSHOT_VERIFICATION_MAX_MISMATCH_RATIO`] may differ.
+pub(crate) fn validate_snapshot<L: Deref + Clone>(snapshot: &[u8], announcement_count: u32, is_full_snapshot: bool, network_graph: &NetworkGraph<L>, logger: L) -> Result<(), String> where L::Target: Logger {
+	let scratch_graph = NetworkGraph::new(config::graph_network(network_graph), logger.clone());
+	let rapid_sync = RapidGossipSync::new(&scratch_graph, logger);
+	// the snapshot's age is for clients to judge, whereas the check is about its encoding
+	rapid_sync.update_network_graph_no_std(snapshot, None)
+		.map_err(|e| format!("it fails to apply: {:?}", e))?;
+
+	let scratch_graph = scratch_graph.read_only();
+	let channel_count = scratch_graph.channels().len();
+	if channel_count != announcement_count as usize {
+		return Err(format!("it announces {} channels, of which {} were applied", announcement_count, channel_count));
+	}
+	if !is_full_snapshot {
+		return Ok(());
+	}
+
+	let source_graph = network_graph.read_only();
+	let mut mismatch_count = 0;
+	for (short_channel_id, channel) in scratch_graph.channels().unordered_iter() {
+		let source_channel = match source_graph.channel(*short_channel_id) {
+			Some(source_channel) => source_channel,
+			None => {
+				// closed since
+				mismatch_count += 1;
+				continue;
+			}
+		};
+		if (channel.node_one, channel.node_two) != (source_channel.node_one, source_channel.node_two) {
+			return Err(format!("channel {} is announced between different nodes than in the graph", short_channel_id));
+		}
+		let directions = [(&channel.one_to_two, &source_channel.one_to_two), (&channel.two_to_one, &source_channel.two_to_one)];
+		if directions.iter().any(|(info, source_info)| info.as_ref().map(policy) != source_info.as_ref().map(policy)) {
+			mismatch_count += 1;
+		}
+	}
+	if mismatch_count as f64 > channel_count as f64 * config::SNAPSHOT_VERIFICATION_MAX_MISMATCH_RATIO {
+		return Err(format!("the policies of {} of its {} channels differ from the graph's", mismatch_count, channel_count));
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use bitcoin::blockdata::constants::ChainHash;
+	use bitcoin::Network;
+	use lightning::routing::gossip::NetworkGraph;
+	use std::sync::Arc;
+
+	use crate::serialize_empty_blob;
+	use crate::types::tests::TestLogger;
+	use crate::validation::validate_snapshot;
+
+	#[test]
+	fn test_snapshot_validation() {
+		let logger = Arc::new(TestLogger::with_id("validation".to_string()));
+		let network_graph = NetworkGraph::new(Network::Bitcoin, logger.clone());
+		let snapshot = serialize_empty_blob(ChainHash::using_genesis_block(Network::Bitcoin), 1_700_000_000, 2);
+		assert!(validate_snapshot(&snapshot, 0, true, &network_graph, logger.clone()).is_ok());
+		// the channels announced must all be applied
+		assert!(validate_snapshot(&snapshot, 1, true, &network_graph, logger.clone()).is_err());
+		assert!(validate_snapshot(&snapshot[..snapshot.len() - 1], 0, false, &network_graph, logger.clone()).is_err());
+
+		let testnet_snapshot = serialize_empty_blob(ChainHash::using_genesis_block(Network::Testnet), 1_700_000_000, 2);
+		assert!(validate_snapshot(&testnet_snapshot, 0, false, &network_graph, logger.clone()).is_err());
+	}
+}
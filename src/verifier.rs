@@ -1,45 +1,191 @@
 use std::collections::HashMap;
 use std::io::ErrorKind;
 use std::ops::Deref;
+use std::str::FromStr;
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use async_trait::async_trait;
 use bitcoin::blockdata::constants::ChainHash;
-use bitcoin::{BlockHash, TxOut};
+use bitcoin::secp256k1::PublicKey;
+use bitcoin::{BlockHash, Network, OutPoint, ScriptBuf, Transaction, TxOut, Txid, VarInt};
+use bitcoin::consensus::encode::{deserialize, deserialize_partial};
 use bitcoin::blockdata::block::Block;
+use bitcoin::bip158::BlockFilter;
 use bitcoin::hashes::Hash;
-use lightning::log_error;
+use lightning::{log_error, log_gossip, log_info, log_warn};
+use lightning::ln::chan_utils::make_funding_redeemscript;
+use lightning::ln::msgs::UnsignedChannelAnnouncement;
 use lightning::routing::gossip::{NetworkGraph, P2PGossipSync};
 use lightning::routing::utxo::{UtxoFuture, UtxoLookup, UtxoResult, UtxoLookupError};
 use lightning::util::logger::Logger;
-use lightning_block_sync::{BlockData, BlockSource};
-use lightning_block_sync::http::BinaryResponse;
+use lightning_block_sync::BlockHeaderData;
+use lightning_block_sync::http::{BinaryResponse, HttpEndpoint, JsonResponse};
 use lightning_block_sync::rest::RestClient;
+use tokio::sync::{Notify, Semaphore};
 
+use crate::chain::{self, ChainSource, ChainTip};
 use crate::config;
+use crate::metrics::{FundingScriptMismatch, Metrics};
 use crate::types::GossipPeerManager;
 
+/// The funding amounts of the channels whose funding outputs have been looked up, mapping from SCID
+/// to funding satoshis
+pub(crate) type FundingAmountCache = Arc<Mutex<HashMap<u64, u64>>>;
+
+/// The funding amount of a channel in satoshis, if its funding output has been looked up
+pub(crate) fn cached_funding_value(funding_amounts: &FundingAmountCache, scid: u64) -> Option<u64> {
+	funding_amounts.lock().unwrap().get(&scid).copied()
+}
+
+/// Cache the funding amount of a channel. Should the cache be bounded and full, a quarter of the
+/// cached amounts is forgotten first, which are looked up anew should their channels need them.
+pub(crate) fn cache_funding_value(funding_amounts: &FundingAmountCache, scid: u64, funding_sats: u64, capacity: Option<usize>) {
+	let mut funding_amounts = funding_amounts.lock().unwrap();
+	if let Some(capacity) = capacity {
+		if funding_amounts.len() >= capacity && !funding_amounts.contains_key(&scid) {
+			let mut evicted_count = 0;
+			funding_amounts.retain(|_, _| {
+				evicted_count += 1;
+				evicted_count > (capacity + 3) / 4
+			});
+		}
+	}
+	funding_amounts.insert(scid, funding_sats);
+}
+
+/// The UTXO lookups waiting for a batch lookup of their block to start, mapping from block height
+/// to the SCIDs and futures of the lookups
+type QueuedLookups = Arc<Mutex<HashMap<u32, Vec<(u64, UtxoFuture)>>>>;
+
+/// Checks the funding outputs looked up for channel announcements against the announced bitcoin
+/// keys, which LDK would otherwise reject mismatching announcements for silently, and reports the
+/// mismatches along with the peers that sent them.
+struct FundingScriptChecker {
+	/// The funding output script each channel announcement pending verification implies, along with
+	/// the peer it was received from
+	announced_scripts: Mutex<HashMap<u64, (ScriptBuf, Option<PublicKey>)>>,
+	/// The funding outputs of the channels that were announced with mismatching keys, such that
+	/// repeated announcements don't each require a lookup
+	mismatched_outputs: Mutex<HashMap<u64, TxOut>>,
+	metrics: Arc<Metrics>,
+}
+
+impl FundingScriptChecker {
+	fn new(metrics: Arc<Metrics>) -> Self {
+		Self { announced_scripts: Mutex::new(HashMap::new()), mismatched_outputs: Mutex::new(HashMap::new()), metrics }
+	}
+
+	fn expect(&self, announcement: &UnsignedChannelAnnouncement, origin: Option<PublicKey>) {
+		let script = match announced_funding_script(announcement) {
+			Some(script) => script,
+			None => return,
+		};
+		let mut announced_scripts = self.announced_scripts.lock().unwrap();
+		// announcements failing other checks are never looked up, so start over rather than
+		// accumulating them indefinitely
+		if announced_scripts.len() >= config::MAX_PENDING_ANNOUNCEMENTS {
+			announced_scripts.clear();
+		}
+		announced_scripts.insert(announcement.short_channel_id, (script, origin));
+	}
+
+	fn cached_output(&self, short_channel_id: u64) -> Option<TxOut> {
+		self.mismatched_outputs.lock().unwrap().get(&short_channel_id).cloned()
+	}
+
+	/// Report the pending announcement of a channel if its keys don't match the funding output
+	fn check<L: Deref>(&self, short_channel_id: u64, funding_output: &TxOut, logger: &L) where L::Target: Logger {
+		let (announced_script, peer) = match self.announced_scripts.lock().unwrap().remove(&short_channel_id) {
+			Some(announced) => announced,
+			None => return,
+		};
+		if announced_script == funding_output.script_pubkey {
+			return;
+		}
+		match peer {
+			Some(peer) => log_warn!(logger, "Rejecting announcement for channel {} from {}, whose bitcoin keys don't match the funding output", short_channel_id, peer),
+			None => log_warn!(logger, "Rejecting announcement for channel {}, whose bitcoin keys don't match the funding output", short_channel_id),
+		}
+		{
+			let mut mismatched_outputs = self.mismatched_outputs.lock().unwrap();
+			if mismatched_outputs.len() >= config::MAX_PENDING_ANNOUNCEMENTS {
+				mismatched_outputs.clear();
+			}
+			mismatched_outputs.insert(short_channel_id, funding_output.clone());
+		}
+		self.metrics.record_funding_script_mismatch(FundingScriptMismatch {
+			short_channel_id,
+			peer,
+			announced_script,
+			funding_script: funding_output.script_pubkey.clone(),
+			detected_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+		});
+	}
+}
+
+/// The funding output script a channel announcement's bitcoin keys imply, unless they're invalid,
+/// in which case LDK rejects the announcement before looking it up
+fn announced_funding_script(announcement: &UnsignedChannelAnnouncement) -> Option<ScriptBuf> {
+	let bitcoin_key_1 = announcement.bitcoin_key_1.as_pubkey().ok()?;
+	let bitcoin_key_2 = announcement.bitcoin_key_2.as_pubkey().ok()?;
+	Some(make_funding_redeemscript(&bitcoin_key_1, &bitcoin_key_2).to_p2wsh())
+}
+
 pub(crate) struct ChainVerifier<L: Deref + Clone + Send + Sync + 'static> where L::Target: Logger {
-	rest_client: Arc<RestClient>,
+	chain_source: Arc<dyn ChainSource>,
 	graph: Arc<NetworkGraph<L>>,
 	outbound_gossiper: Arc<P2PGossipSync<Arc<NetworkGraph<L>>, Arc<Self>, L>>,
 	peer_handler: Mutex<Option<GossipPeerManager<L>>>,
-	/// A cache on the funding amounts for each channel that we've looked up, mapping from SCID to
-	/// funding satoshis.
-	channel_funding_amounts: Arc<Mutex<HashMap<u64, u64>>>,
+	/// A cache on the funding amounts for each channel that we've looked up
+	channel_funding_amounts: FundingAmountCache,
+	funding_script_checker: Arc<FundingScriptChecker>,
+	/// Bounds the number of blocks UTXO lookups hit bitcoind for concurrently. Tokio's semaphore is
+	/// fair, so queued lookups are resolved in the order in which they started waiting.
+	utxo_lookup_limiter: Arc<Semaphore>,
+	queued_lookups: QueuedLookups,
+	/// The chain hash of the network we're configured to operate on
+	chain_hash: ChainHash,
+	/// The number of channel announcements rejected because they were for a different chain
+	mismatched_chain_announcements: AtomicU64,
+	/// The number of UTXO lookups that have yet to be resolved, and a notification of each
+	/// resolution
+	pending_lookups: Arc<(AtomicUsize, Notify)>,
 	logger: L
 }
 
 struct RestBinaryResponse(Vec<u8>);
 
+/// A transaction as served by REST `tx`
+struct RestTransaction(Transaction);
+
+/// A REST `blockfilter` response, which is a block's filter along with the block's hash
+struct RestBlockFilter {
+	block_hash: BlockHash,
+	filter: BlockFilter,
+}
+
+/// The subset of a REST `getutxos` response we care about when querying a single outpoint
+struct RestUtxoStatus {
+	is_unspent: bool,
+}
+
 impl<L: Deref + Clone + Send + Sync + 'static> ChainVerifier<L> where L::Target: Logger {
-	pub(crate) fn new(graph: Arc<NetworkGraph<L>>, outbound_gossiper: Arc<P2PGossipSync<Arc<NetworkGraph<L>>, Arc<Self>, L>>, logger: L) -> Self {
+	pub(crate) fn new(graph: Arc<NetworkGraph<L>>, outbound_gossiper: Arc<P2PGossipSync<Arc<NetworkGraph<L>>, Arc<Self>, L>>, chain_source: Arc<dyn ChainSource>, channel_funding_amounts: FundingAmountCache, metrics: Arc<Metrics>, logger: L) -> Self {
 		ChainVerifier {
-			rest_client: Arc::new(RestClient::new(config::bitcoin_rest_endpoint())),
+			chain_source,
 			outbound_gossiper,
-			graph,
 			peer_handler: Mutex::new(None),
-			channel_funding_amounts: Arc::new(Mutex::new(HashMap::new())),
+			channel_funding_amounts,
+			funding_script_checker: Arc::new(FundingScriptChecker::new(metrics)),
+			utxo_lookup_limiter: Arc::new(Semaphore::new(config::max_concurrent_utxo_lookups())),
+			queued_lookups: Arc::new(Mutex::new(HashMap::new())),
+			chain_hash: ChainHash::using_genesis_block(config::graph_network(&graph)),
+			mismatched_chain_announcements: AtomicU64::new(0),
+			pending_lookups: Arc::new((AtomicUsize::new(0), Notify::new())),
+			graph,
 			logger,
 		}
 	}
@@ -47,86 +193,543 @@ impl<L: Deref + Clone + Send + Sync + 'static> ChainVerifier<L> where L::Target:
 		*self.peer_handler.lock().unwrap() = Some(peer_handler);
 	}
 
+	/// Remember the keys of a channel announcement about to be verified, and the peer it was
+	/// received from, to report if they don't match the funding output. The chain source is told
+	/// about the script they imply, too.
+	pub(crate) fn expect_funding_script(&self, announcement: &UnsignedChannelAnnouncement, origin: Option<PublicKey>) {
+		self.funding_script_checker.expect(announcement, origin);
+		if let Some(script) = announced_funding_script(announcement) {
+			self.chain_source.expect_funding_script(announcement.short_channel_id, &script);
+		}
+	}
+
+	pub(crate) fn mismatched_chain_announcement_count(&self) -> u64 {
+		self.mismatched_chain_announcements.load(Ordering::Relaxed)
+	}
+
+	/// Wait until the UTXO lookups of all channel announcements received so far have been resolved
+	pub(crate) async fn wait_for_pending_lookups(&self) {
+		let (pending_lookup_count, lookup_resolved) = &*self.pending_lookups;
+		loop {
+			let resolution = lookup_resolved.notified();
+			tokio::pin!(resolution);
+			// register for the notification before checking, so as not to miss it
+			resolution.as_mut().enable();
+			if pending_lookup_count.load(Ordering::Acquire) == 0 {
+				return;
+			}
+			resolution.await;
+		}
+	}
+
 	pub(crate) fn get_cached_funding_value(&self, scid: u64) -> Option<u64> {
-		self.channel_funding_amounts.lock().unwrap().get(&scid).map(|v| *v)
+		cached_funding_value(&self.channel_funding_amounts, scid)
 	}
 
 	pub(crate) async fn retrieve_funding_value(&self, scid: u64) -> Result<u64, UtxoLookupError> {
-		Self::retrieve_cache_txo(Arc::clone(&self.rest_client), Some(Arc::clone(&self.channel_funding_amounts)), scid, self.logger.clone())
+		Self::retrieve_cache_txo(Arc::clone(&self.chain_source), Some(Arc::clone(&self.channel_funding_amounts)), scid, false, self.logger.clone())
 			.await.map(|txo| txo.value.to_sat())
 	}
 
-	pub(crate) async fn retrieve_txo(client: Arc<RestClient>, short_channel_id: u64, logger: L) -> Result<TxOut, UtxoLookupError> {
-		Self::retrieve_cache_txo(client, None, short_channel_id, logger).await
+	pub(crate) async fn retrieve_txo(chain_source: Arc<dyn ChainSource>, short_channel_id: u64, logger: L) -> Result<TxOut, UtxoLookupError> {
+		Self::retrieve_cache_txo(chain_source, None, short_channel_id, false, logger).await
 	}
 
-	async fn retrieve_cache_txo(client: Arc<RestClient>, channel_funding_amounts: Option<Arc<Mutex<HashMap<u64, u64>>>>, short_channel_id: u64, logger: L) -> Result<TxOut, UtxoLookupError> {
-		let block_height = (short_channel_id >> 5 * 8) as u32; // block height is most significant three bytes
-		let transaction_index = ((short_channel_id >> 2 * 8) & 0xffffff) as u32;
-		let output_index = (short_channel_id & 0xffff) as u16;
+	/// Look up the funding output of a channel and confirm that it's still unspent, regardless of
+	/// whether `RAPID_GOSSIP_SYNC_SERVER_VERIFY_UNSPENT` is enabled
+	pub(crate) async fn reverify_txo(chain_source: Arc<dyn ChainSource>, short_channel_id: u64, logger: L) -> Result<TxOut, UtxoLookupError> {
+		Self::retrieve_cache_txo(chain_source, None, short_channel_id, true, logger).await
+	}
 
-		let mut block = Self::retrieve_block(client, block_height, logger.clone()).await?;
-		if transaction_index as usize >= block.txdata.len() {
-			log_error!(logger, "Could't find transaction {} in block {}", transaction_index, block_height);
-			return Err(UtxoLookupError::UnknownTx);
-		}
-		let mut transaction = block.txdata.swap_remove(transaction_index as usize);
-		if output_index as usize >= transaction.output.len() {
-			log_error!(logger, "Could't find output {} in transaction {}", output_index, transaction.compute_txid());
-			return Err(UtxoLookupError::UnknownTx);
+	/// Look up the funding output of a channel, optionally also confirming that it's still unspent.
+	#[tracing::instrument(name = "utxo_lookup", skip_all, fields(short_channel_id = short_channel_id))]
+	async fn retrieve_cache_txo(chain_source: Arc<dyn ChainSource>, channel_funding_amounts: Option<FundingAmountCache>, short_channel_id: u64, verify_unspent: bool, logger: L) -> Result<TxOut, UtxoLookupError> {
+		let (block_height, transaction_index, output_index) = funding_position(short_channel_id);
+		let funding_output = chain_source.funding_output(block_height, transaction_index, output_index).await.map_err(|error| {
+			log_error!(logger, "Couldn't retrieve block {}: {}", block_height, error);
+			UtxoLookupError::UnknownChain
+		})?;
+		Self::check_funding_output(&*chain_source, channel_funding_amounts.as_ref(), short_channel_id, funding_output, verify_unspent, &logger).await
+	}
+
+	/// Look up the funding outputs of several channels funded in the block at `block_height` at
+	/// once, such that the block only needs to be retrieved a single time.
+	#[tracing::instrument(name = "block_utxo_lookup", skip_all, fields(block_height = block_height, lookup_count = short_channel_ids.len()))]
+	async fn retrieve_cache_block_txos(chain_source: Arc<dyn ChainSource>, channel_funding_amounts: FundingAmountCache, block_height: u32, short_channel_ids: &[u64], verify_unspent: bool, logger: L) -> Vec<Result<TxOut, UtxoLookupError>> {
+		let positions: Vec<(u32, u16)> = short_channel_ids.iter().map(|short_channel_id| {
+			let (_, transaction_index, output_index) = funding_position(*short_channel_id);
+			(transaction_index, output_index)
+		}).collect();
+		let funding_outputs = match chain_source.funding_outputs(block_height, &positions).await {
+			Ok(funding_outputs) if funding_outputs.len() == positions.len() => funding_outputs,
+			Ok(_) => {
+				log_error!(logger, "Chain source returned the wrong number of outputs for block {}", block_height);
+				return short_channel_ids.iter().map(|_| Err(UtxoLookupError::UnknownChain)).collect();
+			}
+			Err(error) => {
+				log_error!(logger, "Couldn't retrieve block {}: {}", block_height, error);
+				return short_channel_ids.iter().map(|_| Err(UtxoLookupError::UnknownChain)).collect();
+			}
+		};
+		let checks = short_channel_ids.iter().zip(funding_outputs).map(|(short_channel_id, funding_output)| {
+			Self::check_funding_output(&*chain_source, Some(&channel_funding_amounts), *short_channel_id, funding_output, verify_unspent, &logger)
+		});
+		futures::future::join_all(checks).await
+	}
+
+	/// Turn the output a channel's short channel id refers to into the result of its lookup,
+	/// optionally confirming that it's still unspent, and cache its funding amount.
+	async fn check_funding_output(chain_source: &dyn ChainSource, channel_funding_amounts: Option<&FundingAmountCache>, short_channel_id: u64, funding_output: Option<(Txid, TxOut)>, verify_unspent: bool, logger: &L) -> Result<TxOut, UtxoLookupError> {
+		let (block_height, transaction_index, output_index) = funding_position(short_channel_id);
+		let (txid, txo) = match funding_output {
+			Some(funding_output) => funding_output,
+			None => {
+				log_error!(logger, "Could't find output {} of transaction {} in block {}", output_index, transaction_index, block_height);
+				return Err(UtxoLookupError::UnknownTx);
+			}
+		};
+		if verify_unspent {
+			let is_unspent = chain_source.is_output_unspent(OutPoint::new(txid, output_index as u32)).await.map_err(|error| {
+				log_error!(logger, "Couldn't check whether output {} of transaction {} is unspent: {}", output_index, txid, error);
+				UtxoLookupError::UnknownChain
+			})?;
+			if !is_unspent {
+				log_gossip!(logger, "Rejecting channel {}, its funding output {}:{} has been spent", short_channel_id, txid, output_index);
+				return Err(UtxoLookupError::UnknownTx);
+			}
 		}
-		let txo = transaction.output.swap_remove(output_index as usize);
 		if let Some(channel_funding_amounts) = channel_funding_amounts {
-			channel_funding_amounts.lock().unwrap().insert(short_channel_id, txo.value.to_sat());
+			cache_funding_value(channel_funding_amounts, short_channel_id, txo.value.to_sat(), config::funding_amount_cache_capacity());
 		}
 		Ok(txo)
 	}
+}
 
-	async fn retrieve_block(client: Arc<RestClient>, block_height: u32, logger: L) -> Result<Block, UtxoLookupError> {
-		let uri = format!("blockhashbyheight/{}.bin", block_height);
-		let block_hash_result =
-			client.request_resource::<BinaryResponse, RestBinaryResponse>(&uri).await;
-		let block_hash: Vec<u8> = block_hash_result.map_err(|error| {
-			match error.kind() {
-				ErrorKind::InvalidData => {
-					// the response length was likely 0
-					log_error!(logger, "Could't find block hash at height {}: Invalid response! Please make sure the `-rest=1` flag is set.", block_height);
+/// The set of bitcoind REST endpoints UTXO lookups are served from.
+///
+/// Requests are distributed across all healthy endpoints in round-robin fashion. An endpoint that
+/// fails with a transient error is considered unhealthy, and requests fail over to the remaining
+/// endpoints until a periodic health check finds it reachable again. Should all endpoints be
+/// unhealthy, each of them is tried regardless.
+///
+/// Each endpoint keeps a bounded number of connections, and every request is subject to a connect
+/// and a request timeout, such that a hung bitcoind socket fails the request rather than stalling
+/// it indefinitely.
+pub(crate) struct RestClientPool {
+	endpoints: Vec<PooledRestClient>,
+	next_endpoint: AtomicUsize,
+	timeouts: RestTimeouts,
+}
+
+#[derive(Clone, Copy)]
+struct RestTimeouts {
+	connect: Duration,
+	request: Duration,
+	slow_request: Duration,
+}
+
+struct PooledRestClient {
+	host: String,
+	port: u16,
+	path: String,
+	description: String,
+	/// Clients whose connection is idle and may be reused
+	idle_clients: Mutex<Vec<RestClient>>,
+	/// Bounds the number of concurrent connections to the endpoint
+	connection_permits: Semaphore,
+	is_healthy: AtomicBool,
+}
+
+impl PooledRestClient {
+	fn new(endpoint: HttpEndpoint, max_connections: usize) -> Self {
+		let description = format!("{}:{}{}", endpoint.host(), endpoint.port(), endpoint.path());
+		Self {
+			host: endpoint.host().to_string(),
+			port: endpoint.port(),
+			path: endpoint.path().to_string(),
+			description,
+			idle_clients: Mutex::new(Vec::new()),
+			connection_permits: Semaphore::new(max_connections),
+			is_healthy: AtomicBool::new(true),
+		}
+	}
+
+	async fn connect(&self, connect_timeout: Duration) -> std::io::Result<RestClient> {
+		// the REST client connects synchronously once it's first used, so make sure the endpoint
+		// accepts connections beforehand rather than blocking the runtime on an unreachable one
+		match tokio::time::timeout(connect_timeout, tokio::net::TcpStream::connect((self.host.as_str(), self.port))).await {
+			Ok(Ok(_)) => {}
+			Ok(Err(error)) => return Err(error),
+			Err(_) => return Err(std::io::Error::new(ErrorKind::TimedOut, format!("connecting timed out after {:?}", connect_timeout))),
+		}
+		let endpoint = HttpEndpoint::for_host(self.host.clone()).with_port(self.port).with_path(self.path.clone());
+		Ok(RestClient::new(endpoint))
+	}
+
+	/// Request a resource once, over an idle connection if there is one.
+	async fn request_resource<F, T>(&self, resource_path: &str, timeouts: RestTimeouts) -> std::io::Result<T>
+		where F: TryFrom<Vec<u8>, Error = std::io::Error> + TryInto<T, Error = std::io::Error>
+	{
+		let _permit = self.connection_permits.acquire().await.unwrap();
+		let idle_client = self.idle_clients.lock().unwrap().pop();
+		let client = match idle_client {
+			Some(client) => client,
+			None => self.connect(timeouts.connect).await?,
+		};
+		match tokio::time::timeout(timeouts.request, client.request_resource::<F, T>(resource_path)).await {
+			Ok(Ok(response)) => {
+				self.idle_clients.lock().unwrap().push(client);
+				Ok(response)
+			}
+			// the client drops its connection upon errors, so it isn't worth keeping
+			Ok(Err(error)) => Err(error),
+			Err(_) => Err(std::io::Error::new(ErrorKind::TimedOut, format!("request timed out after {:?}", timeouts.request))),
+		}
+	}
+}
+
+impl RestClientPool {
+	pub(crate) fn new(endpoints: Vec<HttpEndpoint>) -> Self {
+		assert!(!endpoints.is_empty(), "At least one bitcoind REST endpoint must be configured");
+		let max_connections = config::bitcoin_rest_max_connections();
+		let endpoints = endpoints.into_iter().map(|endpoint| PooledRestClient::new(endpoint, max_connections)).collect();
+		let timeouts = RestTimeouts {
+			connect: config::bitcoin_rest_connect_timeout(),
+			request: config::bitcoin_rest_request_timeout(),
+			slow_request: config::bitcoin_rest_slow_request_threshold(),
+		};
+		Self { endpoints, next_endpoint: AtomicUsize::new(0), timeouts }
+	}
+
+	/// Create a pool and spawn a background task periodically probing its unhealthy endpoints.
+	pub(crate) fn with_health_checks<L: Deref + Send + Sync + 'static>(endpoints: Vec<HttpEndpoint>, logger: L) -> Arc<Self> where L::Target: Logger {
+		let pool = Arc::new(Self::new(endpoints));
+		if pool.endpoints.len() > 1 {
+			let pool_ref = Arc::downgrade(&pool);
+			tokio::spawn(async move {
+				let mut interval = tokio::time::interval(config::BITCOIN_REST_HEALTH_CHECK_INTERVAL);
+				loop {
+					interval.tick().await;
+					let pool = match pool_ref.upgrade() {
+						Some(pool) => pool,
+						None => break,
+					};
+					pool.check_health(&logger).await;
 				}
-				_ => {
-					log_error!(logger, "Could't find block hash at height {}: {}", block_height, error.to_string());
+			});
+		}
+		pool
+	}
+
+	async fn check_health<L: Deref>(&self, logger: &L) where L::Target: Logger {
+		for endpoint in self.endpoints.iter().filter(|e| !e.is_healthy.load(Ordering::Acquire)) {
+			// the genesis block hash is cheap to serve and available on any chain
+			if endpoint.request_resource::<BinaryResponse, RestBinaryResponse>("blockhashbyheight/0.bin", self.timeouts).await.is_ok() {
+				log_info!(logger, "bitcoind REST endpoint {} is reachable again", endpoint.description);
+				endpoint.is_healthy.store(true, Ordering::Release);
+			}
+		}
+	}
+
+	/// Check whether any endpoint is reachable right now, without retries or backoff.
+	pub(crate) async fn probe(&self) -> bool {
+		for endpoint in self.endpoints.iter() {
+			if endpoint.request_resource::<BinaryResponse, RestBinaryResponse>("blockhashbyheight/0.bin", self.timeouts).await.is_ok() {
+				return true;
+			}
+		}
+		false
+	}
+
+	/// Pick the next endpoint in round-robin order, skipping unhealthy ones where possible.
+	fn select_endpoint(&self) -> &PooledRestClient {
+		let start = self.next_endpoint.fetch_add(1, Ordering::AcqRel);
+		let endpoint_count = self.endpoints.len();
+		(0..endpoint_count)
+			.map(|offset| &self.endpoints[(start + offset) % endpoint_count])
+			.find(|e| e.is_healthy.load(Ordering::Acquire))
+			.unwrap_or(&self.endpoints[start % endpoint_count])
+	}
+
+	fn has_healthy_endpoint(&self) -> bool {
+		self.endpoints.iter().any(|e| e.is_healthy.load(Ordering::Acquire))
+	}
+
+	/// Request a resource, failing over to other endpoints and retrying with exponential backoff
+	/// for as long as the failure looks like bitcoind being temporarily unavailable rather than
+	/// the requested data not existing.
+	pub(crate) async fn request_resource<F, T, L: Deref>(&self, resource_path: &str, logger: &L) -> std::io::Result<T>
+		where F: TryFrom<Vec<u8>, Error = std::io::Error> + TryInto<T, Error = std::io::Error>, L::Target: Logger
+	{
+		let max_retries = config::bitcoin_rest_retries();
+		let base_delay = config::bitcoin_rest_retry_base_delay();
+		let mut attempt = 0;
+		let mut failovers = 0;
+		loop {
+			let endpoint = self.select_endpoint();
+			let started_at = Instant::now();
+			let result = endpoint.request_resource::<F, T>(resource_path, self.timeouts).await;
+			let elapsed = started_at.elapsed();
+			if elapsed >= self.timeouts.slow_request {
+				log_warn!(logger, "Request for {} from bitcoind REST endpoint {} took {:?}", resource_path, endpoint.description, elapsed);
+			}
+			match result {
+				Ok(response) => return Ok(response),
+				Err(error) if is_transient_error(&error) => {
+					if self.endpoints.len() > 1 && endpoint.is_healthy.swap(false, Ordering::AcqRel) {
+						log_warn!(logger, "Marking bitcoind REST endpoint {} as unhealthy: {}", endpoint.description, error);
+					}
+					if failovers + 1 < self.endpoints.len() && self.has_healthy_endpoint() {
+						failovers += 1;
+						log_warn!(logger, "Failing over request for {} away from {}", resource_path, endpoint.description);
+						continue;
+					}
+					if attempt >= max_retries {
+						return Err(error);
+					}
+					let delay = retry_delay(base_delay, attempt);
+					log_warn!(logger, "Transient error requesting {} (attempt {}/{}), retrying in {:?}: {}", resource_path, attempt + 1, max_retries + 1, delay, error);
+					tokio::time::sleep(delay).await;
+					attempt += 1;
+					failovers = 0;
 				}
+				Err(error) => return Err(error),
+			}
+		}
+	}
+}
+
+/// The default [`ChainSource`], backed by the configured bitcoind REST endpoints
+pub(crate) struct RestChainSource<L: Deref + Send + Sync> where L::Target: Logger {
+	client: Arc<RestClientPool>,
+	/// Whether to retrieve funding transactions individually rather than with their whole block,
+	/// which requires bitcoind to run with `-txindex`
+	use_txindex: bool,
+	logger: L,
+}
+
+impl<L: Deref + Clone + Send + Sync + 'static> RestChainSource<L> where L::Target: Logger {
+	pub(crate) fn new(network: Network, logger: L) -> Self {
+		Self::with_endpoints(config::bitcoin_rest_endpoints(network), logger)
+	}
+
+	pub(crate) fn with_endpoints(endpoints: Vec<HttpEndpoint>, logger: L) -> Self {
+		Self { client: RestClientPool::with_health_checks(endpoints, logger.clone()), use_txindex: config::bitcoin_rest_txindex(), logger }
+	}
+}
+
+impl<L: Deref + Send + Sync> RestChainSource<L> where L::Target: Logger {
+	pub(crate) async fn block_hash(&self, height: u32) -> std::io::Result<BlockHash> {
+		let uri = format!("blockhashbyheight/{}.bin", height);
+		let block_hash = self.client.request_resource::<BinaryResponse, RestBinaryResponse, _>(&uri, &self.logger).await.map_err(|error| {
+			match error.kind() {
+				// the response length was likely 0
+				ErrorKind::InvalidData => std::io::Error::new(ErrorKind::InvalidData, "invalid block hash response, please make sure the `-rest=1` flag is set"),
+				_ => error,
 			}
-			UtxoLookupError::UnknownChain
 		})?.0;
-		let block_hash = BlockHash::from_slice(&block_hash).unwrap();
-
-		let block_result = client.get_block(&block_hash).await;
-		match block_result {
-			Ok(BlockData::FullBlock(block)) => {
-				Ok(block)
-			},
-			Ok(_) => unreachable!(),
-			Err(error) => {
-				log_error!(logger, "Couldn't retrieve block {}: {:?} ({})", block_height, error, block_hash);
-				Err(UtxoLookupError::UnknownChain)
+		BlockHash::from_slice(&block_hash).map_err(|_| std::io::Error::new(ErrorKind::InvalidData, "invalid block hash"))
+	}
+
+	pub(crate) async fn block(&self, block_hash: &BlockHash) -> std::io::Result<Block> {
+		let uri = format!("block/{}.bin", block_hash);
+		self.client.request_resource::<BinaryResponse, Block, _>(&uri, &self.logger).await
+	}
+
+	/// The ids of a block's transactions, in order, without retrieving the transactions themselves
+	async fn block_txids(&self, block_hash: &BlockHash) -> std::io::Result<Vec<Txid>> {
+		let uri = format!("block/notxdetails/{}.json", block_hash);
+		let block = self.client.request_resource::<JsonResponse, serde_json::Value, _>(&uri, &self.logger).await?;
+		parse_block_txids(&block)
+	}
+
+	/// A transaction by its id, as served if bitcoind runs with `-txindex`
+	async fn transaction(&self, txid: &Txid) -> std::io::Result<Transaction> {
+		let uri = format!("tx/{}.bin", txid);
+		let transaction = self.client.request_resource::<BinaryResponse, RestTransaction, _>(&uri, &self.logger).await.map_err(|error| {
+			match is_transient_error(&error) {
+				true => error,
+				false => std::io::Error::new(error.kind(), format!("{}, please make sure the `-txindex=1` flag is set", error)),
 			}
+		})?.0;
+		if transaction.compute_txid() != *txid {
+			return Err(std::io::Error::new(ErrorKind::InvalidData, "transaction with a different id"));
 		}
+		Ok(transaction)
+	}
+
+	/// The basic compact filter of a block, as served if bitcoind runs with `-blockfilterindex`
+	pub(crate) async fn block_filter(&self, block_hash: &BlockHash) -> std::io::Result<BlockFilter> {
+		let uri = format!("blockfilter/basic/{}.bin", block_hash);
+		let filter = self.client.request_resource::<BinaryResponse, RestBlockFilter, _>(&uri, &self.logger).await.map_err(|error| {
+			match error.kind() {
+				ErrorKind::InvalidData => std::io::Error::new(ErrorKind::InvalidData, "invalid block filter response, please make sure the `-blockfilterindex=1` flag is set"),
+				_ => error,
+			}
+		})?;
+		if filter.block_hash != *block_hash {
+			return Err(std::io::Error::new(ErrorKind::InvalidData, "block filter for a different block"));
+		}
+		Ok(filter.filter)
 	}
 }
 
+#[async_trait]
+impl<L: Deref + Send + Sync> ChainSource for RestChainSource<L> where L::Target: Logger {
+	async fn block_at_height(&self, height: u32) -> std::io::Result<Block> {
+		let block_hash = self.block_hash(height).await?;
+		self.block(&block_hash).await
+	}
+
+	async fn funding_output(&self, block_height: u32, transaction_index: u32, output_index: u16) -> std::io::Result<Option<(Txid, TxOut)>> {
+		Ok(self.funding_outputs(block_height, &[(transaction_index, output_index)]).await?.pop().flatten())
+	}
+
+	async fn funding_outputs(&self, block_height: u32, positions: &[(u32, u16)]) -> std::io::Result<Vec<Option<(Txid, TxOut)>>> {
+		if !self.use_txindex {
+			let block = self.block_at_height(block_height).await?;
+			return Ok(chain::block_outputs(&block, positions));
+		}
+		// only the funding transactions are retrieved, rather than the whole multi-megabyte block
+		let txids = self.block_txids(&self.block_hash(block_height).await?).await?;
+		let lookups = positions.iter().map(|(transaction_index, output_index)| {
+			let txid = txids.get(*transaction_index as usize).copied();
+			async move {
+				let txid = match txid {
+					Some(txid) => txid,
+					None => return Ok(None),
+				};
+				let mut transaction = self.transaction(&txid).await?;
+				if *output_index as usize >= transaction.output.len() {
+					return Ok(None);
+				}
+				Ok(Some((txid, transaction.output.swap_remove(*output_index as usize))))
+			}
+		});
+		futures::future::try_join_all(lookups).await
+	}
+
+	async fn is_output_unspent(&self, outpoint: OutPoint) -> std::io::Result<bool> {
+		let uri = format!("getutxos/checkmempool/{}-{}.bin", outpoint.txid, outpoint.vout);
+		let status = self.client.request_resource::<BinaryResponse, RestUtxoStatus, _>(&uri, &self.logger).await?;
+		Ok(status.is_unspent)
+	}
+
+	async fn tip(&self) -> std::io::Result<ChainTip> {
+		let chain_info: serde_json::Value = self.client.request_resource::<JsonResponse, serde_json::Value, _>("chaininfo.json", &self.logger).await?;
+		let invalid_chain_info = || std::io::Error::new(ErrorKind::InvalidData, "invalid chain info response");
+		let best_header_height = chain_info["headers"].as_u64().map(|height| height as u32);
+		let best_block_hash = chain_info["bestblockhash"].as_str().ok_or_else(invalid_chain_info)?;
+		let uri = format!("headers/1/{}.json", best_block_hash);
+		let tip = self.client.request_resource::<JsonResponse, BlockHeaderData, _>(&uri, &self.logger).await?;
+		Ok(ChainTip { height: tip.height, time: tip.header.time, best_header_height })
+	}
+
+	async fn is_reachable(&self) -> bool {
+		self.client.probe().await
+	}
+}
+
+/// Determine whether a failed REST request may succeed if retried.
+///
+/// Connection-level failures and 5xx responses (e. g. bitcoind still loading its block index after
+/// a restart) are transient. Unparseable responses and other HTTP errors (most notably a 404 for a
+/// block height beyond the tip) indicate that the data genuinely isn't there.
+fn is_transient_error(error: &std::io::Error) -> bool {
+	match error.kind() {
+		ErrorKind::InvalidData | ErrorKind::InvalidInput => false,
+		ErrorKind::Other => {
+			// lightning-block-sync doesn't expose its HTTP error type, but includes the status
+			// code in the error's description
+			let description = error.to_string();
+			match description.strip_prefix("status_code: ") {
+				Some(status) => status.starts_with('5'),
+				None => true,
+			}
+		}
+		_ => true,
+	}
+}
+
+/// The transaction ids listed by a REST `block/notxdetails` response
+fn parse_block_txids(block: &serde_json::Value) -> std::io::Result<Vec<Txid>> {
+	let invalid_block = || std::io::Error::new(ErrorKind::InvalidData, "invalid block response");
+	block["tx"].as_array().ok_or_else(invalid_block)?.iter()
+		.map(|txid| txid.as_str().and_then(|txid| Txid::from_str(txid).ok()).ok_or_else(invalid_block))
+		.collect()
+}
+
+/// The height of the block a short channel id refers to, and the indices of the transaction within
+/// it and the output within that
+fn funding_position(short_channel_id: u64) -> (u32, u32, u16) {
+	let block_height = (short_channel_id >> 40) as u32; // block height is most significant three bytes
+	let transaction_index = ((short_channel_id >> 16) & 0xffffff) as u32;
+	let output_index = (short_channel_id & 0xffff) as u16;
+	(block_height, transaction_index, output_index)
+}
+
+/// The delay before the given (zero-indexed) retry, doubling with each attempt up to a fixed cap.
+fn retry_delay(base_delay: Duration, attempt: u32) -> Duration {
+	base_delay.saturating_mul(1u32.checked_shl(attempt).unwrap_or(u32::MAX)).min(config::MAX_BITCOIN_REST_RETRY_DELAY)
+}
+
 impl<L: Deref + Clone + Send + Sync + 'static> UtxoLookup for ChainVerifier<L> where L::Target: Logger {
-	fn get_utxo(&self, _genesis_hash: &ChainHash, short_channel_id: u64) -> UtxoResult {
+	fn get_utxo(&self, genesis_hash: &ChainHash, short_channel_id: u64) -> UtxoResult {
+		// LDK already checks announcements against the graph's chain hash, but the graph may have
+		// been restored from a cache written while operating on a different network
+		if *genesis_hash != self.chain_hash {
+			let rejection_count = self.mismatched_chain_announcements.fetch_add(1, Ordering::Relaxed) + 1;
+			log_gossip!(self.logger, "Rejecting announcement for channel {} on foreign chain {} ({} rejected so far)", short_channel_id, genesis_hash, rejection_count);
+			return UtxoResult::Sync(Err(UtxoLookupError::UnknownChain));
+		}
+
+		if let Some(funding_output) = self.funding_script_checker.cached_output(short_channel_id) {
+			self.funding_script_checker.check(short_channel_id, &funding_output, &self.logger);
+			return UtxoResult::Sync(Ok(funding_output));
+		}
+
+		let (block_height, _, _) = funding_position(short_channel_id);
+		self.chain_source.expect_lookup(block_height);
 		let res = UtxoFuture::new();
-		let fut = res.clone();
+		self.pending_lookups.0.fetch_add(1, Ordering::AcqRel);
+		let starts_batch = {
+			let mut queued_lookups = self.queued_lookups.lock().unwrap();
+			let block_lookups = queued_lookups.entry(block_height).or_default();
+			block_lookups.push((short_channel_id, res.clone()));
+			block_lookups.len() == 1
+		};
+		if !starts_batch {
+			// a batch for the block is already waiting to start, and will include this lookup
+			return UtxoResult::Async(res);
+		}
+
 		let graph_ref = Arc::clone(&self.graph);
-		let client_ref = Arc::clone(&self.rest_client);
+		let chain_source_ref = Arc::clone(&self.chain_source);
 		let gossip_ref = Arc::clone(&self.outbound_gossiper);
 		let channel_funding_amounts_cache_ref = Arc::clone(&self.channel_funding_amounts);
 		let pm_ref = self.peer_handler.lock().unwrap().clone();
 		let logger_ref = self.logger.clone();
+		let limiter_ref = Arc::clone(&self.utxo_lookup_limiter);
+		let queued_lookups_ref = Arc::clone(&self.queued_lookups);
+		let verify_unspent = config::verify_unspent_funding_outputs();
+		let pending_lookups = Arc::clone(&self.pending_lookups);
+		let funding_script_checker = Arc::clone(&self.funding_script_checker);
 		tokio::spawn(async move {
-			let res = Self::retrieve_cache_txo(client_ref, Some(channel_funding_amounts_cache_ref), short_channel_id, logger_ref).await;
-			fut.resolve(&*graph_ref, &*gossip_ref, res);
+			let permit = limiter_ref.acquire_owned().await.unwrap();
+			// lookups of channels in the same block queued up while waiting are resolved along
+			// with this one, while later ones start a batch of their own
+			let block_lookups = queued_lookups_ref.lock().unwrap().remove(&block_height).unwrap_or_default();
+			let short_channel_ids: Vec<u64> = block_lookups.iter().map(|(short_channel_id, _)| *short_channel_id).collect();
+			let results = Self::retrieve_cache_block_txos(chain_source_ref, channel_funding_amounts_cache_ref, block_height, &short_channel_ids, verify_unspent, logger_ref.clone()).await;
+			std::mem::drop(permit);
+			for ((short_channel_id, fut), res) in block_lookups.into_iter().zip(results) {
+				if let Ok(funding_output) = &res {
+					funding_script_checker.check(short_channel_id, funding_output, &logger_ref);
+				}
+				fut.resolve(&*graph_ref, &*gossip_ref, res);
+			}
+			pending_lookups.0.fetch_sub(short_channel_ids.len(), Ordering::AcqRel);
+			pending_lookups.1.notify_waiters();
 			if let Some(pm) = pm_ref { pm.process_events(); }
 		});
 		UtxoResult::Async(res)
@@ -140,3 +743,238 @@ impl TryInto<RestBinaryResponse> for BinaryResponse {
 		Ok(RestBinaryResponse(self.0))
 	}
 }
+
+impl TryInto<RestTransaction> for BinaryResponse {
+	type Error = std::io::Error;
+
+	fn try_into(self) -> Result<RestTransaction, Self::Error> {
+		deserialize::<Transaction>(&self.0).map(RestTransaction).map_err(|_| std::io::Error::new(ErrorKind::InvalidData, "invalid transaction response"))
+	}
+}
+
+impl TryInto<RestBlockFilter> for BinaryResponse {
+	type Error = std::io::Error;
+
+	fn try_into(self) -> Result<RestBlockFilter, Self::Error> {
+		// The response consists of the filter type (1 byte), the block hash (32 bytes), and the
+		// length-prefixed filter
+		let invalid_data = || std::io::Error::new(ErrorKind::InvalidData, "invalid blockfilter response");
+		let response = self.0;
+		if response.len() < 33 || response[0] != 0 {
+			return Err(invalid_data());
+		}
+		let block_hash = BlockHash::from_slice(&response[1..33]).map_err(|_| invalid_data())?;
+		let (filter_length, prefix_length) = deserialize_partial::<VarInt>(&response[33..]).map_err(|_| invalid_data())?;
+		let filter = response.get(33 + prefix_length..).filter(|filter| filter.len() as u64 == filter_length.0).ok_or_else(invalid_data)?;
+		Ok(RestBlockFilter { block_hash, filter: BlockFilter::new(filter) })
+	}
+}
+
+impl TryInto<RestUtxoStatus> for BinaryResponse {
+	type Error = std::io::Error;
+
+	fn try_into(self) -> Result<RestUtxoStatus, Self::Error> {
+		// The response consists of the chain tip's height (4 bytes) and hash (32 bytes), followed
+		// by a length-prefixed bitmap flagging which of the requested outpoints are unspent, and
+		// finally the unspent outputs themselves.
+		let invalid_data = || std::io::Error::new(ErrorKind::InvalidData, "invalid getutxos response");
+		let bitmap_data = self.0.get(36..).ok_or_else(invalid_data)?;
+		let (bitmap_length, prefix_length) = deserialize_partial::<VarInt>(bitmap_data).map_err(|_| invalid_data())?;
+		if bitmap_length.0 == 0 {
+			return Err(invalid_data());
+		}
+		let first_bitmap_byte = bitmap_data.get(prefix_length).ok_or_else(invalid_data)?;
+		Ok(RestUtxoStatus { is_unspent: first_bitmap_byte & 1 == 1 })
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use bitcoin::secp256k1::{Secp256k1, SecretKey};
+	use lightning::routing::gossip::NodeId;
+	use lightning::types::features::ChannelFeatures;
+
+	use crate::chain::mock::MockChainSource;
+	use crate::types::tests::TestLogger;
+
+	#[test]
+	fn test_transient_error_classification() {
+		assert!(is_transient_error(&std::io::Error::new(ErrorKind::ConnectionRefused, "refused")));
+		assert!(is_transient_error(&std::io::Error::new(ErrorKind::UnexpectedEof, "no status line")));
+		assert!(is_transient_error(&std::io::Error::new(ErrorKind::Other, "status_code: 503, contents: Loading block index…")));
+		assert!(!is_transient_error(&std::io::Error::new(ErrorKind::Other, "status_code: 404, contents: Block height out of range")));
+		assert!(!is_transient_error(&std::io::Error::new(ErrorKind::InvalidData, "invalid block data")));
+	}
+
+	#[test]
+	fn test_utxo_status_parsing() {
+		let mut response = vec![0u8; 36];
+		response.extend_from_slice(&[1, 0b1]);
+		let status: RestUtxoStatus = BinaryResponse(response).try_into().unwrap();
+		assert!(status.is_unspent);
+
+		let mut response = vec![0u8; 36];
+		response.extend_from_slice(&[1, 0b0]);
+		let status: RestUtxoStatus = BinaryResponse(response).try_into().unwrap();
+		assert!(!status.is_unspent);
+
+		let truncated: Result<RestUtxoStatus, _> = BinaryResponse(vec![0u8; 37]).try_into();
+		assert!(truncated.is_err());
+	}
+
+	#[test]
+	fn test_funding_script_check() {
+		let secp_ctx = Secp256k1::new();
+		let key = |byte: u8| PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[byte; 32]).unwrap());
+		let announcement = |short_channel_id: u64, bitcoin_key_2: PublicKey| UnsignedChannelAnnouncement {
+			features: ChannelFeatures::empty(),
+			chain_hash: ChainHash::using_genesis_block(Network::Bitcoin),
+			short_channel_id,
+			node_id_1: NodeId::from_pubkey(&key(1)),
+			node_id_2: NodeId::from_pubkey(&key(2)),
+			bitcoin_key_1: NodeId::from_pubkey(&key(3)),
+			bitcoin_key_2: NodeId::from_pubkey(&bitcoin_key_2),
+			excess_data: Vec::new(),
+		};
+		let funding_output = TxOut {
+			value: bitcoin::Amount::from_sat(100_000),
+			script_pubkey: make_funding_redeemscript(&key(3), &key(4)).to_p2wsh(),
+		};
+		let logger = Arc::new(TestLogger::with_id("funding_script_check".to_string()));
+		let metrics = Arc::new(Metrics::new());
+		let checker = FundingScriptChecker::new(Arc::clone(&metrics));
+
+		checker.expect(&announcement(42, key(4)), Some(key(5)));
+		checker.check(42, &funding_output, &logger);
+		assert_eq!(metrics.funding_script_mismatch_count(), 0);
+		assert!(checker.cached_output(42).is_none());
+
+		checker.expect(&announcement(43, key(6)), Some(key(5)));
+		checker.check(43, &funding_output, &logger);
+		assert_eq!(metrics.funding_script_mismatch_count(), 1);
+		assert_eq!(checker.cached_output(43), Some(funding_output.clone()));
+		let mismatches = metrics.recent_funding_script_mismatches();
+		assert_eq!(mismatches.len(), 1);
+		assert_eq!(mismatches[0].peer, Some(key(5)));
+		assert_eq!(mismatches[0].funding_script, funding_output.script_pubkey);
+		assert_eq!(metrics.peer_gossip_stats()[0].1.funding_script_mismatches, 1);
+
+		// lookups without a pending announcement aren't checked
+		checker.check(44, &funding_output, &logger);
+		assert_eq!(metrics.funding_script_mismatch_count(), 1);
+	}
+
+	#[tokio::test]
+	async fn test_hung_endpoint_times_out() {
+		// accepts connections, but never responds
+		let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let port = listener.local_addr().unwrap().port();
+		tokio::spawn(async move {
+			let mut connections = Vec::new();
+			while let Ok((connection, _)) = listener.accept().await {
+				connections.push(connection);
+			}
+		});
+
+		let endpoint = PooledRestClient::new(HttpEndpoint::for_host("127.0.0.1".to_string()).with_port(port), 1);
+		let timeouts = RestTimeouts { connect: Duration::from_secs(1), request: Duration::from_millis(200), slow_request: Duration::from_secs(1) };
+		let error = match endpoint.request_resource::<BinaryResponse, RestBinaryResponse>("blockhashbyheight/0.bin", timeouts).await {
+			Ok(_) => panic!("the request should have timed out"),
+			Err(error) => error,
+		};
+		assert_eq!(error.kind(), ErrorKind::TimedOut);
+		assert!(is_transient_error(&error));
+		// the hung connection isn't reused, and its permit has been released
+		assert!(endpoint.idle_clients.lock().unwrap().is_empty());
+		assert_eq!(endpoint.connection_permits.available_permits(), 1);
+	}
+
+	#[tokio::test]
+	async fn test_block_lookup_batch() {
+		let chain_source = MockChainSource::new();
+		let short_channel_id = |transaction_index: u64, output_index: u64| 870000 << 40 | transaction_index << 16 | output_index;
+		chain_source.add_funding_output(short_channel_id(3, 1), TxOut { value: bitcoin::Amount::from_sat(250_000), script_pubkey: ScriptBuf::new() });
+		chain_source.add_funding_output(short_channel_id(5, 0), TxOut { value: bitcoin::Amount::from_sat(500_000), script_pubkey: ScriptBuf::new() });
+		let channel_funding_amounts: FundingAmountCache = Arc::new(Mutex::new(HashMap::new()));
+		let logger = Arc::new(TestLogger::with_id("block_lookup_batch".to_string()));
+
+		let short_channel_ids = [short_channel_id(5, 0), short_channel_id(9, 0), short_channel_id(3, 1)];
+		let results = ChainVerifier::retrieve_cache_block_txos(Arc::new(chain_source), Arc::clone(&channel_funding_amounts), 870000, &short_channel_ids, false, logger).await;
+		assert_eq!(results.len(), 3);
+		assert_eq!(results[0].as_ref().unwrap().value.to_sat(), 500_000);
+		assert!(matches!(results[1], Err(UtxoLookupError::UnknownTx)));
+		assert_eq!(results[2].as_ref().unwrap().value.to_sat(), 250_000);
+		assert_eq!(channel_funding_amounts.lock().unwrap().len(), 2);
+		assert_eq!(channel_funding_amounts.lock().unwrap().get(&short_channel_id(3, 1)), Some(&250_000));
+	}
+
+	#[test]
+	fn test_bounded_funding_amount_cache() {
+		let funding_amounts: FundingAmountCache = Arc::new(Mutex::new(HashMap::new()));
+		for scid in 0..8 {
+			cache_funding_value(&funding_amounts, scid, 1000, Some(8));
+		}
+		// refreshing a cached amount doesn't make room
+		cache_funding_value(&funding_amounts, 7, 2000, Some(8));
+		assert_eq!(funding_amounts.lock().unwrap().len(), 8);
+		cache_funding_value(&funding_amounts, 8, 3000, Some(8));
+		assert_eq!(funding_amounts.lock().unwrap().len(), 7);
+		assert_eq!(cached_funding_value(&funding_amounts, 8), Some(3000));
+
+		cache_funding_value(&funding_amounts, 9, 3000, None);
+		assert_eq!(funding_amounts.lock().unwrap().len(), 8);
+	}
+
+	#[tokio::test]
+	async fn test_block_filter_response() {
+		let secp_ctx = Secp256k1::new();
+		let key = |byte: u8| PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[byte; 32]).unwrap());
+		let funding_script = make_funding_redeemscript(&key(1), &key(2)).to_p2wsh();
+		let chain_source = MockChainSource::new();
+		chain_source.add_funding_output(870000 << 40 | 3 << 16, TxOut { value: bitcoin::Amount::from_sat(250_000), script_pubkey: funding_script.clone() });
+		let block = chain_source.block_at_height(870000).await.unwrap();
+		let block_hash = block.block_hash();
+		let filter = BlockFilter::new_script_filter(&block, |_| Ok(ScriptBuf::new())).unwrap();
+
+		let mut response = vec![0];
+		response.extend_from_slice(block_hash.as_byte_array());
+		response.extend_from_slice(&bitcoin::consensus::serialize(&VarInt(filter.content.len() as u64)));
+		response.extend_from_slice(&filter.content);
+		let parsed: RestBlockFilter = BinaryResponse(response.clone()).try_into().unwrap();
+		assert_eq!(parsed.block_hash, block_hash);
+		assert!(parsed.filter.match_any(&block_hash, std::iter::once(funding_script.as_bytes())).unwrap());
+		let other_script = make_funding_redeemscript(&key(1), &key(3)).to_p2wsh();
+		assert!(!parsed.filter.match_any(&block_hash, std::iter::once(other_script.as_bytes())).unwrap());
+
+		// truncated responses and other filter types are rejected
+		let truncated: Result<RestBlockFilter, _> = BinaryResponse(response[..response.len() - 1].to_vec()).try_into();
+		assert!(truncated.is_err());
+		response[0] = 1;
+		let other_type: Result<RestBlockFilter, _> = BinaryResponse(response).try_into();
+		assert!(other_type.is_err());
+	}
+
+	#[test]
+	fn test_block_txids_parsing() {
+		let coinbase_txid = "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b";
+		let funding_txid = "0e3e2357e806b6cdb1f70b54c3a3a17b6714ee1f0e68bebb44a74b1efd512098";
+		let block = serde_json::json!({ "hash": "00", "nTx": 2, "tx": [coinbase_txid, funding_txid] });
+		let txids = parse_block_txids(&block).unwrap();
+		assert_eq!(txids, vec![Txid::from_str(coinbase_txid).unwrap(), Txid::from_str(funding_txid).unwrap()]);
+
+		// blocks with transaction details don't list plain ids
+		assert!(parse_block_txids(&serde_json::json!({ "tx": [{ "txid": coinbase_txid }] })).is_err());
+		assert!(parse_block_txids(&serde_json::json!({ "hash": "00" })).is_err());
+	}
+
+	#[test]
+	fn test_retry_delay() {
+		let base_delay = Duration::from_millis(500);
+		assert_eq!(retry_delay(base_delay, 0), Duration::from_millis(500));
+		assert_eq!(retry_delay(base_delay, 3), Duration::from_secs(4));
+		assert_eq!(retry_delay(base_delay, 10), config::MAX_BITCOIN_REST_RETRY_DELAY);
+		assert_eq!(retry_delay(base_delay, 40), config::MAX_BITCOIN_REST_RETRY_DELAY);
+	}
+}